@@ -0,0 +1,279 @@
+//! In-process orchestration of a whole mining stack.
+//!
+//! [`Stack::builder`] starts a Template Provider, a pool and, optionally, a JDS/JDC pair and a
+//! translator, all in the test process and each listening on a random local port. A [`Sniffer`]
+//! sits on every SV2 [`Hop`] between them, so that end-to-end tests can assert on the real,
+//! Noise-encrypted, messages the roles exchange without running any binary but the Template
+//! Provider. Stacks without job declaration may even do without a Bitcoin node, with a
+//! [`MockTemplateProvider`] serving synthetic templates instead.
+//!
+//! ```ignore
+//! let stack = Stack::builder().with_job_declaration().with_translator(false).start().await;
+//! let (_minerd, _) = start_minerd(stack.miner_address(), None, None, false).await;
+//! stack
+//!     .expect_flow(
+//!         Hop::JdcPool,
+//!         &[
+//!             (MessageDirection::ToUpstream, MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL),
+//!             (MessageDirection::ToDownstream, MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS),
+//!         ],
+//!     )
+//!     .await;
+//! ```
+
+use crate::{
+    interceptor::MessageDirection,
+    sniffer::Sniffer,
+    start_jdc, start_jds, start_pool, start_sniffer, start_sv2_translator, start_template_provider,
+    template_provider::{DifficultyLevel, TemplateProvider},
+    types::MsgType,
+};
+use jd_client_sv2::JobDeclaratorClient;
+use jd_server::JobDeclaratorServer;
+use pool_sv2::PoolSv2;
+use std::{collections::HashMap, fmt, net::SocketAddr, time::Duration};
+use stratum_apps::mock_tp::{MockTemplateProvider, MockTpConfig, Schedule};
+use tokio::net::TcpStream;
+use translator_sv2::TranslatorSv2;
+
+/// How long [`StackBuilder::start`] waits for a role to listen.
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// An SV2 connection between two roles of a [`Stack`], observed by a [`Sniffer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hop {
+    /// Template Provider to pool.
+    TemplateProviderPool,
+    /// Template Provider to JDC.
+    TemplateProviderJdc,
+    /// Pool to JDC.
+    JdcPool,
+    /// JDS to JDC.
+    JdcJds,
+    /// The topmost SV2 server of the miners (the JDC with job declaration, the pool otherwise) to
+    /// its client: the translator if any, the SV2 mining device connected to
+    /// [`Stack::miner_address`] otherwise.
+    Downstream,
+}
+
+impl Hop {
+    // Also the identifier of the sniffer of the hop.
+    fn name(self) -> &'static str {
+        match self {
+            Hop::TemplateProviderPool => "tp-pool",
+            Hop::TemplateProviderJdc => "tp-jdc",
+            Hop::JdcPool => "jdc-pool",
+            Hop::JdcJds => "jdc-jds",
+            Hop::Downstream => "downstream",
+        }
+    }
+}
+
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Roles to start in a [`Stack`].
+pub struct StackBuilder {
+    sv2_interval: Option<u32>,
+    difficulty_level: DifficultyLevel,
+    job_declaration: bool,
+    // `Some(aggregate_channels)` to start a translator.
+    translator: Option<bool>,
+    mock_template_provider: Option<Schedule>,
+}
+
+impl StackBuilder {
+    /// Sets the interval, in seconds, at which the Template Provider sends new templates.
+    pub fn sv2_interval(mut self, sv2_interval: u32) -> Self {
+        self.sv2_interval = Some(sv2_interval);
+        self
+    }
+
+    /// Sets the difficulty of the network of the Template Provider, [`DifficultyLevel::Low`] by
+    /// default.
+    pub fn difficulty_level(mut self, difficulty_level: DifficultyLevel) -> Self {
+        self.difficulty_level = difficulty_level;
+        self
+    }
+
+    /// Starts a JDS and a JDC declaring the jobs of the miners to the pool.
+    pub fn with_job_declaration(mut self) -> Self {
+        self.job_declaration = true;
+        self
+    }
+
+    /// Starts a translator for SV1 miners, aggregating their channels or not.
+    pub fn with_translator(mut self, aggregate_channels: bool) -> Self {
+        self.translator = Some(aggregate_channels);
+        self
+    }
+
+    /// Serves the templates of `schedule` from a [`MockTemplateProvider`] rather than from a
+    /// Template Provider backed by a Bitcoin node. The JDS needing a node, this excludes job
+    /// declaration.
+    pub fn with_mock_template_provider(mut self, schedule: Schedule) -> Self {
+        self.mock_template_provider = Some(schedule);
+        self
+    }
+
+    /// Starts the roles, upstream first, returning once the miners can connect.
+    pub async fn start(self) -> Stack {
+        let mut sniffers = HashMap::new();
+        let mut sniff = |hop: Hop, upstream: SocketAddr| {
+            let (sniffer, address) = start_sniffer(hop.name(), upstream, false, vec![], None);
+            sniffers.insert(hop, sniffer);
+            address
+        };
+
+        let (template_provider, mock_template_provider, tp_address) =
+            match self.mock_template_provider {
+                Some(schedule) => {
+                    let config = MockTpConfig::new(([127, 0, 0, 1], 0).into(), schedule);
+                    let mock = MockTemplateProvider::start(config)
+                        .await
+                        .expect("Failed to start the mock Template Provider");
+                    let address = mock.local_address();
+                    (None, Some(mock), address)
+                }
+                None => {
+                    let (template_provider, address) =
+                        start_template_provider(self.sv2_interval, self.difficulty_level);
+                    (Some(template_provider), None, address)
+                }
+            };
+        let (pool, pool_address) =
+            start_pool(Some(sniff(Hop::TemplateProviderPool, tp_address))).await;
+
+        let (jds, jdc, server_address) = if self.job_declaration {
+            let template_provider = template_provider
+                .as_ref()
+                .expect("job declaration needs a Template Provider backed by a Bitcoin node");
+            let (jds, jds_address) = start_jds(template_provider.rpc_info());
+            wait_for_listener(jds_address).await;
+            let upstream = (
+                sniff(Hop::JdcPool, pool_address),
+                sniff(Hop::JdcJds, jds_address),
+            );
+            let (jdc, jdc_address) =
+                start_jdc(&[upstream], sniff(Hop::TemplateProviderJdc, tp_address));
+            wait_for_listener(jdc_address).await;
+            (Some(jds), Some(jdc), jdc_address)
+        } else {
+            (None, None, pool_address)
+        };
+
+        let downstream_address = sniff(Hop::Downstream, server_address);
+        let (translator, miner_address) = match self.translator {
+            Some(aggregate_channels) => {
+                let (translator, translator_address) =
+                    start_sv2_translator(downstream_address, aggregate_channels).await;
+                (Some(translator), translator_address)
+            }
+            None => (None, downstream_address),
+        };
+
+        Stack {
+            template_provider,
+            mock_template_provider,
+            pool,
+            jds,
+            jdc,
+            translator,
+            miner_address,
+            sniffers,
+        }
+    }
+}
+
+/// A mining stack running in the test process, stopped when dropped along with the test runtime.
+pub struct Stack {
+    template_provider: Option<TemplateProvider>,
+    mock_template_provider: Option<MockTemplateProvider>,
+    pool: PoolSv2,
+    jds: Option<JobDeclaratorServer>,
+    jdc: Option<JobDeclaratorClient>,
+    translator: Option<TranslatorSv2>,
+    miner_address: SocketAddr,
+    sniffers: HashMap<Hop, Sniffer<'static>>,
+}
+
+impl Stack {
+    /// Returns a builder of a stack made of a Template Provider and a pool.
+    pub fn builder() -> StackBuilder {
+        StackBuilder {
+            sv2_interval: None,
+            difficulty_level: DifficultyLevel::Low,
+            job_declaration: false,
+            translator: None,
+            mock_template_provider: None,
+        }
+    }
+
+    pub fn template_provider(&self) -> Option<&TemplateProvider> {
+        self.template_provider.as_ref()
+    }
+
+    pub fn mock_template_provider(&self) -> Option<&MockTemplateProvider> {
+        self.mock_template_provider.as_ref()
+    }
+
+    pub fn pool(&self) -> &PoolSv2 {
+        &self.pool
+    }
+
+    pub fn jds(&self) -> Option<&JobDeclaratorServer> {
+        self.jds.as_ref()
+    }
+
+    pub fn jdc(&self) -> Option<&JobDeclaratorClient> {
+        self.jdc.as_ref()
+    }
+
+    pub fn translator(&self) -> Option<&TranslatorSv2> {
+        self.translator.as_ref()
+    }
+
+    /// Address the miners connect to: the SV1 address of the translator if any, the
+    /// [`Hop::Downstream`] sniffer otherwise. As a sniffer accepts a single client, only one SV2
+    /// mining device can connect to a stack without translator.
+    pub fn miner_address(&self) -> SocketAddr {
+        self.miner_address
+    }
+
+    /// Returns the sniffer of `hop`.
+    ///
+    /// Panics if the roles of `hop` are not part of the stack.
+    pub fn sniffer(&self, hop: Hop) -> &Sniffer<'static> {
+        self.sniffers
+            .get(&hop)
+            .unwrap_or_else(|| panic!("no {hop} hop in this stack"))
+    }
+
+    /// Waits for the messages of `flow` to go through `hop`, in this order, removing them and
+    /// the messages preceding them from the queues of the sniffer.
+    ///
+    /// Panics if a message is not seen before the timeout of the sniffer.
+    pub async fn expect_flow(&self, hop: Hop, flow: &[(MessageDirection, MsgType)]) {
+        let sniffer = self.sniffer(hop);
+        for (direction, message_type) in flow {
+            sniffer
+                .wait_for_message_type_and_clean_queue(direction.clone(), *message_type)
+                .await;
+        }
+    }
+}
+
+// Roles bind their listening address once connected to their own upstreams, which their
+// downstream sniffer needs before a client connects to it.
+async fn wait_for_listener(address: SocketAddr) {
+    let now = std::time::Instant::now();
+    while TcpStream::connect(address).await.is_err() {
+        if now.elapsed() > LISTEN_TIMEOUT {
+            panic!("timeout while waiting for a role to listen on {address}");
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
@@ -19,6 +19,7 @@ use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, Env
 use translator_sv2::TranslatorSv2;
 use utils::get_available_address;
 
+pub mod harness;
 pub mod interceptor;
 pub mod message_aggregator;
 pub mod mock_roles;
@@ -288,7 +289,7 @@ pub async fn start_sv2_translator(
     let translator_v2 = translator_sv2::TranslatorSv2::new(config);
     let clone_translator_v2 = translator_v2.clone();
     tokio::spawn(async move {
-        clone_translator_v2.start().await;
+        _ = clone_translator_v2.start().await;
     });
     (translator_v2, listening_address)
 }
@@ -0,0 +1,164 @@
+use integration_tests_sv2::{
+    harness::{Hop, Stack},
+    interceptor::MessageDirection,
+    *,
+};
+use std::time::Duration;
+use stratum_apps::{
+    mock_tp::Schedule,
+    stratum_core::{
+        common_messages_sv2::*, job_declaration_sv2::*, mining_sv2::*, template_distribution_sv2::*,
+    },
+};
+
+// This test starts the whole stack (TP, pool, JDS, JDC and translator) through the harness and a
+// minerd process, then checks the messages flowing through every hop, from the templates sent by
+// the TP down to the shares accepted by the pool.
+#[tokio::test]
+async fn harness_full_stack_message_flow() {
+    start_tracing();
+    let stack = Stack::builder()
+        .with_job_declaration()
+        .with_translator(false)
+        .start()
+        .await;
+    let (_minerd_process, _minerd_addr) =
+        start_minerd(stack.miner_address(), None, None, false).await;
+
+    for hop in [Hop::TemplateProviderPool, Hop::TemplateProviderJdc] {
+        stack
+            .expect_flow(
+                hop,
+                &[
+                    (MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION),
+                    (
+                        MessageDirection::ToDownstream,
+                        MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+                    ),
+                    (MessageDirection::ToDownstream, MESSAGE_TYPE_NEW_TEMPLATE),
+                    (
+                        MessageDirection::ToDownstream,
+                        MESSAGE_TYPE_SET_NEW_PREV_HASH,
+                    ),
+                ],
+            )
+            .await;
+    }
+    stack
+        .expect_flow(
+            Hop::JdcJds,
+            &[
+                (MessageDirection::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION),
+                (
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+                ),
+                (
+                    MessageDirection::ToUpstream,
+                    MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN,
+                ),
+                (
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS,
+                ),
+            ],
+        )
+        .await;
+    stack
+        .expect_flow(
+            Hop::Downstream,
+            &[
+                (
+                    MessageDirection::ToUpstream,
+                    MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+                ),
+                (
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+                ),
+                (
+                    MessageDirection::ToUpstream,
+                    MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+                ),
+            ],
+        )
+        .await;
+    stack
+        .expect_flow(
+            Hop::JdcPool,
+            &[
+                (
+                    MessageDirection::ToUpstream,
+                    MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+                ),
+                (
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+                ),
+                (
+                    MessageDirection::ToUpstream,
+                    MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+                ),
+                (
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+                ),
+            ],
+        )
+        .await;
+}
+
+// This test starts a pool fed by the mock Template Provider, without any Bitcoin node, and an SV2
+// mining device, then checks that the synthetic templates turn into jobs the device mines on.
+#[tokio::test]
+async fn harness_pool_with_mock_template_provider() {
+    start_tracing();
+    let stack = Stack::builder()
+        .with_mock_template_provider(Schedule::Periodic {
+            interval: Duration::from_secs(5),
+            new_block_every: 3,
+        })
+        .start()
+        .await;
+    start_mining_device_sv2(stack.miner_address(), None, None, None, 1, None, true);
+
+    stack
+        .expect_flow(
+            Hop::TemplateProviderPool,
+            &[
+                (
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+                ),
+                (MessageDirection::ToDownstream, MESSAGE_TYPE_NEW_TEMPLATE),
+                (
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_SET_NEW_PREV_HASH,
+                ),
+            ],
+        )
+        .await;
+    stack
+        .expect_flow(
+            Hop::Downstream,
+            &[
+                (
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+                ),
+                (
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS,
+                ),
+                (
+                    MessageDirection::ToUpstream,
+                    MESSAGE_TYPE_SUBMIT_SHARES_STANDARD,
+                ),
+                (
+                    MessageDirection::ToDownstream,
+                    MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+                ),
+            ],
+        )
+        .await;
+}
@@ -1,16 +1,25 @@
 use clap::Parser;
-use ext_config::{Config, File, FileFormat};
-use jd_client_sv2::{config::JobDeclaratorClientConfig, error::JDCError};
+use jd_client_sv2::{
+    coinbase_outputs::encode_outputs,
+    config::{JobDeclaratorClientConfig, VerificationMode},
+    error::JDCError,
+    tx_selection::FilterList,
+};
+use stratum_apps::config_helpers::{self, ConfigCheck, ConfigProfile};
+
+use std::path::{Path, PathBuf};
+
+/// Prefix of the environment variables overriding the configuration file, e.g.
+/// `SV2_JDC__LISTENING_ADDRESS`.
+pub const ENV_PREFIX: &str = "SV2_JDC";
 
-use std::path::PathBuf;
-use tracing::error;
 #[derive(Debug, Parser)]
 #[command(author, version, about = "JD Client", long_about = None)]
 pub struct Args {
     #[arg(
         short = 'c',
         long = "config",
-        help = "Path to the TOML configuration file",
+        help = "Path to the configuration file, in TOML, YAML (.yaml, .yml) or JSON (.json)",
         default_value = "jdc-config.toml"
     )]
     pub config_path: PathBuf,
@@ -20,24 +29,256 @@ pub struct Args {
         help = "Path to the log file. If not set, logs will only be written to stdout."
     )]
     pub log_file: Option<PathBuf>,
+    #[arg(
+        long = "check-config",
+        help = "Validate the configuration file, print a report and exit without starting the JDC"
+    )]
+    pub check_config: bool,
+    #[arg(
+        long = "generate-config",
+        value_name = "PROFILE",
+        num_args = 0..=1,
+        default_missing_value = "testnet",
+        help = "Write an example configuration with fresh authority keys for the `mainnet`, `testnet` (default), `signet` or `regtest` profile and exit"
+    )]
+    pub generate_config: Option<ConfigProfile>,
+    #[arg(
+        short = 'o',
+        long = "output",
+        requires = "generate_config",
+        help = "File written by --generate-config instead of stdout. Existing files are not overwritten."
+    )]
+    pub output: Option<PathBuf>,
 }
 
 #[allow(clippy::result_large_err)]
 pub fn process_cli_args() -> Result<JobDeclaratorClientConfig, JDCError> {
     let args = Args::parse();
+    if let Some(profile) = args.generate_config {
+        let config = generate_config(profile);
+        if let Err(e) = config_helpers::write_generated_config(&config, args.output.as_deref()) {
+            eprintln!("Failed to write the generated config: {e}");
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    if args.check_config {
+        check_config(&args.config_path).exit();
+    }
 
-    let config_path = args.config_path.to_str().ok_or_else(|| {
-        error!("Invalid configuration path.");
-        JDCError::BadCliArgs
-    })?;
-
-    let settings = Config::builder()
-        .add_source(File::new(config_path, FileFormat::Toml))
-        .build()?;
-
-    let mut config = settings.try_deserialize::<JobDeclaratorClientConfig>()?;
+    let mut config: JobDeclaratorClientConfig =
+        config_helpers::load_config(&args.config_path, ENV_PREFIX)?;
 
     config.set_log_file(args.log_file);
 
     Ok(config)
 }
+
+/// Loads the configuration at `config_path` and checks it without starting any service.
+pub fn check_config(config_path: &Path) -> ConfigCheck {
+    let mut report = ConfigCheck::new();
+    let parsed: Result<JobDeclaratorClientConfig, _> =
+        config_helpers::load_config(config_path, ENV_PREFIX);
+    let config = match parsed {
+        Ok(config) => config,
+        Err(e) => {
+            report.fail(format!("parse {}", config_path.display()), e);
+            return report;
+        }
+    };
+    report.pass(format!("parse {}", config_path.display()), "ok");
+    report.pass("listening address", config.listening_address());
+    report.socket_address("template provider address", config.tp_address());
+    report.authority_keypair(config.authority_public_key(), config.authority_secret_key());
+    report.coinbase_reward_script(&config.coinbase_reward_script);
+    match config.extra_coinbase_outputs() {
+        Ok(extra) if extra.is_empty() => {}
+        Ok(extra) => report.check(
+            "extra coinbase outputs",
+            encode_outputs(vec![config.get_txout()], &extra)
+                .map(|_| format!("{} appended to the jobs", extra.len())),
+        ),
+        Err(reason) => report.fail("extra coinbase outputs", reason),
+    }
+    report.check(
+        "supported versions",
+        match (
+            config.min_supported_version(),
+            config.max_supported_version(),
+        ) {
+            (min, max) if min <= max => Ok(format!("{min}..={max}")),
+            (min, max) => Err(format!(
+                "`min_supported_version` {min} is above `max_supported_version` {max}"
+            )),
+        },
+    );
+    if config.upstreams().is_empty() {
+        report.pass("upstreams", "none, solo mining only");
+    }
+    for (i, upstream) in config.upstreams().iter().enumerate() {
+        report.ip_address(format!("upstream {i} pool address"), &upstream.pool_address);
+        report.ip_address(format!("upstream {i} JDS address"), &upstream.jds_address);
+    }
+    if !config.tx_filter().is_empty() {
+        report.check(
+            "transaction filter",
+            FilterList::from_config(config.tx_filter()).map(|_| {
+                format!(
+                    "{} txids and {} output scripts excluded",
+                    config.tx_filter().txids().len(),
+                    config.tx_filter().output_scripts().len()
+                )
+            }),
+        );
+    }
+    let verification = config.upstream_verification();
+    let action = match verification.mode() {
+        VerificationMode::Off => None,
+        VerificationMode::Alarm => Some("alarm"),
+        VerificationMode::Refuse => Some("refuse the pool"),
+    };
+    if let Some(action) = action {
+        report.check(
+            "upstream verification",
+            if verification.grace().is_zero() {
+                Err(
+                    "`grace_secs` must be above 0, the pool and the Template Provider do not \
+                     learn about a new block at the same time",
+                )
+            } else {
+                Ok(format!(
+                    "{action} after {}s of divergence",
+                    verification.grace().as_secs()
+                ))
+            },
+        );
+    }
+    if let Some(monitoring) = config.monitoring() {
+        report.pass("monitoring address", monitoring.listen_address);
+    }
+    report
+}
+
+/// Renders a commented configuration for `profile`, with a fresh authority keypair.
+pub fn generate_config(profile: ConfigProfile) -> String {
+    let (authority_public_key, authority_secret_key) = config_helpers::generate_authority_keys();
+    let tp_port = profile.template_provider_port();
+    let coinbase_reward_script = profile.coinbase_reward_script();
+    format!(
+        r#"# SRI JDC config ({profile}), generated with --generate-config
+# Every setting can be overridden by an environment variable named after it, prefixed with
+# SV2_JDC__ (nested tables separated by __). Validate this file with --check-config.
+listening_address = "0.0.0.0:34265"
+# Transport of downstream connections: "noise" (default) or "none" for plaintext SV2 frames.
+# Only disable Noise when every downstream sits on a trusted network.
+# listen_encryption = "none"
+# Maximum size in bytes of a frame received from a downstream, larger frames abort the connection.
+# max_frame_size = 1048576
+# Seconds a downstream may stay silent before being disconnected.
+# downstream_idle_timeout_secs = 600
+# Capacities of the channels of each downstream connection: received frames waiting to be handled
+# (reading stops while full) and frames waiting to be written, unbounded by default. `overflow` is
+# what happens to a frame sent to a full outbound channel: "disconnect" (default), "drop" (share
+# acknowledgements only, other frames disconnect) or "backpressure" (wait for the downstream).
+# downstream_channels.inbound_capacity = 256
+# downstream_channels.outbound_capacity = 1024
+# downstream_channels.overflow = "disconnect"
+
+# Version support
+max_supported_version = 2
+min_supported_version = 2
+
+# Authority keypair for the encrypted downstream connections. Downstreams pin the public key,
+# keep the secret key secret.
+authority_public_key = "{authority_public_key}"
+authority_secret_key = "{authority_secret_key}"
+# Instead of the line above, the secret key can be read from a file or an environment variable:
+# authority_secret_key_file = "/run/secrets/authority_key"
+# authority_secret_key_env = "AUTHORITY_SECRET_KEY"
+cert_validity_sec = 3600
+
+# User identity/username for pool connection
+user_identity = "your_username_here"
+
+# target number of shares per minute applied to every downstream channel
+shares_per_minute = 6.0
+
+# Share batch size
+share_batch_size = 10
+
+# JDC supports two modes:
+# "FULLTEMPLATE"  - full template mining
+# "COINBASEONLY" - coinbase-only mining
+mode = "FULLTEMPLATE"
+
+# Template Provider config (Bitcoin Core with Stratum V2 support, run with -sv2)
+tp_address = "127.0.0.1:{tp_port}"
+# tp_encryption = "none"  # plaintext connection to the TP, for trusted networks only
+
+# string to be added into the Coinbase scriptSig
+jdc_signature = "Sv2MinerSignature"
+
+# Coinbase output used while solo mining, as last resort of the upstreams fallback.
+# Coinbase outputs are specified as descriptors. A full list of descriptors is available at
+#     https://github.com/bitcoin/bips/blob/master/bip-0380.mediawiki#appendix-b-index-of-script-expressions
+# REPLACE this placeholder with your own address before mining: `addr(<address here>)`.
+coinbase_reward_script = "{coinbase_reward_script}"
+# Zero-value outputs appended to the coinbase of the jobs, after the outputs of the pool (or the
+# output above while solo mining), e.g. to embed commitments of your own: `op_return` carries up to
+# 80 bytes of hex data, `script` is a descriptor like `coinbase_reward_script`.
+# extra_coinbase_outputs = [{{ op_return = "6a6f62" }}, {{ script = "raw(51)" }}]
+
+# Logs are written to this file if set, --log-file (or -f) overrides it.
+# log_file = "./jd-client.log"
+
+# While solo mining, interval (in seconds) at which the upstreams below are probed in order
+# to automatically return to pooled mining once one of them recovers (defaults to 60)
+# upstream_retry_interval_secs = 60
+
+# Number of consecutive DeclareMiningJobError tolerated from the current JDS before
+# failing over to the next upstream in the list (defaults to 3)
+# declare_mining_job_error_threshold = 3
+
+# Transactions the mined templates must not include, by txid or by output script descriptor
+# (FULLTEMPLATE mode only). The jobs of a template are only built once its transactions are checked,
+# a template including one of them is not mined.
+# [tx_filter]
+# txids = ["<txid>"]
+# output_scripts = ["raw(<hex script>)"]
+
+# Cross-check of the chain tip announced by the pool (previous block hash of its SetNewPrevHash and
+# height of the coinbase of its jobs) against the Template Provider. "off" (default), "alarm" to
+# log the divergence and report the `upstream_verification` component as degraded on the health
+# endpoint, or "refuse" to also leave the pool for the next upstream (or solo mining). The pool is
+# only reported once both disagreed for `grace_secs` (defaults to 10).
+# [upstream_verification]
+# mode = "alarm"
+# grace_secs = 10
+
+# Pool and JDS pairs, in order of preference. REPLACE the address and authority public key with
+# the ones published by your pool.
+# A list of keys (e.g. the current and the next one during a key rotation) accepts any of them.
+[[upstreams]]
+authority_pubkey = "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72"
+pool_address = "127.0.0.1"
+pool_port = 34254
+jds_address = "127.0.0.1"
+jds_port = 34264
+
+# HTTP endpoint serving the health of the JDC components, the shares rejected per error code and
+# the outcome of the jobs declared to the JDS as JSON on GET /status, and liveness and readiness
+# probes on GET /healthz and GET /readyz (200 or 503).
+# [monitoring]
+# listen_address = "127.0.0.1:9090"
+
+# Log format: "pretty" (default) or "json", one object per line carrying the fields of the
+# enclosing spans (connection id, channel id, user identity). Filter directives select the level
+# per module, RUST_LOG takes precedence over them.
+# [logging]
+# format = "json"
+# filter = "info,jd_client_sv2=debug"
+# Binaries built with the `otel` feature also export spans to an OTLP/gRPC collector.
+# otlp_endpoint = "http://localhost:4317"
+"#
+    )
+}
@@ -0,0 +1,185 @@
+//! Verification of the chain tip announced by the pool.
+//!
+//! The JDC builds its jobs from the templates of its own Template Provider, while the pool
+//! announces the chain tip it mines on through the `SetNewPrevHash` and the jobs of the upstream
+//! channel. The [`ChainTipVerifier`] compares the previous block hash, and the BIP34 height of the
+//! coinbase of the jobs, announced on both sides. The pool is only reported as diverged once both
+//! disagreed for longer than the grace period, since they do not learn about a new block at the
+//! same time.
+
+use std::time::{Duration, Instant};
+
+// Chain tip announced by the pool or the Template Provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ChainTip {
+    prev_hash: Vec<u8>,
+    // BIP34 height of the coinbase of the jobs on the tip, once known.
+    height: Option<u32>,
+}
+
+/// Change in the agreement of the pool with the Template Provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The pool diverged from the Template Provider, for the given reason.
+    Diverged(String),
+    /// The pool agrees with the Template Provider again.
+    Agreed,
+}
+
+/// Compares the chain tips announced by the pool and the Template Provider.
+#[derive(Debug)]
+pub struct ChainTipVerifier {
+    grace: Duration,
+    local: Option<ChainTip>,
+    pool: Option<ChainTip>,
+    // Job id and coinbase height of the last future job of the pool, activated by its next
+    // `SetNewPrevHash`.
+    pool_future_job: Option<(u32, Option<u32>)>,
+    disagreeing_since: Option<Instant>,
+    diverged: bool,
+}
+
+impl ChainTipVerifier {
+    /// Creates a verifier reporting the pool as diverged after disagreeing for `grace`.
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            grace,
+            local: None,
+            pool: None,
+            pool_future_job: None,
+            disagreeing_since: None,
+            diverged: false,
+        }
+    }
+
+    /// Records a `SetNewPrevHash` of the Template Provider, with the coinbase height of the
+    /// template it activates if known.
+    pub fn on_local_prev_hash(&mut self, prev_hash: &[u8], height: Option<u32>, now: Instant) {
+        self.local = Some(ChainTip {
+            prev_hash: prev_hash.to_vec(),
+            height,
+        });
+        self.update(now);
+    }
+
+    /// Records a `SetNewPrevHash` of the pool, activating its job `job_id`.
+    pub fn on_pool_prev_hash(&mut self, prev_hash: &[u8], job_id: u32, now: Instant) {
+        let height = match self.pool_future_job.take() {
+            Some((future_job_id, height)) if future_job_id == job_id => height,
+            _ => None,
+        };
+        self.pool = Some(ChainTip {
+            prev_hash: prev_hash.to_vec(),
+            height,
+        });
+        self.update(now);
+    }
+
+    /// Records the coinbase height of a job of the pool. A `future` job is on the chain tip of the
+    /// next `SetNewPrevHash` of the pool, the others on the current one.
+    pub fn on_pool_job(&mut self, job_id: u32, future: bool, height: Option<u32>, now: Instant) {
+        if future {
+            self.pool_future_job = Some((job_id, height));
+            return;
+        }
+        if let (Some(pool), Some(height)) = (self.pool.as_mut(), height) {
+            pool.height = Some(height);
+        }
+        self.update(now);
+    }
+
+    /// Forgets the chain tip of the pool, once disconnected from it. Returns whether the pool
+    /// was reported as diverged.
+    pub fn reset_pool(&mut self) -> bool {
+        self.pool = None;
+        self.pool_future_job = None;
+        self.disagreeing_since = None;
+        std::mem::take(&mut self.diverged)
+    }
+
+    /// Returns whether the pool diverged from, or agrees again with, the Template Provider since
+    /// the last call.
+    pub fn check(&mut self, now: Instant) -> Option<Verdict> {
+        let divergence = match (self.disagreement(), self.disagreeing_since) {
+            (Some(reason), Some(since)) if now.duration_since(since) >= self.grace => Some(reason),
+            _ => None,
+        };
+        if divergence.is_some() == self.diverged {
+            return None;
+        }
+        self.diverged = divergence.is_some();
+        Some(divergence.map_or(Verdict::Agreed, Verdict::Diverged))
+    }
+
+    // Tracks since when the pool and the Template Provider disagree.
+    fn update(&mut self, now: Instant) {
+        if self.disagreement().is_none() {
+            self.disagreeing_since = None;
+        } else if self.disagreeing_since.is_none() {
+            self.disagreeing_since = Some(now);
+        }
+    }
+
+    // Returns how the pool disagrees with the Template Provider, if it does.
+    fn disagreement(&self) -> Option<String> {
+        let (local, pool) = (self.local.as_ref()?, self.pool.as_ref()?);
+        if local.prev_hash != pool.prev_hash {
+            return Some(format!(
+                "the pool mines on block {} while the Template Provider is on block {}",
+                block_hash(&pool.prev_hash),
+                block_hash(&local.prev_hash)
+            ));
+        }
+        match (pool.height, local.height) {
+            (Some(pool_height), Some(local_height)) if pool_height != local_height => {
+                Some(format!(
+                    "the jobs of the pool are at height {pool_height} while the templates are at height {local_height}"
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the height pushed at the start of the scriptSig of a coinbase, as required by BIP34.
+pub fn script_sig_height(script_sig: &[u8]) -> Option<u32> {
+    match *script_sig.first()? {
+        // OP_1 to OP_16
+        op @ 0x51..=0x60 => Some(u32::from(op - 0x50)),
+        len @ 1..=4 => {
+            let number = script_sig.get(1..=len as usize)?;
+            Some(
+                number
+                    .iter()
+                    .rev()
+                    .fold(0, |height, byte| (height << 8) | u32::from(*byte)),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Returns the BIP34 height of the coinbase whose serialization starts with
+/// `coinbase_tx_prefix`, as in the jobs of the pool.
+pub fn coinbase_tx_prefix_height(coinbase_tx_prefix: &[u8]) -> Option<u32> {
+    // version
+    let mut rest = coinbase_tx_prefix.get(4..)?;
+    // segwit marker and flag
+    if rest.starts_with(&[0, 1]) {
+        rest = &rest[2..];
+    }
+    // a single input, spending the null outpoint, with a scriptSig shorter than 0xfd bytes
+    if rest.first() != Some(&1) {
+        return None;
+    }
+    script_sig_height(rest.get(1 + 36 + 1..)?)
+}
+
+// Formats a previous block hash, little-endian as in SV2 messages, as block explorers do.
+fn block_hash(prev_hash: &[u8]) -> String {
+    prev_hash
+        .iter()
+        .rev()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
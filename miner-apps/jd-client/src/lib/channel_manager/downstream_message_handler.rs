@@ -1,28 +1,31 @@
 use std::sync::atomic::Ordering;
 
-use stratum_apps::stratum_core::{
-    binary_sv2::Str0255,
-    bitcoin::{Amount, Target},
-    channels_sv2::{
-        client,
-        outputs::deserialize_outputs,
-        server::{
-            error::{ExtendedChannelError, StandardChannelError},
-            extended::ExtendedChannel,
-            group::GroupChannel,
-            jobs::job_store::DefaultJobStore,
-            share_accounting::{ShareValidationError, ShareValidationResult},
-            standard::StandardChannel,
+use stratum_apps::{
+    share_reject::ShareRejectReason,
+    stratum_core::{
+        binary_sv2::Str0255,
+        bitcoin::{Amount, Target},
+        channels_sv2::{
+            client,
+            outputs::deserialize_outputs,
+            server::{
+                error::{ExtendedChannelError, StandardChannelError},
+                extended::ExtendedChannel,
+                group::GroupChannel,
+                jobs::job_store::DefaultJobStore,
+                share_accounting::ShareValidationResult,
+                standard::StandardChannel,
+            },
+            Vardiff, VardiffState,
         },
-        Vardiff, VardiffState,
+        handlers_sv2::{HandleMiningMessagesFromClientAsync, SupportedChannelTypes},
+        job_declaration_sv2::PushSolution,
+        mining_sv2::*,
+        parsers_sv2::{JobDeclaration, Mining, TemplateDistribution},
+        template_distribution_sv2::SubmitSolution,
     },
-    handlers_sv2::{HandleMiningMessagesFromClientAsync, SupportedChannelTypes},
-    job_declaration_sv2::PushSolution,
-    mining_sv2::*,
-    parsers_sv2::{JobDeclaration, Mining, TemplateDistribution},
-    template_distribution_sv2::SubmitSolution,
 };
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, instrument, warn};
 
 use crate::{
     channel_manager::{ChannelManager, ChannelManagerChannel},
@@ -870,6 +873,16 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
     //    - Translate the share into an upstream `SubmitSharesExtended`.
     //    - Validate with the upstream channel.
     //    - Forward valid shares (or block solutions) upstream.
+    #[instrument(
+        name = "share",
+        skip_all,
+        fields(
+            channel_id = msg.channel_id,
+            sequence_number = msg.sequence_number,
+            job_id = msg.job_id,
+            nonce = msg.nonce
+        )
+    )]
     async fn handle_submit_shares_standard(
         &mut self,
         client_id: Option<usize>,
@@ -881,11 +894,12 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
         let downstream_id =
             client_id.expect("client_id must be present for downstream_id extraction");
 
-        let build_error = |code: &str| {
+        let build_error = |reason: ShareRejectReason| {
+            self.share_rejects.record(reason);
             Mining::SubmitSharesError(SubmitSharesError {
                 channel_id,
                 sequence_number: msg.sequence_number,
-                error_code: code.to_string().try_into().expect("valid error code"),
+                error_code: reason.error_code(),
             })
         };
 
@@ -903,8 +917,8 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                 let mut messages: Vec<RouteMessageTo> = vec![];
 
                 let Some(standard_channel) = data.standard_channels.get_mut(&channel_id) else {
-                    error!("SubmitSharesError: channel_id: {channel_id}, sequence_number: {}, error_code: invalid-channel-id", msg.sequence_number);
-                    return Ok(vec![(downstream_id, build_error("invalid-channel-id")).into()]);
+                    error!("SubmitSharesError: channel_id: {channel_id}, sequence_number: {}, error_code: {}", msg.sequence_number, ShareRejectReason::InvalidChannelId);
+                    return Ok(vec![(downstream_id, build_error(ShareRejectReason::InvalidChannelId)).into()]);
                 };
 
                 let Some(vardiff) = channel_manager_data.vardiff.get_mut(&(downstream_id, channel_id).into()) else {
@@ -961,16 +975,10 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                         ).into());
                     }
                     Err(err) => {
-                        let code = match err {
-                            ShareValidationError::Invalid => "invalid-share",
-                            ShareValidationError::Stale => "stale-share",
-                            ShareValidationError::InvalidJobId => "invalid-job-id",
-                            ShareValidationError::DoesNotMeetTarget => "difficulty-too-low",
-                            ShareValidationError::DuplicateShare => "duplicate-share",
-                            _ => unreachable!(),
-                        };
-                        error!("❌ SubmitSharesError: ch={}, seq={}, error={code}", channel_id, msg.sequence_number);
-                        messages.push((downstream_id, build_error(code)).into());
+                        let reason = ShareRejectReason::from_validation_error(&err)
+                            .unwrap_or(ShareRejectReason::Invalid);
+                        error!("❌ SubmitSharesError: ch={}, seq={}, error={reason}", channel_id, msg.sequence_number);
+                        messages.push((downstream_id, build_error(reason)).into());
                     }
                 }
 
@@ -1029,11 +1037,11 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                             }
                             Err(err) => {
                                 let code = match err {
-                                    client::share_accounting::ShareValidationError::Invalid => "invalid-share",
-                                    client::share_accounting::ShareValidationError::Stale => "stale-share",
-                                    client::share_accounting::ShareValidationError::InvalidJobId => "invalid-job-id",
-                                    client::share_accounting::ShareValidationError::DoesNotMeetTarget => "difficulty-too-low",
-                                    client::share_accounting::ShareValidationError::DuplicateShare => "duplicate-share",
+                                    client::share_accounting::ShareValidationError::Invalid => ShareRejectReason::Invalid,
+                                    client::share_accounting::ShareValidationError::Stale => ShareRejectReason::StaleJob,
+                                    client::share_accounting::ShareValidationError::InvalidJobId => ShareRejectReason::JobNotFound,
+                                    client::share_accounting::ShareValidationError::DoesNotMeetTarget => ShareRejectReason::AboveTarget,
+                                    client::share_accounting::ShareValidationError::DuplicateShare => ShareRejectReason::Duplicate,
                                     _ => unreachable!(),
                                 };
                                 debug!("❌ SubmitSharesError not forwarding it to upstream: ch={}, seq={}, error={code}", channel_id, upstream_message.sequence_number);
@@ -1064,6 +1072,16 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
     //    - Translate the share into an upstream `SubmitSharesExtended`.
     //    - Validate with the upstream channel.
     //    - Forward valid shares (or block solutions) upstream.
+    #[instrument(
+        name = "share",
+        skip_all,
+        fields(
+            channel_id = msg.channel_id,
+            sequence_number = msg.sequence_number,
+            job_id = msg.job_id,
+            nonce = msg.nonce
+        )
+    )]
     async fn handle_submit_shares_extended(
         &mut self,
         client_id: Option<usize>,
@@ -1075,11 +1093,12 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
         let downstream_id =
             client_id.expect("client_id must be present for downstream_id extraction");
 
-        let build_error = |code: &str| {
+        let build_error = |reason: ShareRejectReason| {
+            self.share_rejects.record(reason);
             Mining::SubmitSharesError(SubmitSharesError {
                 channel_id,
                 sequence_number: msg.sequence_number,
-                error_code: code.to_string().try_into().expect("valid error code"),
+                error_code: reason.error_code(),
             })
         };
 
@@ -1096,8 +1115,8 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                 let mut messages: Vec<RouteMessageTo> = vec![];
 
                 let Some(extended_channel) = data.extended_channels.get_mut(&channel_id) else {
-                    error!("SubmitSharesError: channel_id: {channel_id}, sequence_number: {}, error_code: invalid-channel-id", msg.sequence_number);
-                    return Ok(vec![(downstream_id, build_error("invalid-channel-id")).into()]);
+                    error!("SubmitSharesError: channel_id: {channel_id}, sequence_number: {}, error_code: {}", msg.sequence_number, ShareRejectReason::InvalidChannelId);
+                    return Ok(vec![(downstream_id, build_error(ShareRejectReason::InvalidChannelId)).into()]);
                 };
 
                 let Some(vardiff) = channel_manager_data.vardiff.get_mut(&(downstream_id, channel_id).into()) else {
@@ -1153,16 +1172,10 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                         ).into());
                     }
                     Err(err) => {
-                        let code = match err {
-                            ShareValidationError::Invalid => "invalid-share",
-                            ShareValidationError::Stale => "stale-share",
-                            ShareValidationError::InvalidJobId => "invalid-job-id",
-                            ShareValidationError::DoesNotMeetTarget => "difficulty-too-low",
-                            ShareValidationError::DuplicateShare => "duplicate-share",
-                            _ => unreachable!(),
-                        };
-                        error!("❌ SubmitSharesError on downstream channel: ch={}, seq={}, error={code}", channel_id, msg.sequence_number);
-                        messages.push((downstream_id, build_error(code)).into());
+                        let reason = ShareRejectReason::from_validation_error(&err)
+                            .unwrap_or(ShareRejectReason::Invalid);
+                        error!("❌ SubmitSharesError on downstream channel: ch={}, seq={}, error={reason}", channel_id, msg.sequence_number);
+                        messages.push((downstream_id, build_error(reason)).into());
                     }
                 }
 
@@ -1224,11 +1237,11 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                             }
                             Err(err) => {
                                 let code = match err {
-                                    client::share_accounting::ShareValidationError::Invalid=>"invalid-share",
-                                    client::share_accounting::ShareValidationError::Stale=>"stale-share",
-                                    client::share_accounting::ShareValidationError::InvalidJobId=>"invalid-job-id",
-                                    client::share_accounting::ShareValidationError::DoesNotMeetTarget=>"difficulty-too-low",
-                                    client::share_accounting::ShareValidationError::DuplicateShare=>"duplicate-share",
+                                    client::share_accounting::ShareValidationError::Invalid=>ShareRejectReason::Invalid,
+                                    client::share_accounting::ShareValidationError::Stale=>ShareRejectReason::StaleJob,
+                                    client::share_accounting::ShareValidationError::InvalidJobId=>ShareRejectReason::JobNotFound,
+                                    client::share_accounting::ShareValidationError::DoesNotMeetTarget=>ShareRejectReason::AboveTarget,
+                                    client::share_accounting::ShareValidationError::DuplicateShare=>ShareRejectReason::Duplicate,
                                     _ => unreachable!(),
                                 };
                                 debug!("❌ SubmitSharesError not forwarding it to upstream: ch={}, seq={}, error={code}", channel_id, upstream_message.sequence_number);
@@ -1,9 +1,6 @@
 use stratum_apps::stratum_core::{
     binary_sv2::{self, Sv2DataType, B016M},
-    bitcoin::{
-        self, absolute::LockTime, transaction::Version, OutPoint, ScriptBuf, Sequence, Transaction,
-        TxIn, TxOut, Witness,
-    },
+    bitcoin::{self, TxOut},
     channels_sv2::outputs::deserialize_outputs,
     handlers_sv2::HandleJobDeclarationMessagesFromServerAsync,
     job_declaration_sv2::{
@@ -11,12 +8,12 @@ use stratum_apps::stratum_core::{
         ProvideMissingTransactions, ProvideMissingTransactionsSuccess,
     },
     parsers_sv2::{JobDeclaration, Mining, TemplateDistribution},
-    template_distribution_sv2::CoinbaseOutputConstraints,
 };
 use tracing::{debug, error, info, warn};
 
 use crate::{
     channel_manager::ChannelManager,
+    coinbase_outputs::{coinbase_output_constraints, encode_outputs},
     error::JDCError,
     status::{State, Status},
 };
@@ -28,7 +25,9 @@ impl HandleJobDeclarationMessagesFromServerAsync for ChannelManager {
     //
     // When the JDS confirms job token allocation:
     // - Updates the channel manager state with the newly issued token.
-    // - Checks whether the JDS has provided updated coinbase outputs.
+    // - Appends the extra coinbase outputs of the miner to the coinbase outputs provided by the
+    //   JDS. If they do not fit in a coinbase, the jobs only carry the outputs of the JDS.
+    // - Checks whether the coinbase outputs changed.
     //   - If outputs have changed, recalculates the corresponding size and sigops constraints.
     //   - Sends an updated `CoinbaseOutputConstraints` message to the Template Provider to ensure
     //     the new coinbase rules are enforced.
@@ -40,50 +39,37 @@ impl HandleJobDeclarationMessagesFromServerAsync for ChannelManager {
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
 
+        let jds_coinbase_outputs: Vec<TxOut> =
+            bitcoin::consensus::deserialize(&msg.coinbase_outputs.to_vec())
+                .map_err(JDCError::BitcoinEncodeError)?;
+        let coinbase_outputs =
+            match encode_outputs(jds_coinbase_outputs, &self.extra_coinbase_outputs) {
+                Ok(coinbase_outputs) => coinbase_outputs,
+                Err(e) => {
+                    error!("Extra coinbase outputs left out of the jobs: {e}");
+                    msg.coinbase_outputs.to_vec()
+                }
+            };
+
         let coinbase_changed = self.channel_manager_data.super_safe_lock(|data| {
-            let changed = data.coinbase_outputs != msg.coinbase_outputs.to_vec();
-            data.coinbase_outputs = msg.coinbase_outputs.to_vec();
+            let changed = data.coinbase_outputs != coinbase_outputs;
+            data.coinbase_outputs = coinbase_outputs.clone();
             data.allocate_tokens = Some(msg.clone().into_static());
             changed
         });
 
         if coinbase_changed {
             info!("Coinbase outputs from JDS changed, recalculating constraints");
-            let deserialized_jds_coinbase_outputs: Vec<TxOut> =
-                bitcoin::consensus::deserialize(&msg.coinbase_outputs.to_vec())
-                    .map_err(JDCError::BitcoinEncodeError)?;
-
-            let max_additional_size: usize = deserialized_jds_coinbase_outputs
-                .iter()
-                .map(|o| o.size())
-                .sum();
-
-            // create a dummy coinbase transaction with the empty output
-            // this is used to calculate the sigops of the coinbase output
-            let dummy_coinbase = Transaction {
-                version: Version::TWO,
-                lock_time: LockTime::ZERO,
-                input: vec![TxIn {
-                    previous_output: OutPoint::null(),
-                    script_sig: ScriptBuf::new(),
-                    sequence: Sequence::MAX,
-                    witness: Witness::from(vec![vec![0; 32]]),
-                }],
-                output: deserialized_jds_coinbase_outputs,
-            };
-
-            let max_additional_sigops = dummy_coinbase.total_sigop_cost(|_| None) as u16;
+            let constraints = coinbase_output_constraints(&coinbase_outputs)?;
 
             debug!(
-                max_additional_size,
-                max_additional_sigops, "Computed coinbase output constraints"
+                max_additional_size = constraints.coinbase_output_max_additional_size,
+                max_additional_sigops = constraints.coinbase_output_max_additional_sigops,
+                "Computed coinbase output constraints"
             );
 
             let coinbase_output_constraints_message =
-                TemplateDistribution::CoinbaseOutputConstraints(CoinbaseOutputConstraints {
-                    coinbase_output_max_additional_size: max_additional_size as u32,
-                    coinbase_output_max_additional_sigops: max_additional_sigops,
-                });
+                TemplateDistribution::CoinbaseOutputConstraints(constraints);
 
             self.channel_manager_channel
                 .tp_sender
@@ -105,8 +91,10 @@ impl HandleJobDeclarationMessagesFromServerAsync for ChannelManager {
     // since it indicates the JDS has rejected a declared mining job request.
     //
     // Upon receiving it:
-    // - Triggers the fallback mechanism by signaling a shutdown through the status channel, causing
-    //   the Job Declarator Client to enter `JobDeclaratorShutdownFallback`.
+    // - Increments the count of consecutive declaration errors for the current JDS.
+    // - Once `declare_mining_job_error_threshold` is reached, triggers the fallback mechanism by
+    //   signaling a shutdown through the status channel, causing the Job Declarator Client to enter
+    //   `JobDeclaratorShutdownFallback` and fail over to the next upstream pair.
     //
     // This ensures that the system does not continue relying on a potentially
     // untrustworthy or misbehaving JDS, and instead fails over to a safer state.
@@ -116,6 +104,18 @@ impl HandleJobDeclarationMessagesFromServerAsync for ChannelManager {
         msg: DeclareMiningJobError<'_>,
     ) -> Result<(), Self::Error> {
         warn!("Received: {}", msg);
+        self.declarations.answered(msg.request_id, false);
+        let errors = self.channel_manager_data.super_safe_lock(|data| {
+            data.declare_mining_job_errors += 1;
+            data.declare_mining_job_errors
+        });
+        if errors < self.declare_mining_job_error_threshold {
+            warn!(
+                "⚠️ JDS refused the declared job with a DeclareMiningJobError ({}/{}), keeping current upstream.",
+                errors, self.declare_mining_job_error_threshold
+            );
+            return Ok(());
+        }
         warn!("⚠️ JDS refused the declared job with a DeclareMiningJobError ❌. Starting fallback mechanism.");
         self.channel_manager_channel
             .status_sender
@@ -150,11 +150,12 @@ impl HandleJobDeclarationMessagesFromServerAsync for ChannelManager {
         msg: DeclareMiningJobSuccess<'_>,
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
+        self.declarations.answered(msg.request_id, true);
 
-        let Some(last_declare_job) = self
-            .channel_manager_data
-            .super_safe_lock(|data| data.last_declare_job_store.get(&msg.request_id).cloned())
-        else {
+        let Some(last_declare_job) = self.channel_manager_data.super_safe_lock(|data| {
+            data.declare_mining_job_errors = 0;
+            data.last_declare_job_store.get(&msg.request_id).cloned()
+        }) else {
             error!(
                 "No last_declare_job found for request_id={}",
                 msg.request_id
@@ -266,6 +267,13 @@ impl HandleJobDeclarationMessagesFromServerAsync for ChannelManager {
         if missing_txns.is_empty() {
             warn!("No matching transactions found for request_id={request_id}");
         }
+        let missing_tx_bytes: usize = unknown_positions
+            .iter()
+            .filter_map(|&pos| entry.tx_list.get(pos as usize))
+            .map(Vec::len)
+            .sum();
+        self.declarations
+            .missing_txs_provided(missing_txns.len(), missing_tx_bytes);
 
         let response = ProvideMissingTransactionsSuccess {
             request_id: msg.request_id,
@@ -285,3 +293,123 @@ impl HandleJobDeclarationMessagesFromServerAsync for ChannelManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_channel::{unbounded, Receiver};
+    use std::{path::Path, sync::Arc};
+    use stratum_apps::{
+        config_helpers::load_config, share_reject::ShareRejectCounters, status::HealthAggregator,
+    };
+    use tokio::sync::broadcast;
+
+    use crate::{
+        config::JobDeclaratorClientConfig, monitoring::DeclarationStats,
+        tx_selection::AllTransactions,
+    };
+
+    // Channel manager tolerating `threshold` errors, with the receiver of its status updates.
+    async fn channel_manager(threshold: u32) -> (ChannelManager, Receiver<Status>) {
+        let mut config: JobDeclaratorClientConfig = load_config(
+            Path::new(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/config-examples/jdc-config-local-example.toml"
+            )),
+            "SV2_JDC_TEST",
+        )
+        .unwrap();
+        config.set_declare_mining_job_error_threshold(threshold);
+        let (upstream_sender, upstream_receiver) = unbounded();
+        let (jd_sender, jd_receiver) = unbounded();
+        let (tp_sender, tp_receiver) = unbounded();
+        let (downstream_sender, _) = broadcast::channel(10);
+        let (_, downstream_receiver) = unbounded();
+        let (status_sender, status_receiver) = unbounded();
+        let channel_manager = ChannelManager::new(
+            config,
+            upstream_sender,
+            upstream_receiver,
+            jd_sender,
+            jd_receiver,
+            tp_sender,
+            tp_receiver,
+            downstream_sender,
+            downstream_receiver,
+            status_sender,
+            Vec::new(),
+            Arc::new(ShareRejectCounters::new()),
+            Arc::new(DeclarationStats::new()),
+            Arc::new(HealthAggregator::new()),
+            Arc::new(AllTransactions),
+        )
+        .await
+        .unwrap();
+        (channel_manager, status_receiver)
+    }
+
+    async fn refuse(channel_manager: &mut ChannelManager, request_id: u32) {
+        let error = DeclareMiningJobError {
+            request_id,
+            error_code: "invalid-job-param-value"
+                .to_string()
+                .into_bytes()
+                .try_into()
+                .unwrap(),
+            error_details: Vec::new().try_into().unwrap(),
+        };
+        channel_manager
+            .handle_declare_mining_job_error(None, error)
+            .await
+            .unwrap();
+    }
+
+    fn errors(channel_manager: &ChannelManager) -> u32 {
+        channel_manager
+            .channel_manager_data
+            .super_safe_lock(|data| data.declare_mining_job_errors)
+    }
+
+    #[tokio::test]
+    async fn falls_back_once_the_threshold_is_reached() {
+        let (mut channel_manager, status) = channel_manager(3).await;
+        refuse(&mut channel_manager, 1).await;
+        refuse(&mut channel_manager, 2).await;
+        assert!(status.try_recv().is_err());
+        refuse(&mut channel_manager, 3).await;
+        assert!(matches!(
+            status.try_recv().unwrap().state,
+            State::JobDeclaratorShutdownFallback(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn accepted_declarations_reset_the_errors() {
+        let (mut channel_manager, status) = channel_manager(2).await;
+        refuse(&mut channel_manager, 1).await;
+        let success = DeclareMiningJobSuccess {
+            request_id: 2,
+            new_mining_job_token: Vec::new().try_into().unwrap(),
+        };
+        // The errors are reset before the declared job is looked up, unknown here.
+        assert!(channel_manager
+            .handle_declare_mining_job_success(None, success)
+            .await
+            .is_err());
+        assert_eq!(errors(&channel_manager), 0);
+        refuse(&mut channel_manager, 3).await;
+        assert!(status.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn switching_upstream_resets_the_errors() {
+        let (mut channel_manager, status) = channel_manager(2).await;
+        refuse(&mut channel_manager, 1).await;
+        channel_manager
+            .channel_manager_data
+            .super_safe_lock(|data| data.reset(Vec::new()));
+        assert_eq!(errors(&channel_manager), 0);
+        refuse(&mut channel_manager, 2).await;
+        assert!(status.try_recv().is_err());
+    }
+}
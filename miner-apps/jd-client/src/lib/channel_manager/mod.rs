@@ -1,19 +1,27 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     net::SocketAddr,
     sync::{
         atomic::{AtomicU32, AtomicUsize, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 use async_channel::{Receiver, Sender};
 use stratum_apps::{
+    connections::ConnectionInfo,
     custom_mutex::Mutex,
     key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
-    network_helpers::noise_stream::NoiseTcpStream,
+    network_helpers::{
+        traffic::TrafficSnapshot,
+        transport::{Encryption, Sv2TcpStream},
+    },
+    runtime::{IoChannelsConfig, TaskManager},
+    share_reject::ShareRejectCounters,
+    status::HealthAggregator,
     stratum_core::{
-        bitcoin::Target,
+        bitcoin::{Target, TxOut},
         channels_sv2::{
             client::extended::ExtendedChannel,
             server::{
@@ -42,21 +50,28 @@ use stratum_apps::{
     },
 };
 use tokio::{net::TcpListener, select, sync::broadcast};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 use crate::{
-    channel_manager::downstream_message_handler::RouteMessageTo,
-    config::JobDeclaratorClientConfig,
+    channel_manager::{
+        chain_tip_verifier::{ChainTipVerifier, Verdict},
+        downstream_message_handler::RouteMessageTo,
+    },
+    config::{
+        DeclarationPolicy, JobDeclaratorClientConfig, UpstreamVerification, VerificationMode,
+    },
     downstream::Downstream,
     error::JDCError,
-    status::{handle_error, Status, StatusSender},
-    task_manager::TaskManager,
+    monitoring::DeclarationStats,
+    status::{handle_error, State, Status, StatusSender},
+    tx_selection::TxSelectionPolicy,
     utils::{
         AtomicUpstreamState, ChannelId, DownstreamChannelJobId, DownstreamId, Message,
         PendingChannelRequest, RequestId, ShutdownMessage, TemplateId, UpstreamJobId,
         UpstreamState, VardiffKey,
     },
 };
+mod chain_tip_verifier;
 mod downstream_message_handler;
 mod jd_message_handler;
 mod template_message_handler;
@@ -64,6 +79,9 @@ mod upstream_message_handler;
 
 pub const JDC_SEARCH_SPACE_BYTES: usize = 4;
 
+// Component reported to the health aggregator while the pool diverges from the Template Provider
+const UPSTREAM_VERIFICATION: &str = "upstream_verification";
+
 /// A `DeclaredJob` encapsulates all the relevant data associated with a single
 /// job declaration, including its template, optional messages, coinbase output,
 /// and transaction list.
@@ -138,6 +156,21 @@ pub struct ChannelManagerData {
     // Mapping of `(downstream_id, channel_id)` → vardiff controller.
     // Each entry manages variable difficulty for a specific downstream channel.
     vardiff: HashMap<VardiffKey, VardiffState>,
+    // Number of `DeclareMiningJobError` received from the current JDS since the last
+    // successful declaration.
+    declare_mining_job_errors: u32,
+    // Coinbase value of the last template a job was declared for, and when it was declared.
+    // Used to apply the declaration policy to non-future templates.
+    last_declared_template: Option<(u64, Instant)>,
+    // Compares the chain tip announced by the pool to the one of the Template Provider, if
+    // upstream verification is enabled.
+    chain_tip_verifier: Option<ChainTipVerifier>,
+    // Templates whose jobs are built once their transactions satisfy the transaction selection
+    // policy.
+    templates_awaiting_selection: HashSet<TemplateId>,
+    // `SetNewPrevHash` of the Template Provider whose future template was not mined yet, held
+    // until a template of the new chain tip satisfies the transaction selection policy.
+    held_prev_hash: Option<SetNewPrevHashTdp<'static>>,
 }
 
 impl ChannelManagerData {
@@ -174,6 +207,10 @@ impl ChannelManagerData {
         self.allocate_tokens = None;
         self.upstream_channel = None;
         self.pool_tag_string = None;
+        self.declare_mining_job_errors = 0;
+        self.last_declared_template = None;
+        self.templates_awaiting_selection.clear();
+        self.held_prev_hash = None;
 
         self.coinbase_outputs = coinbase_outputs;
     }
@@ -229,6 +266,16 @@ pub struct ChannelManager {
     share_batch_size: usize,
     shares_per_minute: f32,
     user_identity: String,
+    declare_mining_job_error_threshold: u32,
+    declaration_policy: DeclarationPolicy,
+    upstream_verification: UpstreamVerification,
+    // Zero-value outputs of the miner appended to the coinbase outputs provided by the JDS.
+    extra_coinbase_outputs: Vec<TxOut>,
+    // Decides which templates are mined, from their transactions.
+    tx_selection_policy: Arc<dyn TxSelectionPolicy>,
+    share_rejects: Arc<ShareRejectCounters>,
+    declarations: Arc<DeclarationStats>,
+    health: Arc<HealthAggregator>,
     /// This represent the current state of Upstream channel
     /// 1. NoChannel: No active upstream connection.
     /// 2. Pending: A channel request has been sent, awaiting response.
@@ -252,6 +299,10 @@ impl ChannelManager {
         downstream_receiver: Receiver<(DownstreamId, Mining<'static>)>,
         status_sender: Sender<Status>,
         coinbase_outputs: Vec<u8>,
+        share_rejects: Arc<ShareRejectCounters>,
+        declarations: Arc<DeclarationStats>,
+        health: Arc<HealthAggregator>,
+        tx_selection_policy: Arc<dyn TxSelectionPolicy>,
     ) -> Result<Self, JDCError> {
         let (range_0, range_1, range_2) = {
             let range_1 = 0..JDC_SEARCH_SPACE_BYTES;
@@ -269,6 +320,12 @@ impl ChannelManager {
 
         let extranonce_prefix_factory_extended = make_extranonce_factory();
         let extranonce_prefix_factory_standard = make_extranonce_factory();
+        let extra_coinbase_outputs = config
+            .extra_coinbase_outputs()
+            .map_err(JDCError::InvalidCoinbaseOutputs)?;
+        let upstream_verification = config.upstream_verification().clone();
+        let chain_tip_verifier = (upstream_verification.mode() != VerificationMode::Off)
+            .then(|| ChainTipVerifier::new(upstream_verification.grace()));
 
         let channel_manager_data = Arc::new(Mutex::new(ChannelManagerData {
             downstream: HashMap::new(),
@@ -290,6 +347,11 @@ impl ChannelManager {
             pending_downstream_requests: VecDeque::new(),
             job_factory: None,
             vardiff: HashMap::new(),
+            declare_mining_job_errors: 0,
+            last_declared_template: None,
+            chain_tip_verifier,
+            templates_awaiting_selection: HashSet::new(),
+            held_prev_hash: None,
         }));
 
         let channel_manager_channel = ChannelManagerChannel {
@@ -311,6 +373,14 @@ impl ChannelManager {
             shares_per_minute: config.shares_per_minute() as f32,
             miner_tag_string: config.jdc_signature().to_string(),
             user_identity: config.user_identity().to_string(),
+            declare_mining_job_error_threshold: config.declare_mining_job_error_threshold(),
+            declaration_policy: config.declaration_policy().clone(),
+            upstream_verification,
+            extra_coinbase_outputs,
+            tx_selection_policy,
+            share_rejects,
+            declarations,
+            health,
             upstream_state: AtomicUpstreamState::new(UpstreamState::SoloMining),
         };
 
@@ -325,6 +395,10 @@ impl ChannelManager {
         authority_secret_key: Secp256k1SecretKey,
         cert_validity_sec: u64,
         listening_address: SocketAddr,
+        encryption: Encryption,
+        max_frame_size: Option<usize>,
+        idle_timeout: Option<Duration>,
+        channels: IoChannelsConfig,
         task_manager: Arc<TaskManager>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         status_sender: Sender<Status>,
@@ -332,15 +406,18 @@ impl ChannelManager {
         channel_manager_receiver: broadcast::Sender<(DownstreamId, Mining<'static>)>,
     ) -> Result<(), JDCError> {
         info!("Starting downstream server at {listening_address}");
+        if encryption == Encryption::None {
+            warn!("Downstream connections are not encrypted, only use this on a trusted network");
+        }
         let server = TcpListener::bind(listening_address).await.map_err(|e| {
             error!(error = ?e, "Failed to bind downstream server at {listening_address}");
-            e
+            JDCError::Bind(listening_address, e)
         })?;
 
         let mut shutdown_rx = notify_shutdown.subscribe();
 
         let task_manager_clone = task_manager.clone();
-        task_manager.spawn(async move {
+        task_manager.spawn_critical("downstream_server", async move {
 
             loop {
                 select! {
@@ -380,13 +457,14 @@ impl ChannelManager {
                                         continue;
                                     }
                                 };
-                                let noise_stream = match NoiseTcpStream::<Message>::new(
+                                let stream = match Sv2TcpStream::<Message>::new(
                                     stream,
+                                    encryption,
                                     stratum_apps::stratum_core::codec_sv2::HandshakeRole::Responder(responder),
                                 )
                                 .await
                                 {
-                                    Ok(ns) => ns,
+                                    Ok(stream) => stream.with_max_frame_size(max_frame_size),
                                     Err(e) => {
                                         error!(error = ?e, "Noise handshake failed");
                                         continue;
@@ -397,15 +475,19 @@ impl ChannelManager {
                                     .channel_manager_data
                                     .super_safe_lock(|data| data.downstream_id_factory.fetch_add(1, Ordering::Relaxed));
 
-                                let downstream = Downstream::new(
+                                let span = info_span!("downstream", connection_id = downstream_id);
+                                let downstream = span.in_scope(|| Downstream::new(
                                     downstream_id,
+                                    socket_address,
                                     channel_manager_sender.clone(),
                                     channel_manager_receiver.clone(),
-                                    noise_stream,
+                                    stream,
                                     notify_shutdown.clone(),
                                     task_manager_clone.clone(),
                                     status_sender.clone(),
-                                );
+                                    idle_timeout,
+                                    channels,
+                                ));
 
                                 self.channel_manager_data.super_safe_lock(|data| {
                                     data.downstream.insert(downstream_id, downstream.clone());
@@ -417,6 +499,7 @@ impl ChannelManager {
                                         status_sender.clone(),
                                         task_manager_clone.clone(),
                                     )
+                                    .instrument(span)
                                     .await;
                                 }
 
@@ -446,11 +529,13 @@ impl ChannelManager {
         let status_sender = StatusSender::ChannelManager(status_sender);
         let mut shutdown_rx = notify_shutdown.subscribe();
 
-        task_manager.spawn(async move {
+        task_manager.spawn_critical("channel_manager", async move {
             let cm = self.clone();
             let vd = self.clone();
             let vardiff_future = vd.run_vardiff_loop();
             tokio::pin!(vardiff_future);
+            let verification_future = vd.run_upstream_verification_loop();
+            tokio::pin!(verification_future);
             loop {
                 let mut cm_jds = cm.clone();
                 let mut cm_pool = cm.clone();
@@ -473,12 +558,16 @@ impl ChannelManager {
                                 info!("Channel Manager: Job declarator shutdown signal");
                                 self.upstream_state.set(UpstreamState::SoloMining);
                                 self.channel_manager_data.super_safe_lock(|data| data.reset(coinbase_outputs));
+                                self.reset_upstream_verification();
+                                self.declarations.forget_pending();
                                 drop(tx);
                             }
                             Ok(ShutdownMessage::UpstreamShutdownFallback((coinbase_outputs,tx))) => {
                                 info!("Channel Manager: Upstream shutdown signal");
                                 self.upstream_state.set(UpstreamState::SoloMining);
                                 self.channel_manager_data.super_safe_lock(|data| data.reset(coinbase_outputs));
+                                self.reset_upstream_verification();
+                                self.declarations.forget_pending();
                                 drop(tx);
                             }
                             Err(e) => {
@@ -491,6 +580,7 @@ impl ChannelManager {
                     res = &mut vardiff_future => {
                         info!("Vardiff loop completed with: {res:?}");
                     }
+                    _ = &mut verification_future => {}
                     res = cm_jds.handle_jds_message() => {
                         if let Err(e) = res {
                             if !e.is_critical() {
@@ -635,120 +725,131 @@ impl ChannelManager {
             .recv()
             .await
         {
-            match message {
-                Mining::OpenExtendedMiningChannel(downstream_channel_request) => {
-                    let downstream_msg = downstream_channel_request.clone().into_static();
-
-                    match self.upstream_state.get() {
-                        UpstreamState::NoChannel => {
-                            self.channel_manager_data.super_safe_lock(|data| {
-                                data.pending_downstream_requests
-                                    .push_front((downstream_id, downstream_msg).into());
-                            });
-
-                            if self
-                                .upstream_state
-                                .compare_and_set(UpstreamState::NoChannel, UpstreamState::Pending)
-                                .is_ok()
-                            {
-                                let mut upstream_message = downstream_channel_request;
-                                upstream_message.user_identity =
-                                    self.user_identity.clone().try_into()?;
-                                upstream_message.request_id = 1;
-                                upstream_message.min_extranonce_size +=
-                                    JDC_SEARCH_SPACE_BYTES as u16;
-                                let upstream_message =
-                                    Mining::OpenExtendedMiningChannel(upstream_message)
-                                        .into_static();
-
-                                self.channel_manager_channel
-                                    .upstream_sender
-                                    .send(upstream_message)
-                                    .await
-                                    .map_err(|_| JDCError::ChannelErrorSender)?;
-                            }
-                        }
-                        UpstreamState::Pending => {
-                            self.channel_manager_data.super_safe_lock(|data| {
-                                data.pending_downstream_requests
-                                    .push_back((downstream_id, downstream_msg).into());
-                            });
-                        }
-                        UpstreamState::Connected => {
-                            self.send_open_channel_request_to_mining_handler(
-                                downstream_id,
-                                Mining::OpenExtendedMiningChannel(downstream_msg),
-                            )
-                            .await?;
-                        }
-                        UpstreamState::SoloMining => {
-                            self.send_open_channel_request_to_mining_handler(
-                                downstream_id,
-                                Mining::OpenExtendedMiningChannel(downstream_msg),
-                            )
-                            .await?;
+            self.route_downstream_message(downstream_id, message)
+                .instrument(info_span!("downstream", connection_id = downstream_id))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // Handles a message of the downstream `downstream_id`, see `handle_downstream_message`.
+    async fn route_downstream_message(
+        &mut self,
+        downstream_id: DownstreamId,
+        message: Mining<'static>,
+    ) -> Result<(), JDCError> {
+        match message {
+            Mining::OpenExtendedMiningChannel(downstream_channel_request) => {
+                let downstream_msg = downstream_channel_request.clone().into_static();
+
+                match self.upstream_state.get() {
+                    UpstreamState::NoChannel => {
+                        self.channel_manager_data.super_safe_lock(|data| {
+                            data.pending_downstream_requests
+                                .push_front((downstream_id, downstream_msg).into());
+                        });
+
+                        if self
+                            .upstream_state
+                            .compare_and_set(UpstreamState::NoChannel, UpstreamState::Pending)
+                            .is_ok()
+                        {
+                            let mut upstream_message = downstream_channel_request;
+                            upstream_message.user_identity =
+                                self.user_identity.clone().try_into()?;
+                            upstream_message.request_id = 1;
+                            upstream_message.min_extranonce_size += JDC_SEARCH_SPACE_BYTES as u16;
+                            let upstream_message =
+                                Mining::OpenExtendedMiningChannel(upstream_message).into_static();
+
+                            self.channel_manager_channel
+                                .upstream_sender
+                                .send(upstream_message)
+                                .await
+                                .map_err(|_| JDCError::ChannelErrorSender)?;
                         }
                     }
+                    UpstreamState::Pending => {
+                        self.channel_manager_data.super_safe_lock(|data| {
+                            data.pending_downstream_requests
+                                .push_back((downstream_id, downstream_msg).into());
+                        });
+                    }
+                    UpstreamState::Connected => {
+                        self.send_open_channel_request_to_mining_handler(
+                            downstream_id,
+                            Mining::OpenExtendedMiningChannel(downstream_msg),
+                        )
+                        .await?;
+                    }
+                    UpstreamState::SoloMining => {
+                        self.send_open_channel_request_to_mining_handler(
+                            downstream_id,
+                            Mining::OpenExtendedMiningChannel(downstream_msg),
+                        )
+                        .await?;
+                    }
                 }
-                Mining::OpenStandardMiningChannel(downstream_channel_request) => {
-                    let downstream_msg = downstream_channel_request.clone().into_static();
-
-                    match self.upstream_state.get() {
-                        UpstreamState::NoChannel => {
-                            self.channel_manager_data.super_safe_lock(|data| {
-                                data.pending_downstream_requests
-                                    .push_front((downstream_id, downstream_msg).into())
-                            });
-
-                            if self
-                                .upstream_state
-                                .compare_and_set(UpstreamState::NoChannel, UpstreamState::Pending)
-                                .is_ok()
-                            {
-                                let upstream_open = OpenExtendedMiningChannel {
-                                    user_identity: self.user_identity.clone().try_into().unwrap(),
-                                    request_id: 1,
-                                    nominal_hash_rate: downstream_channel_request.nominal_hash_rate,
-                                    max_target: downstream_channel_request.max_target,
-                                    min_extranonce_size: JDC_SEARCH_SPACE_BYTES as u16,
-                                };
+            }
+            Mining::OpenStandardMiningChannel(downstream_channel_request) => {
+                let downstream_msg = downstream_channel_request.clone().into_static();
+
+                match self.upstream_state.get() {
+                    UpstreamState::NoChannel => {
+                        self.channel_manager_data.super_safe_lock(|data| {
+                            data.pending_downstream_requests
+                                .push_front((downstream_id, downstream_msg).into())
+                        });
 
-                                let message =
-                                    Mining::OpenExtendedMiningChannel(upstream_open).into_static();
-                                self.channel_manager_channel
-                                    .upstream_sender
-                                    .send(message)
-                                    .await
-                                    .map_err(|_| JDCError::ChannelErrorSender)?;
-                            }
-                        }
-                        UpstreamState::Pending => {
-                            self.channel_manager_data.super_safe_lock(|data| {
-                                data.pending_downstream_requests
-                                    .push_back((downstream_id, downstream_msg).into())
-                            });
-                        }
-                        UpstreamState::Connected => {
-                            self.send_open_channel_request_to_mining_handler(
-                                downstream_id,
-                                Mining::OpenStandardMiningChannel(downstream_msg),
-                            )
-                            .await?;
-                        }
-                        UpstreamState::SoloMining => {
-                            self.send_open_channel_request_to_mining_handler(
-                                downstream_id,
-                                Mining::OpenStandardMiningChannel(downstream_msg),
-                            )
-                            .await?;
+                        if self
+                            .upstream_state
+                            .compare_and_set(UpstreamState::NoChannel, UpstreamState::Pending)
+                            .is_ok()
+                        {
+                            let upstream_open = OpenExtendedMiningChannel {
+                                user_identity: self.user_identity.clone().try_into().unwrap(),
+                                request_id: 1,
+                                nominal_hash_rate: downstream_channel_request.nominal_hash_rate,
+                                max_target: downstream_channel_request.max_target,
+                                min_extranonce_size: JDC_SEARCH_SPACE_BYTES as u16,
+                            };
+
+                            let message =
+                                Mining::OpenExtendedMiningChannel(upstream_open).into_static();
+                            self.channel_manager_channel
+                                .upstream_sender
+                                .send(message)
+                                .await
+                                .map_err(|_| JDCError::ChannelErrorSender)?;
                         }
                     }
-                }
-                _ => {
-                    self.handle_mining_message_from_client(Some(downstream_id), message)
+                    UpstreamState::Pending => {
+                        self.channel_manager_data.super_safe_lock(|data| {
+                            data.pending_downstream_requests
+                                .push_back((downstream_id, downstream_msg).into())
+                        });
+                    }
+                    UpstreamState::Connected => {
+                        self.send_open_channel_request_to_mining_handler(
+                            downstream_id,
+                            Mining::OpenStandardMiningChannel(downstream_msg),
+                        )
+                        .await?;
+                    }
+                    UpstreamState::SoloMining => {
+                        self.send_open_channel_request_to_mining_handler(
+                            downstream_id,
+                            Mining::OpenStandardMiningChannel(downstream_msg),
+                        )
                         .await?;
+                    }
                 }
             }
+            _ => {
+                self.handle_mining_message_from_client(Some(downstream_id), message)
+                    .await?;
+            }
         }
 
         Ok(())
@@ -899,6 +1000,7 @@ impl ChannelManager {
     // # Purpose
     // - Executes the vardiff cycle every 60 seconds for all downstreams.
     // - Delegates to [`Self::run_vardiff`] on each tick.
+    // - Logs the traffic of every downstream on each tick.
     async fn run_vardiff_loop(&self) -> Result<(), JDCError> {
         let mut ticker = tokio::time::interval(std::time::Duration::from_secs(60));
         loop {
@@ -908,6 +1010,126 @@ impl ChannelManager {
             if let Err(e) = self.run_vardiff().await {
                 error!(error = ?e, "Vardiff iteration failed");
             }
+            self.log_downstream_traffic();
+        }
+    }
+
+    // Checks the pool against the Template Provider every second, so that a divergence is
+    // reported as soon as its grace period elapsed, whether or not a message follows.
+    async fn run_upstream_verification_loop(&self) {
+        if self.upstream_verification.mode() == VerificationMode::Off {
+            return std::future::pending().await;
+        }
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            ticker.tick().await;
+            self.verify_upstream().await;
+        }
+    }
+
+    // Raises or clears the alarm on the chain tip of the pool, and leaves the pool when it
+    // diverged if the verification refuses its work.
+    async fn verify_upstream(&self) {
+        let verdict = self.channel_manager_data.super_safe_lock(|data| {
+            data.chain_tip_verifier
+                .as_mut()
+                .and_then(|verifier| verifier.check(Instant::now()))
+        });
+        match verdict {
+            Some(Verdict::Diverged(reason)) => {
+                error!("Pool diverged from the Template Provider: {reason}");
+                self.health.degraded(UPSTREAM_VERIFICATION, &reason);
+                if self.upstream_verification.mode() == VerificationMode::Refuse {
+                    warn!("⚠️ Refusing the work of the pool, starting fallback mechanism.");
+                    _ = self
+                        .channel_manager_channel
+                        .status_sender
+                        .send(Status {
+                            state: State::UpstreamShutdownFallback(
+                                JDCError::UpstreamChainTipDiverged(reason),
+                            ),
+                        })
+                        .await;
+                }
+            }
+            Some(Verdict::Agreed) => {
+                info!("Pool agrees with the Template Provider again");
+                self.health.healthy(UPSTREAM_VERIFICATION);
+            }
+            None => {}
+        }
+    }
+
+    // Forgets the chain tip of the pool left, clearing the alarm it raised if any.
+    fn reset_upstream_verification(&self) {
+        let diverged = self.channel_manager_data.super_safe_lock(|data| {
+            data.chain_tip_verifier
+                .as_mut()
+                .is_some_and(|verifier| verifier.reset_pool())
+        });
+        if diverged {
+            self.health.healthy(UPSTREAM_VERIFICATION);
+        }
+    }
+
+    /// Describes the downstream connections with their traffic, by id.
+    pub fn connections(&self) -> Vec<(ConnectionInfo, TrafficSnapshot)> {
+        let mut downstreams = self
+            .channel_manager_data
+            .super_safe_lock(|data| data.downstream.values().cloned().collect::<Vec<_>>());
+        downstreams.sort_unstable_by_key(|downstream| downstream.downstream_id);
+        downstreams
+            .into_iter()
+            .map(|downstream| {
+                let mut channels = downstream.downstream_data.super_safe_lock(|data| {
+                    data.standard_channels
+                        .keys()
+                        .chain(data.extended_channels.keys())
+                        .copied()
+                        .collect::<Vec<_>>()
+                });
+                channels.sort_unstable();
+                let traffic = downstream.traffic.snapshot();
+                let info = ConnectionInfo {
+                    id: downstream.downstream_id as u64,
+                    address: downstream.address.to_string(),
+                    connected_secs: traffic.connected_secs,
+                    channels,
+                };
+                (info, traffic)
+            })
+            .collect()
+    }
+
+    // Logs the frame and byte counters of every downstream connection, so that chatty or stuck
+    // downstreams stand out, along with the high watermarks of its channels.
+    fn log_downstream_traffic(&self) {
+        let downstreams = self.channel_manager_data.super_safe_lock(|data| {
+            data.downstream
+                .iter()
+                .map(|(id, downstream)| {
+                    (
+                        *id,
+                        downstream.traffic.snapshot(),
+                        downstream.outbound_dropped(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        });
+        for (downstream_id, traffic, outbound_dropped) in downstreams {
+            info!(
+                downstream_id,
+                frames_received = traffic.frames_received,
+                bytes_received = traffic.bytes_received,
+                frames_sent = traffic.frames_sent,
+                bytes_sent = traffic.bytes_sent,
+                frames_received_per_minute = traffic.frames_received_per_minute(),
+                last_received_secs_ago = ?traffic.last_received_secs_ago,
+                inbound_high_watermark = traffic.inbound_high_watermark,
+                outbound_high_watermark = traffic.outbound_high_watermark,
+                outbound_dropped,
+                "Downstream traffic"
+            );
         }
     }
 
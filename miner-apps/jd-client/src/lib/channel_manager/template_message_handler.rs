@@ -1,4 +1,4 @@
-use std::sync::atomic::Ordering;
+use std::{sync::atomic::Ordering, time::Instant};
 
 use stratum_apps::stratum_core::{
     binary_sv2::{Seq064K, U256},
@@ -10,10 +10,14 @@ use stratum_apps::stratum_core::{
     parsers_sv2::{JobDeclaration, Mining, TemplateDistribution},
     template_distribution_sv2::*,
 };
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    channel_manager::{downstream_message_handler::RouteMessageTo, ChannelManager, DeclaredJob},
+    channel_manager::{
+        chain_tip_verifier::script_sig_height, downstream_message_handler::RouteMessageTo,
+        ChannelManager, DeclaredJob,
+    },
+    config::block_subsidy,
     error::JDCError,
     jd_mode::{get_jd_mode, JdMode},
 };
@@ -33,6 +37,10 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
     //
     // Also updates future/active template state and triggers token
     // allocation if needed.
+    //
+    // Unless solo mining, non-future templates not satisfying the configured
+    // `DeclarationPolicy` (fee increase, minimum interval) are dropped, and
+    // downstreams keep mining on the last declared job.
     async fn handle_new_template(
         &mut self,
         _server_id: Option<usize>,
@@ -40,18 +48,38 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
 
-        let coinbase_outputs = self.channel_manager_data.super_safe_lock(|data| {
+        if !msg.future_template && get_jd_mode() != JdMode::SoloMining {
+            let subsidy =
+                script_sig_height(msg.coinbase_prefix.inner_as_ref()).map_or(0, block_subsidy);
+            let should_declare = self.channel_manager_data.super_safe_lock(|data| {
+                match data.last_declared_template {
+                    Some((last_value, declared_at)) => self.declaration_policy.should_declare(
+                        last_value,
+                        msg.coinbase_tx_value_remaining,
+                        subsidy,
+                        declared_at.elapsed(),
+                    ),
+                    None => true,
+                }
+            });
+            if !should_declare {
+                debug!(
+                    "Template {} does not satisfy the declaration policy, skipping it",
+                    msg.template_id
+                );
+                return Ok(());
+            }
+        }
+
+        let selects_transactions = self.selects_transactions();
+        self.channel_manager_data.super_safe_lock(|data| {
             data.template_store
                 .insert(msg.template_id, msg.clone().into_static());
-            if msg.future_template {
-                data.last_future_template = Some(msg.clone().into_static());
+            if selects_transactions {
+                data.templates_awaiting_selection.insert(msg.template_id);
             }
-            data.coinbase_outputs.clone()
         });
 
-        let mut coinbase_outputs = deserialize_outputs(coinbase_outputs)
-            .map_err(|_| JDCError::ChannelManagerHasBadCoinbaseOutputs)?;
-
         if get_jd_mode() == JdMode::FullTemplate {
             let tx_data_request =
                 TemplateDistribution::RequestTransactionData(RequestTransactionData {
@@ -65,182 +93,15 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                 .map_err(|_e| JDCError::ChannelErrorSender)?;
         }
 
-        let messages = self.channel_manager_data.super_safe_lock(|channel_manager_data| {
-            let mut messages: Vec<RouteMessageTo> = Vec::new();
-            coinbase_outputs[0].value = Amount::from_sat(msg.coinbase_tx_value_remaining);
-
-            for (downstream_id, downstream) in channel_manager_data.downstream.iter_mut() {
-
-                let  messages_ = downstream.downstream_data.super_safe_lock(|data| {
-
-                    let mut messages: Vec<RouteMessageTo> = vec![];
-
-                    let group_channel_job = if let Some(ref mut group_channel) = data.group_channels {
-                        if group_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()).is_ok() {
-                            match msg.future_template {
-                                true => {
-                                    let future_job_id = group_channel
-                                            .get_future_template_to_job_id()
-                                            .get(&msg.template_id)
-                                            .expect("job_id must exist");
-                                    Some(group_channel
-                                        .get_future_jobs()
-                                        .get(future_job_id)
-                                        .expect("future job must exist")).cloned()
-                                },
-                                false => {
-                                    Some(group_channel
-                                        .get_active_job()
-                                        .expect("active job must exist")).cloned()
-                                }
-                            }
-                        } else {
-                            tracing::error!("Some issue with downstream: {downstream_id}, group channel");
-                            None
-                        }
-                    } else {
-                        None
-                    };
-
-                    if let Some(upstream_channel) = channel_manager_data.upstream_channel.as_mut() {
-                        if !msg.future_template && get_jd_mode() == JdMode::CoinbaseOnly {
-                                if let (Some(token), Some(prevhash)) = (
-                                    channel_manager_data.allocate_tokens.clone(),
-                                    channel_manager_data.last_new_prev_hash.clone(),
-                                ) {
-                                    let request_id = channel_manager_data.request_id_factory.fetch_add(1, Ordering::Relaxed);
-                                    let job_factory = channel_manager_data.job_factory.as_mut().unwrap();
-                                    let full_extranonce_size = upstream_channel.get_full_extranonce_size();
-                                    let custom_job = job_factory.new_custom_job(upstream_channel.get_channel_id(), request_id, token.clone().mining_job_token, prevhash.clone().into(), msg.clone(), coinbase_outputs.clone(), full_extranonce_size);
-
-                                    if let Ok(custom_job) = custom_job{
-                                        let last_declare = DeclaredJob {
-                                            declare_mining_job: None,
-                                            template: msg.clone().into_static(),
-                                            prev_hash: Some(prevhash),
-                                            set_custom_mining_job: Some(custom_job.clone().into_static()),
-                                            coinbase_output: channel_manager_data.coinbase_outputs.clone(),
-                                            tx_list: Vec::new(),
-                                        };
-                                        channel_manager_data
-                                            .last_declare_job_store
-                                            .insert(request_id, last_declare);
-                                        messages.push(
-                                            Mining::SetCustomMiningJob(custom_job).into()
-                                        );
-                                    }
-                                }
-                        }
-                    }
-                    match msg.future_template {
-                        true => {
-                            for (channel_id, standard_channel) in data.standard_channels.iter_mut() {
-                                if data.group_channels.is_none() {
-                                    if let Err(e) = standard_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
-                                        tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
-                                        continue;
-                                    }
-                                    let standard_job_id = standard_channel.get_future_template_to_job_id().get(&msg.template_id).expect("job_id must exist");
-                                    let standard_job = standard_channel.get_future_jobs().get(standard_job_id).expect("standard job must exist");
-                                    channel_manager_data.downstream_channel_id_and_job_id_to_template_id.insert((*downstream_id, *channel_id, *standard_job_id).into(), msg.template_id);
-                                    let standard_job_message = standard_job.get_job_message();
-                                    messages.push((*downstream_id, Mining::NewMiningJob(standard_job_message.clone())).into());
-                                }
-                                if let Some(ref group_channel_job) = group_channel_job {
-                                    if let Err(e) = standard_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
-                                        tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
-                                        continue;
-                                    }
-                                    _ = standard_channel
-                                    .on_group_channel_job(group_channel_job.clone());
-                                }
-                            }
-                            if let Some(group_channel_job) = group_channel_job {
-                                let job_message = group_channel_job.get_job_message();
-                                messages.push((*downstream_id, Mining::NewExtendedMiningJob(job_message.clone())).into());
-                            }
-
-                            for (channel_id, extended_channel) in data.extended_channels.iter_mut() {
-                                if let Err(e) = extended_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
-                                    tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
-                                    continue;
-                                }
-                                let extended_job_id = extended_channel
-                                    .get_future_template_to_job_id()
-                                    .get(&msg.template_id)
-                                    .expect("job_id must exist");
-
-                                let extended_job = extended_channel
-                                    .get_future_jobs()
-                                    .get(extended_job_id)
-                                    .expect("extended job must exist");
-
-                                channel_manager_data.downstream_channel_id_and_job_id_to_template_id.insert((*downstream_id, *channel_id, *extended_job_id).into(), msg.template_id);
-                                let extended_job_message = extended_job.get_job_message();
-
-                                messages.push((*downstream_id,Mining::NewExtendedMiningJob(extended_job_message.clone())).into());
-                            }
-                        }
-                        false => {
-                            for (channel_id, standard_channel) in data.standard_channels.iter_mut() {
-                                if data.group_channels.is_none() {
-                                    if let Err(e) = standard_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
-                                        tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
-                                        continue;
-                                    }
-                                    let standard_job = standard_channel.get_active_job().expect("standard job must exist");
-                                    channel_manager_data.downstream_channel_id_and_job_id_to_template_id.insert((*downstream_id, *channel_id, standard_job.get_job_id()).into(), msg.template_id);
-                                    let standard_job_message = standard_job.get_job_message();
-                                    messages.push((*downstream_id, Mining::NewMiningJob(standard_job_message.clone())).into());
-                                }
-                                if let Some(ref group_channel_job) = group_channel_job {
-                                    if let Err(e) = standard_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
-                                        tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
-                                        continue;
-                                    }
-                                    _ = standard_channel
-                                    .on_group_channel_job(group_channel_job.clone());
-                                }
-                            }
-                            if let Some(group_channel_job) = group_channel_job {
-                                let job_message = group_channel_job.get_job_message();
-                                messages.push((*downstream_id, Mining::NewExtendedMiningJob(job_message.clone())).into());
-                            }
-
-                            for (channel_id, extended_channel) in data.extended_channels.iter_mut() {
-                                if let Err(e) = extended_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
-                                    tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
-                                    continue;
-                                }
-                                let extended_job = extended_channel
-                                    .get_active_job()
-                                    .expect("extended job must exist");
-
-                                channel_manager_data.downstream_channel_id_and_job_id_to_template_id.insert((*downstream_id, *channel_id, extended_job.get_job_id()).into(), msg.template_id);
-                                let extended_job_message = extended_job.get_job_message();
-
-                                messages.push((*downstream_id,Mining::NewExtendedMiningJob(extended_job_message.clone())).into());
-                            }
-                        }
-                    }
-
-                    messages
-
-                });
-                messages.extend(messages_);
-            }
-            messages
-        });
-
-        if get_jd_mode() == JdMode::CoinbaseOnly && !msg.future_template {
-            _ = self.allocate_tokens(1).await;
-        }
-
-        for message in messages {
-            message.forward(&self.channel_manager_channel).await;
+        if selects_transactions {
+            debug!(
+                "Template {} awaiting its transactions for the selection policy",
+                msg.template_id
+            );
+            return Ok(());
         }
 
-        Ok(())
+        self.distribute_template(msg.into_static()).await
     }
 
     // Handles a `RequestTransactionDataError` message from the Template Provider.
@@ -264,6 +125,8 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
     // Handles a `RequestTransactionDataSuccess` message from the Template Provider.
     //
     // Flow:
+    // - If the template awaits the selection of its transactions, drop it unless they satisfy the
+    //   transaction selection policy, otherwise build and dispatch its jobs.
     // - If the template is not a future template, immediately declare a mining job to JDS.
     // - If the template is a future template:
     //   - Check if the current `prevhash` activates this template.
@@ -279,6 +142,19 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
         let transactions_data = msg.transaction_list;
         let excess_data = msg.excess_data;
 
+        let tx_list: Vec<Transaction> = transactions_data
+            .to_vec()
+            .iter()
+            .map(|raw_tx| consensus::deserialize(raw_tx).expect("invalid tx"))
+            .collect();
+
+        let awaiting_selection = self
+            .channel_manager_data
+            .super_safe_lock(|data| data.templates_awaiting_selection.remove(&msg.template_id));
+        if awaiting_selection && !self.select_template(msg.template_id, &tx_list).await? {
+            return Ok(());
+        }
+
         let coinbase_outputs = self
             .channel_manager_data
             .super_safe_lock(|data| data.coinbase_outputs.clone());
@@ -312,12 +188,6 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
             Amount::from_sat(template_message.coinbase_tx_value_remaining);
         let reserialized_outputs = consensus::serialize(&deserialized_outputs);
 
-        let tx_list: Vec<Transaction> = transactions_data
-            .to_vec()
-            .iter()
-            .map(|raw_tx| consensus::deserialize(raw_tx).expect("invalid tx"))
-            .collect();
-
         let txids_as_u256: Vec<U256<'static>> = tx_list
             .iter()
             .map(|tx| {
@@ -378,12 +248,21 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
         });
 
         if is_activated_future_template {
-            return Ok(());
+            return self.release_held_prev_hash(msg.template_id).await;
         }
 
         if let Some(declare_job) = declare_job {
+            let request_id = declare_job.request_id;
             let message = JobDeclaration::DeclareMiningJob(declare_job);
-            _ = self.channel_manager_channel.jd_sender.send(message).await;
+            if self
+                .channel_manager_channel
+                .jd_sender
+                .send(message)
+                .await
+                .is_ok()
+            {
+                self.declarations.declared(request_id);
+            }
         }
 
         Ok(())
@@ -396,6 +275,10 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
     // - In CoinbaseOnly mode → send a `CustomMiningJob` for the activated future template.
     // - Update the upstream channel state.
     // - Update all downstream channels and propagate the new `prevhash` via `SetNewPrevHash`.
+    // - Record the new chain tip, to check the one announced by the pool against it.
+    //
+    // If the future template it activates awaits the selection of its transactions, or was not
+    // selected, the `prevhash` is held until a template of the new chain tip is selected.
     async fn handle_set_new_prev_hash(
         &mut self,
         _server_id: Option<usize>,
@@ -403,6 +286,26 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
 
+        if self.selects_transactions() {
+            let held = self.channel_manager_data.super_safe_lock(|data| {
+                let activates_future_job = data
+                    .last_future_template
+                    .as_ref()
+                    .is_some_and(|template| template.template_id == msg.template_id);
+                if !activates_future_job {
+                    data.held_prev_hash = Some(msg.clone().into_static());
+                }
+                !activates_future_job
+            });
+            if held {
+                debug!(
+                    "Holding prevhash of template {} until a template of its chain tip is selected",
+                    msg.template_id
+                );
+                return Ok(());
+            }
+        }
+
         let coinbase_outputs = self
             .channel_manager_data
             .super_safe_lock(|data| data.coinbase_outputs.clone());
@@ -433,6 +336,7 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
 
         if get_jd_mode() == JdMode::FullTemplate {
             if let Some(Some(job)) = declare_job {
+                let request_id = job.request_id;
                 let message = JobDeclaration::DeclareMiningJob(job);
 
                 self.channel_manager_channel
@@ -440,11 +344,25 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                     .send(message)
                     .await
                     .map_err(|_e| JDCError::ChannelErrorSender)?;
+                self.declarations.declared(request_id);
             }
         }
 
         let messages = self.channel_manager_data.super_safe_lock(|data| {
             data.last_new_prev_hash = Some(msg.clone().into_static());
+            let activated_template = future_template
+                .as_ref()
+                .filter(|template| template.template_id == msg.template_id);
+            if let Some(template) = activated_template {
+                data.last_declared_template =
+                    Some((template.coinbase_tx_value_remaining, Instant::now()));
+            }
+            if let Some(verifier) = data.chain_tip_verifier.as_mut() {
+                let height = activated_template.and_then(|template| {
+                    script_sig_height(template.coinbase_prefix.inner_as_ref())
+                });
+                verifier.on_local_prev_hash(msg.prev_hash.inner_as_ref(), height, Instant::now());
+            }
             data.last_declare_job_store.iter_mut().for_each(|(_k, v)| {
                 if v.template.future_template && v.template.template_id == msg.template_id {
                     v.prev_hash = Some(msg.clone().into_static());
@@ -603,3 +521,271 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
         Ok(())
     }
 }
+
+impl ChannelManager {
+    // Returns `true` if the jobs of a template are only built once its transactions satisfy the
+    // transaction selection policy.
+    fn selects_transactions(&self) -> bool {
+        get_jd_mode() == JdMode::FullTemplate && !self.tx_selection_policy.is_passthrough()
+    }
+
+    // Builds and dispatches the jobs of a template awaiting the selection of its transactions,
+    // if they satisfy the transaction selection policy. Returns `false` if the template is
+    // dropped.
+    //
+    // While a `prevhash` is held, the templates of its chain tip are dispatched as the future
+    // template it activates, and those of the previous chain tip are dropped.
+    async fn select_template(
+        &mut self,
+        template_id: u64,
+        transactions: &[Transaction],
+    ) -> Result<bool, JDCError> {
+        let rejection = self.tx_selection_policy.rejects(transactions);
+        let template = self.channel_manager_data.super_safe_lock(|data| {
+            let mut template = data.template_store.remove(&template_id)?;
+            if let Some(reason) = rejection {
+                warn!("Template {template_id} not mined, {reason}");
+                return None;
+            }
+            match data.held_prev_hash.as_mut() {
+                Some(prev_hash) if template_id >= prev_hash.template_id => {
+                    prev_hash.template_id = template_id;
+                    template.future_template = true;
+                }
+                Some(_) => {
+                    debug!("Template {template_id} of the previous chain tip dropped");
+                    return None;
+                }
+                None => {
+                    let stale = !template.future_template
+                        && data
+                            .last_new_prev_hash
+                            .as_ref()
+                            .is_some_and(|prev_hash| template_id < prev_hash.template_id);
+                    if stale {
+                        debug!("Template {template_id} of the previous chain tip dropped");
+                        return None;
+                    }
+                }
+            }
+            data.template_store.insert(template_id, template.clone());
+            Some(template)
+        });
+
+        let Some(template) = template else {
+            return Ok(false);
+        };
+        self.distribute_template(template).await?;
+        Ok(true)
+    }
+
+    // Activates the future template `template_id` with the `prevhash` held for it, if any.
+    async fn release_held_prev_hash(&mut self, template_id: u64) -> Result<(), JDCError> {
+        let held_prev_hash =
+            self.channel_manager_data
+                .super_safe_lock(|data| match data.held_prev_hash.as_ref() {
+                    Some(prev_hash) if prev_hash.template_id == template_id => {
+                        data.held_prev_hash.take()
+                    }
+                    _ => None,
+                });
+        match held_prev_hash {
+            Some(prev_hash) => self.handle_set_new_prev_hash(None, prev_hash).await,
+            None => Ok(()),
+        }
+    }
+
+    // Builds the jobs of a template for all downstream channels and dispatches them, as well as
+    // the `SetCustomMiningJob` of the upstream channel in CoinbaseOnly mode.
+    async fn distribute_template(&mut self, msg: NewTemplate<'static>) -> Result<(), JDCError> {
+        let coinbase_outputs = self.channel_manager_data.super_safe_lock(|data| {
+            if msg.future_template {
+                data.last_future_template = Some(msg.clone());
+            } else {
+                data.last_declared_template =
+                    Some((msg.coinbase_tx_value_remaining, Instant::now()));
+            }
+            data.coinbase_outputs.clone()
+        });
+
+        let mut coinbase_outputs = deserialize_outputs(coinbase_outputs)
+            .map_err(|_| JDCError::ChannelManagerHasBadCoinbaseOutputs)?;
+
+        let messages = self.channel_manager_data.super_safe_lock(|channel_manager_data| {
+            let mut messages: Vec<RouteMessageTo> = Vec::new();
+            coinbase_outputs[0].value = Amount::from_sat(msg.coinbase_tx_value_remaining);
+
+            for (downstream_id, downstream) in channel_manager_data.downstream.iter_mut() {
+
+                let  messages_ = downstream.downstream_data.super_safe_lock(|data| {
+
+                    let mut messages: Vec<RouteMessageTo> = vec![];
+
+                    let group_channel_job = if let Some(ref mut group_channel) = data.group_channels {
+                        if group_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()).is_ok() {
+                            match msg.future_template {
+                                true => {
+                                    let future_job_id = group_channel
+                                            .get_future_template_to_job_id()
+                                            .get(&msg.template_id)
+                                            .expect("job_id must exist");
+                                    Some(group_channel
+                                        .get_future_jobs()
+                                        .get(future_job_id)
+                                        .expect("future job must exist")).cloned()
+                                },
+                                false => {
+                                    Some(group_channel
+                                        .get_active_job()
+                                        .expect("active job must exist")).cloned()
+                                }
+                            }
+                        } else {
+                            tracing::error!("Some issue with downstream: {downstream_id}, group channel");
+                            None
+                        }
+                    } else {
+                        None
+                    };
+
+                    if let Some(upstream_channel) = channel_manager_data.upstream_channel.as_mut() {
+                        if !msg.future_template && get_jd_mode() == JdMode::CoinbaseOnly {
+                                if let (Some(token), Some(prevhash)) = (
+                                    channel_manager_data.allocate_tokens.clone(),
+                                    channel_manager_data.last_new_prev_hash.clone(),
+                                ) {
+                                    let request_id = channel_manager_data.request_id_factory.fetch_add(1, Ordering::Relaxed);
+                                    let job_factory = channel_manager_data.job_factory.as_mut().unwrap();
+                                    let full_extranonce_size = upstream_channel.get_full_extranonce_size();
+                                    let custom_job = job_factory.new_custom_job(upstream_channel.get_channel_id(), request_id, token.clone().mining_job_token, prevhash.clone().into(), msg.clone(), coinbase_outputs.clone(), full_extranonce_size);
+
+                                    if let Ok(custom_job) = custom_job{
+                                        let last_declare = DeclaredJob {
+                                            declare_mining_job: None,
+                                            template: msg.clone().into_static(),
+                                            prev_hash: Some(prevhash),
+                                            set_custom_mining_job: Some(custom_job.clone().into_static()),
+                                            coinbase_output: channel_manager_data.coinbase_outputs.clone(),
+                                            tx_list: Vec::new(),
+                                        };
+                                        channel_manager_data
+                                            .last_declare_job_store
+                                            .insert(request_id, last_declare);
+                                        messages.push(
+                                            Mining::SetCustomMiningJob(custom_job).into()
+                                        );
+                                    }
+                                }
+                        }
+                    }
+                    match msg.future_template {
+                        true => {
+                            for (channel_id, standard_channel) in data.standard_channels.iter_mut() {
+                                if data.group_channels.is_none() {
+                                    if let Err(e) = standard_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
+                                        tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
+                                        continue;
+                                    }
+                                    let standard_job_id = standard_channel.get_future_template_to_job_id().get(&msg.template_id).expect("job_id must exist");
+                                    let standard_job = standard_channel.get_future_jobs().get(standard_job_id).expect("standard job must exist");
+                                    channel_manager_data.downstream_channel_id_and_job_id_to_template_id.insert((*downstream_id, *channel_id, *standard_job_id).into(), msg.template_id);
+                                    let standard_job_message = standard_job.get_job_message();
+                                    messages.push((*downstream_id, Mining::NewMiningJob(standard_job_message.clone())).into());
+                                }
+                                if let Some(ref group_channel_job) = group_channel_job {
+                                    if let Err(e) = standard_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
+                                        tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
+                                        continue;
+                                    }
+                                    _ = standard_channel
+                                    .on_group_channel_job(group_channel_job.clone());
+                                }
+                            }
+                            if let Some(group_channel_job) = group_channel_job {
+                                let job_message = group_channel_job.get_job_message();
+                                messages.push((*downstream_id, Mining::NewExtendedMiningJob(job_message.clone())).into());
+                            }
+
+                            for (channel_id, extended_channel) in data.extended_channels.iter_mut() {
+                                if let Err(e) = extended_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
+                                    tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
+                                    continue;
+                                }
+                                let extended_job_id = extended_channel
+                                    .get_future_template_to_job_id()
+                                    .get(&msg.template_id)
+                                    .expect("job_id must exist");
+
+                                let extended_job = extended_channel
+                                    .get_future_jobs()
+                                    .get(extended_job_id)
+                                    .expect("extended job must exist");
+
+                                channel_manager_data.downstream_channel_id_and_job_id_to_template_id.insert((*downstream_id, *channel_id, *extended_job_id).into(), msg.template_id);
+                                let extended_job_message = extended_job.get_job_message();
+
+                                messages.push((*downstream_id,Mining::NewExtendedMiningJob(extended_job_message.clone())).into());
+                            }
+                        }
+                        false => {
+                            for (channel_id, standard_channel) in data.standard_channels.iter_mut() {
+                                if data.group_channels.is_none() {
+                                    if let Err(e) = standard_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
+                                        tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
+                                        continue;
+                                    }
+                                    let standard_job = standard_channel.get_active_job().expect("standard job must exist");
+                                    channel_manager_data.downstream_channel_id_and_job_id_to_template_id.insert((*downstream_id, *channel_id, standard_job.get_job_id()).into(), msg.template_id);
+                                    let standard_job_message = standard_job.get_job_message();
+                                    messages.push((*downstream_id, Mining::NewMiningJob(standard_job_message.clone())).into());
+                                }
+                                if let Some(ref group_channel_job) = group_channel_job {
+                                    if let Err(e) = standard_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
+                                        tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
+                                        continue;
+                                    }
+                                    _ = standard_channel
+                                    .on_group_channel_job(group_channel_job.clone());
+                                }
+                            }
+                            if let Some(group_channel_job) = group_channel_job {
+                                let job_message = group_channel_job.get_job_message();
+                                messages.push((*downstream_id, Mining::NewExtendedMiningJob(job_message.clone())).into());
+                            }
+
+                            for (channel_id, extended_channel) in data.extended_channels.iter_mut() {
+                                if let Err(e) = extended_channel.on_new_template(msg.clone().into_static(), coinbase_outputs.clone()) {
+                                    tracing::error!("Error while adding template to standard channel: {channel_id:?} {e:?}");
+                                    continue;
+                                }
+                                let extended_job = extended_channel
+                                    .get_active_job()
+                                    .expect("extended job must exist");
+
+                                channel_manager_data.downstream_channel_id_and_job_id_to_template_id.insert((*downstream_id, *channel_id, extended_job.get_job_id()).into(), msg.template_id);
+                                let extended_job_message = extended_job.get_job_message();
+
+                                messages.push((*downstream_id,Mining::NewExtendedMiningJob(extended_job_message.clone())).into());
+                            }
+                        }
+                    }
+
+                    messages
+
+                });
+                messages.extend(messages_);
+            }
+            messages
+        });
+
+        if get_jd_mode() == JdMode::CoinbaseOnly && !msg.future_template {
+            _ = self.allocate_tokens(1).await;
+        }
+
+        for message in messages {
+            message.forward(&self.channel_manager_channel).await;
+        }
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::atomic::Ordering;
+use std::{sync::atomic::Ordering, time::Instant};
 
 use stratum_apps::stratum_core::{
     bitcoin::Target,
@@ -15,8 +15,8 @@ use tracing::{debug, error, info, warn};
 
 use crate::{
     channel_manager::{
-        downstream_message_handler::RouteMessageTo, ChannelManager, DeclaredJob,
-        JDC_SEARCH_SPACE_BYTES,
+        chain_tip_verifier::coinbase_tx_prefix_height, downstream_message_handler::RouteMessageTo,
+        ChannelManager, DeclaredJob, JDC_SEARCH_SPACE_BYTES,
     },
     error::{ChannelSv2Error, JDCError},
     jd_mode::{get_jd_mode, JdMode},
@@ -495,25 +495,55 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         Ok(())
     }
 
-    // Handles `NewExtendedMiningJob` messages from upstream. JDC ignores it.
+    // Handles `NewExtendedMiningJob` messages from upstream. JDC does not mine on it, the
+    // height of its coinbase is only checked against the Template Provider when upstream
+    // verification is enabled.
     async fn handle_new_extended_mining_job(
         &mut self,
         _server_id: Option<usize>,
         msg: NewExtendedMiningJob<'_>,
     ) -> Result<(), Self::Error> {
-        warn!("Received: {}", msg);
-        warn!("⚠️ JDC does not expect jobs from the upstream server — ignoring.");
+        let verified = self.channel_manager_data.super_safe_lock(|data| {
+            let Some(verifier) = data.chain_tip_verifier.as_mut() else {
+                return false;
+            };
+            verifier.on_pool_job(
+                msg.job_id,
+                msg.is_future(),
+                coinbase_tx_prefix_height(msg.coinbase_tx_prefix.inner_as_ref()),
+                Instant::now(),
+            );
+            true
+        });
+        if verified {
+            debug!("Received: {}", msg);
+        } else {
+            warn!("Received: {}", msg);
+            warn!("⚠️ JDC does not expect jobs from the upstream server — ignoring.");
+        }
         Ok(())
     }
 
-    // Handles `SetNewPrevHash` messages from upstream. JDC ignores it.
+    // Handles `SetNewPrevHash` messages from upstream. JDC does not mine on it, the chain tip is
+    // only checked against the Template Provider when upstream verification is enabled.
     async fn handle_set_new_prev_hash(
         &mut self,
         _server_id: Option<usize>,
         msg: SetNewPrevHash<'_>,
     ) -> Result<(), Self::Error> {
-        warn!("Received: {}", msg);
-        warn!("⚠️ JDC does not expect prevhash updates from the upstream server — ignoring.");
+        let verified = self.channel_manager_data.super_safe_lock(|data| {
+            let Some(verifier) = data.chain_tip_verifier.as_mut() else {
+                return false;
+            };
+            verifier.on_pool_prev_hash(msg.prev_hash.inner_as_ref(), msg.job_id, Instant::now());
+            true
+        });
+        if verified {
+            info!("Received: {}", msg);
+        } else {
+            warn!("Received: {}", msg);
+            warn!("⚠️ JDC does not expect prevhash updates from the upstream server — ignoring.");
+        }
         Ok(())
     }
 
@@ -0,0 +1,79 @@
+//! ## Coinbase Outputs Module
+//!
+//! Builds the coinbase outputs of the jobs of the JDC. The outputs of the pool (or the
+//! `coinbase_reward_script` while solo mining) come first, the first one receiving the reward,
+//! followed by the zero-value `extra_coinbase_outputs` of the miner. The
+//! [`CoinbaseOutputConstraints`] sent to the Template Provider reserve room for all of them, so
+//! that the templates leave space for the commitments of the miner.
+
+use stratum_apps::stratum_core::{
+    bitcoin::{
+        self, absolute::LockTime, consensus::Encodable, transaction::Version, OutPoint, ScriptBuf,
+        Sequence, Transaction, TxIn, TxOut, Witness,
+    },
+    template_distribution_sv2::CoinbaseOutputConstraints,
+};
+
+use crate::error::JDCError;
+
+/// Maximum size of the coinbase outputs, a quarter of the block weight being left to the
+/// transactions of the template.
+const MAX_OUTPUTS_SIZE: usize = 1_000_000;
+
+/// Encodes `outputs` followed by `extra_outputs`, as the coinbase outputs of the jobs.
+///
+/// Fails if they do not fit in the [`CoinbaseOutputConstraints`] of a template.
+pub fn encode_outputs(
+    mut outputs: Vec<TxOut>,
+    extra_outputs: &[TxOut],
+) -> Result<Vec<u8>, JDCError> {
+    outputs.extend_from_slice(extra_outputs);
+    constraints(&outputs)?;
+    let mut encoded = vec![];
+    outputs.consensus_encode(&mut encoded)?;
+    Ok(encoded)
+}
+
+/// Returns the [`CoinbaseOutputConstraints`] reserving room for the encoded `coinbase_outputs`
+/// in the templates of the Template Provider.
+pub fn coinbase_output_constraints(
+    coinbase_outputs: &[u8],
+) -> Result<CoinbaseOutputConstraints, JDCError> {
+    let outputs: Vec<TxOut> = bitcoin::consensus::deserialize(coinbase_outputs)?;
+    constraints(&outputs)
+}
+
+fn constraints(outputs: &[TxOut]) -> Result<CoinbaseOutputConstraints, JDCError> {
+    let size: usize = outputs.iter().map(|output| output.size()).sum();
+    if size > MAX_OUTPUTS_SIZE {
+        return Err(JDCError::InvalidCoinbaseOutputs(format!(
+            "{size} bytes of coinbase outputs exceed the {MAX_OUTPUTS_SIZE} bytes a template can reserve"
+        )));
+    }
+
+    // create a dummy coinbase transaction with the outputs
+    // this is used to calculate the sigops of the coinbase outputs
+    let dummy_coinbase = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::from(vec![vec![0; 32]]),
+        }],
+        output: outputs.to_vec(),
+    };
+    let sigops = dummy_coinbase.total_sigop_cost(|_| None);
+    let sigops = u16::try_from(sigops).map_err(|_| {
+        JDCError::InvalidCoinbaseOutputs(format!(
+            "{sigops} sigops of coinbase outputs exceed the {} a template can reserve",
+            u16::MAX
+        ))
+    })?;
+
+    Ok(CoinbaseOutputConstraints {
+        coinbase_output_max_additional_size: size as u32,
+        coinbase_output_max_additional_sigops: sigops,
+    })
+}
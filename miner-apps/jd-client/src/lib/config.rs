@@ -3,18 +3,40 @@ use std::{
     net::SocketAddr,
     path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 use stratum_apps::{
-    config_helpers::CoinbaseRewardScript,
-    key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
-    stratum_core::bitcoin::{Amount, TxOut},
+    config_helpers::{logging::LoggingConfig, CoinbaseRewardScript},
+    key_utils::{AuthorityPublicKeys, Secp256k1PublicKey, Secp256k1SecretKey},
+    monitoring::MonitoringConfig,
+    network_helpers::transport::Encryption,
+    runtime::IoChannelsConfig,
+    stratum_core::bitcoin::{hex::FromHex, script::PushBytesBuf, Amount, ScriptBuf, TxOut},
 };
 
+/// Maximum size of the data of an `OP_RETURN` extra coinbase output, as relayed by Bitcoin Core.
+pub const MAX_OP_RETURN_DATA_SIZE: usize = 80;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct JobDeclaratorClientConfig {
     // The address on which the JDC will listen for incoming connections when acting as an
     // upstream.
     listening_address: SocketAddr,
+    /// Transport used for downstream connections. `none` disables Noise, for trusted networks
+    /// only.
+    #[serde(default)]
+    listen_encryption: Encryption,
+    /// Maximum size (in bytes) of a frame received from a downstream, unlimited if unset.
+    max_frame_size: Option<usize>,
+    /// Seconds a downstream may stay silent before being disconnected, no timeout if unset.
+    downstream_idle_timeout_secs: Option<u64>,
+    /// Capacities of the channels of the downstream connections, and behavior of a full outbound
+    /// one. Unbounded if unset.
+    #[serde(default)]
+    downstream_channels: IoChannelsConfig,
+    /// HTTP endpoint serving the health of the JDC components and the shares rejected per error
+    /// code. Disabled if unset.
+    monitoring: Option<MonitoringConfig>,
     // The maximum supported SV2 protocol version.
     max_supported_version: u16,
     // The minimum supported SV2 protocol version.
@@ -28,16 +50,46 @@ pub struct JobDeclaratorClientConfig {
     /// The address of the TP that this JDC will connect to.
     tp_address: String,
     /// The expected public key of the TP's authority for authentication (optional).
-    tp_authority_public_key: Option<Secp256k1PublicKey>,
-    /// A list of upstream Job Declarator Servers (JDS) that this JDC can connect to.
-    /// JDC can fallover between these upstreams.
+    tp_authority_public_key: Option<AuthorityPublicKeys>,
+    /// Transport used for the TP connection. `none` disables Noise, for trusted networks only.
+    #[serde(default)]
+    tp_encryption: Encryption,
+    /// Cross-checking of the chain tip announced by the pool against the one of the TP.
+    #[serde(default)]
+    upstream_verification: UpstreamVerification,
+    /// An ordered list of upstream pool + Job Declarator Server (JDS) pairs that this JDC can
+    /// connect to. JDC fails over to the next pair when the current one is lost.
     upstreams: Vec<Upstream>,
+    /// Number of `DeclareMiningJobError` tolerated from the current JDS before failing over to
+    /// the next upstream. Read as 1 if set to 0.
+    #[serde(
+        default = "default_declare_mining_job_error_threshold",
+        deserialize_with = "deserialize_error_threshold"
+    )]
+    declare_mining_job_error_threshold: u32,
+    /// Interval (in seconds) at which the upstreams are probed while solo mining, in order to
+    /// return to pooled mining once one of them recovers.
+    #[serde(default = "default_upstream_retry_interval_secs")]
+    upstream_retry_interval_secs: u64,
+    /// Policy governing when a job is declared from a non-future template.
+    #[serde(default)]
+    declaration_policy: DeclarationPolicy,
+    /// Transactions the templates mined must not include.
+    #[serde(default)]
+    tx_filter: TxFilter,
     /// This is only used during solo-mining.
     pub coinbase_reward_script: CoinbaseRewardScript,
+    /// Zero-value outputs of the miner appended to the coinbase of the jobs, after the outputs of
+    /// the pool (or `coinbase_reward_script` while solo mining).
+    #[serde(default)]
+    extra_coinbase_outputs: Vec<ExtraCoinbaseOutput>,
     /// A signature string identifying this JDC instance.
     jdc_signature: String,
     /// The path to the log file where JDC will write logs.
     log_file: Option<PathBuf>,
+    /// Format and filter of the logs.
+    #[serde(default)]
+    logging: LoggingConfig,
     /// User Identity
     user_identity: String,
     /// Shares per minute
@@ -65,17 +117,30 @@ impl JobDeclaratorClientConfig {
     ) -> Self {
         Self {
             listening_address,
+            listen_encryption: Encryption::default(),
+            max_frame_size: None,
+            downstream_idle_timeout_secs: None,
+            downstream_channels: IoChannelsConfig::default(),
+            monitoring: None,
             max_supported_version: protocol_config.max_supported_version,
             min_supported_version: protocol_config.min_supported_version,
             authority_public_key: pool_config.authority_public_key,
             authority_secret_key: pool_config.authority_secret_key,
             cert_validity_sec: tp_config.cert_validity_sec,
             tp_address: tp_config.tp_address,
-            tp_authority_public_key: tp_config.tp_authority_public_key,
+            tp_authority_public_key: tp_config.tp_authority_public_key.map(Into::into),
+            tp_encryption: Encryption::default(),
+            upstream_verification: UpstreamVerification::default(),
             upstreams,
+            declare_mining_job_error_threshold: default_declare_mining_job_error_threshold(),
+            upstream_retry_interval_secs: default_upstream_retry_interval_secs(),
+            declaration_policy: DeclarationPolicy::default(),
+            tx_filter: TxFilter::default(),
             coinbase_reward_script: protocol_config.coinbase_reward_script,
+            extra_coinbase_outputs: Vec::new(),
             jdc_signature,
             log_file: None,
+            logging: LoggingConfig::default(),
             user_identity,
             shares_per_minute,
             share_batch_size,
@@ -90,6 +155,32 @@ impl JobDeclaratorClientConfig {
         &self.listening_address
     }
 
+    /// Returns the transport used for downstream connections.
+    pub fn listen_encryption(&self) -> Encryption {
+        self.listen_encryption
+    }
+
+    /// Returns the maximum size of a frame received from a downstream, if any.
+    pub fn max_frame_size(&self) -> Option<usize> {
+        self.max_frame_size
+    }
+
+    /// Returns how long a downstream may stay silent before being disconnected, if set.
+    pub fn downstream_idle_timeout(&self) -> Option<Duration> {
+        self.downstream_idle_timeout_secs.map(Duration::from_secs)
+    }
+
+    /// Returns the capacities of the channels of the downstream connections, and what happens
+    /// when a downstream reads too slowly to keep its outbound channel from filling up.
+    pub fn downstream_channels(&self) -> &IoChannelsConfig {
+        &self.downstream_channels
+    }
+
+    /// Returns the configuration of the HTTP endpoint serving the JDC health, if enabled.
+    pub fn monitoring(&self) -> Option<&MonitoringConfig> {
+        self.monitoring.as_ref()
+    }
+
     /// Returns the list of upstreams.
     ///
     /// JDC will try to fallback to the next upstream in case of failure of the current one.
@@ -97,6 +188,46 @@ impl JobDeclaratorClientConfig {
         &self.upstreams
     }
 
+    /// Returns the number of `DeclareMiningJobError` tolerated before failing over.
+    pub fn declare_mining_job_error_threshold(&self) -> u32 {
+        self.declare_mining_job_error_threshold
+    }
+
+    /// Sets the number of `DeclareMiningJobError` tolerated before failing over.
+    pub fn set_declare_mining_job_error_threshold(&mut self, threshold: u32) {
+        self.declare_mining_job_error_threshold = threshold.max(1);
+    }
+
+    /// Returns the interval (in seconds) at which upstreams are probed while solo mining.
+    pub fn upstream_retry_interval_secs(&self) -> u64 {
+        self.upstream_retry_interval_secs
+    }
+
+    /// Sets the interval (in seconds) at which upstreams are probed while solo mining.
+    pub fn set_upstream_retry_interval_secs(&mut self, interval: u64) {
+        self.upstream_retry_interval_secs = interval.max(1);
+    }
+
+    /// Returns the policy governing when a job is declared from a non-future template.
+    pub fn declaration_policy(&self) -> &DeclarationPolicy {
+        &self.declaration_policy
+    }
+
+    /// Sets the policy governing when a job is declared from a non-future template.
+    pub fn set_declaration_policy(&mut self, declaration_policy: DeclarationPolicy) {
+        self.declaration_policy = declaration_policy;
+    }
+
+    /// Returns the transactions the templates mined must not include.
+    pub fn tx_filter(&self) -> &TxFilter {
+        &self.tx_filter
+    }
+
+    /// Sets the transactions the templates mined must not include.
+    pub fn set_tx_filter(&mut self, tx_filter: TxFilter) {
+        self.tx_filter = tx_filter;
+    }
+
     /// Returns the authority public key.
     pub fn authority_public_key(&self) -> &Secp256k1PublicKey {
         &self.authority_public_key
@@ -117,11 +248,26 @@ impl JobDeclaratorClientConfig {
         &self.tp_address
     }
 
-    /// Returns Template Provider authority public key.
-    pub fn tp_authority_public_key(&self) -> Option<&Secp256k1PublicKey> {
+    /// Returns Template Provider authority public keys.
+    pub fn tp_authority_public_key(&self) -> Option<&AuthorityPublicKeys> {
         self.tp_authority_public_key.as_ref()
     }
 
+    /// Returns the encryption of the Template Provider connection.
+    pub fn tp_encryption(&self) -> Encryption {
+        self.tp_encryption
+    }
+
+    /// Returns how the chain tip announced by the pool is checked against the Template Provider.
+    pub fn upstream_verification(&self) -> &UpstreamVerification {
+        &self.upstream_verification
+    }
+
+    /// Sets how the chain tip announced by the pool is checked against the Template Provider.
+    pub fn set_upstream_verification(&mut self, upstream_verification: UpstreamVerification) {
+        self.upstream_verification = upstream_verification;
+    }
+
     /// Returns the minimum supported version.
     pub fn min_supported_version(&self) -> u16 {
         self.min_supported_version
@@ -144,6 +290,24 @@ impl JobDeclaratorClientConfig {
         }
     }
 
+    /// Returns the extra coinbase outputs of the miner, or why one of them is invalid.
+    pub fn extra_coinbase_outputs(&self) -> Result<Vec<TxOut>, String> {
+        self.extra_coinbase_outputs
+            .iter()
+            .enumerate()
+            .map(|(index, output)| {
+                output
+                    .to_txout()
+                    .map_err(|e| format!("extra coinbase output {index}: {e}"))
+            })
+            .collect()
+    }
+
+    /// Sets the extra coinbase outputs of the miner.
+    pub fn set_extra_coinbase_outputs(&mut self, extra_coinbase_outputs: Vec<ExtraCoinbaseOutput>) {
+        self.extra_coinbase_outputs = extra_coinbase_outputs;
+    }
+
     pub fn log_file(&self) -> Option<&Path> {
         self.log_file.as_deref()
     }
@@ -152,6 +316,10 @@ impl JobDeclaratorClientConfig {
             self.log_file = Some(log_file);
         }
     }
+    /// Returns the format and filter of the logs.
+    pub fn logging(&self) -> &LoggingConfig {
+        &self.logging
+    }
     pub fn user_identity(&self) -> &str {
         &self.user_identity
     }
@@ -165,6 +333,246 @@ impl JobDeclaratorClientConfig {
     }
 }
 
+fn default_declare_mining_job_error_threshold() -> u32 {
+    3
+}
+
+// Reads the `declare_mining_job_error_threshold`, as clamped by its setter.
+fn deserialize_error_threshold<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(u32::deserialize(deserializer)?.max(1))
+}
+
+fn default_upstream_retry_interval_secs() -> u64 {
+    60
+}
+
+/// Policy governing when the JDC declares a new job from a non-future template.
+///
+/// Future templates (activated by a new `prevhash`) are always declared. Non-future templates,
+/// which only update the transaction set of the current block, are declared only if:
+/// - at least `min_interval_secs` elapsed since the last declared template, and
+/// - the coinbase value increased by at least `min_fee_delta_sats` sats or by at least
+///   `min_fee_delta_percent` percent of the fees of the last declared template, its coinbase
+///   value minus the block subsidy. Unset (zero) thresholds are ignored, if both are unset any
+///   template qualifies.
+///
+/// Templates not satisfying the policy are dropped, downstreams keep mining on the last declared
+/// job. The default policy declares every template.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct DeclarationPolicy {
+    min_fee_delta_sats: u64,
+    min_fee_delta_percent: f64,
+    min_interval_secs: u64,
+}
+
+impl DeclarationPolicy {
+    /// Creates a new instance of [`DeclarationPolicy`].
+    pub fn new(
+        min_fee_delta_sats: u64,
+        min_fee_delta_percent: f64,
+        min_interval_secs: u64,
+    ) -> Self {
+        Self {
+            min_fee_delta_sats,
+            min_fee_delta_percent,
+            min_interval_secs,
+        }
+    }
+
+    /// Returns the minimum coinbase value increase, in sats.
+    pub fn min_fee_delta_sats(&self) -> u64 {
+        self.min_fee_delta_sats
+    }
+
+    /// Returns the minimum coinbase value increase, in percent.
+    pub fn min_fee_delta_percent(&self) -> f64 {
+        self.min_fee_delta_percent
+    }
+
+    /// Returns the minimum interval between two declared templates.
+    pub fn min_interval(&self) -> Duration {
+        Duration::from_secs(self.min_interval_secs)
+    }
+
+    /// Returns `true` if a non-future template with a coinbase value of `new_value` must be
+    /// declared, given the coinbase value of the last declared template, the block `subsidy`
+    /// included in both and the time elapsed since the last one was declared.
+    pub fn should_declare(
+        &self,
+        last_value: u64,
+        new_value: u64,
+        subsidy: u64,
+        elapsed: Duration,
+    ) -> bool {
+        if elapsed < self.min_interval() {
+            return false;
+        }
+        let check_sats = self.min_fee_delta_sats > 0;
+        let check_percent = self.min_fee_delta_percent > 0.0;
+        if !check_sats && !check_percent {
+            return true;
+        }
+        let delta = new_value.saturating_sub(last_value);
+        let last_fees = last_value.saturating_sub(subsidy);
+        let delta_percent = if last_fees == 0 {
+            f64::INFINITY
+        } else {
+            delta as f64 * 100.0 / last_fees as f64
+        };
+        (check_sats && delta >= self.min_fee_delta_sats)
+            || (check_percent && delta > 0 && delta_percent >= self.min_fee_delta_percent)
+    }
+}
+
+/// Returns the subsidy of the block at `height`, in sats, following the halving schedule of
+/// mainnet, testnet and signet.
+///
+/// On regtest, whose subsidy halves every 150 blocks, it is overestimated past the first halving,
+/// so that any fee increase satisfies `min_fee_delta_percent`.
+pub fn block_subsidy(height: u32) -> u64 {
+    const INITIAL_SUBSIDY: u64 = 50 * 100_000_000;
+    const HALVING_INTERVAL: u32 = 210_000;
+    INITIAL_SUBSIDY
+        .checked_shr(height / HALVING_INTERVAL)
+        .unwrap_or(0)
+}
+
+/// Filter list of the transactions the templates mined must not include, by txid or by the
+/// script of one of their outputs.
+///
+/// Only applied in `FULLTEMPLATE` mode, where the JDC receives the transactions of the
+/// templates: a template including a filtered transaction is not mined. The default filter
+/// excludes nothing.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TxFilter {
+    txids: Vec<String>,
+    output_scripts: Vec<CoinbaseRewardScript>,
+}
+
+impl TxFilter {
+    /// Creates a new instance of [`TxFilter`].
+    pub fn new(txids: Vec<String>, output_scripts: Vec<CoinbaseRewardScript>) -> Self {
+        Self {
+            txids,
+            output_scripts,
+        }
+    }
+
+    /// Returns the filtered txids, hex encoded as displayed by block explorers.
+    pub fn txids(&self) -> &[String] {
+        &self.txids
+    }
+
+    /// Returns the descriptors of the filtered output scripts.
+    pub fn output_scripts(&self) -> &[CoinbaseRewardScript] {
+        &self.output_scripts
+    }
+
+    /// Returns `true` if the filter excludes no transaction.
+    pub fn is_empty(&self) -> bool {
+        self.txids.is_empty() && self.output_scripts.is_empty()
+    }
+}
+
+/// Output of the miner appended to the coinbase of the jobs, e.g. to embed a commitment of its
+/// own.
+///
+/// It carries no value: the reward goes to the outputs of the pool, or to
+/// `coinbase_reward_script` while solo mining.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtraCoinbaseOutput {
+    /// `OP_RETURN` output carrying the given hex encoded data, up to
+    /// [`MAX_OP_RETURN_DATA_SIZE`] bytes.
+    OpReturn(String),
+    /// Output locked by the script of the given descriptor, e.g. `raw(<hex script>)`.
+    Script(CoinbaseRewardScript),
+}
+
+impl ExtraCoinbaseOutput {
+    /// Returns the zero-value output to append to the coinbase, or why it is invalid.
+    pub fn to_txout(&self) -> Result<TxOut, String> {
+        let script_pubkey = match self {
+            Self::OpReturn(data) => {
+                let data = Vec::<u8>::from_hex(data)
+                    .map_err(|e| format!("`op_return` data is not hex: {e}"))?;
+                if data.len() > MAX_OP_RETURN_DATA_SIZE {
+                    return Err(format!(
+                        "`op_return` data of {} bytes exceeds {MAX_OP_RETURN_DATA_SIZE} bytes",
+                        data.len()
+                    ));
+                }
+                let data = PushBytesBuf::try_from(data).map_err(|e| e.to_string())?;
+                ScriptBuf::new_op_return(data)
+            }
+            Self::Script(script) => script.script_pubkey(),
+        };
+        Ok(TxOut {
+            value: Amount::ZERO,
+            script_pubkey,
+        })
+    }
+}
+
+/// What the JDC does when the pool mines on another chain tip than its Template Provider.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VerificationMode {
+    /// The chain tip of the pool is not checked.
+    #[default]
+    Off,
+    /// The divergence is logged and reported as an alarm on the health of the JDC.
+    Alarm,
+    /// As `Alarm`, and the pool is left for the next upstream, or solo mining.
+    Refuse,
+}
+
+/// Cross-checking of the chain tip announced by the pool against the Template Provider.
+///
+/// The previous block hash of the `SetNewPrevHash` of the pool, and the BIP34 height of the
+/// coinbase of its jobs, are compared to the ones of the Template Provider. Since the pool and
+/// the Template Provider do not learn about a new block at the same time, they are only reported
+/// as diverged once they disagreed for `grace_secs`. This protects the miners from working on a
+/// chain tip of a malicious or broken pool.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct UpstreamVerification {
+    mode: VerificationMode,
+    grace_secs: u64,
+}
+
+impl Default for UpstreamVerification {
+    fn default() -> Self {
+        Self {
+            mode: VerificationMode::Off,
+            grace_secs: 10,
+        }
+    }
+}
+
+impl UpstreamVerification {
+    /// Creates a new instance of [`UpstreamVerification`].
+    pub fn new(mode: VerificationMode, grace_secs: u64) -> Self {
+        Self { mode, grace_secs }
+    }
+
+    /// Returns what the JDC does when the pool diverges from the Template Provider.
+    pub fn mode(&self) -> VerificationMode {
+        self.mode
+    }
+
+    /// Returns how long the pool and the Template Provider may disagree before the pool is
+    /// reported as diverged.
+    pub fn grace(&self) -> Duration {
+        Duration::from_secs(self.grace_secs)
+    }
+}
+
 #[derive(Debug, Deserialize, Clone, Default)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum ConfigJDCMode {
@@ -264,14 +672,21 @@ impl ProtocolConfig {
 /// Represents necessary fields required to connect to JDS
 #[derive(Debug, Deserialize, Clone)]
 pub struct Upstream {
-    // The public key of the upstream pool's authority for authentication.
-    pub authority_pubkey: Secp256k1PublicKey,
+    // The public keys of the upstream pool's authority for authentication: a single key, or the
+    // current and the next key while the pool rotates its keypair.
+    pub authority_pubkey: AuthorityPublicKeys,
     // The address of the upstream pool's main server.
     pub pool_address: String,
     pub pool_port: u16,
+    /// Transport used for the pool connection. `none` disables Noise, for trusted networks only.
+    #[serde(default)]
+    pub pool_encryption: Encryption,
     // The network address of the JDS.
     pub jds_address: String,
     pub jds_port: u16,
+    /// Transport used for the JDS connection. `none` disables Noise, for trusted networks only.
+    #[serde(default)]
+    pub jds_encryption: Encryption,
 }
 
 impl Upstream {
@@ -284,11 +699,64 @@ impl Upstream {
         jds_port: u16,
     ) -> Self {
         Self {
-            authority_pubkey,
+            authority_pubkey: authority_pubkey.into(),
             pool_address,
             pool_port,
+            pool_encryption: Encryption::default(),
             jds_address,
             jds_port,
+            jds_encryption: Encryption::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_apps::config_helpers::load_config_with_overrides;
+
+    #[test]
+    fn a_zero_error_threshold_is_read_as_one() {
+        let config: JobDeclaratorClientConfig = load_config_with_overrides(
+            Path::new(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/config-examples/jdc-config-local-example.toml"
+            )),
+            "SV2_JDC_TEST",
+            &[("declare_mining_job_error_threshold", "0".to_string())],
+        )
+        .unwrap();
+        assert_eq!(config.declare_mining_job_error_threshold(), 1);
+    }
+
+    #[test]
+    fn block_subsidy_halves_every_210000_blocks() {
+        assert_eq!(block_subsidy(0), 5_000_000_000);
+        assert_eq!(block_subsidy(209_999), 5_000_000_000);
+        assert_eq!(block_subsidy(210_000), 2_500_000_000);
+        assert_eq!(block_subsidy(840_000), 312_500_000);
+        assert_eq!(block_subsidy(64 * 210_000), 0);
+    }
+
+    #[test]
+    fn fee_delta_percent_is_relative_to_the_fees() {
+        let policy = DeclarationPolicy::new(0, 1.0, 0);
+        let subsidy = block_subsidy(900_000);
+        // 0.1 BTC of fees on top of the 3.125 BTC subsidy.
+        let last_value = subsidy + 10_000_000;
+
+        // 2% more fees, only 0.06% more coinbase value.
+        assert!(policy.should_declare(last_value, last_value + 200_000, subsidy, Duration::ZERO));
+        // 0.5% more fees.
+        assert!(!policy.should_declare(last_value, last_value + 50_000, subsidy, Duration::ZERO));
+        assert!(!policy.should_declare(last_value, last_value, subsidy, Duration::ZERO));
+    }
+
+    #[test]
+    fn any_fee_increase_qualifies_without_previous_fees() {
+        let policy = DeclarationPolicy::new(0, 1.0, 0);
+        let subsidy = block_subsidy(900_000);
+        assert!(policy.should_declare(subsidy, subsidy + 1, subsidy, Duration::ZERO));
+        assert!(!policy.should_declare(subsidy, subsidy, subsidy, Duration::ZERO));
+    }
+}
@@ -1,12 +1,18 @@
 use std::{
     collections::HashMap,
+    net::SocketAddr,
     sync::{atomic::AtomicU32, Arc},
+    time::Duration,
 };
 
-use async_channel::{unbounded, Receiver, Sender};
+use async_channel::{Receiver, Sender};
 use stratum_apps::{
     custom_mutex::Mutex,
-    network_helpers::noise_stream::NoiseTcpStream,
+    network_helpers::{traffic::ConnectionTraffic, transport::Sv2TcpStream},
+    runtime::{
+        message_type::{protocol_message_type, MessageType},
+        spawn_io_tasks, IoChannelsConfig, PrioritySender, TaskManager,
+    },
     stratum_core::{
         channels_sv2::server::{
             extended::ExtendedChannel,
@@ -26,11 +32,7 @@ use tracing::{debug, error, warn};
 use crate::{
     error::JDCError,
     status::{handle_error, Status, StatusSender},
-    task_manager::TaskManager,
-    utils::{
-        protocol_message_type, spawn_io_tasks, ChannelId, DownstreamId, Message, MessageType,
-        SV2Frame, ShutdownMessage, StdFrame,
-    },
+    utils::{ChannelId, DownstreamId, Message, SV2Frame, ShutdownMessage, StdFrame},
 };
 
 mod message_handler;
@@ -64,7 +66,7 @@ pub struct DownstreamData {
 pub struct DownstreamChannel {
     channel_manager_sender: Sender<(DownstreamId, Mining<'static>)>,
     channel_manager_receiver: broadcast::Sender<(DownstreamId, Mining<'static>)>,
-    downstream_sender: Sender<SV2Frame>,
+    downstream_sender: PrioritySender,
     downstream_receiver: Receiver<SV2Frame>,
 }
 
@@ -74,34 +76,43 @@ pub struct Downstream {
     pub downstream_data: Arc<Mutex<DownstreamData>>,
     downstream_channel: DownstreamChannel,
     pub downstream_id: DownstreamId,
+    /// Address of the downstream peer.
+    pub address: SocketAddr,
+    /// Frame and byte counters of the connection.
+    pub traffic: Arc<ConnectionTraffic>,
 }
 
 impl Downstream {
     /// Creates a new [`Downstream`] instance and spawns the necessary I/O tasks.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         downstream_id: DownstreamId,
+        address: SocketAddr,
         channel_manager_sender: Sender<(DownstreamId, Mining<'static>)>,
         channel_manager_receiver: broadcast::Sender<(DownstreamId, Mining<'static>)>,
-        noise_stream: NoiseTcpStream<Message>,
+        stream: Sv2TcpStream<Message>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         task_manager: Arc<TaskManager>,
         status_sender: Sender<Status>,
+        idle_timeout: Option<Duration>,
+        channels: IoChannelsConfig,
     ) -> Self {
-        let (noise_stream_reader, noise_stream_writer) = noise_stream.into_split();
+        let (stream_reader, stream_writer) = stream.into_split();
         let status_sender = StatusSender::Downstream {
             downstream_id,
             tx: status_sender,
         };
-        let (inbound_tx, inbound_rx) = unbounded::<SV2Frame>();
-        let (outbound_tx, outbound_rx) = unbounded::<SV2Frame>();
-        spawn_io_tasks(
+        let (inbound_tx, inbound_rx) = channels.inbound_channel();
+        let (outbound_tx, outbound_rx) = channels.outbound_channel();
+        let traffic = spawn_io_tasks(
             task_manager,
-            noise_stream_reader,
-            noise_stream_writer,
+            stream_reader,
+            stream_writer,
             outbound_rx,
             inbound_tx,
             notify_shutdown,
             status_sender,
+            idle_timeout,
         );
 
         let downstream_channel = DownstreamChannel {
@@ -121,9 +132,17 @@ impl Downstream {
             downstream_channel,
             downstream_data,
             downstream_id,
+            address,
+            traffic,
         }
     }
 
+    /// Returns how many share acknowledgements were dropped for overflowing the outbound channel
+    /// of the downstream.
+    pub fn outbound_dropped(&self) -> u64 {
+        self.downstream_channel.downstream_sender.dropped()
+    }
+
     /// Starts the downstream loop.
     ///
     /// Responsibilities:
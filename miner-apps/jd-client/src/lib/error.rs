@@ -12,8 +12,9 @@
 //! This module ensures that all errors can be passed around consistently, including across async
 //! boundaries.
 use ext_config::ConfigError;
-use std::fmt;
+use std::{fmt, net::SocketAddr};
 use stratum_apps::{
+    exit::ExitStatus,
     network_helpers,
     stratum_core::{
         binary_sv2, bitcoin,
@@ -80,6 +81,8 @@ pub enum JDCError {
     InvalidSocketAddress(String),
     /// Timeout error
     Timeout,
+    /// No frame received from the peer within the idle timeout
+    IdleTimeout(std::time::Duration),
     /// Declared job corresponding to request Id not found.
     LastDeclareJobNotFound(RequestId),
     /// No active job with job id
@@ -116,6 +119,16 @@ pub enum JDCError {
     ChannelSv2(ChannelSv2Error),
     /// Extranonce prefix error
     ExtranoncePrefixFactoryError(ExtendedExtranonceError),
+    /// The downstream listening address cannot be bound
+    Bind(SocketAddr, std::io::Error),
+    /// No connection to the Template Provider could be established
+    TemplateProviderUnreachable(String),
+    /// The pool mines on another chain tip than the Template Provider
+    UpstreamChainTipDiverged(String),
+    /// The coinbase outputs of the jobs are invalid or do not fit in a coinbase
+    InvalidCoinbaseOutputs(String),
+    /// The configured transaction filter is invalid
+    InvalidTxFilter(String),
 }
 
 impl std::error::Error for JDCError {}
@@ -144,6 +157,7 @@ impl fmt::Display for JDCError {
             BitcoinEncodeError(_) => write!(f, "Error generated during encoding"),
             InvalidSocketAddress(ref s) => write!(f, "Invalid socket address: {s}"),
             Timeout => write!(f, "Time out error"),
+            IdleTimeout(timeout) => write!(f, "No frame received for {timeout:?}"),
             LastDeclareJobNotFound(request_id) => {
                 write!(f, "last declare job not found for request id: {request_id}")
             }
@@ -204,11 +218,35 @@ impl fmt::Display for JDCError {
             ChannelSv2(channel_error) => {
                 write!(f, "Channel error: {channel_error:?}")
             }
+            Bind(address, ref e) => write!(f, "Failed to bind {address}: {e}"),
+            TemplateProviderUnreachable(ref address) => {
+                write!(f, "Template Provider unreachable at {address}")
+            }
+            UpstreamChainTipDiverged(ref reason) => {
+                write!(f, "Pool diverged from the Template Provider: {reason}")
+            }
+            InvalidCoinbaseOutputs(ref reason) => write!(f, "Invalid coinbase outputs: {reason}"),
+            InvalidTxFilter(ref reason) => write!(f, "Invalid transaction filter: {reason}"),
         }
     }
 }
 
 impl JDCError {
+    /// Returns the exit status of the JDC process when it stops on this error.
+    pub fn exit_status(&self) -> ExitStatus {
+        use JDCError::*;
+        match self {
+            BadCliArgs
+            | BadConfigDeserialize(_)
+            | InvalidCoinbaseOutputs(_)
+            | InvalidTxFilter(_) => ExitStatus::Config,
+            CodecNoise(_) => ExitStatus::Key,
+            Bind(..) => ExitStatus::Bind,
+            TemplateProviderUnreachable(_) => ExitStatus::Unreachable,
+            _ => ExitStatus::Failure,
+        }
+    }
+
     fn is_non_critical_variant(&self) -> bool {
         matches!(
             self,
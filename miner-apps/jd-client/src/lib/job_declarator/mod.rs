@@ -3,31 +3,26 @@ use std::{net::SocketAddr, sync::Arc};
 use async_channel::{unbounded, Receiver, Sender};
 use stratum_apps::{
     custom_mutex::Mutex,
-    key_utils::Secp256k1PublicKey,
-    network_helpers::noise_stream::NoiseTcpStream,
+    key_utils::AuthorityPublicKeys,
+    network_helpers::transport::{connect, Encryption},
+    runtime::{
+        message_type::{protocol_message_type, MessageType},
+        spawn_io_tasks, TaskManager,
+    },
     stratum_core::{
-        codec_sv2::HandshakeRole,
         framing_sv2,
         handlers_sv2::HandleCommonMessagesFromServerAsync,
-        noise_sv2::Initiator,
         parsers_sv2::{AnyMessage, JobDeclaration},
     },
 };
-use tokio::{
-    net::TcpStream,
-    sync::{broadcast, mpsc},
-};
+use tokio::sync::{broadcast, mpsc};
 use tracing::{debug, error, info, warn};
 
 use crate::{
     config::ConfigJDCMode,
     error::JDCError,
     status::{handle_error, Status, StatusSender},
-    task_manager::TaskManager,
-    utils::{
-        get_setup_connection_message_jds, protocol_message_type, spawn_io_tasks, Message,
-        MessageType, SV2Frame, ShutdownMessage, StdFrame,
-    },
+    utils::{get_setup_connection_message_jds, Message, SV2Frame, ShutdownMessage, StdFrame},
 };
 
 mod message_handler;
@@ -62,10 +57,17 @@ impl JobDeclarator {
     /// Creates a new JobDeclarator instance by connecting and performing a Noise handshake.
     ///
     /// - Establishes TCP connection.
-    /// - Performs SV2 Noise handshake.
+    /// - Performs SV2 Noise handshake, unless the JDS encryption of the upstream is `none`.
     /// - Spawns background IO tasks for reading/writing frames.
     pub async fn new(
-        upstreams: &(SocketAddr, SocketAddr, Secp256k1PublicKey, bool),
+        upstreams: &(
+            SocketAddr,
+            SocketAddr,
+            AuthorityPublicKeys,
+            bool,
+            Encryption,
+            Encryption,
+        ),
         channel_manager_sender: Sender<JobDeclaration<'static>>,
         channel_manager_receiver: Receiver<JobDeclaration<'static>>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
@@ -73,19 +75,15 @@ impl JobDeclarator {
         task_manager: Arc<TaskManager>,
         status_sender: Sender<Status>,
     ) -> Result<Self, JDCError> {
-        let (_, addr, pubkey, _) = upstreams;
+        let (_, addr, pubkeys, _, _, encryption) = upstreams;
         info!("Connecting to JD Server at {addr}");
-        let stream = tokio::time::timeout(
+        let (noise_stream_reader, noise_stream_writer) = tokio::time::timeout(
             tokio::time::Duration::from_secs(5),
-            TcpStream::connect(addr),
+            connect::<Message>(*addr, *encryption, Some(pubkeys)),
         )
-        .await??;
+        .await??
+        .into_split();
         info!("Connection established with JD Server at {addr} in mode: {mode:?}");
-        let initiator = Initiator::from_raw_k(pubkey.into_bytes())?;
-        let (noise_stream_reader, noise_stream_writer) =
-            NoiseTcpStream::<Message>::new(stream, HandshakeRole::Initiator(initiator))
-                .await?
-                .into_split();
 
         let status_sender = StatusSender::JobDeclarator(status_sender);
         let (inbound_tx, inbound_rx) = unbounded::<SV2Frame>();
@@ -99,6 +97,7 @@ impl JobDeclarator {
             inbound_tx,
             notify_shutdown,
             status_sender,
+            None,
         );
         let job_declarator_data = Arc::new(Mutex::new(JobDeclaratorData));
         let job_declarator_channel = JobDeclaratorChannel {
@@ -2,77 +2,137 @@ use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use async_channel::{unbounded, Receiver, Sender};
 use stratum_apps::{
-    key_utils::Secp256k1PublicKey,
+    key_utils::AuthorityPublicKeys,
+    network_helpers::transport::Encryption,
+    runtime::TaskManager,
+    share_reject::ShareRejectCounters,
+    status::HealthAggregator,
     stratum_core::{
-        bitcoin::consensus::Encodable,
+        noise_sv2::Responder,
         parsers_sv2::{JobDeclaration, Mining},
     },
 };
-use tokio::sync::{broadcast, mpsc};
-use tracing::{debug, info, warn};
+use tokio::{
+    net::TcpStream,
+    sync::{broadcast, mpsc},
+};
+use tracing::{debug, error, info, warn};
 
 use crate::{
     channel_manager::ChannelManager,
+    coinbase_outputs::encode_outputs,
     config::{ConfigJDCMode, JobDeclaratorClientConfig},
     error::JDCError,
     jd_mode::{set_jd_mode, JdMode},
     job_declarator::JobDeclarator,
-    status::{State, Status},
-    task_manager::TaskManager,
+    monitoring::{DeclarationStats, JdcStatusProvider},
+    status::{report_task_panics, State, Status},
     template_receiver::TemplateReceiver,
+    tx_selection::{policy_from_config, TxSelectionPolicy},
     upstream::Upstream,
-    utils::{ShutdownMessage, UpstreamState},
+    utils::{ShutdownMessage, UpstreamState, UpstreamTransition},
 };
 
 mod channel_manager;
+pub mod coinbase_outputs;
 pub mod config;
 mod downstream;
 pub mod error;
 pub mod jd_mode;
 mod job_declarator;
+pub mod monitoring;
 mod status;
-mod task_manager;
 mod template_receiver;
+pub mod tx_selection;
 mod upstream;
 pub mod utils;
 
+// Components reported to the health aggregator
+const TEMPLATE_RECEIVER: &str = "template_receiver";
+const CHANNEL_MANAGER: &str = "channel_manager";
+const UPSTREAM: &str = "upstream";
+
 /// Represent Job Declarator Client
 #[derive(Clone)]
 pub struct JobDeclaratorClient {
     config: JobDeclaratorClientConfig,
     notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    upstream_transitions: broadcast::Sender<UpstreamTransition>,
+    tx_selection_policy: Option<Arc<dyn TxSelectionPolicy>>,
 }
 
 impl JobDeclaratorClient {
     /// Creates a new [`JobDeclaratorClient`] instance.
     pub fn new(config: JobDeclaratorClientConfig) -> Self {
         let (notify_shutdown, _) = tokio::sync::broadcast::channel::<ShutdownMessage>(100);
+        let (upstream_transitions, _) = broadcast::channel::<UpstreamTransition>(100);
         Self {
             config,
             notify_shutdown,
+            upstream_transitions,
+            tx_selection_policy: None,
         }
     }
 
+    /// Mines only the templates whose transactions satisfy `policy`, instead of the policy
+    /// configured by `tx_filter`.
+    pub fn with_tx_selection_policy(mut self, policy: Arc<dyn TxSelectionPolicy>) -> Self {
+        self.tx_selection_policy = Some(policy);
+        self
+    }
+
+    /// Subscribes to the [`UpstreamTransition`]s published while connecting to, and failing over
+    /// between, the configured upstream pairs.
+    pub fn subscribe_upstream_transitions(&self) -> broadcast::Receiver<UpstreamTransition> {
+        self.upstream_transitions.subscribe()
+    }
+
+    // Logs an upstream transition and publishes it to the subscribers, if any.
+    fn notify_upstream_transition(&self, transition: UpstreamTransition) {
+        info!("Upstream transition: {transition}");
+        let _ = self.upstream_transitions.send(transition);
+    }
+
     /// Starts the Job Declarator Client (JDC) main loop.
-    pub async fn start(&self) {
+    ///
+    /// Returns the error the JDC stopped on, if it did not shut down cleanly.
+    pub async fn start(&self) -> Result<(), JDCError> {
         info!(
             "Job declarator client starting... setting up subsystems, User Identity: {}",
             self.config.user_identity()
         );
+        if let Err(e) = Responder::from_authority_kp(
+            &self.config.authority_public_key().into_bytes(),
+            &self.config.authority_secret_key().into_bytes(),
+            Duration::from_secs(self.config.cert_validity_sec()),
+        ) {
+            error!("Invalid authority keypair: {e:?}");
+            return Err(JDCError::CodecNoise(e));
+        }
 
-        let miner_coinbase_outputs = vec![self.config.get_txout()];
-        let mut encoded_outputs = vec![];
-
-        miner_coinbase_outputs
-            .consensus_encode(&mut encoded_outputs)
-            .expect("Invalid coinbase output in config");
+        let extra_coinbase_outputs = self
+            .config
+            .extra_coinbase_outputs()
+            .map_err(JDCError::InvalidCoinbaseOutputs)?;
+        let encoded_outputs =
+            encode_outputs(vec![self.config.get_txout()], &extra_coinbase_outputs)?;
+        let tx_selection_policy = match &self.tx_selection_policy {
+            Some(policy) => policy.clone(),
+            None => {
+                policy_from_config(self.config.tx_filter()).map_err(JDCError::InvalidTxFilter)?
+            }
+        };
 
         let notify_shutdown = self.notify_shutdown.clone();
         let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
         let task_manager = Arc::new(TaskManager::new());
 
         let (status_sender, status_receiver) = async_channel::unbounded::<Status>();
+        report_task_panics(&task_manager, status_sender.clone());
 
+        let health = Arc::new(HealthAggregator::new());
+        let share_rejects = Arc::new(ShareRejectCounters::new());
+        let declarations = Arc::new(DeclarationStats::new());
         let (channel_manager_to_upstream_sender, channel_manager_to_upstream_receiver) =
             unbounded();
         let (upstream_to_channel_manager_sender, upstream_to_channel_manager_receiver) =
@@ -103,27 +163,48 @@ impl JobDeclaratorClient {
             downstream_to_channel_manager_receiver,
             status_sender.clone(),
             encoded_outputs.clone(),
+            share_rejects.clone(),
+            declarations.clone(),
+            health.clone(),
+            tx_selection_policy,
         )
-        .await
-        .unwrap();
+        .await?;
+
+        if let Some(monitoring_config) = self.config.monitoring().cloned() {
+            stratum_apps::monitoring::spawn(
+                &task_manager,
+                monitoring_config,
+                Arc::new(
+                    JdcStatusProvider::new(
+                        health.clone(),
+                        share_rejects,
+                        declarations,
+                        task_manager.clone(),
+                    )
+                    .with_channel_manager(channel_manager.clone()),
+                ),
+                notify_shutdown.subscribe(),
+                |message| matches!(message, ShutdownMessage::ShutdownAll),
+            );
+        }
 
         let channel_manager_clone = channel_manager.clone();
 
         // Initialize the template Receiver
         let tp_address = self.config.tp_address().to_string();
-        let tp_pubkey = self.config.tp_authority_public_key().copied();
+        let tp_pubkey = self.config.tp_authority_public_key().cloned();
 
         let template_receiver = TemplateReceiver::new(
             tp_address.clone(),
             tp_pubkey,
+            self.config.tp_encryption(),
             channel_manager_to_tp_receiver,
             tp_to_channel_manager_sender,
             notify_shutdown.clone(),
             task_manager.clone(),
             status_sender.clone(),
         )
-        .await
-        .unwrap();
+        .await?;
 
         info!("Template provider setup done");
 
@@ -140,6 +221,7 @@ impl JobDeclaratorClient {
                 encoded_outputs.clone(),
             )
             .await;
+        health.healthy(TEMPLATE_RECEIVER);
 
         let mut upstream_addresses: Vec<_> = self
             .config
@@ -154,7 +236,14 @@ impl JobDeclaratorClient {
                     u.jds_address.parse().expect("Invalid JD address"),
                     u.jds_port,
                 );
-                (pool_addr, jd_addr, u.authority_pubkey, false)
+                (
+                    pool_addr,
+                    jd_addr,
+                    u.authority_pubkey.clone(),
+                    false,
+                    u.pool_encryption,
+                    u.jds_encryption,
+                )
             })
             .collect();
 
@@ -165,9 +254,12 @@ impl JobDeclaratorClient {
                 task_manager.clone(),
             )
             .await;
+        health.healthy(CHANNEL_MANAGER);
 
         info!("Attempting to initialize upstream...");
 
+        let mut active_upstream = None;
+
         match self
             .initialize_jd(
                 &mut upstream_addresses,
@@ -182,7 +274,9 @@ impl JobDeclaratorClient {
             )
             .await
         {
-            Ok((upstream, job_declarator)) => {
+            Ok((index, upstream, job_declarator)) => {
+                active_upstream = Some(index);
+                health.healthy(UPSTREAM);
                 upstream
                     .start(
                         self.config.min_supported_version(),
@@ -210,148 +304,257 @@ impl JobDeclaratorClient {
             }
             Err(e) => {
                 tracing::error!("Failed to initialize upstream: {:?}", e);
+                health.degraded(
+                    UPSTREAM,
+                    format!("solo mining, no upstream reachable: {e:?}"),
+                );
                 set_jd_mode(jd_mode::JdMode::SoloMining);
+                self.notify_upstream_transition(UpstreamTransition::SoloMining);
+                // No upstream or JD will ever signal completion on this sender.
+                drop(shutdown_complete_tx);
             }
         };
 
-        _ = channel_manager_clone
+        if let Err(e) = channel_manager_clone
             .clone()
             .start_downstream_server(
                 *self.config.authority_public_key(),
                 *self.config.authority_secret_key(),
                 self.config.cert_validity_sec(),
                 *self.config.listening_address(),
+                self.config.listen_encryption(),
+                self.config.max_frame_size(),
+                self.config.downstream_idle_timeout(),
+                *self.config.downstream_channels(),
                 task_manager.clone(),
                 notify_shutdown.clone(),
                 status_sender.clone(),
                 downstream_to_channel_manager_sender.clone(),
                 channel_manager_to_downstream_sender.clone(),
             )
-            .await;
+            .await
+        {
+            let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+            task_manager.abort_all().await;
+            return Err(e);
+        }
+        health.set_ready(true);
 
         info!("Spawning status listener task...");
         let notify_shutdown_clone = notify_shutdown.clone();
 
+        let mut upstream_retry_interval = tokio::time::interval(Duration::from_secs(
+            self.config.upstream_retry_interval_secs(),
+        ));
+        upstream_retry_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        let mut result = Ok(());
         loop {
-            tokio::select! {
+            let reconnect = tokio::select! {
                 _ = tokio::signal::ctrl_c() => {
                     info!("Ctrl+C received — initiating graceful shutdown...");
                     let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
                     break;
                 }
+                task = task_manager.critical_failure() => {
+                    warn!("Critical task {task} failed — initiating full shutdown.");
+                    health.failed(task, "critical task failed");
+                    result = Err(JDCError::Shutdown);
+                    let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
+                    break;
+                }
                 message = status_receiver.recv() => {
-                    if let Ok(status) = message {
-                        match status.state {
-                            State::DownstreamShutdown{downstream_id,..} => {
-                                warn!("Downstream {downstream_id:?} disconnected — Channel manager.");
-                                let _ = notify_shutdown_clone.send(ShutdownMessage::DownstreamShutdown(downstream_id));
-                            }
-                            State::TemplateReceiverShutdown(_) => {
-                                warn!("Template Receiver shutdown requested — initiating full shutdown.");
-                                let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
-                                break;
-                            }
-                            State::ChannelManagerShutdown(_) => {
-                                warn!("Channel Manager shutdown requested — initiating full shutdown.");
-                                let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
-                                break;
-                            }
-                            State::UpstreamShutdownFallback(_) | State::JobDeclaratorShutdownFallback(_) => {
-                                warn!("Upstream/Job Declarator connection dropped — attempting reconnection...");
-                                let (tx, mut rx) = mpsc::channel::<()>(1);
-                                let _ = notify_shutdown_clone.send(ShutdownMessage::UpstreamShutdownFallback((encoded_outputs.clone(), tx)));
-                                set_jd_mode(JdMode::SoloMining);
-                                shutdown_complete_rx.recv().await;
-                                tracing::error!("Existing Upstream or JD instance taken out");
-                                rx.recv().await;
-                                tracing::error!("All entities acknowledged Upstream fallback. Preparing fallback.");
-
-                                let (shutdown_complete_tx_fallback, shutdown_complete_rx_fallback) = mpsc::channel::<()>(1);
-
-                                shutdown_complete_rx = shutdown_complete_rx_fallback;
-
-                                info!("Attempting to initialize Jd and upstream...");
-
-                                match self
-                                    .initialize_jd(
-                                        &mut upstream_addresses,
-                                        channel_manager_to_upstream_receiver.clone(),
-                                        upstream_to_channel_manager_sender.clone(),
-                                        channel_manager_to_jd_receiver.clone(),
-                                        jd_to_channel_manager_sender.clone(),
-                                        notify_shutdown.clone(),
-                                        status_sender.clone(),
-                                        self.config.mode.clone(),
-                                        task_manager.clone(),
-                                    )
-                                    .await
-                                {
-                                    Ok((upstream, job_declarator)) => {
-                                        upstream
-                                            .start(
-                                                self.config.min_supported_version(),
-                                                self.config.max_supported_version(),
-                                                notify_shutdown.clone(),
-                                                shutdown_complete_tx_fallback.clone(),
-                                                status_sender.clone(),
-                                                task_manager.clone(),
-                                            )
-                                            .await;
-
-                                        job_declarator
-                                            .start(
-                                                notify_shutdown.clone(),
-                                                shutdown_complete_tx_fallback,
-                                                status_sender.clone(),
-                                                task_manager.clone(),
-                                            )
-                                            .await;
-
-                                        channel_manager_clone.upstream_state.set(UpstreamState::NoChannel);
-
-                                        _ = channel_manager_clone.allocate_tokens(1).await;
-                                    }
-                                    Err(e) => {
-                                        tracing::error!("Failed to initialize upstream: {:?}", e);
-                                        channel_manager_clone.upstream_state.set(UpstreamState::SoloMining);
-                                        set_jd_mode(jd_mode::JdMode::SoloMining);
-                                        info!("Fallback to solo mining mode");
-                                    }
-                                };
-
-                                _ = channel_manager_clone.clone()
-                                    .start_downstream_server(
-                                        *self.config.authority_public_key(),
-                                        *self.config.authority_secret_key(),
-                                        self.config.cert_validity_sec(),
-                                        *self.config.listening_address(),
-                                        task_manager.clone(),
-                                        notify_shutdown.clone(),
-                                        status_sender.clone(),
-                                        downstream_to_channel_manager_sender.clone(),
-                                        channel_manager_to_downstream_sender.clone(),
-                                    )
-                                    .await;
-                                }
+                    let Ok(status) = message else {
+                        continue;
+                    };
+                    match status.state {
+                        State::DownstreamShutdown{downstream_id,..} => {
+                            warn!("Downstream {downstream_id:?} disconnected — Channel manager.");
+                            let _ = notify_shutdown_clone.send(ShutdownMessage::DownstreamShutdown(downstream_id));
+                            false
+                        }
+                        State::TemplateReceiverShutdown(e) => {
+                            warn!("Template Receiver shutdown requested — initiating full shutdown.");
+                            health.failed(TEMPLATE_RECEIVER, format!("{e:?}"));
+                            result = Err(e);
+                            let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
+                            break;
+                        }
+                        State::ChannelManagerShutdown(e) => {
+                            warn!("Channel Manager shutdown requested — initiating full shutdown.");
+                            health.failed(CHANNEL_MANAGER, format!("{e:?}"));
+                            result = Err(e);
+                            let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
+                            break;
+                        }
+                        State::TaskPanicked(panic) => {
+                            warn!("Reported panic: {panic}");
+                            false
+                        }
+                        State::UpstreamShutdownFallback(e) | State::JobDeclaratorShutdownFallback(e) => {
+                            warn!("Upstream/Job Declarator connection dropped — attempting reconnection...");
+                            health.degraded(UPSTREAM, format!("failing over: {e}"));
+                            self.notify_upstream_transition(UpstreamTransition::FailingOver {
+                                index: active_upstream.take(),
+                                reason: e.to_string(),
+                            });
+                            true
+                        }
+                    }
+                }
+                _ = upstream_retry_interval.tick(), if active_upstream.is_none() && !upstream_addresses.is_empty() => {
+                    match first_reachable_upstream(&upstream_addresses).await {
+                        Some(index) => {
+                            info!("Upstream {} is reachable again — leaving solo mining...", index + 1);
+                            self.notify_upstream_transition(UpstreamTransition::Recovering { index });
+                            // Give every upstream a fresh chance, starting from the preferred one.
+                            upstream_addresses.iter_mut().for_each(|upstream| upstream.3 = false);
+                            true
+                        }
+                        None => {
+                            debug!("No upstream reachable, keep solo mining");
+                            false
                         }
                     }
                 }
+            };
+
+            if !reconnect {
+                continue;
             }
+
+            let (tx, mut rx) = mpsc::channel::<()>(1);
+            let _ = notify_shutdown_clone.send(ShutdownMessage::UpstreamShutdownFallback((
+                encoded_outputs.clone(),
+                tx,
+            )));
+            set_jd_mode(JdMode::SoloMining);
+            shutdown_complete_rx.recv().await;
+            tracing::error!("Existing Upstream or JD instance taken out");
+            rx.recv().await;
+            tracing::error!("All entities acknowledged Upstream fallback. Preparing fallback.");
+
+            let (shutdown_complete_tx_fallback, shutdown_complete_rx_fallback) =
+                mpsc::channel::<()>(1);
+
+            shutdown_complete_rx = shutdown_complete_rx_fallback;
+
+            info!("Attempting to initialize Jd and upstream...");
+
+            match self
+                .initialize_jd(
+                    &mut upstream_addresses,
+                    channel_manager_to_upstream_receiver.clone(),
+                    upstream_to_channel_manager_sender.clone(),
+                    channel_manager_to_jd_receiver.clone(),
+                    jd_to_channel_manager_sender.clone(),
+                    notify_shutdown.clone(),
+                    status_sender.clone(),
+                    self.config.mode.clone(),
+                    task_manager.clone(),
+                )
+                .await
+            {
+                Ok((index, upstream, job_declarator)) => {
+                    active_upstream = Some(index);
+                    health.healthy(UPSTREAM);
+                    upstream
+                        .start(
+                            self.config.min_supported_version(),
+                            self.config.max_supported_version(),
+                            notify_shutdown.clone(),
+                            shutdown_complete_tx_fallback.clone(),
+                            status_sender.clone(),
+                            task_manager.clone(),
+                        )
+                        .await;
+
+                    job_declarator
+                        .start(
+                            notify_shutdown.clone(),
+                            shutdown_complete_tx_fallback,
+                            status_sender.clone(),
+                            task_manager.clone(),
+                        )
+                        .await;
+
+                    channel_manager_clone
+                        .upstream_state
+                        .set(UpstreamState::NoChannel);
+
+                    _ = channel_manager_clone.allocate_tokens(1).await;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to initialize upstream: {:?}", e);
+                    health.degraded(
+                        UPSTREAM,
+                        format!("solo mining, no upstream reachable: {e:?}"),
+                    );
+                    channel_manager_clone
+                        .upstream_state
+                        .set(UpstreamState::SoloMining);
+                    set_jd_mode(jd_mode::JdMode::SoloMining);
+                    info!("Fallback to solo mining mode");
+                    self.notify_upstream_transition(UpstreamTransition::SoloMining);
+                }
+            };
+
+            _ = channel_manager_clone
+                .clone()
+                .start_downstream_server(
+                    *self.config.authority_public_key(),
+                    *self.config.authority_secret_key(),
+                    self.config.cert_validity_sec(),
+                    *self.config.listening_address(),
+                    self.config.listen_encryption(),
+                    self.config.max_frame_size(),
+                    self.config.downstream_idle_timeout(),
+                    *self.config.downstream_channels(),
+                    task_manager.clone(),
+                    notify_shutdown.clone(),
+                    status_sender.clone(),
+                    downstream_to_channel_manager_sender.clone(),
+                    channel_manager_to_downstream_sender.clone(),
+                )
+                .await;
         }
 
         warn!("Graceful shutdown");
+        let running_tasks = task_manager.running_tasks();
+        info!("Aborting {} tasks still running", running_tasks.len());
+        for task in running_tasks {
+            debug!(
+                task = task.name,
+                spawned_at = task.spawned_at,
+                uptime_secs = task.uptime_secs,
+                restarts = task.restarts,
+                "Aborting task"
+            );
+        }
         task_manager.abort_all().await;
 
         info!("Joining remaining tasks...");
         task_manager.join_all().await;
         info!("JD Client shutdown complete.");
+        result
     }
 
-    /// Initializes an upstream pool + JD connection pair.
+    /// Initializes the first available upstream pool + JD connection pair.
+    ///
+    /// Pairs are tried in configuration order. Pairs that were already used, or that failed
+    /// every retry, are skipped so that each fallback moves on to the next pair. Returns the
+    /// index of the connected pair.
     #[allow(clippy::too_many_arguments)]
     pub async fn initialize_jd(
         &self,
-        upstreams: &mut [(SocketAddr, SocketAddr, Secp256k1PublicKey, bool)],
+        upstreams: &mut [(
+            SocketAddr,
+            SocketAddr,
+            AuthorityPublicKeys,
+            bool,
+            Encryption,
+            Encryption,
+        )],
         channel_manager_to_upstream_receiver: Receiver<Mining<'static>>,
         upstream_to_channel_manager_sender: Sender<Mining<'static>>,
         channel_manager_to_jd_receiver: Receiver<JobDeclaration<'static>>,
@@ -360,7 +563,7 @@ impl JobDeclaratorClient {
         status_sender: Sender<Status>,
         mode: ConfigJDCMode,
         task_manager: Arc<TaskManager>,
-    ) -> Result<(Upstream, JobDeclarator), JDCError> {
+    ) -> Result<(usize, Upstream, JobDeclarator), JDCError> {
         const MAX_RETRIES: usize = 3;
         let upstream_len = upstreams.len();
         for (i, upstream_addr) in upstreams.iter_mut().enumerate() {
@@ -380,6 +583,12 @@ impl JobDeclaratorClient {
                 continue;
             }
 
+            self.notify_upstream_transition(UpstreamTransition::Connecting {
+                index: i,
+                pool: upstream_addr.0,
+                jds: upstream_addr.1,
+            });
+
             for attempt in 1..=MAX_RETRIES {
                 info!("Connection attempt {}/{}...", attempt, MAX_RETRIES);
 
@@ -396,9 +605,14 @@ impl JobDeclaratorClient {
                 )
                 .await
                 {
-                    Ok(pair) => {
+                    Ok((upstream, job_declarator)) => {
                         upstream_addr.3 = true;
-                        return Ok(pair);
+                        self.notify_upstream_transition(UpstreamTransition::Connected {
+                            index: i,
+                            pool: upstream_addr.0,
+                            jds: upstream_addr.1,
+                        });
+                        return Ok((i, upstream, job_declarator));
                     }
                     Err(e) => {
                         let (tx, mut rx) = mpsc::channel::<()>(1);
@@ -427,10 +641,40 @@ impl JobDeclaratorClient {
     }
 }
 
+// Returns the index of the first upstream (pool + JDS pair) accepting TCP connections on both
+// endpoints, if any.
+async fn first_reachable_upstream(
+    upstreams: &[(
+        SocketAddr,
+        SocketAddr,
+        AuthorityPublicKeys,
+        bool,
+        Encryption,
+        Encryption,
+    )],
+) -> Option<usize> {
+    const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+    for (index, (pool_addr, jd_addr, _, _, _, _)) in upstreams.iter().enumerate() {
+        let pool = tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(pool_addr));
+        let jds = tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(jd_addr));
+        if let (Ok(Ok(_)), Ok(Ok(_))) = tokio::join!(pool, jds) {
+            return Some(index);
+        }
+    }
+    None
+}
+
 // Attempts to initialize a single upstream (pool + JDS pair).
 #[allow(clippy::too_many_arguments)]
 async fn try_initialize_single(
-    upstream_addr: &(SocketAddr, SocketAddr, Secp256k1PublicKey, bool),
+    upstream_addr: &(
+        SocketAddr,
+        SocketAddr,
+        AuthorityPublicKeys,
+        bool,
+        Encryption,
+        Encryption,
+    ),
     upstream_to_channel_manager_sender: Sender<Mining<'static>>,
     channel_manager_to_upstream_receiver: Receiver<Mining<'static>>,
     jd_to_channel_manager_sender: Sender<JobDeclaration<'static>>,
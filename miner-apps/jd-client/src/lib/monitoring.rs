@@ -0,0 +1,226 @@
+//! ## Monitoring Module
+//!
+//! Builds the document served by the JDC's HTTP monitoring endpoint
+//! (see [`stratum_apps::monitoring`]): the health of the JDC components, the number of shares
+//! of its downstreams rejected per error code, the outcome of the jobs declared to the JDS, the
+//! connections of the downstreams with their traffic, the tasks currently running and the number
+//! of frames refused for exceeding the maximum frame size.
+//! The health also answers the `/healthz` and `/readyz` probes.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use stratum_apps::{
+    connections::ConnectionInfo,
+    custom_mutex::Mutex,
+    monitoring::StatusProvider,
+    network_helpers::{self, traffic::TrafficSnapshot},
+    runtime::{TaskInfo, TaskManager},
+    share_reject::ShareRejectCounters,
+    status::{HealthAggregator, HealthReport, HealthTransition},
+};
+
+use crate::channel_manager::ChannelManager;
+
+/// Jobs declared to the JDS: how many it accepted and refused, how often it asked for the
+/// transactions it was missing, and the time from each `DeclareMiningJob` to its answer,
+/// including the round trips of the missing transactions.
+#[derive(Debug, Default)]
+pub struct DeclarationStats {
+    declared: AtomicU64,
+    accepted: AtomicU64,
+    refused: AtomicU64,
+    missing_tx_requests: AtomicU64,
+    missing_txs_provided: AtomicU64,
+    missing_tx_bytes_provided: AtomicU64,
+    // When each declaration awaiting an answer was sent, by request id.
+    pending: Mutex<HashMap<u32, Instant>>,
+    answer_count: AtomicU64,
+    answer_total_us: AtomicU64,
+    answer_last_us: AtomicU64,
+    answer_max_us: AtomicU64,
+}
+
+impl DeclarationStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a `DeclareMiningJob` sent to the JDS.
+    pub fn declared(&self, request_id: u32) {
+        self.declared.fetch_add(1, Ordering::Relaxed);
+        self.pending
+            .super_safe_lock(|pending| pending.insert(request_id, Instant::now()));
+    }
+
+    /// Records the answer of the JDS to the declaration `request_id`.
+    pub fn answered(&self, request_id: u32, accepted: bool) {
+        let counter = if accepted {
+            &self.accepted
+        } else {
+            &self.refused
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let declared_at = self
+            .pending
+            .super_safe_lock(|pending| pending.remove(&request_id));
+        if let Some(declared_at) = declared_at {
+            self.record_latency(declared_at.elapsed());
+        }
+    }
+
+    /// Records a `ProvideMissingTransactions` answered with `count` transactions of `bytes`
+    /// bytes in total.
+    pub fn missing_txs_provided(&self, count: usize, bytes: usize) {
+        self.missing_tx_requests.fetch_add(1, Ordering::Relaxed);
+        self.missing_txs_provided
+            .fetch_add(count as u64, Ordering::Relaxed);
+        self.missing_tx_bytes_provided
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Forgets the declarations awaiting an answer, once disconnected from their JDS.
+    pub fn forget_pending(&self) {
+        self.pending.super_safe_lock(|pending| pending.clear());
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        let latency_us = latency.as_micros() as u64;
+        self.answer_count.fetch_add(1, Ordering::Relaxed);
+        self.answer_total_us
+            .fetch_add(latency_us, Ordering::Relaxed);
+        self.answer_last_us.store(latency_us, Ordering::Relaxed);
+        self.answer_max_us.fetch_max(latency_us, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> DeclarationStatus {
+        let accepted = self.accepted.load(Ordering::Relaxed);
+        let refused = self.refused.load(Ordering::Relaxed);
+        let answer_count = self.answer_count.load(Ordering::Relaxed);
+        DeclarationStatus {
+            declared: self.declared.load(Ordering::Relaxed),
+            accepted,
+            refused,
+            acceptance_rate: (accepted + refused > 0)
+                .then(|| accepted as f64 / (accepted + refused) as f64),
+            missing_tx_requests: self.missing_tx_requests.load(Ordering::Relaxed),
+            missing_txs_provided: self.missing_txs_provided.load(Ordering::Relaxed),
+            missing_tx_bytes_provided: self.missing_tx_bytes_provided.load(Ordering::Relaxed),
+            latency: LatencyStatus {
+                count: answer_count,
+                last_us: self.answer_last_us.load(Ordering::Relaxed),
+                max_us: self.answer_max_us.load(Ordering::Relaxed),
+                mean_us: self
+                    .answer_total_us
+                    .load(Ordering::Relaxed)
+                    .checked_div(answer_count)
+                    .unwrap_or_default(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DeclarationStatus {
+    declared: u64,
+    accepted: u64,
+    refused: u64,
+    // Share of the answered declarations the JDS accepted, unset before the first answer.
+    acceptance_rate: Option<f64>,
+    missing_tx_requests: u64,
+    missing_txs_provided: u64,
+    missing_tx_bytes_provided: u64,
+    latency: LatencyStatus,
+}
+
+#[derive(Debug, Serialize)]
+struct LatencyStatus {
+    count: u64,
+    last_us: u64,
+    max_us: u64,
+    mean_us: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ConnectionStatus {
+    #[serde(flatten)]
+    info: ConnectionInfo,
+    traffic: TrafficSnapshot,
+}
+
+#[derive(Debug, Serialize)]
+struct JdcStatus {
+    health: HealthReport,
+    shares_rejected: BTreeMap<&'static str, u64>,
+    declarations: DeclarationStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connections: Option<Vec<ConnectionStatus>>,
+    tasks: Vec<TaskInfo>,
+    oversized_frames: u64,
+}
+
+/// [`StatusProvider`] of the JDC.
+pub struct JdcStatusProvider {
+    health: Arc<HealthAggregator>,
+    share_rejects: Arc<ShareRejectCounters>,
+    declarations: Arc<DeclarationStats>,
+    task_manager: Arc<TaskManager>,
+    channel_manager: Option<ChannelManager>,
+}
+
+impl JdcStatusProvider {
+    pub fn new(
+        health: Arc<HealthAggregator>,
+        share_rejects: Arc<ShareRejectCounters>,
+        declarations: Arc<DeclarationStats>,
+        task_manager: Arc<TaskManager>,
+    ) -> Self {
+        Self {
+            health,
+            share_rejects,
+            declarations,
+            task_manager,
+            channel_manager: None,
+        }
+    }
+
+    /// Also serves the connections of the downstreams of `channel_manager` with their traffic.
+    pub fn with_channel_manager(mut self, channel_manager: ChannelManager) -> Self {
+        self.channel_manager = Some(channel_manager);
+        self
+    }
+}
+
+impl StatusProvider for JdcStatusProvider {
+    fn status(&self) -> serde_json::Value {
+        serde_json::to_value(JdcStatus {
+            health: self.health.snapshot(),
+            shares_rejected: self.share_rejects.snapshot(),
+            declarations: self.declarations.snapshot(),
+            connections: self.channel_manager.as_ref().map(|channel_manager| {
+                channel_manager
+                    .connections()
+                    .into_iter()
+                    .map(|(info, traffic)| ConnectionStatus { info, traffic })
+                    .collect()
+            }),
+            tasks: self.task_manager.running_tasks(),
+            oversized_frames: network_helpers::oversized_frames(),
+        })
+        .expect("JDC status is always serializable")
+    }
+
+    fn health(&self) -> HealthReport {
+        self.health.snapshot()
+    }
+
+    fn health_transitions(&self) -> Vec<HealthTransition> {
+        self.health.transitions()
+    }
+}
@@ -6,9 +6,15 @@
 //! and receive status updates via typed channels. Errors are automatically
 //! converted into shutdown signals, allowing coordinated teardown of tasks.
 
+use std::time::Duration;
+
+use stratum_apps::runtime::{IoTaskStatus, TaskManager, TaskPanic};
 use tracing::{debug, error, warn};
 
-use crate::{error::JDCError, utils::DownstreamId};
+use crate::{
+    error::JDCError,
+    utils::{DownstreamId, ShutdownMessage},
+};
 
 /// Sender type for propagating status updates from different system components.
 #[derive(Debug, Clone)]
@@ -89,6 +95,30 @@ impl StatusSender {
     }
 }
 
+impl IoTaskStatus<ShutdownMessage> for StatusSender {
+    fn stops_on(&self, message: &ShutdownMessage) -> bool {
+        let status_type = StatusType::from(self);
+        match message {
+            ShutdownMessage::ShutdownAll => true,
+            ShutdownMessage::DownstreamShutdown(down_id) => {
+                matches!(status_type, StatusType::Downstream(id) if id == *down_id)
+            }
+            // The template receiver outlives fallbacks and upstream changes
+            ShutdownMessage::JobDeclaratorShutdownFallback(_)
+            | ShutdownMessage::UpstreamShutdownFallback(_)
+            | ShutdownMessage::UpstreamShutdown(_)
+            | ShutdownMessage::JobDeclaratorShutdown(_) => {
+                status_type != StatusType::TemplateReceiver
+            }
+            ShutdownMessage::DownstreamShutdownAll => false,
+        }
+    }
+
+    async fn idle_timeout(&self, idle_timeout: Duration) {
+        handle_error(self, JDCError::IdleTimeout(idle_timeout)).await;
+    }
+}
+
 /// Represents the state of a component, typically triggered by an error or shutdown event.
 #[derive(Debug)]
 pub enum State {
@@ -105,6 +135,8 @@ pub enum State {
     ChannelManagerShutdown(JDCError),
     /// Upstream has shut down during fallback with a reason.
     UpstreamShutdownFallback(JDCError),
+    /// A task panicked, see [`TaskManager::on_panic`].
+    TaskPanicked(TaskPanic),
 }
 
 /// Wrapper around a component’s state, sent as status updates across the system.
@@ -152,3 +184,17 @@ pub async fn handle_error(sender: &StatusSender, e: JDCError) {
     error!("Error in {:?}: {:?}", sender, e);
     send_status(sender, e).await;
 }
+
+/// Forwards the panics caught in the tasks of `task_manager` to the main loop, as
+/// [`State::TaskPanicked`] statuses sent on `tx`.
+pub fn report_task_panics(task_manager: &TaskManager, tx: async_channel::Sender<Status>) {
+    task_manager.on_panic(move |panic| {
+        let tx = tx.clone();
+        async move {
+            let state = State::TaskPanicked(panic);
+            if let Err(e) = tx.send(Status { state }).await {
+                error!("Failed to send task panic status: {e:?}");
+            }
+        }
+    });
+}
@@ -10,37 +10,32 @@
 //! - Forward messages from the channel manager upstream to the template provider
 //! - Send [`CoinbaseOutputConstraints`] to the template provider
 
-use std::{net::SocketAddr, sync::Arc};
+use std::sync::Arc;
 
 use async_channel::{unbounded, Receiver, Sender};
 use stratum_apps::{
     custom_mutex::Mutex,
-    key_utils::Secp256k1PublicKey,
-    network_helpers::noise_stream::NoiseTcpStream,
+    key_utils::AuthorityPublicKeys,
+    network_helpers::transport::{connect_address, Encryption},
+    runtime::{
+        message_type::{protocol_message_type, MessageType},
+        spawn_io_tasks, TaskManager,
+    },
     stratum_core::{
-        bitcoin::{
-            self, absolute::LockTime, transaction::Version, OutPoint, ScriptBuf, Sequence,
-            Transaction, TxIn, TxOut, Witness,
-        },
-        codec_sv2::HandshakeRole,
         framing_sv2,
         handlers_sv2::HandleCommonMessagesFromServerAsync,
-        noise_sv2::Initiator,
         parsers_sv2::{AnyMessage, TemplateDistribution},
         template_distribution_sv2::CoinbaseOutputConstraints,
     },
 };
-use tokio::{net::TcpStream, sync::broadcast};
+use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
 
 use crate::{
+    coinbase_outputs::coinbase_output_constraints,
     error::JDCError,
     status::{handle_error, Status, StatusSender},
-    task_manager::TaskManager,
-    utils::{
-        get_setup_connection_message_tp, protocol_message_type, spawn_io_tasks, Message,
-        MessageType, SV2Frame, ShutdownMessage, StdFrame,
-    },
+    utils::{get_setup_connection_message_tp, Message, SV2Frame, ShutdownMessage, StdFrame},
 };
 
 mod message_handler;
@@ -85,14 +80,15 @@ pub struct TemplateReceiver {
 impl TemplateReceiver {
     /// Establish a new connection to a Template Provider.
     ///
-    /// - Opens a TCP connection
-    /// - Performs Noise handshake
+    /// - Opens a TCP connection, or a unix socket connection for a `unix:<path>` address
+    /// - Performs Noise handshake, accepting any of `public_keys`, unless `encryption` is `none`
     /// - Spawns IO tasks for inbound/outbound frames
     ///
-    /// Retries up to 3 times before returning [`JDCError::Shutdown`].
+    /// Retries up to 3 times before returning [`JDCError::TemplateProviderUnreachable`].
     pub async fn new(
         tp_address: String,
-        public_key: Option<Secp256k1PublicKey>,
+        public_keys: Option<AuthorityPublicKeys>,
+        encryption: Encryption,
         channel_manager_receiver: Receiver<TemplateDistribution<'static>>,
         channel_manager_sender: Sender<TemplateDistribution<'static>>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
@@ -104,70 +100,42 @@ impl TemplateReceiver {
         for attempt in 1..=MAX_RETRIES {
             info!(attempt, MAX_RETRIES, "Connecting to template provider");
 
-            let initiator = match public_key {
-                Some(pub_key) => {
-                    debug!(attempt, "Using public key for initiator handshake");
-                    Initiator::from_raw_k(pub_key.into_bytes())
-                }
-                None => {
-                    debug!(attempt, "Using anonymous initiator (no public key)");
-                    Initiator::without_pk()
-                }
-            }?;
-
-            match TcpStream::connect(tp_address.as_str()).await {
+            match connect_address::<Message>(&tp_address, encryption, public_keys.as_ref()).await {
                 Ok(stream) => {
-                    info!(
-                        attempt,
-                        "TCP connection established, starting Noise handshake"
+                    info!(attempt, "Connection to template provider established");
+
+                    let (reader, writer) = stream.into_split();
+
+                    let status_sender = StatusSender::TemplateReceiver(status_sender);
+                    let (inbound_tx, inbound_rx) = unbounded::<SV2Frame>();
+                    let (outbound_tx, outbound_rx) = unbounded::<SV2Frame>();
+
+                    info!(attempt, "Spawning IO tasks for template receiver");
+                    spawn_io_tasks(
+                        task_manager.clone(),
+                        reader,
+                        writer,
+                        outbound_rx,
+                        inbound_tx,
+                        notify_shutdown,
+                        status_sender,
+                        None,
                     );
 
-                    match NoiseTcpStream::<Message>::new(
-                        stream,
-                        HandshakeRole::Initiator(initiator),
-                    )
-                    .await
-                    {
-                        Ok(noise_stream) => {
-                            info!(attempt, "Noise handshake completed successfully");
-
-                            let (noise_stream_reader, noise_stream_writer) =
-                                noise_stream.into_split();
-
-                            let status_sender = StatusSender::TemplateReceiver(status_sender);
-                            let (inbound_tx, inbound_rx) = unbounded::<SV2Frame>();
-                            let (outbound_tx, outbound_rx) = unbounded::<SV2Frame>();
-
-                            info!(attempt, "Spawning IO tasks for template receiver");
-                            spawn_io_tasks(
-                                task_manager.clone(),
-                                noise_stream_reader,
-                                noise_stream_writer,
-                                outbound_rx,
-                                inbound_tx,
-                                notify_shutdown,
-                                status_sender,
-                            );
-
-                            let template_receiver_data = Arc::new(Mutex::new(TemplateReceiverData));
-                            let template_receiver_channel = TemplateReceiverChannel {
-                                channel_manager_receiver,
-                                channel_manager_sender,
-                                tp_receiver: inbound_rx,
-                                tp_sender: outbound_tx,
-                            };
-
-                            info!(attempt, "TemplateReceiver initialized successfully");
-                            return Ok(TemplateReceiver {
-                                template_receiver_channel,
-                                template_receiver_data,
-                                tp_address,
-                            });
-                        }
-                        Err(e) => {
-                            error!(attempt, error = ?e, "Noise handshake failed");
-                        }
-                    }
+                    let template_receiver_data = Arc::new(Mutex::new(TemplateReceiverData));
+                    let template_receiver_channel = TemplateReceiverChannel {
+                        channel_manager_receiver,
+                        channel_manager_sender,
+                        tp_receiver: inbound_rx,
+                        tp_sender: outbound_tx,
+                    };
+
+                    info!(attempt, "TemplateReceiver initialized successfully");
+                    return Ok(TemplateReceiver {
+                        template_receiver_channel,
+                        template_receiver_data,
+                        tp_address,
+                    });
                 }
                 Err(e) => {
                     warn!(attempt, MAX_RETRIES, error = ?e, "Failed to connect to template provider");
@@ -181,7 +149,7 @@ impl TemplateReceiver {
         }
 
         error!("Exhausted all connection attempts, shutting down TemplateReceiver");
-        Err(JDCError::Shutdown)
+        Err(JDCError::TemplateProviderUnreachable(tp_address))
     }
 
     /// Start unified message loop for template receiver.
@@ -210,7 +178,8 @@ impl TemplateReceiver {
         _ = self.coinbase_constraints(coinbase_outputs).await;
 
         info!("Setup Connection done. connection with template receiver is now done");
-        task_manager.spawn(
+        task_manager.spawn_critical(
+            "template_receiver",
             async move {
                 loop {
                     let mut self_clone_1 = self.clone();
@@ -332,38 +301,13 @@ impl TemplateReceiver {
         &mut self,
         coinbase_outputs: Vec<u8>,
     ) -> Result<(), JDCError> {
+        let constraints: CoinbaseOutputConstraints =
+            coinbase_output_constraints(&coinbase_outputs)?;
         debug!(
-            "Deserializing coinbase outputs ({} bytes)",
-            coinbase_outputs.len()
+            max_size = constraints.coinbase_output_max_additional_size,
+            max_sigops = constraints.coinbase_output_max_additional_sigops,
+            "Calculated coinbase output constraints"
         );
-        let outputs: Vec<TxOut> = bitcoin::consensus::deserialize(&coinbase_outputs)?;
-
-        let max_size: u32 = outputs.iter().map(|o| o.size() as u32).sum();
-        debug!(
-            max_size,
-            outputs_count = outputs.len(),
-            "Calculated max coinbase output size"
-        );
-
-        let dummy_coinbase = Transaction {
-            version: Version::TWO,
-            lock_time: LockTime::ZERO,
-            input: vec![TxIn {
-                previous_output: OutPoint::null(),
-                script_sig: ScriptBuf::new(),
-                sequence: Sequence::MAX,
-                witness: Witness::from(vec![vec![0; 32]]),
-            }],
-            output: outputs,
-        };
-
-        let max_sigops = dummy_coinbase.total_sigop_cost(|_| None) as u16;
-        debug!(max_sigops, "Calculated max sigops for coinbase");
-
-        let constraints = CoinbaseOutputConstraints {
-            coinbase_output_max_additional_size: max_size,
-            coinbase_output_max_additional_sigops: max_sigops,
-        };
 
         let msg = AnyMessage::TemplateDistribution(
             TemplateDistribution::CoinbaseOutputConstraints(constraints),
@@ -385,13 +329,11 @@ impl TemplateReceiver {
 
     // Performs the initial handshake with template provider.
     pub async fn setup_connection(&mut self, addr: String) -> Result<(), JDCError> {
-        let socket: SocketAddr = addr.parse().map_err(|_| {
+        info!(%addr, "Building setup connection message for upstream");
+        let setup_msg = get_setup_connection_message_tp(&addr).ok_or_else(|| {
             error!(%addr, "Invalid socket address");
             JDCError::InvalidSocketAddress(addr.clone())
         })?;
-
-        info!(%socket, "Building setup connection message for upstream");
-        let setup_msg = get_setup_connection_message_tp(socket);
         let frame: StdFrame = Message::Common(setup_msg.into()).try_into()?;
 
         info!("Sending setup connection message to upstream");
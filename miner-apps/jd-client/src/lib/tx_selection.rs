@@ -0,0 +1,111 @@
+//! ## Transaction Selection Module
+//!
+//! Decides which transactions of the Template Provider the JDC mines. In `FULLTEMPLATE` mode,
+//! the JDC requests the transactions of each template before building its jobs, and only mines
+//! the templates whose transactions are all accepted by its [`TxSelectionPolicy`]. Downstreams
+//! keep mining on the last accepted template meanwhile.
+//!
+//! A template cannot be trimmed of the transactions a policy excludes, since the Template
+//! Provider does not report their fees, which its coinbase value includes. Policies therefore
+//! judge the transactions on their content only.
+//!
+//! The JDC applies a [`FilterList`] built from the `tx_filter` configuration, or
+//! [`AllTransactions`] if it is empty. Library users can plug their own policy with
+//! `JobDeclaratorClient::with_tx_selection_policy`.
+
+use std::{collections::HashSet, fmt, str::FromStr, sync::Arc};
+
+use stratum_apps::stratum_core::bitcoin::{ScriptBuf, Transaction, Txid};
+
+use crate::config::TxFilter;
+
+/// Policy deciding which transactions of the templates the JDC mines.
+pub trait TxSelectionPolicy: fmt::Debug + Send + Sync {
+    /// Returns why `transaction` must not be mined, if it must not.
+    fn excludes(&self, transaction: &Transaction) -> Option<String>;
+
+    /// Returns `true` if the policy never excludes a transaction, in which case the jobs of a
+    /// template are built without waiting for its transactions.
+    fn is_passthrough(&self) -> bool {
+        false
+    }
+
+    /// Returns why the template made of `transactions` must not be mined, if it must not.
+    fn rejects(&self, transactions: &[Transaction]) -> Option<String> {
+        transactions.iter().find_map(|transaction| {
+            self.excludes(transaction)
+                .map(|reason| format!("transaction {}: {reason}", transaction.compute_txid()))
+        })
+    }
+}
+
+/// Default policy, mining every transaction of the templates.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AllTransactions;
+
+impl TxSelectionPolicy for AllTransactions {
+    fn excludes(&self, _transaction: &Transaction) -> Option<String> {
+        None
+    }
+
+    fn is_passthrough(&self) -> bool {
+        true
+    }
+}
+
+/// Policy excluding the transactions of a filter list, by txid or by the script of one of their
+/// outputs.
+#[derive(Debug, Default, Clone)]
+pub struct FilterList {
+    txids: HashSet<Txid>,
+    output_scripts: HashSet<ScriptBuf>,
+}
+
+impl FilterList {
+    /// Creates a policy excluding `txids` and the transactions paying to `output_scripts`.
+    pub fn new(
+        txids: impl IntoIterator<Item = Txid>,
+        output_scripts: impl IntoIterator<Item = ScriptBuf>,
+    ) -> Self {
+        Self {
+            txids: txids.into_iter().collect(),
+            output_scripts: output_scripts.into_iter().collect(),
+        }
+    }
+
+    /// Creates the policy configured by `filter`, or returns why a txid of it is invalid.
+    pub fn from_config(filter: &TxFilter) -> Result<Self, String> {
+        let txids = filter
+            .txids()
+            .iter()
+            .map(|txid| Txid::from_str(txid).map_err(|e| format!("invalid txid `{txid}`: {e}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let output_scripts = filter
+            .output_scripts()
+            .iter()
+            .map(|script| script.script_pubkey());
+        Ok(Self::new(txids, output_scripts))
+    }
+}
+
+impl TxSelectionPolicy for FilterList {
+    fn excludes(&self, transaction: &Transaction) -> Option<String> {
+        if self.txids.contains(&transaction.compute_txid()) {
+            return Some("txid is filtered".to_string());
+        }
+        transaction
+            .output
+            .iter()
+            .position(|output| self.output_scripts.contains(&output.script_pubkey))
+            .map(|index| format!("output {index} pays to a filtered script"))
+    }
+}
+
+/// Returns the policy configured by `filter`: a [`FilterList`], or [`AllTransactions`] if it is
+/// empty.
+pub fn policy_from_config(filter: &TxFilter) -> Result<Arc<dyn TxSelectionPolicy>, String> {
+    if filter.is_empty() {
+        return Ok(Arc::new(AllTransactions));
+    }
+    Ok(Arc::new(FilterList::from_config(filter)?))
+}
@@ -4,7 +4,7 @@
 //! with an upstream SV2 server (e.g., pool).
 //!
 //! Responsibilities:
-//! - Establish a TCP + Noise encrypted connection to upstream
+//! - Establish a TCP connection to upstream, Noise encrypted unless `pool_encryption = "none"`
 //! - Perform `SetupConnection` handshake
 //! - Forward SV2 mining messages between upstream and channel manager
 //! - Handle common messages from upstream
@@ -14,30 +14,25 @@ use std::{net::SocketAddr, sync::Arc};
 use async_channel::{unbounded, Receiver, Sender};
 use stratum_apps::{
     custom_mutex::Mutex,
-    key_utils::Secp256k1PublicKey,
-    network_helpers::noise_stream::NoiseTcpStream,
+    key_utils::AuthorityPublicKeys,
+    network_helpers::transport::{connect, Encryption},
+    runtime::{
+        message_type::{protocol_message_type, MessageType},
+        spawn_io_tasks, TaskManager,
+    },
     stratum_core::{
-        codec_sv2::HandshakeRole,
         framing_sv2,
         handlers_sv2::HandleCommonMessagesFromServerAsync,
-        noise_sv2::Initiator,
         parsers_sv2::{AnyMessage, Mining},
     },
 };
-use tokio::{
-    net::TcpStream,
-    sync::{broadcast, mpsc},
-};
+use tokio::sync::{broadcast, mpsc};
 use tracing::{debug, error, info, warn};
 
 use crate::{
     error::JDCError,
     status::{handle_error, Status, StatusSender},
-    task_manager::TaskManager,
-    utils::{
-        get_setup_connection_message, protocol_message_type, spawn_io_tasks, Message, MessageType,
-        SV2Frame, ShutdownMessage, StdFrame,
-    },
+    utils::{get_setup_connection_message, Message, SV2Frame, ShutdownMessage, StdFrame},
 };
 
 mod message_handler;
@@ -72,29 +67,33 @@ pub struct Upstream {
 impl Upstream {
     /// Create a new [`Upstream`] connection to the given address.
     ///
-    /// - Establishes TCP + Noise connection
+    /// - Establishes TCP + Noise (or plaintext) connection, accepting any of the upstream
+    ///   authority keys
     /// - Spawns IO tasks to handle inbound/outbound traffic
     pub async fn new(
-        upstreams: &(SocketAddr, SocketAddr, Secp256k1PublicKey, bool),
+        upstreams: &(
+            SocketAddr,
+            SocketAddr,
+            AuthorityPublicKeys,
+            bool,
+            Encryption,
+            Encryption,
+        ),
         channel_manager_sender: Sender<Mining<'static>>,
         channel_manager_receiver: Receiver<Mining<'static>>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         task_manager: Arc<TaskManager>,
         status_sender: Sender<Status>,
     ) -> Result<Self, JDCError> {
-        let (addr, _, pubkey, _) = upstreams;
-        let stream = tokio::time::timeout(
+        let (addr, _, pubkeys, _, encryption, _) = upstreams;
+        debug!("Begin with noise setup in upstream connection");
+        let (noise_stream_reader, noise_stream_writer) = tokio::time::timeout(
             tokio::time::Duration::from_secs(5),
-            TcpStream::connect(addr),
+            connect::<Message>(*addr, *encryption, Some(pubkeys)),
         )
-        .await??;
+        .await??
+        .into_split();
         info!("Connected to upstream at {}", addr);
-        let initiator = Initiator::from_raw_k(pubkey.into_bytes())?;
-        debug!("Begin with noise setup in upstream connection");
-        let (noise_stream_reader, noise_stream_writer) =
-            NoiseTcpStream::<Message>::new(stream, HandshakeRole::Initiator(initiator))
-                .await?
-                .into_split();
 
         let status_sender = StatusSender::Upstream(status_sender);
         let (inbound_tx, inbound_rx) = unbounded::<SV2Frame>();
@@ -108,6 +107,7 @@ impl Upstream {
             inbound_tx,
             notify_shutdown,
             status_sender,
+            None,
         );
 
         debug!("Noise setup done  in upstream connection");
@@ -1,17 +1,34 @@
+use std::process::ExitCode;
+
 use jd_client_sv2::JobDeclaratorClient;
-use stratum_apps::config_helpers::logging::init_logging;
+use stratum_apps::{
+    config_helpers::logging::{init_logging, shutdown_logging},
+    exit::ExitStatus,
+};
 
 use crate::args::process_cli_args;
 
 mod args;
 
 #[tokio::main]
-async fn main() {
-    let jdc_config = process_cli_args().unwrap_or_else(|e| {
-        eprintln!("Job Declarator Client config error: {e}");
-        std::process::exit(1);
-    });
+async fn main() -> ExitCode {
+    let jdc_config = match process_cli_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Job Declarator Client config error: {e}");
+            return e.exit_status().into();
+        }
+    };
 
-    init_logging(jdc_config.log_file());
-    JobDeclaratorClient::new(jdc_config).start().await;
+    init_logging(jdc_config.log_file(), jdc_config.logging());
+    let status = match JobDeclaratorClient::new(jdc_config).start().await {
+        Ok(()) => ExitStatus::Success,
+        Err(e) => {
+            let status = e.exit_status();
+            tracing::error!("Job Declarator Client stopped: {e}, exiting with {status}");
+            status
+        }
+    };
+    shutdown_logging();
+    status.into()
 }
@@ -0,0 +1,153 @@
+//! Load tests a pool with thousands of simulated downstream channels.
+//!
+//! Opens `--connections` Noise connections to the pool, each one opening
+//! `--channels-per-connection` standard or extended channels, which submit shares at
+//! `--shares-per-minute` on average, `--valid-ratio` of them meeting the channel target. The
+//! counters and latency percentiles are reported every `--report-interval-secs` and at the end of
+//! the run.
+
+use std::{net::ToSocketAddrs, sync::Arc, time::Duration};
+
+use clap::{Parser, ValueEnum};
+use mining_device::loadgen::{self, ChannelKind, LoadConfig, LoadStats};
+use stratum_apps::key_utils::Secp256k1PublicKey;
+use tracing::info;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Channels {
+    Standard,
+    Extended,
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "pool-loadgen",
+    version,
+    about = "Load test a Stratum V2 pool with simulated channels"
+)]
+struct Args {
+    #[arg(
+        short,
+        long,
+        help = "Address of the pool in this format ip:port or domain:port"
+    )]
+    address_pool: String,
+    #[arg(
+        short,
+        long,
+        help = "Pool pub key, when left empty the pool certificate is not checked"
+    )]
+    pubkey_pool: Option<Secp256k1PublicKey>,
+    #[arg(long, help = "Number of connections to open", default_value = "100")]
+    connections: u32,
+    #[arg(
+        long,
+        help = "Number of channels opened on each connection",
+        default_value = "10"
+    )]
+    channels_per_connection: u32,
+    #[arg(
+        long,
+        value_enum,
+        help = "Kind of the channels to open",
+        default_value = "standard"
+    )]
+    channels: Channels,
+    #[arg(
+        long,
+        help = "Hashrate (in H/s) advertised by each channel, low enough for the pool to set targets the valid shares can be mined for",
+        default_value = "1000"
+    )]
+    nominal_hashrate: f32,
+    #[arg(
+        long,
+        help = "Average number of shares submitted per minute by each channel",
+        default_value = "6"
+    )]
+    shares_per_minute: f64,
+    #[arg(
+        long,
+        help = "Ratio of the submitted shares meeting the channel target, between 0 and 1",
+        default_value = "0.98"
+    )]
+    valid_ratio: f64,
+    #[arg(
+        long,
+        help = "Seconds over which the connections are opened, evenly spread",
+        default_value = "10"
+    )]
+    ramp_up_secs: u64,
+    #[arg(
+        long,
+        help = "User identity of the channels, suffixed with the connection and channel numbers",
+        default_value = "loadgen"
+    )]
+    user_identity: String,
+    #[arg(
+        long,
+        help = "Seconds between two reports of the counters and latencies",
+        default_value = "10"
+    )]
+    report_interval_secs: u64,
+    #[arg(
+        long,
+        help = "Stops the run after this many seconds, runs until Ctrl+C otherwise"
+    )]
+    duration_secs: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    tracing_subscriber::fmt::init();
+    let address = args
+        .address_pool
+        .to_socket_addrs()
+        .expect("Invalid pool address, use one of this formats: ip:port, domain:port")
+        .next()
+        .expect("Invalid pool address, use one of this formats: ip:port, domain:port");
+    assert!(
+        (0.0..=1.0).contains(&args.valid_ratio),
+        "The valid ratio must be between 0 and 1"
+    );
+    let config = LoadConfig {
+        address,
+        authority_public_key: args.pubkey_pool,
+        connections: args.connections,
+        channels_per_connection: args.channels_per_connection,
+        channel_kind: match args.channels {
+            Channels::Standard => ChannelKind::Standard,
+            Channels::Extended => ChannelKind::Extended,
+        },
+        nominal_hashrate: args.nominal_hashrate,
+        shares_per_minute: args.shares_per_minute,
+        valid_ratio: args.valid_ratio,
+        ramp_up: Duration::from_secs(args.ramp_up_secs),
+        user_identity: args.user_identity,
+    };
+
+    let stats = Arc::new(LoadStats::new());
+    let reporter_stats = stats.clone();
+    let report_interval = Duration::from_secs(args.report_interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(report_interval);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            info!("Load report:\n{}", reporter_stats.report());
+        }
+    });
+
+    let duration = async {
+        match args.duration_secs {
+            Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = loadgen::run(config, stats.clone()) => info!("All the connections are closed"),
+        _ = duration => info!("Run duration elapsed"),
+        _ = tokio::signal::ctrl_c() => info!("Interrupted"),
+    }
+    info!("Final load report:\n{}", stats.report());
+}
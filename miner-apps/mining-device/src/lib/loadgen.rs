@@ -0,0 +1,847 @@
+//! Load generation against a pool.
+//!
+//! [`run`] opens many Noise connections to a pool, each one opening standard or extended channels
+//! which submit shares at a configurable rate. Like the shares of the simulation mode (see
+//! [`Simulation`]), the share of them meant to be valid is actually mined for the channel target,
+//! so that the pool goes through its whole validation path; the advertised nominal hashrate must
+//! therefore stay low enough for the pool to set easy targets. The other shares carry a random
+//! nonce, which misses any realistic target.
+//!
+//! The latencies observed by the channels are gathered in [`LoadStats`]:
+//! - time to first job: from the request opening a channel to its first active job
+//! - job delivery: for every new block, from the first channel receiving its `SetNewPrevHash` to
+//!   each of the others receiving it
+//! - share acknowledgement: from the submission of a share to the `SubmitShares.Success` or
+//!   `SubmitShares.Error` covering it
+
+use async_channel::{Receiver, Sender};
+use codec_sv2::HandshakeRole;
+use common_messages_sv2::{Protocol, SetupConnection, MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS};
+use mining_sv2::*;
+use network_helpers_sv2::noise_connection::Connection;
+use noise_sv2::Initiator;
+use parsers_sv2::{CommonMessages, Mining, MiningDeviceMessages};
+use rand::{thread_rng, Rng};
+use roles_logic_sv2::utils::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use stratum_apps::{
+    key_utils::Secp256k1PublicKey,
+    stratum_core::bitcoin::{
+        block::{Header, Version},
+        hash_types::BlockHash,
+        hashes::{sha256d, Hash},
+        CompactTarget,
+    },
+};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+use crate::{hash_meets_target_le, EitherFrame, FastSha256d, Message, Simulation, StdFrame};
+
+/// Hashes tried to mine a valid share before giving up on it.
+const MAX_MINING_ATTEMPTS: u32 = 1 << 22;
+
+/// Random nonces tried to fabricate an invalid share.
+const MAX_INVALID_ATTEMPTS: u32 = 64;
+
+/// Kind of the channels opened by the load generator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// Standard channels, on connections requiring standard jobs.
+    Standard,
+    /// Extended channels, rolling their own extranonce.
+    Extended,
+}
+
+/// Parameters of a load generation run.
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    /// Address of the pool.
+    pub address: SocketAddr,
+    /// Authority public key of the pool, its certificate is not checked if `None`.
+    pub authority_public_key: Option<Secp256k1PublicKey>,
+    /// Number of connections to open.
+    pub connections: u32,
+    /// Number of channels opened on each connection.
+    pub channels_per_connection: u32,
+    pub channel_kind: ChannelKind,
+    /// Hashrate (H/s) advertised when opening each channel.
+    pub nominal_hashrate: f32,
+    /// Average share rate of each channel, intervals between shares being exponentially
+    /// distributed.
+    pub shares_per_minute: f64,
+    /// Ratio of the shares meeting the channel target, between 0 and 1.
+    pub valid_ratio: f64,
+    /// Time over which the connections are opened, evenly spread, to ramp the load up.
+    pub ramp_up: Duration,
+    /// User identity of the channels, suffixed with the connection and channel numbers.
+    pub user_identity: String,
+}
+
+/// Latency percentiles of a set of samples, nearest-rank.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Percentiles {
+    pub count: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Percentiles {
+    pub fn from_samples(samples: &[Duration]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let rank = |percent: usize| sorted[(sorted.len() * percent).div_ceil(100) - 1];
+        Self {
+            count: sorted.len(),
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+impl fmt::Display for Percentiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "p50 {:?}, p90 {:?}, p99 {:?}, max {:?} ({} samples)",
+            self.p50, self.p90, self.p99, self.max, self.count
+        )
+    }
+}
+
+/// Counters and latency samples of a run, shared by its connections.
+#[derive(Debug)]
+pub struct LoadStats {
+    connections: AtomicU64,
+    channels: AtomicU64,
+    channel_errors: AtomicU64,
+    jobs: AtomicU64,
+    submitted: AtomicU64,
+    submitted_valid: AtomicU64,
+    unmined: AtomicU64,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    rejections: Mutex<BTreeMap<String, u64>>,
+    first_job: Mutex<Vec<Duration>>,
+    job_delivery: Mutex<Vec<Duration>>,
+    share_ack: Mutex<Vec<Duration>>,
+    // First reception of the `SetNewPrevHash` of each block, by previous block hash.
+    blocks: Mutex<HashMap<[u8; 32], Instant>>,
+}
+
+impl Default for LoadStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoadStats {
+    pub fn new() -> Self {
+        Self {
+            connections: AtomicU64::new(0),
+            channels: AtomicU64::new(0),
+            channel_errors: AtomicU64::new(0),
+            jobs: AtomicU64::new(0),
+            submitted: AtomicU64::new(0),
+            submitted_valid: AtomicU64::new(0),
+            unmined: AtomicU64::new(0),
+            accepted: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            rejections: Mutex::new(BTreeMap::new()),
+            first_job: Mutex::new(Vec::new()),
+            job_delivery: Mutex::new(Vec::new()),
+            share_ack: Mutex::new(Vec::new()),
+            blocks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the counters and latency percentiles since the start of the run.
+    pub fn report(&self) -> LoadReport {
+        let percentiles =
+            |samples: &Mutex<Vec<Duration>>| samples.safe_lock(|s| Percentiles::from_samples(s));
+        LoadReport {
+            connections: self.connections.load(Ordering::Relaxed),
+            channels: self.channels.load(Ordering::Relaxed),
+            channel_errors: self.channel_errors.load(Ordering::Relaxed),
+            jobs: self.jobs.load(Ordering::Relaxed),
+            submitted: self.submitted.load(Ordering::Relaxed),
+            submitted_valid: self.submitted_valid.load(Ordering::Relaxed),
+            unmined: self.unmined.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            rejections: self.rejections.safe_lock(|r| r.clone()).unwrap(),
+            time_to_first_job: percentiles(&self.first_job).unwrap(),
+            job_delivery: percentiles(&self.job_delivery).unwrap(),
+            share_ack: percentiles(&self.share_ack).unwrap(),
+        }
+    }
+
+    fn record(samples: &Mutex<Vec<Duration>>, sample: Duration) {
+        samples.safe_lock(|s| s.push(sample)).unwrap();
+    }
+
+    fn new_block_seen(&self, prev_hash: [u8; 32], now: Instant) {
+        let first = self
+            .blocks
+            .safe_lock(|blocks| *blocks.entry(prev_hash).or_insert(now))
+            .unwrap();
+        Self::record(&self.job_delivery, now.duration_since(first));
+    }
+}
+
+/// Snapshot of the [`LoadStats`] of a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadReport {
+    /// Connections currently open.
+    pub connections: u64,
+    /// Channels opened successfully.
+    pub channels: u64,
+    /// Channels refused by the pool.
+    pub channel_errors: u64,
+    /// Jobs received, over all channels.
+    pub jobs: u64,
+    /// Shares submitted.
+    pub submitted: u64,
+    /// Shares submitted meeting their channel target.
+    pub submitted_valid: u64,
+    /// Valid shares not submitted, none being found within the mining attempts.
+    pub unmined: u64,
+    /// Shares accepted by the pool.
+    pub accepted: u64,
+    /// Shares rejected by the pool.
+    pub rejected: u64,
+    /// Shares rejected by the pool, by error code.
+    pub rejections: BTreeMap<String, u64>,
+    pub time_to_first_job: Percentiles,
+    pub job_delivery: Percentiles,
+    pub share_ack: Percentiles,
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "connections: {}, channels: {} ({} refused), jobs: {}",
+            self.connections, self.channels, self.channel_errors, self.jobs
+        )?;
+        writeln!(
+            f,
+            "shares: {} submitted ({} valid, {} not mined), {} accepted, {} rejected",
+            self.submitted, self.submitted_valid, self.unmined, self.accepted, self.rejected
+        )?;
+        for (error_code, count) in &self.rejections {
+            writeln!(f, "  {error_code}: {count}")?;
+        }
+        writeln!(f, "time to first job: {}", self.time_to_first_job)?;
+        writeln!(f, "job delivery: {}", self.job_delivery)?;
+        write!(f, "share acknowledgement: {}", self.share_ack)
+    }
+}
+
+/// Opens the connections of `config`, ramping them up, and runs them until they all close.
+pub async fn run(config: LoadConfig, stats: Arc<LoadStats>) {
+    let config = Arc::new(config);
+    let ramp_step = config.ramp_up / config.connections.max(1);
+    info!(
+        "Opening {} connections of {} {:?} channels to {}",
+        config.connections, config.channels_per_connection, config.channel_kind, config.address
+    );
+    let mut connections = tokio::task::JoinSet::new();
+    for index in 0..config.connections {
+        let (config, stats) = (config.clone(), stats.clone());
+        connections.spawn(async move {
+            if let Err(e) = run_connection(&config, &stats, index).await {
+                warn!("Connection {index}: {e}");
+            }
+        });
+        tokio::time::sleep(ramp_step).await;
+    }
+    while connections.join_next().await.is_some() {}
+}
+
+async fn run_connection(
+    config: &Arc<LoadConfig>,
+    stats: &Arc<LoadStats>,
+    index: u32,
+) -> Result<(), String> {
+    let socket = TcpStream::connect(config.address)
+        .await
+        .map_err(|e| format!("failed to connect: {e}"))?;
+    let initiator = Initiator::new(config.authority_public_key.map(|key| key.0));
+    let (receiver, sender): (Receiver<EitherFrame>, Sender<EitherFrame>) =
+        Connection::new(socket, HandshakeRole::Initiator(initiator))
+            .await
+            .map_err(|e| format!("handshake failed: {e:?}"))?;
+
+    let flags = match config.channel_kind {
+        // REQUIRES_STANDARD_JOBS
+        ChannelKind::Standard => 0b0001,
+        ChannelKind::Extended => 0,
+    };
+    let setup = SetupConnection {
+        protocol: Protocol::MiningProtocol,
+        min_version: 2,
+        max_version: 2,
+        flags,
+        endpoint_host: config
+            .address
+            .ip()
+            .to_string()
+            .into_bytes()
+            .try_into()
+            .unwrap(),
+        endpoint_port: config.address.port(),
+        vendor: String::from("pool-loadgen").try_into().unwrap(),
+        hardware_version: String::new().try_into().unwrap(),
+        firmware: String::new().try_into().unwrap(),
+        device_id: format!("loadgen-{index}").try_into().unwrap(),
+    };
+    send(
+        &sender,
+        MiningDeviceMessages::Common(CommonMessages::SetupConnection(setup)),
+    )
+    .await?;
+    let mut frame = next_frame(&receiver).await?;
+    let message_type = frame.get_header().ok_or("invalid frame")?.msg_type();
+    if message_type != MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS {
+        return Err(format!(
+            "SetupConnection refused with message {message_type:#x}"
+        ));
+    }
+
+    stats.connections.fetch_add(1, Ordering::Relaxed);
+    let result = run_channels(config, stats, index, &receiver, &sender).await;
+    stats.connections.fetch_sub(1, Ordering::Relaxed);
+    result
+}
+
+async fn run_channels(
+    config: &Arc<LoadConfig>,
+    stats: &Arc<LoadStats>,
+    index: u32,
+    receiver: &Receiver<EitherFrame>,
+    sender: &Sender<EitherFrame>,
+) -> Result<(), String> {
+    let mut channels = Vec::new();
+    for request_id in 0..config.channels_per_connection {
+        let user_identity = format!("{}.{index}.{request_id}", config.user_identity);
+        let open = match config.channel_kind {
+            ChannelKind::Standard => Mining::OpenStandardMiningChannel(OpenStandardMiningChannel {
+                request_id: request_id.into(),
+                user_identity: user_identity.try_into().unwrap(),
+                nominal_hash_rate: config.nominal_hashrate,
+                max_target: vec![0xff_u8; 32].try_into().unwrap(),
+            }),
+            ChannelKind::Extended => Mining::OpenExtendedMiningChannel(OpenExtendedMiningChannel {
+                request_id,
+                user_identity: user_identity.try_into().unwrap(),
+                nominal_hash_rate: config.nominal_hashrate,
+                max_target: vec![0xff_u8; 32].try_into().unwrap(),
+                min_extranonce_size: 4,
+            }),
+        };
+        channels.push(Arc::new(Mutex::new(Channel::new(Instant::now()))));
+        send(sender, MiningDeviceMessages::Mining(open)).await?;
+    }
+    // Dropping the handler along with the connection stops its share submission tasks.
+    let mut connection = ConnectionHandler {
+        config: config.clone(),
+        stats: stats.clone(),
+        sender: sender.clone(),
+        channels,
+        channel_ids: HashMap::new(),
+        tasks: tokio::task::JoinSet::new(),
+    };
+    loop {
+        let mut frame = next_frame(receiver).await?;
+        let message_type = frame.get_header().ok_or("invalid frame")?.msg_type();
+        match MiningDeviceMessages::try_from((message_type, frame.payload())) {
+            Ok(MiningDeviceMessages::Mining(message)) => connection.handle(message),
+            Ok(message) => debug!("Connection {index}: ignoring {message:?}"),
+            Err(e) => warn!("Connection {index}: undecodable message {message_type:#x}: {e:?}"),
+        }
+    }
+}
+
+struct ConnectionHandler {
+    config: Arc<LoadConfig>,
+    stats: Arc<LoadStats>,
+    sender: Sender<EitherFrame>,
+    // Channels by request id.
+    channels: Vec<Arc<Mutex<Channel>>>,
+    // Request ids by channel id, once open.
+    channel_ids: HashMap<u32, usize>,
+    tasks: tokio::task::JoinSet<()>,
+}
+
+impl ConnectionHandler {
+    fn handle(&mut self, message: Mining<'_>) {
+        let now = Instant::now();
+        match message {
+            Mining::OpenStandardMiningChannelSuccess(m) => {
+                let request_id = m.get_request_id_as_u32();
+                self.opened(
+                    request_id,
+                    m.channel_id,
+                    m.target.to_vec(),
+                    m.extranonce_prefix.to_vec(),
+                    0,
+                );
+            }
+            Mining::OpenExtendedMiningChannelSuccess(m) => {
+                self.opened(
+                    m.request_id,
+                    m.channel_id,
+                    m.target.to_vec(),
+                    m.extranonce_prefix.to_vec(),
+                    m.extranonce_size as usize,
+                );
+            }
+            Mining::OpenMiningChannelError(m) => {
+                self.stats.channel_errors.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "Channel {} refused: {}",
+                    m.request_id,
+                    String::from_utf8_lossy(m.error_code.as_ref())
+                );
+            }
+            Mining::NewMiningJob(m) => {
+                let job = Job {
+                    job_id: m.job_id,
+                    version: m.version,
+                    work: JobWork::Standard {
+                        merkle_root: m.merkle_root.to_vec().try_into().unwrap(),
+                    },
+                };
+                self.new_job(m.channel_id, job, m.is_future(), now);
+            }
+            Mining::NewExtendedMiningJob(m) => {
+                let merkle_path = m
+                    .merkle_path
+                    .clone()
+                    .into_inner()
+                    .iter()
+                    .map(|hash| hash.to_vec().try_into().unwrap())
+                    .collect();
+                let job = Job {
+                    job_id: m.job_id,
+                    version: m.version,
+                    work: JobWork::Extended {
+                        coinbase_prefix: m.coinbase_tx_prefix.to_vec(),
+                        coinbase_suffix: m.coinbase_tx_suffix.to_vec(),
+                        merkle_path,
+                    },
+                };
+                self.new_job(m.channel_id, job, m.is_future(), now);
+            }
+            Mining::SetNewPrevHash(m) => {
+                let prev_hash = PrevHash {
+                    prev_hash: m.prev_hash.to_vec().try_into().unwrap(),
+                    min_ntime: m.min_ntime,
+                    nbits: m.nbits,
+                };
+                self.stats.new_block_seen(prev_hash.prev_hash, now);
+                for channel in self.addressed(m.channel_id) {
+                    let first_job = channel
+                        .safe_lock(|c| c.new_prev_hash(m.job_id, prev_hash, now))
+                        .unwrap();
+                    if let Some(first_job) = first_job {
+                        LoadStats::record(&self.stats.first_job, first_job);
+                    }
+                }
+            }
+            Mining::SetTarget(m) => {
+                let target = m.maximum_target.to_vec().try_into().unwrap();
+                for channel in self.addressed(m.channel_id) {
+                    channel.safe_lock(|c| c.target = target).unwrap();
+                }
+            }
+            Mining::SetExtranoncePrefix(m) => {
+                let prefix = m.extranonce_prefix.to_vec();
+                for channel in self.addressed(m.channel_id) {
+                    channel
+                        .safe_lock(|c| c.extranonce_prefix = prefix.clone())
+                        .unwrap();
+                }
+            }
+            Mining::SubmitSharesSuccess(m) => {
+                self.stats
+                    .accepted
+                    .fetch_add(m.new_submits_accepted_count.into(), Ordering::Relaxed);
+                for channel in self.addressed(m.channel_id) {
+                    let latencies = channel
+                        .safe_lock(|c| c.acknowledge(m.last_sequence_number, now))
+                        .unwrap();
+                    for latency in latencies {
+                        LoadStats::record(&self.stats.share_ack, latency);
+                    }
+                }
+            }
+            Mining::SubmitSharesError(m) => {
+                self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+                let error_code = String::from_utf8_lossy(m.error_code.as_ref()).into_owned();
+                self.stats
+                    .rejections
+                    .safe_lock(|r| *r.entry(error_code).or_default() += 1)
+                    .unwrap();
+                for channel in self.addressed(m.channel_id) {
+                    let latency = channel
+                        .safe_lock(|c| c.pending.remove(&m.sequence_number))
+                        .unwrap();
+                    if let Some(submitted) = latency {
+                        LoadStats::record(&self.stats.share_ack, now.duration_since(submitted));
+                    }
+                }
+            }
+            Mining::CloseChannel(m) => {
+                warn!(
+                    "Channel {} closed by the pool: {}",
+                    m.channel_id,
+                    String::from_utf8_lossy(m.reason_code.as_ref())
+                );
+                for channel in self.addressed(m.channel_id) {
+                    channel.safe_lock(|c| c.closed = true).unwrap();
+                }
+            }
+            message => debug!("Ignoring {message:?}"),
+        }
+    }
+
+    fn opened(
+        &mut self,
+        request_id: u32,
+        channel_id: u32,
+        target: Vec<u8>,
+        extranonce_prefix: Vec<u8>,
+        extranonce_size: usize,
+    ) {
+        let Some(channel) = self.channels.get(request_id as usize).cloned() else {
+            warn!("Channel opened for unknown request {request_id}");
+            return;
+        };
+        channel
+            .safe_lock(|c| {
+                c.id = Some(channel_id);
+                c.target = target.try_into().unwrap_or([0xff; 32]);
+                c.extranonce_prefix = extranonce_prefix;
+                c.extranonce_size = extranonce_size;
+            })
+            .unwrap();
+        self.channel_ids.insert(channel_id, request_id as usize);
+        self.stats.channels.fetch_add(1, Ordering::Relaxed);
+        self.tasks.spawn(submit_shares(
+            channel,
+            self.sender.clone(),
+            self.config.clone(),
+            self.stats.clone(),
+        ));
+    }
+
+    fn new_job(&mut self, channel_id: u32, job: Job, future: bool, now: Instant) {
+        self.stats.jobs.fetch_add(1, Ordering::Relaxed);
+        for channel in self.addressed(channel_id) {
+            let first_job = channel
+                .safe_lock(|c| c.new_job(job.clone(), future, now))
+                .unwrap();
+            if let Some(first_job) = first_job {
+                LoadStats::record(&self.stats.first_job, first_job);
+            }
+        }
+    }
+
+    // Channels a message for `channel_id` applies to: the channel itself, or all of them for a
+    // message sent to their group.
+    fn addressed(&self, channel_id: u32) -> Vec<Arc<Mutex<Channel>>> {
+        match self.channel_ids.get(&channel_id) {
+            Some(index) => vec![self.channels[*index].clone()],
+            None => self.channels.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Job {
+    job_id: u32,
+    version: u32,
+    work: JobWork,
+}
+
+#[derive(Debug, Clone)]
+enum JobWork {
+    Standard {
+        merkle_root: [u8; 32],
+    },
+    Extended {
+        coinbase_prefix: Vec<u8>,
+        coinbase_suffix: Vec<u8>,
+        merkle_path: Vec<[u8; 32]>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PrevHash {
+    prev_hash: [u8; 32],
+    min_ntime: u32,
+    nbits: u32,
+}
+
+#[derive(Debug)]
+struct Channel {
+    requested: Instant,
+    id: Option<u32>,
+    closed: bool,
+    target: [u8; 32],
+    extranonce_prefix: Vec<u8>,
+    extranonce_size: usize,
+    prev_hash: Option<PrevHash>,
+    future_jobs: Vec<Job>,
+    active_job: Option<Job>,
+    has_had_job: bool,
+    next_sequence_number: u32,
+    // Submission time of the shares not acknowledged yet, by sequence number.
+    pending: BTreeMap<u32, Instant>,
+}
+
+impl Channel {
+    fn new(requested: Instant) -> Self {
+        Self {
+            requested,
+            id: None,
+            closed: false,
+            target: [0; 32],
+            extranonce_prefix: Vec::new(),
+            extranonce_size: 0,
+            prev_hash: None,
+            future_jobs: Vec::new(),
+            active_job: None,
+            has_had_job: false,
+            next_sequence_number: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    // Returns the time to the first job of the channel if `job` is the first one to be active.
+    fn new_job(&mut self, job: Job, future: bool, now: Instant) -> Option<Duration> {
+        if future {
+            self.future_jobs.push(job);
+            return None;
+        }
+        self.active_job = Some(job);
+        self.first_job(now)
+    }
+
+    fn new_prev_hash(
+        &mut self,
+        job_id: u32,
+        prev_hash: PrevHash,
+        now: Instant,
+    ) -> Option<Duration> {
+        self.prev_hash = Some(prev_hash);
+        if let Some(job) = self.future_jobs.drain(..).find(|job| job.job_id == job_id) {
+            self.active_job = Some(job);
+        }
+        self.first_job(now)
+    }
+
+    fn first_job(&mut self, now: Instant) -> Option<Duration> {
+        if self.has_had_job || self.active_job.is_none() || self.prev_hash.is_none() {
+            return None;
+        }
+        self.has_had_job = true;
+        Some(now.duration_since(self.requested))
+    }
+
+    // Removes the shares acknowledged up to `last_sequence_number`, returning their latencies.
+    fn acknowledge(&mut self, last_sequence_number: u32, now: Instant) -> Vec<Duration> {
+        let pending = self
+            .pending
+            .split_off(&last_sequence_number.wrapping_add(1));
+        let acknowledged = std::mem::replace(&mut self.pending, pending);
+        acknowledged
+            .into_values()
+            .map(|submitted| now.duration_since(submitted))
+            .collect()
+    }
+
+    // The work of the active job, with a random extranonce for extended channels.
+    fn work(&self) -> Option<Work> {
+        let (job, prev_hash) = (self.active_job.as_ref()?, self.prev_hash?);
+        let mut extranonce = vec![0_u8; self.extranonce_size];
+        thread_rng().fill(extranonce.as_mut_slice());
+        let merkle_root = match &job.work {
+            JobWork::Standard { merkle_root } => *merkle_root,
+            JobWork::Extended {
+                coinbase_prefix,
+                coinbase_suffix,
+                merkle_path,
+            } => {
+                let coinbase = [
+                    coinbase_prefix.as_slice(),
+                    self.extranonce_prefix.as_slice(),
+                    extranonce.as_slice(),
+                    coinbase_suffix.as_slice(),
+                ]
+                .concat();
+                merkle_path.iter().fold(
+                    sha256d::Hash::hash(&coinbase).to_byte_array(),
+                    |root, branch| sha256d::Hash::hash(&[root, *branch].concat()).to_byte_array(),
+                )
+            }
+        };
+        Some(Work {
+            channel_id: self.id?,
+            job_id: job.job_id,
+            version: job.version,
+            prev_hash,
+            merkle_root,
+            extranonce,
+            target: self.target,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Work {
+    channel_id: u32,
+    job_id: u32,
+    version: u32,
+    prev_hash: PrevHash,
+    merkle_root: [u8; 32],
+    extranonce: Vec<u8>,
+    target: [u8; 32],
+}
+
+impl Work {
+    // Looks for a nonce meeting the target, or missing it if `valid` is false.
+    fn find_nonce(&self, valid: bool) -> Option<u32> {
+        let header = Header {
+            version: Version::from_consensus(self.version as i32),
+            prev_blockhash: BlockHash::from_raw_hash(Hash::from_byte_array(
+                self.prev_hash.prev_hash,
+            )),
+            merkle_root: Hash::from_byte_array(self.merkle_root),
+            time: self.prev_hash.min_ntime,
+            bits: CompactTarget::from_consensus(self.prev_hash.nbits),
+            nonce: 0,
+        };
+        let mut hasher = FastSha256d::from_header_static(&header);
+        let attempts = if valid {
+            MAX_MINING_ATTEMPTS
+        } else {
+            MAX_INVALID_ATTEMPTS
+        };
+        let start: u32 = thread_rng().gen();
+        (0..attempts).map(|i| start.wrapping_add(i)).find(|nonce| {
+            let hash = hasher.hash_with_nonce_time(*nonce, self.prev_hash.min_ntime);
+            hash_meets_target_le(&hash, &self.target) == valid
+        })
+    }
+
+    fn share(&self, sequence_number: u32, nonce: u32) -> Mining<'static> {
+        if self.extranonce.is_empty() {
+            Mining::SubmitSharesStandard(SubmitSharesStandard {
+                channel_id: self.channel_id,
+                sequence_number,
+                job_id: self.job_id,
+                nonce,
+                ntime: self.prev_hash.min_ntime,
+                version: self.version,
+            })
+        } else {
+            Mining::SubmitSharesExtended(SubmitSharesExtended {
+                channel_id: self.channel_id,
+                sequence_number,
+                job_id: self.job_id,
+                nonce,
+                ntime: self.prev_hash.min_ntime,
+                version: self.version,
+                extranonce: self.extranonce.clone().try_into().unwrap(),
+            })
+        }
+    }
+}
+
+// Submits the shares of `channel` until it closes.
+async fn submit_shares(
+    channel: Arc<Mutex<Channel>>,
+    sender: Sender<EitherFrame>,
+    config: Arc<LoadConfig>,
+    stats: Arc<LoadStats>,
+) {
+    let simulation = Simulation::new(config.nominal_hashrate, Some(config.shares_per_minute));
+    let valid_ratio = config.valid_ratio.clamp(0.0, 1.0);
+    loop {
+        tokio::time::sleep(simulation.next_share_delay(None)).await;
+        let (closed, work) = channel.safe_lock(|c| (c.closed, c.work())).unwrap();
+        if closed {
+            return;
+        }
+        let Some(work) = work else {
+            continue;
+        };
+        let valid = thread_rng().gen_bool(valid_ratio);
+        let mining = work.clone();
+        let Ok(nonce) = tokio::task::spawn_blocking(move || mining.find_nonce(valid)).await else {
+            return;
+        };
+        let Some(nonce) = nonce else {
+            if valid {
+                stats.unmined.fetch_add(1, Ordering::Relaxed);
+            }
+            continue;
+        };
+        let share = channel
+            .safe_lock(|c| {
+                let sequence_number = c.next_sequence_number;
+                c.next_sequence_number = c.next_sequence_number.wrapping_add(1);
+                c.pending.insert(sequence_number, Instant::now());
+                work.share(sequence_number, nonce)
+            })
+            .unwrap();
+        if send(&sender, MiningDeviceMessages::Mining(share))
+            .await
+            .is_err()
+        {
+            return;
+        }
+        stats.submitted.fetch_add(1, Ordering::Relaxed);
+        if valid {
+            stats.submitted_valid.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+async fn send(sender: &Sender<EitherFrame>, message: Message) -> Result<(), String> {
+    let frame: StdFrame = message
+        .try_into()
+        .map_err(|e| format!("failed to encode a message: {e:?}"))?;
+    sender
+        .send(frame.into())
+        .await
+        .map_err(|_| "connection closed".to_string())
+}
+
+async fn next_frame(receiver: &Receiver<EitherFrame>) -> Result<StdFrame, String> {
+    receiver
+        .recv()
+        .await
+        .map_err(|_| "connection closed".to_string())?
+        .try_into()
+        .map_err(|_| "unexpected handshake frame".to_string())
+}
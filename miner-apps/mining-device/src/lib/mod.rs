@@ -19,8 +19,9 @@ use roles_logic_sv2::{
 };
 use std::{
     net::{SocketAddr, ToSocketAddrs},
+    ops::RangeInclusive,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicU64, Ordering},
         Arc,
     },
     thread::available_parallelism,
@@ -37,6 +38,10 @@ use tracing::{debug, error, info};
 
 use stratum_apps::stratum_core::bitcoin::consensus::encode::serialize as btc_serialize;
 
+pub mod loadgen;
+mod simulation;
+pub use simulation::Simulation;
+
 // Fast SHA256d midstate hasher
 use sha2::{
     compress256,
@@ -102,6 +107,52 @@ pub async fn connect(
     handicap: u32,
     nominal_hashrate_multiplier: Option<f32>,
     single_submit: bool,
+) {
+    connect_device(
+        address,
+        pub_key,
+        device_id,
+        user_id,
+        handicap,
+        nominal_hashrate_multiplier,
+        single_submit,
+        None,
+    )
+    .await
+}
+
+/// Connects a simulated device that mines shares on a schedule instead of hashing continuously,
+/// see [`Simulation`].
+pub async fn simulate(
+    address: String,
+    pub_key: Option<Secp256k1PublicKey>,
+    device_id: Option<String>,
+    user_id: Option<String>,
+    simulation: Simulation,
+) {
+    connect_device(
+        address,
+        pub_key,
+        device_id,
+        user_id,
+        0,
+        None,
+        false,
+        Some(simulation),
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn connect_device(
+    address: String,
+    pub_key: Option<Secp256k1PublicKey>,
+    device_id: Option<String>,
+    user_id: Option<String>,
+    handicap: u32,
+    nominal_hashrate_multiplier: Option<f32>,
+    single_submit: bool,
+    simulation: Option<Simulation>,
 ) {
     let address = address
         .clone()
@@ -146,6 +197,7 @@ pub async fn connect(
         handicap,
         nominal_hashrate_multiplier,
         single_submit,
+        simulation,
     )
     .await
 }
@@ -274,17 +326,24 @@ fn open_channel(
     device_id: Option<String>,
     nominal_hashrate_multiplier: Option<f32>,
     handicap: u32,
+    simulation: Option<Simulation>,
 ) -> OpenStandardMiningChannel<'static> {
     let user_identity = device_id.unwrap_or_default().try_into().unwrap();
     let id: u32 = 10;
-    info!("Measuring CPU hashrate");
-    let measured_total_hs = measure_hashrate(5, handicap);
-    let measured_total_mhs = measured_total_hs / 1_000_000.0;
-    info!(
-        "Measured CPU hashrate ≈ {} MH/s",
-        format_mhs(measured_total_mhs)
-    );
-    let measured_hashrate = measured_total_hs as f32;
+    let measured_hashrate = match simulation {
+        // Simulated devices do not hash, they advertise the configured hashrate
+        Some(simulation) => simulation.nominal_hashrate,
+        None => {
+            info!("Measuring CPU hashrate");
+            let measured_total_hs = measure_hashrate(5, handicap);
+            let measured_total_mhs = measured_total_hs / 1_000_000.0;
+            info!(
+                "Measured CPU hashrate ≈ {} MH/s",
+                format_mhs(measured_total_mhs)
+            );
+            measured_total_hs as f32
+        }
+    };
     let nominal_hash_rate = match nominal_hashrate_multiplier {
         Some(m) => measured_hashrate * m,
         None => measured_hashrate,
@@ -311,6 +370,7 @@ impl Device {
         handicap: u32,
         nominal_hashrate_multiplier: Option<f32>,
         single_submit: bool,
+        simulation: Option<Simulation>,
     ) {
         let setup_connection_handler = Arc::new(Mutex::new(SetupConnectionHandler::new()));
         SetupConnectionHandler::setup(
@@ -339,7 +399,7 @@ impl Device {
             },
         };
         let open_channel = MiningDeviceMessages::Mining(Mining::OpenStandardMiningChannel(
-            open_channel(user_id, nominal_hashrate_multiplier, handicap),
+            open_channel(user_id, nominal_hashrate_multiplier, handicap, simulation),
         ));
         let frame: StdFrame = open_channel.try_into().unwrap();
         self_.sender.send(frame.into()).await.unwrap();
@@ -348,7 +408,12 @@ impl Device {
 
         let (share_send, share_recv) = async_channel::unbounded();
 
-        start_mining_threads(update_miners, miner, share_send);
+        match simulation {
+            Some(simulation) => {
+                simulation::start_simulated_shares(update_miners, miner, share_send, simulation)
+            }
+            None => start_mining_threads(update_miners, miner, share_send),
+        }
         tokio::task::spawn(async move {
             let recv = share_recv.clone();
             loop {
@@ -931,149 +996,127 @@ fn generate_random_32_byte_array() -> [u8; 32] {
     arr
 }
 
+/// Splits the nonce space into `workers` contiguous, non-overlapping ranges, one per worker
+/// thread. The last range absorbs the remainder so that the whole `u32` space is covered.
+pub fn nonce_ranges(workers: u32) -> Vec<RangeInclusive<u32>> {
+    let workers = workers.max(1);
+    let unit = u32::MAX / workers;
+    (0..workers)
+        .map(|i| {
+            let start = i * unit;
+            let end = if i == workers - 1 {
+                u32::MAX
+            } else {
+                start + unit - 1
+            };
+            start..=end
+        })
+        .collect()
+}
+
 fn start_mining_threads(
     have_new_job: Receiver<()>,
     miner: Arc<Mutex<Miner>>,
     share_send: Sender<(u32, u32, u32, u32)>,
 ) {
     tokio::task::spawn(async move {
-        let mut killers: Vec<Arc<AtomicBool>> = vec![];
-        loop {
+        // Bumped on every new job, workers stop as soon as it no longer matches the epoch they
+        // were started with.
+        let epoch = Arc::new(AtomicU64::new(0));
+        while have_new_job.recv().await.is_ok() {
+            let job_epoch = epoch.fetch_add(1, Ordering::AcqRel) + 1;
+            let miner = miner.safe_lock(|m| m.clone()).unwrap();
             // Determine number of workers based on override or auto (N-1)
-            let p = worker_count();
-            let unit = u32::MAX / p;
-            while have_new_job.recv().await.is_ok() {
-                while let Some(killer) = killers.pop() {
-                    killer.store(true, Ordering::Relaxed);
-                }
-                let miner = miner.safe_lock(|m| m.clone()).unwrap();
-                for i in 0..p {
-                    let mut miner = miner.clone();
-                    let share_send = share_send.clone();
-                    let killer = Arc::new(AtomicBool::new(false));
-                    miner.header.as_mut().map(|h| h.nonce = i * unit);
-                    killers.push(killer.clone());
-                    std::thread::spawn(move || {
-                        mine(miner, share_send, killer);
-                    });
-                }
+            for range in nonce_ranges(worker_count()) {
+                let mut miner = miner.clone();
+                let share_send = share_send.clone();
+                let epoch = epoch.clone();
+                miner.header.as_mut().map(|h| h.nonce = *range.start());
+                std::thread::spawn(move || {
+                    mine(miner, share_send, range, epoch, job_epoch);
+                });
             }
         }
     });
 }
 
-fn mine(mut miner: Miner, share_send: Sender<(u32, u32, u32, u32)>, kill: Arc<AtomicBool>) {
-    if miner.handicap != 0 {
-        loop {
-            if kill.load(Ordering::Relaxed) {
-                break;
-            }
+/// Moves `header` `step` nonces forward. Once `range` is exhausted the nonce goes back to the
+/// start of the range and `ntime` is rolled, so that the thread never hashes the nonces of
+/// another worker.
+#[inline]
+fn advance_nonce(header: &mut Header, step: u32, range: &RangeInclusive<u32>) {
+    match header.nonce.checked_add(step) {
+        Some(nonce) if nonce <= *range.end() => header.nonce = nonce,
+        _ => {
+            header.nonce = *range.start();
+            header.time = header.time.wrapping_add(1);
+        }
+    }
+}
+
+fn mine(
+    mut miner: Miner,
+    share_send: Sender<(u32, u32, u32, u32)>,
+    range: RangeInclusive<u32>,
+    epoch: Arc<AtomicU64>,
+    job_epoch: u64,
+) {
+    loop {
+        if epoch.load(Ordering::Relaxed) != job_epoch {
+            break;
+        }
+        if miner.handicap != 0 {
             std::thread::sleep(std::time::Duration::from_micros(miner.handicap.into()));
-            // Prefer fast path with micro-batching when possible
-            let can_fast =
-                miner.fast_hasher.is_some() && miner.target.is_some() && miner.header.is_some();
-            if can_fast {
-                let header = miner.header.as_mut().unwrap();
-                let time = header.time;
-                let start = header.nonce;
-                let tgt_le = miner.target.unwrap().to_little_endian();
-                let fast = miner.fast_hasher.as_mut().unwrap();
-                let mut found = None;
-                let batch = nonces_per_call();
-                for i in 0..batch {
-                    let nonce = start.wrapping_add(i);
-                    let hash = fast.hash_with_nonce_time(nonce, time);
-                    if hash_meets_target_le(&hash, &tgt_le) {
-                        found = Some((nonce, hash));
-                        break;
-                    }
-                }
-                if let Some((nonce, hash)) = found {
-                    header.nonce = nonce;
-                    info!(
-                        "Found share with nonce: {}, for target: {:?}, with hash: {:?}",
-                        header.nonce, miner.target, hash,
-                    );
-                    let job_id = miner.job_id.unwrap();
-                    let version = miner.version;
-                    share_send
-                        .try_send((nonce, job_id, version.unwrap(), time))
-                        .unwrap();
-                }
-                // Advance nonce window
-                header.nonce = start.wrapping_add(batch);
-            } else {
-                if miner.next_share().is_valid() {
-                    let nonce = miner.header.unwrap().nonce;
-                    let time = miner.header.unwrap().time;
-                    let job_id = miner.job_id.unwrap();
-                    let version = miner.version;
-                    share_send
-                        .try_send((nonce, job_id, version.unwrap(), time))
-                        .unwrap();
+        }
+        // Prefer fast path with micro-batching when possible
+        let can_fast =
+            miner.fast_hasher.is_some() && miner.target.is_some() && miner.header.is_some();
+        if can_fast {
+            let header = miner.header.as_mut().unwrap();
+            let time = header.time;
+            let start = header.nonce;
+            let tgt_le = miner.target.unwrap().to_little_endian();
+            let fast = miner.fast_hasher.as_mut().unwrap();
+            let mut found = None;
+            // Never hash past the end of this thread's range
+            let batch = nonces_per_call().min((range.end() - start).saturating_add(1));
+            for i in 0..batch {
+                let nonce = start + i;
+                let hash = fast.hash_with_nonce_time(nonce, time);
+                if hash_meets_target_le(&hash, &tgt_le) {
+                    found = Some((nonce, hash));
+                    break;
                 }
-                miner
-                    .header
-                    .as_mut()
-                    .map(|h| h.nonce = h.nonce.wrapping_add(1));
             }
-        }
-    } else {
-        loop {
-            // Prefer fast path with micro-batching when possible
-            if kill.load(Ordering::Relaxed) {
-                break;
+            if let Some((nonce, hash)) = found {
+                header.nonce = nonce;
+                info!(
+                    "Found share with nonce: {}, for target: {:?}, with hash: {:?}",
+                    header.nonce, miner.target, hash,
+                );
+                let job_id = miner.job_id.unwrap();
+                let version = miner.version;
+                share_send
+                    .try_send((nonce, job_id, version.unwrap(), time))
+                    .unwrap();
             }
-            let can_fast =
-                miner.fast_hasher.is_some() && miner.target.is_some() && miner.header.is_some();
-            if can_fast {
-                let header = miner.header.as_mut().unwrap();
-                let time = header.time;
-                let start = header.nonce;
-                let tgt_le = miner.target.unwrap().to_little_endian();
-                let fast = miner.fast_hasher.as_mut().unwrap();
-                let mut found = None;
-                let batch = nonces_per_call();
-                for i in 0..batch {
-                    let nonce = start.wrapping_add(i);
-                    let hash = fast.hash_with_nonce_time(nonce, time);
-                    if hash_meets_target_le(&hash, &tgt_le) {
-                        found = Some((nonce, hash));
-                        break;
-                    }
-                }
-                if let Some((nonce, hash)) = found {
-                    header.nonce = nonce;
-                    info!(
-                        "Found share with nonce: {}, for target: {:?}, with hash: {:?}",
-                        header.nonce, miner.target, hash,
-                    );
-                    let job_id = miner.job_id.unwrap();
-                    let version = miner.version;
-                    share_send
-                        .try_send((nonce, job_id, version.unwrap(), time))
-                        .unwrap();
-                }
-                // Advance nonce window
-                header.nonce = start.wrapping_add(batch);
-            } else {
-                if miner.next_share().is_valid() {
-                    if kill.load(Ordering::Relaxed) {
-                        break;
-                    }
-                    let nonce = miner.header.unwrap().nonce;
-                    let time = miner.header.unwrap().time;
-                    let job_id = miner.job_id.unwrap();
-                    let version = miner.version;
-                    share_send
-                        .try_send((nonce, job_id, version.unwrap(), time))
-                        .unwrap();
+            // Advance nonce window
+            header.nonce = start;
+            advance_nonce(header, batch, &range);
+        } else {
+            if miner.next_share().is_valid() {
+                if epoch.load(Ordering::Relaxed) != job_epoch {
+                    break;
                 }
-                miner
-                    .header
-                    .as_mut()
-                    .map(|h| h.nonce = h.nonce.wrapping_add(1));
+                let nonce = miner.header.unwrap().nonce;
+                let time = miner.header.unwrap().time;
+                let job_id = miner.job_id.unwrap();
+                let version = miner.version;
+                share_send
+                    .try_send((nonce, job_id, version.unwrap(), time))
+                    .unwrap();
             }
+            miner.header.as_mut().map(|h| advance_nonce(h, 1, &range));
         }
     }
 }
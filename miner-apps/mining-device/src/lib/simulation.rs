@@ -0,0 +1,154 @@
+//! Share rate simulation mode.
+//!
+//! Instead of hashing continuously, a simulated device sends `SubmitSharesStandard` messages for
+//! the current job at the rate a device with the advertised nominal hashrate would find shares
+//! for the channel target (or at a fixed rate). Intervals between shares are exponentially
+//! distributed, like the ones of a real miner. Each share is mined for the channel target when
+//! it is due, so that upstreams accept it: mining a share takes `2^256 / (target + 1)` hashes on
+//! average, the advertised hashrate must therefore stay low enough for the upstream to set easy
+//! targets, e.g. the maximal one. A single process can then drive thousands of simulated devices,
+//! which makes it suitable for load testing pools and proxies.
+
+use async_channel::{Receiver, Sender};
+use primitive_types::U256;
+use rand::{thread_rng, Rng};
+use roles_logic_sv2::utils::Mutex;
+use std::{sync::Arc, time::Duration};
+use stratum_apps::stratum_core::bitcoin::blockdata::block::Header;
+use tracing::{debug, warn};
+
+use crate::{hash_meets_target_le, FastSha256d, Miner};
+
+/// Delay used while the share rate cannot be computed (no target or job yet).
+const IDLE_DELAY: Duration = Duration::from_secs(1);
+
+/// Hashes tried to mine a share before giving up on it.
+const MAX_MINING_ATTEMPTS: u32 = 1 << 22;
+
+/// Parameters of a simulated device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Simulation {
+    /// Hashrate (H/s) advertised when opening the channel and used to derive the share rate
+    /// from the channel target.
+    pub nominal_hashrate: f32,
+    /// Fixed share rate, overriding the one derived from `nominal_hashrate` and the target.
+    pub shares_per_minute: Option<f64>,
+}
+
+impl Simulation {
+    pub fn new(nominal_hashrate: f32, shares_per_minute: Option<f64>) -> Self {
+        Self {
+            nominal_hashrate,
+            shares_per_minute,
+        }
+    }
+
+    /// Average time between two shares, `None` if it cannot be computed.
+    ///
+    /// A hash meets `target` with probability `(target + 1) / 2^256`, so a device with
+    /// `nominal_hashrate` finds a share every `2^256 / ((target + 1) * nominal_hashrate)`
+    /// seconds on average.
+    pub fn mean_share_interval(&self, target: Option<U256>) -> Option<Duration> {
+        let shares_per_second = match self.shares_per_minute {
+            Some(shares_per_minute) => shares_per_minute / 60.0,
+            None => {
+                let target = u256_to_f64(target?) + 1.0;
+                self.nominal_hashrate as f64 * target / 2_f64.powi(256)
+            }
+        };
+        if !shares_per_second.is_finite() || shares_per_second <= 0.0 {
+            return None;
+        }
+        Duration::try_from_secs_f64(1.0 / shares_per_second).ok()
+    }
+
+    /// Samples the delay before the next share.
+    pub(crate) fn next_share_delay(&self, target: Option<U256>) -> Duration {
+        match self.mean_share_interval(target) {
+            Some(mean) => {
+                let u: f64 = thread_rng().gen();
+                Duration::try_from_secs_f64(-(1.0 - u).ln() * mean.as_secs_f64())
+                    .unwrap_or(Duration::MAX)
+            }
+            None => IDLE_DELAY,
+        }
+    }
+}
+
+fn u256_to_f64(value: U256) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 2_f64.powi(64) + *limb as f64)
+}
+
+/// Looks for a nonce of `header` meeting `target`, from a random one, within
+/// [`MAX_MINING_ATTEMPTS`] hashes.
+fn find_nonce(header: &Header, target: U256) -> Option<u32> {
+    let mut hasher = FastSha256d::from_header_static(header);
+    let target = target.to_little_endian();
+    let start: u32 = thread_rng().gen();
+    (0..MAX_MINING_ATTEMPTS)
+        .map(|i| start.wrapping_add(i))
+        .find(|nonce| {
+            let hash = hasher.hash_with_nonce_time(*nonce, header.time);
+            hash_meets_target_le(&hash, &target)
+        })
+}
+
+/// Counterpart of `start_mining_threads` for simulated devices: mines and sends a share for the
+/// current job after every [`Simulation::next_share_delay`].
+pub(crate) fn start_simulated_shares(
+    have_new_job: Receiver<()>,
+    miner: Arc<Mutex<Miner>>,
+    share_send: Sender<(u32, u32, u32, u32)>,
+    simulation: Simulation,
+) {
+    tokio::task::spawn(async move {
+        // Nothing to submit before the first job
+        if have_new_job.recv().await.is_err() {
+            return;
+        }
+        loop {
+            let target = miner.safe_lock(|m| m.target).unwrap();
+            tokio::select! {
+                notification = have_new_job.recv() => {
+                    if notification.is_err() {
+                        return;
+                    }
+                    // Waiting times are memoryless, restarting the wait when the job or target
+                    // changes does not bias the share rate.
+                    continue;
+                }
+                _ = tokio::time::sleep(simulation.next_share_delay(target)) => {}
+            }
+
+            let work = miner
+                .safe_lock(|m| Some((m.job_id?, m.version?, m.header?, m.target?)))
+                .unwrap();
+            let Some((job_id, version, header, target)) = work else {
+                continue;
+            };
+            let Ok(nonce) = tokio::task::spawn_blocking(move || find_nonce(&header, target)).await
+            else {
+                return;
+            };
+            let Some(nonce) = nonce else {
+                warn!(
+                    "No share mined for job id {job_id} within {MAX_MINING_ATTEMPTS} hashes, the \
+                     simulated hashrate is too high for the target"
+                );
+                continue;
+            };
+            debug!("Simulated share with nonce: {nonce}, for job id: {job_id}");
+            if share_send
+                .send((nonce, job_id, version, header.time))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
+}
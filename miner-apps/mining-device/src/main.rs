@@ -56,6 +56,24 @@ struct Args {
         help = "Number of worker threads to use for mining. Defaults to logical CPUs minus one (leaves one core free)."
     )]
     cores: Option<u32>,
+    #[arg(
+        long,
+        help = "Enables the share rate simulation mode: instead of hashing continuously, mine shares when a device with this nominal hashrate (in H/s) would find them for the channel target"
+    )]
+    simulate_hashrate: Option<f32>,
+    #[arg(
+        long,
+        requires = "simulate_hashrate",
+        help = "Mine shares at this fixed rate instead of deriving it from the simulated hashrate and the channel target"
+    )]
+    simulate_shares_per_minute: Option<f64>,
+    #[arg(
+        long,
+        requires = "simulate_hashrate",
+        help = "Number of simulated devices to connect, each one opening its own connection and channel",
+        default_value = "1"
+    )]
+    simulated_devices: u32,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -63,6 +81,28 @@ async fn main() {
     let args = Args::parse();
     tracing_subscriber::fmt::init();
     info!("start");
+    if let Some(nominal_hashrate) = args.simulate_hashrate {
+        let simulation =
+            mining_device::Simulation::new(nominal_hashrate, args.simulate_shares_per_minute);
+        info!(
+            "Simulating {} devices of {} H/s",
+            args.simulated_devices, nominal_hashrate
+        );
+        let mut devices = tokio::task::JoinSet::new();
+        for i in 0..args.simulated_devices {
+            // Suffix identities so that the upstream can tell simulated devices apart
+            let suffix = |id: &Option<String>| id.as_ref().map(|id| format!("{id}.{i}"));
+            devices.spawn(mining_device::simulate(
+                args.address_pool.clone(),
+                args.pubkey_pool,
+                suffix(&args.id_device),
+                suffix(&args.id_user),
+                simulation,
+            ));
+        }
+        while devices.join_next().await.is_some() {}
+        return;
+    }
     // Configure micro-batch size
     mining_device::set_nonces_per_call(args.nonces_per_call);
     // Optional override of worker threads
@@ -0,0 +1,25 @@
+use mining_device::loadgen::{LoadStats, Percentiles};
+use std::time::Duration;
+
+#[test]
+fn percentiles_are_nearest_rank() {
+    let samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+    let percentiles = Percentiles::from_samples(&samples);
+    assert_eq!(percentiles.count, 100);
+    assert_eq!(percentiles.p50, Duration::from_millis(50));
+    assert_eq!(percentiles.p90, Duration::from_millis(90));
+    assert_eq!(percentiles.p99, Duration::from_millis(99));
+    assert_eq!(percentiles.max, Duration::from_millis(100));
+
+    let single = Percentiles::from_samples(&[Duration::from_millis(7)]);
+    assert_eq!(single.p50, Duration::from_millis(7));
+    assert_eq!(single.p99, Duration::from_millis(7));
+}
+
+#[test]
+fn empty_run_reports_nothing() {
+    let report = LoadStats::new().report();
+    assert_eq!(report.submitted, 0);
+    assert_eq!(report.share_ack, Percentiles::default());
+    assert!(report.rejections.is_empty());
+}
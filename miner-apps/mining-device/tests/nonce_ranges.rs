@@ -0,0 +1,19 @@
+use mining_device::nonce_ranges;
+
+#[test]
+fn nonce_ranges_cover_the_nonce_space_without_overlap() {
+    for workers in [1, 2, 3, 7, 16, 64] {
+        let ranges = nonce_ranges(workers);
+        assert_eq!(ranges.len(), workers as usize);
+        assert_eq!(*ranges.first().unwrap().start(), 0);
+        assert_eq!(*ranges.last().unwrap().end(), u32::MAX);
+        for pair in ranges.windows(2) {
+            assert_eq!(*pair[0].end() + 1, *pair[1].start());
+        }
+    }
+}
+
+#[test]
+fn zero_workers_means_one_range() {
+    assert_eq!(nonce_ranges(0), vec![0..=u32::MAX]);
+}
@@ -0,0 +1,24 @@
+use mining_device::Simulation;
+use primitive_types::U256;
+use std::time::Duration;
+
+#[test]
+fn fixed_share_rate_overrides_target() {
+    let simulation = Simulation::new(1_000_000.0, Some(6.0));
+    assert_eq!(
+        simulation.mean_share_interval(None),
+        Some(Duration::from_secs(10))
+    );
+}
+
+#[test]
+fn share_rate_follows_hashrate_and_target() {
+    // A target of 2^224 - 1 is met once every 2^32 hashes
+    let target = (U256::one() << 224) - 1;
+    let simulation = Simulation::new(2_f32.powi(32) / 5.0, None);
+    let interval = simulation.mean_share_interval(Some(target)).unwrap();
+    assert!((interval.as_secs_f64() - 5.0).abs() < 1e-6, "{interval:?}");
+
+    // Without a target there is no rate to derive
+    assert_eq!(simulation.mean_share_interval(None), None);
+}
@@ -3,11 +3,17 @@
 //! It provides the `Args` struct to hold parsed arguments,
 //! and the `from_args` function to parse them from the command line.
 use clap::Parser;
-use ext_config::{Config, File, FileFormat};
-use std::path::PathBuf;
-use tracing::error;
+use std::path::{Path, PathBuf};
+use stratum_apps::{
+    config_helpers::{self, ConfigCheck, ConfigProfile},
+    persistence::PersistenceConfig,
+};
 use translator_sv2::{config::TranslatorConfig, error::TproxyError};
 
+/// Prefix of the environment variables overriding the configuration file, e.g.
+/// `SV2_TRANSLATOR__DOWNSTREAM_PORT`.
+pub const ENV_PREFIX: &str = "SV2_TRANSLATOR";
+
 /// Holds the parsed CLI arguments.
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Translator Proxy", long_about = None)]
@@ -15,7 +21,7 @@ pub struct Args {
     #[arg(
         short = 'c',
         long = "config",
-        help = "Path to the TOML configuration file",
+        help = "Path to the configuration file, in TOML, YAML (.yaml, .yml) or JSON (.json)",
         default_value = "proxy-config.toml"
     )]
     pub config_path: PathBuf,
@@ -25,6 +31,26 @@ pub struct Args {
         help = "Path to the log file. If not set, logs will only be written to stdout."
     )]
     pub log_file: Option<PathBuf>,
+    #[arg(
+        long = "check-config",
+        help = "Validate the configuration file, print a report and exit without starting the proxy"
+    )]
+    pub check_config: bool,
+    #[arg(
+        long = "generate-config",
+        value_name = "PROFILE",
+        num_args = 0..=1,
+        default_missing_value = "testnet",
+        help = "Write an example configuration for the `mainnet`, `testnet` (default), `signet` or `regtest` profile and exit"
+    )]
+    pub generate_config: Option<ConfigProfile>,
+    #[arg(
+        short = 'o',
+        long = "output",
+        requires = "generate_config",
+        help = "File written by --generate-config instead of stdout. Existing files are not overwritten."
+    )]
+    pub output: Option<PathBuf>,
 }
 
 /// Process CLI args, if any.
@@ -32,21 +58,208 @@ pub struct Args {
 pub fn process_cli_args() -> Result<TranslatorConfig, TproxyError> {
     // Parse CLI arguments
     let args = Args::parse();
+    if let Some(profile) = args.generate_config {
+        let config = generate_config(profile);
+        if let Err(e) = config_helpers::write_generated_config(&config, args.output.as_deref()) {
+            eprintln!("Failed to write the generated config: {e}");
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    if args.check_config {
+        check_config(&args.config_path).exit();
+    }
 
-    // Build configuration from the provided file path
-    let config_path = args.config_path.to_str().ok_or_else(|| {
-        error!("Invalid configuration path.");
-        TproxyError::BadCliArgs
-    })?;
-
-    let settings = Config::builder()
-        .add_source(File::new(config_path, FileFormat::Toml))
-        .build()?;
-
-    // Deserialize settings into TranslatorConfig
-    let mut config = settings.try_deserialize::<TranslatorConfig>()?;
+    // Build configuration from the provided file path and the environment
+    let mut config: TranslatorConfig = config_helpers::load_config(&args.config_path, ENV_PREFIX)?;
 
     config.set_log_dir(args.log_file);
 
     Ok(config)
 }
+
+/// Loads the configuration at `config_path` and checks it without starting any service.
+pub fn check_config(config_path: &Path) -> ConfigCheck {
+    let mut report = ConfigCheck::new();
+    let parsed: Result<TranslatorConfig, _> = config_helpers::load_config(config_path, ENV_PREFIX);
+    let config = match parsed {
+        Ok(config) => config,
+        Err(e) => {
+            report.fail(format!("parse {}", config_path.display()), e);
+            return report;
+        }
+    };
+    report.pass(format!("parse {}", config_path.display()), "ok");
+    report.ip_address("downstream address", &config.downstream_address);
+    if let Some(tls) = &config.downstream_tls {
+        report.check(
+            "downstream TLS",
+            tls.acceptor()
+                .map(|_| format!("certificate {}", tls.cert_path.display())),
+        );
+    }
+    report.check(
+        "supported versions",
+        match (config.min_supported_version, config.max_supported_version) {
+            (min, max) if min <= max => Ok(format!("{min}..={max}")),
+            (min, max) => Err(format!(
+                "`min_supported_version` {min} is above `max_supported_version` {max}"
+            )),
+        },
+    );
+    report.check(
+        "user identity",
+        match config.user_identity.is_empty() {
+            true => Err("`user_identity` must not be empty"),
+            false => Ok(&config.user_identity),
+        },
+    );
+    if config.upstreams.is_empty() {
+        report.fail(
+            "upstreams",
+            "at least one `[[upstreams]]` entry is required",
+        );
+    }
+    for (i, upstream) in config.upstreams.iter().enumerate() {
+        report.ip_address(format!("upstream {i} address"), &upstream.address);
+    }
+    if let Some(monitoring) = &config.monitoring {
+        report.pass("monitoring address", monitoring.listen_address);
+    }
+    report.banlist(&config.banlist);
+    let limits = &config.sv1_limits;
+    report.check(
+        "SV1 message limits",
+        if limits.max_message_size == 0 {
+            Err("`sv1_limits.max_message_size` must not be 0".to_string())
+        } else if limits.messages.burst == 0 {
+            Err("`sv1_limits.messages.burst` must not be 0".to_string())
+        } else {
+            Ok(format!(
+                "{} bytes, {} messages/s",
+                limits.max_message_size, limits.messages.rate
+            ))
+        },
+    );
+    match &config.persistence {
+        Some(PersistenceConfig::File { path }) => report.appendable_file("share log", path),
+        None => report.pass("share log", "disabled"),
+    }
+    report
+}
+
+/// Renders a commented configuration for `profile`.
+///
+/// The proxy has no authority keypair of its own, the profile only sizes the difficulty for the
+/// miners expected on the network (CPU miners on regtest).
+pub fn generate_config(profile: ConfigProfile) -> String {
+    let min_individual_miner_hashrate = match profile {
+        ConfigProfile::Regtest => "10_000_000.0",
+        ConfigProfile::Mainnet | ConfigProfile::Testnet => "10_000_000_000_000.0",
+    };
+    format!(
+        r#"# SRI Translator Proxy config ({profile}), generated with --generate-config
+# Every setting can be overridden by an environment variable named after it, prefixed with
+# SV2_TRANSLATOR__ (nested tables separated by __). Validate this file with --check-config.
+
+# SV1 Downstream Connection (where miners connect)
+downstream_address = "0.0.0.0"
+downstream_port = 34255
+# Seconds an accepted miner may take to complete its TLS handshake and get authorized before being
+# disconnected (10 by default).
+# handshake_timeout_secs = 10
+
+# Version support
+max_supported_version = 2
+min_supported_version = 2
+
+# Extranonce2 size for downstream connections
+# This controls the rollable part of the extranonce for downstream miners
+# Max value for CGminer: 8
+# Min value: 2
+downstream_extranonce2_size = 4
+
+# User identity/username for pool connection
+# This will be appended with a counter for each mining client (e.g., username.miner1, username.miner2)
+user_identity = "your_username_here"
+
+# Aggregate channels: if true, all miners share one upstream channel; if false, each miner gets its own channel
+aggregate_channels = false
+
+# On low-bandwidth links (satellite, LTE), jobs that do not clean the previous ones (e.g. fee bumps)
+# can be sent to the miners at most once per that many seconds. Clean jobs are always sent at once.
+# notify_coalescing_secs = 30
+
+# Logs are written to this file if set, --log-file (or -f) overrides it.
+# log_file = "./tproxy.log"
+
+# Difficulty params
+[downstream_difficulty_config]
+# hashes/s of the weakest miner that will be connecting
+min_individual_miner_hashrate = {min_individual_miner_hashrate}
+# target number of shares per minute the miner should be sending
+shares_per_minute = 6.0
+# variable difficulty adjustment, disable it when the upstream is a JDC (JDC handles vardiff)
+enable_vardiff = true
+# bounds applied to the difficulty miners suggest via mining.suggest_difficulty
+# min_suggested_difficulty = 1.0
+# max_suggested_difficulty = 1_000_000.0
+
+# Upstream SV2 pools or JDCs, in order of preference. REPLACE the address and authority public key
+# with the ones published by your pool (or set in your JDC).
+# A list of keys (e.g. the current and the next one during a key rotation) accepts any of them.
+[[upstreams]]
+address = "127.0.0.1"
+port = 34254
+authority_pubkey = "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72"
+# encryption = "none"  # plaintext SV2 frames, the upstream must set listen_encryption = "none" too
+
+# Upstream reconnection with an exponential backoff, when no upstream in the list can be reached.
+# [reconnect]
+# initial_backoff_secs = 1
+# max_backoff_secs = 60
+# max_attempts = 10  # retry forever if unset
+
+# Serve SV1 miners over TLS (stratum+ssl) instead of plaintext TCP on downstream_port.
+# [downstream_tls]
+# cert_path = "./tproxy-cert.pem"  # PEM certificate chain, leaf first
+# key_path = "./tproxy-key.pem"    # PEM private key
+
+# HTTP endpoint serving the connected miners and the health of the proxy as JSON on GET /status,
+# and liveness and readiness probes on GET /healthz and GET /readyz (200 or 503).
+# [monitoring]
+# listen_address = "127.0.0.1:9090"
+
+# Log format: "pretty" (default) or "json", one object per line carrying the fields of the
+# enclosing spans (connection id, channel id, user identity). Filter directives select the level
+# per module, RUST_LOG takes precedence over them.
+# [logging]
+# format = "json"
+# filter = "info,translator_sv2=debug"
+# Binaries built with the `otel` feature also export spans to an OTLP/gRPC collector.
+# otlp_endpoint = "http://localhost:4317"
+
+# Connections of miners from banned IP addresses and CIDR ranges are refused. Bans are managed on
+# /bans of the monitoring endpoint and kept in memory, or loaded from and saved to `file` if set.
+# [banlist]
+# file = "./tproxy-banlist.txt"
+
+# Limits on the messages of each miner, protecting the proxy from broken firmware. Miners sending
+# longer or too many consecutive malformed messages are disconnected, messages over the rate are
+# dropped and miners dropped more than max_violations messages in a row are disconnected too. The
+# address of a disconnected miner is banned for ban_secs if set.
+# [sv1_limits]
+# max_message_size = 16384
+# max_malformed_messages = 10
+# max_violations = 100
+# ban_secs = 3600
+# messages = {{ rate = 20.0, burst = 100 }}
+
+# Local log of every share validated by the proxy, accepted or rejected, before the upstream
+# acknowledges it (one JSON object per line).
+# [persistence]
+# backend = "file"
+# path = "./tproxy-shares.jsonl"
+"#
+    )
+}
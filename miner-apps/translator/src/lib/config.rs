@@ -7,13 +7,30 @@
 //!
 //! This module handles:
 //! - Upstream server address, port, and authentication key ([`UpstreamConfig`])
+//! - Upstream reconnection backoff ([`ReconnectConfig`])
 //! - Downstream interface address and port ([`DownstreamConfig`])
 //! - Supported protocol versions
-//! - Downstream difficulty adjustment parameters ([`DownstreamDifficultyConfig`])
-use std::path::{Path, PathBuf};
+//! - Downstream difficulty adjustment parameters and bounds for miner suggested difficulties
+//!   ([`DownstreamDifficultyConfig`])
+//! - Local log of the SV1 shares validated by the Translator ([`PersistenceConfig`])
+//! - Rate and size limits on the messages of each SV1 miner ([`Sv1LimitsConfig`])
+//! - Coalescing of the job notifications sent to SV1 miners on low-bandwidth links
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use serde::Deserialize;
-use stratum_apps::key_utils::Secp256k1PublicKey;
+use stratum_apps::{
+    banlist::BanListConfig,
+    config_helpers::logging::LoggingConfig,
+    key_utils::{AuthorityPublicKeys, Secp256k1PublicKey},
+    monitoring::MonitoringConfig,
+    network_helpers::{sv1_connection::Sv1ReadLimits, tls::TlsConfig, transport::Encryption},
+    persistence::PersistenceConfig,
+    rate_limit::TokenBucketConfig,
+    runtime::DEFAULT_HANDSHAKE_TIMEOUT,
+};
 
 /// Configuration for the Translator.
 #[derive(Debug, Deserialize, Clone)]
@@ -23,6 +40,11 @@ pub struct TranslatorConfig {
     pub downstream_address: String,
     /// The port for the downstream interface.
     pub downstream_port: u16,
+    /// TLS settings for the downstream interface. SV1 miners connect in plaintext if unset.
+    pub downstream_tls: Option<TlsConfig>,
+    /// Seconds an accepted SV1 miner may take to complete its TLS handshake and get authorized
+    /// before being disconnected. 10 seconds if unset.
+    pub handshake_timeout_secs: Option<u64>,
     /// The maximum supported protocol version for communication.
     pub max_supported_version: u16,
     /// The minimum supported protocol version for communication.
@@ -38,8 +60,32 @@ pub struct TranslatorConfig {
     /// Whether to aggregate all downstream connections into a single upstream channel.
     /// If true, all miners share one channel. If false, each miner gets its own channel.
     pub aggregate_channels: bool,
+    /// Backoff settings used to reconnect to the upstreams when the connection is lost.
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    /// Seconds the upstream may stay silent before the connection is considered dead and the
+    /// upstreams are reconnected. No timeout if unset.
+    pub upstream_idle_timeout_secs: Option<u64>,
+    /// Seconds a miner channel is sent at most one job that does not clean the previous ones in,
+    /// the latest job of the interval being forwarded at its end. Clean jobs are always forwarded
+    /// right away. Every job is forwarded right away if unset.
+    pub notify_coalescing_secs: Option<u64>,
+    /// HTTP endpoint listing the connected miners and the upstream health. Disabled if unset.
+    pub monitoring: Option<MonitoringConfig>,
+    /// Bans of the SV1 miners addresses, managed on the monitoring endpoint.
+    #[serde(default)]
+    pub banlist: BanListConfig,
+    /// Limits on the messages of each SV1 miner.
+    #[serde(default)]
+    pub sv1_limits: Sv1LimitsConfig,
+    /// Log of every SV1 share validated by the Translator, accepted or rejected, before the
+    /// upstream acknowledges it. Disabled if unset.
+    pub persistence: Option<PersistenceConfig>,
     /// The path to the log file for the Translator.
     log_file: Option<PathBuf>,
+    /// Format and filter of the logs.
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -48,8 +94,12 @@ pub struct Upstream {
     pub address: String,
     /// The port of the upstream server.
     pub port: u16,
-    /// The Secp256k1 public key used to authenticate the upstream authority.
-    pub authority_pubkey: Secp256k1PublicKey,
+    /// The Secp256k1 public keys used to authenticate the upstream authority: a single key, or
+    /// the current and the next key while the upstream rotates its keypair.
+    pub authority_pubkey: AuthorityPublicKeys,
+    /// Encryption of the connection, Noise unless set to `"none"` for trusted networks.
+    #[serde(default)]
+    pub encryption: Encryption,
 }
 
 impl Upstream {
@@ -58,7 +108,8 @@ impl Upstream {
         Self {
             address,
             port,
-            authority_pubkey,
+            authority_pubkey: authority_pubkey.into(),
+            encryption: Encryption::default(),
         }
     }
 }
@@ -82,13 +133,23 @@ impl TranslatorConfig {
             upstreams,
             downstream_address,
             downstream_port,
+            downstream_tls: None,
+            handshake_timeout_secs: None,
             max_supported_version,
             min_supported_version,
             downstream_extranonce2_size,
             user_identity,
             downstream_difficulty_config,
             aggregate_channels,
+            reconnect: ReconnectConfig::default(),
+            upstream_idle_timeout_secs: None,
+            notify_coalescing_secs: None,
+            monitoring: None,
+            banlist: BanListConfig::default(),
+            sv1_limits: Sv1LimitsConfig::default(),
+            persistence: None,
             log_file: None,
+            logging: LoggingConfig::default(),
         }
     }
 
@@ -100,6 +161,135 @@ impl TranslatorConfig {
     pub fn log_dir(&self) -> Option<&Path> {
         self.log_file.as_deref()
     }
+
+    /// Returns how long the upstream may stay silent before being reconnected, if set.
+    pub fn upstream_idle_timeout(&self) -> Option<Duration> {
+        self.upstream_idle_timeout_secs.map(Duration::from_secs)
+    }
+
+    /// Returns the interval the jobs that do not clean the previous ones are coalesced over, if
+    /// set.
+    pub fn notify_coalescing_interval(&self) -> Option<Duration> {
+        self.notify_coalescing_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Returns how long an accepted SV1 miner may take to get authorized before being
+    /// disconnected.
+    pub fn handshake_timeout(&self) -> Duration {
+        self.handshake_timeout_secs
+            .map_or(DEFAULT_HANDSHAKE_TIMEOUT, Duration::from_secs)
+    }
+}
+
+/// Upstream reconnection settings.
+///
+/// When no configured upstream can be reached, the whole list is retried after an exponentially
+/// growing delay, starting at `initial_backoff_secs` and capped at `max_backoff_secs`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// Delay before retrying the upstream list for the first time, in seconds.
+    pub initial_backoff_secs: u64,
+    /// Maximum delay between two passes over the upstream list, in seconds.
+    pub max_backoff_secs: u64,
+    /// Maximum number of passes over the upstream list before giving up. Retries forever if
+    /// unset.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_secs: 1,
+            max_backoff_secs: 60,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Creates a new `ReconnectConfig` instance.
+    pub fn new(
+        initial_backoff_secs: u64,
+        max_backoff_secs: u64,
+        max_attempts: Option<u32>,
+    ) -> Self {
+        Self {
+            initial_backoff_secs,
+            max_backoff_secs,
+            max_attempts,
+        }
+    }
+
+    /// Returns the delay to wait after the `attempt`-th (starting at 1) failed pass over the
+    /// upstream list.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        Duration::from_secs(
+            self.initial_backoff_secs
+                .saturating_mul(factor)
+                .min(self.max_backoff_secs),
+        )
+    }
+
+    /// Returns `true` if another pass over the upstream list is allowed after `attempt` failed
+    /// ones.
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt < max)
+    }
+}
+
+/// Limits on the messages of each SV1 miner, protecting the Translator from broken firmware.
+///
+/// A miner sending a message longer than `max_message_size` bytes, or more than
+/// `max_malformed_messages` consecutive messages that are not valid JSON-RPC requests, is
+/// disconnected. Messages over the `messages` token bucket are dropped, and the miner is
+/// disconnected once more than `max_violations` consecutive messages have been dropped. The IP
+/// address of a miner disconnected for breaking a limit is also banned for `ban_secs` if set.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Sv1LimitsConfig {
+    /// Longest message accepted, in bytes.
+    pub max_message_size: usize,
+    /// Consecutive malformed messages tolerated.
+    pub max_malformed_messages: u32,
+    /// Rate and burst of the messages of a miner.
+    pub messages: TokenBucketConfig,
+    /// Consecutive messages dropped over the rate limit tolerated.
+    pub max_violations: u32,
+    /// Seconds the IP address of a miner disconnected for breaking a limit is banned. Not banned
+    /// if unset.
+    pub ban_secs: Option<u64>,
+}
+
+impl Default for Sv1LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: 16_384,
+            max_malformed_messages: 10,
+            messages: TokenBucketConfig::new(20.0, 100),
+            max_violations: 100,
+            ban_secs: None,
+        }
+    }
+}
+
+impl Sv1LimitsConfig {
+    /// Returns the limits applied to the lines read from the miners.
+    pub fn read_limits(&self) -> Sv1ReadLimits {
+        Sv1ReadLimits {
+            max_message_size: self.max_message_size,
+            max_malformed_messages: Some(self.max_malformed_messages),
+        }
+    }
+
+    /// Returns how long the IP address of a miner disconnected for breaking a limit is banned,
+    /// if it is.
+    pub fn ban_duration(&self) -> Option<Duration> {
+        self.ban_secs.map(Duration::from_secs)
+    }
 }
 
 /// Configuration settings for managing difficulty adjustments on the downstream connection.
@@ -112,6 +302,12 @@ pub struct DownstreamDifficultyConfig {
     /// Whether to enable variable difficulty adjustment mechanism.
     /// If false, difficulty will be managed by upstream (useful with JDC).
     pub enable_vardiff: bool,
+    /// Lower bound applied to the difficulty suggested by miners via `mining.suggest_difficulty`.
+    #[serde(default)]
+    pub min_suggested_difficulty: Option<f64>,
+    /// Upper bound applied to the difficulty suggested by miners via `mining.suggest_difficulty`.
+    #[serde(default)]
+    pub max_suggested_difficulty: Option<f64>,
 }
 
 impl DownstreamDifficultyConfig {
@@ -125,6 +321,20 @@ impl DownstreamDifficultyConfig {
             min_individual_miner_hashrate,
             shares_per_minute,
             enable_vardiff,
+            min_suggested_difficulty: None,
+            max_suggested_difficulty: None,
+        }
+    }
+
+    /// Clamps a difficulty suggested by a miner within the configured bounds.
+    pub fn clamp_suggested_difficulty(&self, difficulty: f64) -> f64 {
+        let difficulty = match self.min_suggested_difficulty {
+            Some(min) => difficulty.max(min),
+            None => difficulty,
+        };
+        match self.max_suggested_difficulty {
+            Some(max) => difficulty.min(max),
+            None => difficulty,
         }
     }
 }
@@ -160,6 +370,34 @@ mod tests {
         assert!(config.enable_vardiff);
     }
 
+    #[test]
+    fn test_clamp_suggested_difficulty() {
+        let mut config = create_test_difficulty_config();
+        assert_eq!(config.clamp_suggested_difficulty(0.001), 0.001);
+        assert_eq!(config.clamp_suggested_difficulty(1e12), 1e12);
+
+        config.min_suggested_difficulty = Some(1.0);
+        config.max_suggested_difficulty = Some(65536.0);
+        assert_eq!(config.clamp_suggested_difficulty(0.001), 1.0);
+        assert_eq!(config.clamp_suggested_difficulty(1024.0), 1024.0);
+        assert_eq!(config.clamp_suggested_difficulty(1e12), 65536.0);
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let reconnect = ReconnectConfig::new(2, 30, Some(5));
+        assert_eq!(reconnect.backoff(1), Duration::from_secs(2));
+        assert_eq!(reconnect.backoff(2), Duration::from_secs(4));
+        assert_eq!(reconnect.backoff(4), Duration::from_secs(16));
+        assert_eq!(reconnect.backoff(5), Duration::from_secs(30));
+        assert_eq!(reconnect.backoff(100), Duration::from_secs(30));
+        assert!(reconnect.should_retry(4));
+        assert!(!reconnect.should_retry(5));
+
+        let forever = ReconnectConfig::default();
+        assert!(forever.should_retry(u32::MAX));
+    }
+
     #[test]
     fn test_translator_config_creation() {
         let upstreams = vec![create_test_upstream()];
@@ -180,12 +418,39 @@ mod tests {
         assert_eq!(config.upstreams.len(), 1);
         assert_eq!(config.downstream_address, "0.0.0.0");
         assert_eq!(config.downstream_port, 3333);
+        assert!(config.downstream_tls.is_none());
         assert_eq!(config.max_supported_version, 2);
         assert_eq!(config.min_supported_version, 1);
         assert_eq!(config.downstream_extranonce2_size, 4);
         assert_eq!(config.user_identity, "test_user");
         assert!(config.aggregate_channels);
+        assert_eq!(config.reconnect, ReconnectConfig::default());
+        assert_eq!(config.sv1_limits, Sv1LimitsConfig::default());
+        assert!(config.monitoring.is_none());
         assert!(config.log_file.is_none());
+        assert!(config.upstream_idle_timeout().is_none());
+        assert!(config.notify_coalescing_interval().is_none());
+        assert_eq!(config.handshake_timeout(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_upstream_idle_timeout() {
+        let mut config = TranslatorConfig::new(
+            vec![create_test_upstream()],
+            "0.0.0.0".to_string(),
+            3333,
+            create_test_difficulty_config(),
+            2,
+            1,
+            4,
+            "test_user".to_string(),
+            false,
+        );
+        config.upstream_idle_timeout_secs = Some(90);
+        assert_eq!(
+            config.upstream_idle_timeout(),
+            Some(Duration::from_secs(90))
+        );
     }
 
     #[test]
@@ -9,10 +9,14 @@
 //!   asynchronous channels.
 
 use ext_config::ConfigError;
-use std::{fmt, sync::PoisonError};
-use stratum_apps::stratum_core::{
-    binary_sv2, framing_sv2, handlers_sv2::HandlerErrorType, noise_sv2, parsers_sv2::ParserError,
-    sv1_api::server_to_client::SetDifficulty,
+use std::{fmt, net::SocketAddr, sync::PoisonError};
+use stratum_apps::{
+    exit::ExitStatus,
+    stratum_core::{
+        binary_sv2, channels_sv2::client::error::ExtendedChannelError, framing_sv2,
+        handlers_sv2::HandlerErrorType, mining_sv2::ExtendedExtranonceError, noise_sv2,
+        parsers_sv2::ParserError, sv1_api::server_to_client::SetDifficulty,
+    },
 };
 use tokio::sync::broadcast;
 
@@ -22,6 +26,8 @@ pub enum TproxyError {
     SV1Error,
     /// Error from the network helpers library
     NetworkHelpersError(stratum_apps::network_helpers::Error),
+    /// Invalid TLS configuration for the downstream listener
+    Tls(stratum_apps::network_helpers::tls::Error),
     /// Error from roles logic parser library
     ParserError(ParserError),
     /// Errors on bad CLI argument input.
@@ -58,14 +64,44 @@ pub enum TproxyError {
     JobNotFound,
     /// Invalid merkle root during share validation
     InvalidMerkleRoot,
+    /// Share version bits outside of the negotiated version rolling mask
+    VersionBitsOutOfMask { version_bits: u32, mask: u32 },
     /// Shutdown signal received
     Shutdown,
     /// Pending channel not found for the given request ID
     PendingChannelNotFound(u32),
+    /// Error from an extended channel, e.g. an extranonce prefix it cannot take
+    ExtendedChannel(ExtendedChannelError),
+    /// The extranonce prefixes of the downstream channels cannot be allocated
+    ExtranoncePrefixFactory(ExtendedExtranonceError),
     /// Represents a generic channel send failure, described by a string.
     General(String),
     /// Error bubbling up from translator-core library
     TranslatorCore(stratum_apps::stratum_core::stratum_translation::error::StratumTranslationError),
+    /// The downstream listening address cannot be bound
+    Bind(SocketAddr, std::io::Error),
+    /// None of the upstreams could be connected to
+    UpstreamUnreachable(String),
+    /// The share log cannot be opened
+    Persistence(stratum_apps::persistence::Error),
+    /// An SV1 miner repeatedly sent messages over its rate limit
+    RateLimitExceeded,
+    /// An SV1 miner repeatedly sent messages that are not valid SV1 requests
+    MalformedMessages,
+}
+
+impl TproxyError {
+    /// Returns the exit status of the translator process when it stops on this error.
+    pub fn exit_status(&self) -> ExitStatus {
+        use TproxyError::*;
+        match self {
+            BadCliArgs | BadConfigDeserialize(_) => ExitStatus::Config,
+            Tls(_) => ExitStatus::Key,
+            Bind(..) => ExitStatus::Bind,
+            UpstreamUnreachable(_) => ExitStatus::Unreachable,
+            _ => ExitStatus::Failure,
+        }
+    }
 }
 
 impl std::error::Error for TproxyError {}
@@ -101,14 +137,28 @@ impl fmt::Display for TproxyError {
             }
             JobNotFound => write!(f, "Job not found during share validation"),
             InvalidMerkleRoot => write!(f, "Invalid merkle root during share validation"),
+            VersionBitsOutOfMask { version_bits, mask } => write!(
+                f,
+                "Share version bits {version_bits:#010x} outside of version rolling mask {mask:#010x}"
+            ),
             Shutdown => write!(f, "Shutdown signal"),
             PendingChannelNotFound(request_id) => {
                 write!(f, "No pending channel found for request_id: {}", request_id)
             }
+            ExtendedChannel(ref e) => write!(f, "Extended channel error: {e:?}"),
+            ExtranoncePrefixFactory(ref e) => {
+                write!(f, "Failed to allocate extranonce prefixes: {e:?}")
+            }
             SV1Error => write!(f, "Sv1 error"),
             TranslatorCore(ref e) => write!(f, "Translator core error: {e:?}"),
             NetworkHelpersError(ref e) => write!(f, "Network helpers error: {e:?}"),
+            Tls(ref e) => write!(f, "TLS error: {e}"),
             ParserError(ref e) => write!(f, "Roles logic parser error: {e:?}"),
+            Bind(address, ref e) => write!(f, "Failed to bind {address}: {e}"),
+            UpstreamUnreachable(ref e) => write!(f, "No upstream reachable: {e}"),
+            Persistence(ref e) => write!(f, "Persistence error: `{e}`"),
+            RateLimitExceeded => write!(f, "SV1 message rate limit repeatedly exceeded"),
+            MalformedMessages => write!(f, "Too many consecutive malformed SV1 messages"),
         }
     }
 }
@@ -192,6 +242,18 @@ impl From<stratum_apps::network_helpers::Error> for TproxyError {
     }
 }
 
+impl From<stratum_apps::persistence::Error> for TproxyError {
+    fn from(e: stratum_apps::persistence::Error) -> Self {
+        TproxyError::Persistence(e)
+    }
+}
+
+impl From<stratum_apps::network_helpers::tls::Error> for TproxyError {
+    fn from(e: stratum_apps::network_helpers::tls::Error) -> Self {
+        TproxyError::Tls(e)
+    }
+}
+
 impl From<stratum_apps::stratum_core::stratum_translation::error::StratumTranslationError>
     for TproxyError
 {
@@ -202,6 +264,18 @@ impl From<stratum_apps::stratum_core::stratum_translation::error::StratumTransla
     }
 }
 
+impl From<ExtendedChannelError> for TproxyError {
+    fn from(e: ExtendedChannelError) -> Self {
+        TproxyError::ExtendedChannel(e)
+    }
+}
+
+impl From<ExtendedExtranonceError> for TproxyError {
+    fn from(e: ExtendedExtranonceError) -> Self {
+        TproxyError::ExtranoncePrefixFactory(e)
+    }
+}
+
 impl From<ParserError> for TproxyError {
     fn from(value: ParserError) -> Self {
         TproxyError::ParserError(value)
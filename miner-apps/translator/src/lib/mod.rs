@@ -11,31 +11,47 @@
 //! It relies on several sub-modules (`config`, `downstream_sv1`, `upstream_sv2`, `proxy`, `status`,
 //! etc.) for specialized functionalities.
 #![allow(clippy::module_inception)]
-use async_channel::unbounded;
-use std::{net::SocketAddr, sync::Arc};
-use tokio::sync::mpsc;
+use async_channel::{unbounded, Receiver, Sender};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use stratum_apps::{
+    banlist::BanList,
+    custom_mutex::Mutex,
+    key_utils::AuthorityPublicKeys,
+    network_helpers::{traffic::ConnectionTraffic, transport::Encryption},
+    persistence::Persistence,
+    runtime::TaskManager,
+    status::HealthAggregator,
+    stratum_core::parsers_sv2::Mining,
+};
+use tokio::sync::{broadcast, mpsc};
 use tracing::{debug, error, info, warn};
 
 pub use stratum_apps::stratum_core::sv1_api::server_to_client;
 
-use config::TranslatorConfig;
+use config::{ReconnectConfig, TranslatorConfig};
 
 use crate::{
-    status::{State, Status},
+    error::TproxyError,
+    monitoring::{TranslatorStatusProvider, UpstreamHealth},
+    status::{report_task_panics, State, Status},
     sv1::sv1_server::sv1_server::Sv1Server,
     sv2::{channel_manager::ChannelMode, ChannelManager, Upstream},
-    task_manager::TaskManager,
     utils::ShutdownMessage,
 };
 
 pub mod config;
 pub mod error;
+pub mod monitoring;
 pub mod status;
 pub mod sv1;
 pub mod sv2;
-mod task_manager;
 pub mod utils;
 
+// Components reported to the health aggregator
+const UPSTREAM: &str = "upstream";
+const SV1_SERVER: &str = "sv1_server";
+const CHANNEL_MANAGER: &str = "channel_manager";
+
 /// The main struct that manages the SV1/SV2 translator.
 #[derive(Clone, Debug)]
 pub struct TranslatorSv2 {
@@ -54,8 +70,9 @@ impl TranslatorSv2 {
     /// Starts the translator.
     ///
     /// This method starts the main event loop, which handles connections,
-    /// protocol translation, job management, and status reporting.
-    pub async fn start(self) {
+    /// protocol translation, job management, and status reporting. Returns the error the
+    /// translator stopped on, if it did not shut down cleanly.
+    pub async fn start(self) -> Result<(), TproxyError> {
         info!("Starting Translator Proxy...");
 
         let (notify_shutdown, _) = tokio::sync::broadcast::channel::<ShutdownMessage>(1);
@@ -63,6 +80,7 @@ impl TranslatorSv2 {
         let task_manager = Arc::new(TaskManager::new());
 
         let (status_sender, status_receiver) = async_channel::unbounded::<Status>();
+        report_task_panics(&task_manager, status_sender.clone());
 
         let (channel_manager_to_upstream_sender, channel_manager_to_upstream_receiver) =
             unbounded();
@@ -82,30 +100,14 @@ impl TranslatorSv2 {
             .map(|upstream| {
                 let upstream_addr =
                     SocketAddr::new(upstream.address.parse().unwrap(), upstream.port);
-                (upstream_addr, upstream.authority_pubkey)
+                (
+                    upstream_addr,
+                    upstream.authority_pubkey.clone(),
+                    upstream.encryption,
+                )
             })
             .collect::<Vec<_>>();
 
-        let upstream = match Upstream::new(
-            &upstream_addresses,
-            upstream_to_channel_manager_sender.clone(),
-            channel_manager_to_upstream_receiver.clone(),
-            notify_shutdown.clone(),
-            shutdown_complete_tx.clone(),
-            task_manager.clone(),
-        )
-        .await
-        {
-            Ok(upstream) => {
-                debug!("Upstream initialized successfully.");
-                upstream
-            }
-            Err(e) => {
-                error!("Failed to initialize upstream connection: {e:?}");
-                return;
-            }
-        };
-
         let channel_manager = Arc::new(ChannelManager::new(
             channel_manager_to_upstream_sender,
             upstream_to_channel_manager_receiver,
@@ -123,13 +125,49 @@ impl TranslatorSv2 {
             self.config.downstream_port,
         );
 
+        let persistence = match Persistence::new(self.config.persistence.as_ref()) {
+            Ok(persistence) => persistence,
+            Err(e) => {
+                error!("Failed to open the share log: {e}");
+                return Err(e.into());
+            }
+        };
+
         let sv1_server = Arc::new(Sv1Server::new(
             downstream_addr,
             channel_manager_to_sv1_server_receiver,
             sv1_server_to_channel_manager_sender,
             self.config.clone(),
+            persistence,
         ));
 
+        let upstream_health = Arc::new(Mutex::new(UpstreamHealth::default()));
+        let health = Arc::new(HealthAggregator::new());
+        let banlist = match BanList::new(&self.config.banlist) {
+            Ok(banlist) => Arc::new(banlist),
+            Err(e) => {
+                error!("Failed to load the ban list: {e}");
+                return Err(TproxyError::Io(e));
+            }
+        };
+
+        if let Some(monitoring_config) = self.config.monitoring.clone() {
+            let provider = Arc::new(TranslatorStatusProvider::new(
+                sv1_server.sv1_server_data(),
+                upstream_health.clone(),
+                health.clone(),
+                task_manager.clone(),
+                banlist.clone(),
+            ));
+            stratum_apps::monitoring::spawn(
+                &task_manager,
+                monitoring_config,
+                provider,
+                notify_shutdown.subscribe(),
+                |message| matches!(message, ShutdownMessage::ShutdownAll),
+            );
+        }
+
         ChannelManager::run_channel_manager_tasks(
             channel_manager.clone(),
             notify_shutdown.clone(),
@@ -139,23 +177,49 @@ impl TranslatorSv2 {
         )
         .await;
 
-        if let Err(e) = upstream
-            .start(
-                notify_shutdown.clone(),
-                shutdown_complete_tx.clone(),
-                status_sender.clone(),
-                task_manager.clone(),
-            )
-            .await
+        let upstream_idle_timeout = self.config.upstream_idle_timeout();
+        match connect_upstream(
+            &self.config.reconnect,
+            &upstream_addresses,
+            upstream_idle_timeout,
+            upstream_to_channel_manager_sender.clone(),
+            channel_manager_to_upstream_receiver.clone(),
+            notify_shutdown.clone(),
+            shutdown_complete_tx.clone(),
+            status_sender.clone(),
+            task_manager.clone(),
+        )
+        .await
         {
-            error!("Failed to start upstream listener: {e:?}");
-            return;
+            Ok((address, traffic)) => {
+                upstream_health.super_safe_lock(|health| health.connected(address, traffic));
+                health.healthy(UPSTREAM);
+                health.set_ready(true);
+            }
+            // Ctrl+C received while connecting.
+            Err(TproxyError::Shutdown) => {
+                let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                return Ok(());
+            }
+            Err(e) => {
+                error!("Failed to initialize upstream connection: {e:?}");
+                health.failed(UPSTREAM, format!("{e:?}"));
+                let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                return Err(TproxyError::UpstreamUnreachable(e.to_string()));
+            }
         }
 
         let notify_shutdown_clone = notify_shutdown.clone();
         let shutdown_complete_tx_clone = shutdown_complete_tx.clone();
         let status_sender_clone = status_sender.clone();
         let task_manager_clone = task_manager.clone();
+        let reconnect = self.config.reconnect.clone();
+        let upstream_health_clone = upstream_health.clone();
+        let health_clone = health.clone();
+        let channel_manager_clone = channel_manager.clone();
+        // Error the translator stops on, reported as its exit status.
+        let failure = Arc::new(Mutex::new(None));
+        let failure_clone = failure.clone();
         task_manager.spawn(async move {
             loop {
                 tokio::select! {
@@ -164,6 +228,14 @@ impl TranslatorSv2 {
                         let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
                         break;
                     }
+                    task = task_manager_clone.critical_failure() => {
+                        warn!("Critical task {task} failed — initiating full shutdown.");
+                        health_clone.failed(task, "critical task failed");
+                        let reason = format!("critical task {task} failed");
+                        failure_clone.super_safe_lock(|failure| *failure = Some(TproxyError::General(reason)));
+                        let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
+                        break;
+                    }
                     message = status_receiver.recv() => {
                         if let Ok(status) = message {
                             match status.state {
@@ -171,48 +243,62 @@ impl TranslatorSv2 {
                                     warn!("Downstream {downstream_id:?} disconnected — notifying SV1 server.");
                                     let _ = notify_shutdown_clone.send(ShutdownMessage::DownstreamShutdown(downstream_id));
                                 }
-                                State::Sv1ServerShutdown(_) => {
+                                State::Sv1ServerShutdown(e) => {
                                     warn!("SV1 Server shutdown requested — initiating full shutdown.");
+                                    health_clone.failed(SV1_SERVER, format!("{e:?}"));
+                                    failure_clone.super_safe_lock(|failure| *failure = Some(e));
                                     let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
                                     break;
                                 }
-                                State::ChannelManagerShutdown(_) => {
+                                State::ChannelManagerShutdown(e) => {
                                     warn!("Channel Manager shutdown requested — initiating full shutdown.");
+                                    health_clone.failed(CHANNEL_MANAGER, format!("{e:?}"));
+                                    failure_clone.super_safe_lock(|failure| *failure = Some(e));
                                     let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
                                     break;
                                 }
+                                State::TaskPanicked(panic) => {
+                                    warn!("Reported panic: {panic}");
+                                }
                                 State::UpstreamShutdown(msg) => {
                                     warn!("Upstream connection dropped: {msg:?} — attempting reconnection...");
+                                    upstream_health_clone.super_safe_lock(|health| health.disconnected());
+                                    health_clone.degraded(UPSTREAM, format!("reconnecting after {msg:?}"));
+                                    health_clone.set_ready(false);
 
-                                    match Upstream::new(
+                                    // SV1 miners stay connected while the upstream list is retried with
+                                    // backoff, and get new channels once a new upstream is up.
+                                    match connect_upstream(
+                                        &reconnect,
                                         &upstream_addresses,
+                                        upstream_idle_timeout,
                                         upstream_to_channel_manager_sender.clone(),
                                         channel_manager_to_upstream_receiver.clone(),
                                         notify_shutdown_clone.clone(),
                                         shutdown_complete_tx_clone.clone(),
-                                        task_manager_clone.clone()
+                                        status_sender_clone.clone(),
+                                        task_manager_clone.clone(),
                                     ).await {
-                                        Ok(upstream) => {
-                                            if let Err(e) = upstream
-                                                .start(
-                                                    notify_shutdown_clone.clone(),
-                                                    shutdown_complete_tx_clone.clone(),
-                                                    status_sender_clone.clone(),
-                                                    task_manager_clone.clone()
-                                                )
-                                                .await
-                                            {
-                                                error!("Restarted upstream failed to start: {e:?}");
-                                                let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
-                                                break;
-                                            } else {
-                                                info!("Upstream restarted successfully.");
-                                                // Reset channel manager state and shutdown downstreams in one message
-                                                let _ = notify_shutdown_clone.send(ShutdownMessage::UpstreamReconnectedResetAndShutdownDownstreams);
-                                            }
+                                        Ok((address, traffic)) => {
+                                            info!("Upstream restarted successfully.");
+                                            upstream_health_clone.super_safe_lock(|health| health.connected(address, traffic));
+                                            health_clone.healthy(UPSTREAM);
+                                            health_clone.set_ready(true);
+                                            // The channel manager forgets the channels of the previous
+                                            // upstream before the SV1 server reopens the ones of its
+                                            // miners, which stay connected.
+                                            channel_manager_clone
+                                                .channel_manager_data
+                                                .super_safe_lock(|data| data.reset_for_upstream_reconnection());
+                                            let _ = notify_shutdown_clone.send(ShutdownMessage::UpstreamReconnected);
                                         }
                                         Err(e) => {
                                             error!("Failed to reinitialize upstream after disconnect: {e:?}");
+                                            health_clone.failed(UPSTREAM, format!("{e:?}"));
+                                            if !matches!(e, TproxyError::Shutdown) {
+                                                let e = TproxyError::UpstreamUnreachable(e.to_string());
+                                                failure_clone.super_safe_lock(|failure| *failure = Some(e));
+                                            }
                                             let _ = notify_shutdown_clone.send(ShutdownMessage::ShutdownAll);
                                             break;
                                         }
@@ -227,6 +313,7 @@ impl TranslatorSv2 {
 
         if let Err(e) = Sv1Server::start(
             sv1_server,
+            banlist,
             notify_shutdown.clone(),
             shutdown_complete_tx.clone(),
             status_sender.clone(),
@@ -235,6 +322,8 @@ impl TranslatorSv2 {
         .await
         {
             error!("SV1 server startup failed: {e:?}");
+            health.failed(SV1_SERVER, format!("{e:?}"));
+            failure.super_safe_lock(|failure| *failure = Some(e));
             notify_shutdown.send(ShutdownMessage::ShutdownAll).unwrap();
         }
 
@@ -247,11 +336,90 @@ impl TranslatorSv2 {
             }
             _ = tokio::time::sleep(shutdown_timeout) => {
                 warn!("Graceful shutdown timed out after {shutdown_timeout:?} — forcing shutdown.");
+                for task in task_manager.running_tasks() {
+                    warn!(
+                        task = task.name,
+                        spawned_at = task.spawned_at,
+                        "Task still running, aborting it"
+                    );
+                }
                 task_manager.abort_all().await;
             }
         }
         info!("Joining remaining tasks...");
         task_manager.join_all().await;
         info!("TranslatorSv2 shutdown complete.");
+        match failure.super_safe_lock(|failure| failure.take()) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Connects to the first reachable upstream and starts it, returning its address and the traffic
+/// counters of the connection.
+///
+/// Every pass goes through the whole upstream list (see [`Upstream::new`]). When a pass fails,
+/// the next one is attempted after an exponential backoff driven by `reconnect`, until an
+/// upstream is started, `reconnect.max_attempts` is exhausted or Ctrl+C is received.
+#[allow(clippy::too_many_arguments)]
+async fn connect_upstream(
+    reconnect: &ReconnectConfig,
+    upstream_addresses: &[(SocketAddr, AuthorityPublicKeys, Encryption)],
+    idle_timeout: Option<Duration>,
+    upstream_to_channel_manager_sender: Sender<Mining<'static>>,
+    channel_manager_to_upstream_receiver: Receiver<Mining<'static>>,
+    notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    shutdown_complete_tx: mpsc::Sender<()>,
+    status_sender: Sender<Status>,
+    task_manager: Arc<TaskManager>,
+) -> Result<(SocketAddr, Arc<ConnectionTraffic>), TproxyError> {
+    let mut attempt: u32 = 0;
+    loop {
+        attempt += 1;
+        let result = match Upstream::new(
+            upstream_addresses,
+            idle_timeout,
+            upstream_to_channel_manager_sender.clone(),
+            channel_manager_to_upstream_receiver.clone(),
+            notify_shutdown.clone(),
+            shutdown_complete_tx.clone(),
+            task_manager.clone(),
+        )
+        .await
+        {
+            Ok(upstream) => {
+                let address = upstream.address();
+                let traffic = upstream.traffic();
+                upstream
+                    .start(
+                        notify_shutdown.clone(),
+                        shutdown_complete_tx.clone(),
+                        status_sender.clone(),
+                        task_manager.clone(),
+                    )
+                    .await
+                    .map(|()| (address, traffic))
+            }
+            Err(e) => Err(e),
+        };
+
+        let e = match result {
+            Ok(connected) => return Ok(connected),
+            Err(e) => e,
+        };
+        if !reconnect.should_retry(attempt) {
+            error!("Giving up on upstreams after {attempt} attempt(s)");
+            return Err(e);
+        }
+        let backoff = reconnect.backoff(attempt);
+        warn!("No upstream available (attempt {attempt}): {e:?} — retrying in {backoff:?}");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                info!("Ctrl+C received while reconnecting to upstream");
+                return Err(TproxyError::Shutdown);
+            }
+            _ = tokio::time::sleep(backoff) => {}
+        }
     }
 }
@@ -0,0 +1,241 @@
+//! ## Monitoring Module
+//!
+//! Builds the document served by the translator's HTTP monitoring endpoint
+//! (see [`stratum_apps::monitoring`]): the connected SV1 miners with their share statistics and
+//! their connections as listed by every role, the health of the upstream connection, the tasks
+//! currently running, the number of miners dropped for not completing their handshake in time and
+//! the number of frames refused for exceeding the maximum frame size.
+//! The health of the translator components also answers the `/healthz` and `/readyz` probes.
+
+use serde::Serialize;
+use std::{net::SocketAddr, sync::Arc, time::SystemTime};
+use stratum_apps::{
+    banlist::BanList,
+    connections::ConnectionInfo,
+    custom_mutex::Mutex,
+    monitoring::StatusProvider,
+    network_helpers::{
+        self,
+        traffic::{ConnectionTraffic, TrafficSnapshot},
+    },
+    runtime::{self, TaskInfo, TaskManager},
+    status::{HealthAggregator, HealthReport, HealthTransition},
+    unix_time,
+};
+
+use crate::sv1::sv1_server::data::Sv1ServerData;
+
+/// State of the upstream connection, updated by the translator main loop.
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamHealth {
+    /// Upstream currently connected to, `None` while (re)connecting.
+    pub address: Option<SocketAddr>,
+    /// When the current connection was established, or when it was lost.
+    pub since: Option<SystemTime>,
+    /// Number of times the upstream connection was re-established after being lost.
+    pub reconnections: u32,
+    /// Frame and byte counters of the current connection.
+    pub traffic: Option<Arc<ConnectionTraffic>>,
+}
+
+impl UpstreamHealth {
+    /// Records a successful connection to `address`.
+    pub fn connected(&mut self, address: SocketAddr, traffic: Arc<ConnectionTraffic>) {
+        if self.since.is_some() {
+            self.reconnections += 1;
+        }
+        self.address = Some(address);
+        self.since = Some(SystemTime::now());
+        self.traffic = Some(traffic);
+    }
+
+    /// Records the loss of the upstream connection.
+    pub fn disconnected(&mut self) {
+        self.address = None;
+        self.since = Some(SystemTime::now());
+        self.traffic = None;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct UpstreamStatus {
+    connected: bool,
+    address: Option<String>,
+    since: Option<u64>,
+    reconnections: u32,
+    traffic: Option<TrafficSnapshot>,
+}
+
+#[derive(Debug, Serialize)]
+struct MinerStatus {
+    downstream_id: u32,
+    worker_name: String,
+    address: String,
+    channel_id: Option<u32>,
+    difficulty: f64,
+    estimated_hashrate: Option<f32>,
+    shares_accepted: u64,
+    shares_rejected: u64,
+    last_share_time: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct TranslatorStatus {
+    upstream: UpstreamStatus,
+    miners: Vec<MinerStatus>,
+    connections: Vec<ConnectionInfo>,
+    tasks: Vec<TaskInfo>,
+    health: HealthReport,
+    handshake_timeouts: u64,
+    oversized_frames: u64,
+}
+
+/// [`StatusProvider`] of the translator.
+pub struct TranslatorStatusProvider {
+    sv1_server_data: Arc<Mutex<Sv1ServerData>>,
+    upstream_health: Arc<Mutex<UpstreamHealth>>,
+    health: Arc<HealthAggregator>,
+    task_manager: Arc<TaskManager>,
+    banlist: Arc<BanList>,
+}
+
+impl TranslatorStatusProvider {
+    pub fn new(
+        sv1_server_data: Arc<Mutex<Sv1ServerData>>,
+        upstream_health: Arc<Mutex<UpstreamHealth>>,
+        health: Arc<HealthAggregator>,
+        task_manager: Arc<TaskManager>,
+        banlist: Arc<BanList>,
+    ) -> Self {
+        Self {
+            sv1_server_data,
+            upstream_health,
+            health,
+            task_manager,
+            banlist,
+        }
+    }
+}
+
+impl StatusProvider for TranslatorStatusProvider {
+    fn status(&self) -> serde_json::Value {
+        let upstream = self
+            .upstream_health
+            .super_safe_lock(|health| UpstreamStatus {
+                connected: health.address.is_some(),
+                address: health.address.map(|address| address.to_string()),
+                since: health.since.map(unix_time::secs),
+                reconnections: health.reconnections,
+                traffic: health.traffic.as_ref().map(|traffic| traffic.snapshot()),
+            });
+
+        let (downstreams, connections) = self.sv1_server_data.super_safe_lock(|data| {
+            (
+                data.downstreams.values().cloned().collect::<Vec<_>>(),
+                data.downstreams.list(),
+            )
+        });
+        let mut miners: Vec<MinerStatus> = downstreams
+            .iter()
+            .map(|downstream| {
+                downstream.downstream_data.super_safe_lock(|d| MinerStatus {
+                    downstream_id: d.downstream_id,
+                    worker_name: d.authorized_worker_name.clone(),
+                    address: d.address.to_string(),
+                    channel_id: d.channel_id,
+                    difficulty: d.target.difficulty_float(),
+                    estimated_hashrate: d.hashrate,
+                    shares_accepted: d.shares_accepted.get(),
+                    shares_rejected: d.shares_rejected.get(),
+                    last_share_time: d.last_share_time.get().map(unix_time::secs),
+                })
+            })
+            .collect();
+        miners.sort_by_key(|miner| miner.downstream_id);
+
+        let tasks = self.task_manager.running_tasks();
+
+        serde_json::to_value(TranslatorStatus {
+            upstream,
+            miners,
+            connections,
+            tasks,
+            health: self.health.snapshot(),
+            handshake_timeouts: runtime::handshake_timeouts(),
+            oversized_frames: network_helpers::oversized_frames(),
+        })
+        .expect("translator status is always serializable")
+    }
+
+    fn health(&self) -> HealthReport {
+        self.health.snapshot()
+    }
+
+    fn health_transitions(&self) -> Vec<HealthTransition> {
+        self.health.transitions()
+    }
+
+    fn banlist(&self) -> Option<&BanList> {
+        Some(&self.banlist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upstream_health_counts_reconnections() {
+        let address: SocketAddr = "127.0.0.1:34254".parse().unwrap();
+        let mut health = UpstreamHealth::default();
+        health.connected(address, Arc::new(ConnectionTraffic::new()));
+        assert_eq!(health.address, Some(address));
+        assert_eq!(health.reconnections, 0);
+        assert!(health.traffic.is_some());
+
+        health.disconnected();
+        assert!(health.address.is_none());
+        assert!(health.traffic.is_none());
+        health.connected(address, Arc::new(ConnectionTraffic::new()));
+        assert_eq!(health.reconnections, 1);
+    }
+
+    #[test]
+    fn test_status_without_miners() {
+        let provider = TranslatorStatusProvider::new(
+            Arc::new(Mutex::new(Sv1ServerData::new(true))),
+            Arc::new(Mutex::new(UpstreamHealth::default())),
+            Arc::new(HealthAggregator::new()),
+            Arc::new(TaskManager::new()),
+            Arc::new(BanList::new(&Default::default()).unwrap()),
+        );
+        let status = provider.status();
+        assert_eq!(status["upstream"]["connected"], false);
+        assert_eq!(status["upstream"]["traffic"], serde_json::Value::Null);
+        assert_eq!(status["miners"], serde_json::json!([]));
+        assert_eq!(status["connections"], serde_json::json!([]));
+        assert_eq!(status["tasks"], serde_json::json!([]));
+        assert_eq!(status["health"]["state"], "healthy");
+        assert_eq!(status["health"]["ready"], false);
+        assert!(!provider.health().is_ready());
+    }
+
+    #[tokio::test]
+    async fn test_status_lists_running_tasks() {
+        let task_manager = Arc::new(TaskManager::new());
+        task_manager.spawn_critical("channel_manager", std::future::pending::<()>());
+        let provider = TranslatorStatusProvider::new(
+            Arc::new(Mutex::new(Sv1ServerData::new(true))),
+            Arc::new(Mutex::new(UpstreamHealth::default())),
+            Arc::new(HealthAggregator::new()),
+            task_manager.clone(),
+            Arc::new(BanList::new(&Default::default()).unwrap()),
+        );
+
+        let tasks = provider.status()["tasks"].clone();
+        assert_eq!(tasks[0]["name"], "channel_manager");
+        assert_eq!(tasks[0]["supervision"], "critical");
+        assert_eq!(tasks[0]["restarts"], 0);
+        task_manager.abort_all().await;
+    }
+}
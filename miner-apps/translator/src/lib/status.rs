@@ -6,6 +6,7 @@
 //! Each task wraps its report in a [`Status`] and sends it over an async channel,
 //! tagged with a [`Sender`] variant that identifies the source subsystem.
 
+use stratum_apps::runtime::{TaskManager, TaskPanic};
 use tracing::{debug, error, warn};
 
 use crate::error::TproxyError;
@@ -70,6 +71,8 @@ pub enum State {
     ChannelManagerShutdown(TproxyError),
     /// Upstream SV2 connection closed or failed.
     UpstreamShutdown(TproxyError),
+    /// A task panicked, see [`TaskManager::on_panic`].
+    TaskPanicked(TaskPanic),
 }
 
 /// A message reporting the current [`State`] of a component.
@@ -115,3 +118,17 @@ pub async fn handle_error(sender: &StatusSender, e: TproxyError) {
     error!("Error in {:?}: {:?}", sender, e);
     send_status(sender, e).await;
 }
+
+/// Forwards the panics caught in the tasks of `task_manager` to the main loop, as
+/// [`State::TaskPanicked`] statuses sent on `tx`.
+pub fn report_task_panics(task_manager: &TaskManager, tx: async_channel::Sender<Status>) {
+    task_manager.on_panic(move |panic| {
+        let tx = tx.clone();
+        async move {
+            let state = State::TaskPanicked(panic);
+            if let Err(e) = tx.send(Status { state }).await {
+                error!("Failed to send task panic status: {e:?}");
+            }
+        }
+    });
+}
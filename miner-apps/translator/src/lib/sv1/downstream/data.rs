@@ -1,23 +1,30 @@
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
+    net::SocketAddr,
     sync::{atomic::AtomicBool, Arc},
+    time::SystemTime,
 };
 use stratum_apps::{
     custom_mutex::Mutex,
+    persistence::{Persistence, Sv1ShareEvent},
+    rate_limit::TokenBucket,
     stratum_core::{
         bitcoin::Target,
-        sv1_api::{json_rpc, utils::HexU32Be},
+        channels_sv2::target::bytes_to_hex,
+        sv1_api::{client_to_server, json_rpc, utils::HexU32Be},
     },
 };
-use tracing::debug;
+use tracing::{debug, field, info_span, Span};
 
 use super::SubmitShareWithChannelId;
-use crate::sv1::sv1_server::data::Sv1ServerData;
+use crate::{config::Sv1LimitsConfig, error::TproxyError, sv1::sv1_server::data::Sv1ServerData};
 
 #[derive(Debug)]
 pub struct DownstreamData {
     pub channel_id: Option<u32>,
     pub downstream_id: u32,
+    // Address the miner connected from
+    pub address: SocketAddr,
     pub extranonce1: Vec<u8>,
     pub extranonce2_len: usize,
     pub version_rolling_mask: Option<HexU32Be>,
@@ -44,18 +51,46 @@ pub struct DownstreamData {
     pub sv1_server_data: Arc<Mutex<Sv1ServerData>>,
     // Tracks the upstream target for this downstream, used for vardiff target comparison
     pub upstream_target: Option<Target>,
+    // Difficulty suggested by the miner via `mining.suggest_difficulty`, used to pick the
+    // initial target of the channel
+    pub suggested_difficulty: Option<f64>,
+    // Whether the miner sent `mining.extranonce.subscribe`, to be sent its new `extranonce1` in a
+    // `mining.set_extranonce` when the upstream changes the extranonce prefix of its channel
+    pub extranonce_subscribed: Cell<bool>,
+    // Shares that passed (or failed) the translator's validation, and when the last one was
+    // submitted. Updated from `handle_submit`, which only gets a shared reference.
+    pub shares_accepted: Cell<u64>,
+    pub shares_rejected: Cell<u64>,
+    pub last_share_time: Cell<Option<SystemTime>>,
+    // Log every share validated by `handle_submit` is recorded to, before it is sent upstream
+    pub persistence: Persistence,
+    // Messages of the miner over `message_limiter` are dropped, and the miner is disconnected
+    // once more than `max_rate_limit_violations` consecutive ones were
+    pub message_limiter: TokenBucket,
+    pub rate_limit_violations: u32,
+    pub max_rate_limit_violations: u32,
+    // Consecutive messages of the miner that are not valid SV1 requests, the miner is
+    // disconnected once there are more than `max_malformed_messages`
+    pub malformed_messages: u32,
+    pub max_malformed_messages: u32,
+    // Span of the tasks of this miner, its user identity and channel id are recorded once known
+    pub span: Span,
 }
 
 impl DownstreamData {
     pub fn new(
         downstream_id: u32,
+        address: SocketAddr,
         target: Target,
         hashrate: Option<f32>,
         sv1_server_data: Arc<Mutex<Sv1ServerData>>,
+        persistence: Persistence,
+        limits: &Sv1LimitsConfig,
     ) -> Self {
         DownstreamData {
             channel_id: None,
             downstream_id,
+            address,
             extranonce1: vec![0; 8],
             extranonce2_len: 4,
             version_rolling_mask: None,
@@ -75,9 +110,82 @@ impl DownstreamData {
             pending_share: RefCell::new(None),
             sv1_server_data,
             upstream_target: None,
+            suggested_difficulty: None,
+            extranonce_subscribed: Cell::new(false),
+            shares_accepted: Cell::new(0),
+            shares_rejected: Cell::new(0),
+            last_share_time: Cell::new(None),
+            persistence,
+            message_limiter: TokenBucket::new(limits.messages),
+            rate_limit_violations: 0,
+            max_rate_limit_violations: limits.max_violations,
+            malformed_messages: 0,
+            max_malformed_messages: limits.max_malformed_messages,
+            span: info_span!(
+                "downstream",
+                connection_id = downstream_id,
+                user_identity = field::Empty,
+                channel_id = field::Empty,
+            ),
         }
     }
 
+    /// Consumes a token of the message rate limit of the miner.
+    ///
+    /// Returns `false` if the message is over the limit and must be dropped, and an error once
+    /// the miner has been over its limit too many times in a row.
+    pub fn check_rate_limit(&mut self) -> Result<bool, TproxyError> {
+        if self.message_limiter.try_acquire() {
+            self.rate_limit_violations = 0;
+            return Ok(true);
+        }
+        self.rate_limit_violations += 1;
+        if self.rate_limit_violations > self.max_rate_limit_violations {
+            return Err(TproxyError::RateLimitExceeded);
+        }
+        Ok(false)
+    }
+
+    /// Counts a message of the miner that is not a valid SV1 request, returns an error once too
+    /// many were received in a row.
+    pub fn malformed_message(&mut self) -> Result<(), TproxyError> {
+        self.malformed_messages += 1;
+        if self.malformed_messages > self.max_malformed_messages {
+            return Err(TproxyError::MalformedMessages);
+        }
+        Ok(())
+    }
+
+    /// Records a share of the miner validated on `channel_id` to the share log, rejected with
+    /// `rejection` if set.
+    pub fn record_share(
+        &self,
+        share: &client_to_server::Submit<'static>,
+        channel_id: u32,
+        rejection: Option<String>,
+    ) {
+        if !self.persistence.is_enabled() {
+            return;
+        }
+        let mut extranonce = self.extranonce1.clone();
+        extranonce.extend_from_slice(share.extra_nonce2.0.as_ref());
+        self.persistence.record(Sv1ShareEvent {
+            client: self.address.to_string(),
+            downstream_id: self.downstream_id,
+            worker: self.authorized_worker_name.clone(),
+            channel_id,
+            sv1_job_id: share.job_id.clone(),
+            // The SV1 job ids are the ids of the SV2 jobs they are translated from.
+            job_id: share.job_id.parse().ok(),
+            extranonce: bytes_to_hex(&extranonce),
+            nonce: share.nonce.0,
+            ntime: share.time.0,
+            version_bits: share.version_bits.as_ref().map(|bits| bits.0),
+            accepted: rejection.is_none(),
+            error: rejection,
+        });
+    }
+
     pub fn set_pending_target(&mut self, new_target: Target) {
         self.pending_target = Some(new_target);
         debug!("Downstream {}: Set pending target", self.downstream_id);
@@ -96,3 +204,53 @@ impl DownstreamData {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_apps::rate_limit::TokenBucketConfig;
+
+    fn downstream_data(limits: &Sv1LimitsConfig) -> DownstreamData {
+        DownstreamData::new(
+            1,
+            "127.0.0.1:4000".parse().unwrap(),
+            Target::MAX,
+            None,
+            Arc::new(Mutex::new(Sv1ServerData::new(true))),
+            Persistence::disabled(),
+            limits,
+        )
+    }
+
+    #[test]
+    fn disconnects_after_too_many_messages_over_the_rate_limit() {
+        let limits = Sv1LimitsConfig {
+            messages: TokenBucketConfig::new(0.0, 2),
+            max_violations: 1,
+            ..Default::default()
+        };
+        let mut data = downstream_data(&limits);
+        assert!(data.check_rate_limit().unwrap());
+        assert!(data.check_rate_limit().unwrap());
+        assert!(!data.check_rate_limit().unwrap());
+        assert!(matches!(
+            data.check_rate_limit(),
+            Err(TproxyError::RateLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn disconnects_after_too_many_malformed_messages() {
+        let limits = Sv1LimitsConfig {
+            max_malformed_messages: 2,
+            ..Default::default()
+        };
+        let mut data = downstream_data(&limits);
+        assert!(data.malformed_message().is_ok());
+        assert!(data.malformed_message().is_ok());
+        assert!(matches!(
+            data.malformed_message(),
+            Err(TproxyError::MalformedMessages)
+        ));
+    }
+}
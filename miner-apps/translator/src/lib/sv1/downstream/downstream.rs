@@ -1,18 +1,23 @@
 use super::DownstreamMessages;
 use crate::{
+    config::Sv1LimitsConfig,
     error::TproxyError,
     status::{handle_error, StatusSender},
     sv1::{
         downstream::{channel::DownstreamChannelState, data::DownstreamData},
         sv1_server::data::Sv1ServerData,
     },
-    task_manager::TaskManager,
-    utils::ShutdownMessage,
+    utils::{parse_suggested_difficulty, ShutdownMessage},
 };
 use async_channel::{Receiver, Sender};
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use stratum_apps::{
+    banlist::{BanList, BanTarget},
+    connections::ConnectionMetadata,
     custom_mutex::Mutex,
+    network_helpers::sv1_connection::ConnectionViolation,
+    persistence::Persistence,
+    runtime::TaskManager,
     stratum_core::{
         bitcoin::Target,
         sv1_api::{
@@ -22,7 +27,7 @@ use stratum_apps::{
     },
 };
 use tokio::sync::{broadcast, mpsc};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, warn, Instrument};
 
 /// Represents a downstream SV1 miner connection.
 ///
@@ -41,6 +46,11 @@ use tracing::{debug, error, info, warn};
 pub struct Downstream {
     pub downstream_data: Arc<Mutex<DownstreamData>>,
     downstream_channel_state: DownstreamChannelState,
+    // Limit of the connection broken by the miner, if it was closed for breaking one
+    violation: ConnectionViolation,
+    // Bans the address of a miner disconnected for breaking its limits, if `ban_duration` is set
+    banlist: Arc<BanList>,
+    ban_duration: Option<Duration>,
 }
 
 impl Downstream {
@@ -48,6 +58,7 @@ impl Downstream {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         downstream_id: u32,
+        address: SocketAddr,
         downstream_sv1_sender: Sender<json_rpc::Message>,
         downstream_sv1_receiver: Receiver<json_rpc::Message>,
         sv1_server_sender: Sender<DownstreamMessages>,
@@ -55,12 +66,19 @@ impl Downstream {
         target: Target,
         hashrate: Option<f32>,
         sv1_server_data: Arc<Mutex<Sv1ServerData>>,
+        persistence: Persistence,
+        limits: &Sv1LimitsConfig,
+        violation: ConnectionViolation,
+        banlist: Arc<BanList>,
     ) -> Self {
         let downstream_data = Arc::new(Mutex::new(DownstreamData::new(
             downstream_id,
+            address,
             target,
             hashrate,
             sv1_server_data,
+            persistence,
+            limits,
         )));
         let downstream_channel_state = DownstreamChannelState::new(
             downstream_sv1_sender,
@@ -71,6 +89,9 @@ impl Downstream {
         Self {
             downstream_data,
             downstream_channel_state,
+            violation,
+            banlist,
+            ban_duration: limits.ban_duration(),
         }
     }
 
@@ -97,7 +118,9 @@ impl Downstream {
             .sv1_server_receiver
             .resubscribe();
         let mut shutdown_rx = notify_shutdown.subscribe();
-        let downstream_id = self.downstream_data.super_safe_lock(|d| d.downstream_id);
+        let (downstream_id, span) = self
+            .downstream_data
+            .super_safe_lock(|d| (d.downstream_id, d.span.clone()));
         task_manager.spawn(async move {
             loop {
                 tokio::select! {
@@ -115,10 +138,6 @@ impl Downstream {
                                 info!("All downstream shutdown message received");
                                 break;
                             }
-                            Ok(ShutdownMessage::UpstreamReconnectedResetAndShutdownDownstreams) => {
-                                info!("All downstream shutdown message received (upstream reconnected)");
-                                break;
-                            }
                             Ok(_) => {
                                 // shutdown for other downstream
                             }
@@ -133,6 +152,7 @@ impl Downstream {
                     res = Self::handle_downstream_message(self.clone()) => {
                         if let Err(e) = res {
                             error!("Downstream {downstream_id}: error in downstream message handler: {e:?}");
+                            self.ban_if_misbehaving(&e);
                             handle_error(&status_sender, e).await;
                             break;
                         }
@@ -157,7 +177,30 @@ impl Downstream {
             warn!("Downstream {downstream_id}: unified task shutting down");
             self.downstream_channel_state.drop();
             drop(shutdown_complete_tx);
-        });
+        }.instrument(span));
+    }
+
+    // Bans the address of the miner for `ban_duration` if it is disconnected on `error` for
+    // breaking one of its limits.
+    fn ban_if_misbehaving(&self, error: &TproxyError) {
+        let reason = match (error, self.violation.get()) {
+            (TproxyError::RateLimitExceeded | TproxyError::MalformedMessages, _) => {
+                error.to_string()
+            }
+            (_, Some(violation)) => violation.to_string(),
+            (_, None) => return,
+        };
+        let Some(ban_duration) = self.ban_duration else {
+            return;
+        };
+        let ip = self.downstream_data.super_safe_lock(|d| d.address.ip());
+        info!("Banning {ip} for {ban_duration:?}: {reason}");
+        if let Err(e) = self
+            .banlist
+            .ban(BanTarget::Ip(ip.into()), Some(ban_duration), Some(reason))
+        {
+            warn!(error = ?e, "Failed to save the ban of {ip}");
+        }
     }
 
     /// Handles messages received from the SV1 server.
@@ -172,6 +215,8 @@ impl Downstream {
     /// - For `mining.set_difficulty`: Always caches the message (never sent immediately)
     /// - For `mining.notify`: Sends any pending set_difficulty first, then forwards the notify
     /// - For other messages: Forwards directly to the miner
+    /// - For `mining.set_extranonce`: Forwards directly to the miner, even before handshake
+    ///   completion
     /// - Caches both `mining.set_difficulty` and `mining.notify` messages if handshake is not yet
     ///   complete
     /// - On handshake completion: sends cached messages in correct order (set_difficulty first,
@@ -314,6 +359,23 @@ impl Downstream {
                                     d.last_job_version_field = Some(notify.version.0);
                                 });
                             }
+                            "mining.set_extranonce" => {
+                                // Only sent once the channel is open, the answer to a
+                                // `mining.subscribe` holding the previous `extranonce1` may
+                                // already be out
+                                debug!("Down: SV1 handshake not complete, sending mining.set_extranonce");
+                                self.downstream_channel_state
+                                    .downstream_sv1_sender
+                                    .send(message.clone())
+                                    .await
+                                    .map_err(|e| {
+                                        error!(
+                                            "Down: Failed to send mining.set_extranonce to downstream: {:?}",
+                                            e
+                                        );
+                                        TproxyError::ChannelErrorSender
+                                    })?;
+                            }
                             _ => {
                                 debug!(
                                     "Down: SV1 handshake not complete, skipping other notification"
@@ -356,12 +418,17 @@ impl Downstream {
     /// - `mining.subscribe` - Subscription requests
     /// - `mining.authorize` - Authorization requests
     /// - `mining.submit` - Share submissions
+    /// - `mining.suggest_difficulty` - Initial difficulty suggestions
+    /// - `mining.extranonce.subscribe` - Subscriptions to extranonce changes
     /// - Other SV1 protocol messages
     ///
     /// The method delegates message processing to the downstream data handler,
     /// which implements the SV1 protocol logic and generates appropriate responses.
     /// Responses are sent back to the miner, while share submissions are forwarded
     /// to the SV1 server for upstream processing.
+    ///
+    /// Messages over the rate limit of the miner and requests the handler fails on are dropped,
+    /// an error is returned once the miner sent too many of either in a row.
     pub async fn handle_downstream_message(self: Arc<Self>) -> Result<(), TproxyError> {
         let message = match self
             .downstream_channel_state
@@ -376,12 +443,38 @@ impl Downstream {
             }
         };
 
-        // Check if channel is established
-        let channel_established = self
+        if !self
             .downstream_data
-            .super_safe_lock(|d| d.channel_id.is_some());
+            .super_safe_lock(|d| d.check_rate_limit())?
+        {
+            warn!("Down: Message rate limit hit, dropping message");
+            return Ok(());
+        }
+
+        // `mining.suggest_difficulty` is handled here since it has to be known before the
+        // channel is opened, and is never queued. Neither is `mining.extranonce.subscribe`,
+        // which is answered right away.
+        if let Message::StandardRequest(request) = &message {
+            if request.method == "mining.suggest_difficulty" {
+                return self.handle_suggest_difficulty(request).await;
+            }
+            if request.method == "mining.extranonce.subscribe" {
+                return self.handle_extranonce_subscribe(request).await;
+            }
+        }
+
+        // Check if channel is established. Once the handshake is complete, the channel is only
+        // missing while it is reopened on a new upstream: the messages are handled right away,
+        // the shares being rejected until the channel is back.
+        let (channel_established, handshake_complete) = self.downstream_data.super_safe_lock(|d| {
+            (
+                d.channel_id.is_some(),
+                d.sv1_handshake_complete
+                    .load(std::sync::atomic::Ordering::SeqCst),
+            )
+        });
 
-        if !channel_established {
+        if !channel_established && !handshake_complete {
             // Check if this is the first message (queue is empty) and send OpenChannel request
             let is_first_message = self
                 .downstream_data
@@ -449,10 +542,15 @@ impl Downstream {
                 // Message was handled but no response needed
             }
             Err(e) => {
-                error!("Down: Error handling downstream message: {:?}", e);
-                return Err(e.into());
+                // Broken firmware is only disconnected once it keeps sending invalid requests
+                warn!("Down: Dropping invalid downstream message: {:?}", e);
+                return self
+                    .downstream_data
+                    .super_safe_lock(|d| d.malformed_message());
             }
         }
+        self.downstream_data
+            .super_safe_lock(|d| d.malformed_messages = 0);
 
         // Check if there's a pending share to send to the Sv1Server
         let pending_share = self
@@ -472,6 +570,81 @@ impl Downstream {
         Ok(())
     }
 
+    /// Handles a `mining.suggest_difficulty` request from the miner.
+    ///
+    /// The suggested difficulty is stored and used by the SV1 server to pick the initial target
+    /// when opening the channel for this downstream. Suggestions received once the channel is
+    /// open are left to vardiff.
+    async fn handle_suggest_difficulty(
+        self: &Arc<Self>,
+        request: &json_rpc::StandardRequest,
+    ) -> Result<(), TproxyError> {
+        let difficulty = parse_suggested_difficulty(request);
+        let (downstream_id, channel_open) = self.downstream_data.super_safe_lock(|d| {
+            if d.channel_id.is_none() {
+                if let Some(difficulty) = difficulty {
+                    d.suggested_difficulty = Some(difficulty);
+                }
+            }
+            (d.downstream_id, d.channel_id.is_some())
+        });
+        match (difficulty, channel_open) {
+            (Some(difficulty), false) => {
+                info!("Down: Downstream {downstream_id} suggested difficulty {difficulty}");
+            }
+            (Some(difficulty), true) => {
+                debug!(
+                    "Down: Ignoring difficulty {difficulty} suggested by downstream {downstream_id} after channel opening"
+                );
+            }
+            (None, _) => {
+                warn!(
+                    "Down: Invalid mining.suggest_difficulty from downstream {downstream_id}: {:?}",
+                    request.params
+                );
+            }
+        }
+
+        let response = json_rpc::Response {
+            id: request.id,
+            error: None,
+            result: serde_json::Value::Bool(difficulty.is_some()),
+        };
+        self.downstream_channel_state
+            .downstream_sv1_sender
+            .send(response.into())
+            .await
+            .map_err(|e| {
+                error!("Down: Failed to send message to downstream: {:?}", e);
+                TproxyError::ChannelErrorSender
+            })
+    }
+
+    /// Handles a `mining.extranonce.subscribe` request from the miner.
+    ///
+    /// The miner is then sent its new `extranonce1` in a `mining.set_extranonce` when the
+    /// upstream changes the extranonce prefix of its channel, instead of being disconnected.
+    async fn handle_extranonce_subscribe(
+        self: &Arc<Self>,
+        request: &json_rpc::StandardRequest,
+    ) -> Result<(), TproxyError> {
+        self.downstream_data
+            .super_safe_lock(|d| d.handle_extranonce_subscribe());
+        let response = json_rpc::Response {
+            id: request.id,
+            error: None,
+            result: serde_json::Value::Bool(true),
+        };
+        self.downstream_channel_state
+            .downstream_sv1_sender
+            .send(response.into())
+            .await
+            .map_err(|e| {
+                error!("Down: Failed to send message to downstream: {:?}", e);
+                TproxyError::ChannelErrorSender
+            })
+    }
+
     /// Handles SV1 handshake completion after mining.authorize.
     ///
     /// This method is called when the downstream completes the SV1 handshake
@@ -529,3 +702,10 @@ impl Downstream {
         Ok(())
     }
 }
+
+impl ConnectionMetadata for Downstream {
+    fn channels(&self) -> Vec<u32> {
+        self.downstream_data
+            .super_safe_lock(|d| d.channel_id.into_iter().collect())
+    }
+}
@@ -1,3 +1,4 @@
+use std::time::SystemTime;
 use stratum_apps::stratum_core::sv1_api::{
     client_to_server, json_rpc, server_to_client,
     utils::{Extranonce, HexU32Be},
@@ -7,7 +8,7 @@ use tracing::{debug, error, info, warn};
 
 use crate::{
     sv1::downstream::{data::DownstreamData, SubmitShareWithChannelId},
-    utils::validate_sv1_share,
+    utils::{validate_sv1_share, BIP320_VERSION_ROLLING_MASK},
 };
 
 // Implements `IsServer` for `Downstream` to handle the Sv1 messages.
@@ -18,20 +19,31 @@ impl IsServer<'static> for DownstreamData {
     ) -> (Option<server_to_client::VersionRollingParams>, Option<bool>) {
         info!("Received mining.configure from Sv1 downstream");
         debug!("Down: Handling mining.configure: {:?}", request);
+        // Only the BIP320 general purpose bits can be rolled on SV2 extended channels, shares
+        // rolling any other bit are rejected in `validate_sv1_share`.
         self.version_rolling_mask = request
             .version_rolling_mask()
-            .map(|mask| HexU32Be(mask & 0x1FFFE000));
+            .map(|mask| HexU32Be(mask & BIP320_VERSION_ROLLING_MASK));
         self.version_rolling_min_bit = request.version_rolling_min_bit_count();
 
-        debug!(
-            "Negotiated version_rolling_mask is {:?}",
-            self.version_rolling_mask
-        );
+        let Some(mask) = self.version_rolling_mask.clone() else {
+            debug!("Downstream did not request the version-rolling extension");
+            return (None, Some(false));
+        };
+        let min_bit_count = self.version_rolling_min_bit.clone().unwrap_or(HexU32Be(0));
+        if mask.0.count_ones() < min_bit_count.0 {
+            warn!(
+                "Negotiated version_rolling_mask {:#010x} has fewer bits than the requested min-bit-count {}",
+                mask.0, min_bit_count.0
+            );
+        }
+
+        debug!("Negotiated version_rolling_mask is {:#010x}", mask.0);
         (
-            Some(server_to_client::VersionRollingParams::new(
-                self.version_rolling_mask.clone().unwrap_or(HexU32Be(0)),
-                self.version_rolling_min_bit.clone().unwrap_or(HexU32Be(0)),
-            ).expect("Version mask invalid, automatic version mask selection not supported, please change it in crate::downstream::mod.rs")),
+            Some(
+                server_to_client::VersionRollingParams::new(mask, min_bit_count)
+                    .expect("Version mask is restricted to BIP320 bits, it is always a valid mask"),
+            ),
             Some(false),
         )
     }
@@ -65,19 +77,32 @@ impl IsServer<'static> for DownstreamData {
                 "Received mining.submit from SV1 downstream for channel id: {}",
                 channel_id
             );
-            let is_valid_share = validate_sv1_share(
+            let rejection = match validate_sv1_share(
                 request,
                 self.target,
                 self.extranonce1.clone(),
                 self.version_rolling_mask.clone(),
                 self.sv1_server_data.clone(),
                 channel_id,
-            )
-            .unwrap_or(false);
-            if !is_valid_share {
+            ) {
+                Ok(true) => None,
+                Ok(false) => Some("Share does not meet the target of the miner".to_string()),
+                Err(e) => {
+                    error!(
+                        "Share validation failed for channel id {}: {}",
+                        channel_id, e
+                    );
+                    Some(e.to_string())
+                }
+            };
+            self.last_share_time.set(Some(SystemTime::now()));
+            self.record_share(request, channel_id, rejection.clone());
+            if rejection.is_some() {
                 error!("Invalid share for channel id: {}", channel_id);
+                self.shares_rejected.set(self.shares_rejected.get() + 1);
                 return false;
             }
+            self.shares_accepted.set(self.shares_accepted.get() + 1);
             let to_send: SubmitShareWithChannelId = SubmitShareWithChannelId {
                 channel_id,
                 downstream_id: self.downstream_id,
@@ -97,7 +122,10 @@ impl IsServer<'static> for DownstreamData {
     }
 
     /// Indicates to the server that the client supports the mining.set_extranonce method.
-    fn handle_extranonce_subscribe(&self) {}
+    fn handle_extranonce_subscribe(&self) {
+        info!("Received mining.extranonce.subscribe from Sv1 downstream");
+        self.extranonce_subscribed.set(true);
+    }
 
     /// Checks if a Downstream role is authorized.
     fn is_authorized(&self, name: &str) -> bool {
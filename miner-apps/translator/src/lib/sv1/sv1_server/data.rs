@@ -1,13 +1,24 @@
 use crate::sv1::downstream::downstream::Downstream;
 use std::{
     collections::HashMap,
-    sync::{atomic::AtomicU32, Arc, RwLock},
+    sync::{Arc, RwLock},
 };
-use stratum_apps::stratum_core::{
-    bitcoin::Target, channels_sv2::vardiff::classic::VardiffState, mining_sv2::SetNewPrevHash,
-    sv1_api::server_to_client,
+use stratum_apps::{
+    connections::ConnectionRegistry,
+    stratum_core::{
+        bitcoin::Target, channels_sv2::vardiff::classic::VardiffState, mining_sv2::SetNewPrevHash,
+        sv1_api::server_to_client,
+    },
 };
 
+/// A job sent to the Sv1 downstreams, kept around to validate the shares submitted for it.
+#[derive(Debug, Clone)]
+pub struct Sv1Job {
+    pub notify: server_to_client::Notify<'static>,
+    /// Whether the upstream allows rolling the BIP320 version bits on this job.
+    pub version_rolling_allowed: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct PendingTargetUpdate {
     pub downstream_id: u32,
@@ -17,14 +28,14 @@ pub struct PendingTargetUpdate {
 
 #[derive(Debug)]
 pub struct Sv1ServerData {
-    pub downstreams: HashMap<u32, Arc<Downstream>>,
+    /// Connected Sv1 downstreams, registered under the ids allocated by the registry
+    pub downstreams: ConnectionRegistry<u32, Arc<Downstream>>,
     pub vardiff: HashMap<u32, Arc<RwLock<VardiffState>>>,
     pub prevhash: Option<SetNewPrevHash<'static>>,
-    pub downstream_id_factory: AtomicU32,
     /// Job storage for aggregated mode - all Sv1 downstreams share the same jobs
-    pub aggregated_valid_jobs: Option<Vec<server_to_client::Notify<'static>>>,
+    pub aggregated_valid_jobs: Option<Vec<Sv1Job>>,
     /// Job storage for non-aggregated mode - each Sv1 downstream has its own jobs
-    pub non_aggregated_valid_jobs: Option<HashMap<u32, Vec<server_to_client::Notify<'static>>>>,
+    pub non_aggregated_valid_jobs: Option<HashMap<u32, Vec<Sv1Job>>>,
     /// Tracks pending target updates that are waiting for SetTarget response from upstream
     pub pending_target_updates: Vec<PendingTargetUpdate>,
     /// The initial target used when opening channels - used when no downstreams remain
@@ -34,10 +45,9 @@ pub struct Sv1ServerData {
 impl Sv1ServerData {
     pub fn new(aggregate_channels: bool) -> Self {
         Self {
-            downstreams: HashMap::new(),
+            downstreams: ConnectionRegistry::new(0),
             vardiff: HashMap::new(),
             prevhash: None,
-            downstream_id_factory: AtomicU32::new(0),
             aggregated_valid_jobs: aggregate_channels.then(Vec::new),
             non_aggregated_valid_jobs: (!aggregate_channels).then(HashMap::new),
             pending_target_updates: Vec::new(),
@@ -74,7 +74,7 @@ impl DifficultyManager {
                         Ok(ShutdownMessage::DownstreamShutdownAll) => {
                             sv1_server_data.super_safe_lock(|d|{
                                 d.vardiff = HashMap::new();
-                                d.downstreams = HashMap::new();
+                                d.downstreams.clear();
                             });
                             info!("🔌 All downstreams removed from sv1 server as upstream changed");
 
@@ -85,20 +85,6 @@ impl DifficultyManager {
                                 is_aggregated,
                             ).await;
                         }
-                        Ok(ShutdownMessage::UpstreamReconnectedResetAndShutdownDownstreams) => {
-                            sv1_server_data.super_safe_lock(|d|{
-                                d.vardiff = HashMap::new();
-                                d.downstreams = HashMap::new();
-                            });
-                            info!("🔌 All downstreams removed from sv1 server as upstream reconnected");
-
-                            // In aggregated mode, send UpdateChannel to reflect the new state (no downstreams)
-                            Self::send_update_channel_on_downstream_state_change(
-                                &sv1_server_data,
-                                &channel_manager_sender,
-                                is_aggregated,
-                            ).await;
-                        }
                         _ => {}
                     }
                 }
@@ -1,4 +1,5 @@
 pub(super) mod channel;
 pub mod data;
 pub mod difficulty_manager;
+pub mod notify_coalescer;
 pub mod sv1_server;
@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use stratum_apps::stratum_core::sv1_api::server_to_client::Notify;
+
+/// Coalesces the `mining.notify` updates of each channel, for farms on low-bandwidth links.
+///
+/// A job that does not clean the previous ones (e.g. a fee-bump of the same block template) is
+/// held back if the channel was sent a job less than `interval` ago, replacing any job already
+/// held, and is only forwarded once the interval has elapsed. Clean jobs are always forwarded
+/// right away, dropping the job held for their channel.
+#[derive(Debug)]
+pub struct NotifyCoalescer {
+    interval: Duration,
+    channels: HashMap<u32, ChannelNotifies>,
+}
+
+#[derive(Debug)]
+struct ChannelNotifies {
+    last_sent: Instant,
+    held: Option<Notify<'static>>,
+}
+
+impl NotifyCoalescer {
+    /// Creates a coalescer forwarding at most one non-clean job per `interval` on each channel.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Returns `notify` if it is to be forwarded to `channel_id` now, holds it back otherwise.
+    pub fn offer(
+        &mut self,
+        channel_id: u32,
+        notify: Notify<'static>,
+        now: Instant,
+    ) -> Option<Notify<'static>> {
+        match self.channels.get_mut(&channel_id) {
+            Some(channel) if !notify.clean_jobs && now < channel.last_sent + self.interval => {
+                channel.held = Some(notify);
+                None
+            }
+            _ => {
+                self.channels.insert(
+                    channel_id,
+                    ChannelNotifies {
+                        last_sent: now,
+                        held: None,
+                    },
+                );
+                Some(notify)
+            }
+        }
+    }
+
+    /// Returns when the next held job is due, if any job is held.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.channels
+            .values()
+            .filter(|channel| channel.held.is_some())
+            .map(|channel| channel.last_sent + self.interval)
+            .min()
+    }
+
+    /// Drops the held jobs, and forgets when a job was last forwarded to each channel.
+    pub fn clear(&mut self) {
+        self.channels.clear();
+    }
+
+    /// Takes the held jobs due by `now`, with the channel each is for.
+    pub fn take_due(&mut self, now: Instant) -> Vec<(u32, Notify<'static>)> {
+        let interval = self.interval;
+        self.channels
+            .iter_mut()
+            .filter(|(_, channel)| now >= channel.last_sent + interval)
+            .filter_map(|(channel_id, channel)| {
+                let notify = channel.held.take()?;
+                channel.last_sent = now;
+                Some((*channel_id, notify))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_apps::stratum_core::sv1_api::json_rpc;
+
+    fn notify(job_id: &str, clean_jobs: bool) -> Notify<'static> {
+        let message: json_rpc::Message = serde_json::from_value(serde_json::json!({
+            "method": "mining.notify",
+            "params": [
+                job_id,
+                "00".repeat(32),
+                "01000000",
+                "ffffffff",
+                [],
+                "20000000",
+                "1d00ffff",
+                "6553f100",
+                clean_jobs,
+            ],
+        }))
+        .unwrap();
+        let json_rpc::Message::Notification(notification) = message else {
+            panic!("not a notification");
+        };
+        Notify::try_from(notification).unwrap()
+    }
+
+    #[test]
+    fn holds_back_the_last_non_clean_job_of_the_interval() {
+        let mut coalescer = NotifyCoalescer::new(Duration::from_secs(10));
+        let start = Instant::now();
+        assert!(coalescer.offer(1, notify("1", true), start).is_some());
+        assert!(coalescer
+            .offer(1, notify("2", false), start + Duration::from_secs(1))
+            .is_none());
+        assert!(coalescer
+            .offer(1, notify("3", false), start + Duration::from_secs(2))
+            .is_none());
+        // Other channels are not held back by the jobs of the first one
+        assert!(coalescer.offer(2, notify("4", false), start).is_some());
+        assert_eq!(coalescer.next_due(), Some(start + Duration::from_secs(10)));
+
+        assert!(coalescer
+            .take_due(start + Duration::from_secs(9))
+            .is_empty());
+        let due = coalescer.take_due(start + Duration::from_secs(10));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0, 1);
+        assert_eq!(due[0].1.job_id, "3");
+        assert_eq!(coalescer.next_due(), None);
+    }
+
+    #[test]
+    fn forwards_clean_jobs_right_away() {
+        let mut coalescer = NotifyCoalescer::new(Duration::from_secs(10));
+        let start = Instant::now();
+        assert!(coalescer.offer(1, notify("1", false), start).is_some());
+        assert!(coalescer
+            .offer(1, notify("2", false), start + Duration::from_secs(1))
+            .is_none());
+        let clean = coalescer.offer(1, notify("3", true), start + Duration::from_secs(2));
+        assert_eq!(clean.unwrap().job_id, "3");
+        // The held job was for the previous block
+        assert_eq!(coalescer.next_due(), None);
+        assert!(coalescer
+            .take_due(start + Duration::from_secs(20))
+            .is_empty());
+    }
+}
@@ -5,12 +5,13 @@ use crate::{
     sv1::{
         downstream::{downstream::Downstream, DownstreamMessages},
         sv1_server::{
-            channel::Sv1ServerChannelState, data::Sv1ServerData,
+            channel::Sv1ServerChannelState,
+            data::{Sv1Job, Sv1ServerData},
             difficulty_manager::DifficultyManager,
+            notify_coalescer::NotifyCoalescer,
         },
     },
-    task_manager::TaskManager,
-    utils::ShutdownMessage,
+    utils::{difficulty_to_hashrate, ShutdownMessage},
 };
 use async_channel::{Receiver, Sender};
 use std::{
@@ -20,15 +21,20 @@ use std::{
         atomic::{AtomicBool, AtomicU32, Ordering},
         Arc, RwLock,
     },
+    time::Instant,
 };
 use stratum_apps::{
+    banlist::BanList,
+    connections::{ConnectionRegistry, ShutdownHandle},
     custom_mutex::Mutex,
-    network_helpers::sv1_connection::ConnectionSV1,
+    network_helpers::{sv1_connection::ConnectionSV1, tls::TlsAcceptor},
+    persistence::Persistence,
+    runtime::{HandshakeDeadline, TaskManager},
     stratum_core::{
         binary_sv2::Str0255,
         bitcoin::Target,
         channels_sv2::{target::hash_rate_to_target, Vardiff, VardiffState},
-        mining_sv2::{CloseChannel, SetTarget},
+        mining_sv2::{CloseChannel, SetExtranoncePrefix, SetTarget},
         parsers_sv2::Mining,
         stratum_translation::{
             sv1_to_sv2::{
@@ -37,12 +43,13 @@ use stratum_apps::{
             },
             sv2_to_sv1::{build_sv1_notify_from_sv2, build_sv1_set_difficulty_from_sv2_target},
         },
-        sv1_api::IsServer,
+        sv1_api::{server_to_client, IsServer},
     },
 };
 use tokio::{
     net::TcpListener,
     sync::{broadcast, mpsc},
+    time::sleep_until,
 };
 use tracing::{debug, error, info, warn};
 
@@ -62,6 +69,10 @@ pub struct Sv1Server {
     shares_per_minute: f32,
     listener_addr: SocketAddr,
     config: TranslatorConfig,
+    // Log of the shares validated by the downstreams
+    persistence: Persistence,
+    // Holds back the jobs that do not clean the previous ones, if configured
+    notify_coalescer: Option<Mutex<NotifyCoalescer>>,
     clean_job: AtomicBool,
     sequence_counter: AtomicU32,
     miner_counter: AtomicU32,
@@ -80,6 +91,7 @@ impl Sv1Server {
     /// * `channel_manager_receiver` - Channel to receive messages from the channel manager
     /// * `channel_manager_sender` - Channel to send messages to the channel manager
     /// * `config` - Configuration settings for the translator
+    /// * `persistence` - Log the shares validated by the downstreams are recorded to
     ///
     /// # Returns
     /// A new Sv1Server instance ready to accept connections
@@ -88,15 +100,21 @@ impl Sv1Server {
         channel_manager_receiver: Receiver<Mining<'static>>,
         channel_manager_sender: Sender<Mining<'static>>,
         config: TranslatorConfig,
+        persistence: Persistence,
     ) -> Self {
         let shares_per_minute = config.downstream_difficulty_config.shares_per_minute;
         let sv1_server_channel_state =
             Sv1ServerChannelState::new(channel_manager_receiver, channel_manager_sender);
         let sv1_server_data = Arc::new(Mutex::new(Sv1ServerData::new(config.aggregate_channels)));
+        let notify_coalescer = config
+            .notify_coalescing_interval()
+            .map(|interval| Mutex::new(NotifyCoalescer::new(interval)));
         Self {
             sv1_server_channel_state,
             sv1_server_data,
             config,
+            persistence,
+            notify_coalescer,
             listener_addr,
             shares_per_minute,
             clean_job: AtomicBool::new(true),
@@ -119,6 +137,7 @@ impl Sv1Server {
     /// The server will continue running until a shutdown signal is received.
     ///
     /// # Arguments
+    /// * `banlist` - Bans of the miner addresses, whose connections are refused
     /// * `notify_shutdown` - Broadcast channel for shutdown coordination
     /// * `shutdown_complete_tx` - Channel to signal shutdown completion
     /// * `status_sender` - Channel for sending status updates
@@ -129,6 +148,7 @@ impl Sv1Server {
     /// * `Err(TproxyError)` - Server encountered an error
     pub async fn start(
         self: Arc<Self>,
+        banlist: Arc<BanList>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         shutdown_complete_tx: mpsc::Sender<()>,
         status_sender: Sender<Status>,
@@ -165,16 +185,31 @@ impl Sv1Server {
             info!("Variable difficulty adjustment disabled - upstream will manage difficulty, SV1 server will forward SetTarget messages to downstreams");
         }
 
+        let tls_acceptor = match &self.config.downstream_tls {
+            Some(tls_config) => Some(tls_config.acceptor().inspect_err(|e| {
+                error!("Failed to load downstream TLS configuration: {e}");
+            })?),
+            None => None,
+        };
+
         let listener = TcpListener::bind(self.listener_addr).await.map_err(|e| {
             error!("Failed to bind to {}: {}", self.listener_addr, e);
-            e
+            TproxyError::Bind(self.listener_addr, e)
         })?;
 
-        info!("Translator Proxy: listening on {}", self.listener_addr);
+        info!(
+            "Translator Proxy: listening on {}{}",
+            self.listener_addr,
+            if tls_acceptor.is_some() { " (TLS)" } else { "" }
+        );
 
         let sv1_status_sender = StatusSender::Sv1Server(status_sender.clone());
 
         loop {
+            let next_held_notify = self
+                .notify_coalescer
+                .as_ref()
+                .and_then(|coalescer| coalescer.super_safe_lock(|c| c.next_due()));
             tokio::select! {
                 message = shutdown_rx_main.recv() => {
                     match message {
@@ -224,7 +259,7 @@ impl Sv1Server {
                                 if self.config.downstream_difficulty_config.enable_vardiff {
                                     d.vardiff = HashMap::new();
                                 }
-                                d.downstreams = HashMap::new();
+                                d.downstreams.clear();
                             });
                             info!("🔌 All downstreams removed from sv1 server as upstream changed");
 
@@ -237,22 +272,10 @@ impl Sv1Server {
                                     ).await;
                             }
                         }
-                        Ok(ShutdownMessage::UpstreamReconnectedResetAndShutdownDownstreams) => {
-                            self.sv1_server_data.super_safe_lock(|d|{
-                                if self.config.downstream_difficulty_config.enable_vardiff {
-                                    d.vardiff = HashMap::new();
-                                }
-                                d.downstreams = HashMap::new();
-                            });
-                            info!("🔌 All downstreams removed from sv1 server as upstream reconnected");
-
-                            // In aggregated mode, send UpdateChannel to reflect the new state (no downstreams)
-                            if self.config.downstream_difficulty_config.enable_vardiff {
-                                DifficultyManager::send_update_channel_on_downstream_state_change(
-                                        &self.sv1_server_data,
-                                        &self.sv1_server_channel_state.channel_manager_sender,
-                                        self.config.aggregate_channels,
-                                    ).await;
+                        Ok(ShutdownMessage::UpstreamReconnected) => {
+                            info!("Upstream reconnected, reopening the channels of the downstreams");
+                            if let Err(e) = self.reopen_downstream_channels().await {
+                                error!("Failed to reopen the channels of the downstreams: {e:?}");
                             }
                         }
                         _ => {}
@@ -261,45 +284,65 @@ impl Sv1Server {
                 result = listener.accept() => {
                     match result {
                         Ok((stream, addr)) => {
+                            if banlist.is_ip_banned(addr.ip()) {
+                                info!("Refusing SV1 connection from banned address {}", addr);
+                                continue;
+                            }
                             info!("New SV1 downstream connection from {}", addr);
-
-                            let connection = ConnectionSV1::new(stream).await;
-                            let downstream_id = self.sv1_server_data.super_safe_lock(|v| v.downstream_id_factory.fetch_add(1, Ordering::Relaxed));
-                            let downstream = Arc::new(Downstream::new(
-                                downstream_id,
-                                connection.sender().clone(),
-                                connection.receiver().clone(),
-                                self.sv1_server_channel_state.downstream_to_sv1_server_sender.clone(),
-                                self.sv1_server_channel_state.sv1_server_to_downstream_sender.clone().subscribe(),
-                                first_target,
-                                Some(self.config.downstream_difficulty_config.min_individual_miner_hashrate),
-                                self.sv1_server_data.clone(),
-                            ));
-                            // vardiff initialization (only if enabled)
-                            _ = self.sv1_server_data
-                                .safe_lock(|d| {
-                                    d.downstreams.insert(downstream_id, downstream.clone());
-                                    // Insert vardiff state for this downstream only if vardiff is enabled
-                                    if self.config.downstream_difficulty_config.enable_vardiff {
-                                        let vardiff = Arc::new(RwLock::new(VardiffState::new().expect("Failed to create vardiffstate")));
-                                        d.vardiff.insert(downstream_id, vardiff);
-                                    }
-                                });
-                            info!("Downstream {} registered successfully (channel will be opened after first message)", downstream_id);
-
-                            // Start downstream tasks immediately, but defer channel opening until first message
-                            let status_sender = StatusSender::Downstream {
-                                downstream_id,
-                                tx: status_sender.clone(),
-                            };
-
-                            Downstream::run_downstream_tasks(
-                                downstream,
-                                notify_shutdown.clone(),
-                                shutdown_complete_tx.clone(),
-                                status_sender,
-                                task_manager.clone(),
-                            );
+                            let deadline = HandshakeDeadline::start(addr, self.config.handshake_timeout());
+
+                            match tls_acceptor.clone() {
+                                Some(acceptor) => {
+                                    // Run the TLS handshake in its own task so that a slow client
+                                    // does not hold up the other connections.
+                                    let sv1_server = Arc::clone(&self);
+                                    let banlist = banlist.clone();
+                                    let notify_shutdown = notify_shutdown.clone();
+                                    let shutdown_complete_tx = shutdown_complete_tx.clone();
+                                    let status_sender = status_sender.clone();
+                                    let task_manager_clone = task_manager.clone();
+                                    task_manager.spawn(async move {
+                                        match deadline.run(acceptor.accept(stream)).await {
+                                            Some(Ok(tls_stream)) => {
+                                                let connection = ConnectionSV1::with_limits(
+                                                    tls_stream,
+                                                    sv1_server.config.sv1_limits.read_limits(),
+                                                ).await;
+                                                sv1_server.add_downstream(
+                                                    connection,
+                                                    addr,
+                                                    banlist,
+                                                    deadline,
+                                                    first_target,
+                                                    notify_shutdown,
+                                                    shutdown_complete_tx,
+                                                    status_sender,
+                                                    task_manager_clone,
+                                                );
+                                            }
+                                            Some(Err(e)) => warn!("TLS handshake with {} failed: {:?}", addr, e),
+                                            None => {}
+                                        }
+                                    });
+                                }
+                                None => {
+                                    let connection = ConnectionSV1::with_limits(
+                                        stream,
+                                        self.config.sv1_limits.read_limits(),
+                                    ).await;
+                                    self.add_downstream(
+                                        connection,
+                                        addr,
+                                        banlist.clone(),
+                                        deadline,
+                                        first_target,
+                                        notify_shutdown.clone(),
+                                        shutdown_complete_tx.clone(),
+                                        status_sender.clone(),
+                                        task_manager.clone(),
+                                    );
+                                }
+                            }
                         }
                         Err(e) => {
                             warn!("Failed to accept new connection: {:?}", e);
@@ -316,13 +359,17 @@ impl Sv1Server {
                 }
                 res = Self::handle_upstream_message(
                     Arc::clone(&self),
-                    first_target,
+                    &notify_shutdown,
                 ) => {
                     if let Err(e) = res {
                         handle_error(&sv1_status_sender, e).await;
                         break;
                     }
                 }
+                _ = sleep_until(next_held_notify.unwrap_or_else(Instant::now).into()),
+                    if next_held_notify.is_some() => {
+                    self.send_held_notifies();
+                }
             }
         }
         self.sv1_server_channel_state.drop();
@@ -331,6 +378,112 @@ impl Sv1Server {
         Ok(())
     }
 
+    /// Returns the state shared with the downstreams (connected miners, jobs, vardiff).
+    pub fn sv1_server_data(&self) -> Arc<Mutex<Sv1ServerData>> {
+        self.sv1_server_data.clone()
+    }
+
+    /// Registers a newly connected SV1 miner and starts its tasks.
+    ///
+    /// The SV2 channel for the miner is only opened once its first message is received. A miner
+    /// still not authorized once `deadline` passes is disconnected, and a miner disconnected for
+    /// breaking its message limits is banned from `banlist` if configured.
+    #[allow(clippy::too_many_arguments)]
+    fn add_downstream(
+        self: &Arc<Self>,
+        connection: ConnectionSV1,
+        address: SocketAddr,
+        banlist: Arc<BanList>,
+        deadline: HandshakeDeadline,
+        first_target: Target,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        shutdown_complete_tx: mpsc::Sender<()>,
+        status_sender: Sender<Status>,
+        task_manager: Arc<TaskManager>,
+    ) {
+        let downstream_id = self
+            .sv1_server_data
+            .super_safe_lock(|v| v.downstreams.allocate_id());
+        let downstream = Arc::new(Downstream::new(
+            downstream_id,
+            address,
+            connection.sender().clone(),
+            connection.receiver().clone(),
+            self.sv1_server_channel_state
+                .downstream_to_sv1_server_sender
+                .clone(),
+            self.sv1_server_channel_state
+                .sv1_server_to_downstream_sender
+                .clone()
+                .subscribe(),
+            first_target,
+            Some(
+                self.config
+                    .downstream_difficulty_config
+                    .min_individual_miner_hashrate,
+            ),
+            self.sv1_server_data.clone(),
+            self.persistence.clone(),
+            &self.config.sv1_limits,
+            connection.violation(),
+            banlist,
+        ));
+        let shutdown = {
+            let notify_shutdown = notify_shutdown.clone();
+            ShutdownHandle::new(move || {
+                let _ = notify_shutdown.send(ShutdownMessage::DownstreamShutdown(downstream_id));
+            })
+        };
+        // vardiff initialization (only if enabled)
+        _ = self.sv1_server_data.safe_lock(|d| {
+            d.downstreams
+                .register(downstream_id, address, downstream.clone(), shutdown);
+            // Insert vardiff state for this downstream only if vardiff is enabled
+            if self.config.downstream_difficulty_config.enable_vardiff {
+                let vardiff = Arc::new(RwLock::new(
+                    VardiffState::new().expect("Failed to create vardiffstate"),
+                ));
+                d.vardiff.insert(downstream_id, vardiff);
+            }
+        });
+        info!(
+            "Downstream {} registered successfully (channel will be opened after first message)",
+            downstream_id
+        );
+
+        let sv1_server_data = self.sv1_server_data.clone();
+        let notify_stalled = notify_shutdown.clone();
+        task_manager.spawn_named("sv1_handshake_deadline", async move {
+            deadline.expired().await;
+            // Miners which disconnected in the meantime are no longer registered.
+            let downstream =
+                sv1_server_data.super_safe_lock(|d| d.downstreams.get(&downstream_id).cloned());
+            let stalled = downstream.is_some_and(|downstream| {
+                downstream
+                    .downstream_data
+                    .super_safe_lock(|d| d.authorized_worker_name.is_empty())
+            });
+            if stalled {
+                deadline.timed_out();
+                let _ = notify_stalled.send(ShutdownMessage::DownstreamShutdown(downstream_id));
+            }
+        });
+
+        // Start downstream tasks immediately, but defer channel opening until first message
+        let status_sender = StatusSender::Downstream {
+            downstream_id,
+            tx: status_sender,
+        };
+
+        Downstream::run_downstream_tasks(
+            downstream,
+            notify_shutdown,
+            shutdown_complete_tx,
+            status_sender,
+            task_manager,
+        );
+    }
+
     /// Handles messages received from downstream SV1 miners.
     ///
     /// This method processes share submissions from miners by:
@@ -436,7 +589,6 @@ impl Sv1Server {
     /// - Channel error messages (TODO: implement proper handling)
     ///
     /// # Arguments
-    /// * `first_target` - Initial difficulty target for new connections
     /// * `notify_shutdown` - Broadcast channel for shutdown coordination
     /// * `shutdown_complete_tx` - Channel to signal shutdown completion
     /// * `status_sender` - Channel for sending status updates
@@ -447,7 +599,7 @@ impl Sv1Server {
     /// * `Err(TproxyError)` - Error processing the message
     pub async fn handle_upstream_message(
         self: Arc<Self>,
-        first_target: Target,
+        notify_shutdown: &broadcast::Sender<ShutdownMessage>,
     ) -> Result<(), TproxyError> {
         let message = self
             .sv1_server_channel_state
@@ -469,13 +621,30 @@ impl Sv1Server {
                 if let Some(downstream) = Self::get_downstream(downstream_id, downstreams) {
                     let initial_target =
                         Target::from_le_bytes(m.target.inner_as_ref().try_into().unwrap());
-                    downstream.downstream_data.safe_lock(|d| {
-                        d.extranonce1 = m.extranonce_prefix.to_vec();
-                        d.extranonce2_len = m.extranonce_size.into();
+                    let extranonce1 = m.extranonce_prefix.to_vec();
+                    let extranonce2_len = m.extranonce_size.into();
+                    // A miner past its handshake is getting its channel reopened on a new
+                    // upstream, and has to learn about a change of its extranonce
+                    let extranonce_changed = downstream.downstream_data.safe_lock(|d| {
+                        let changed = d.sv1_handshake_complete.load(Ordering::SeqCst)
+                            && (d.extranonce1 != extranonce1
+                                || d.extranonce2_len != extranonce2_len);
+                        d.extranonce1 = extranonce1;
+                        d.extranonce2_len = extranonce2_len;
                         d.channel_id = Some(m.channel_id);
+                        d.span.record("channel_id", m.channel_id);
                         // Set the initial upstream target from OpenExtendedMiningChannelSuccess
                         d.set_upstream_target(initial_target);
+                        changed
                     })?;
+                    if extranonce_changed {
+                        self.send_extranonce(
+                            downstream_id,
+                            &downstream,
+                            m.channel_id,
+                            notify_shutdown,
+                        )?;
+                    }
 
                     // Process all queued messages now that channel is established
                     if let Ok(queued_messages) = downstream.downstream_data.safe_lock(|d| {
@@ -514,10 +683,13 @@ impl Sv1Server {
                         }
                     }
 
-                    let set_difficulty = build_sv1_set_difficulty_from_sv2_target(first_target)
-                        .map_err(|_| {
-                            TproxyError::General("Failed to generate set_difficulty".into())
-                        })?;
+                    // Downstreams start at the configured difficulty, unless they suggested one
+                    let downstream_target =
+                        downstream.downstream_data.super_safe_lock(|d| d.target);
+                    let set_difficulty =
+                        build_sv1_set_difficulty_from_sv2_target(downstream_target).map_err(
+                            |_| TproxyError::General("Failed to generate set_difficulty".into()),
+                        )?;
                     // send the set_difficulty message to the downstream
                     self.sv1_server_channel_state
                         .sv1_server_to_downstream_sender
@@ -544,14 +716,17 @@ impl Sv1Server {
                     self.clean_job.store(false, Ordering::SeqCst);
 
                     // Update job storage based on the configured mode
-                    let notify_parsed = notify.clone();
+                    let job = Sv1Job {
+                        notify: notify.clone(),
+                        version_rolling_allowed: m.version_rolling_allowed,
+                    };
                     self.sv1_server_data.super_safe_lock(|server_data| {
                         if let Some(ref mut aggregated_jobs) = server_data.aggregated_valid_jobs {
                             // Aggregated mode: all downstreams share the same jobs
                             if clean_jobs {
                                 aggregated_jobs.clear();
                             }
-                            aggregated_jobs.push(notify_parsed);
+                            aggregated_jobs.push(job);
                         } else if let Some(ref mut non_aggregated_jobs) =
                             server_data.non_aggregated_valid_jobs
                         {
@@ -562,14 +737,24 @@ impl Sv1Server {
                             if clean_jobs {
                                 channel_jobs.clear();
                             }
-                            channel_jobs.push(notify_parsed);
+                            channel_jobs.push(job);
                         }
                     });
 
-                    let _ = self
-                        .sv1_server_channel_state
-                        .sv1_server_to_downstream_sender
-                        .send((m.channel_id, None, notify.into()));
+                    let notify = match &self.notify_coalescer {
+                        Some(coalescer) => coalescer
+                            .super_safe_lock(|c| c.offer(m.channel_id, notify, Instant::now())),
+                        None => Some(notify),
+                    };
+                    match notify {
+                        Some(notify) => {
+                            let _ = self
+                                .sv1_server_channel_state
+                                .sv1_server_to_downstream_sender
+                                .send((m.channel_id, None, notify.into()));
+                        }
+                        None => debug!("Holding back job {} of channel {}", m.job_id, m.channel_id),
+                    }
                 }
             }
 
@@ -601,6 +786,10 @@ impl Sv1Server {
                 }
             }
 
+            Mining::SetExtranoncePrefix(m) => {
+                self.handle_set_extranonce_prefix(m, notify_shutdown)?;
+            }
+
             Mining::CloseChannel(_) => {
                 todo!("Handle CloseChannel message from upstream");
             }
@@ -619,10 +808,29 @@ impl Sv1Server {
         Ok(())
     }
 
+    // Forwards the jobs held back by the coalescer whose interval has elapsed.
+    fn send_held_notifies(&self) {
+        let Some(coalescer) = &self.notify_coalescer else {
+            return;
+        };
+        let due = coalescer.super_safe_lock(|c| c.take_due(Instant::now()));
+        for (channel_id, notify) in due {
+            debug!(
+                "Forwarding held back job {} of channel {channel_id}",
+                notify.job_id
+            );
+            let _ = self
+                .sv1_server_channel_state
+                .sv1_server_to_downstream_sender
+                .send((channel_id, None, notify.into()));
+        }
+    }
+
     /// Opens an extended mining channel for a downstream connection.
     ///
     /// This method initiates the SV2 channel setup process by:
-    /// - Calculating the initial target based on configuration
+    /// - Calculating the initial target based on configuration, or on the difficulty suggested by
+    ///   the miner (clamped within the configured bounds)
     /// - Generating a unique user identity for the miner
     /// - Creating an OpenExtendedMiningChannel message
     /// - Sending the request to the channel manager
@@ -639,11 +847,40 @@ impl Sv1Server {
     ) -> Result<(), TproxyError> {
         let config = &self.config.downstream_difficulty_config;
 
-        let hashrate = config.min_individual_miner_hashrate as f64;
+        let default_hashrate = config.min_individual_miner_hashrate as f64;
         let shares_per_min = config.shares_per_minute as f64;
         let min_extranonce_size = self.config.downstream_extranonce2_size;
         let vardiff_enabled = config.enable_vardiff;
 
+        let (downstream_id, suggested_difficulty) = downstream
+            .downstream_data
+            .super_safe_lock(|d| (d.downstream_id, d.suggested_difficulty));
+        let hashrate = match suggested_difficulty {
+            Some(difficulty) => {
+                let difficulty = config.clamp_suggested_difficulty(difficulty);
+                let hashrate = difficulty_to_hashrate(difficulty, shares_per_min);
+                match hash_rate_to_target(hashrate, shares_per_min) {
+                    Ok(target) => {
+                        info!(
+                            "Downstream {downstream_id}: using suggested difficulty {difficulty} ({hashrate} h/s) as initial difficulty"
+                        );
+                        downstream.downstream_data.safe_lock(|d| {
+                            d.target = target;
+                            d.hashrate = Some(hashrate as f32);
+                        })?;
+                        hashrate
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Downstream {downstream_id}: ignoring suggested difficulty {difficulty}: {e:?}"
+                        );
+                        default_hashrate
+                    }
+                }
+            }
+            None => default_hashrate,
+        };
+
         let max_target = if vardiff_enabled {
             hash_rate_to_target(hashrate, shares_per_min).unwrap()
         } else {
@@ -655,21 +892,24 @@ impl Sv1Server {
         // Store the initial target for use when no downstreams remain
         self.sv1_server_data.super_safe_lock(|data| {
             if data.initial_target.is_none() {
-                data.initial_target = Some(max_target);
+                data.initial_target = Some(if vardiff_enabled {
+                    hash_rate_to_target(default_hashrate, shares_per_min).unwrap()
+                } else {
+                    max_target
+                });
             }
         });
 
         let miner_id = self.miner_counter.fetch_add(1, Ordering::SeqCst) + 1;
         let user_identity = format!("{}.miner{}", self.config.user_identity, miner_id);
 
-        downstream
-            .downstream_data
-            .safe_lock(|d| d.user_identity = user_identity.clone())?;
+        downstream.downstream_data.safe_lock(|d| {
+            d.span.record("user_identity", user_identity.as_str());
+            d.user_identity = user_identity.clone();
+        })?;
 
         if let Ok(open_channel_msg) = build_sv2_open_extended_mining_channel(
-            downstream
-                .downstream_data
-                .super_safe_lock(|d| d.downstream_id),
+            downstream_id,
             user_identity.clone(),
             hashrate as f32,
             max_target,
@@ -687,18 +927,101 @@ impl Sv1Server {
         Ok(())
     }
 
+    /// Reopens the channels of the downstreams once the upstream reconnected.
+    ///
+    /// The jobs of the previous upstream are dropped. The miners stay connected: every downstream
+    /// whose channel was open gets a new one, for the same user identity and at its current
+    /// difficulty, and is sent `mining.set_difficulty` with the first job of the new channel. The
+    /// downstreams which were waiting for their channel ask for it again.
+    async fn reopen_downstream_channels(&self) -> Result<(), TproxyError> {
+        let downstreams: Vec<Arc<Downstream>> = self.sv1_server_data.super_safe_lock(|data| {
+            data.prevhash = None;
+            if let Some(jobs) = data.aggregated_valid_jobs.as_mut() {
+                jobs.clear();
+            }
+            if let Some(jobs) = data.non_aggregated_valid_jobs.as_mut() {
+                jobs.clear();
+            }
+            data.pending_target_updates.clear();
+            data.downstreams
+                .iter()
+                .map(|(_, downstream)| downstream.clone())
+                .collect()
+        });
+        if let Some(coalescer) = &self.notify_coalescer {
+            coalescer.super_safe_lock(|c| c.clear());
+        }
+        self.clean_job.store(true, Ordering::SeqCst);
+
+        for downstream in downstreams {
+            let (was_open, was_requested) = downstream.downstream_data.super_safe_lock(|d| {
+                let was_open = d.channel_id.take().is_some();
+                d.cached_set_difficulty = None;
+                d.pending_target = None;
+                d.pending_hashrate = None;
+                (was_open, !d.queued_sv1_handshake_messages.is_empty())
+            });
+            if was_open {
+                self.reopen_extended_mining_channel(&downstream).await?;
+            } else if was_requested {
+                self.open_extended_mining_channel(downstream).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Asks the upstream for a new channel for a downstream whose channel was open on the previous
+    // upstream.
+    async fn reopen_extended_mining_channel(
+        &self,
+        downstream: &Downstream,
+    ) -> Result<(), TproxyError> {
+        let config = &self.config.downstream_difficulty_config;
+        let (downstream_id, user_identity, hashrate, target) =
+            downstream.downstream_data.super_safe_lock(|d| {
+                (
+                    d.downstream_id,
+                    d.user_identity.clone(),
+                    d.hashrate
+                        .unwrap_or(config.min_individual_miner_hashrate as f32),
+                    d.target,
+                )
+            });
+        let max_target = if config.enable_vardiff {
+            target
+        } else {
+            Target::from_le_bytes([0xff; 32])
+        };
+        let open_channel_msg = build_sv2_open_extended_mining_channel(
+            downstream_id,
+            user_identity,
+            hashrate,
+            max_target,
+            self.config.downstream_extranonce2_size,
+        )
+        .map_err(|_| {
+            TproxyError::General("Failed to build OpenExtendedMiningChannel message".into())
+        })?;
+        debug!("Reopening the channel of downstream {downstream_id}");
+        self.sv1_server_channel_state
+            .channel_manager_sender
+            .send(Mining::OpenExtendedMiningChannel(open_channel_msg))
+            .await
+            .map_err(|_| TproxyError::ChannelErrorSender)
+    }
+
     /// Retrieves a downstream connection by ID from the provided map.
     ///
     /// # Arguments
     /// * `downstream_id` - The ID of the downstream connection to find
-    /// * `downstream` - HashMap containing downstream connections
+    /// * `downstream` - Registry of the downstream connections
     ///
     /// # Returns
     /// * `Some(Downstream)` - If a downstream with the given ID exists
     /// * `None` - If no downstream with the given ID is found
     pub fn get_downstream(
         downstream_id: u32,
-        downstream: HashMap<u32, Arc<Downstream>>,
+        downstream: ConnectionRegistry<u32, Arc<Downstream>>,
     ) -> Option<Arc<Downstream>> {
         downstream.get(&downstream_id).cloned()
     }
@@ -740,12 +1063,86 @@ impl Sv1Server {
         }
     }
 
+    /// Makes the new extranonce prefix of a channel the `extranonce1` of its miner.
+    ///
+    /// Miners which sent `mining.extranonce.subscribe` get it in a `mining.set_extranonce`, the
+    /// others cannot learn it and are disconnected, to get it on reconnection.
+    fn handle_set_extranonce_prefix(
+        &self,
+        m: SetExtranoncePrefix<'_>,
+        notify_shutdown: &broadcast::Sender<ShutdownMessage>,
+    ) -> Result<(), TproxyError> {
+        let affected_downstream = self.sv1_server_data.super_safe_lock(|data| {
+            data.downstreams
+                .iter()
+                .find_map(|(downstream_id, downstream)| {
+                    downstream.downstream_data.super_safe_lock(|d| {
+                        (d.channel_id == Some(m.channel_id))
+                            .then(|| (*downstream_id, downstream.clone()))
+                    })
+                })
+        });
+        let Some((downstream_id, downstream)) = affected_downstream else {
+            warn!(
+                "No downstream found for channel {} to set the extranonce prefix of",
+                m.channel_id
+            );
+            return Ok(());
+        };
+
+        downstream
+            .downstream_data
+            .super_safe_lock(|d| d.extranonce1 = m.extranonce_prefix.to_vec());
+        self.send_extranonce(downstream_id, &downstream, m.channel_id, notify_shutdown)
+    }
+
+    /// Sends its current `extranonce1` to the miner of `downstream` in a `mining.set_extranonce`,
+    /// or disconnects it if it did not send `mining.extranonce.subscribe`.
+    fn send_extranonce(
+        &self,
+        downstream_id: u32,
+        downstream: &Downstream,
+        channel_id: u32,
+        notify_shutdown: &broadcast::Sender<ShutdownMessage>,
+    ) -> Result<(), TproxyError> {
+        let (subscribed, extranonce1, extranonce2_len) =
+            downstream.downstream_data.super_safe_lock(|d| {
+                (
+                    d.extranonce_subscribed.get(),
+                    d.extranonce1.clone(),
+                    d.extranonce2_len,
+                )
+            });
+        if !subscribed {
+            info!(
+                "Downstream {downstream_id} did not subscribe to extranonce changes, disconnecting it"
+            );
+            _ = notify_shutdown.send(ShutdownMessage::DownstreamShutdown(downstream_id));
+            return Ok(());
+        }
+
+        debug!("Sending mining.set_extranonce to downstream {downstream_id}");
+        let set_extranonce = server_to_client::SetExtranonce {
+            extra_nonce1: extranonce1.try_into().map_err(|_| TproxyError::SV1Error)?,
+            extra_nonce2_size: extranonce2_len,
+        };
+        self.sv1_server_channel_state
+            .sv1_server_to_downstream_sender
+            .send((channel_id, Some(downstream_id), set_extranonce.into()))
+            .map_err(|_| TproxyError::ChannelErrorSender)?;
+        Ok(())
+    }
+
     /// Sends set_difficulty to all downstreams (aggregated mode).
     /// Used only when vardiff is disabled.
     async fn send_set_difficulty_to_all_downstreams(&self, target: Target) {
-        let downstreams = self
-            .sv1_server_data
-            .super_safe_lock(|data| data.downstreams.clone());
+        let downstreams: Vec<(u32, Arc<Downstream>)> =
+            self.sv1_server_data.super_safe_lock(|data| {
+                data.downstreams
+                    .iter()
+                    .map(|(downstream_id, downstream)| (*downstream_id, downstream.clone()))
+                    .collect()
+            });
 
         for (downstream_id, downstream) in downstreams {
             let channel_id = downstream.downstream_data.super_safe_lock(|d| d.channel_id);
@@ -834,7 +1231,10 @@ impl Sv1Server {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{DownstreamDifficultyConfig, TranslatorConfig, Upstream};
+    use crate::{
+        config::{DownstreamDifficultyConfig, TranslatorConfig, Upstream},
+        sv1::sv1_server::data::PendingTargetUpdate,
+    };
     use async_channel::unbounded;
     use std::{collections::HashMap, str::FromStr};
     use stratum_apps::key_utils::Secp256k1PublicKey;
@@ -865,7 +1265,13 @@ mod tests {
         let config = create_test_config();
         let addr = "127.0.0.1:3333".parse().unwrap();
 
-        Sv1Server::new(addr, cm_receiver, cm_sender, config)
+        Sv1Server::new(
+            addr,
+            cm_receiver,
+            cm_sender,
+            config,
+            Persistence::disabled(),
+        )
     }
 
     #[test]
@@ -889,7 +1295,13 @@ mod tests {
         let (_downstream_sender, cm_receiver) = unbounded();
         let addr = "127.0.0.1:3333".parse().unwrap();
 
-        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
+        let server = Sv1Server::new(
+            addr,
+            cm_receiver,
+            cm_sender,
+            config,
+            Persistence::disabled(),
+        );
 
         assert!(server.config.aggregate_channels);
         assert!(server.config.downstream_difficulty_config.enable_vardiff);
@@ -905,7 +1317,13 @@ mod tests {
         let (_downstream_sender, cm_receiver) = unbounded();
         let addr = "127.0.0.1:3333".parse().unwrap();
 
-        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
+        let server = Sv1Server::new(
+            addr,
+            cm_receiver,
+            cm_sender,
+            config,
+            Persistence::disabled(),
+        );
 
         assert!(!server.config.aggregate_channels);
         assert!(!server.config.downstream_difficulty_config.enable_vardiff);
@@ -913,7 +1331,7 @@ mod tests {
 
     #[test]
     fn test_get_downstream_basic() {
-        let downstreams = HashMap::new();
+        let downstreams = ConnectionRegistry::new(0);
 
         // Test non-existing downstream
         let not_found = Sv1Server::get_downstream(999, downstreams);
@@ -955,7 +1373,13 @@ mod tests {
         let (_downstream_sender, cm_receiver) = unbounded();
         let addr = "127.0.0.1:3333".parse().unwrap();
 
-        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
+        let server = Sv1Server::new(
+            addr,
+            cm_receiver,
+            cm_sender,
+            config,
+            Persistence::disabled(),
+        );
         let target: Target = hash_rate_to_target(200.0, 5.0).unwrap();
 
         let set_target = SetTarget {
@@ -977,7 +1401,13 @@ mod tests {
         let (_downstream_sender, cm_receiver) = unbounded();
         let addr = "127.0.0.1:3333".parse().unwrap();
 
-        let server = Sv1Server::new(addr, cm_receiver, cm_sender, config);
+        let server = Sv1Server::new(
+            addr,
+            cm_receiver,
+            cm_sender,
+            config,
+            Persistence::disabled(),
+        );
         let target: Target = hash_rate_to_target(200.0, 5.0).unwrap();
 
         let set_target = SetTarget {
@@ -1022,4 +1452,26 @@ mod tests {
         server.clean_job.store(true, Ordering::SeqCst);
         assert!(server.clean_job.load(Ordering::SeqCst));
     }
+
+    #[tokio::test]
+    async fn test_reopen_downstream_channels_drops_the_jobs_of_the_previous_upstream() {
+        let server = create_test_sv1_server();
+        server.clean_job.store(false, Ordering::SeqCst);
+        server.sv1_server_data.super_safe_lock(|data| {
+            data.pending_target_updates.push(PendingTargetUpdate {
+                downstream_id: 1,
+                new_target: Target::MAX,
+                new_hashrate: 100.0,
+            });
+        });
+
+        server.reopen_downstream_channels().await.unwrap();
+
+        assert!(server.clean_job.load(Ordering::SeqCst));
+        server.sv1_server_data.super_safe_lock(|data| {
+            assert!(data.prevhash.is_none());
+            assert!(data.pending_target_updates.is_empty());
+            assert_eq!(data.aggregated_valid_jobs.as_ref().map(Vec::len), Some(0));
+        });
+    }
 }
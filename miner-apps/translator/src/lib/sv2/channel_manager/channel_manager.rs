@@ -5,13 +5,13 @@ use crate::{
         channel::ChannelState,
         data::{ChannelManagerData, ChannelMode},
     },
-    task_manager::TaskManager,
     utils::ShutdownMessage,
 };
 use async_channel::{Receiver, Sender};
 use std::sync::{Arc, RwLock};
 use stratum_apps::{
     custom_mutex::Mutex,
+    runtime::TaskManager,
     stratum_core::{
         channels_sv2::client::extended::ExtendedChannel,
         handlers_sv2::HandleMiningMessagesFromServerAsync,
@@ -19,7 +19,7 @@ use stratum_apps::{
     },
 };
 use tokio::sync::{broadcast, mpsc};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 /// Extra bytes allocated for translator search space in aggregated mode.
 /// This allows the translator to manage multiple downstream connections
@@ -109,7 +109,7 @@ impl ChannelManager {
     ) {
         let mut shutdown_rx = notify_shutdown.subscribe();
         let status_sender = StatusSender::ChannelManager(status_sender);
-        task_manager.spawn(async move {
+        task_manager.spawn_critical("channel_manager", async move {
             loop {
                 tokio::select! {
                     message = shutdown_rx.recv() => {
@@ -118,13 +118,6 @@ impl ChannelManager {
                                 info!("ChannelManager: received shutdown signal.");
                                 break;
                             }
-                            Ok(ShutdownMessage::UpstreamReconnectedResetAndShutdownDownstreams) => {
-                                info!("ChannelManager: upstream reconnected, resetting channel state.");
-                                self.channel_manager_data.super_safe_lock(|data| {
-                                    data.reset_for_upstream_reconnection();
-                                });
-                                // Note: DownstreamShutdownAll handling is done by SV1Server separately
-                            }
                             Ok(_) => {
                                 // Ignore other shutdown message types
                             }
@@ -529,14 +522,26 @@ impl ChannelManager {
                         }
                     }
 
-                    info!(
-                        "SubmitSharesExtended: valid share, forwarding it to upstream | channel_id: {}, sequence_number: {} ☑️",
-                        m.channel_id, m.sequence_number
+                    // Same fields as the `share` spans of the upstream, to follow the share in
+                    // exported traces
+                    let span = info_span!(
+                        "share",
+                        channel_id = m.channel_id,
+                        sequence_number = m.sequence_number,
+                        job_id = m.job_id,
+                        nonce = m.nonce
                     );
+                    span.in_scope(|| {
+                        info!(
+                            "SubmitSharesExtended: valid share, forwarding it to upstream | channel_id: {}, sequence_number: {} ☑️",
+                            m.channel_id, m.sequence_number
+                        )
+                    });
                     let message = Mining::SubmitSharesExtended(m);
                     self.channel_state
                         .upstream_sender
                         .send(message)
+                        .instrument(span)
                         .await
                         .map_err(|e| {
                             error!("Error while sending message to upstream: {e:?}");
@@ -5,10 +5,13 @@ use std::{
 use stratum_apps::{
     custom_mutex::Mutex,
     stratum_core::{
-        channels_sv2::client::extended::ExtendedChannel, mining_sv2::ExtendedExtranonce,
+        binary_sv2::B032, channels_sv2::client::extended::ExtendedChannel,
+        mining_sv2::ExtendedExtranonce,
     },
 };
 
+use crate::{error::TproxyError, utils::proxy_extranonce_prefix_len};
+
 /// Defines the operational mode for channel management.
 ///
 /// The channel manager can operate in two different modes that affect how
@@ -96,6 +99,81 @@ impl ChannelManagerData {
         // Note: we intentionally preserve `mode` as it's a configuration setting
     }
 
+    /// Applies a `SetExtranoncePrefix` of the upstream to the channel `channel_id`, and returns
+    /// the downstream channels whose extranonce prefix changed, with their new prefix.
+    ///
+    /// In aggregated mode, the upstream channel is shared by all the downstreams: the extranonce
+    /// prefix factory is rebuilt on the new prefix, keeping the search space of the translator,
+    /// and every downstream channel gets a new prefix from it. In non-aggregated mode, only the
+    /// channel `channel_id` changes, its factory being rebuilt likewise if its extranonce is
+    /// adjusted.
+    pub fn set_extranonce_prefix(
+        &mut self,
+        channel_id: u32,
+        extranonce_prefix: Vec<u8>,
+    ) -> Result<Vec<(u32, Vec<u8>)>, TproxyError> {
+        if self.mode == ChannelMode::Aggregated {
+            let (Some(upstream_channel), Some(factory)) = (
+                self.upstream_extended_channel.clone(),
+                self.extranonce_prefix_factory.clone(),
+            ) else {
+                return Ok(Vec::new());
+            };
+            let mut upstream_channel = upstream_channel.write()?;
+            if upstream_channel.get_channel_id() != channel_id {
+                return Ok(Vec::new());
+            }
+            upstream_channel.set_extranonce_prefix(extranonce_prefix.clone())?;
+            let downstream_extranonce_len = factory.super_safe_lock(|f| f.get_range2_len());
+            let mut factory = extranonce_factory(
+                extranonce_prefix,
+                proxy_extranonce_prefix_len(
+                    upstream_channel.get_rollable_extranonce_size().into(),
+                    downstream_extranonce_len,
+                ),
+                downstream_extranonce_len,
+            )?;
+            let mut prefixes = Vec::with_capacity(self.extended_channels.len());
+            for (channel_id, channel) in &self.extended_channels {
+                let prefix = factory
+                    .next_prefix_extended(downstream_extranonce_len)?
+                    .to_vec();
+                channel.write()?.set_extranonce_prefix(prefix.clone())?;
+                prefixes.push((*channel_id, prefix));
+            }
+            self.extranonce_prefix_factory = Some(Arc::new(Mutex::new(factory)));
+            return Ok(prefixes);
+        }
+
+        let Some(channel) = self.extended_channels.get(&channel_id).cloned() else {
+            return Ok(Vec::new());
+        };
+        let mut channel = channel.write()?;
+        let factory = self
+            .extranonce_factories
+            .as_mut()
+            .and_then(|factories| factories.get_mut(&channel_id));
+        let prefix = match factory {
+            Some(factory) => {
+                let (upstream_prefix_len, downstream_extranonce_len) =
+                    factory.super_safe_lock(|f| (f.get_range0_len(), f.get_range2_len()));
+                let mut new_factory = extranonce_factory(
+                    extranonce_prefix,
+                    channel.get_extranonce_prefix().len() - upstream_prefix_len,
+                    downstream_extranonce_len,
+                )?;
+                let prefix = new_factory
+                    .next_prefix_extended(downstream_extranonce_len)?
+                    .to_vec();
+                *factory = Arc::new(Mutex::new(new_factory));
+                prefix
+            }
+            None => extranonce_prefix,
+        };
+        channel.set_extranonce_prefix(prefix.clone())?;
+        Ok(vec![(channel_id, prefix)])
+    }
+
     /// Gets the next sequence number for a valid share and increments the counter.
     ///
     /// The counter_key determines which counter to use:
@@ -108,3 +186,93 @@ impl ChannelManagerData {
         current
     }
 }
+
+// Builds the factory of the extranonce prefixes of downstream channels: the prefix of the upstream
+// channel, followed by `proxy_len` bytes of search space of the translator, leaving
+// `downstream_len` bytes to the miners.
+fn extranonce_factory(
+    upstream_prefix: Vec<u8>,
+    proxy_len: usize,
+    downstream_len: usize,
+) -> Result<ExtendedExtranonce, TproxyError> {
+    let range_0 = 0..upstream_prefix.len();
+    let range_1 = range_0.end..range_0.end + proxy_len;
+    let range_2 = range_1.end..range_1.end + downstream_len;
+    let upstream_prefix: B032 = upstream_prefix.try_into()?;
+    Ok(ExtendedExtranonce::from_upstream_extranonce(
+        upstream_prefix.into(),
+        range_0,
+        range_1,
+        range_2,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_apps::stratum_core::bitcoin::Target;
+
+    fn channel(
+        channel_id: u32,
+        extranonce_prefix: Vec<u8>,
+        rollable_extranonce_size: u16,
+    ) -> Arc<RwLock<ExtendedChannel<'static>>> {
+        Arc::new(RwLock::new(ExtendedChannel::new(
+            channel_id,
+            "user".to_string(),
+            extranonce_prefix,
+            Target::MAX,
+            1.0,
+            true,
+            rollable_extranonce_size,
+        )))
+    }
+
+    #[test]
+    fn test_set_extranonce_prefix_aggregated() {
+        let mut data = ChannelManagerData::new(ChannelMode::Aggregated);
+        let mut factory = extranonce_factory(vec![0xaa; 4], 4, 4).unwrap();
+        data.upstream_extended_channel = Some(channel(1, vec![0xaa; 4], 8));
+        for channel_id in [1, 2] {
+            let prefix = factory.next_prefix_extended(4).unwrap().to_vec();
+            data.extended_channels
+                .insert(channel_id, channel(channel_id, prefix, 4));
+        }
+        data.extranonce_prefix_factory = Some(Arc::new(Mutex::new(factory)));
+
+        // Only the upstream channel is known to the upstream.
+        assert!(data
+            .set_extranonce_prefix(7, vec![0xcc; 4])
+            .unwrap()
+            .is_empty());
+
+        let prefixes = data.set_extranonce_prefix(1, vec![0xbb; 4]).unwrap();
+        assert_eq!(prefixes.len(), 2);
+        assert_ne!(prefixes[0].1, prefixes[1].1);
+        for (channel_id, prefix) in &prefixes {
+            assert_eq!(prefix.len(), 8);
+            assert!(prefix.starts_with(&[0xbb; 4]));
+            let channel = data.extended_channels[channel_id].read().unwrap();
+            assert_eq!(channel.get_extranonce_prefix(), prefix);
+        }
+        let upstream_channel = data.upstream_extended_channel.as_ref().unwrap();
+        assert_eq!(
+            upstream_channel.read().unwrap().get_extranonce_prefix(),
+            &vec![0xbb; 4]
+        );
+    }
+
+    #[test]
+    fn test_set_extranonce_prefix_non_aggregated() {
+        let mut data = ChannelManagerData::new(ChannelMode::NonAggregated);
+        data.extended_channels
+            .insert(3, channel(3, vec![0xaa; 4], 8));
+
+        let prefixes = data.set_extranonce_prefix(3, vec![0xbb; 4]).unwrap();
+        assert_eq!(prefixes, [(3, vec![0xbb; 4])]);
+        assert!(data
+            .set_extranonce_prefix(4, vec![0xbb; 4])
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -276,13 +276,40 @@ impl HandleMiningMessagesFromServerAsync for ChannelManager {
         Ok(())
     }
 
+    // Gives the downstream channels affected by the new extranonce prefix their own new prefix,
+    // and hands each of them to the SV1 server, which sends it to the miner as its new
+    // `extranonce1`.
     async fn handle_set_extranonce_prefix(
         &mut self,
         _server_id: Option<usize>,
         m: SetExtranoncePrefix<'_>,
     ) -> Result<(), Self::Error> {
-        warn!("Received: {}", m);
-        warn!("⚠️ Cannot process SetExtranoncePrefix since set_extranonce is not supported for majority of sv1 clients. Ignoring.");
+        info!("Received: {}", m);
+        let prefixes = self
+            .channel_manager_data
+            .super_safe_lock(|channel_manager_data| {
+                channel_manager_data
+                    .set_extranonce_prefix(m.channel_id, m.extranonce_prefix.to_vec())
+            })?;
+        if prefixes.is_empty() {
+            debug!(
+                "No downstream channel affected by the extranonce prefix of channel {}",
+                m.channel_id
+            );
+        }
+        for (channel_id, extranonce_prefix) in prefixes {
+            self.channel_state
+                .sv1_server_sender
+                .send(Mining::SetExtranoncePrefix(SetExtranoncePrefix {
+                    channel_id,
+                    extranonce_prefix: extranonce_prefix.try_into()?,
+                }))
+                .await
+                .map_err(|e| {
+                    error!("Failed to send SetExtranoncePrefix: {:?}", e);
+                    TproxyError::ChannelErrorSender
+                })?;
+        }
         Ok(())
     }
 
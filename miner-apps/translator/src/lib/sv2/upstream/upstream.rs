@@ -2,28 +2,28 @@ use crate::{
     error::TproxyError,
     status::{handle_error, Status, StatusSender},
     sv2::upstream::channel::UpstreamChannelState,
-    task_manager::TaskManager,
-    utils::{
-        protocol_message_type, spawn_io_tasks, Message, MessageType, SV2Frame, ShutdownMessage,
-        StdFrame,
-    },
+    utils::{Message, SV2Frame, ShutdownMessage, StdFrame, UpstreamIoStatus},
 };
 use async_channel::{unbounded, Receiver, Sender};
 use std::{net::SocketAddr, sync::Arc};
 use stratum_apps::{
-    key_utils::Secp256k1PublicKey,
-    network_helpers::noise_stream::NoiseTcpStream,
+    key_utils::AuthorityPublicKeys,
+    network_helpers::{
+        traffic::ConnectionTraffic,
+        transport::{connect, Encryption},
+    },
+    runtime::{
+        message_type::{protocol_message_type, MessageType},
+        spawn_io_tasks, TaskManager,
+    },
     stratum_core::{
-        codec_sv2::HandshakeRole,
         common_messages_sv2::{Protocol, SetupConnection},
         framing_sv2,
         handlers_sv2::HandleCommonMessagesFromServerAsync,
-        noise_sv2::Initiator,
         parsers_sv2::{AnyMessage, Mining},
     },
 };
 use tokio::{
-    net::TcpStream,
     sync::{broadcast, mpsc},
     time::{sleep, Duration},
 };
@@ -45,6 +45,8 @@ use tracing::{debug, error, info, warn};
 #[derive(Debug, Clone)]
 pub struct Upstream {
     upstream_channel_state: UpstreamChannelState,
+    address: SocketAddr,
+    traffic: Arc<ConnectionTraffic>,
 }
 
 impl Upstream {
@@ -55,7 +57,9 @@ impl Upstream {
     /// to connect to each server multiple times before giving up.
     ///
     /// # Arguments
-    /// * `upstreams` - List of (address, public_key) pairs for upstream servers
+    /// * `upstreams` - List of (address, accepted authority keys, encryption) of the upstream
+    ///   servers
+    /// * `idle_timeout` - Closes the connection when no frame is received for that long
     /// * `channel_manager_sender` - Channel to send messages to the channel manager
     /// * `channel_manager_receiver` - Channel to receive messages from the channel manager
     /// * `notify_shutdown` - Broadcast channel for shutdown coordination
@@ -65,7 +69,8 @@ impl Upstream {
     /// * `Ok(Upstream)` - Successfully connected to an upstream server
     /// * `Err(TproxyError)` - Failed to connect to any upstream server
     pub async fn new(
-        upstreams: &[(SocketAddr, Secp256k1PublicKey)],
+        upstreams: &[(SocketAddr, AuthorityPublicKeys, Encryption)],
+        idle_timeout: Option<Duration>,
         channel_manager_sender: Sender<Mining<'static>>,
         channel_manager_receiver: Receiver<Mining<'static>>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
@@ -75,7 +80,7 @@ impl Upstream {
         let mut shutdown_rx = notify_shutdown.subscribe();
         const RETRIES_PER_UPSTREAM: u8 = 3;
 
-        for (index, (addr, pubkey)) in upstreams.iter().enumerate() {
+        for (index, (addr, pubkeys, encryption)) in upstreams.iter().enumerate() {
             info!("Trying to connect to upstream {} at {}", index, addr);
 
             for attempt in 1..=RETRIES_PER_UPSTREAM {
@@ -85,50 +90,45 @@ impl Upstream {
                     return Err(TproxyError::Shutdown);
                 }
 
-                match TcpStream::connect(addr).await {
-                    Ok(socket) => {
+                match connect::<Message>(*addr, *encryption, Some(pubkeys)).await {
+                    Ok(stream) => {
                         info!(
                             "Connected to upstream at {addr} (attempt {attempt}/{RETRIES_PER_UPSTREAM})"
                         );
 
-                        let initiator = Initiator::from_raw_k(pubkey.into_bytes())?;
-                        match NoiseTcpStream::new(socket, HandshakeRole::Initiator(initiator)).await
-                        {
-                            Ok(stream) => {
-                                let (reader, writer) = stream.into_split();
-
-                                let (outbound_tx, outbound_rx) = unbounded();
-                                let (inbound_tx, inbound_rx) = unbounded();
-
-                                spawn_io_tasks(
-                                    task_manager,
-                                    reader,
-                                    writer,
-                                    outbound_rx,
-                                    inbound_tx,
-                                    notify_shutdown,
-                                );
-
-                                let upstream_channel_state = UpstreamChannelState::new(
-                                    channel_manager_sender,
-                                    channel_manager_receiver,
-                                    inbound_rx,
-                                    outbound_tx,
-                                );
-                                debug!("Successfully initialized upstream channel with {addr}");
-
-                                return Ok(Self {
-                                    upstream_channel_state,
-                                });
-                            }
-                            Err(e) => {
-                                error!("Failed Noise handshake with {addr}: {e:?}. Retrying...");
-                            }
-                        }
+                        let (reader, writer) = stream.into_split();
+
+                        let (outbound_tx, outbound_rx) = unbounded();
+                        let (inbound_tx, inbound_rx) = unbounded();
+
+                        let traffic = spawn_io_tasks(
+                            task_manager,
+                            reader,
+                            writer,
+                            outbound_rx,
+                            inbound_tx,
+                            notify_shutdown,
+                            UpstreamIoStatus,
+                            idle_timeout,
+                        );
+
+                        let upstream_channel_state = UpstreamChannelState::new(
+                            channel_manager_sender,
+                            channel_manager_receiver,
+                            inbound_rx,
+                            outbound_tx,
+                        );
+                        debug!("Successfully initialized upstream channel with {addr}");
+
+                        return Ok(Self {
+                            upstream_channel_state,
+                            address: *addr,
+                            traffic,
+                        });
                     }
                     Err(e) => {
                         error!(
-                            "Failed to connect to {addr}: {e}. Retry {attempt}/{RETRIES_PER_UPSTREAM}..."
+                            "Failed to connect to {addr}: {e:?}. Retry {attempt}/{RETRIES_PER_UPSTREAM}..."
                         );
                     }
                 }
@@ -144,6 +144,16 @@ impl Upstream {
         Err(TproxyError::Shutdown)
     }
 
+    /// Returns the address of the upstream server this connection was established with.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns the frame and byte counters of the connection.
+    pub fn traffic(&self) -> Arc<ConnectionTraffic> {
+        self.traffic.clone()
+    }
+
     /// Starts the upstream connection and begins message processing.
     ///
     /// This method:
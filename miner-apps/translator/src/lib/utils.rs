@@ -1,9 +1,6 @@
-use std::sync::Arc;
-
-use async_channel::{Receiver, Sender};
 use stratum_apps::{
     custom_mutex::Mutex,
-    network_helpers::noise_stream::{NoiseTcpReadHalf, NoiseTcpWriteHalf},
+    runtime::IoTaskStatus,
     stratum_core::{
         binary_sv2::{Sv2DataType, U256},
         bitcoin::{
@@ -17,50 +14,14 @@ use stratum_apps::{
             target::{bytes_to_hex, u256_to_block_hash},
         },
         codec_sv2::StandardSv2Frame,
-        framing_sv2::framing::{Frame, Sv2Frame},
+        framing_sv2::framing::Sv2Frame,
         parsers_sv2::AnyMessage,
-        sv1_api::{client_to_server, utils::HexU32Be},
+        sv1_api::{client_to_server, json_rpc, utils::HexU32Be},
     },
 };
+use tracing::debug;
 
-use stratum_apps::stratum_core::{
-    common_messages_sv2::{
-        MESSAGE_TYPE_CHANNEL_ENDPOINT_CHANGED, MESSAGE_TYPE_RECONNECT,
-        MESSAGE_TYPE_SETUP_CONNECTION, MESSAGE_TYPE_SETUP_CONNECTION_ERROR,
-        MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
-    },
-    job_declaration_sv2::{
-        MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN, MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS,
-        MESSAGE_TYPE_DECLARE_MINING_JOB, MESSAGE_TYPE_DECLARE_MINING_JOB_ERROR,
-        MESSAGE_TYPE_DECLARE_MINING_JOB_SUCCESS, MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS,
-        MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS_SUCCESS, MESSAGE_TYPE_PUSH_SOLUTION,
-    },
-    mining_sv2::{
-        MESSAGE_TYPE_CLOSE_CHANNEL, MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
-        MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB, MESSAGE_TYPE_NEW_MINING_JOB,
-        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
-        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS, MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR,
-        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL,
-        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS, MESSAGE_TYPE_SET_CUSTOM_MINING_JOB,
-        MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_ERROR, MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_SUCCESS,
-        MESSAGE_TYPE_SET_EXTRANONCE_PREFIX, MESSAGE_TYPE_SET_GROUP_CHANNEL,
-        MESSAGE_TYPE_SET_TARGET, MESSAGE_TYPE_SUBMIT_SHARES_ERROR,
-        MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED, MESSAGE_TYPE_SUBMIT_SHARES_STANDARD,
-        MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS, MESSAGE_TYPE_UPDATE_CHANNEL,
-        MESSAGE_TYPE_UPDATE_CHANNEL_ERROR,
-    },
-    template_distribution_sv2::{
-        MESSAGE_TYPE_COINBASE_OUTPUT_CONSTRAINTS, MESSAGE_TYPE_NEW_TEMPLATE,
-        MESSAGE_TYPE_REQUEST_TRANSACTION_DATA, MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_ERROR,
-        MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_SUCCESS, MESSAGE_TYPE_SET_NEW_PREV_HASH,
-        MESSAGE_TYPE_SUBMIT_SOLUTION,
-    },
-};
-
-use tokio::sync::broadcast;
-use tracing::{debug, error, trace, warn, Instrument};
-
-use crate::{error::TproxyError, task_manager::TaskManager};
+use crate::error::TproxyError;
 
 /// Type alias for SV2 messages with static lifetime
 pub type Message = AnyMessage<'static>;
@@ -69,14 +30,33 @@ pub type StdFrame = StandardSv2Frame<Message>;
 /// Type alias for sv2 frame
 pub type SV2Frame = Sv2Frame<Message, buffer_sv2::Slice>;
 
+/// General purpose version bits that can be rolled by miners, as specified in BIP320.
+pub const BIP320_VERSION_ROLLING_MASK: u32 = 0x1FFF_E000;
+
+/// Returns the version bits a downstream may roll on a job.
+///
+/// This is the mask negotiated through `mining.configure`, restricted to the BIP320 bits, or no
+/// bits at all if the miner did not negotiate version rolling or the upstream job does not allow
+/// it (`version_rolling_allowed` of the SV2 `NewExtendedMiningJob`).
+pub fn effective_version_rolling_mask(
+    negotiated_mask: Option<&HexU32Be>,
+    version_rolling_allowed: bool,
+) -> u32 {
+    match negotiated_mask {
+        Some(mask) if version_rolling_allowed => mask.0 & BIP320_VERSION_ROLLING_MASK,
+        _ => 0,
+    }
+}
+
 /// Validates an SV1 share against the target difficulty and job parameters.
 ///
 /// This function performs complete share validation by:
 /// 1. Finding the corresponding job from the valid jobs storage
-/// 2. Constructing the full extranonce from extranonce1 and extranonce2
-/// 3. Calculating the merkle root from the coinbase transaction and merkle path
-/// 4. Building the block header with the share's nonce and timestamp
-/// 5. Hashing the header and comparing against the target difficulty
+/// 2. Checking that the share only rolls version bits allowed by the negotiated mask and the job
+/// 3. Constructing the full extranonce from extranonce1 and extranonce2
+/// 4. Calculating the merkle root from the coinbase transaction and merkle path
+/// 5. Building the block header with the share's nonce and timestamp
+/// 6. Hashing the header and comparing against the target difficulty
 ///
 /// # Arguments
 /// * `share` - The SV1 submit message containing the share data
@@ -89,7 +69,8 @@ pub type SV2Frame = Sv2Frame<Message, buffer_sv2::Slice>;
 /// # Returns
 /// * `Ok(true)` if the share is valid and meets the target
 /// * `Ok(false)` if the share is valid but doesn't meet the target
-/// * `Err(TproxyError)` if validation fails due to missing job or invalid data
+/// * `Err(TproxyError)` if validation fails due to missing job, out-of-mask version bits or invalid
+///   data
 pub fn validate_sv1_share(
     share: &client_to_server::Submit<'static>,
     target: Target,
@@ -107,30 +88,39 @@ pub fn validate_sv1_share(
                 // Aggregated mode: search in shared jobs
                 aggregated_jobs
                     .iter()
-                    .find(|job| job.job_id == job_id)
+                    .find(|job| job.notify.job_id == job_id)
                     .cloned()
             } else if let Some(ref non_aggregated_jobs) = server_data.non_aggregated_valid_jobs {
                 // Non-aggregated mode: search in channel-specific jobs
                 non_aggregated_jobs
                     .get(&channel_id)
-                    .and_then(|channel_jobs| channel_jobs.iter().find(|job| job.job_id == job_id))
+                    .and_then(|channel_jobs| {
+                        channel_jobs.iter().find(|job| job.notify.job_id == job_id)
+                    })
                     .cloned()
             } else {
                 None
             }
         })
         .ok_or(TproxyError::JobNotFound)?;
+    let mask =
+        effective_version_rolling_mask(version_rolling_mask.as_ref(), job.version_rolling_allowed);
+    let job = job.notify;
 
     let mut full_extranonce = vec![];
     full_extranonce.extend_from_slice(extranonce1.as_slice());
     full_extranonce.extend_from_slice(share.extra_nonce2.0.as_ref());
 
-    let share_version = share
-        .version_bits
-        .clone()
-        .map(|vb| vb.0)
-        .unwrap_or(job.version.0);
-    let mask = version_rolling_mask.unwrap_or(HexU32Be(0x1FFFE000_u32)).0;
+    let share_version = match share.version_bits.clone() {
+        Some(version_bits) if version_bits.0 & !mask != 0 => {
+            return Err(TproxyError::VersionBitsOutOfMask {
+                version_bits: version_bits.0,
+                mask,
+            });
+        }
+        Some(version_bits) => version_bits.0,
+        None => job.version.0,
+    };
     let version = (job.version.0 & !mask) | (share_version & mask);
 
     let prev_hash_vec: Vec<u8> = job.prev_hash.clone().into();
@@ -206,6 +196,31 @@ pub fn proxy_extranonce_prefix_len(
     channel_rollable_extranonce_size - downstream_rollable_extranonce_size
 }
 
+/// Extracts the difficulty from a `mining.suggest_difficulty` request.
+///
+/// The difficulty is expected as the first element of the `params` array, although a bare number
+/// is accepted as well.
+///
+/// # Returns
+/// The suggested difficulty, or `None` if it is missing, not a number or not strictly positive
+pub fn parse_suggested_difficulty(request: &json_rpc::StandardRequest) -> Option<f64> {
+    let param = match &request.params {
+        serde_json::Value::Array(params) => params.first()?,
+        param => param,
+    };
+    param
+        .as_f64()
+        .filter(|difficulty| difficulty.is_finite() && *difficulty > 0.0)
+}
+
+/// Converts an SV1 difficulty into the hashrate (in h/s) producing `shares_per_minute` shares
+/// at that difficulty.
+///
+/// A difficulty 1 share requires on average 2^32 hashes.
+pub fn difficulty_to_hashrate(difficulty: f64, shares_per_minute: f64) -> f64 {
+    difficulty * 2f64.powi(32) * shares_per_minute / 60.0
+}
+
 /// Messages used for coordinating shutdown across different components.
 ///
 /// This enum defines the different types of shutdown signals that can be sent
@@ -218,213 +233,19 @@ pub enum ShutdownMessage {
     DownstreamShutdownAll,
     /// Shutdown a specific downstream connection by ID
     DownstreamShutdown(u32),
-    /// Reset channel manager state and shutdown downstreams due to upstream reconnection
-    UpstreamReconnectedResetAndShutdownDownstreams,
+    /// The upstream reconnected: the channels of the downstreams are reopened on it
+    UpstreamReconnected,
 }
 
-#[track_caller]
-#[allow(clippy::too_many_arguments)]
-pub fn spawn_io_tasks(
-    task_manager: Arc<TaskManager>,
-    mut reader: NoiseTcpReadHalf<Message>,
-    mut writer: NoiseTcpWriteHalf<Message>,
-    outbound_rx: Receiver<SV2Frame>,
-    inbound_tx: Sender<SV2Frame>,
-    notify_shutdown: broadcast::Sender<ShutdownMessage>,
-) {
-    let caller = std::panic::Location::caller();
-    let inbound_tx_clone = inbound_tx.clone();
-    let outbound_rx_clone = outbound_rx.clone();
-    {
-        let mut shutdown_rx = notify_shutdown.subscribe();
-        task_manager.spawn(
-            async move {
-                trace!("Reader task started");
-                loop {
-                    tokio::select! {
-                        message = shutdown_rx.recv() => {
-                            if let Ok(ShutdownMessage::ShutdownAll) = message {
-                                trace!("Received global shutdown");
-                                inbound_tx.close();
-                                break;
-                            }
-                        }
-                        res = reader.read_frame() => {
-                            match res {
-                                Ok(frame) => {
-                                    match frame {
-                                        Frame::HandShake(frame) => {
-                                            error!(?frame, "Received handshake frame");
-                                            drop(frame);
-                                            break;
-                                        },
-                                        Frame::Sv2(sv2_frame) => {
-                                            trace!("Received inbound frame");
-                                            if let Err(e) = inbound_tx.send(sv2_frame).await {
-                                                inbound_tx.close();
-                                                error!(error=?e, "Failed to forward inbound frame");
-                                                break;
-                                            }
-                                        },
-                                    }
-                                }
-                                Err(e) => {
-                                    error!(error=?e, "Reader error");
-                                    inbound_tx.close();
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-                inbound_tx.close();
-                outbound_rx_clone.close();
-                drop(inbound_tx);
-                drop(outbound_rx_clone);
-                warn!("Reader task exited.");
-            }
-            .instrument(tracing::trace_span!(
-                "reader_task",
-                spawned_at = %format!("{}:{}", caller.file(), caller.line())
-            )),
-        );
-    }
-
-    {
-        let mut shutdown_rx = notify_shutdown.subscribe();
-
-        task_manager.spawn(
-            async move {
-                trace!("Writer task started");
-                loop {
-                    tokio::select! {
-                        message = shutdown_rx.recv() => {
-                            if let Ok(ShutdownMessage::ShutdownAll) = message {
-                                trace!("Received global shutdown");
-                                inbound_tx_clone.close();
-                                break;
-                            }
-                        }
-                        res = outbound_rx.recv() => {
-                            match res {
-                                Ok(frame) => {
-                                    trace!("Sending outbound frame");
-                                    if let Err(e) = writer.write_frame(frame.into()).await {
-                                        error!(error=?e, "Writer error");
-                                        outbound_rx.close();
-                                        break;
-                                    }
-                                }
-                                Err(_) => {
-                                    outbound_rx.close();
-                                    warn!("Outbound channel closed");
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-                outbound_rx.close();
-                inbound_tx_clone.close();
-                drop(outbound_rx);
-                drop(inbound_tx_clone);
-                warn!("Writer task exited.");
-            }
-            .instrument(tracing::trace_span!(
-                "writer_task",
-                spawned_at = %format!("{}:{}", caller.file(), caller.line())
-            )),
-        );
-    }
-}
-
-pub fn is_common_message(message_type: u8) -> bool {
-    matches!(
-        message_type,
-        MESSAGE_TYPE_SETUP_CONNECTION
-            | MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS
-            | MESSAGE_TYPE_SETUP_CONNECTION_ERROR
-            | MESSAGE_TYPE_CHANNEL_ENDPOINT_CHANGED
-            | MESSAGE_TYPE_RECONNECT
-    )
-}
-
-pub fn is_mining_message(message_type: u8) -> bool {
-    matches!(
-        message_type,
-        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL
-            | MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS
-            | MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR
-            | MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL
-            | MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS
-            | MESSAGE_TYPE_NEW_MINING_JOB
-            | MESSAGE_TYPE_UPDATE_CHANNEL
-            | MESSAGE_TYPE_UPDATE_CHANNEL_ERROR
-            | MESSAGE_TYPE_CLOSE_CHANNEL
-            | MESSAGE_TYPE_SET_EXTRANONCE_PREFIX
-            | MESSAGE_TYPE_SUBMIT_SHARES_STANDARD
-            | MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED
-            | MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS
-            | MESSAGE_TYPE_SUBMIT_SHARES_ERROR
-            // | MESSAGE_TYPE_RESERVED
-            | 0x1e
-            | MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB
-            | MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH
-            | MESSAGE_TYPE_SET_TARGET
-            | MESSAGE_TYPE_SET_CUSTOM_MINING_JOB
-            | MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_SUCCESS
-            | MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_ERROR
-            | MESSAGE_TYPE_SET_GROUP_CHANNEL
-    )
-}
-
-pub fn is_job_declaration_message(message_type: u8) -> bool {
-    matches!(
-        message_type,
-        MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN
-            | MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS
-            | MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS
-            | MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS_SUCCESS
-            | MESSAGE_TYPE_DECLARE_MINING_JOB
-            | MESSAGE_TYPE_DECLARE_MINING_JOB_SUCCESS
-            | MESSAGE_TYPE_DECLARE_MINING_JOB_ERROR
-            | MESSAGE_TYPE_PUSH_SOLUTION
-    )
-}
-
-pub fn is_template_distribution_message(message_type: u8) -> bool {
-    matches!(
-        message_type,
-        MESSAGE_TYPE_COINBASE_OUTPUT_CONSTRAINTS
-            | MESSAGE_TYPE_NEW_TEMPLATE
-            | MESSAGE_TYPE_SET_NEW_PREV_HASH
-            | MESSAGE_TYPE_REQUEST_TRANSACTION_DATA
-            | MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_SUCCESS
-            | MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_ERROR
-            | MESSAGE_TYPE_SUBMIT_SOLUTION
-    )
-}
-
-#[derive(Debug, PartialEq, Eq)]
-pub enum MessageType {
-    Common,
-    Mining,
-    JobDeclaration,
-    TemplateDistribution,
-    Unknown,
-}
+/// Status of the upstream connection I/O tasks, stopped on [`ShutdownMessage::ShutdownAll`] only.
+///
+/// The upstream notices a closed or idle connection through its channels, so nothing is reported.
+#[derive(Debug, Clone, Copy)]
+pub struct UpstreamIoStatus;
 
-pub fn protocol_message_type(message_type: u8) -> MessageType {
-    if is_common_message(message_type) {
-        MessageType::Common
-    } else if is_mining_message(message_type) {
-        MessageType::Mining
-    } else if is_job_declaration_message(message_type) {
-        MessageType::JobDeclaration
-    } else if is_template_distribution_message(message_type) {
-        MessageType::TemplateDistribution
-    } else {
-        MessageType::Unknown
+impl IoTaskStatus<ShutdownMessage> for UpstreamIoStatus {
+    fn stops_on(&self, message: &ShutdownMessage) -> bool {
+        matches!(message, ShutdownMessage::ShutdownAll)
     }
 }
 
@@ -439,12 +260,66 @@ mod tests {
         assert_eq!(proxy_extranonce_prefix_len(4, 4), 0);
     }
 
+    #[test]
+    fn test_effective_version_rolling_mask() {
+        let negotiated = HexU32Be(0xFFFF_FFFF);
+        assert_eq!(
+            effective_version_rolling_mask(Some(&negotiated), true),
+            BIP320_VERSION_ROLLING_MASK
+        );
+        assert_eq!(
+            effective_version_rolling_mask(Some(&HexU32Be(0x0000_6000)), true),
+            0x0000_6000
+        );
+        assert_eq!(effective_version_rolling_mask(Some(&negotiated), false), 0);
+        assert_eq!(effective_version_rolling_mask(None, true), 0);
+    }
+
+    #[test]
+    fn test_parse_suggested_difficulty() {
+        let request = |params| json_rpc::StandardRequest {
+            id: 1,
+            method: "mining.suggest_difficulty".to_string(),
+            params,
+        };
+        assert_eq!(
+            parse_suggested_difficulty(&request(serde_json::json!([1024]))),
+            Some(1024.0)
+        );
+        assert_eq!(
+            parse_suggested_difficulty(&request(serde_json::json!(0.5))),
+            Some(0.5)
+        );
+        assert_eq!(
+            parse_suggested_difficulty(&request(serde_json::json!([]))),
+            None
+        );
+        assert_eq!(
+            parse_suggested_difficulty(&request(serde_json::json!(["high"]))),
+            None
+        );
+        assert_eq!(
+            parse_suggested_difficulty(&request(serde_json::json!([-1]))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_difficulty_to_hashrate() {
+        // Difficulty 1 at one share per minute is 2^32 hashes per minute.
+        assert_eq!(difficulty_to_hashrate(1.0, 1.0), 2f64.powi(32) / 60.0);
+        assert_eq!(
+            difficulty_to_hashrate(1024.0, 6.0),
+            1024.0 * 2f64.powi(32) / 10.0
+        );
+    }
+
     #[test]
     fn test_shutdown_message_debug() {
         let msg1 = ShutdownMessage::ShutdownAll;
         let msg2 = ShutdownMessage::DownstreamShutdown(123);
         let msg3 = ShutdownMessage::DownstreamShutdownAll;
-        let msg4 = ShutdownMessage::UpstreamReconnectedResetAndShutdownDownstreams;
+        let msg4 = ShutdownMessage::UpstreamReconnected;
 
         // Test Debug implementation
         assert!(format!("{:?}", msg1).contains("ShutdownAll"));
@@ -1,7 +1,8 @@
 mod args;
-use std::process;
-
-use stratum_apps::config_helpers::logging::init_logging;
+use stratum_apps::{
+    config_helpers::logging::{init_logging, shutdown_logging},
+    exit::ExitStatus,
+};
 pub use translator_sv2::{config, error, status, sv1, sv2, TranslatorSv2};
 
 use crate::args::process_cli_args;
@@ -9,17 +10,27 @@ use crate::args::process_cli_args;
 /// Entrypoint for the Translator binary.
 ///
 /// Loads the configuration from TOML and initializes the main runtime
-/// defined in `translator_sv2::TranslatorSv2`. Errors during startup are logged.
+/// defined in `translator_sv2::TranslatorSv2`. Errors are logged, and the process exits with
+/// their [`ExitStatus`].
 #[tokio::main]
 async fn main() {
     let proxy_config = process_cli_args().unwrap_or_else(|e| {
         eprintln!("Translator proxy config error: {e}");
-        std::process::exit(1);
+        e.exit_status().exit();
     });
 
-    init_logging(proxy_config.log_dir());
+    init_logging(proxy_config.log_dir(), &proxy_config.logging);
 
-    TranslatorSv2::new(proxy_config).start().await;
+    let status = match TranslatorSv2::new(proxy_config).start().await {
+        Ok(()) => ExitStatus::Success,
+        Err(e) => {
+            let status = e.exit_status();
+            tracing::error!("Translator stopped: {e}, exiting with {status}");
+            status
+        }
+    };
+    shutdown_logging();
 
-    process::exit(1);
+    // Exits right away, rather than waiting for the tasks left behind by the runtime.
+    status.exit();
 }
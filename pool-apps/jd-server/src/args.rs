@@ -1,19 +1,35 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
 use clap::Parser;
-use ext_config::{Config, File, FileFormat};
 use jd_server::{
     config::JobDeclaratorServerConfig,
     error::JdsError,
+    Uri,
     // error::{Error, ProxyResult},
 };
 
-use tracing::error;
+use stratum_apps::{
+    banlist::{BanList, BanTarget},
+    config_helpers::{self, ConfigCheck, ConfigProfile},
+    persistence::PersistenceConfig,
+};
+
+/// Prefix of the environment variables overriding the configuration file, e.g.
+/// `SV2_JDS__LISTEN_JD_ADDRESS`.
+pub const ENV_PREFIX: &str = "SV2_JDS";
 
 /// CLI argument parser for the JDS binary.
 ///
 /// Supports the following flags:
 /// - `-c`, `--config`: specify a custom config file path
+/// - `--check-config`: validate the config file, print a report and exit
+/// - `--generate-config [profile]`: write an example config with fresh authority keys and exit
+/// - `-o`, `--output`: file written by `--generate-config` instead of stdout
+/// - `--ban <target>`, `--unban <target>`, `--list-bans`: manage the ban list file and exit
 /// - `-h`, `--help`: print help and usage info
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Job Declarator Server (JDS)", long_about = None)]
@@ -21,7 +37,7 @@ pub struct Args {
     #[arg(
         short = 'c',
         long = "config",
-        help = "Path to the TOML configuration file",
+        help = "Path to the configuration file, in TOML, YAML (.yaml, .yml) or JSON (.json)",
         default_value = "jds-config.toml"
     )]
     pub config_path: std::path::PathBuf,
@@ -31,6 +47,58 @@ pub struct Args {
         help = "Path to the log file. If not set, logs will only be written to stdout."
     )]
     pub log_file: Option<PathBuf>,
+    #[arg(
+        long = "check-config",
+        help = "Validate the configuration file, print a report and exit without starting the JDS"
+    )]
+    pub check_config: bool,
+    #[arg(
+        long = "generate-config",
+        value_name = "PROFILE",
+        num_args = 0..=1,
+        default_missing_value = "testnet",
+        help = "Write an example configuration with fresh authority keys for the `mainnet`, `testnet` (default), `signet` or `regtest` profile and exit"
+    )]
+    pub generate_config: Option<ConfigProfile>,
+    #[arg(
+        short = 'o',
+        long = "output",
+        requires = "generate_config",
+        help = "File written by --generate-config instead of stdout. Existing files are not overwritten."
+    )]
+    pub output: Option<PathBuf>,
+    #[arg(
+        long = "ban",
+        value_name = "TARGET",
+        help = "Ban an IP address, a CIDR range or a public key in the ban list file and exit. A running JDS reloads the file"
+    )]
+    pub ban: Option<BanTarget>,
+    #[arg(
+        long = "ban-ttl",
+        value_name = "SECS",
+        requires = "ban",
+        help = "Seconds after which the ban set by --ban is lifted, permanent if unset"
+    )]
+    pub ban_ttl: Option<u64>,
+    #[arg(
+        long = "ban-reason",
+        value_name = "REASON",
+        requires = "ban",
+        help = "Reason recorded with the ban set by --ban"
+    )]
+    pub ban_reason: Option<String>,
+    #[arg(
+        long = "unban",
+        value_name = "TARGET",
+        conflicts_with = "ban",
+        help = "Lift the ban of an IP address, a CIDR range or a public key in the ban list file and exit"
+    )]
+    pub unban: Option<BanTarget>,
+    #[arg(
+        long = "list-bans",
+        help = "Print the bans of the ban list file and exit"
+    )]
+    pub list_bans: bool,
 }
 
 /// Process CLI args and load configuration.
@@ -38,26 +106,32 @@ pub struct Args {
 pub fn process_cli_args() -> Result<JobDeclaratorServerConfig, JdsError> {
     // Parse CLI arguments
     let args = Args::parse();
+    if let Some(profile) = args.generate_config {
+        let config = generate_config(profile);
+        if let Err(e) = config_helpers::write_generated_config(&config, args.output.as_deref()) {
+            eprintln!("Failed to write the generated config: {e}");
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    if args.check_config {
+        check_config(&args.config_path).exit();
+    }
+    if args.ban.is_some() || args.unban.is_some() || args.list_bans {
+        match manage_bans(&args) {
+            Ok(output) => println!("{output}"),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
 
-    // Build configuration from the provided file path
-    let config_path = args.config_path.to_str().ok_or_else(|| {
-        error!("Invalid configuration path.");
-        JdsError::BadCliArgs
-    })?;
-
-    let settings = Config::builder()
-        .add_source(File::new(config_path, FileFormat::Toml))
-        .build()
-        .map_err(|e| {
-            error!("Failed to build config: {}", e);
-            JdsError::BadCliArgs
-        })?;
-
-    // Deserialize settings into JobDeclaratorServerConfig
-    let mut config = settings
-        .try_deserialize::<JobDeclaratorServerConfig>()
-        .map_err(|e| {
-            error!("Failed to deserialize config: {}", e);
+    // Build configuration from the provided file path and the environment
+    let mut config: JobDeclaratorServerConfig =
+        config_helpers::load_config(&args.config_path, ENV_PREFIX).map_err(|e| {
+            eprintln!("Failed to load config: {}", e);
             JdsError::BadCliArgs
         })?;
 
@@ -65,3 +139,220 @@ pub fn process_cli_args() -> Result<JobDeclaratorServerConfig, JdsError> {
 
     Ok(config)
 }
+
+/// Loads the configuration at `config_path` and checks it without starting any service.
+///
+/// The Bitcoin Core RPC URL is only parsed: the node is not contacted.
+pub fn check_config(config_path: &Path) -> ConfigCheck {
+    let mut report = ConfigCheck::new();
+    let parsed: Result<JobDeclaratorServerConfig, _> =
+        config_helpers::load_config(config_path, ENV_PREFIX);
+    let config = match parsed {
+        Ok(config) => config,
+        Err(e) => {
+            report.fail(format!("parse {}", config_path.display()), e);
+            return report;
+        }
+    };
+    report.pass(format!("parse {}", config_path.display()), "ok");
+    report.resolvable_address("listen address", config.listen_jd_address());
+    report.check(
+        "handshake timeout",
+        config
+            .check_handshake_timeout()
+            .map(|()| format!("{}s", config.handshake_timeout().as_secs())),
+    );
+    report.authority_keypair(config.authority_public_key(), config.authority_secret_key());
+    report.check(
+        "certificate validity",
+        match config.cert_validity_sec() {
+            0 => Err("`cert_validity_sec` must be positive".to_string()),
+            secs => Ok(format!("{secs}s")),
+        },
+    );
+    report.coinbase_reward_script(config.coinbase_reward_scripts());
+    report.check(
+        "network",
+        config.check_network().map(|()| match config.network() {
+            Some(network) => network.to_string(),
+            None => "not set".to_string(),
+        }),
+    );
+    report.check(
+        "Bitcoin Core RPC URL",
+        match config.core_rpc_port() {
+            Some(port) => {
+                let url = format!("{}:{port}", config.core_rpc_url().trim_end_matches('/'));
+                Uri::from_str(&url).map_err(|e| format!("`{url}` is not a valid URI: {e}"))
+            }
+            None => Err("`core_rpc_port` must be set when `network` is not".to_string()),
+        },
+    );
+    match config.persistence() {
+        Some(PersistenceConfig::File { path }) => report.appendable_file("persistence", path),
+        None => report.pass("persistence", "disabled"),
+    }
+    report.check(
+        "RPC client",
+        config.rpc_client().check().map(|()| {
+            format!(
+                "{}ms timeout, {} concurrent requests",
+                config.rpc_client().timeout().as_millis(),
+                config.rpc_client().max_concurrent_requests()
+            )
+        }),
+    );
+    if let Some(p2p_mempool) = config.p2p_mempool() {
+        report.socket_address("P2P mempool address", p2p_mempool.address());
+    }
+    report.banlist(config.banlist());
+    if let Some(monitoring) = config.monitoring() {
+        report.pass("monitoring address", monitoring.listen_address);
+    }
+    report
+}
+
+/// Applies the `--ban`, `--unban` and `--list-bans` flags to the ban list file of the
+/// configuration, returning what to print.
+fn manage_bans(args: &Args) -> Result<String, String> {
+    let config: JobDeclaratorServerConfig =
+        config_helpers::load_config(&args.config_path, ENV_PREFIX)
+            .map_err(|e| format!("Failed to load config: {e}"))?;
+    let Some(file) = &config.banlist().file else {
+        return Err("No ban list file: set `file` in the `[banlist]` table".to_string());
+    };
+    let banlist = BanList::new(config.banlist())
+        .map_err(|e| format!("Failed to load {}: {e}", file.display()))?;
+    if let Some(target) = args.ban {
+        let ttl = args.ban_ttl.map(Duration::from_secs);
+        let ban = banlist
+            .ban(target, ttl, args.ban_reason.clone())
+            .map_err(|e| format!("Failed to save {}: {e}", file.display()))?;
+        return Ok(format!("Banned {ban}"));
+    }
+    if let Some(target) = args.unban {
+        return match banlist.unban(&target) {
+            Ok(true) => Ok(format!("Lifted the ban of {target}")),
+            Ok(false) => Err(format!("{target} is not banned")),
+            Err(e) => Err(format!("Failed to save {}: {e}", file.display())),
+        };
+    }
+    let bans = banlist.list();
+    if bans.is_empty() {
+        return Ok("No bans".to_string());
+    }
+    Ok(bans
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// Renders a commented configuration for `profile`, with a fresh authority keypair.
+pub fn generate_config(profile: ConfigProfile) -> String {
+    let (authority_public_key, authority_secret_key) = config_helpers::generate_authority_keys();
+    let network = profile.network();
+    let rpc_port = profile.bitcoin_rpc_port();
+    let coinbase_reward_script = profile.coinbase_reward_script();
+    format!(
+        r#"# SRI JDS config ({profile}), generated with --generate-config
+# Every setting can be overridden by an environment variable named after it, prefixed with
+# SV2_JDS__ (nested tables separated by __). Validate this file with --check-config.
+
+# Bitcoin network: "mainnet", "testnet4", "signet" or "regtest". The coinbase reward address must
+# belong to it, a Bitcoin Core RPC on the default port of another network is refused,
+# `core_rpc_port` defaults to the RPC port of the network, and persisted events are tagged with it.
+network = "{network}"
+
+# If set to true, JDS require JDC to reveal the transactions they are going to mine on
+full_template_mode_required = true
+
+# Authority keypair of the Noise handshakes. JDCs pin a single key for the Pool and its JDS, so
+# use the keypair of the Pool this JDS serves. Keep the secret key secret.
+authority_public_key = "{authority_public_key}"
+authority_secret_key = "{authority_secret_key}"
+# Instead of the line above, the secret key can be read from a file or an environment variable:
+# authority_secret_key_file = "/run/secrets/authority_key"
+# authority_secret_key_env = "AUTHORITY_SECRET_KEY"
+cert_validity_sec = 3600
+
+# Coinbase outputs are specified as descriptors. A full list of descriptors is available at
+#     https://github.com/bitcoin/bips/blob/master/bip-0380.mediawiki#appendix-b-index-of-script-expressions
+# REPLACE this placeholder with the address of the Pool: `addr(<address here>)`.
+coinbase_reward_script = "{coinbase_reward_script}"
+
+# Logs are written to this file if set, --log-file (or -f) overrides it.
+# log_file = "./jd-server.log"
+
+listen_jd_address = "0.0.0.0:34264"
+# Transport of JDC connections: "noise" (default) or "none" for plaintext SV2 frames.
+# listen_encryption = "none"
+# Seconds an accepted JDC may take to complete its Noise handshake and SetupConnection before
+# being dropped (10 by default).
+# handshake_timeout_secs = 10
+# Bitcoin Core RPC used to keep the mempool in sync
+core_rpc_url = "http://127.0.0.1"
+core_rpc_port = {rpc_port}
+core_rpc_user = "username"
+core_rpc_pass = "password"
+# or core_rpc_pass_file = "/run/secrets/rpc_pass", core_rpc_pass_env = "RPC_PASS"
+# Time interval used for JDS mempool update
+[mempool_update_interval]
+unit = "secs"
+value = 1
+
+# Enable this section to durably record every job declaration decision and every received
+# solution (one JSON object per line).
+# [persistence]
+# backend = "file"
+# path = "./jd-server-events.jsonl"
+
+# Per-connection rate limits for the expensive Job Declaration messages. Messages over the limit
+# are refused, and a connection is dropped after `max_violations` consecutive refusals.
+# [rate_limit]
+# max_violations = 20
+# [rate_limit.allocate_mining_job_token]
+# rate = 10.0
+# burst = 20
+# [rate_limit.declare_mining_job]
+# rate = 5.0
+# burst = 10
+
+# Requests to the Bitcoin Core RPC: up to `max_concurrent_requests` in flight at once, each failing
+# after `timeout_ms`. After `failure_threshold` consecutive failures the node is deemed overloaded
+# and requests are refused for `cooldown_secs`, `submitblock` excepted.
+# [rpc_client]
+# timeout_ms = 5000
+# max_concurrent_requests = 16
+# failure_threshold = 5
+# cooldown_secs = 30
+
+# Synchronizes the mempool from the transactions relayed by the node at `address`, its P2P port,
+# instead of requesting each declared transaction over RPC. The mempool is no longer polled over
+# RPC while connected. Requires `network`. A node on the same host may be reached on a unix socket
+# as `address = "unix:/path/to/socket"`.
+# [p2p_mempool]
+# address = "127.0.0.1:48333"
+# reconnect_interval_secs = 10
+
+# Log format: "pretty" (default) or "json", one object per line carrying the fields of the
+# enclosing spans (connection id, channel id, user identity). Filter directives select the level
+# per module, RUST_LOG takes precedence over them.
+# [logging]
+# format = "json"
+# filter = "info,jd_server=debug"
+# Binaries built with the `otel` feature also export spans to an OTLP/gRPC collector.
+# otlp_endpoint = "http://localhost:4317"
+
+# Connections from banned IP addresses and CIDR ranges are refused. Bans are loaded from `file`,
+# managed with --ban, --unban and --list-bans, and reloaded when the file is modified.
+# [banlist]
+# file = "./jds-banlist.txt"
+
+# HTTP endpoint serving, as JSON on GET /status, the latency and outcome of the Bitcoin Core RPC
+# calls per method, the state of their circuit breaker and that of the P2P mempool synchronization.
+# [monitoring]
+# listen_address = "127.0.0.1:9092"
+"#
+    )
+}
@@ -17,26 +17,47 @@ use std::{
     time::Duration,
 };
 use stratum_apps::{
-    config_helpers::CoinbaseRewardScript,
+    banlist::BanListConfig,
+    config_helpers::{logging::LoggingConfig, BitcoinNetwork, CoinbaseRewardScript},
+    encryption::Encryption,
     key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+    monitoring::MonitoringConfig,
+    persistence::PersistenceConfig,
+    rate_limit::TokenBucketConfig,
+    runtime::DEFAULT_HANDSHAKE_TIMEOUT,
 };
 
 #[derive(Debug, serde::Deserialize, Clone)]
 pub struct JobDeclaratorServerConfig {
+    network: Option<BitcoinNetwork>,
     #[serde(default = "default_true")]
     full_template_mode_required: bool,
     listen_jd_address: String,
+    #[serde(default)]
+    listen_encryption: Encryption,
+    handshake_timeout_secs: Option<u64>,
     authority_public_key: Secp256k1PublicKey,
     authority_secret_key: Secp256k1SecretKey,
     cert_validity_sec: u64,
     coinbase_reward_script: CoinbaseRewardScript,
     core_rpc_url: String,
-    core_rpc_port: u16,
+    core_rpc_port: Option<u16>,
     core_rpc_user: String,
     core_rpc_pass: String,
     #[serde(deserialize_with = "stratum_apps::config_helpers::duration_from_toml")]
     mempool_update_interval: Duration,
+    #[serde(default)]
+    rpc_client: RpcClientConfig,
+    p2p_mempool: Option<P2pMempoolConfig>,
     log_file: Option<PathBuf>,
+    #[serde(default)]
+    logging: LoggingConfig,
+    persistence: Option<PersistenceConfig>,
+    #[serde(default)]
+    rate_limit: RateLimitConfig,
+    #[serde(default)]
+    banlist: BanListConfig,
+    monitoring: Option<MonitoringConfig>,
 }
 
 impl JobDeclaratorServerConfig {
@@ -55,18 +76,49 @@ impl JobDeclaratorServerConfig {
         mempool_update_interval: Duration,
     ) -> Self {
         Self {
+            network: None,
             full_template_mode_required: true,
             listen_jd_address,
+            listen_encryption: Encryption::default(),
+            handshake_timeout_secs: None,
             authority_public_key,
             authority_secret_key,
             cert_validity_sec,
             coinbase_reward_script,
             core_rpc_url: core_rpc.url,
-            core_rpc_port: core_rpc.port,
+            core_rpc_port: Some(core_rpc.port),
             core_rpc_user: core_rpc.user,
             core_rpc_pass: core_rpc.pass,
             mempool_update_interval,
+            rpc_client: RpcClientConfig::default(),
+            p2p_mempool: None,
             log_file: None,
+            logging: LoggingConfig::default(),
+            persistence: None,
+            banlist: BanListConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            monitoring: None,
+        }
+    }
+
+    /// Returns the Bitcoin network the JDS is deployed on, if set.
+    pub fn network(&self) -> Option<BitcoinNetwork> {
+        self.network
+    }
+
+    /// Checks the coinbase reward script and the Bitcoin Core RPC port against the network, if
+    /// set. The network must be set to synchronize the mempool over P2P.
+    pub fn check_network(&self) -> Result<(), String> {
+        let Some(network) = self.network else {
+            if self.p2p_mempool.is_some() {
+                return Err("`network` must be set to synchronize the mempool over P2P".to_string());
+            }
+            return Ok(());
+        };
+        network.check_coinbase_reward_script(&self.coinbase_reward_script)?;
+        match self.core_rpc_port {
+            Some(port) => network.check_bitcoin_rpc_port(port),
+            None => Ok(()),
         }
     }
 
@@ -75,6 +127,27 @@ impl JobDeclaratorServerConfig {
         &self.listen_jd_address
     }
 
+    /// Returns the encryption of the JDC listener.
+    pub fn listen_encryption(&self) -> Encryption {
+        self.listen_encryption
+    }
+
+    /// Returns how long an accepted connection may take to complete its Noise handshake and
+    /// `SetupConnection` before being dropped.
+    pub fn handshake_timeout(&self) -> Duration {
+        self.handshake_timeout_secs
+            .map_or(DEFAULT_HANDSHAKE_TIMEOUT, Duration::from_secs)
+    }
+
+    /// Checks that `handshake_timeout_secs` is positive, a zero timeout dropping every
+    /// connection.
+    pub fn check_handshake_timeout(&self) -> Result<(), String> {
+        match self.handshake_timeout_secs {
+            Some(0) => Err("`handshake_timeout_secs` must be positive".to_string()),
+            _ => Ok(()),
+        }
+    }
+
     /// Returns the public key of the authority.
     pub fn authority_public_key(&self) -> &Secp256k1PublicKey {
         &self.authority_public_key
@@ -90,9 +163,11 @@ impl JobDeclaratorServerConfig {
         &self.core_rpc_url
     }
 
-    /// Returns the port of the core RPC.
-    pub fn core_rpc_port(&self) -> u16 {
+    /// Returns the port of the core RPC, the default RPC port of the network if unset. `None`
+    /// if neither is set.
+    pub fn core_rpc_port(&self) -> Option<u16> {
         self.core_rpc_port
+            .or_else(|| self.network.map(BitcoinNetwork::bitcoin_rpc_port))
     }
 
     /// Returns the user of the core RPC.
@@ -129,6 +204,66 @@ impl JobDeclaratorServerConfig {
         self.mempool_update_interval
     }
 
+    /// Returns the timeouts, concurrency and circuit breaker of the requests to the core RPC.
+    pub fn rpc_client(&self) -> &RpcClientConfig {
+        &self.rpc_client
+    }
+
+    /// Sets the timeouts, concurrency and circuit breaker of the requests to the core RPC.
+    pub fn set_rpc_client(&mut self, rpc_client: RpcClientConfig) {
+        self.rpc_client = rpc_client;
+    }
+
+    /// Returns the P2P synchronization of the mempool, if enabled.
+    pub fn p2p_mempool(&self) -> Option<&P2pMempoolConfig> {
+        self.p2p_mempool.as_ref()
+    }
+
+    /// Sets the P2P synchronization of the mempool.
+    pub fn set_p2p_mempool(&mut self, p2p_mempool: Option<P2pMempoolConfig>) {
+        self.p2p_mempool = p2p_mempool;
+    }
+
+    /// Returns the persistence backend configuration, if any.
+    ///
+    /// When set, every job declaration decision and every received solution is recorded.
+    pub fn persistence(&self) -> Option<&PersistenceConfig> {
+        self.persistence.as_ref()
+    }
+
+    /// Sets the persistence backend configuration.
+    pub fn set_persistence(&mut self, persistence: Option<PersistenceConfig>) {
+        self.persistence = persistence;
+    }
+
+    /// Returns the per-connection rate limits applied to Job Declaration messages.
+    pub fn rate_limit(&self) -> &RateLimitConfig {
+        &self.rate_limit
+    }
+
+    /// Sets the per-connection rate limits applied to Job Declaration messages.
+    pub fn set_rate_limit(&mut self, rate_limit: RateLimitConfig) {
+        self.rate_limit = rate_limit;
+    }
+
+    /// Returns the ban list configuration.
+    ///
+    /// The JDS has no admin endpoint: its bans are managed in the ban list file, reloaded when
+    /// modified.
+    pub fn banlist(&self) -> &BanListConfig {
+        &self.banlist
+    }
+
+    /// Returns the monitoring endpoint configuration, if any.
+    pub fn monitoring(&self) -> Option<&MonitoringConfig> {
+        self.monitoring.as_ref()
+    }
+
+    /// Sets the monitoring endpoint configuration.
+    pub fn set_monitoring(&mut self, monitoring: Option<MonitoringConfig>) {
+        self.monitoring = monitoring;
+    }
+
     /// Sets the listening address of Bitcoin core RPC.
     pub fn set_core_rpc_url(&mut self, url: String) {
         self.core_rpc_url = url;
@@ -142,6 +277,11 @@ impl JobDeclaratorServerConfig {
     pub fn log_file(&self) -> Option<&Path> {
         self.log_file.as_deref()
     }
+    /// Returns the format and filter of the logs.
+    pub fn logging(&self) -> &LoggingConfig {
+        &self.logging
+    }
+
     pub fn set_log_file(&mut self, log_file: Option<PathBuf>) {
         if let Some(path) = log_file {
             self.log_file = Some(path);
@@ -153,6 +293,174 @@ fn default_true() -> bool {
     true
 }
 
+/// Per-connection rate limits for the expensive Job Declaration messages.
+///
+/// Each JDC connection gets its own token bucket for `AllocateMiningJobToken` and
+/// `DeclareMiningJob`. Messages over the limit are refused (with a `DeclareMiningJobError` for
+/// declarations), and the connection is dropped once `max_violations` consecutive messages have
+/// been refused.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    allocate_mining_job_token: TokenBucketConfig,
+    declare_mining_job: TokenBucketConfig,
+    max_violations: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            allocate_mining_job_token: TokenBucketConfig::new(10.0, 20),
+            declare_mining_job: TokenBucketConfig::new(5.0, 10),
+            max_violations: 20,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Creates a new instance of [`RateLimitConfig`].
+    pub fn new(
+        allocate_mining_job_token: TokenBucketConfig,
+        declare_mining_job: TokenBucketConfig,
+        max_violations: u32,
+    ) -> Self {
+        Self {
+            allocate_mining_job_token,
+            declare_mining_job,
+            max_violations,
+        }
+    }
+
+    /// Returns the token bucket applied to `AllocateMiningJobToken`.
+    pub fn allocate_mining_job_token(&self) -> TokenBucketConfig {
+        self.allocate_mining_job_token
+    }
+
+    /// Returns the token bucket applied to `DeclareMiningJob`.
+    pub fn declare_mining_job(&self) -> TokenBucketConfig {
+        self.declare_mining_job
+    }
+
+    /// Returns the number of consecutive refused messages after which a connection is dropped.
+    pub fn max_violations(&self) -> u32 {
+        self.max_violations
+    }
+}
+
+/// Requests to the core RPC.
+///
+/// Up to `max_concurrent_requests` requests are in flight at once, each failing after
+/// `timeout_ms`. Once `failure_threshold` consecutive requests failed or timed out, requests are
+/// refused for `cooldown_secs` before a single one probes the node again.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct RpcClientConfig {
+    timeout_ms: u64,
+    max_concurrent_requests: usize,
+    failure_threshold: u32,
+    cooldown_secs: u64,
+}
+
+impl Default for RpcClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 5_000,
+            max_concurrent_requests: 16,
+            failure_threshold: 5,
+            cooldown_secs: 30,
+        }
+    }
+}
+
+impl RpcClientConfig {
+    /// Creates a new instance of [`RpcClientConfig`].
+    pub fn new(
+        timeout_ms: u64,
+        max_concurrent_requests: usize,
+        failure_threshold: u32,
+        cooldown_secs: u64,
+    ) -> Self {
+        Self {
+            timeout_ms,
+            max_concurrent_requests,
+            failure_threshold,
+            cooldown_secs,
+        }
+    }
+
+    /// Returns how long the node may take to answer a request.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Returns the number of requests in flight at once, at least one.
+    pub fn max_concurrent_requests(&self) -> usize {
+        self.max_concurrent_requests.max(1)
+    }
+
+    /// Returns the number of consecutive failed requests opening the circuit, at least one.
+    pub fn failure_threshold(&self) -> u32 {
+        self.failure_threshold.max(1)
+    }
+
+    /// Returns for how long requests are refused once the circuit opened.
+    pub fn cooldown(&self) -> Duration {
+        Duration::from_secs(self.cooldown_secs)
+    }
+
+    /// Checks that the requests can complete and be sent.
+    pub fn check(&self) -> Result<(), String> {
+        if self.timeout_ms == 0 {
+            return Err("`timeout_ms` must be positive".to_string());
+        }
+        if self.max_concurrent_requests == 0 {
+            return Err("`max_concurrent_requests` must be positive".to_string());
+        }
+        if self.failure_threshold == 0 {
+            return Err("`failure_threshold` must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Synchronization of the mempool from the transactions relayed by a Bitcoin node over P2P.
+///
+/// The JDS connects to the node at `address`, a `host:port` or a unix socket as `unix:<path>`, as
+/// a peer, keeps the transactions it announces and forgets those confirmed by the blocks it
+/// announces. The mempool is no longer polled over RPC while connected, and the connection is
+/// retried every `reconnect_interval_secs` once lost. Requires `network`, whose magic the
+/// messages carry.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct P2pMempoolConfig {
+    address: String,
+    #[serde(default = "default_reconnect_interval_secs")]
+    reconnect_interval_secs: u64,
+}
+
+impl P2pMempoolConfig {
+    /// Creates a new instance of [`P2pMempoolConfig`].
+    pub fn new(address: String, reconnect_interval_secs: u64) -> Self {
+        Self {
+            address,
+            reconnect_interval_secs,
+        }
+    }
+
+    /// Returns the P2P address of the node, `unix:<path>` for a unix socket.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns how long to wait before connecting to the node again, at least one second.
+    pub fn reconnect_interval(&self) -> Duration {
+        Duration::from_secs(self.reconnect_interval_secs.max(1))
+    }
+}
+
+fn default_reconnect_interval_secs() -> u64 {
+    10
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct CoreRpc {
     url: String,
@@ -179,7 +487,9 @@ mod tests {
     use std::path::PathBuf;
     use stratum_apps::stratum_core::bitcoin::{self, Amount, ScriptBuf, TxOut};
 
-    use crate::config::JobDeclaratorServerConfig;
+    use crate::config::{JobDeclaratorServerConfig, P2pMempoolConfig, RpcClientConfig};
+    use std::time::Duration;
+    use stratum_apps::rate_limit::TokenBucketConfig;
 
     const COINBASE_CONFIG_TEMPLATE: &'static str = r#"
         full_template_mode_required = true
@@ -293,4 +603,168 @@ mod tests {
             "Miniscript: unexpected «Error while parsing simple public key»",
         );
     }
+
+    #[test]
+    fn test_rate_limit_defaults_and_overrides() {
+        let pk = TEST_PK_HEX;
+        let config =
+            load_coinbase_config_str(&format!("\"wpkh({pk})\"")).expect("Failed to parse config");
+        assert_eq!(config.rate_limit().max_violations(), 20);
+        assert_eq!(
+            config.rate_limit().declare_mining_job(),
+            TokenBucketConfig::new(5.0, 10)
+        );
+
+        let s = COINBASE_CONFIG_TEMPLATE
+            .replace("%COINBASE_REWARD_SCRIPT%", &format!("\"wpkh({pk})\""))
+            + r#"
+        [rate_limit]
+        max_violations = 3
+        [rate_limit.declare_mining_job]
+        rate = 1.0
+        burst = 2
+    "#;
+        let config: JobDeclaratorServerConfig = Config::builder()
+            .add_source(File::from_str(&s, FileFormat::Toml))
+            .build()
+            .expect("Failed to build config")
+            .try_deserialize()
+            .expect("Failed to parse config");
+        assert_eq!(config.rate_limit().max_violations(), 3);
+        assert_eq!(
+            config.rate_limit().declare_mining_job(),
+            TokenBucketConfig::new(1.0, 2)
+        );
+        assert_eq!(
+            config.rate_limit().allocate_mining_job_token(),
+            TokenBucketConfig::new(10.0, 20)
+        );
+    }
+
+    #[test]
+    fn test_rpc_client_defaults_and_overrides() {
+        let pk = TEST_PK_HEX;
+        let config =
+            load_coinbase_config_str(&format!("\"wpkh({pk})\"")).expect("Failed to parse config");
+        assert_eq!(config.rpc_client(), &RpcClientConfig::default());
+        assert_eq!(config.rpc_client().timeout(), Duration::from_secs(5));
+        assert!(config.monitoring().is_none());
+
+        let s = COINBASE_CONFIG_TEMPLATE
+            .replace("%COINBASE_REWARD_SCRIPT%", &format!("\"wpkh({pk})\""))
+            + r#"
+        [rpc_client]
+        timeout_ms = 250
+        failure_threshold = 0
+        [monitoring]
+        listen_address = "127.0.0.1:9091"
+    "#;
+        let config: JobDeclaratorServerConfig = Config::builder()
+            .add_source(File::from_str(&s, FileFormat::Toml))
+            .build()
+            .expect("Failed to build config")
+            .try_deserialize()
+            .expect("Failed to parse config");
+        assert_eq!(config.rpc_client().timeout(), Duration::from_millis(250));
+        assert_eq!(config.rpc_client().max_concurrent_requests(), 16);
+        assert_eq!(config.rpc_client().cooldown(), Duration::from_secs(30));
+        assert!(config.rpc_client().check().is_err());
+        assert_eq!(
+            config
+                .monitoring()
+                .map(|monitoring| monitoring.listen_address),
+            Some("127.0.0.1:9091".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_p2p_mempool_requires_network() {
+        let pk = TEST_PK_HEX;
+        let config =
+            load_coinbase_config_str(&format!("\"wpkh({pk})\"")).expect("Failed to parse config");
+        assert!(config.p2p_mempool().is_none());
+
+        let s = COINBASE_CONFIG_TEMPLATE
+            .replace("%COINBASE_REWARD_SCRIPT%", &format!("\"wpkh({pk})\""))
+            + r#"
+        [p2p_mempool]
+        address = "127.0.0.1:48333"
+    "#;
+        let mut config: JobDeclaratorServerConfig = Config::builder()
+            .add_source(File::from_str(&s, FileFormat::Toml))
+            .build()
+            .expect("Failed to build config")
+            .try_deserialize()
+            .expect("Failed to parse config");
+        assert_eq!(
+            config.p2p_mempool(),
+            Some(&P2pMempoolConfig::new("127.0.0.1:48333".to_string(), 10))
+        );
+        assert_eq!(
+            config
+                .p2p_mempool()
+                .map(P2pMempoolConfig::reconnect_interval),
+            Some(Duration::from_secs(10))
+        );
+        assert!(config.check_network().is_err());
+
+        config.set_p2p_mempool(None);
+        assert!(config.check_network().is_ok());
+    }
+
+    #[test]
+    fn test_network_checks_rpc_port() {
+        let pk = TEST_PK_HEX;
+        let template = COINBASE_CONFIG_TEMPLATE
+            .replace("%COINBASE_REWARD_SCRIPT%", &format!("\"wpkh({pk})\""));
+        let parse = |s: String| -> JobDeclaratorServerConfig {
+            Config::builder()
+                .add_source(File::from_str(&s, FileFormat::Toml))
+                .build()
+                .expect("Failed to build config")
+                .try_deserialize()
+                .expect("Failed to parse config")
+        };
+
+        let config = parse(format!("network = \"testnet4\"\n{template}"));
+        assert!(config.check_network().is_ok());
+        assert_eq!(config.core_rpc_port(), Some(48332));
+
+        // 48332 is the RPC port of testnet4.
+        let config = parse(format!("network = \"regtest\"\n{template}"));
+        assert!(config.check_network().is_err());
+
+        let config = parse(format!(
+            "network = \"regtest\"\n{}",
+            template.replace("core_rpc_port = 48332", "")
+        ));
+        assert!(config.check_network().is_ok());
+        assert_eq!(config.core_rpc_port(), Some(18443));
+    }
+
+    #[test]
+    fn test_handshake_timeout_must_be_positive() {
+        let pk = TEST_PK_HEX;
+        let template = COINBASE_CONFIG_TEMPLATE
+            .replace("%COINBASE_REWARD_SCRIPT%", &format!("\"wpkh({pk})\""));
+        let parse = |s: String| -> JobDeclaratorServerConfig {
+            Config::builder()
+                .add_source(File::from_str(&s, FileFormat::Toml))
+                .build()
+                .expect("Failed to build config")
+                .try_deserialize()
+                .expect("Failed to parse config")
+        };
+
+        let config = parse(template.clone());
+        assert!(config.check_handshake_timeout().is_ok());
+        assert_eq!(config.handshake_timeout(), Duration::from_secs(10));
+
+        let config = parse(format!("handshake_timeout_secs = 3\n{template}"));
+        assert!(config.check_handshake_timeout().is_ok());
+        assert_eq!(config.handshake_timeout(), Duration::from_secs(3));
+
+        let config = parse(format!("handshake_timeout_secs = 0\n{template}"));
+        assert!(config.check_handshake_timeout().is_err());
+    }
 }
@@ -24,6 +24,7 @@ use std::{
     fmt::Debug,
     sync::{MutexGuard, PoisonError},
 };
+use stratum_apps::exit::ExitStatus;
 
 use crate::mempool::error::JdsMempoolError;
 
@@ -41,6 +42,8 @@ pub enum JdsError {
     Custom(String),
     Sv2ProtocolError((u32, Mining<'static>)),
     MempoolError(JdsMempoolError),
+    Persistence(stratum_apps::persistence::Error),
+    RateLimitExceeded(String),
     ImpossibleToReconstructBlock(String),
     NoLastDeclaredJob,
     InvalidRPCUrl,
@@ -48,6 +51,22 @@ pub enum JdsError {
     InvalidPrevHash,
     InvalidCoinbase,
     InvalidMerkleRoot,
+    Config(String),
+    Bind(String, std::io::Error),
+}
+
+impl JdsError {
+    /// Returns the exit status of the JDS process when it stops on this error.
+    pub fn exit_status(&self) -> ExitStatus {
+        use JdsError::*;
+        match self {
+            Config(_) | InvalidRPCUrl | BadCliArgs => ExitStatus::Config,
+            Noise(_) => ExitStatus::Key,
+            Bind(..) => ExitStatus::Bind,
+            MempoolError(_) => ExitStatus::Unreachable,
+            _ => ExitStatus::Failure,
+        }
+    }
 }
 
 impl std::fmt::Display for JdsError {
@@ -68,6 +87,8 @@ impl std::fmt::Display for JdsError {
                 write!(f, "Received Sv2 Protocol Error from upstream: `{e:?}`")
             }
             MempoolError(ref e) => write!(f, "Mempool error: `{e:?}`"),
+            Persistence(ref e) => write!(f, "Persistence error: `{e}`"),
+            RateLimitExceeded(ref e) => write!(f, "Rate limit exceeded by downstream `{e}`"),
             ImpossibleToReconstructBlock(e) => {
                 write!(f, "Error in reconstructing the block: {e:?}")
             }
@@ -77,6 +98,8 @@ impl std::fmt::Display for JdsError {
             InvalidPrevHash => write!(f, "Invalid previous hash"),
             InvalidCoinbase => write!(f, "Invalid coinbase"),
             InvalidMerkleRoot => write!(f, "Invalid merkle root"),
+            Config(ref e) => write!(f, "Invalid configuration: {e}"),
+            Bind(ref address, ref e) => write!(f, "Failed to bind {address}: {e}"),
         }
     }
 }
@@ -151,3 +174,9 @@ impl From<JdsMempoolError> for JdsError {
         JdsError::MempoolError(error)
     }
 }
+
+impl From<stratum_apps::persistence::Error> for JdsError {
+    fn from(error: stratum_apps::persistence::Error) -> Self {
+        JdsError::Persistence(error)
+    }
+}
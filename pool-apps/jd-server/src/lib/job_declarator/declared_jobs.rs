@@ -0,0 +1,442 @@
+//! Jobs declared by a downstream JDC, one per mining job token.
+//!
+//! A JDC may hold several declared jobs at once, e.g. while its miners still work on the job of
+//! the previous template, and declare them in any order on the tokens it was allocated. Each
+//! token holds the validation state of the last job declared on it: which of its transactions
+//! are in the JDS mempool, and which ones the JDC was asked to provide. Declaring on a token only
+//! replaces the job of that token, and a `ProvideMissingTransactionsSuccess` completes the job of
+//! its request id. A `PushSolution` does not name its job, so the block is rebuilt from the job
+//! whose header it solves, newest first.
+//!
+//! At most [`MAX_DECLARED_JOBS`] jobs are held per JDC, the oldest declared one being dropped
+//! first.
+
+use bitcoin::{
+    block::{Header, Version},
+    consensus::deserialize,
+    hashes::{sha256d, FromSliceError, Hash},
+    merkle_tree, BlockHash, CompactTarget, Transaction, TxMerkleNode, Txid,
+};
+use job_declaration_sv2::{DeclareMiningJob, PushSolution};
+use nohash_hasher::BuildNoHashHasher;
+use std::collections::{HashMap, VecDeque};
+
+use super::TransactionState;
+
+/// Number of declared jobs held per JDC.
+pub const MAX_DECLARED_JOBS: usize = 16;
+
+/// Job declared on a token, along with the state of its transactions.
+#[derive(Clone, Debug)]
+pub struct DeclaredJob {
+    pub job: DeclareMiningJob<'static>,
+    txids: Vec<Txid>,
+    transactions: Vec<TransactionState>,
+    // Positions of the transactions the JDC still has to provide.
+    missing: Vec<u16>,
+}
+
+impl DeclaredJob {
+    /// Creates the state of `job`, whose transactions are in the mempool if `is_known`.
+    pub fn new(
+        job: DeclareMiningJob<'static>,
+        is_known: impl Fn(&Txid) -> bool,
+    ) -> Result<Self, FromSliceError> {
+        let txids = job
+            .tx_ids_list
+            .inner_as_ref()
+            .iter()
+            .map(|txid| sha256d::Hash::from_slice(txid).map(Txid::from))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut transactions = Vec::with_capacity(txids.len());
+        let mut missing = vec![];
+        for (i, txid) in txids.iter().enumerate() {
+            if is_known(txid) {
+                transactions.push(TransactionState::PresentInMempool(*txid));
+            } else {
+                transactions.push(TransactionState::Missing);
+                missing.push(i as u16);
+            }
+        }
+        Ok(Self {
+            job,
+            txids,
+            transactions,
+            missing,
+        })
+    }
+
+    /// Returns the state of the transactions of the job, in block order.
+    pub fn transactions(&self) -> &[TransactionState] {
+        &self.transactions
+    }
+
+    /// Returns the positions of the transactions the JDC still has to provide.
+    pub fn missing(&self) -> &[u16] {
+        &self.missing
+    }
+
+    /// Returns `true` once every transaction of the job is in the mempool.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Returns the ids of the transactions of the job that are in the mempool.
+    pub fn known_transactions(&self) -> Vec<Txid> {
+        self.transactions
+            .iter()
+            .filter_map(|transaction| match transaction {
+                TransactionState::PresentInMempool(txid) => Some(*txid),
+                TransactionState::Missing => None,
+            })
+            .collect()
+    }
+
+    /// Returns `true` if `txid`, in its serialized form, is a transaction of the job.
+    pub fn references(&self, txid: &[u8]) -> bool {
+        self.txids.iter().any(|known| known.as_byte_array() == txid)
+    }
+
+    /// Marks the missing transactions as provided, in the order they were requested.
+    ///
+    /// Returns `false`, leaving the job untouched, if more transactions were provided than
+    /// missing.
+    pub fn provide(&mut self, transactions: &[Transaction]) -> bool {
+        if transactions.len() > self.missing.len() {
+            return false;
+        }
+        let still_missing = self.missing.split_off(transactions.len());
+        for (index, transaction) in self.missing.iter().zip(transactions) {
+            self.transactions[*index as usize] =
+                TransactionState::PresentInMempool(transaction.compute_txid());
+        }
+        self.missing = still_missing;
+        true
+    }
+
+    /// Returns the coinbase of the job completed with the extranonce of `solution`.
+    pub fn coinbase(&self, solution: &PushSolution) -> Option<Transaction> {
+        let mut serialized_coinbase = self.job.coinbase_tx_prefix.to_vec();
+        serialized_coinbase.extend_from_slice(&solution.extranonce.to_vec());
+        serialized_coinbase.extend_from_slice(&self.job.coinbase_tx_suffix.to_vec());
+        deserialize(&serialized_coinbase).ok()
+    }
+
+    /// Returns `true` if `solution` is a block of this job: its header, committing to the
+    /// coinbase and the declared transactions, meets its target.
+    pub fn is_solved_by(&self, solution: &PushSolution) -> bool {
+        let Some(coinbase) = self.coinbase(solution) else {
+            return false;
+        };
+        let Ok(prev_hash) = sha256d::Hash::from_slice(&solution.prev_hash.to_vec()) else {
+            return false;
+        };
+        let txids = std::iter::once(coinbase.compute_txid()).chain(self.txids.iter().copied());
+        let Some(merkle_root) = merkle_tree::calculate_root(txids) else {
+            return false;
+        };
+        let header = Header {
+            version: Version::from_consensus(solution.version as i32),
+            prev_blockhash: BlockHash::from_raw_hash(prev_hash),
+            merkle_root: TxMerkleNode::from_raw_hash(merkle_root.to_raw_hash()),
+            time: solution.ntime,
+            bits: CompactTarget::from_consensus(solution.nbits),
+            nonce: solution.nonce,
+        };
+        header.target().is_met_by(header.block_hash())
+    }
+}
+
+/// Tokens allocated to a JDC, each with the job last declared on it.
+#[derive(Debug, Default)]
+pub struct DeclaredJobs {
+    next_token: u32,
+    tokens: HashMap<u32, Option<DeclaredJob>, BuildNoHashHasher<u32>>,
+    // Tokens holding a job, oldest declaration first.
+    declarations: VecDeque<u32>,
+}
+
+impl DeclaredJobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocates a new token, on which no job is declared yet.
+    pub fn allocate_token(&mut self) -> u32 {
+        let token = self.next_token;
+        self.next_token = self.next_token.wrapping_add(1);
+        self.tokens.insert(token, None);
+        token
+    }
+
+    /// Returns `true` if `token` was allocated to the JDC.
+    pub fn is_allocated(&self, token: u32) -> bool {
+        self.tokens.contains_key(&token)
+    }
+
+    /// Returns `true` if no job is held.
+    pub fn is_empty(&self) -> bool {
+        self.declarations.is_empty()
+    }
+
+    /// Declares `job` on the allocated `token`, leaving the jobs of the other tokens untouched.
+    ///
+    /// Returns the jobs no longer held: the one previously declared on `token`, and the oldest
+    /// one if more than [`MAX_DECLARED_JOBS`] are declared.
+    pub fn declare(&mut self, token: u32, job: DeclaredJob) -> Vec<DeclaredJob> {
+        let mut dropped = vec![];
+        if let Some(replaced) = self.tokens.insert(token, Some(job)).flatten() {
+            self.declarations.retain(|declared| *declared != token);
+            dropped.push(replaced);
+        }
+        self.declarations.push_back(token);
+        if self.declarations.len() > MAX_DECLARED_JOBS {
+            let oldest = self.declarations.pop_front();
+            // The token stays allocated, a new job may be declared on it.
+            if let Some(evicted) = oldest.and_then(|token| self.tokens.get_mut(&token)?.take()) {
+                dropped.push(evicted);
+            }
+        }
+        dropped
+    }
+
+    /// Returns the jobs held, newest declaration first.
+    pub fn jobs(&self) -> impl Iterator<Item = &DeclaredJob> {
+        self.declarations
+            .iter()
+            .rev()
+            .filter_map(|token| self.tokens.get(token)?.as_ref())
+    }
+
+    /// Returns `true` if a job held has `txid`, in its serialized form, as transaction.
+    pub fn references(&self, txid: &[u8]) -> bool {
+        self.jobs().any(|job| job.references(txid))
+    }
+
+    /// Returns the job of `request_id` still waiting for transactions from the JDC.
+    pub fn awaiting_transactions(&mut self, request_id: u32) -> Option<&mut DeclaredJob> {
+        let token = self
+            .declarations
+            .iter()
+            .rev()
+            .find(|token| {
+                self.tokens[*token]
+                    .as_ref()
+                    .is_some_and(|job| job.job.request_id == request_id && !job.is_complete())
+            })
+            .copied()?;
+        self.tokens.get_mut(&token)?.as_mut()
+    }
+
+    /// Returns the newest job solved by `solution`, or the newest job held if none is, so that
+    /// the block is still submitted to the node.
+    pub fn solved_by(&self, solution: &PushSolution) -> Option<&DeclaredJob> {
+        self.jobs()
+            .find(|job| job.is_solved_by(solution))
+            .or_else(|| self.jobs().next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binary_sv2::{Seq064K, B032, U256};
+    use bitcoin::{
+        absolute::LockTime, consensus::serialize, transaction, Amount, OutPoint, ScriptBuf,
+        Sequence, TxIn, TxOut, Witness,
+    };
+
+    // Start of the scriptSig of the coinbase, before its extranonce: a BIP34 height push.
+    const HEIGHT_PUSH: [u8; 2] = [0x01, 0x64];
+    const EXTRANONCE_SIZE: usize = 8;
+    // Target met by about half of the hashes.
+    const EASY_NBITS: u32 = 0x207fffff;
+
+    fn txid(byte: u8) -> Txid {
+        Txid::from_byte_array([byte; 32])
+    }
+
+    fn provided_transaction(value: u64) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn declare_mining_job(
+        request_id: u32,
+        token: u32,
+        reward: u64,
+        txids: &[Txid],
+    ) -> DeclareMiningJob<'static> {
+        let mut script_sig = HEIGHT_PUSH.to_vec();
+        script_sig.extend_from_slice(&[0; EXTRANONCE_SIZE]);
+        let coinbase = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::from_bytes(script_sig),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(reward),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let serialized = serialize(&coinbase);
+        // version, input count, outpoint and scriptSig length
+        let prefix_size = 4 + 1 + 36 + 1 + HEIGHT_PUSH.len();
+        let tx_ids = txids
+            .iter()
+            .map(|txid| U256::Owned(txid.as_byte_array().to_vec()))
+            .collect();
+        DeclareMiningJob {
+            request_id,
+            mining_job_token: token.to_le_bytes().to_vec().try_into().unwrap(),
+            version: 0x2000_0000,
+            coinbase_tx_prefix: serialized[..prefix_size].to_vec().try_into().unwrap(),
+            coinbase_tx_suffix: serialized[prefix_size + EXTRANONCE_SIZE..]
+                .to_vec()
+                .try_into()
+                .unwrap(),
+            tx_ids_list: Seq064K::new(tx_ids).unwrap(),
+            excess_data: Vec::new().try_into().unwrap(),
+        }
+    }
+
+    fn push_solution(nonce: u32) -> PushSolution<'static> {
+        PushSolution {
+            extranonce: B032::Owned(vec![7; EXTRANONCE_SIZE]),
+            prev_hash: U256::Owned(vec![1; 32]),
+            ntime: 1_700_000_000,
+            nbits: EASY_NBITS,
+            nonce,
+            version: 0x2000_0000,
+        }
+    }
+
+    fn declared(request_id: u32, token: u32, reward: u64, txids: &[Txid]) -> DeclaredJob {
+        let known = txid(1);
+        DeclaredJob::new(declare_mining_job(request_id, token, reward, txids), |id| {
+            *id == known
+        })
+        .unwrap()
+    }
+
+    fn request_ids(jobs: &DeclaredJobs) -> Vec<u32> {
+        jobs.jobs().map(|job| job.job.request_id).collect()
+    }
+
+    #[test]
+    fn interleaved_declarations_keep_one_job_per_token() {
+        let mut jobs = DeclaredJobs::new();
+        let first = jobs.allocate_token();
+        let second = jobs.allocate_token();
+        assert!(jobs.is_allocated(first) && jobs.is_allocated(second));
+        assert!(!jobs.is_allocated(second + 1));
+
+        assert!(jobs
+            .declare(first, declared(1, first, 50, &[txid(1)]))
+            .is_empty());
+        assert!(jobs
+            .declare(second, declared(2, second, 50, &[txid(2)]))
+            .is_empty());
+        assert_eq!(request_ids(&jobs), vec![2, 1]);
+
+        // Redeclaring on the first token only replaces its own job.
+        let dropped = jobs.declare(first, declared(3, first, 50, &[txid(1), txid(3)]));
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].job.request_id, 1);
+        assert_eq!(request_ids(&jobs), vec![3, 2]);
+        assert!(jobs.references(txid(2).as_byte_array()));
+        assert!(!jobs.references(txid(4).as_byte_array()));
+    }
+
+    #[test]
+    fn oldest_job_is_dropped_past_the_limit() {
+        let mut jobs = DeclaredJobs::new();
+        let tokens: Vec<u32> = (0..=MAX_DECLARED_JOBS)
+            .map(|_| jobs.allocate_token())
+            .collect();
+        for (request_id, token) in tokens.iter().enumerate() {
+            let dropped = jobs.declare(*token, declared(request_id as u32, *token, 50, &[]));
+            assert_eq!(dropped.len(), usize::from(request_id == MAX_DECLARED_JOBS));
+        }
+        assert_eq!(jobs.jobs().count(), MAX_DECLARED_JOBS);
+        assert!(!request_ids(&jobs).contains(&0));
+        // The token of the dropped job can still be declared on.
+        assert!(jobs.is_allocated(tokens[0]));
+    }
+
+    #[test]
+    fn provided_transactions_complete_the_job_of_their_request() {
+        let mut jobs = DeclaredJobs::new();
+        let first = jobs.allocate_token();
+        let second = jobs.allocate_token();
+        jobs.declare(first, declared(1, first, 50, &[txid(1), txid(2), txid(3)]));
+        jobs.declare(second, declared(2, second, 50, &[txid(4)]));
+        assert!(jobs.jobs().all(|job| !job.is_complete()));
+
+        let job = jobs.awaiting_transactions(1).unwrap();
+        assert_eq!(job.missing(), &[1, 2]);
+        assert_eq!(job.known_transactions(), vec![txid(1)]);
+        assert!(!job.provide(&[
+            provided_transaction(1),
+            provided_transaction(2),
+            provided_transaction(3)
+        ]));
+        assert_eq!(job.missing(), &[1, 2]);
+        assert!(job.provide(&[provided_transaction(1)]));
+        assert_eq!(job.missing(), &[2]);
+        assert!(job.provide(&[provided_transaction(2)]));
+        assert!(job.is_complete());
+        assert_eq!(job.known_transactions().len(), 3);
+
+        // The completed job no longer waits for transactions, the other one still does.
+        assert!(jobs.awaiting_transactions(1).is_none());
+        assert!(jobs.awaiting_transactions(3).is_none());
+        let job = jobs.awaiting_transactions(2).unwrap();
+        assert_eq!(job.missing(), &[0]);
+    }
+
+    #[test]
+    fn solutions_are_matched_to_the_job_they_solve() {
+        let mut jobs = DeclaredJobs::new();
+        let older = jobs.allocate_token();
+        let newer = jobs.allocate_token();
+        jobs.declare(older, declared(1, older, 50, &[txid(1)]));
+        jobs.declare(newer, declared(2, newer, 25, &[txid(1)]));
+
+        let newer_job = jobs.jobs().next().unwrap();
+        let older_job = jobs.jobs().nth(1).unwrap();
+        let solves = |job: &DeclaredJob, nonce| job.is_solved_by(&push_solution(nonce));
+        let older_nonce = (0..1000)
+            .find(|nonce| solves(older_job, *nonce) && !solves(newer_job, *nonce))
+            .unwrap();
+        let newer_nonce = (0..1000)
+            .find(|nonce| solves(newer_job, *nonce) && !solves(older_job, *nonce))
+            .unwrap();
+        let neither_nonce = (0..1000)
+            .find(|nonce| !solves(newer_job, *nonce) && !solves(older_job, *nonce))
+            .unwrap();
+
+        let solved = |nonce| {
+            jobs.solved_by(&push_solution(nonce))
+                .unwrap()
+                .job
+                .request_id
+        };
+        assert_eq!(solved(older_nonce), 1);
+        assert_eq!(solved(newer_nonce), 2);
+        // A solution of no job held is still built on the newest one.
+        assert_eq!(solved(neither_nonce), 2);
+        assert!(DeclaredJobs::new().solved_by(&push_solution(0)).is_none());
+    }
+}
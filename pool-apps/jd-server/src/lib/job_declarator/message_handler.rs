@@ -1,8 +1,7 @@
-use binary_sv2::{Decodable, Serialize, U256};
 use bitcoin::{
     consensus::Decodable as BitcoinDecodable,
-    hashes::{sha256d, Hash},
-    Transaction, Txid,
+    hashes::{sha256d, Hash, HashEngine},
+    Transaction,
 };
 use job_declaration_sv2::{
     AllocateMiningJobToken, AllocateMiningJobTokenSuccess, DeclareMiningJob, DeclareMiningJobError,
@@ -15,22 +14,23 @@ use roles_logic_sv2::{
     handlers::{job_declaration::ParseJobDeclarationMessagesFromDownstream, SendTo_},
     utils::Mutex,
 };
-use std::{
-    convert::TryInto,
-    io::Cursor,
-    sync::{atomic::Ordering, Arc},
-};
+use std::{convert::TryInto, io::Cursor, sync::Arc, time::Instant};
+use stratum_apps::persistence::{JobDeclarationDecision, JobDeclarationEvent, SolutionEvent};
 pub type SendTo = SendTo_<JobDeclaration<'static>, ()>;
 use crate::mempool::JDsMempool;
 
-use super::{signed_token, TransactionState};
+use super::{
+    declared_jobs::{DeclaredJob, DeclaredJobs},
+    signed_token,
+};
 use parsers_sv2::AnyMessage as AllMessages;
-use tracing::{debug, info};
+use tracing::{debug, info, instrument};
 
 use super::JobDeclaratorDownstream;
 
 impl JobDeclaratorDownstream {
-    fn verify_job(&mut self, message: &DeclareMiningJob) -> bool {
+    // Returns the token of the job, if it was allocated to the client.
+    fn verify_job(&mut self, message: &DeclareMiningJob) -> Option<u32> {
         // Convert token from B0255 to u32
         let four_byte_array: [u8; 4] = message
             .mining_job_token
@@ -38,14 +38,16 @@ impl JobDeclaratorDownstream {
             .to_vec()
             .as_slice()
             .try_into()
-            .unwrap();
+            .ok()?;
         let token_u32 = u32::from_le_bytes(four_byte_array);
         // TODO Function to implement, it must be checked if the requested job has:
         // 1. right coinbase
         // 2. right version field
         // 3. right prev-hash
         // 4. right nbits
-        self.token_to_job_map.contains_key(&(token_u32))
+        self.declared_jobs
+            .is_allocated(token_u32)
+            .then_some(token_u32)
     }
 }
 
@@ -59,8 +61,7 @@ impl ParseJobDeclarationMessagesFromDownstream for JobDeclaratorDownstream {
             message.request_id
         );
         debug!("`AllocateMiningJobToken`: {:?}", message.request_id);
-        let token = self.tokens.fetch_add(1, Ordering::Relaxed);
-        self.token_to_job_map.insert(token, None);
+        let token = self.declared_jobs.allocate_token();
         let message_success = AllocateMiningJobTokenSuccess {
             request_id: message.request_id,
             mining_job_token: token.to_le_bytes().to_vec().try_into().unwrap(),
@@ -84,54 +85,55 @@ impl ParseJobDeclarationMessagesFromDownstream for JobDeclaratorDownstream {
             message.request_id
         );
         debug!("`DeclareMiningJob`: {}", message);
-        if let Some(old_mining_job) = self.declared_mining_job.0.take() {
-            clear_declared_mining_job(old_mining_job, &message, self.mempool.clone())?;
-        }
-        let mut known_transactions: Vec<Txid> = vec![];
-        if self.verify_job(&message) {
-            let txids = message.tx_ids_list.inner_as_ref();
+        let started = Instant::now();
+        if let Some(token) = self.verify_job(&message) {
             let mempool = self.mempool.safe_lock(|x| x.mempool.clone())?;
-            let mut transactions_with_state = vec![TransactionState::Missing; txids.len()];
-            let mut missing_txs: Vec<u16> = Vec::new();
-            for (i, txid) in txids.iter().enumerate() {
-                let hash = sha256d::Hash::from_slice(txid)?;
-                let txid = Txid::from(hash);
-                match mempool.contains_key(&txid) {
-                    true => {
-                        transactions_with_state[i] = TransactionState::PresentInMempool(txid);
-                        known_transactions.push(txid);
-                    }
-                    false => {
-                        missing_txs.push(i as u16);
-                    }
-                }
+            let declared_job = DeclaredJob::new(message.clone().into_static(), |txid| {
+                mempool.contains_key(txid)
+            })?;
+            let mut known_transactions = declared_job.known_transactions();
+            let missing_txs = declared_job.missing().to_vec();
+            // only the job previously declared on this token is replaced, the jobs declared on
+            // the other tokens are kept along with their transactions
+            for dropped_job in self.declared_jobs.declare(token, declared_job) {
+                clear_declared_mining_job(
+                    dropped_job.job,
+                    &self.declared_jobs,
+                    self.mempool.clone(),
+                )?;
             }
-            self.declared_mining_job = (
-                Some(message.clone().into_static()),
-                transactions_with_state,
-                missing_txs.clone(),
-            );
             // here we send the transactions that we want to be stored in jds mempool with full data
 
             self.add_txs_to_mempool
                 .add_txs_to_mempool_inner
                 .known_transactions
                 .append(&mut known_transactions);
-            let mut full_token = [0u8; 255];
-            message.mining_job_token.to_bytes(&mut full_token)?;
-            let mining_job_token = &mut full_token[..32];
             if missing_txs.is_empty() {
                 let message_success = DeclareMiningJobSuccess {
                     request_id: message.request_id,
                     new_mining_job_token: signed_token(
-                        U256::from_bytes(mining_job_token)?,
+                        message.mining_job_token.inner_as_ref(),
                         &self.public_key.clone(),
                         &self.private_key.clone(),
                     ),
                 };
+                self.persistence.record(job_declaration_event(
+                    &self.client,
+                    &message,
+                    JobDeclarationDecision::Accepted,
+                    None,
+                    started,
+                ));
                 let message_enum_success = JobDeclaration::DeclareMiningJobSuccess(message_success);
                 Ok(SendTo::Respond(message_enum_success))
             } else {
+                self.persistence.record(job_declaration_event(
+                    &self.client,
+                    &message,
+                    JobDeclarationDecision::MissingTransactions,
+                    None,
+                    started,
+                ));
                 let message_provide_missing_transactions = ProvideMissingTransactions {
                     request_id: message.request_id,
                     unknown_tx_position_list: missing_txs.into(),
@@ -143,9 +145,17 @@ impl ParseJobDeclarationMessagesFromDownstream for JobDeclaratorDownstream {
                 Ok(SendTo::Respond(message_enum_provide_missing_transactions))
             }
         } else {
+            let error_code = "invalid-mining-job-token";
+            self.persistence.record(job_declaration_event(
+                &self.client,
+                &message,
+                JobDeclarationDecision::Rejected,
+                Some(error_code),
+                started,
+            ));
             let message_error = DeclareMiningJobError {
                 request_id: message.request_id,
-                error_code: Vec::new().try_into().unwrap(),
+                error_code: error_code.to_string().into_bytes().try_into().unwrap(),
                 error_details: Vec::new().try_into().unwrap(),
             };
             let message_enum_error = JobDeclaration::DeclareMiningJobError(message_error);
@@ -162,85 +172,119 @@ impl ParseJobDeclarationMessagesFromDownstream for JobDeclaratorDownstream {
             message.request_id
         );
         debug!("`ProvideMissingTransactionsSuccess`: {}", message);
-        let (declared_mining_job, ref mut transactions_with_state, missing_indexes) =
-            &mut self.declared_mining_job;
+        let started = Instant::now();
+        if self.declared_jobs.is_empty() {
+            return Err(Error::NoValidJob);
+        }
+        // the job is looked up by request_id, so that old ProvideMissingTransactionsSuccess are
+        // ignored (see issue #860) and that each job declared by the client is completed
+        // independently
+        let Some(declared_job) = self.declared_jobs.awaiting_transactions(message.request_id)
+        else {
+            return Ok(SendTo::None(None));
+        };
         let mut unknown_transactions: Vec<Transaction> = vec![];
-        match declared_mining_job {
-            Some(declared_job) => {
-                let id = declared_job.request_id;
-                // check request_id in order to ignore old ProvideMissingTransactionsSuccess (see
-                // issue #860)
-                if id == message.request_id {
-                    for (i, tx) in message.transaction_list.inner_as_ref().iter().enumerate() {
-                        let mut cursor = Cursor::new(tx);
-                        let transaction =
-                            Transaction::consensus_decode_from_finite_reader(&mut cursor)
-                                .map_err(|e| Error::TxDecodingError(e.to_string()))?;
-                        Vec::push(&mut unknown_transactions, transaction.clone());
-                        let index =
-                            *missing_indexes
-                                .get(i)
-                                .ok_or(Error::LogicErrorMessage(Box::new(
-                                    AllMessages::JobDeclaration(
-                                        JobDeclaration::ProvideMissingTransactionsSuccess(
-                                            message.clone().into_static(),
-                                        ),
-                                    ),
-                                )))? as usize;
-                        // insert the missing transactions in the mempool
-                        transactions_with_state[index] =
-                            TransactionState::PresentInMempool(transaction.compute_txid());
-                    }
-                    self.add_txs_to_mempool
-                        .add_txs_to_mempool_inner
-                        .unknown_transactions
-                        .append(&mut unknown_transactions);
-                    // if there still a missing transaction return an error
-                    for tx_with_state in transactions_with_state {
-                        match tx_with_state {
-                            TransactionState::PresentInMempool(_) => continue,
-                            TransactionState::Missing => return Err(Error::JDSMissingTransactions),
-                        }
-                    }
-                    let mut full_token = [0u8; 255];
-                    declared_job
-                        .mining_job_token
-                        .clone()
-                        .to_bytes(&mut full_token)?;
-                    let mining_job_token = &mut full_token[..32];
-                    let message_success = DeclareMiningJobSuccess {
-                        request_id: message.request_id,
-                        new_mining_job_token: signed_token(
-                            U256::from_bytes(mining_job_token)?,
-                            &self.public_key.clone(),
-                            &self.private_key.clone(),
-                        ),
-                    };
-                    let message_enum_success =
-                        JobDeclaration::DeclareMiningJobSuccess(message_success);
-                    return Ok(SendTo::Respond(message_enum_success));
-                }
-            }
-            None => return Err(Error::NoValidJob),
+        for tx in message.transaction_list.inner_as_ref() {
+            let mut cursor = Cursor::new(tx);
+            let transaction = Transaction::consensus_decode_from_finite_reader(&mut cursor)
+                .map_err(|e| Error::TxDecodingError(e.to_string()))?;
+            unknown_transactions.push(transaction);
         }
-        Ok(SendTo::None(None))
+        if !declared_job.provide(&unknown_transactions) {
+            return Err(Error::LogicErrorMessage(Box::new(
+                AllMessages::JobDeclaration(JobDeclaration::ProvideMissingTransactionsSuccess(
+                    message.clone().into_static(),
+                )),
+            )));
+        }
+        // insert the missing transactions in the mempool
+        self.add_txs_to_mempool
+            .add_txs_to_mempool_inner
+            .unknown_transactions
+            .append(&mut unknown_transactions);
+        // if there still a missing transaction return an error
+        if !declared_job.is_complete() {
+            return Err(Error::JDSMissingTransactions);
+        }
+        let message_success = DeclareMiningJobSuccess {
+            request_id: message.request_id,
+            new_mining_job_token: signed_token(
+                declared_job.job.mining_job_token.inner_as_ref(),
+                &self.public_key.clone(),
+                &self.private_key.clone(),
+            ),
+        };
+        self.persistence.record(job_declaration_event(
+            &self.client,
+            &declared_job.job,
+            JobDeclarationDecision::Accepted,
+            None,
+            started,
+        ));
+        let message_enum_success = JobDeclaration::DeclareMiningJobSuccess(message_success);
+        Ok(SendTo::Respond(message_enum_success))
     }
 
+    #[instrument(
+        name = "solution",
+        skip_all,
+        fields(client = %self.client, nonce = message.nonce, ntime = message.ntime)
+    )]
     fn handle_push_solution(&mut self, message: PushSolution<'_>) -> Result<SendTo, Error> {
         info!("Received PushSolution from JDC");
         debug!("`PushSolution`: {}", message);
+        self.persistence.record(SolutionEvent {
+            client: self.client.clone(),
+            prev_hash: hex::encode(message.prev_hash.to_vec()),
+            extranonce: hex::encode(message.extranonce.to_vec()),
+            version: message.version,
+            ntime: message.ntime,
+            nonce: message.nonce,
+            nbits: message.nbits,
+        });
         let m = JobDeclaration::PushSolution(message.clone().into_static());
         Ok(SendTo::None(Some(m)))
     }
 }
 
+// Builds the persisted record of a job declaration.
+//
+// The template hash commits to the coinbase prefix and suffix and to the declared transaction
+// ids, so that two declarations of the same template always share the same hash.
+fn job_declaration_event(
+    client: &str,
+    message: &DeclareMiningJob,
+    decision: JobDeclarationDecision,
+    error_code: Option<&str>,
+    started: Instant,
+) -> JobDeclarationEvent {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(&message.coinbase_tx_prefix.to_vec());
+    engine.input(&message.coinbase_tx_suffix.to_vec());
+    for txid in message.tx_ids_list.inner_as_ref() {
+        engine.input(txid);
+    }
+    let template_hash = sha256d::Hash::from_engine(engine);
+    JobDeclarationEvent {
+        client: client.to_string(),
+        request_id: message.request_id,
+        token: hex::encode(message.mining_job_token.to_vec()),
+        template_hash: template_hash.to_string(),
+        tx_count: message.tx_ids_list.inner_as_ref().len(),
+        decision,
+        error_code: error_code.map(str::to_string),
+        processing_time_us: started.elapsed().as_micros() as u64,
+    }
+}
+
+// Releases the transactions of a job no longer held by the client, except those of the jobs it
+// still holds.
 fn clear_declared_mining_job(
     old_mining_job: DeclareMiningJob,
-    new_mining_job: &DeclareMiningJob,
+    declared_jobs: &DeclaredJobs,
     mempool: Arc<Mutex<JDsMempool>>,
 ) -> Result<(), Error> {
     let old_transactions = old_mining_job.tx_ids_list.inner_as_ref();
-    let new_transactions = new_mining_job.tx_ids_list.inner_as_ref();
 
     if old_transactions.is_empty() {
         info!("No transactions to remove from mempool");
@@ -252,7 +296,7 @@ fn clear_declared_mining_job(
 
         for old_txid in old_transactions
             .iter()
-            .filter(|&id| !new_transactions.contains(id))
+            .filter(|&id| !declared_jobs.references(id))
         {
             if let Some(tx) = mempool_txs.get(*old_txid) {
                 if let Some((transaction, _)) = tx.as_ref() {
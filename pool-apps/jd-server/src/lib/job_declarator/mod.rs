@@ -17,15 +17,17 @@
 //! The design is one-task-per-downstream, with communication via channels and internal
 //! synchronization.
 
+pub mod declared_jobs;
 pub mod message_handler;
+pub mod plain_connection;
 use super::{
     error::JdsError, mempool::JDsMempool, status, EitherFrame, JobDeclaratorServerConfig, StdFrame,
 };
 use async_channel::{Receiver, Sender};
-use binary_sv2::{self, B0255, U256};
+use binary_sv2::{self, B0255};
 use bitcoin::{
     block::{Header, Version},
-    consensus::{deserialize, encode::serialize},
+    consensus::encode::serialize,
     hashes::{sha256d::Hash as DHash, Hash},
     Amount, Block, BlockHash, CompactTarget, Transaction, TxOut, Txid,
 };
@@ -34,24 +36,50 @@ use common_messages_sv2::{
     Protocol, SetupConnection, SetupConnectionError, SetupConnectionSuccess,
 };
 use core::panic;
+use declared_jobs::{DeclaredJob, DeclaredJobs};
 use error_handling::handle_result;
-use job_declaration_sv2::{DeclareMiningJob, PushSolution};
+use job_declaration_sv2::{
+    DeclareMiningJob, DeclareMiningJobError, PushSolution, MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN,
+    MESSAGE_TYPE_DECLARE_MINING_JOB,
+};
 use network_helpers_sv2::noise_connection::Connection;
-use nohash_hasher::BuildNoHashHasher;
 use noise_sv2::Responder;
 use parsers_sv2::{AnyMessage as JdsMessages, JobDeclaration};
+use plain_connection::PlainConnection;
 use roles_logic_sv2::{
     handlers::job_declaration::{ParseJobDeclarationMessagesFromDownstream, SendTo},
     utils::Mutex,
 };
-use std::{
-    collections::HashMap,
-    convert::TryInto,
-    sync::{atomic::AtomicU32, Arc},
+use std::{convert::TryInto, sync::Arc};
+use stratum_apps::{
+    banlist::BanList,
+    connections::{ConnectionMetadata, ConnectionRegistry, ShutdownHandle},
+    encryption::Encryption,
+    key_utils::{sign_mining_job_token, Secp256k1PublicKey, Secp256k1SecretKey},
+    persistence::Persistence,
+    rate_limit::TokenBucket,
+    runtime::{HandshakeDeadline, TaskManager},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::Duration,
 };
-use stratum_apps::key_utils::{Secp256k1PublicKey, Secp256k1SecretKey, SignatureService};
-use tokio::{net::TcpListener, time::Duration};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+/// Connection of a Job Declarator Client, registered once its `SetupConnection` succeeded.
+#[derive(Debug, Clone)]
+pub struct JdcConnection {
+    /// Whether the client requires the full template mode.
+    pub full_template_mode_required: bool,
+}
+
+// Job declaration connections open no channels.
+impl ConnectionMetadata for JdcConnection {}
+
+/// Registry of the connections of the Job Declarator Clients, shared with the monitoring
+/// endpoint.
+pub type JdcConnections =
+    Arc<stratum_apps::custom_mutex::Mutex<ConnectionRegistry<usize, JdcConnection>>>;
 
 /// Represents whether a transaction declared in a mining job is known to the JDS mempool
 /// or still missing and needs to be fetched/provided.
@@ -78,11 +106,22 @@ pub struct AddTrasactionsToMempool {
     pub sender_add_txs_to_mempool: Sender<AddTrasactionsToMempoolInner>,
 }
 
+/// Outcome of applying the per-connection rate limits to an incoming message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitOutcome {
+    /// The message is within limits and can be processed.
+    Allowed,
+    /// The message is over the limit and must be refused.
+    Refused,
+    /// Too many consecutive messages were refused, the connection must be dropped.
+    Exceeded,
+}
+
 /// Represents a single downstream connection to a JDC.
 ///
 /// This struct tracks all state relevant to one connection, including:
-/// - The declared mining job and missing transactions
-/// - Mapping between tokens and job IDs
+/// - The tokens allocated to the client, and the job declared on each of them along with its
+///   missing transactions
 /// - Interaction with the mempool
 ///
 /// It operates in its own async task and communicates with the rest of the system
@@ -98,22 +137,24 @@ pub struct JobDeclaratorDownstream {
     #[allow(dead_code)]
     // TODO: use coinbase output
     coinbase_output: Vec<u8>,
-    token_to_job_map: HashMap<u32, Option<u8>, BuildNoHashHasher<u32>>,
-    tokens: AtomicU32,
+    declared_jobs: DeclaredJobs,
     public_key: Secp256k1PublicKey,
     private_key: Secp256k1SecretKey,
     mempool: Arc<Mutex<JDsMempool>>,
-    // Vec<u16> is the vector of missing transactions
-    declared_mining_job: (
-        Option<DeclareMiningJob<'static>>,
-        Vec<TransactionState>,
-        Vec<u16>,
-    ),
     add_txs_to_mempool: AddTrasactionsToMempool,
+    // Identifies the downstream in persisted events (its peer address)
+    client: String,
+    persistence: Persistence,
+    allocate_token_limiter: TokenBucket,
+    declare_job_limiter: TokenBucket,
+    // Number of consecutive messages refused by the rate limiters
+    rate_limit_violations: u32,
+    max_rate_limit_violations: u32,
 }
 
 impl JobDeclaratorDownstream {
     /// Creates a new downstream connection context.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         full_template_mode_required: bool,
         receiver: Receiver<EitherFrame>,
@@ -121,10 +162,9 @@ impl JobDeclaratorDownstream {
         config: &JobDeclaratorServerConfig,
         mempool: Arc<Mutex<JDsMempool>>,
         sender_add_txs_to_mempool: Sender<AddTrasactionsToMempoolInner>,
+        client: String,
+        persistence: Persistence,
     ) -> Self {
-        // TODO: use next variables
-        let token_to_job_map = HashMap::with_hasher(BuildNoHashHasher::default());
-        let tokens = AtomicU32::new(0);
         let add_txs_to_mempool_inner = AddTrasactionsToMempoolInner {
             known_transactions: vec![],
             unknown_transactions: vec![],
@@ -139,29 +179,64 @@ impl JobDeclaratorDownstream {
             receiver,
             sender,
             coinbase_output,
-            token_to_job_map,
-            tokens,
+            declared_jobs: DeclaredJobs::new(),
             public_key: *config.authority_public_key(),
             private_key: *config.authority_secret_key(),
             mempool,
-            declared_mining_job: (None, Vec::new(), Vec::new()),
             add_txs_to_mempool: AddTrasactionsToMempool {
                 add_txs_to_mempool_inner,
                 sender_add_txs_to_mempool,
             },
+            client,
+            persistence,
+            allocate_token_limiter: TokenBucket::new(
+                config.rate_limit().allocate_mining_job_token(),
+            ),
+            declare_job_limiter: TokenBucket::new(config.rate_limit().declare_mining_job()),
+            rate_limit_violations: 0,
+            max_rate_limit_violations: config.rate_limit().max_violations(),
+        }
+    }
+
+    /// Applies the per-connection rate limits to an incoming message.
+    ///
+    /// Only `AllocateMiningJobToken` and `DeclareMiningJob` are limited, as they are the messages
+    /// that cost the JDS token bookkeeping, mempool lookups and RPC round trips.
+    pub fn check_rate_limit(&mut self, message_type: u8) -> RateLimitOutcome {
+        let allowed = match message_type {
+            MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN => self.allocate_token_limiter.try_acquire(),
+            MESSAGE_TYPE_DECLARE_MINING_JOB => self.declare_job_limiter.try_acquire(),
+            _ => return RateLimitOutcome::Allowed,
+        };
+        if allowed {
+            self.rate_limit_violations = 0;
+            return RateLimitOutcome::Allowed;
+        }
+        self.rate_limit_violations += 1;
+        if self.rate_limit_violations > self.max_rate_limit_violations {
+            RateLimitOutcome::Exceeded
+        } else {
+            RateLimitOutcome::Refused
         }
     }
 
+    // Returns the job `message` is a solution of, among the jobs declared by the client.
+    fn solved_job(
+        self_mutex: &Arc<Mutex<Self>>,
+        message: &PushSolution,
+    ) -> Result<DeclaredJob, Box<JdsError>> {
+        self_mutex
+            .safe_lock(|x| x.declared_jobs.solved_by(message).cloned())
+            .map_err(|e| Box::new(JdsError::PoisonLock(e.to_string())))?
+            .ok_or(Box::new(JdsError::NoLastDeclaredJob))
+    }
+
     fn get_block_hex(
-        self_mutex: Arc<Mutex<Self>>,
+        mempool: &Arc<Mutex<JDsMempool>>,
+        declared_job: &DeclaredJob,
         message: PushSolution,
     ) -> Result<String, Box<JdsError>> {
-        let (last_declare_, _, _) = self_mutex
-            .clone()
-            .safe_lock(|x| x.declared_mining_job.clone())
-            .map_err(|e| Box::new(JdsError::PoisonLock(e.to_string())))?;
-        let last_declare = last_declare_.ok_or(Box::new(JdsError::NoLastDeclaredJob))?;
-        let mut transactions_list = Self::collect_txs_in_job(self_mutex)?;
+        let mut transactions_list = Self::collect_txs_in_job(mempool, declared_job)?;
 
         let hash: [u8; 32] = message
             .prev_hash
@@ -183,12 +258,9 @@ impl JobDeclaratorDownstream {
             nonce: message.nonce,
         };
 
-        let mut serialized_coinbase = Vec::new();
-        serialized_coinbase.extend_from_slice(last_declare.coinbase_tx_prefix.to_vec().as_slice());
-        serialized_coinbase.extend_from_slice(message.extranonce.to_vec().as_slice());
-        serialized_coinbase.extend_from_slice(last_declare.coinbase_tx_suffix.to_vec().as_slice());
-        let coinbase = deserialize(&serialized_coinbase[..])
-            .map_err(|_| Box::new(JdsError::InvalidCoinbase))?;
+        let coinbase = declared_job
+            .coinbase(&message)
+            .ok_or(Box::new(JdsError::InvalidCoinbase))?;
         transactions_list.insert(0, coinbase);
 
         let mut block = Block {
@@ -204,16 +276,12 @@ impl JobDeclaratorDownstream {
         Ok(hex::encode(serialize(&block)))
     }
 
-    fn collect_txs_in_job(self_mutex: Arc<Mutex<Self>>) -> Result<Vec<Transaction>, Box<JdsError>> {
-        let (_, transactions_with_state, _) = self_mutex
-            .clone()
-            .safe_lock(|x| x.declared_mining_job.clone())
-            .map_err(|e| Box::new(JdsError::PoisonLock(e.to_string())))?;
-        let mempool = self_mutex
-            .safe_lock(|x| x.mempool.clone())
-            .map_err(|e| Box::new(JdsError::PoisonLock(e.to_string())))?;
+    fn collect_txs_in_job(
+        mempool: &Arc<Mutex<JDsMempool>>,
+        declared_job: &DeclaredJob,
+    ) -> Result<Vec<Transaction>, Box<JdsError>> {
         let mut transactions_list: Vec<Transaction> = Vec::new();
-        for tx_with_state in transactions_with_state.iter().enumerate() {
+        for tx_with_state in declared_job.transactions().iter().enumerate() {
             if let TransactionState::PresentInMempool(txid) = tx_with_state.1 {
                 let tx = mempool
                     .safe_lock(|x| x.mempool.get(txid).cloned())
@@ -252,22 +320,6 @@ impl JobDeclaratorDownstream {
         });
     }
 
-    fn get_transactions_in_job(self_mutex: Arc<Mutex<Self>>) -> Vec<Txid> {
-        let mut known_transactions: Vec<Txid> = Vec::new();
-        let job_transactions = self_mutex
-            .safe_lock(|a| a.declared_mining_job.1.clone())
-            .unwrap();
-        for transaction in job_transactions {
-            match transaction {
-                TransactionState::PresentInMempool(txid) => known_transactions.push(txid),
-                TransactionState::Missing => {
-                    continue;
-                }
-            }
-        }
-        known_transactions
-    }
-
     /// Sends a single Job Declaration message back to the downstream client.
     ///
     /// Wraps the message into a `StdFrame` and sends it through the established channel.
@@ -281,218 +333,272 @@ impl JobDeclaratorDownstream {
         Ok(())
     }
 
-    /// Starts the message processing loop for this downstream connection.
+    /// Runs the message processing loop of this downstream connection.
     ///
     /// - Waits for incoming SV2 messages
     /// - Delegates message parsing to [`ParseJobDeclarationMessagesFromDownstream`]
     /// - Sends appropriate responses back to the client
     /// - Updates the JDS mempool as needed
     ///
-    /// This loop runs until the client disconnects or a critical error is encountered.
-    pub fn start(
+    /// This loop runs until the client disconnects or a critical error is encountered, on the
+    /// task of the connection.
+    pub async fn run(
         self_mutex: Arc<Mutex<Self>>,
         tx_status: status::Sender,
         new_block_sender: Sender<String>,
     ) {
         let recv = self_mutex.safe_lock(|s| s.receiver.clone()).unwrap();
-        tokio::spawn(async move {
-            loop {
-                match recv.recv().await {
-                    Ok(message) => {
-                        let mut frame: StdFrame = handle_result!(tx_status, message.try_into());
-                        let header = frame
-                            .get_header()
-                            .ok_or_else(|| JdsError::Custom(String::from("No header set")));
-                        let header = handle_result!(tx_status, header);
-                        let message_type = header.msg_type();
-                        let payload = frame.payload();
-                        let next_message_to_send =
-                            ParseJobDeclarationMessagesFromDownstream::handle_message_job_declaration(
-                                self_mutex.clone(),
-                                message_type,
-                                payload,
+        loop {
+            match recv.recv().await {
+                Ok(message) => {
+                    let mut frame: StdFrame = handle_result!(tx_status, message.try_into());
+                    let header = frame
+                        .get_header()
+                        .ok_or_else(|| JdsError::Custom(String::from("No header set")));
+                    let header = handle_result!(tx_status, header);
+                    let message_type = header.msg_type();
+                    let payload = frame.payload();
+                    let (rate_limit, client) = self_mutex
+                        .safe_lock(|s| (s.check_rate_limit(message_type), s.client.clone()))
+                        .unwrap();
+                    match rate_limit {
+                        RateLimitOutcome::Allowed => (),
+                        RateLimitOutcome::Refused => {
+                            warn!(
+                                "Rate limit hit by {} for message type {}, refusing message",
+                                client, message_type
                             );
-                        // How works the txs recognition and txs storing in JDS mempool
-                        // when a DMJ arrives, the JDS compares the received transactions with the
-                        // ids in the the JDS mempool. Then there are two scenarios
-                        // 1. the JDS recognizes all the transactions. Then, just before a DMJS is
-                        //    sent, the JDS mempool is triggered to fill in the JDS mempool the id
-                        //    of declared job with the full transaction (with send_tx_to_mempool
-                        //    method(), that eventually will ask the transactions to a bitcoin node
-                        //    via RPC)
-                        // 2. there are some unknown txids. Just before sending PMT, the JDS mempool
-                        //    is triggered to fill the known txids with the full transactions. When
-                        //    a PMTS arrives, just before sending a DMJS, the unknown full
-                        //    transactions provided by the downstream are added to the JDS mempool
-                        match next_message_to_send {
-                            Ok(SendTo::Respond(m)) => {
-                                match m {
-                                    JobDeclaration::AllocateMiningJobToken(_) => {
-                                        error!("Send unexpected message: AMJT");
-                                    }
-                                    JobDeclaration::AllocateMiningJobTokenSuccess(_) => {
-                                        debug!("Send message: AMJTS");
-                                    }
-                                    JobDeclaration::DeclareMiningJob(_) => {
-                                        error!("Send unexpected message: DMJ");
-                                    }
-                                    JobDeclaration::DeclareMiningJobError(_) => {
-                                        debug!("Send nmessage: DMJE");
-                                    }
-                                    JobDeclaration::DeclareMiningJobSuccess(_) => {
-                                        debug!("Send message: DMJS. Updating the JDS mempool.");
-                                        Self::send_txs_to_mempool(self_mutex.clone()).await;
-                                    }
-                                    JobDeclaration::ProvideMissingTransactions(_) => {
-                                        debug!("Send message: PMT. Updating the JDS mempool.");
-                                        Self::send_txs_to_mempool(self_mutex.clone()).await;
-                                    }
-                                    JobDeclaration::ProvideMissingTransactionsSuccess(_) => {
-                                        error!("Send unexpected PMTS");
-                                    }
-                                    JobDeclaration::PushSolution(_) => todo!(),
+                            // `AllocateMiningJobToken` has no error response, it is dropped
+                            if message_type == MESSAGE_TYPE_DECLARE_MINING_JOB {
+                                if let Ok(declare_mining_job) =
+                                    binary_sv2::from_bytes::<DeclareMiningJob>(payload)
+                                {
+                                    let message_error = DeclareMiningJobError {
+                                        request_id: declare_mining_job.request_id,
+                                        error_code: "rate-limited"
+                                            .to_string()
+                                            .into_bytes()
+                                            .try_into()
+                                            .unwrap(),
+                                        error_details: Vec::new().try_into().unwrap(),
+                                    };
+                                    let _ = Self::send(
+                                        self_mutex.clone(),
+                                        JobDeclaration::DeclareMiningJobError(message_error),
+                                    )
+                                    .await;
                                 }
-                                Self::send(self_mutex.clone(), m).await.unwrap();
-                            }
-                            Ok(SendTo::RelayNewMessage(message)) => {
-                                error!("JD Server: unexpected relay new message {}", message);
                             }
-                            Ok(SendTo::RelayNewMessageToRemote(remote, message)) => {
-                                error!(
-                                    "JD Server: unexpected relay new message to remote. Remote: {:?}, Message: {}",
-                                    remote,
-                                    message
-                                );
-                            }
-                            Ok(SendTo::RelaySameMessageToRemote(remote)) => {
-                                error!(
-                                    "JD Server: unexpected relay same message to remote. Remote: {:?}",
-                                    remote
-                                );
-                            }
-                            Ok(SendTo::Multiple(multiple)) => {
-                                error!("JD Server: unexpected multiple messages: {:?}", multiple);
+                            continue;
+                        }
+                        RateLimitOutcome::Exceeded => {
+                            error!(
+                                "Rate limit repeatedly exceeded by {}, disconnecting",
+                                client
+                            );
+                            recv.close();
+                            handle_result!(tx_status, Err(JdsError::RateLimitExceeded(client)));
+                            break;
+                        }
+                    }
+                    let next_message_to_send =
+                        ParseJobDeclarationMessagesFromDownstream::handle_message_job_declaration(
+                            self_mutex.clone(),
+                            message_type,
+                            payload,
+                        );
+                    // How works the txs recognition and txs storing in JDS mempool
+                    // when a DMJ arrives, the JDS compares the received transactions with the
+                    // ids in the the JDS mempool. Then there are two scenarios
+                    // 1. the JDS recognizes all the transactions. Then, just before a DMJS is
+                    //    sent, the JDS mempool is triggered to fill in the JDS mempool the id
+                    //    of declared job with the full transaction (with send_tx_to_mempool
+                    //    method(), that eventually will ask the transactions to a bitcoin node
+                    //    via RPC)
+                    // 2. there are some unknown txids. Just before sending PMT, the JDS mempool
+                    //    is triggered to fill the known txids with the full transactions. When
+                    //    a PMTS arrives, just before sending a DMJS, the unknown full
+                    //    transactions provided by the downstream are added to the JDS mempool
+                    match next_message_to_send {
+                        Ok(SendTo::Respond(m)) => {
+                            match m {
+                                JobDeclaration::AllocateMiningJobToken(_) => {
+                                    error!("Send unexpected message: AMJT");
+                                }
+                                JobDeclaration::AllocateMiningJobTokenSuccess(_) => {
+                                    debug!("Send message: AMJTS");
+                                }
+                                JobDeclaration::DeclareMiningJob(_) => {
+                                    error!("Send unexpected message: DMJ");
+                                }
+                                JobDeclaration::DeclareMiningJobError(_) => {
+                                    debug!("Send nmessage: DMJE");
+                                }
+                                JobDeclaration::DeclareMiningJobSuccess(_) => {
+                                    debug!("Send message: DMJS. Updating the JDS mempool.");
+                                    Self::send_txs_to_mempool(self_mutex.clone()).await;
+                                }
+                                JobDeclaration::ProvideMissingTransactions(_) => {
+                                    debug!("Send message: PMT. Updating the JDS mempool.");
+                                    Self::send_txs_to_mempool(self_mutex.clone()).await;
+                                }
+                                JobDeclaration::ProvideMissingTransactionsSuccess(_) => {
+                                    error!("Send unexpected PMTS");
+                                }
+                                JobDeclaration::PushSolution(_) => todo!(),
                             }
-                            Ok(SendTo::None(m)) => {
-                                match m {
-                                    Some(JobDeclaration::PushSolution(message)) => {
-                                        match Self::collect_txs_in_job(self_mutex.clone()) {
-                                            Ok(_) => {
-                                                info!(
-                                                    "All transactions in downstream job are recognized correctly by the JD Server"
-                                                );
-                                                let hexdata =
+                            Self::send(self_mutex.clone(), m).await.unwrap();
+                        }
+                        Ok(SendTo::RelayNewMessage(message)) => {
+                            error!("JD Server: unexpected relay new message {}", message);
+                        }
+                        Ok(SendTo::RelayNewMessageToRemote(remote, message)) => {
+                            error!(
+                                "JD Server: unexpected relay new message to remote. Remote: {:?}, Message: {}",
+                                remote,
+                                message
+                            );
+                        }
+                        Ok(SendTo::RelaySameMessageToRemote(remote)) => {
+                            error!(
+                                "JD Server: unexpected relay same message to remote. Remote: {:?}",
+                                remote
+                            );
+                        }
+                        Ok(SendTo::Multiple(multiple)) => {
+                            error!("JD Server: unexpected multiple messages: {:?}", multiple);
+                        }
+                        Ok(SendTo::None(m)) => {
+                            match m {
+                                Some(JobDeclaration::PushSolution(message)) => {
+                                    // The solution may be of any job still held by the client
+                                    let declared_job = match Self::solved_job(&self_mutex, &message)
+                                    {
+                                        Ok(declared_job) => declared_job,
+                                        Err(e) => {
+                                            error!(
+                                                "Received solution but encountered error: {:?}",
+                                                e
+                                            );
+                                            recv.close();
+                                            break;
+                                        }
+                                    };
+                                    let mempool = self_mutex
+                                        .clone()
+                                        .safe_lock(|a| a.mempool.clone())
+                                        .unwrap();
+                                    match Self::collect_txs_in_job(&mempool, &declared_job) {
+                                        Ok(_) => {
+                                            info!(
+                                                "All transactions in downstream job are recognized correctly by the JD Server"
+                                            );
+                                            let hexdata =
+                                                match JobDeclaratorDownstream::get_block_hex(
+                                                    &mempool,
+                                                    &declared_job,
+                                                    message,
+                                                ) {
+                                                    Ok(inner) => inner,
+                                                    Err(e) => {
+                                                        error!(
+                                                        "Received solution but encountered error: {:?}",
+                                                        e
+                                                    );
+                                                        recv.close();
+                                                        //TODO should we brake it?
+                                                        break;
+                                                    }
+                                                };
+                                            let _ = new_block_sender.send(hexdata).await;
+                                        }
+                                        Err(error) => {
+                                            error!("Missing transactions: {:?}", error);
+                                            // TODO print here the ip of the downstream
+                                            let retrieve_transactions =
+                                                AddTrasactionsToMempoolInner {
+                                                    known_transactions: declared_job
+                                                        .known_transactions(),
+                                                    unknown_transactions: Vec::new(),
+                                                };
+                                            tokio::select! {
+                                                _ = JDsMempool::add_tx_data_to_mempool(mempool.clone(), retrieve_transactions) => {
                                                     match JobDeclaratorDownstream::get_block_hex(
-                                                        self_mutex.clone(),
-                                                        message,
+                                                        &mempool,
+                                                        &declared_job,
+                                                        message.clone(),
                                                     ) {
-                                                        Ok(inner) => inner,
+                                                        Ok(hexdata) => {
+                                                            let _ = new_block_sender.send(hexdata).await;
+                                                        },
                                                         Err(e) => {
-                                                            error!(
-                                                            "Received solution but encountered error: {:?}",
-                                                            e
-                                                        );
-                                                            recv.close();
-                                                            //TODO should we brake it?
-                                                            break;
+                                                            handle_result!(
+                                                                tx_status,
+                                                                Err(*e)
+                                                            );
                                                         }
                                                     };
-                                                let _ = new_block_sender.send(hexdata).await;
-                                            }
-                                            Err(error) => {
-                                                error!("Missing transactions: {:?}", error);
-                                                // TODO print here the ip of the downstream
-                                                let known_transactions =
-                                                    JobDeclaratorDownstream::get_transactions_in_job(
-                                                        self_mutex.clone()
-                                                    );
-                                                let retrieve_transactions =
-                                                    AddTrasactionsToMempoolInner {
-                                                        known_transactions,
-                                                        unknown_transactions: Vec::new(),
-                                                    };
-                                                let mempool = self_mutex
-                                                    .clone()
-                                                    .safe_lock(|a| a.mempool.clone())
-                                                    .unwrap();
-                                                tokio::select! {
-                                                    _ = JDsMempool::add_tx_data_to_mempool(mempool, retrieve_transactions) => {
-                                                        match JobDeclaratorDownstream::get_block_hex(
-                                                            self_mutex.clone(),
-                                                            message.clone(),
-                                                        ) {
-                                                            Ok(hexdata) => {
-                                                                let _ = new_block_sender.send(hexdata).await;
-                                                            },
-                                                            Err(e) => {
-                                                                handle_result!(
-                                                                    tx_status,
-                                                                    Err(*e)
-                                                                );
-                                                            }
-                                                        };
-                                                    }
-                                                    _ = tokio::time::sleep(Duration::from_secs(60)) => {}
                                                 }
+                                                _ = tokio::time::sleep(Duration::from_secs(60)) => {}
                                             }
-                                        };
-                                    }
-                                    Some(JobDeclaration::DeclareMiningJob(_)) => {
-                                        error!("JD Server received an unexpected message {:?}", m);
-                                    }
-                                    Some(JobDeclaration::DeclareMiningJobSuccess(_)) => {
-                                        error!("JD Server received an unexpected message {:?}", m);
-                                    }
-                                    Some(JobDeclaration::DeclareMiningJobError(_)) => {
-                                        error!("JD Server received an unexpected message {:?}", m);
-                                    }
-                                    Some(JobDeclaration::AllocateMiningJobToken(_)) => {
-                                        error!("JD Server received an unexpected message {:?}", m);
-                                    }
-                                    Some(JobDeclaration::AllocateMiningJobTokenSuccess(_)) => {
-                                        error!("JD Server received an unexpected message {:?}", m);
-                                    }
-                                    Some(JobDeclaration::ProvideMissingTransactions(_)) => {
-                                        error!("JD Server received an unexpected message {:?}", m);
-                                    }
-                                    Some(JobDeclaration::ProvideMissingTransactionsSuccess(_)) => {
-                                        error!("JD Server received an unexpected message {:?}", m);
-                                    }
-                                    None => (),
+                                        }
+                                    };
                                 }
-                            }
-                            Err(e) => {
-                                error!("{:?}", e);
-                                handle_result!(
-                                    tx_status,
-                                    Err(JdsError::Custom("Invalid message received".to_string()))
-                                );
-                                recv.close();
-                                break;
+                                Some(JobDeclaration::DeclareMiningJob(_)) => {
+                                    error!("JD Server received an unexpected message {:?}", m);
+                                }
+                                Some(JobDeclaration::DeclareMiningJobSuccess(_)) => {
+                                    error!("JD Server received an unexpected message {:?}", m);
+                                }
+                                Some(JobDeclaration::DeclareMiningJobError(_)) => {
+                                    error!("JD Server received an unexpected message {:?}", m);
+                                }
+                                Some(JobDeclaration::AllocateMiningJobToken(_)) => {
+                                    error!("JD Server received an unexpected message {:?}", m);
+                                }
+                                Some(JobDeclaration::AllocateMiningJobTokenSuccess(_)) => {
+                                    error!("JD Server received an unexpected message {:?}", m);
+                                }
+                                Some(JobDeclaration::ProvideMissingTransactions(_)) => {
+                                    error!("JD Server received an unexpected message {:?}", m);
+                                }
+                                Some(JobDeclaration::ProvideMissingTransactionsSuccess(_)) => {
+                                    error!("JD Server received an unexpected message {:?}", m);
+                                }
+                                None => (),
                             }
                         }
-                    }
-                    Err(err) => {
-                        handle_result!(tx_status, Err(JdsError::ChannelRecv(err)));
-                        break;
+                        Err(e) => {
+                            error!("{:?}", e);
+                            handle_result!(
+                                tx_status,
+                                Err(JdsError::Custom("Invalid message received".to_string()))
+                            );
+                            recv.close();
+                            break;
+                        }
                     }
                 }
+                Err(err) => {
+                    handle_result!(tx_status, Err(JdsError::ChannelRecv(err)));
+                    break;
+                }
             }
-        });
+        }
     }
 }
 
+/// Signs the mining job token of a declared job, so that the pool can check that the custom job
+/// it receives was declared here.
 pub fn signed_token(
-    tx_hash_list_hash: U256,
+    mining_job_token: &[u8],
     _pub_key: &Secp256k1PublicKey,
     prv_key: &Secp256k1SecretKey,
 ) -> B0255<'static> {
-    let secp = SignatureService::default();
-
-    let signature = secp.sign(tx_hash_list_hash.to_vec(), prv_key.0);
-
-    // Sign message
-    signature.as_ref().to_vec().try_into().unwrap()
+    sign_mining_job_token(mining_job_token, prv_key)
+        .expect("allocated tokens fit in 32 bytes")
+        .try_into()
+        .unwrap()
 }
 
 fn _get_random_token() -> B0255<'static> {
@@ -508,7 +614,7 @@ fn _get_random_token() -> B0255<'static> {
 ///
 /// Responsibilities:
 /// - Listening on the configured address
-/// - Performing the SV2 Noise handshake
+/// - Performing the SV2 Noise handshake, unless the listener is plaintext
 /// - Handling `SetupConnection` messages
 /// - Spawning the downstream message loop
 pub struct JobDeclarator {}
@@ -518,128 +624,215 @@ impl JobDeclarator {
     ///
     /// - Accepts configuration and shared components (status sender, mempool, etc.).
     /// - Initializes internal state.
-    /// - Begins listening for downstream connections via
+    /// - Begins accepting downstream connections on `listener` via
     ///   [`JobDeclarator::accept_incoming_connection`].
+    #[allow(clippy::too_many_arguments)]
     pub async fn start(
+        listener: TcpListener,
         config: JobDeclaratorServerConfig,
         status_tx: crate::status::Sender,
         mempool: Arc<Mutex<JDsMempool>>,
         new_block_sender: Sender<String>,
         sender_add_txs_to_mempool: Sender<AddTrasactionsToMempoolInner>,
+        persistence: Persistence,
+        banlist: Arc<BanList>,
+        connections: JdcConnections,
+        task_manager: Arc<TaskManager>,
     ) {
         let self_ = Arc::new(Mutex::new(Self {}));
         info!("JD INITIALIZED");
         Self::accept_incoming_connection(
             self_,
+            listener,
             config,
             status_tx,
             mempool,
             new_block_sender,
             sender_add_txs_to_mempool,
+            persistence,
+            banlist,
+            connections,
+            task_manager,
         )
         .await;
     }
+    #[allow(clippy::too_many_arguments)]
     async fn accept_incoming_connection(
         _self_: Arc<Mutex<JobDeclarator>>,
+        listener: TcpListener,
         config: JobDeclaratorServerConfig,
         status_tx: crate::status::Sender,
         mempool: Arc<Mutex<JDsMempool>>,
         new_block_sender: Sender<String>,
         sender_add_txs_to_mempool: Sender<AddTrasactionsToMempoolInner>,
+        persistence: Persistence,
+        banlist: Arc<BanList>,
+        connections: JdcConnections,
+        task_manager: Arc<TaskManager>,
     ) {
-        let listener = TcpListener::bind(config.listen_jd_address()).await.unwrap();
-
-        while let Ok((stream, _)) = listener.accept().await {
-            let responder = Responder::from_authority_kp(
-                &config.authority_public_key().into_bytes(),
-                &config.authority_secret_key().into_bytes(),
-                std::time::Duration::from_secs(config.cert_validity_sec()),
-            )
-            .unwrap();
+        while let Ok((stream, address)) = listener.accept().await {
+            if banlist.is_ip_banned(address.ip()) {
+                info!("Refusing connection from banned address {}", address);
+                continue;
+            }
 
-            let addr = stream.peer_addr();
-
-            if let Ok((receiver, sender)) =
-                Connection::new(stream, HandshakeRole::Responder(responder)).await
-            {
-                match receiver.recv().await {
-                    Ok(EitherFrame::Sv2(mut sv2_message)) => {
-                        debug!("Received SV2 message: {:?}", sv2_message);
-                        let payload = sv2_message.payload();
-
-                        if let Ok(setup_connection) =
-                            binary_sv2::from_bytes::<SetupConnection>(payload)
-                        {
-                            let flag = setup_connection.flags;
-                            let is_valid = SetupConnection::check_flags(
-                                Protocol::JobDeclarationProtocol,
-                                config.full_template_mode_required() as u32,
-                                flag,
-                            );
+            let deadline = HandshakeDeadline::start(address, config.handshake_timeout());
+            // The handshake runs on its own task, so that a stalling peer does not hold back the
+            // other connections.
+            task_manager.spawn_named(
+                "jdc_connection",
+                Self::handle_incoming_connection(
+                    stream,
+                    deadline,
+                    config.clone(),
+                    status_tx.clone(),
+                    mempool.clone(),
+                    new_block_sender.clone(),
+                    sender_add_txs_to_mempool.clone(),
+                    persistence.clone(),
+                    connections.clone(),
+                    task_manager.clone(),
+                ),
+            );
+        }
+    }
 
-                            if is_valid {
-                                let success_message = SetupConnectionSuccess {
-                                    used_version: 2,
-                                    flags: (setup_connection.flags & 1u32),
-                                };
-                                info!("Sending success message for proxy");
-                                let sv2_frame: StdFrame = JdsMessages::Common(success_message.into())
-        .try_into()
-        .expect("Failed to convert setup connection response message to standard frame");
-
-                                sender.send(sv2_frame.into()).await.unwrap();
-
-                                let jddownstream = Arc::new(Mutex::new(
-                                    JobDeclaratorDownstream::new(
-                                        (setup_connection.flags & 1u32) != 0u32, /* this takes a
-                                                                                  * bool instead
-                                                                                  * of u32 */
-                                        receiver.clone(),
-                                        sender.clone(),
-                                        &config,
-                                        mempool.clone(),
-                                        sender_add_txs_to_mempool.clone(), /* each downstream has its own sender (multi producer single consumer) */
-                                    ),
-                                ));
-
-                                JobDeclaratorDownstream::start(
-                                    jddownstream,
-                                    status_tx.clone(),
-                                    new_block_sender.clone(),
-                                );
-                            } else {
-                                let error_message = SetupConnectionError {
-                                    flags: flag,
-                                    error_code: "unsupported-feature-flags"
-                                        .to_string()
-                                        .into_bytes()
-                                        .try_into()
-                                        .unwrap(),
-                                };
-                                info!("Sending error message for proxy");
-                                let sv2_frame: StdFrame = JdsMessages::Common(error_message.into())
-        .try_into()
-        .expect("Failed to convert setup connection response message to standard frame");
+    /// Completes the Noise handshake, unless `listen_encryption` is `none`, and `SetupConnection`
+    /// of an accepted connection within `deadline`, then starts its [`JobDeclaratorDownstream`],
+    /// registered in `connections` while it runs.
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_incoming_connection(
+        stream: TcpStream,
+        deadline: HandshakeDeadline,
+        config: JobDeclaratorServerConfig,
+        status_tx: crate::status::Sender,
+        mempool: Arc<Mutex<JDsMempool>>,
+        new_block_sender: Sender<String>,
+        sender_add_txs_to_mempool: Sender<AddTrasactionsToMempoolInner>,
+        persistence: Persistence,
+        connections: JdcConnections,
+        task_manager: Arc<TaskManager>,
+    ) {
+        let addr = stream.peer_addr();
+
+        let connection = match config.listen_encryption() {
+            Encryption::Noise => {
+                let responder = Responder::from_authority_kp(
+                    &config.authority_public_key().into_bytes(),
+                    &config.authority_secret_key().into_bytes(),
+                    std::time::Duration::from_secs(config.cert_validity_sec()),
+                )
+                .unwrap();
+                let handshake = Connection::new(stream, HandshakeRole::Responder(responder));
+                let Some(connection) = deadline.run(handshake).await else {
+                    return;
+                };
+                connection
+            }
+            Encryption::None => Ok(PlainConnection::new(stream, &task_manager)),
+        };
+        if let Ok((receiver, sender)) = connection {
+            let Some(message) = deadline.run(receiver.recv()).await else {
+                return;
+            };
+            match message {
+                Ok(EitherFrame::Sv2(mut sv2_message)) => {
+                    debug!("Received SV2 message: {:?}", sv2_message);
+                    let payload = sv2_message.payload();
+
+                    if let Ok(setup_connection) = binary_sv2::from_bytes::<SetupConnection>(payload)
+                    {
+                        let flag = setup_connection.flags;
+                        let is_valid = SetupConnection::check_flags(
+                            Protocol::JobDeclarationProtocol,
+                            config.full_template_mode_required() as u32,
+                            flag,
+                        );
 
-                                sender.send(sv2_frame.into()).await.unwrap();
+                        if is_valid {
+                            let success_message = SetupConnectionSuccess {
+                                used_version: 2,
+                                flags: (setup_connection.flags & 1u32),
+                            };
+                            info!("Sending success message for proxy");
+                            let sv2_frame: StdFrame = JdsMessages::Common(success_message.into())
+    .try_into()
+    .expect("Failed to convert setup connection response message to standard frame");
+
+                            sender.send(sv2_frame.into()).await.unwrap();
+
+                            let full_template_mode_required =
+                                (setup_connection.flags & 1u32) != 0u32;
+                            let jddownstream = Arc::new(Mutex::new(JobDeclaratorDownstream::new(
+                                full_template_mode_required,
+                                receiver.clone(),
+                                sender.clone(),
+                                &config,
+                                mempool.clone(),
+                                sender_add_txs_to_mempool.clone(), /* each downstream has its own sender (multi producer single consumer) */
+                                addr.as_ref().map_or_else(
+                                    |_| "unknown".to_string(),
+                                    |addr| addr.to_string(),
+                                ),
+                                persistence.clone(),
+                            )));
+
+                            // Registered while its message loop runs, which disconnecting it
+                            // ends by closing the receiver.
+                            let id = addr.as_ref().ok().map(|address| {
+                                let shutdown = ShutdownHandle::new(move || {
+                                    receiver.close();
+                                });
+                                let connection = JdcConnection {
+                                    full_template_mode_required,
+                                };
+                                connections.super_safe_lock(|connections| {
+                                    let id = connections.allocate_id();
+                                    connections.register(id, *address, connection, shutdown);
+                                    id
+                                })
+                            });
+                            JobDeclaratorDownstream::run(
+                                jddownstream,
+                                status_tx.clone(),
+                                new_block_sender.clone(),
+                            )
+                            .await;
+                            if let Some(id) = id {
+                                connections.super_safe_lock(|connections| connections.remove(&id));
                             }
                         } else {
-                            error!("Error parsing SetupConnection message");
+                            let error_message = SetupConnectionError {
+                                flags: flag,
+                                error_code: "unsupported-feature-flags"
+                                    .to_string()
+                                    .into_bytes()
+                                    .try_into()
+                                    .unwrap(),
+                            };
+                            info!("Sending error message for proxy");
+                            let sv2_frame: StdFrame = JdsMessages::Common(error_message.into())
+    .try_into()
+    .expect("Failed to convert setup connection response message to standard frame");
+
+                            sender.send(sv2_frame.into()).await.unwrap();
                         }
-                    }
-                    Ok(EitherFrame::HandShake(handshake_message)) => {
-                        error!(
-                            "Unexpected handshake message from upstream: {:?} at {:?}",
-                            handshake_message, addr
-                        );
-                    }
-                    Err(e) => {
-                        error!("Error receiving message: {:?}", e);
+                    } else {
+                        error!("Error parsing SetupConnection message");
                     }
                 }
-            } else {
-                error!("Cannot connect to {:?}", addr);
+                Ok(EitherFrame::HandShake(handshake_message)) => {
+                    error!(
+                        "Unexpected handshake message from upstream: {:?} at {:?}",
+                        handshake_message, addr
+                    );
+                }
+                Err(e) => {
+                    error!("Error receiving message: {:?}", e);
+                }
             }
+        } else {
+            error!("Cannot connect to {:?}", addr);
         }
     }
 }
@@ -0,0 +1,129 @@
+//! Plaintext connections of the JDCs, accepted when `listen_encryption = "none"`.
+//!
+//! The counterpart of the Noise `Connection` of `network_helpers_sv2`: no handshake takes place,
+//! and the SV2 frames of the connection are exchanged over the same pair of channels, so that the
+//! rest of the JDS does not depend on the transport.
+
+use async_channel::{unbounded, Receiver, Sender};
+use binary_sv2::{Deserialize, GetSize, Serialize};
+use codec_sv2::{Encoder, StandardDecoder, StandardEitherFrame, StandardSv2Frame};
+use stratum_apps::runtime::TaskManager;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+use tracing::{debug, error};
+
+/// Plaintext counterpart of `network_helpers_sv2::noise_connection::Connection`.
+pub struct PlainConnection;
+
+impl PlainConnection {
+    /// Starts the reader and writer tasks of `stream` on `task_manager`, returning the receiver of
+    /// the incoming frames and the sender of the outgoing ones.
+    ///
+    /// Closing either channel, or the socket, closes both of them.
+    pub fn new<Message>(
+        stream: TcpStream,
+        task_manager: &TaskManager,
+    ) -> (
+        Receiver<StandardEitherFrame<Message>>,
+        Sender<StandardEitherFrame<Message>>,
+    )
+    where
+        Message: Serialize + Deserialize<'static> + GetSize + Send + 'static,
+    {
+        let (sender_incoming, receiver_incoming) = unbounded();
+        let (sender_outgoing, receiver_outgoing) = unbounded();
+        let (reader, writer) = stream.into_split();
+
+        Self::spawn_reader(
+            task_manager,
+            reader,
+            sender_incoming,
+            sender_outgoing.clone(),
+        );
+        Self::spawn_writer(
+            task_manager,
+            writer,
+            receiver_outgoing,
+            receiver_incoming.clone(),
+        );
+
+        (receiver_incoming, sender_outgoing)
+    }
+
+    fn spawn_reader<Message>(
+        task_manager: &TaskManager,
+        mut reader: OwnedReadHalf,
+        sender_incoming: Sender<StandardEitherFrame<Message>>,
+        sender_outgoing: Sender<StandardEitherFrame<Message>>,
+    ) where
+        Message: Serialize + Deserialize<'static> + GetSize + Send + 'static,
+    {
+        task_manager.spawn_named("plain_connection_reader", async move {
+            let mut decoder = StandardDecoder::<Message>::new();
+            loop {
+                if let Err(e) = reader.read_exact(decoder.writable()).await {
+                    debug!("Reader: socket closed: {e:?}");
+                    break;
+                }
+                match decoder.next_frame() {
+                    Ok(frame) => {
+                        if sender_incoming.send(frame.into()).await.is_err() {
+                            error!("Reader: channel closed, shutting down.");
+                            break;
+                        }
+                    }
+                    Err(codec_sv2::Error::MissingBytes(_)) => {}
+                    Err(e) => {
+                        error!("Reader: error while decoding frame: {e:?}");
+                        break;
+                    }
+                }
+            }
+
+            sender_incoming.close();
+            sender_outgoing.close();
+        });
+    }
+
+    fn spawn_writer<Message>(
+        task_manager: &TaskManager,
+        mut writer: OwnedWriteHalf,
+        receiver_outgoing: Receiver<StandardEitherFrame<Message>>,
+        receiver_incoming: Receiver<StandardEitherFrame<Message>>,
+    ) where
+        Message: Serialize + Deserialize<'static> + GetSize + Send + 'static,
+    {
+        task_manager.spawn_named("plain_connection_writer", async move {
+            let mut encoder = Encoder::<Message>::new();
+            while let Ok(frame) = receiver_outgoing.recv().await {
+                // Handshake frames only exist on Noise connections
+                let Ok(frame) = StandardSv2Frame::<Message>::try_from(frame) else {
+                    error!("Writer: unexpected handshake frame, shutting down.");
+                    break;
+                };
+                let bytes = match encoder.encode(frame) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        error!("Writer: error while encoding frame: {e:?}");
+                        break;
+                    }
+                };
+                if let Err(e) = writer.write_all(bytes).await {
+                    error!("Writer: error while writing frame: {e:?}");
+                    break;
+                }
+            }
+
+            if let Err(e) = writer.shutdown().await {
+                debug!("Writer: error during shutdown: {e:?}");
+            }
+            receiver_outgoing.close();
+            receiver_incoming.close();
+        });
+    }
+}
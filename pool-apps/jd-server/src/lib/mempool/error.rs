@@ -24,6 +24,11 @@ pub enum JdsMempoolError {
     NoClient,
     /// An RPC call to the Bitcoin node failed.
     Rpc(RpcError),
+    /// The Bitcoin node did not answer the RPC call of the given method in time.
+    RpcTimeout(&'static str),
+    /// The RPC call was refused without being sent, the Bitcoin node having failed too many
+    /// consecutive calls.
+    CircuitOpen,
     /// A poisoned lock was encountered while accessing the mempool
     PoisonLock(String),
 }
@@ -58,6 +63,10 @@ pub fn handle_error(err: &JdsMempoolError) {
             error!("{:?}", err);
             error!("Unable to establish RPC connection with Template Provider (possible reasons: not fully synced, down)");
         }
+        JdsMempoolError::RpcTimeout(_) | JdsMempoolError::CircuitOpen => {
+            warn!("{:?}", err);
+            warn!("Bitcoin node is not answering RPC calls in time (possible reasons: overloaded, busy validating a block)");
+        }
         JdsMempoolError::PoisonLock(_) => {
             error!("{:?}", err);
             error!("Poison lock error)");
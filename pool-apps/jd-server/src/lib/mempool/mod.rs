@@ -5,10 +5,16 @@
 //!
 //! Its core responsibilities are:
 //! - Keeping a local copy of txids and (optionally) their full transaction data
-//! - Pulling known transactions from the Bitcoin node on demand (via `getrawtransaction`)
+//! - Pulling known transactions from the Bitcoin node on demand (via `getrawtransaction`, all the
+//!   transactions of a job being requested concurrently)
 //! - Accepting and tracking raw transactions received from clients
 //! - Forwarding valid blocks to the Bitcoin node via `submitblock`
 //!
+//! The calls to the Bitcoin node go through the [`rpc::RpcClient`], which times them out and
+//! stops sending them while the node is overloaded. With a `[p2p_mempool]` table, the full data of
+//! the transactions is also received from the node over P2P (see [`p2p`]), so that most of them
+//! no longer need to be requested when a job is declared.
+//!
 //! Internally, `JDsMempool` uses a `HashMap<Txid, Option<(Transaction, u32)>>`:
 //! - `None`: transaction only known by ID, data is missing
 //! - `Some`: full transaction is known, `u32` is a reference counter for eviction
@@ -16,13 +22,21 @@
 //! Most methods are `Arc<Mutex<_>>`-wrapped and should be reviewed for locking efficiency.
 
 pub mod error;
+pub mod p2p;
+pub mod rpc;
 use super::job_declarator::AddTrasactionsToMempoolInner;
-use crate::mempool::error::JdsMempoolError;
+use crate::{
+    config::RpcClientConfig,
+    mempool::error::JdsMempoolError,
+    mempool::rpc::{RpcClient, RpcStats},
+};
 use async_channel::Receiver;
 use bitcoin::{blockdata::transaction::Transaction, hash_types::Txid};
 use hashbrown::HashMap;
 use rpc_sv2::{mini_rpc_client, mini_rpc_client::RpcError};
 use std::{str::FromStr, sync::Arc};
+use tokio::task::JoinSet;
+use tracing::error;
 
 use roles_logic_sv2::utils::Mutex;
 /// Wrapper around a known transaction and its hash.
@@ -37,24 +51,16 @@ pub struct TransactionWithHash {
 pub struct JDsMempool {
     /// Local map of known txids and their associated data (if available).
     pub mempool: HashMap<Txid, Option<(Transaction, u32)>>,
-    /// Auth for RPC connection to the node.
-    auth: mini_rpc_client::Auth,
-    /// URI of the Bitcoin node.
-    url: rpc_sv2::Uri,
+    /// RPC client of the node, unless its URL is not an HTTP one.
+    client: Option<Arc<RpcClient>>,
     /// Receiver for new block solutions coming from JDC.
     new_block_receiver: Receiver<String>,
 }
 
 impl JDsMempool {
-    /// Returns a MiniRpcClient if the URL looks valid.
-    pub fn get_client(&self) -> Option<mini_rpc_client::MiniRpcClient> {
-        let url = self.url.to_string();
-        if url.contains("http") {
-            let client = mini_rpc_client::MiniRpcClient::new(self.url.clone(), self.auth.clone());
-            Some(client)
-        } else {
-            None
-        }
+    /// Returns the RPC client of the node if the URL looks valid.
+    pub fn get_client(&self) -> Option<Arc<RpcClient>> {
+        self.client.clone()
     }
 
     /// This function is used only for debug purposes and should not be used
@@ -66,34 +72,83 @@ impl JDsMempool {
         tx_list_
     }
 
-    /// Instantiates a new empty mempool for JDS.
+    /// Instantiates a new empty mempool for JDS, recording its RPC calls in `rpc_stats`.
     pub fn new(
         url: rpc_sv2::Uri,
         username: String,
         password: String,
         new_block_receiver: Receiver<String>,
+        rpc_config: &RpcClientConfig,
+        rpc_stats: Arc<RpcStats>,
     ) -> Self {
         let auth = mini_rpc_client::Auth::new(username, password);
+        let client = url
+            .to_string()
+            .contains("http")
+            .then(|| Arc::new(RpcClient::new(url, auth, rpc_config, rpc_stats)));
         let empty_mempool: HashMap<Txid, Option<(Transaction, u32)>> = HashMap::new();
         JDsMempool {
             mempool: empty_mempool,
-            auth,
-            url,
+            client,
             new_block_receiver,
         }
     }
 
+    // Inserts the full data of `transaction`, counting one more reference to it.
+    fn add_transaction(&mut self, transaction: Transaction) {
+        self.mempool
+            .entry(transaction.compute_txid())
+            .and_modify(|entry| {
+                if let Some((_, count)) = entry {
+                    *count += 1;
+                } else {
+                    *entry = Some((transaction.clone(), 1));
+                }
+            })
+            .or_insert(Some((transaction, 1)));
+    }
+
+    /// Returns `true` if the full data of the transaction `txid` is known.
+    pub fn has_transaction(&self, txid: &Txid) -> bool {
+        matches!(self.mempool.get(txid), Some(Some(_)))
+    }
+
+    /// Inserts the full data of a transaction relayed by the node, unless already known. Returns
+    /// `true` if it was not.
+    pub fn add_relayed_transaction(&mut self, transaction: Transaction) -> bool {
+        let entry = self
+            .mempool
+            .entry(transaction.compute_txid())
+            .or_insert(None);
+        if entry.is_some() {
+            return false;
+        }
+        *entry = Some((transaction, 1));
+        true
+    }
+
+    /// Forgets the transactions confirmed by a block, returning how many of them were known.
+    pub fn remove_confirmed(&mut self, txids: impl IntoIterator<Item = Txid>) -> usize {
+        txids
+            .into_iter()
+            .filter(|txid| self.mempool.remove(txid).is_some())
+            .count()
+    }
+
     /// Simple RPC ping to verify connection to Bitcoin node.
     pub async fn health(self_: Arc<Mutex<Self>>) -> Result<(), JdsMempoolError> {
         let client = self_
             .safe_lock(|a| a.get_client())?
             .ok_or(JdsMempoolError::NoClient)?;
-        client.health().await.map_err(JdsMempoolError::Rpc)
+        client.health().await
     }
 
     /// Inserts transactions into the mempool:
-    /// - known txids are fetched from the Bitcoin node
+    /// - known txids are fetched from the Bitcoin node, concurrently
     /// - unknown txs are directly inserted
+    ///
+    /// The transactions fetched are inserted even if fetching others failed, the first error
+    /// being returned once all requests completed.
     pub async fn add_tx_data_to_mempool(
         self_: Arc<Mutex<Self>>,
         add_txs_to_mempool_inner: AddTrasactionsToMempoolInner,
@@ -104,47 +159,37 @@ impl JDsMempool {
             .safe_lock(|a| a.get_client())?
             .ok_or(JdsMempoolError::NoClient)?;
         // fill in the mempool the transactions id in the mempool with the full transactions
-        // retrieved from the jd client
+        // retrieved from the node, the client bounding the requests in flight
+        let mut requests = JoinSet::new();
         for txid in txids {
             if let Some(None) = self_
                 .safe_lock(|a| a.mempool.get(&txid).cloned())
                 .map_err(|e| JdsMempoolError::PoisonLock(e.to_string()))?
             {
-                let transaction = client
-                    .get_raw_transaction(&txid.to_string(), None)
-                    .await
-                    .map_err(JdsMempoolError::Rpc)?;
-                let _ = self_.safe_lock(|a| {
-                    a.mempool
-                        .entry(transaction.compute_txid())
-                        .and_modify(|entry| {
-                            if let Some((_, count)) = entry {
-                                *count += 1;
-                            } else {
-                                *entry = Some((transaction.clone(), 1));
-                            }
-                        })
-                        .or_insert(Some((transaction, 1)));
-                });
+                let client = client.clone();
+                requests.spawn(async move { client.get_raw_transaction(&txid).await });
+            }
+        }
+        let mut result = Ok(());
+        while let Some(request) = requests.join_next().await {
+            match request {
+                Ok(Ok(transaction)) => {
+                    let _ = self_.safe_lock(|a| a.add_transaction(transaction));
+                }
+                Ok(Err(e)) => {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+                Err(e) => error!("Failed to fetch a transaction from the node: {e}"),
             }
         }
 
         // fill in the mempool the transactions given in input
         for transaction in transactions {
-            let _ = self_.safe_lock(|a| {
-                a.mempool
-                    .entry(transaction.compute_txid())
-                    .and_modify(|entry| {
-                        if let Some((_, count)) = entry {
-                            *count += 1;
-                        } else {
-                            *entry = Some((transaction.clone(), 1));
-                        }
-                    })
-                    .or_insert(Some((transaction, 1)));
-            });
+            let _ = self_.safe_lock(|a| a.add_transaction(transaction));
         }
-        Ok(())
+        result
     }
 
     /// Periodically synchronizes the mempool with the Bitcoin node.
@@ -190,9 +235,9 @@ impl JDsMempool {
             .ok_or(JdsMempoolError::NoClient)?;
 
         while let Ok(block_hex) = new_block_receiver.recv().await {
-            match mini_rpc_client::MiniRpcClient::submit_block(&client, block_hex).await {
+            match client.submit_block(block_hex).await {
                 Ok(_) => return Ok(()),
-                Err(e) => JdsMempoolError::Rpc(e),
+                Err(e) => e,
             };
         }
         Ok(())
@@ -0,0 +1,426 @@
+//! ## P2P Mempool Synchronization
+//!
+//! Keeps the mempool of the JDS synchronized from the transaction relay of a Bitcoin node,
+//! instead of requesting each transaction of the declared jobs with `getrawtransaction`. The JDS
+//! connects to the node configured in `[p2p_mempool]` as a regular peer asking for transaction
+//! relay, then:
+//! - requests (`getdata`) every transaction and block the node announces (`inv`)
+//! - inserts the full data of the transactions received (`tx`) in the mempool
+//! - forgets the transactions confirmed by the blocks received (`block`)
+//!
+//! The transactions already in the mempool of the node when the connection is established are
+//! not announced: the mempool is polled over RPC once after each connection, their full data
+//! being requested over RPC when a job declares them. Until the node is connected again, the
+//! mempool is polled over RPC every `mempool_update_interval`, as without P2P synchronization.
+//!
+//! The node is dialed over TCP, or over a unix domain socket when `address` is `unix:<path>`, e.g.
+//! a node running on the same host behind a local socket.
+//!
+//! The state of the connection and the messages received are recorded in [`P2pSync`], served
+//! under `p2p_mempool` on the monitoring endpoint.
+
+use bitcoin::{
+    consensus::encode::{deserialize, serialize},
+    p2p::{
+        message::{NetworkMessage, RawNetworkMessage},
+        message_blockdata::Inventory,
+        message_network::VersionMessage,
+        Address, Magic, ServiceFlags,
+    },
+    Txid,
+};
+use roles_logic_sv2::utils::Mutex;
+use serde::Serialize;
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use stratum_apps::config_helpers::BitcoinNetwork;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tracing::{debug, info, warn};
+
+use super::JDsMempool;
+use crate::config::P2pMempoolConfig;
+
+/// User agent announced to the node.
+const USER_AGENT: &str = concat!("/jd_server:", env!("CARGO_PKG_VERSION"), "/");
+/// Size of the header of a P2P message: magic, command, payload length and checksum.
+const HEADER_SIZE: usize = 24;
+/// Largest payload accepted, the maximum size of a P2P message of Bitcoin Core.
+const MAX_PAYLOAD_SIZE: usize = 4_000_000;
+/// How long the node may take to complete the version handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the node may stay silent before the connection is deemed lost. Bitcoin Core pings
+/// its peers every two minutes.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// Prefix of the addresses designating a unix socket, as in the Template Provider addresses.
+const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// Returns the magic of the P2P messages of `network`.
+pub fn magic(network: BitcoinNetwork) -> Magic {
+    match network {
+        BitcoinNetwork::Mainnet => Magic::BITCOIN,
+        BitcoinNetwork::Testnet4 => Magic::from_bytes([0x1c, 0x16, 0x3f, 0x28]),
+        BitcoinNetwork::Signet => Magic::SIGNET,
+        BitcoinNetwork::Regtest => Magic::REGTEST,
+    }
+}
+
+/// State of the P2P synchronization of the mempool, as served on the monitoring endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct P2pStatus {
+    pub connected: bool,
+    /// Number of connections established with the node.
+    pub connections: u64,
+    /// Number of transactions received from the node whose full data was not known yet.
+    pub transactions_received: u64,
+    pub blocks_received: u64,
+    /// Number of transactions forgotten once confirmed by a block.
+    pub transactions_confirmed: u64,
+}
+
+/// State of the P2P synchronization of the mempool, shared with the RPC polling of the mempool.
+#[derive(Debug, Default)]
+pub struct P2pSync {
+    connected: AtomicBool,
+    // Set on each connection, so that the mempool is polled over RPC once more for the
+    // transactions the node will not announce.
+    resync: AtomicBool,
+    connections: AtomicU64,
+    transactions_received: AtomicU64,
+    blocks_received: AtomicU64,
+    transactions_confirmed: AtomicU64,
+}
+
+impl P2pSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if the mempool must be polled over RPC: the node is not connected, or it
+    /// connected since the last poll.
+    pub fn should_poll(&self) -> bool {
+        !self.connected.load(Ordering::Relaxed) || self.resync.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> P2pStatus {
+        P2pStatus {
+            connected: self.connected.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+            transactions_received: self.transactions_received.load(Ordering::Relaxed),
+            blocks_received: self.blocks_received.load(Ordering::Relaxed),
+            transactions_confirmed: self.transactions_confirmed.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_connected(&self, connected: bool) {
+        if connected {
+            self.connections.fetch_add(1, Ordering::Relaxed);
+            self.resync.store(true, Ordering::Relaxed);
+        }
+        self.connected.store(connected, Ordering::Relaxed);
+    }
+}
+
+/// Synchronizes `mempool` from the node of `config` for as long as the JDS runs, connecting
+/// again every `reconnect_interval_secs` once the connection failed or was lost.
+pub async fn run(
+    mempool: Arc<Mutex<JDsMempool>>,
+    config: P2pMempoolConfig,
+    magic: Magic,
+    sync: Arc<P2pSync>,
+) {
+    loop {
+        if let Err(e) = synchronize(&mempool, config.address(), magic, &sync).await {
+            warn!(
+                "P2P mempool synchronization with {} interrupted: {e}, polling the mempool over RPC",
+                config.address()
+            );
+        }
+        sync.set_connected(false);
+        tokio::time::sleep(config.reconnect_interval()).await;
+    }
+}
+
+// Connects to the node at `address` and handles its messages until the connection is lost.
+async fn synchronize(
+    mempool: &Mutex<JDsMempool>,
+    address: &str,
+    magic: Magic,
+    sync: &P2pSync,
+) -> io::Result<()> {
+    let mut peer = tokio::time::timeout(HANDSHAKE_TIMEOUT, Peer::connect(address, magic))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "handshake timed out"))??;
+    info!("Synchronizing the mempool from the transactions relayed by {address}");
+    sync.set_connected(true);
+    loop {
+        let message = tokio::time::timeout(IDLE_TIMEOUT, peer.receive())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "node stayed silent"))??;
+        match message {
+            NetworkMessage::Ping(nonce) => peer.send(NetworkMessage::Pong(nonce)).await?,
+            NetworkMessage::Inv(inventory) => {
+                let requests = mempool
+                    .safe_lock(|mempool| requests(&inventory, |txid| mempool.has_transaction(txid)))
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                if !requests.is_empty() {
+                    peer.send(NetworkMessage::GetData(requests)).await?;
+                }
+            }
+            NetworkMessage::Tx(transaction) => {
+                let inserted = mempool
+                    .safe_lock(|mempool| mempool.add_relayed_transaction(transaction))
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                if inserted {
+                    sync.transactions_received.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            NetworkMessage::Block(block) => {
+                let txids = block.txdata.iter().map(|tx| tx.compute_txid());
+                let confirmed = mempool
+                    .safe_lock(|mempool| mempool.remove_confirmed(txids))
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                debug!(
+                    "Block {} confirmed {confirmed} transactions of the mempool",
+                    block.block_hash()
+                );
+                sync.blocks_received.fetch_add(1, Ordering::Relaxed);
+                sync.transactions_confirmed
+                    .fetch_add(confirmed as u64, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Returns the `getdata` requests answering the announcement of `inventory`: the transactions
+// whose full data is not known yet, and the blocks, both with their witnesses.
+fn requests(inventory: &[Inventory], is_known: impl Fn(&Txid) -> bool) -> Vec<Inventory> {
+    inventory
+        .iter()
+        .filter_map(|item| match item {
+            Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+                (!is_known(txid)).then_some(Inventory::WitnessTransaction(*txid))
+            }
+            Inventory::Block(hash) | Inventory::WitnessBlock(hash) => {
+                Some(Inventory::WitnessBlock(*hash))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+// Connects to the node at `address`, a unix socket if it starts with `unix:` and a TCP
+// `host:port` otherwise, returning the halves of the connection and the local and remote
+// addresses announced in the version message, unspecified over a unix socket.
+async fn dial(address: &str) -> io::Result<(Reader, Writer, SocketAddr, SocketAddr)> {
+    if let Some(path) = address.strip_prefix(UNIX_SOCKET_PREFIX) {
+        let (reader, writer) = dial_unix(path).await?;
+        let unspecified = SocketAddr::from(([0, 0, 0, 0], 0));
+        return Ok((reader, writer, unspecified, unspecified));
+    }
+    let stream = TcpStream::connect(address).await?;
+    let (local, remote) = (stream.local_addr()?, stream.peer_addr()?);
+    let (reader, writer) = stream.into_split();
+    Ok((Box::new(reader), Box::new(writer), local, remote))
+}
+
+#[cfg(unix)]
+async fn dial_unix(path: &str) -> io::Result<(Reader, Writer)> {
+    let (reader, writer) = UnixStream::connect(path).await?.into_split();
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+#[cfg(not(unix))]
+async fn dial_unix(_path: &str) -> io::Result<(Reader, Writer)> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix sockets are not supported on this platform",
+    ))
+}
+
+// Connection to the node, past the version handshake.
+struct Peer {
+    reader: BufReader<Reader>,
+    writer: Writer,
+    magic: Magic,
+}
+
+impl Peer {
+    // Connects to the node at `address` and completes the version handshake, asking for the
+    // relay of its transactions.
+    async fn connect(address: &str, magic: Magic) -> io::Result<Self> {
+        let (reader, writer, local, remote) = dial(address).await?;
+        let mut peer = Self {
+            reader: BufReader::new(reader),
+            writer,
+            magic,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        let mut version = VersionMessage::new(
+            ServiceFlags::NONE,
+            timestamp,
+            Address::new(&remote, ServiceFlags::NONE),
+            Address::new(&local, ServiceFlags::NONE),
+            rand::random(),
+            USER_AGENT.to_string(),
+            0,
+        );
+        version.relay = true;
+        peer.send(NetworkMessage::Version(version)).await?;
+
+        let (mut version_received, mut verack_received) = (false, false);
+        while !(version_received && verack_received) {
+            match peer.receive().await? {
+                NetworkMessage::Version(_) => {
+                    version_received = true;
+                    peer.send(NetworkMessage::Verack).await?;
+                }
+                NetworkMessage::Verack => verack_received = true,
+                NetworkMessage::Ping(nonce) => peer.send(NetworkMessage::Pong(nonce)).await?,
+                _ => {}
+            }
+        }
+        Ok(peer)
+    }
+
+    async fn send(&mut self, message: NetworkMessage) -> io::Result<()> {
+        write_message(&mut self.writer, self.magic, message).await
+    }
+
+    async fn receive(&mut self) -> io::Result<NetworkMessage> {
+        read_message(&mut self.reader, self.magic).await
+    }
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    magic: Magic,
+    message: NetworkMessage,
+) -> io::Result<()> {
+    writer
+        .write_all(&serialize(&RawNetworkMessage::new(magic, message)))
+        .await?;
+    writer.flush().await
+}
+
+// Reads the next message, failing on a message of another network or whose payload is too large
+// or does not match its checksum.
+async fn read_message<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    magic: Magic,
+) -> io::Result<NetworkMessage> {
+    let mut bytes = vec![0; HEADER_SIZE];
+    reader.read_exact(&mut bytes).await?;
+    if bytes[..4] != magic.to_bytes() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message of another network",
+        ));
+    }
+    let length = u32::from_le_bytes(bytes[16..20].try_into().expect("length must be 4 bytes"));
+    let length = length as usize;
+    if length > MAX_PAYLOAD_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("payload of {length} bytes is too large"),
+        ));
+    }
+    bytes.resize(HEADER_SIZE + length, 0);
+    reader.read_exact(&mut bytes[HEADER_SIZE..]).await?;
+    let message: RawNetworkMessage =
+        deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(message.into_payload())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{hashes::Hash, BlockHash};
+
+    #[test]
+    fn requests_unknown_transactions_and_blocks() {
+        let known = Txid::from_byte_array([1; 32]);
+        let unknown = Txid::from_byte_array([2; 32]);
+        let block = BlockHash::from_byte_array([3; 32]);
+        let inventory = [
+            Inventory::Transaction(known),
+            Inventory::Transaction(unknown),
+            Inventory::Block(block),
+        ];
+        assert_eq!(
+            requests(&inventory, |txid| *txid == known),
+            vec![
+                Inventory::WitnessTransaction(unknown),
+                Inventory::WitnessBlock(block)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn messages_round_trip_on_their_network_only() {
+        let (mut client, mut node) = tokio::io::duplex(1024);
+        write_message(&mut client, Magic::REGTEST, NetworkMessage::Ping(42))
+            .await
+            .unwrap();
+        assert_eq!(
+            read_message(&mut node, Magic::REGTEST).await.unwrap(),
+            NetworkMessage::Ping(42)
+        );
+
+        write_message(&mut client, Magic::SIGNET, NetworkMessage::Verack)
+            .await
+            .unwrap();
+        let error = read_message(&mut node, Magic::REGTEST).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn handshakes_with_a_node_on_a_unix_socket() {
+        let path = std::env::temp_dir().join(format!("jds-p2p-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let node = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let NetworkMessage::Version(version) =
+                read_message(&mut stream, Magic::REGTEST).await.unwrap()
+            else {
+                panic!("the peer must open with its version");
+            };
+            assert!(version.relay);
+            write_message(
+                &mut stream,
+                Magic::REGTEST,
+                NetworkMessage::Version(version),
+            )
+            .await
+            .unwrap();
+            write_message(&mut stream, Magic::REGTEST, NetworkMessage::Verack)
+                .await
+                .unwrap();
+            read_message(&mut stream, Magic::REGTEST).await.unwrap()
+        });
+
+        let address = format!("{UNIX_SOCKET_PREFIX}{}", path.display());
+        Peer::connect(&address, Magic::REGTEST).await.unwrap();
+        assert_eq!(node.await.unwrap(), NetworkMessage::Verack);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,464 @@
+//! ## Bitcoin Core RPC Client
+//!
+//! [`RpcClient`] wraps the async RPC client of `rpc_sv2` for the JDS mempool. Requests run
+//! concurrently, up to `max_concurrent_requests` in flight at once, and each of them fails with
+//! [`JdsMempoolError::RpcTimeout`] if the node did not answer within `timeout_ms`.
+//!
+//! After `failure_threshold` consecutive failed or timed out requests, the node is deemed
+//! overloaded and the circuit opens: requests fail at once with
+//! [`JdsMempoolError::CircuitOpen`] instead of piling up on the node. Once `cooldown_secs` have
+//! elapsed, a single request probes the node, closing the circuit if it succeeds and opening it
+//! again otherwise. `submitblock` bypasses the circuit and is never timed out, so that a block is
+//! always handed to the node.
+//!
+//! The outcome and latency of the requests are recorded per method in [`RpcStats`], served under
+//! `rpc` on the monitoring endpoint.
+
+use bitcoin::{Transaction, Txid};
+use roles_logic_sv2::utils::Mutex;
+use rpc_sv2::mini_rpc_client::{Auth, MiniRpcClient, RpcError};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use super::error::JdsMempoolError;
+use crate::config::RpcClientConfig;
+
+/// State of the circuit between the JDS and the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Requests are sent to the node.
+    Closed,
+    /// The node is deemed overloaded, requests are refused.
+    Open,
+    /// A request is probing the node after the cooldown.
+    HalfOpen,
+}
+
+/// Circuit breaker refusing requests after consecutive failures, until a probe succeeds.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// Creates a closed circuit opening after `failure_threshold` consecutive failures, for
+    /// `cooldown`.
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            cooldown,
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+        }
+    }
+
+    /// Returns the state of the circuit.
+    pub fn state(&self) -> CircuitState {
+        self.state
+    }
+
+    /// Returns whether a request may be sent at `now`. Once the cooldown elapsed, the first
+    /// request allowed is the probe, and the others are refused until its outcome is recorded.
+    pub fn allows(&mut self, now: Instant) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::HalfOpen => false,
+            CircuitState::Open => {
+                let cooled_down = self
+                    .opened_at
+                    .is_none_or(|opened_at| now.duration_since(opened_at) >= self.cooldown);
+                if cooled_down {
+                    self.state = CircuitState::HalfOpen;
+                }
+                cooled_down
+            }
+        }
+    }
+
+    /// Records the outcome of a request completed at `now`. Returns the new state of the circuit
+    /// if it changed.
+    pub fn record(&mut self, success: bool, now: Instant) -> Option<CircuitState> {
+        let previous = self.state;
+        if success {
+            self.consecutive_failures = 0;
+            self.state = CircuitState::Closed;
+            self.opened_at = None;
+        } else {
+            self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+            let trips = self.state == CircuitState::HalfOpen
+                || self.consecutive_failures >= self.failure_threshold;
+            if trips && self.state != CircuitState::Open {
+                self.state = CircuitState::Open;
+                self.opened_at = Some(now);
+            }
+        }
+        (self.state != previous).then_some(self.state)
+    }
+}
+
+/// Outcome of an RPC request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Success,
+    Failure,
+    Timeout,
+    Rejected,
+}
+
+#[derive(Debug, Default)]
+struct MethodStats {
+    requests: u64,
+    failures: u64,
+    timeouts: u64,
+    rejected: u64,
+    latency_count: u64,
+    latency_total_us: u64,
+    latency_last_us: u64,
+    latency_max_us: u64,
+}
+
+impl MethodStats {
+    fn record(&mut self, outcome: Outcome, latency: Option<Duration>) {
+        self.requests += 1;
+        match outcome {
+            Outcome::Success => (),
+            Outcome::Failure => self.failures += 1,
+            Outcome::Timeout => self.timeouts += 1,
+            Outcome::Rejected => self.rejected += 1,
+        }
+        if let Some(latency) = latency {
+            let latency_us = latency.as_micros() as u64;
+            self.latency_count += 1;
+            self.latency_total_us += latency_us;
+            self.latency_last_us = latency_us;
+            self.latency_max_us = self.latency_max_us.max(latency_us);
+        }
+    }
+
+    fn snapshot(&self) -> MethodStatus {
+        MethodStatus {
+            requests: self.requests,
+            failures: self.failures,
+            timeouts: self.timeouts,
+            rejected: self.rejected,
+            latency: LatencyStatus {
+                count: self.latency_count,
+                last_us: self.latency_last_us,
+                max_us: self.latency_max_us,
+                mean_us: self
+                    .latency_total_us
+                    .checked_div(self.latency_count)
+                    .unwrap_or_default(),
+            },
+        }
+    }
+}
+
+/// Requests sent to the node: per method, how many failed, timed out or were refused by the open
+/// circuit, and the latency of those sent, along with the state of the circuit.
+#[derive(Debug)]
+pub struct RpcStats {
+    methods: Mutex<BTreeMap<&'static str, MethodStats>>,
+    in_flight: AtomicU64,
+    circuit: Mutex<CircuitState>,
+    circuit_opened: AtomicU64,
+}
+
+impl RpcStats {
+    pub fn new() -> Self {
+        Self {
+            methods: Mutex::new(BTreeMap::new()),
+            in_flight: AtomicU64::new(0),
+            circuit: Mutex::new(CircuitState::Closed),
+            circuit_opened: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, method: &'static str, outcome: Outcome, latency: Option<Duration>) {
+        let _ = self.methods.safe_lock(|methods| {
+            methods.entry(method).or_default().record(outcome, latency);
+        });
+    }
+
+    fn circuit_changed(&self, state: CircuitState) {
+        if state == CircuitState::Open {
+            self.circuit_opened.fetch_add(1, Ordering::Relaxed);
+        }
+        let _ = self.circuit.safe_lock(|circuit| *circuit = state);
+    }
+
+    /// Returns the status served under `rpc` on the monitoring endpoint.
+    pub fn snapshot(&self) -> RpcStatus {
+        RpcStatus {
+            circuit: self
+                .circuit
+                .safe_lock(|circuit| *circuit)
+                .unwrap_or(CircuitState::Closed),
+            circuit_opened: self.circuit_opened.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            methods: self
+                .methods
+                .safe_lock(|methods| {
+                    methods
+                        .iter()
+                        .map(|(method, stats)| (*method, stats.snapshot()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for RpcStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcStatus {
+    circuit: CircuitState,
+    // Number of times the circuit opened since startup.
+    circuit_opened: u64,
+    in_flight: u64,
+    methods: BTreeMap<&'static str, MethodStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct MethodStatus {
+    requests: u64,
+    failures: u64,
+    timeouts: u64,
+    rejected: u64,
+    latency: LatencyStatus,
+}
+
+#[derive(Debug, Serialize)]
+struct LatencyStatus {
+    count: u64,
+    last_us: u64,
+    max_us: u64,
+    mean_us: u64,
+}
+
+/// Async client of the Bitcoin Core RPC, with concurrent requests, per-request timeouts and a
+/// circuit breaker.
+pub struct RpcClient {
+    client: MiniRpcClient,
+    timeout: Duration,
+    permits: Semaphore,
+    breaker: Mutex<CircuitBreaker>,
+    stats: Arc<RpcStats>,
+}
+
+impl fmt::Debug for RpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcClient")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RpcClient {
+    /// Creates a client of the node at `url`, recording its requests in `stats`.
+    pub fn new(
+        url: rpc_sv2::Uri,
+        auth: Auth,
+        config: &RpcClientConfig,
+        stats: Arc<RpcStats>,
+    ) -> Self {
+        Self {
+            client: MiniRpcClient::new(url, auth),
+            timeout: config.timeout(),
+            permits: Semaphore::new(config.max_concurrent_requests()),
+            breaker: Mutex::new(CircuitBreaker::new(
+                config.failure_threshold(),
+                config.cooldown(),
+            )),
+            stats,
+        }
+    }
+
+    /// Checks that the node answers.
+    pub async fn health(&self) -> Result<(), JdsMempoolError> {
+        self.call("health", true, self.client.health()).await
+    }
+
+    /// Fetches the transaction `txid` from the mempool of the node.
+    pub async fn get_raw_transaction(&self, txid: &Txid) -> Result<Transaction, JdsMempoolError> {
+        let txid = txid.to_string();
+        self.call(
+            "getrawtransaction",
+            true,
+            self.client.get_raw_transaction(&txid, None),
+        )
+        .await
+    }
+
+    /// Lists the ids of the transactions in the mempool of the node.
+    pub async fn get_raw_mempool(&self) -> Result<Vec<String>, JdsMempoolError> {
+        self.call("getrawmempool", true, self.client.get_raw_mempool())
+            .await
+    }
+
+    /// Submits a block to the node, whatever the state of the circuit and however long the node
+    /// takes to answer.
+    pub async fn submit_block(&self, block_hex: String) -> Result<(), JdsMempoolError> {
+        self.call("submitblock", false, self.client.submit_block(block_hex))
+            .await
+    }
+
+    // Sends `request`, through the circuit breaker and with the timeout if `guarded`.
+    async fn call<T>(
+        &self,
+        method: &'static str,
+        guarded: bool,
+        request: impl Future<Output = Result<T, RpcError>>,
+    ) -> Result<T, JdsMempoolError> {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+        if guarded && !self.allows() {
+            self.stats.record(method, Outcome::Rejected, None);
+            return Err(JdsMempoolError::CircuitOpen);
+        }
+        self.stats.in_flight.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let result = if guarded {
+            match tokio::time::timeout(self.timeout, request).await {
+                Ok(result) => result.map_err(JdsMempoolError::Rpc),
+                Err(_) => Err(JdsMempoolError::RpcTimeout(method)),
+            }
+        } else {
+            request.await.map_err(JdsMempoolError::Rpc)
+        };
+        let latency = started.elapsed();
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+        let outcome = match &result {
+            Ok(_) => Outcome::Success,
+            Err(JdsMempoolError::RpcTimeout(_)) => Outcome::Timeout,
+            Err(_) => Outcome::Failure,
+        };
+        self.stats.record(method, outcome, Some(latency));
+        if guarded {
+            self.record(outcome == Outcome::Success);
+        }
+        result
+    }
+
+    fn allows(&self) -> bool {
+        self.breaker
+            .safe_lock(|breaker| breaker.allows(Instant::now()))
+            .unwrap_or(true)
+    }
+
+    fn record(&self, success: bool) {
+        let changed = self
+            .breaker
+            .safe_lock(|breaker| breaker.record(success, Instant::now()))
+            .unwrap_or_default();
+        match changed {
+            Some(CircuitState::Open) => {
+                warn!("Bitcoin Core RPC is failing, refusing requests for the cooldown")
+            }
+            Some(CircuitState::Closed) => info!("Bitcoin Core RPC recovered"),
+            _ => (),
+        }
+        if let Some(state) = changed {
+            self.stats.circuit_changed(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn circuit_opens_after_consecutive_failures() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::new(3, COOLDOWN);
+        assert_eq!(breaker.record(false, now), None);
+        assert_eq!(breaker.record(false, now), None);
+        // A success resets the count of consecutive failures.
+        assert_eq!(breaker.record(true, now), None);
+        assert_eq!(breaker.record(false, now), None);
+        assert_eq!(breaker.record(false, now), None);
+        assert!(breaker.allows(now));
+        assert_eq!(breaker.record(false, now), Some(CircuitState::Open));
+        assert!(!breaker.allows(now));
+        assert!(!breaker.allows(now + COOLDOWN / 2));
+    }
+
+    #[test]
+    fn single_probe_closes_or_reopens_the_circuit() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::new(1, COOLDOWN);
+        assert_eq!(breaker.record(false, now), Some(CircuitState::Open));
+
+        // Only one probe is let through once cooled down.
+        let probe = now + COOLDOWN;
+        assert!(breaker.allows(probe));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(!breaker.allows(probe));
+
+        // A failed probe opens the circuit for another cooldown.
+        assert_eq!(breaker.record(false, probe), Some(CircuitState::Open));
+        assert!(!breaker.allows(probe + COOLDOWN / 2));
+        assert!(breaker.allows(probe + COOLDOWN));
+        assert_eq!(
+            breaker.record(true, probe + COOLDOWN),
+            Some(CircuitState::Closed)
+        );
+        assert!(breaker.allows(probe + COOLDOWN));
+    }
+
+    #[test]
+    fn stats_are_recorded_per_method() {
+        let stats = RpcStats::new();
+        stats.record(
+            "getrawtransaction",
+            Outcome::Success,
+            Some(Duration::from_micros(10)),
+        );
+        stats.record(
+            "getrawtransaction",
+            Outcome::Timeout,
+            Some(Duration::from_micros(30)),
+        );
+        stats.record("getrawtransaction", Outcome::Rejected, None);
+        stats.circuit_changed(CircuitState::Open);
+
+        let status = serde_json::to_value(stats.snapshot()).unwrap();
+        assert_eq!(status["circuit"], "open");
+        assert_eq!(status["circuit_opened"], 1);
+        let method = &status["methods"]["getrawtransaction"];
+        assert_eq!(method["requests"], 3);
+        assert_eq!(method["timeouts"], 1);
+        assert_eq!(method["rejected"], 1);
+        assert_eq!(method["latency"]["count"], 2);
+        assert_eq!(method["latency"]["mean_us"], 20);
+        assert_eq!(method["latency"]["max_us"], 30);
+    }
+}
@@ -3,9 +3,10 @@
 //! This module serves as the central coordination layer of the Job Declarator Server (JDS).
 //!
 //! It connects all core components:
-//! - `mempool`: a local cache of Bitcoin transactions, synchronized via RPC.
+//! - `mempool`: a local cache of Bitcoin transactions, synchronized via RPC and optionally P2P.
 //! - `job_declarator`: protocol logic for handling downstream job declaration clients.
 //! - `status`: a simple health/error propagation mechanism.
+//! - `monitoring`: the document served by the optional HTTP monitoring endpoint.
 //! - `config`: configuration loader and accessor.
 //!
 //! The [`JobDeclaratorServer`] struct represents the entrypoint to the system's async runtime.
@@ -21,21 +22,35 @@ pub mod config;
 pub mod error;
 pub mod job_declarator;
 pub mod mempool;
+pub mod monitoring;
 pub mod status;
 use async_channel::{bounded, unbounded, Receiver, Sender};
 use config::JobDeclaratorServerConfig;
 use error::JdsError;
 use error_handling::handle_result;
-use job_declarator::JobDeclarator;
-use mempool::error::JdsMempoolError;
+use job_declarator::{JdcConnections, JobDeclarator};
+use mempool::{
+    error::JdsMempoolError,
+    p2p::{self, P2pSync},
+    rpc::RpcStats,
+};
+use monitoring::JdsStatusProvider;
 pub use rpc_sv2::Uri;
-use std::{ops::Sub, str::FromStr, sync::Arc};
+use std::{ops::Sub, str::FromStr, sync::Arc, time::Duration};
 
 use codec_sv2::{StandardEitherFrame, StandardSv2Frame};
+use noise_sv2::Responder;
 use parsers_sv2::AnyMessage as JdsMessages;
 use roles_logic_sv2::utils::Mutex;
-use tokio::{select, task};
-use tracing::{error, info, warn};
+use stratum_apps::{
+    banlist::BanList, connections::ConnectionRegistry, persistence::Persistence,
+    runtime::TaskManager,
+};
+use tokio::{net::TcpListener, select};
+use tracing::{debug, error, info, warn};
+
+/// How often the ban list file is checked for modifications.
+const BANLIST_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Type alias for incoming SV2 messages.
 pub type Message = JdsMessages<'static>;
@@ -80,19 +95,51 @@ impl JobDeclaratorServer {
         if config.core_rpc_url().ends_with('/') {
             config.set_core_rpc_url(config.core_rpc_url().trim_end_matches('/').to_string());
         }
-        let url = config.core_rpc_url().to_string() + ":" + &config.core_rpc_port().to_string();
+        if let Err(e) = config.check_network() {
+            error!("Configuration does not match its network: {e}");
+            return Err(JdsError::Config(e));
+        }
+        if let Err(e) = Responder::from_authority_kp(
+            &config.authority_public_key().into_bytes(),
+            &config.authority_secret_key().into_bytes(),
+            Duration::from_secs(config.cert_validity_sec()),
+        ) {
+            error!("Invalid authority keypair: {e:?}");
+            return Err(JdsError::Noise(e));
+        }
+        let Some(port) = config.core_rpc_port() else {
+            error!("`core_rpc_port` must be set when `network` is not");
+            return Err(JdsError::InvalidRPCUrl);
+        };
+        let url = config.core_rpc_url().to_string() + ":" + &port.to_string();
         let username = config.core_rpc_user();
         let password = config.core_rpc_pass();
         // Channel for sending new blocks to the Bitcoin node
         let (new_block_sender, new_block_receiver): (Sender<String>, Receiver<String>) =
             bounded(10);
         let url = Uri::from_str(&url.clone()).expect("Invalid core rpc url");
+        if let Err(e) = config.rpc_client().check() {
+            error!("Invalid `[rpc_client]` configuration: {e}");
+            return Err(JdsError::Config(e));
+        }
+        if let Err(e) = config.check_handshake_timeout() {
+            error!("Invalid configuration: {e}");
+            return Err(JdsError::Config(e));
+        }
+        // Connections of the Job Declarator Clients, served on the monitoring endpoint
+        let connections: JdcConnections = Arc::new(stratum_apps::custom_mutex::Mutex::new(
+            ConnectionRegistry::new(1),
+        ));
+        // Outcome and latency of the RPC calls, served on the monitoring endpoint
+        let rpc_stats = Arc::new(RpcStats::new());
         // Shared mempool instance
         let mempool = Arc::new(Mutex::new(mempool::JDsMempool::new(
             url,
             username.to_string(),
             password.to_string(),
             new_block_receiver,
+            config.rpc_client(),
+            rpc_stats.clone(),
         )));
         let mempool_update_interval = config.mempool_update_interval();
         let mempool_cloned_ = mempool.clone();
@@ -102,17 +149,71 @@ impl JobDeclaratorServer {
             error!("JDS Connection with bitcoin core failed {:?}", e);
             return Err(JdsError::MempoolError(e));
         }
+        // Durable record of job declarations and solutions (disabled when not configured)
+        let persistence = match Persistence::new(config.persistence()) {
+            Ok(persistence) => persistence.with_network(config.network()),
+            Err(e) => {
+                error!("Failed to initialize persistence: {}", e);
+                return Err(JdsError::Persistence(e));
+            }
+        };
+        // Bans of downstream addresses, managed in the ban list file if any
+        let banlist = match BanList::new(config.banlist()) {
+            Ok(banlist) => Arc::new(banlist),
+            Err(e) => {
+                error!("Failed to load the ban list: {}", e);
+                return Err(JdsError::Io(e));
+            }
+        };
+        // State of the P2P synchronization of the mempool, if enabled
+        let p2p_sync = config.p2p_mempool().map(|_| Arc::new(P2pSync::new()));
+        let task_manager = Arc::new(TaskManager::new());
+        // ========== Task: Serve the monitoring endpoint ========== //
+        if let Some(monitoring_config) = config.monitoring().cloned() {
+            let mut status_provider = JdsStatusProvider::new(connections.clone(), rpc_stats);
+            if let Some(sync) = p2p_sync.clone() {
+                status_provider = status_provider.with_p2p_mempool(sync);
+            }
+            let status_provider = Arc::new(status_provider);
+            task_manager.spawn_named("monitoring", async move {
+                let shutdown = std::future::pending::<()>();
+                if let Err(e) =
+                    stratum_apps::monitoring::serve(&monitoring_config, status_provider, shutdown)
+                        .await
+                {
+                    error!("Monitoring endpoint failed: {}", e);
+                }
+            });
+        }
+
         let (status_tx, status_rx) = unbounded();
         let sender = status::Sender::Downstream(status_tx.clone());
         let mut last_empty_mempool_warning =
             std::time::Instant::now().sub(std::time::Duration::from_secs(60));
 
+        // ========== Task: Synchronize the mempool from the P2P relay ========== //
+        if let (Some(p2p_config), Some(network), Some(sync)) = (
+            config.p2p_mempool().cloned(),
+            config.network(),
+            p2p_sync.clone(),
+        ) {
+            task_manager.spawn_named(
+                "p2p_mempool",
+                p2p::run(mempool.clone(), p2p_config, p2p::magic(network), sync),
+            );
+        }
+
         let sender_update_mempool = sender.clone();
         // ========== Task: Periodically update the mempool via RPC ========== //
-        task::spawn(async move {
+        task_manager.spawn_named("mempool_update", async move {
             loop {
+                // The node relays the transactions to the JDS while connected over P2P
                 let update_mempool_result: Result<(), mempool::error::JdsMempoolError> =
-                    mempool::JDsMempool::update_mempool(mempool_cloned_.clone()).await;
+                    if p2p_sync.as_ref().is_none_or(|sync| sync.should_poll()) {
+                        mempool::JDsMempool::update_mempool(mempool_cloned_.clone()).await
+                    } else {
+                        Ok(())
+                    };
                 if let Err(err) = update_mempool_result {
                     match err {
                         JdsMempoolError::EmptyMempool => {
@@ -130,6 +231,10 @@ impl JobDeclaratorServer {
                             mempool::error::handle_error(&err);
                             handle_result!(sender_update_mempool, Err(err));
                         }
+                        // The node is overloaded rather than down, the update is retried later
+                        JdsMempoolError::RpcTimeout(_) | JdsMempoolError::CircuitOpen => {
+                            mempool::error::handle_error(&err);
+                        }
                         JdsMempoolError::PoisonLock(_) => {
                             mempool::error::handle_error(&err);
                             handle_result!(sender_update_mempool, Err(err));
@@ -143,10 +248,25 @@ impl JobDeclaratorServer {
             }
         });
 
+        // ========== Task: Reload the ban list file when modified ========== //
+        if banlist.file().is_some() {
+            let banlist = banlist.clone();
+            task_manager.spawn_named("banlist_reload", async move {
+                loop {
+                    tokio::time::sleep(BANLIST_RELOAD_INTERVAL).await;
+                    match banlist.reload_if_modified() {
+                        Ok(true) => info!("Ban list reloaded: {} ban(s)", banlist.list().len()),
+                        Ok(false) => {}
+                        Err(e) => warn!("Failed to reload the ban list, keeping the bans: {}", e),
+                    }
+                }
+            });
+        }
+
         // ========== Task: Listen for SubmitSolution events ========== //
         let mempool_cloned = mempool.clone();
         let sender_submit_solution = sender.clone();
-        task::spawn(async move {
+        task_manager.spawn_named("submit_solution", async move {
             loop {
                 let result = mempool::JDsMempool::on_submit(mempool_cloned.clone()).await;
                 if let Err(err) = result {
@@ -169,26 +289,41 @@ impl JobDeclaratorServer {
         });
 
         // ========== Task: Launch Job Declarator server ========== //
+        // Bound before spawning, so that a listening address in use fails the startup.
+        let listener = match TcpListener::bind(config.listen_jd_address()).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to bind {}: {}", config.listen_jd_address(), e);
+                return Err(JdsError::Bind(config.listen_jd_address().to_string(), e));
+            }
+        };
         let cloned = config.clone();
         let mempool_cloned = mempool.clone();
         let (sender_add_txs_to_mempool, receiver_add_txs_to_mempool) = unbounded();
-        task::spawn(async move {
+        let job_declarator_task_manager = task_manager.clone();
+        task_manager.spawn_named("job_declarator", async move {
             JobDeclarator::start(
+                listener,
                 cloned,
                 sender,
                 mempool_cloned,
                 new_block_sender,
                 sender_add_txs_to_mempool,
+                persistence,
+                banlist,
+                connections,
+                job_declarator_task_manager,
             )
             .await
         });
 
         // ========== Task: Add transactions to mempool when received ========== //
-        task::spawn(async move {
+        let add_txs_task_manager = task_manager.clone();
+        task_manager.spawn_named("add_txs_to_mempool", async move {
             loop {
                 if let Ok(add_transactions_to_mempool) = receiver_add_txs_to_mempool.recv().await {
                     let mempool_cloned = mempool.clone();
-                    task::spawn(async move {
+                    add_txs_task_manager.spawn_named("add_tx_data", async move {
                         match mempool::JDsMempool::add_tx_data_to_mempool(
                             mempool_cloned,
                             add_transactions_to_mempool,
@@ -208,6 +343,7 @@ impl JobDeclaratorServer {
         });
 
         // ========== Central Runtime Loop: Shutdown and Error Reactions ========== //
+        let mut result = Ok(());
         loop {
             let task_status = select! {
                 task_status = status_rx.recv() => task_status,
@@ -236,6 +372,7 @@ impl JobDeclaratorServer {
                 }
                 status::State::TemplateProviderShutdown(err) => {
                     error!("SHUTDOWN from Upstream: {}\nTry to reconnecting or connecting to a new upstream", err);
+                    result = Err(err);
                     break;
                 }
                 status::State::Healthy(msg) => {
@@ -246,6 +383,19 @@ impl JobDeclaratorServer {
                 }
             }
         }
-        Ok(())
+
+        let running_tasks = task_manager.running_tasks();
+        info!("Aborting {} tasks still running", running_tasks.len());
+        for task in running_tasks {
+            debug!(
+                task = task.name,
+                spawned_at = task.spawned_at,
+                uptime_secs = task.uptime_secs,
+                restarts = task.restarts,
+                "Aborting task"
+            );
+        }
+        task_manager.abort_all().await;
+        result
     }
 }
@@ -0,0 +1,65 @@
+//! ## Monitoring Module
+//!
+//! Builds the document served by the JDS's HTTP monitoring endpoint
+//! (see [`stratum_apps::monitoring`]): the connections of the Job Declarator Clients, the requests
+//! sent to the Bitcoin Core RPC, per method, the state of their circuit breaker, the number of JDCs
+//! dropped for not completing their handshake in time and, if enabled, the P2P synchronization of
+//! the mempool.
+
+use serde::Serialize;
+use std::sync::Arc;
+use stratum_apps::{connections::ConnectionInfo, monitoring::StatusProvider, runtime};
+
+use crate::{
+    job_declarator::JdcConnections,
+    mempool::{
+        p2p::{P2pStatus, P2pSync},
+        rpc::{RpcStats, RpcStatus},
+    },
+};
+
+#[derive(Debug, Serialize)]
+struct JdsStatus {
+    connections: Vec<ConnectionInfo>,
+    rpc: RpcStatus,
+    handshake_timeouts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2p_mempool: Option<P2pStatus>,
+}
+
+/// [`StatusProvider`] of the JDS.
+pub struct JdsStatusProvider {
+    connections: JdcConnections,
+    rpc: Arc<RpcStats>,
+    p2p_mempool: Option<Arc<P2pSync>>,
+}
+
+impl JdsStatusProvider {
+    pub fn new(connections: JdcConnections, rpc: Arc<RpcStats>) -> Self {
+        Self {
+            connections,
+            rpc,
+            p2p_mempool: None,
+        }
+    }
+
+    /// Also serves the state of the P2P synchronization of the mempool.
+    pub fn with_p2p_mempool(mut self, p2p_mempool: Arc<P2pSync>) -> Self {
+        self.p2p_mempool = Some(p2p_mempool);
+        self
+    }
+}
+
+impl StatusProvider for JdsStatusProvider {
+    fn status(&self) -> serde_json::Value {
+        serde_json::to_value(JdsStatus {
+            connections: self
+                .connections
+                .super_safe_lock(|connections| connections.list()),
+            rpc: self.rpc.snapshot(),
+            handshake_timeouts: runtime::handshake_timeouts(),
+            p2p_mempool: self.p2p_mempool.as_ref().map(|sync| sync.snapshot()),
+        })
+        .expect("JDS status is always serializable")
+    }
+}
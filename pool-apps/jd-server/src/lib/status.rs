@@ -146,6 +146,12 @@ pub async fn handle_error(sender: &Sender, e: JdsError) -> error_handling::Error
         JdsError::MempoolError(_) => {
             send_status(sender, e, error_handling::ErrorBranch::Break).await
         }
+        JdsError::Persistence(_) => {
+            send_status(sender, e, error_handling::ErrorBranch::Break).await
+        }
+        JdsError::RateLimitExceeded(_) => {
+            send_status(sender, e, error_handling::ErrorBranch::Break).await
+        }
         JdsError::ImpossibleToReconstructBlock(_) => {
             send_status(sender, e, error_handling::ErrorBranch::Continue).await
         }
@@ -409,6 +415,22 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_handle_error_rate_limit_exceeded_error() {
+        let (tx, rx) = bounded(1);
+        let sender = Sender::Downstream(tx);
+        let error = JdsError::RateLimitExceeded("127.0.0.1:34264".to_string());
+        let error_string = error.to_string();
+        handle_error(&sender, error).await;
+        match rx.recv().await {
+            Ok(status) => match status.state {
+                State::Healthy(e) => assert_eq!(e, error_string),
+                _ => panic!("Unexpected state received"),
+            },
+            Err(_) => panic!("Failed to receive status"),
+        }
+    }
+
     #[tokio::test]
     async fn test_handle_error_last_mempool_error() {
         let (tx, rx) = bounded(1);
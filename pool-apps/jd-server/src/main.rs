@@ -1,28 +1,42 @@
 //! Entry point for the Job Declarator Server (JDS).
 //!
-//! This binary parses CLI arguments, loads the TOML configuration file, and
+//! This binary parses CLI arguments, loads the configuration file, and
 //! starts the main runtime defined in `jd_server::JobDeclaratorServer`.
 //!
 //! The actual task orchestration and shutdown logic are managed in `lib/mod.rs`.
 mod args;
 use args::process_cli_args;
 use jd_server::JobDeclaratorServer;
-use stratum_apps::config_helpers::logging::init_logging;
+use std::process::ExitCode;
+use stratum_apps::{
+    config_helpers::logging::{init_logging, shutdown_logging},
+    exit::ExitStatus,
+};
 use tracing::error;
 
 /// Entrypoint for the Job Declarator Server binary.
 ///
-/// Loads the configuration from TOML and initializes the main runtime
-/// defined in `jd_server::JobDeclaratorServer`. Errors during startup are logged.
+/// Loads the configuration file and initializes the main runtime
+/// defined in `jd_server::JobDeclaratorServer`. Errors are logged, and the process exits with
+/// their [`ExitStatus`].
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     let config = match process_cli_args() {
         Ok(cfg) => cfg,
         Err(e) => {
-            error!("Failed to process CLI arguments: {}", e);
-            return;
+            eprintln!("Failed to process CLI arguments: {}", e);
+            return e.exit_status().into();
         }
     };
-    init_logging(config.log_file());
-    let _ = JobDeclaratorServer::new(config).start().await;
+    init_logging(config.log_file(), config.logging());
+    let status = match JobDeclaratorServer::new(config).start().await {
+        Ok(()) => ExitStatus::Success,
+        Err(e) => {
+            let status = e.exit_status();
+            error!("JDS stopped: {e}, exiting with {status}");
+            status
+        }
+    };
+    shutdown_logging();
+    status.into()
 }
@@ -0,0 +1,174 @@
+//! Round-trip latency of a share through the I/O tasks of an SV2 connection: written by a
+//! downstream, read by the reader task of the pool, decoded and answered by a handler, then
+//! written back by the writer task of the pool.
+//!
+//! Both ends run on loopback TCP, once with the Noise transport and once in plaintext, to tell the
+//! cost of the encryption apart from the one of the routing.
+
+use async_channel::{unbounded, Receiver, Sender};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pool_sv2::utils::{Message, SV2Frame};
+use std::{sync::Arc, time::Duration};
+use stratum_apps::{
+    key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+    network_helpers::transport::{Encryption, Sv2TcpStream},
+    runtime::{spawn_io_tasks, IoTaskStatus, TaskManager},
+    stratum_core::{
+        codec_sv2::HandshakeRole,
+        mining_sv2::{SubmitSharesStandard, SubmitSharesSuccess},
+        noise_sv2::{Initiator, Responder},
+        parsers_sv2::{AnyMessage, Mining},
+    },
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+    sync::broadcast,
+};
+
+const CERTIFICATE_VALIDITY: Duration = Duration::from_secs(3600);
+
+// The I/O tasks of the bench only stop with the runtime.
+#[derive(Clone)]
+struct NeverStop;
+
+impl IoTaskStatus<()> for NeverStop {
+    fn stops_on(&self, _message: &()) -> bool {
+        false
+    }
+}
+
+// The channels of one end of a connection, once its I/O tasks are spawned.
+struct Endpoint {
+    outbound: Sender<SV2Frame>,
+    inbound: Receiver<SV2Frame>,
+}
+
+fn spawn_endpoint(
+    task_manager: &Arc<TaskManager>,
+    notify_shutdown: &broadcast::Sender<()>,
+    stream: Sv2TcpStream<Message>,
+) -> Endpoint {
+    let (reader, writer) = stream.into_split();
+    let (outbound, outbound_rx) = unbounded();
+    let (inbound_tx, inbound) = unbounded();
+    spawn_io_tasks(
+        task_manager.clone(),
+        reader,
+        writer,
+        outbound_rx,
+        inbound_tx,
+        notify_shutdown.clone(),
+        NeverStop,
+        None,
+    );
+    Endpoint { outbound, inbound }
+}
+
+// Connects a downstream to a pool over loopback and returns the downstream end. The pool end is
+// driven by a handler acknowledging every share it receives.
+async fn connect(
+    encryption: Encryption,
+    task_manager: &Arc<TaskManager>,
+    notify_shutdown: &broadcast::Sender<()>,
+) -> Endpoint {
+    let secret_key = Secp256k1SecretKey::generate();
+    let public_key = Secp256k1PublicKey::from(secret_key);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let responder = Responder::from_authority_kp(
+        &public_key.into_bytes(),
+        &secret_key.into_bytes(),
+        CERTIFICATE_VALIDITY,
+    )
+    .unwrap();
+    let pool = async {
+        let (stream, _) = listener.accept().await.unwrap();
+        Sv2TcpStream::<Message>::new(stream, encryption, HandshakeRole::Responder(responder))
+            .await
+            .unwrap()
+    };
+    let downstream = async {
+        let stream = TcpStream::connect(address).await.unwrap();
+        let initiator = Initiator::new(Some(public_key.0));
+        Sv2TcpStream::<Message>::new(stream, encryption, HandshakeRole::Initiator(initiator))
+            .await
+            .unwrap()
+    };
+    let (pool, downstream) = tokio::join!(pool, downstream);
+
+    let pool = spawn_endpoint(task_manager, notify_shutdown, pool);
+    task_manager.spawn_named("handler", handle_shares(pool));
+    spawn_endpoint(task_manager, notify_shutdown, downstream)
+}
+
+// Decodes the frames read by the pool and answers each share with a `SubmitSharesSuccess`.
+async fn handle_shares(pool: Endpoint) {
+    while let Ok(mut frame) = pool.inbound.recv().await {
+        let message_type = frame.get_header().expect("frame with a header").msg_type();
+        let success = match AnyMessage::try_from((message_type, frame.payload())) {
+            Ok(AnyMessage::Mining(Mining::SubmitSharesStandard(share))) => SubmitSharesSuccess {
+                channel_id: share.channel_id,
+                last_sequence_number: share.sequence_number,
+                new_submits_accepted_count: 1,
+                new_shares_sum: 1,
+            },
+            other => panic!("unexpected message: {other:?}"),
+        };
+        let frame: SV2Frame = AnyMessage::Mining(Mining::SubmitSharesSuccess(success))
+            .try_into()
+            .expect("encodable message");
+        if pool.outbound.send(frame).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn share(sequence_number: u32) -> SV2Frame {
+    let share = SubmitSharesStandard {
+        channel_id: 1,
+        sequence_number,
+        job_id: 1,
+        nonce: sequence_number,
+        ntime: 1_700_000_000,
+        version: 0x2000_0000,
+    };
+    AnyMessage::Mining(Mining::SubmitSharesStandard(share))
+        .try_into()
+        .expect("encodable message")
+}
+
+fn bench_frame_routing(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("pool_frame_routing");
+    group.warm_up_time(Duration::from_millis(500));
+    group.measurement_time(Duration::from_secs(3));
+    group.throughput(Throughput::Elements(1));
+
+    for (name, encryption) in [("noise", Encryption::Noise), ("plain", Encryption::None)] {
+        let task_manager = Arc::new(TaskManager::new());
+        let (notify_shutdown, _) = broadcast::channel(1);
+        let downstream = runtime.block_on(connect(encryption, &task_manager, &notify_shutdown));
+        let mut sequence_number = 0u32;
+        group.bench_function(BenchmarkId::new("round_trip", name), |b| {
+            b.iter(|| {
+                sequence_number = sequence_number.wrapping_add(1);
+                runtime.block_on(async {
+                    downstream
+                        .outbound
+                        .send(share(sequence_number))
+                        .await
+                        .unwrap();
+                    downstream.inbound.recv().await.unwrap()
+                })
+            })
+        });
+        runtime.block_on(task_manager.abort_all());
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_frame_routing);
+criterion_main!(benches);
@@ -0,0 +1,49 @@
+//! Cost of serializing the persistence records the pool writes for every share it validates.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::time::Duration;
+use stratum_apps::persistence::{PersistenceRecord, ShareEvent};
+
+fn share_event(accepted: bool) -> ShareEvent {
+    ShareEvent {
+        client: "192.168.1.20:49152".to_string(),
+        channel_id: 7,
+        account: accepted.then(|| "bigfarm".to_string()),
+        worker: accepted.then(|| "rig12".to_string()),
+        sequence_number: 1_024,
+        job_id: 42,
+        nonce: 0x1234_5678,
+        ntime: 1_700_000_000,
+        version: 0x2000_0000,
+        accepted,
+        error_code: (!accepted).then(|| "difficulty-too-low".to_string()),
+        share_difficulty: accepted.then_some(1_310.72),
+        best_share_difficulty: accepted.then_some(48_612.5),
+        notable: false,
+    }
+}
+
+fn bench_persistence(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pool_persistence");
+    group.warm_up_time(Duration::from_millis(500));
+    group.measurement_time(Duration::from_secs(3));
+    group.throughput(Throughput::Elements(1));
+
+    for (name, accepted) in [("accepted_share", true), ("rejected_share", false)] {
+        let event = share_event(accepted);
+        // The line the file backend appends, from the event handed to the persistence handle.
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let record = PersistenceRecord::new(event.clone(), Some("mainnet"));
+                let mut line = serde_json::to_vec(black_box(&record)).expect("serializable");
+                line.push(b'\n');
+                black_box(line)
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_persistence);
+criterion_main!(benches);
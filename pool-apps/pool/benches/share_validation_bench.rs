@@ -0,0 +1,124 @@
+//! Throughput of the validation of the shares submitted on a standard channel, the work the pool
+//! does for every `SubmitSharesStandard` it receives.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use pool_sv2::channel_manager::FULL_EXTRANONCE_SIZE;
+use std::time::Duration;
+use stratum_apps::stratum_core::{
+    binary_sv2::Seq0255,
+    bitcoin::{Amount, CompactTarget, ScriptBuf, Target, TxOut},
+    channels_sv2::server::{
+        jobs::{job_store::DefaultJobStore, standard::StandardJob},
+        standard::StandardChannel,
+    },
+    mining_sv2::SubmitSharesStandard,
+    template_distribution_sv2::{NewTemplate, SetNewPrevHash},
+};
+
+type Channel = StandardChannel<'static, DefaultJobStore<StandardJob<'static>>>;
+
+// A mainnet difficulty, so that no share of the bench is a block.
+const N_BITS: u32 = 0x1702_c4e4;
+const HEADER_TIMESTAMP: u32 = 1_700_000_000;
+const VERSION: u32 = 0x2000_0000;
+
+// A standard channel with an active job, returning it along with the id of that job. The target of
+// the channel follows `nominal_hash_rate`, so that the shares meet it or not.
+fn open_channel(nominal_hash_rate: f32) -> (Channel, u32) {
+    let mut channel = StandardChannel::new_for_pool(
+        1,
+        "bench".to_string(),
+        (0..FULL_EXTRANONCE_SIZE as u8).collect(),
+        Target::MAX,
+        nominal_hash_rate,
+        1,
+        6.0,
+        DefaultJobStore::new(),
+        "bench".to_string(),
+    )
+    .expect("valid channel parameters");
+
+    let template = NewTemplate {
+        template_id: 1,
+        future_template: true,
+        version: VERSION,
+        coinbase_tx_version: 2,
+        coinbase_prefix: vec![0x03, 0x40, 0x0d, 0x03]
+            .try_into()
+            .expect("a height push fits a B0255"),
+        coinbase_tx_input_sequence: u32::MAX,
+        coinbase_tx_value_remaining: 312_500_000,
+        coinbase_tx_outputs_count: 0,
+        coinbase_tx_outputs: Vec::new().try_into().expect("empty B064K"),
+        coinbase_tx_locktime: 0,
+        merkle_path: Seq0255::new(Vec::new()).expect("empty Seq0255"),
+    };
+    let pool_output = TxOut {
+        value: Amount::from_sat(template.coinbase_tx_value_remaining),
+        script_pubkey: ScriptBuf::from_bytes([&[0x00, 0x14][..], &[0xab; 20]].concat()),
+    };
+    channel
+        .on_new_template(template, vec![pool_output])
+        .expect("valid template");
+    let job_id = *channel
+        .get_future_template_to_job_id()
+        .get(&1)
+        .expect("future job id must exist");
+
+    let target = Target::from_compact(CompactTarget::from_consensus(N_BITS));
+    let prev_hash = SetNewPrevHash {
+        template_id: 1,
+        prev_hash: [0x11; 32].into(),
+        header_timestamp: HEADER_TIMESTAMP,
+        n_bits: N_BITS,
+        target: target.to_le_bytes().into(),
+    };
+    channel
+        .on_set_new_prev_hash(prev_hash)
+        .expect("valid prev hash");
+    (channel, job_id)
+}
+
+fn share(job_id: u32, sequence_number: u32) -> SubmitSharesStandard {
+    SubmitSharesStandard {
+        channel_id: 1,
+        sequence_number,
+        job_id,
+        // Shares must differ to not be rejected as duplicates.
+        nonce: sequence_number,
+        ntime: HEADER_TIMESTAMP,
+        version: VERSION,
+    }
+}
+
+fn bench_share_validation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pool_share_validation");
+    group.warm_up_time(Duration::from_millis(500));
+    group.measurement_time(Duration::from_secs(3));
+    group.throughput(Throughput::Elements(1));
+
+    // A target every hash meets: each share is accounted for as a valid one.
+    let (mut channel, job_id) = open_channel(1.0);
+    let mut sequence_number = 0u32;
+    group.bench_function("valid", |b| {
+        b.iter(|| {
+            sequence_number = sequence_number.wrapping_add(1);
+            black_box(channel.validate_share(share(job_id, sequence_number)))
+        })
+    });
+
+    // A target no hash meets: each share is rejected for its difficulty.
+    let (mut channel, job_id) = open_channel(1e18);
+    let mut sequence_number = 0u32;
+    group.bench_function("low_difficulty", |b| {
+        b.iter(|| {
+            sequence_number = sequence_number.wrapping_add(1);
+            black_box(channel.validate_share(share(job_id, sequence_number)))
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_share_validation);
+criterion_main!(benches);
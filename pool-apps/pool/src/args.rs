@@ -3,9 +3,29 @@
 //! Defines the `Args` struct and a function to process CLI arguments into a PoolConfig.
 
 use clap::Parser;
-use ext_config::{Config, File, FileFormat};
-use pool_sv2::config::PoolConfig;
-use std::path::PathBuf;
+#[cfg(feature = "payments")]
+use pool_sv2::payments::PaymentEngine;
+use pool_sv2::{
+    channel_manager::FULL_EXTRANONCE_SIZE, config::PoolConfig, pool_signature::PoolSignature,
+    template_receiver::CoinbaseConstraints,
+};
+#[cfg(feature = "payments")]
+use std::collections::HashSet;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+#[cfg(feature = "payments")]
+use stratum_apps::persistence::{
+    owed_balances, read_payments, FileBackend, PaymentEvent, PersistenceBackend, PersistenceRecord,
+};
+use stratum_apps::{
+    config_helpers::{self, BitcoinNetwork, ConfigCheck, ConfigProfile},
+    exit::ExitStatus,
+    persistence::{read_rounds, PayoutAddresses, PayoutBatch, PayoutFormat, PersistenceConfig},
+    stratum_core::bitcoin::{address::NetworkUnchecked, Address},
+    webhook::Webhook,
+};
 
 /// Holds the parsed CLI arguments for the Pool binary.
 #[derive(Parser, Debug)]
@@ -14,7 +34,7 @@ pub struct Args {
     #[arg(
         short = 'c',
         long = "config",
-        help = "Path to the TOML configuration file",
+        help = "Path to the configuration file, in TOML, YAML (.yaml, .yml) or JSON (.json)",
         default_value = "pool-config.toml"
     )]
     pub config_path: PathBuf,
@@ -24,19 +44,851 @@ pub struct Args {
         help = "Path to the log file. If not set, logs will only be written to stdout."
     )]
     pub log_file: Option<PathBuf>,
+    #[arg(
+        long = "check-config",
+        help = "Validate the configuration file, print a report and exit without starting the Pool"
+    )]
+    pub check_config: bool,
+    #[arg(
+        long = "export-payouts",
+        value_name = "EVENTS_FILE",
+        help = "Print the payouts of the rounds persisted to EVENTS_FILE, paid to the addresses of `payout_addresses`, and exit without starting the Pool"
+    )]
+    pub export_payouts: Option<PathBuf>,
+    #[arg(
+        long = "payout-format",
+        value_name = "FORMAT",
+        default_value_t = PayoutFormat::Csv,
+        help = "Format of the payouts printed by --export-payouts: `csv` or `json`"
+    )]
+    pub payout_format: PayoutFormat,
+    #[cfg(feature = "payments")]
+    #[arg(
+        long = "pay",
+        value_name = "EVENTS_FILE",
+        help = "Pay the balances owed by the rounds persisted to EVENTS_FILE from the `[payments]` wallet, record the payment to EVENTS_FILE and exit without starting the Pool"
+    )]
+    pub pay: Option<PathBuf>,
+    #[cfg(feature = "payments")]
+    #[arg(
+        long = "dry-run",
+        requires = "pay",
+        help = "Print the unsigned payment built by --pay without signing, broadcasting or recording it"
+    )]
+    pub dry_run: bool,
+    #[arg(
+        long = "generate-config",
+        value_name = "PROFILE",
+        num_args = 0..=1,
+        default_missing_value = "testnet",
+        help = "Write an example configuration with fresh authority keys for the `mainnet`, `testnet` (default), `signet` or `regtest` profile and exit"
+    )]
+    pub generate_config: Option<ConfigProfile>,
+    #[arg(
+        short = 'o',
+        long = "output",
+        requires = "generate_config",
+        help = "File written by --generate-config instead of stdout. Existing files are not overwritten."
+    )]
+    pub output: Option<PathBuf>,
+    #[arg(
+        long = "listen-address",
+        value_name = "ADDRESS",
+        help = "Address downstreams connect to, overrides `listen_address`"
+    )]
+    pub listen_address: Option<SocketAddr>,
+    #[arg(
+        long = "tp-address",
+        value_name = "ADDRESS",
+        help = "Template Provider address, overrides `tp_address`"
+    )]
+    pub tp_address: Option<String>,
+    #[arg(
+        long = "log-level",
+        value_name = "FILTER",
+        help = "Log level or filter directives (e.g. `debug` or `info,pool_sv2=debug`), overrides `logging.filter` (`RUST_LOG` still takes precedence)"
+    )]
+    pub log_level: Option<String>,
+    #[arg(
+        long = "coinbase-address",
+        value_name = "ADDRESS",
+        help = "Address the block rewards are paid to, overrides `coinbase_reward_script` with `addr(<ADDRESS>)`"
+    )]
+    pub coinbase_address: Option<String>,
+}
+
+impl Args {
+    /// Returns the settings overridden on the command line, by their path in the configuration.
+    pub fn config_overrides(&self) -> Vec<(&'static str, String)> {
+        let mut overrides = Vec::new();
+        if let Some(listen_address) = self.listen_address {
+            overrides.push(("listen_address", listen_address.to_string()));
+        }
+        if let Some(tp_address) = &self.tp_address {
+            overrides.push(("tp_address", tp_address.clone()));
+        }
+        if let Some(log_level) = &self.log_level {
+            overrides.push(("logging.filter", log_level.clone()));
+        }
+        if let Some(coinbase_address) = &self.coinbase_address {
+            overrides.push((
+                "coinbase_reward_script",
+                format!("addr({coinbase_address})"),
+            ));
+        }
+        overrides
+    }
 }
 
 /// Parses CLI arguments and loads the PoolConfig from the specified file.
-pub fn process_cli_args() -> PoolConfig {
+///
+/// Also returns the config path, so that the file can be read again at runtime.
+pub async fn process_cli_args() -> (PoolConfig, PathBuf) {
     let args = Args::parse();
-    let config_path = args.config_path.to_str().expect("Invalid config path");
-    let mut config: PoolConfig = Config::builder()
-        .add_source(File::new(config_path, FileFormat::Toml))
-        .build()
-        .and_then(|settings| settings.try_deserialize::<PoolConfig>())
-        .expect("Failed to load or deserialize config");
+    if let Some(profile) = args.generate_config {
+        let config = generate_config(profile);
+        if let Err(e) = config_helpers::write_generated_config(&config, args.output.as_deref()) {
+            eprintln!("Failed to write the generated config: {e}");
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    let overrides = args.config_overrides();
+    if args.check_config {
+        check_config(&args.config_path, &overrides).exit();
+    }
+    if let Some(events) = &args.export_payouts {
+        match export_payouts(&args.config_path, &overrides, events) {
+            Ok(batches) => {
+                for batch in &batches {
+                    for unmapped in &batch.unmapped {
+                        eprintln!(
+                            "Round {}: no payout address for `{}`, {} sats left out",
+                            batch.round, unmapped.user_identity, unmapped.amount
+                        );
+                    }
+                }
+                print!("{}", args.payout_format.render(&batches));
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Failed to export the payouts: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(feature = "payments")]
+    if let Some(events) = &args.pay {
+        match pay(&args.config_path, &overrides, events, args.dry_run).await {
+            Ok(Some(payment)) => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&payment).expect("a payment serializes")
+                );
+                std::process::exit(0);
+            }
+            Ok(None) => {
+                eprintln!("No balance reaches `min_payout_sat`, nothing to pay");
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Failed to pay: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    let mut config = load_config(&args.config_path, &overrides).unwrap_or_else(|e| {
+        eprintln!(
+            "Failed to load the config {}: {e}",
+            args.config_path.display()
+        );
+        ExitStatus::Config.exit()
+    });
 
     config.set_log_dir(args.log_file);
 
-    config
+    (config, args.config_path)
+}
+
+/// Prefix of the environment variables overriding the configuration file, e.g.
+/// `SV2_POOL__LISTEN_ADDRESS`.
+pub const ENV_PREFIX: &str = "SV2_POOL";
+
+/// Loads the PoolConfig from the file at `config_path`, overridden by the `SV2_POOL__*`
+/// environment variables and then by the command line `overrides`.
+pub fn load_config(
+    config_path: &Path,
+    overrides: &[(&str, String)],
+) -> Result<PoolConfig, ext_config::ConfigError> {
+    config_helpers::load_config_with_overrides(config_path, ENV_PREFIX, overrides)
+}
+
+/// Loads the configuration at `config_path`, with the command line `overrides`, and splits the
+/// reward of every round persisted to `events` between the addresses of `payout_addresses`.
+///
+/// Rounds persisted on another network than the configured one are skipped.
+pub fn export_payouts(
+    config_path: &Path,
+    overrides: &[(&str, String)],
+    events: &Path,
+) -> Result<Vec<PayoutBatch>, String> {
+    let config = load_config(config_path, overrides)
+        .map_err(|e| format!("failed to load {}: {e}", config_path.display()))?;
+    let addresses = match config.payout_addresses() {
+        Some(path) => PayoutAddresses::load(path).map_err(|e| {
+            format!(
+                "failed to load the payout addresses from {}: {e}",
+                path.display()
+            )
+        })?,
+        None => PayoutAddresses::default(),
+    }
+    .with_separator(config.worker_identity().separator());
+    let network = config.network().map(|network| network.to_string());
+    let rounds = read_rounds(events, network.as_deref())
+        .map_err(|e| format!("failed to read the rounds from {}: {e}", events.display()))?;
+    Ok(rounds
+        .iter()
+        .map(|round| PayoutBatch::new(round, &addresses))
+        .collect())
+}
+
+/// Loads the configuration at `config_path`, with the command line `overrides`, and pays the
+/// balances owed by the rounds persisted to `events` that the payments already recorded there
+/// have not settled. The broadcast payment is appended to `events`, a `dry_run` payment is only
+/// built.
+///
+/// Returns `None` if no balance reaches `min_payout_sat`.
+#[cfg(feature = "payments")]
+pub async fn pay(
+    config_path: &Path,
+    overrides: &[(&str, String)],
+    events: &Path,
+    dry_run: bool,
+) -> Result<Option<PaymentEvent>, String> {
+    let config = load_config(config_path, overrides)
+        .map_err(|e| format!("failed to load {}: {e}", config_path.display()))?;
+    let engine = PaymentEngine::new(
+        config
+            .payments()
+            .ok_or("no `[payments]` table is configured")?,
+        config.network(),
+    )
+    .map_err(|e| e.to_string())?;
+    let batches = export_payouts(config_path, overrides, events)?;
+    let network = config.network().map(|network| network.to_string());
+    let payments = read_payments(events, network.as_deref())
+        .map_err(|e| format!("failed to read the payments from {}: {e}", events.display()))?;
+    let spent: HashSet<String> = payments
+        .iter()
+        .filter(|payment| payment.submitted)
+        .flat_map(|payment| payment.inputs.iter().cloned())
+        .collect();
+    let owed = owed_balances(&batches, &payments);
+    let Some(payment) = engine
+        .pay(&owed, &spent, dry_run)
+        .await
+        .map_err(|e| e.to_string())?
+    else {
+        return Ok(None);
+    };
+    if payment.submitted {
+        let record = PersistenceRecord::new(payment.clone(), network);
+        FileBackend::open(events)
+            .and_then(|mut backend| {
+                backend.persist(&record)?;
+                backend.flush()
+            })
+            .map_err(|e| {
+                format!(
+                    "payment {} broadcast but not recorded to {}: {e}",
+                    payment.txid,
+                    events.display()
+                )
+            })?;
+    }
+    Ok(Some(payment))
+}
+
+/// Loads the configuration at `config_path`, with the command line `overrides`, and checks it
+/// without starting any service.
+pub fn check_config(config_path: &Path, overrides: &[(&str, String)]) -> ConfigCheck {
+    let mut report = ConfigCheck::new();
+    let config = match load_config(config_path, overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            report.fail(format!("parse {}", config_path.display()), e);
+            return report;
+        }
+    };
+    report.pass(format!("parse {}", config_path.display()), "ok");
+    report.pass("listen address", config.listen_address());
+    report.check(
+        "tcp options",
+        config.tcp().check().map(|()| config.tcp().to_string()),
+    );
+    report.check(
+        "network",
+        config.check_network().map(|()| match config.network() {
+            Some(network) => network.to_string(),
+            None => "not set".to_string(),
+        }),
+    );
+    report.socket_address("template provider address", &config.tp_address());
+    report.authority_keypair(config.authority_public_key(), config.authority_secret_key());
+    report.check(
+        "certificate validity",
+        match config.cert_validity_sec() {
+            0 => Err("`cert_validity_sec` must be positive".to_string()),
+            secs => Ok(format!("{secs}s")),
+        },
+    );
+    report.coinbase_reward_script(config.coinbase_reward_script());
+    match PoolSignature::parse(config.pool_signature()) {
+        Ok(pool_signature) => {
+            let coinbase_constraints = CoinbaseConstraints::new(
+                &[config.get_txout()],
+                &pool_signature,
+                FULL_EXTRANONCE_SIZE,
+            );
+            report.check(
+                "coinbase constraints",
+                coinbase_constraints
+                    .to_message()
+                    .map(|_| coinbase_constraints.to_string()),
+            );
+        }
+        Err(e) => report.fail("pool signature", e),
+    }
+    report.check(
+        "shares per minute",
+        match config.shares_per_minute() {
+            spm if spm > 0.0 => Ok(spm),
+            _ => Err("`shares_per_minute` must be positive"),
+        },
+    );
+    report.check(
+        "share batch size",
+        match config.share_batch_size() {
+            0 => Err("`share_batch_size` must be positive"),
+            size => Ok(size),
+        },
+    );
+    for (name, cap) in [
+        (
+            "channels per connection",
+            config.max_channels_per_connection(),
+        ),
+        ("channels per IP", config.max_channels_per_ip()),
+    ] {
+        report.check(
+            name,
+            match cap {
+                Some(0) => Err("a channel cap must be positive".to_string()),
+                Some(max) => Ok(format!("at most {max}")),
+                None => Ok("unlimited".to_string()),
+            },
+        );
+    }
+    let setup_connection = config.setup_connection();
+    report.check(
+        "setup connection",
+        match (
+            setup_connection.min_version(),
+            setup_connection.max_version(),
+        ) {
+            (min, max) if min <= max => Ok(format!(
+                "versions {min}..={max}, flags {:#05b}",
+                setup_connection.accepted_flags()
+            )),
+            _ => Err("`setup_connection.min_version` must not exceed `max_version`"),
+        },
+    );
+    report.pass(
+        "future jobs",
+        match config.future_jobs_ahead() {
+            Some(0) => "sent on activation".to_string(),
+            Some(max) => format!("at most {max} sent ahead of each new block"),
+            None => "all sent ahead".to_string(),
+        },
+    );
+    report.check(
+        "template staleness",
+        match config.template_staleness() {
+            Some(window) if window.is_zero() => {
+                Err("`template_staleness_secs` must be positive".to_string())
+            }
+            Some(window) => Ok(format!("warning after {}s", window.as_secs())),
+            None => Ok("not watched".to_string()),
+        },
+    );
+    report.check(
+        "share acknowledgement SLO",
+        match config.share_ack_slo() {
+            Some(slo) if slo.is_zero() => Err("`share_ack_slo_ms` must be positive".to_string()),
+            Some(slo) => Ok(format!("p99 under {}ms", slo.as_millis())),
+            None => Ok("not watched".to_string()),
+        },
+    );
+    let initial_difficulty = config.initial_difficulty();
+    report.check(
+        "initial difficulty",
+        match (
+            initial_difficulty.min_difficulty(),
+            initial_difficulty.max_difficulty(),
+        ) {
+            (Some(min), _) if !(min.is_finite() && min > 0.0) => {
+                Err("`min_difficulty` must be positive".to_string())
+            }
+            (_, Some(max)) if !(max.is_finite() && max > 0.0) => {
+                Err("`max_difficulty` must be positive".to_string())
+            }
+            (Some(min), Some(max)) if min > max => {
+                Err("`min_difficulty` must not be greater than `max_difficulty`".to_string())
+            }
+            (None, None) => Ok("from the announced hashrate".to_string()),
+            (min, max) => Ok(format!(
+                "from the announced hashrate, between {} and {}",
+                min.map_or("0".to_string(), |min| min.to_string()),
+                max.map_or("unbounded".to_string(), |max| max.to_string())
+            )),
+        },
+    );
+    for floor in config.difficulty_floors() {
+        report.check(
+            format!("difficulty floor of `{}`", floor.user_identity()),
+            match floor.min_difficulty() {
+                difficulty if difficulty.is_finite() && difficulty > 0.0 => Ok(format!(
+                    "{difficulty}, from {} h/s",
+                    floor.min_hashrate(config.shares_per_minute())
+                )),
+                _ => Err("`min_difficulty` must be positive"),
+            },
+        );
+    }
+    for (index, rule) in config.device_rules().iter().enumerate() {
+        report.check(
+            format!("device rule #{}", index + 1),
+            if rule.has_patterns() {
+                Ok(format!("{:?}", rule.action()).to_lowercase())
+            } else {
+                Err("no `vendor`, `hardware_version`, `firmware` or `device_id` pattern set")
+            },
+        );
+    }
+    report.pass(
+        "worker identity",
+        format!("`account{}worker`", config.worker_identity().separator()),
+    );
+    match config.share_rate_limit() {
+        Some(share_rate_limit) => {
+            let bucket = share_rate_limit.bucket(config.shares_per_minute());
+            report.check(
+                "share rate limit",
+                match bucket.rate {
+                    rate if rate.is_finite() && rate > 0.0 => Ok(format!(
+                        "{rate:.2} shares/s per channel (burst {}), closed after {} rejected shares{}",
+                        bucket.burst,
+                        share_rate_limit.max_violations(),
+                        match share_rate_limit.ban_duration() {
+                            Some(ban) => format!(" and banned for {}s", ban.as_secs()),
+                            None => String::new(),
+                        }
+                    )),
+                    _ => Err("`share_rate_limit.rate_multiplier` must be positive"),
+                },
+            );
+        }
+        None => report.pass("share rate limit", "disabled"),
+    }
+    report.pass(
+        "custom job tokens",
+        if !cfg!(feature = "jd") {
+            "custom jobs refused, built without the `jd` feature".to_string()
+        } else if !config.setup_connection().work_selection() {
+            "custom jobs refused".to_string()
+        } else {
+            match config.jds_authority_public_key() {
+                Some(keys) => format!("verified against {} JDS key(s)", keys.keys().len()),
+                None => "not verified".to_string(),
+            }
+        },
+    );
+    if let Some(monitoring) = config.monitoring() {
+        report.pass("monitoring address", monitoring.listen_address);
+    }
+    #[cfg(feature = "control")]
+    if let Some(control) = config.control() {
+        let authentication = match control.auth_token {
+            Some(_) => "token required",
+            None => "unauthenticated",
+        };
+        report.pass(
+            "control address",
+            format!("{} ({authentication})", control.listen_address),
+        );
+    }
+    report.banlist(config.banlist());
+    match config.persistence() {
+        Some(PersistenceConfig::File { path }) => report.appendable_file("persistence", path),
+        None => report.pass("persistence", "disabled"),
+    }
+    match config.session_resumption() {
+        Some(session_resumption) => {
+            report.appendable_file("session resumption", session_resumption.path())
+        }
+        None => report.pass("session resumption", "disabled"),
+    }
+    match config.extranonce_allocator() {
+        Some(allocator) => report.appendable_file("extranonce allocator", allocator.path()),
+        None => report.pass("extranonce allocator", "not persisted"),
+    }
+    match config.accounting() {
+        Some(accounting) => report.appendable_file("accounting snapshot", accounting.path()),
+        None => report.pass("accounting snapshot", "disabled"),
+    }
+    match config.lifetime() {
+        Some(lifetime) => report.appendable_file("lifetime statistics", lifetime.path()),
+        None => report.pass("lifetime statistics", "disabled"),
+    }
+    match config.coordination() {
+        Some(coordination) => report.resolvable_address("coordination", coordination.address()),
+        None => report.pass("coordination", "disabled"),
+    }
+    match config.notable_shares() {
+        Some(notable_shares) => {
+            report.pass(
+                "notable shares",
+                format!(
+                    "from {}% of the network difficulty",
+                    notable_shares.threshold() * 100.0
+                ),
+            );
+            if let Some(url) = notable_shares.webhook_url() {
+                report.check("notable share webhook", Webhook::parse_url(url));
+            }
+        }
+        None => report.pass("notable shares", "disabled"),
+    }
+    match config.payout_addresses() {
+        Some(path) => report.check(
+            "payout addresses",
+            check_payout_addresses(path, config.network()),
+        ),
+        None => report.pass("payout addresses", "not set"),
+    }
+    match config.payments() {
+        #[cfg(feature = "payments")]
+        Some(payments) => report.check(
+            "payments",
+            PaymentEngine::new(payments, config.network()).map(|_| {
+                format!(
+                    "from {} at {} sat/vB, at least {} sats",
+                    payments.rpc_url(),
+                    payments.fee_rate(),
+                    payments.min_payout()
+                )
+            }),
+        ),
+        #[cfg(not(feature = "payments"))]
+        Some(_) => report.fail(
+            "payments",
+            "`[payments]` is set but the Pool is built without the `payments` feature",
+        ),
+        None => report.pass("payments", "disabled"),
+    }
+    report
+}
+
+// Loads the payout addresses at `path` and checks that each one is valid on `network`, if set.
+fn check_payout_addresses(path: &Path, network: Option<BitcoinNetwork>) -> Result<String, String> {
+    let addresses = PayoutAddresses::load(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut count = 0;
+    for (user, address) in addresses.iter() {
+        let address = address
+            .parse::<Address<NetworkUnchecked>>()
+            .map_err(|e| format!("address of `{user}`: {e}"))?;
+        if let Some(network) = network {
+            address
+                .require_network(network.bitcoin_network())
+                .map_err(|e| format!("address of `{user}`: {e}"))?;
+        }
+        count += 1;
+    }
+    Ok(format!("{count} users from {}", path.display()))
+}
+
+/// Renders a commented configuration for `profile`, with a fresh authority keypair.
+pub fn generate_config(profile: ConfigProfile) -> String {
+    let (authority_public_key, authority_secret_key) = config_helpers::generate_authority_keys();
+    let network = profile.network();
+    let tp_port = profile.template_provider_port();
+    let coinbase_reward_script = profile.coinbase_reward_script();
+    let rpc_port = profile.bitcoin_rpc_port();
+    format!(
+        r#"# SRI Pool config ({profile}), generated with --generate-config
+# Every setting can be overridden by an environment variable named after it, prefixed with
+# SV2_POOL__ (nested tables separated by __). Validate this file with --check-config.
+
+# Bitcoin network: "mainnet", "testnet4", "signet" or "regtest". The coinbase reward address must
+# belong to it, a Template Provider on the default port of another network is refused,
+# `tp_address` gets the default port of the network if it has none, and persisted events are
+# tagged with it.
+network = "{network}"
+
+# Authority keypair signing the certificates of the Noise handshakes. Downstreams pin the public
+# key, keep the secret key secret.
+authority_public_key = "{authority_public_key}"
+authority_secret_key = "{authority_secret_key}"
+# Instead of the line above, the secret key can be read from a file or an environment variable:
+# authority_secret_key_file = "/run/secrets/authority_key"
+# authority_secret_key_env = "AUTHORITY_SECRET_KEY"
+cert_validity_sec = 3600
+# Disconnect sessions once their certificate expired, so that they reconnect under a freshly signed
+# one. Otherwise they keep working and are reported as running on an expired certificate.
+# renew_expired_certificates = true
+listen_address = "0.0.0.0:34254"
+# Transport of downstream connections: "noise" (default) or "none" for plaintext SV2 frames.
+# Only disable Noise when every downstream sits on a trusted network.
+# listen_encryption = "none"
+# Maximum size in bytes of a frame received from a downstream, larger frames abort the connection.
+# max_frame_size = 1048576
+# Seconds a downstream may stay silent before being disconnected.
+# downstream_idle_timeout_secs = 600
+# Seconds an accepted downstream may take to complete its Noise handshake and SetupConnection
+# before being disconnected (10 by default).
+# handshake_timeout_secs = 10
+# Caps on the channels a downstream connection, and all the connections from the same IP address,
+# may have open at once. Channels over the cap are refused with OpenMiningChannelError
+# (`too-many-channels-per-connection` or `too-many-channels-per-ip`). Unlimited if unset.
+# max_channels_per_connection = 100
+# max_channels_per_ip = 1000
+
+# Coinbase outputs are specified as descriptors. A full list of descriptors is available at
+#     https://github.com/bitcoin/bips/blob/master/bip-0380.mediawiki#appendix-b-index-of-script-expressions
+# REPLACE this placeholder with your own address before mining: `addr(<address here>)`.
+coinbase_reward_script = "{coinbase_reward_script}"
+
+# Server Id (number to guarantee unique search space allocation across different Pool servers)
+server_id = 1
+
+# Pool signature (string to be included in coinbase tx). `{height}`, `{server_id}` and `{date}`
+# are expanded when a channel opens, `{{` and `}}` stand for literal braces.
+pool_signature = "Stratum V2 SRI Pool"
+
+# Public key of the Job Declarator Server authority, which signs the mining job tokens of the
+# custom jobs it accepted. Custom jobs whose token it did not sign are refused with
+# `invalid-mining-job-token`. Not checked if unset. A list of keys accepts any of them.
+# jds_authority_public_key = ""
+
+# Logs are written to this file if set, --log-file (or -f) overrides it.
+# log_file = "./pool.log"
+
+# Template Provider config (Bitcoin Core with Stratum V2 support, run with -sv2)
+tp_address = "127.0.0.1:{tp_port}"
+# Public key of the Template Provider authority, logged by the TP at startup. Not checked if unset.
+# A list of keys (e.g. the current and the next one during a key rotation) accepts any of them.
+# tp_authority_public_key = ""
+shares_per_minute = 6.0
+share_batch_size = 10
+# Future jobs are sent to downstreams ahead of the block they build on, so that miners switch to
+# it on a short SetNewPrevHash. Past `future_jobs_ahead` per block, they are only sent along with
+# the SetNewPrevHash activating them, trading switchover latency for bandwidth (0 never sends them
+# ahead, all are sent ahead if unset). `future_jobs_min_ntime` is the min_ntime of SetNewPrevHash:
+# "template" (default) uses the template timestamp, "now" the current time if later.
+# future_jobs_ahead = 1
+# future_jobs_min_ntime = "now"
+# Seconds without a new template after which the templates are stale: a warning is logged once per
+# window and the template receiver reported degraded until one arrives. Not watched if unset.
+# template_staleness_secs = 120
+# Milliseconds the p99 of the share acknowledgements, from the reception of a share to its answer
+# being queued to the downstream, is expected to stay under. A warning is logged and the share
+# acknowledgements reported degraded for every minute more than 1% of them take longer. Their
+# latencies are reported in the status either way.
+# share_ack_slo_ms = 50
+# File mapping user identities, or the account of `account.worker` identities, to the addresses
+# their share of the block rewards is paid to, one `<user_identity> <address>` per line. The
+# payouts of the rounds are exported with --export-payouts and served on GET /payouts.
+# payout_addresses = "./payout-addresses.txt"
+
+# User identities are split into an account and a worker at the first `separator` ("." by
+# default), as in `account.worker`: the work of the rounds and the shares persisted are reported
+# per account and worker, difficulty floors and payout addresses match the account too. With
+# `pattern` set, channels whose user identity does not match it as a whole are refused with
+# `unknown-user`.
+# [worker_identity]
+# separator = "."
+# pattern = "[a-z0-9]+(\\.[A-Za-z0-9_-]+)?"
+
+# HTTP endpoint serving the health of the pool components and the shares rejected per error code
+# as JSON on GET /status, and liveness and readiness probes on GET /healthz and GET /readyz (200
+# or 503).
+# [monitoring]
+# listen_address = "127.0.0.1:9090"
+
+# gRPC control service (sv2.control.v1.Control, see stratum-apps/proto/control.proto), in binaries
+# built with the `control` feature: status, connections, disconnection, draining, reload of the
+# authority keypair, management of the ban list and vardiff overrides. Requests must carry
+# `authorization: Bearer <auth_token>` if set. Plaintext, keep it on a trusted address.
+# [control]
+# listen_address = "127.0.0.1:50051"
+# auth_token = "change-me"
+
+# Log format: "pretty" (default) or "json", one object per line carrying the fields of the
+# enclosing spans (connection id, channel id, user identity). Filter directives select the level
+# per module, RUST_LOG takes precedence over them.
+# [logging]
+# format = "json"
+# filter = "info,pool_sv2=debug"
+# Binaries built with the `otel` feature also export spans to an OTLP/gRPC collector.
+# otlp_endpoint = "http://localhost:4317"
+
+# Connections from banned IP addresses and CIDR ranges are refused. Bans are managed on /bans of
+# the monitoring endpoint and kept in memory, or loaded from and saved to `file` if set.
+# [banlist]
+# file = "./banlist.txt"
+
+# Every submitted share and custom job, accepted or not, recorded as one JSON object per line with
+# the error code of the rejected ones, every round of shares closed by a block found and every
+# channel closed.
+# [persistence]
+# backend = "file"
+# path = "./pool-shares.jsonl"
+
+# Pays the balances owed by the persisted rounds with --pay, in binaries built with the `payments`
+# feature. The payouts of at least `min_payout_sat` are batched in one transaction funded by the
+# `[[payments.utxos]]` if listed, by the spendable outputs of the wallet otherwise, signed by the
+# wallet of `rpc_url` and returned to `change_address`. Requires `network`.
+# [payments]
+# rpc_url = "http://127.0.0.1:{rpc_port}/wallet/payouts"
+# rpc_user = "username"
+# rpc_password = "password"
+# change_address = ""
+# fee_rate_sat_vb = 2.0
+# min_payout_sat = 10000
+# [[payments.utxos]]
+# txid = ""
+# vout = 0
+# amount_sat = 100000
+
+# Bounds of the difficulty new channels are opened at, from the hashrate they announce, so that
+# a bogus announced hashrate (e.g. zero) does not start a channel far from its actual one.
+# [initial_difficulty]
+# min_difficulty = 1.0
+# max_difficulty = 1000000.0
+
+# Minimum share difficulty of the channels whose user identity matches `user_identity` (`*` matches
+# any characters), so that known large farms are not started at a low difficulty. The vardiff never
+# lowers their difficulty below it. The first matching floor applies.
+# [[difficulty_floors]]
+# user_identity = "bigfarm.*"
+# min_difficulty = 65536.0
+
+# Per-channel limit on submitted shares, at `rate_multiplier` times `shares_per_minute`. Shares
+# over it are rejected with `rate-limited`, and after `max_violations` consecutive rejections the
+# channel is closed and, if `ban_secs` is set, the IP address of the downstream banned. Disabled
+# unless the table is present, missing fields take the defaults below.
+# [share_rate_limit]
+# rate_multiplier = 20.0
+# burst = 100
+# max_violations = 100
+# ban_secs = 3600
+
+# Channels saved on a graceful shutdown (Ctrl+C) and resumed when the same user identity reopens a
+# channel of the same kind from the same IP address within `max_age_secs` of it: the channel keeps
+# its id, extranonce prefix and target, so that the work in flight during an upgrade is not
+# rejected.
+# [session_resumption]
+# path = "./pool-sessions.json"
+# max_age_secs = 300
+
+# State file the extranonce prefixes of the channels are leased from by blocks of 65536, so that
+# the next run of the pool, even after a crash, does not hand out the prefixes of this one.
+# [extranonce_allocator]
+# path = "./pool-extranonce.json"
+
+# Snapshot of the work of the current round, written every `snapshot_interval_secs`, whenever a
+# round closes and on shutdown, and restored at startup, so that a restart does not wipe the work
+# the users accrued in the round.
+# [accounting]
+# path = "./pool-accounting.json"
+# snapshot_interval_secs = 60
+
+# Lifetime statistics of the pool (when it first ran, the shares accepted and their work, the
+# blocks found, and when every user identity got its first share accepted), saved every
+# `save_interval_secs` and on shutdown, and restored at startup.
+# [lifetime]
+# path = "./pool-lifetime.json"
+# save_interval_secs = 60
+
+# Coordination of the pool instances scaled horizontally behind a load balancer, each with its own
+# `server_id`, through a shared Redis server: the accepted shares are remembered for
+# `duplicate_window_secs` to detect those submitted again to another instance, the shares and work
+# of every user and account are aggregated under `<key_prefix>:user:<user_identity>` and
+# `<key_prefix>:account:<account>`, and the blocks found are announced on `<key_prefix>:blocks`.
+# Rounds and payouts remain per instance. Only the `redis` backend is supported.
+# [coordination]
+# backend = "redis"
+# address = "127.0.0.1:6379"
+# password = "secret"
+# key_prefix = "pool"
+# duplicate_window_secs = 600
+# reconnect_interval_secs = 5
+
+# Shares meeting at least `threshold` times the network difficulty (0.01 for 1% by default) are
+# notable, a warning that a block find is statistically near: they are logged at INFO level,
+# flagged `"notable": true` in the persisted share events, and POSTed as JSON to `webhook_url` if
+# set (plain http:// only).
+# [notable_shares]
+# threshold = 0.01
+# webhook_url = "http://127.0.0.1:8080/notable-share"
+
+# TCP options of the downstream listener and of the Template Provider connection, by default
+# those of the operating system. `reuse_port` lets several pool processes accept on the same
+# `listen_address`, `nodelay` disables Nagle's algorithm on the small SV2 messages, `keepalive_secs`
+# enables keepalive probes on connections idle that long (every `keepalive_interval_secs`, at most
+# `keepalive_retries` of them), and `bind_device` binds the sockets to a network interface (Linux
+# only).
+# [tcp]
+# reuse_port = true
+# nodelay = true
+# keepalive_secs = 60
+# keepalive_interval_secs = 10
+# keepalive_retries = 6
+# bind_device = "eth1"
+
+# Mining protocol versions and SetupConnection flags accepted from downstreams. Connections asking
+# for versions outside `min_version..=max_version` are refused with `protocol-version-mismatch`,
+# and those setting a flag which is not accepted (e.g. `work_selection = false` to refuse custom
+# jobs) with `unsupported-feature-flags`. By default version 2 and every flag are accepted, but
+# `work_selection` is never accepted by a pool built without the `jd` feature.
+# [setup_connection]
+# min_version = 2
+# max_version = 2
+# standard_jobs = true
+# work_selection = true
+# version_rolling = true
+
+# Rules on the devices described by the SetupConnection of downstreams (`vendor`,
+# `hardware_version`, `firmware`, `device_id`), e.g. to refuse a firmware known to be broken. Each
+# field set is a pattern, where `*` matches any characters, and the first rule a device matches
+# applies: `action = "warn"` accepts the connection with a warning, `action = "reject"` refuses it
+# with `unsupported-device`.
+# [[device_rules]]
+# vendor = "Bitmain"
+# firmware = "1.0.*"
+# action = "reject"
+
+# Capacities of the channels between each downstream connection and the pool: received frames
+# waiting to be handled (`inbound_capacity`, reading stops while full) and frames waiting to be
+# written (`outbound_capacity`). Both are unbounded by default. `overflow` sets what happens to a
+# frame sent to a downstream whose outbound channel is full: "disconnect" (default) closes the
+# connection, "drop" drops share acknowledgements and disconnects on any other frame, and
+# "backpressure" makes the pool wait for the downstream, slowing down every other one meanwhile.
+# [downstream_channels]
+# inbound_capacity = 256
+# outbound_capacity = 1024
+# overflow = "disconnect"
+"#
+    )
 }
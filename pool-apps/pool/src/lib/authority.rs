@@ -0,0 +1,281 @@
+//! ## Authority Module
+//!
+//! Holds the authority keypair the Pool uses to sign the Noise certificate of every new
+//! downstream session, and allows rotating it at runtime.
+//!
+//! The certificate is only checked during the Noise handshake, so rotating the keypair only
+//! affects connections accepted afterwards: sessions negotiated under the previous certificate
+//! keep working. The previous key is tracked as retired until the last certificate it signed
+//! expires, which is `cert_validity_sec` after the rotation.
+//!
+//! Every session gets a certificate signed at its handshake, so new handshakes never see an
+//! expired one, but long-lived sessions outlast theirs. The certificate of each session is
+//! tracked: a warning is logged once it enters the last tenth of its validity, and on expiry the
+//! session is either reported as running on a lapsed certificate or, with
+//! `renew_expired_certificates`, disconnected so that the downstream reconnects under a freshly
+//! signed one.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use stratum_apps::{
+    custom_mutex::Mutex,
+    key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+    status::HealthAggregator,
+    stratum_core::noise_sv2::{self, Responder},
+};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::utils::ShutdownMessage;
+
+// Component reported to the health aggregator
+const AUTHORITY_CERTIFICATES: &str = "authority_certificates";
+
+// Certificates are reported as expiring during the last 1/EXPIRY_WARNING_DIVISOR of their
+// validity.
+const EXPIRY_WARNING_DIVISOR: u32 = 10;
+
+/// An authority key that is no longer used to sign new certificates.
+#[derive(Debug, Clone, Copy)]
+pub struct RetiredAuthorityKey {
+    pub public_key: Secp256k1PublicKey,
+    /// Expiry of the last certificate signed with this key.
+    pub certificates_expire_at: SystemTime,
+}
+
+/// Sessions whose certificate changed state since the previous
+/// [`AuthorityKeys::check_certificates`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CertificateExpiry {
+    /// Sessions whose certificate entered the last tenth of its validity, with the time left.
+    pub expiring: Vec<(usize, Duration)>,
+    /// Sessions whose certificate expired.
+    pub expired: Vec<usize>,
+    /// Number of sessions running on an expired certificate, including the ones in `expired`.
+    pub lapsed: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SessionCertificate {
+    expires_at: SystemTime,
+    expiring_reported: bool,
+    expired_reported: bool,
+}
+
+#[derive(Debug)]
+struct AuthorityKeysInner {
+    public_key: Secp256k1PublicKey,
+    secret_key: Secp256k1SecretKey,
+    retired: Vec<RetiredAuthorityKey>,
+    certificates: HashMap<usize, SessionCertificate>,
+}
+
+/// Shared handle on the Pool authority keypair.
+///
+/// Cloning the handle does not clone the keys: a rotation through any clone is seen by the
+/// downstream server.
+#[derive(Debug, Clone)]
+pub struct AuthorityKeys {
+    inner: Arc<Mutex<AuthorityKeysInner>>,
+    cert_validity: Duration,
+}
+
+impl AuthorityKeys {
+    pub fn new(
+        public_key: Secp256k1PublicKey,
+        secret_key: Secp256k1SecretKey,
+        cert_validity: Duration,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(AuthorityKeysInner {
+                public_key,
+                secret_key,
+                retired: Vec::new(),
+                certificates: HashMap::new(),
+            })),
+            cert_validity,
+        }
+    }
+
+    /// Returns the public key signing new certificates.
+    pub fn public_key(&self) -> Secp256k1PublicKey {
+        self.inner.super_safe_lock(|inner| inner.public_key)
+    }
+
+    /// Returns the previous keys whose certificates may still be in use.
+    pub fn retired_keys(&self) -> Vec<RetiredAuthorityKey> {
+        let now = SystemTime::now();
+        self.inner.super_safe_lock(|inner| {
+            inner.retired.retain(|key| key.certificates_expire_at > now);
+            inner.retired.clone()
+        })
+    }
+
+    /// Creates the Noise responder for a new downstream session, signing its certificate with the
+    /// current key.
+    pub fn responder(&self) -> Result<Box<Responder>, noise_sv2::Error> {
+        let (public_key, secret_key) = self
+            .inner
+            .super_safe_lock(|inner| (inner.public_key, inner.secret_key));
+        Responder::from_authority_kp(
+            &public_key.into_bytes(),
+            &secret_key.into_bytes(),
+            self.cert_validity,
+        )
+    }
+
+    /// Replaces the keypair used for new sessions.
+    ///
+    /// The keypair is checked before being installed: on error the current one is kept.
+    pub fn rotate(
+        &self,
+        public_key: Secp256k1PublicKey,
+        secret_key: Secp256k1SecretKey,
+    ) -> Result<(), noise_sv2::Error> {
+        Responder::from_authority_kp(
+            &public_key.into_bytes(),
+            &secret_key.into_bytes(),
+            self.cert_validity,
+        )?;
+
+        let certificates_expire_at = SystemTime::now() + self.cert_validity;
+        let previous = self.inner.super_safe_lock(|inner| {
+            if inner.public_key.into_bytes() == public_key.into_bytes() {
+                return None;
+            }
+            let previous = inner.public_key;
+            inner.retired.push(RetiredAuthorityKey {
+                public_key: previous,
+                certificates_expire_at,
+            });
+            inner.public_key = public_key;
+            inner.secret_key = secret_key;
+            Some(previous)
+        });
+
+        match previous {
+            Some(previous) => info!(
+                "Authority key rotated from {previous} to {public_key}, sessions negotiated under the previous key remain valid for up to {}s",
+                self.cert_validity.as_secs()
+            ),
+            None => info!("Authority key unchanged, nothing to rotate"),
+        }
+        Ok(())
+    }
+
+    /// Records the certificate signed for the session of `downstream_id`, at its handshake.
+    pub fn certificate_issued(&self, downstream_id: usize) {
+        let certificate = SessionCertificate {
+            expires_at: SystemTime::now() + self.cert_validity,
+            expiring_reported: false,
+            expired_reported: false,
+        };
+        self.inner.super_safe_lock(|inner| {
+            inner.certificates.insert(downstream_id, certificate);
+        });
+    }
+
+    /// Stops tracking the certificate of a closed session.
+    pub fn certificate_dropped(&self, downstream_id: usize) {
+        self.inner.super_safe_lock(|inner| {
+            inner.certificates.remove(&downstream_id);
+        });
+    }
+
+    /// Returns the sessions whose certificate started expiring or expired since the previous
+    /// check, as of `now`.
+    pub fn check_certificates(&self, now: SystemTime) -> CertificateExpiry {
+        let warning_window = self.cert_validity / EXPIRY_WARNING_DIVISOR;
+        let mut expiry = CertificateExpiry::default();
+        self.inner.super_safe_lock(|inner| {
+            for (downstream_id, certificate) in inner.certificates.iter_mut() {
+                match certificate.expires_at.duration_since(now) {
+                    Ok(left) if left > warning_window => {}
+                    Ok(left) => {
+                        if !certificate.expiring_reported {
+                            certificate.expiring_reported = true;
+                            expiry.expiring.push((*downstream_id, left));
+                        }
+                    }
+                    Err(_) => {
+                        expiry.lapsed += 1;
+                        if !certificate.expired_reported {
+                            certificate.expired_reported = true;
+                            expiry.expired.push(*downstream_id);
+                        }
+                    }
+                }
+            }
+        });
+        expiry
+    }
+
+    /// Watches the certificates of the sessions until the Pool shuts down.
+    ///
+    /// Warns about the certificates about to expire and, once they expired, either disconnects
+    /// their sessions if `renew` is set, so that the downstreams reconnect under a new
+    /// certificate, or reports the sessions on lapsed certificates as degrading `health`.
+    pub async fn monitor_certificates(
+        self,
+        renew: bool,
+        health: Arc<HealthAggregator>,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    ) {
+        let mut shutdown_rx = notify_shutdown.subscribe();
+        let period = (self.cert_validity / EXPIRY_WARNING_DIVISOR)
+            .clamp(Duration::from_secs(1), Duration::from_secs(60));
+        let mut interval = tokio::time::interval(period);
+        health.healthy(AUTHORITY_CERTIFICATES);
+        loop {
+            tokio::select! {
+                message = shutdown_rx.recv() => match message {
+                    Ok(ShutdownMessage::DownstreamShutdown(downstream_id)) => {
+                        self.certificate_dropped(downstream_id);
+                    }
+                    Ok(ShutdownMessage::DownstreamShutdownAll) => {
+                        self.inner.super_safe_lock(|inner| inner.certificates.clear());
+                    }
+                    Ok(ShutdownMessage::ShutdownAll) | Err(_) => break,
+                },
+                _ = interval.tick() => {
+                    let expiry = self.check_certificates(SystemTime::now());
+                    for (downstream_id, left) in expiry.expiring {
+                        warn!(
+                            downstream_id,
+                            "Noise certificate of the session expires in {}s",
+                            left.as_secs()
+                        );
+                    }
+                    for downstream_id in expiry.expired {
+                        if renew {
+                            info!(
+                                downstream_id,
+                                "Noise certificate of the session expired, disconnecting it to re-sign on reconnection"
+                            );
+                            self.certificate_dropped(downstream_id);
+                            let _ = notify_shutdown
+                                .send(ShutdownMessage::DownstreamShutdown(downstream_id));
+                        } else {
+                            warn!(
+                                downstream_id,
+                                "Noise certificate of the session expired, it keeps working until the downstream reconnects"
+                            );
+                        }
+                    }
+                    if renew || expiry.lapsed == 0 {
+                        health.healthy(AUTHORITY_CERTIFICATES);
+                    } else {
+                        health.degraded(
+                            AUTHORITY_CERTIFICATES,
+                            format!("{} sessions run on an expired certificate", expiry.lapsed),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Activation of the jobs of a new block.
+//!
+//! On a `SetNewPrevHash` of the Template Provider, the channel manager serializes the messages
+//! switching the downstreams to the new block (the `SetNewPrevHash` of every channel, preceded by
+//! the future jobs held back) into a [`JobActivation`], and hands it to a task doing nothing but
+//! fanning activations out: the frames are queued straight to the [`OutboundQueues`] of the
+//! connections, on their priority lane. The lane never waits for a slow downstream nor drops a
+//! frame, so that queuing an activation takes the same short time whatever the state of the
+//! downstreams. The channel manager waits for the activation to be queued before handling its
+//! next message, so that no later message to a downstream, such as the jobs of the next
+//! template, overtakes it.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_channel::{unbounded, Receiver, Sender};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    oneshot,
+};
+use tracing::{info, warn};
+
+use crate::{
+    channel_manager::job_distribution::SharedFrame, downstream::outbound::OutboundQueues,
+    monitoring::LatencyStats, utils::ShutdownMessage,
+};
+
+/// Switchover time past which an activation is reported as slow.
+const SWITCHOVER_TARGET: Duration = Duration::from_millis(100);
+
+/// Messages switching the downstreams to the jobs of a new block.
+#[derive(Debug)]
+pub struct JobActivation {
+    /// Template whose jobs are activated.
+    pub template_id: u64,
+    /// Reception of the `SetNewPrevHash` from the Template Provider.
+    pub received: Instant,
+    /// Frames to queue, in order, with the downstream each goes to.
+    pub frames: Vec<(usize, SharedFrame)>,
+}
+
+/// Hands the activations to the fan-out task.
+#[derive(Clone)]
+pub struct ActivationFanout {
+    sender: Sender<(JobActivation, oneshot::Sender<()>)>,
+    receiver: Receiver<(JobActivation, oneshot::Sender<()>)>,
+}
+
+impl Default for ActivationFanout {
+    fn default() -> Self {
+        let (sender, receiver) = unbounded();
+        Self { sender, receiver }
+    }
+}
+
+impl ActivationFanout {
+    /// Fans `activation` out, returns once its frames are queued to every downstream.
+    pub async fn activate(&self, activation: JobActivation) {
+        let (done, fanned_out) = oneshot::channel();
+        if self.sender.send((activation, done)).await.is_err() {
+            warn!("Job activation fan-out stopped, the activation is dropped");
+            return;
+        }
+        _ = fanned_out.await;
+    }
+
+    /// Queues the frames of every activation to `queues` until the Pool shuts down, and records
+    /// the time taken from the reception of each `SetNewPrevHash` to its last frame being queued
+    /// in `job_activations`.
+    pub async fn run(
+        self,
+        queues: OutboundQueues,
+        job_activations: Arc<LatencyStats>,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    ) {
+        let mut shutdown_rx = notify_shutdown.subscribe();
+        loop {
+            tokio::select! {
+                message = shutdown_rx.recv() => match message {
+                    Ok(ShutdownMessage::ShutdownAll) | Err(RecvError::Closed) => break,
+                    _ => {}
+                },
+                activation = self.receiver.recv() => {
+                    let Ok((activation, done)) = activation else {
+                        break;
+                    };
+                    let fanout_started = Instant::now();
+                    let downstreams = queues.send_all_priority(&activation.frames);
+                    let fanout = fanout_started.elapsed();
+                    let latency = activation.received.elapsed();
+                    job_activations.record(latency);
+                    _ = done.send(());
+                    if latency > SWITCHOVER_TARGET {
+                        warn!(
+                            "Activated the jobs of template {} on {downstreams} downstreams in {latency:?}, {fanout:?} of which fanning out",
+                            activation.template_id
+                        );
+                    } else {
+                        info!(
+                            "Activated the jobs of template {} on {downstreams} downstreams in {latency:?}",
+                            activation.template_id
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
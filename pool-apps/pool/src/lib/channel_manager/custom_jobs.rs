@@ -0,0 +1,177 @@
+//! Custom jobs of the Job Declarator Clients, compiled with the `jd` feature.
+//!
+//! A client which negotiated `REQUIRES_WORK_SELECTION` mines on its own templates by sending a
+//! `SetCustomMiningJob`, checked against the payout of the pool, the token of the Job Declarator
+//! Server and the chain tip the pool knows of before being accepted.
+
+use std::net::SocketAddr;
+
+use stratum_apps::{
+    key_utils::verify_mining_job_token,
+    persistence::CustomJobEvent,
+    stratum_core::{
+        bitcoin::{consensus::Decodable, Amount, TxOut},
+        mining_sv2::{SetCustomMiningJob, SetCustomMiningJobError, SetCustomMiningJobSuccess},
+        parsers_sv2::Mining,
+    },
+};
+use tracing::error;
+
+use crate::{
+    channel_manager::{ChannelManager, ChannelManagerData, RouteMessageTo},
+    error::{PoolError, PoolResult},
+};
+
+/// Returns whether a coinbase prefix starts with the block height, as required by BIP34.
+fn is_bip34_height_push(coinbase_prefix: &[u8]) -> bool {
+    match coinbase_prefix.first() {
+        // OP_1 to OP_16
+        Some(0x51..=0x60) => true,
+        Some(&len @ 1..=5) => coinbase_prefix.len() > len as usize,
+        _ => false,
+    }
+}
+
+impl ChannelManager {
+    /// Handles a `SetCustomMiningJob` of `downstream_id`, answering it with a
+    /// `SetCustomMiningJobSuccess` or a `SetCustomMiningJobError`.
+    pub(super) async fn set_custom_mining_job(
+        &self,
+        downstream_id: usize,
+        msg: SetCustomMiningJob<'_>,
+    ) -> PoolResult<()> {
+        let coinbase_outputs = Vec::<TxOut>::consensus_decode(
+            &mut msg.coinbase_tx_outputs.inner_as_ref().to_vec().as_slice(),
+        )
+        .ok();
+        let pool_script = self.coinbase_reward_script.script_pubkey();
+        let pool_payout: u64 = coinbase_outputs
+            .iter()
+            .flatten()
+            .filter(|output| output.script_pubkey == pool_script)
+            .map(|output| output.value.to_sat())
+            .sum();
+        // Every outcome is recorded, so that disputes about the jobs a client mined on can be
+        // settled against what the pool checked.
+        let custom_job_event = |client: SocketAddr, error_code: Option<&str>| CustomJobEvent {
+            client: client.to_string(),
+            channel_id: msg.channel_id,
+            request_id: msg.request_id,
+            token: hex::encode(msg.mining_job_token.inner_as_ref()),
+            prev_hash: hex::encode(msg.prev_hash.inner_as_ref()),
+            coinbase_outputs: hex::encode(msg.coinbase_tx_outputs.inner_as_ref()),
+            pool_payout,
+            accepted: error_code.is_none(),
+            error_code: error_code.map(str::to_string),
+        };
+        let reject = |client: SocketAddr, error_code: &str| -> RouteMessageTo<'static> {
+            error!("SetCustomMiningJobError: {error_code}");
+            self.persistence
+                .record(custom_job_event(client, Some(error_code)));
+            let error = SetCustomMiningJobError {
+                request_id: msg.request_id,
+                channel_id: msg.channel_id,
+                error_code: error_code
+                    .to_string()
+                    .try_into()
+                    .expect("error code must be valid string"),
+            };
+            (downstream_id, Mining::SetCustomMiningJobError(error)).into()
+        };
+
+        let message: RouteMessageTo =
+            self.channel_manager_data
+                .super_safe_lock(|channel_manager_data| {
+                    let custom_job_error = self.custom_job_error(
+                        channel_manager_data,
+                        &msg,
+                        coinbase_outputs.as_deref(),
+                    );
+
+                    let Some(downstream) = channel_manager_data.downstream.get_mut(&downstream_id)
+                    else {
+                        return Err(PoolError::DownstreamNotFound(downstream_id));
+                    };
+                    let address = downstream.address;
+
+                    if let Some(error_code) = custom_job_error {
+                        return Ok(reject(address, error_code));
+                    }
+
+                    downstream
+                        .downstream_data
+                        .super_safe_lock(|downstream_data| {
+                            let Some(extended_channel) =
+                                downstream_data.extended_channels.get_mut(&msg.channel_id)
+                            else {
+                                return Ok(reject(address, "invalid-channel-id"));
+                            };
+
+                            let job_id = extended_channel
+                                .on_set_custom_mining_job(msg.clone().into_static())?;
+                            self.persistence.record(custom_job_event(address, None));
+
+                            let success = SetCustomMiningJobSuccess {
+                                channel_id: msg.channel_id,
+                                request_id: msg.request_id,
+                                job_id,
+                            };
+                            Ok((downstream_id, Mining::SetCustomMiningJobSuccess(success)).into())
+                        })
+                })?;
+
+        message.forward(&self.channel_manager_channel).await;
+        Ok(())
+    }
+
+    /// Returns the error code refusing a custom job, if its coinbase does not pay the pool, its
+    /// mining job token was not signed by the Job Declarator Server or it does not build on the
+    /// chain tip the pool knows of.
+    ///
+    /// `coinbase_outputs` is `None` if the outputs of the job could not be decoded.
+    fn custom_job_error(
+        &self,
+        channel_manager_data: &ChannelManagerData,
+        msg: &SetCustomMiningJob,
+        coinbase_outputs: Option<&[TxOut]>,
+    ) -> Option<&'static str> {
+        let Some(coinbase_outputs) = coinbase_outputs else {
+            return Some("invalid-coinbase-outputs");
+        };
+        if let Some(jds_authority_public_key) = &self.jds_authority_public_key {
+            if !verify_mining_job_token(
+                msg.mining_job_token.inner_as_ref(),
+                jds_authority_public_key,
+            ) {
+                return Some("invalid-mining-job-token");
+            }
+        }
+        let pool_script = self.coinbase_reward_script.script_pubkey();
+        if !coinbase_outputs
+            .iter()
+            .any(|output| output.script_pubkey == pool_script)
+        {
+            return Some("pool-payout-script-missing");
+        }
+        // The pool output receives the whole reward, other outputs only carry commitments.
+        if coinbase_outputs
+            .iter()
+            .any(|output| output.script_pubkey != pool_script && output.value > Amount::ZERO)
+        {
+            return Some("pool-payout-amount-too-low");
+        }
+        if !is_bip34_height_push(msg.coinbase_prefix.inner_as_ref()) {
+            return Some("invalid-coinbase-prefix");
+        }
+        // Jobs on another tip are left to the share validation, the Template Provider of the
+        // client may be ahead of the pool's.
+        if let Some(last_new_prev_hash) = &channel_manager_data.last_new_prev_hash {
+            if last_new_prev_hash.prev_hash.inner_as_ref() == msg.prev_hash.inner_as_ref()
+                && last_new_prev_hash.n_bits != msg.nbits
+            {
+                return Some("invalid-nbits");
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,125 @@
+//! Serialization of the messages the channel manager sends to downstreams.
+//!
+//! Messages are serialized once, by the channel manager, into a [`SharedFrame`] every downstream
+//! they are sent to holds a reference of. The work left to each connection is to copy the frame
+//! into its [`FramePool`] and encrypt it.
+//!
+//! The jobs of group channels, and the `SetNewPrevHash` activating them, are the same for every
+//! downstream whose group channel has the same id and is at the same job: [`SharedFrames`] hands
+//! out a single frame for all of them.
+
+use std::{collections::HashMap, sync::Arc};
+
+use stratum_apps::{
+    network_helpers::frame_pool::FramePool,
+    stratum_core::parsers_sv2::{AnyMessage, CommonMessages, Mining},
+};
+
+use tracing::error;
+
+use crate::{
+    channel_manager::RouteMessageTo,
+    error::PoolResult,
+    utils::{SV2Frame, StdFrame},
+};
+
+/// Size of the header of a frame: extension type, message type and payload length.
+const FRAME_HEADER_SIZE: usize = 6;
+
+/// A message serialized once for all the downstreams it is sent to.
+#[derive(Debug, Clone)]
+pub struct SharedFrame(Arc<[u8]>);
+
+impl SharedFrame {
+    /// Serializes `message`, header included.
+    pub fn new(message: Mining<'_>) -> PoolResult<Self> {
+        Self::serialize(AnyMessage::Mining(message.into_static()))
+    }
+
+    /// Serializes the common `message`, header included.
+    pub fn common(message: CommonMessages<'_>) -> PoolResult<Self> {
+        Self::serialize(AnyMessage::Common(message.into_static()))
+    }
+
+    fn serialize(message: AnyMessage<'static>) -> PoolResult<Self> {
+        let frame: StdFrame = message.try_into()?;
+        let mut bytes = vec![0; frame.encoded_length()];
+        frame.serialize(&mut bytes)?;
+        Ok(Self(bytes.into()))
+    }
+
+    /// Returns the type of the message.
+    pub fn message_type(&self) -> u8 {
+        self.0[2]
+    }
+
+    /// Returns the serialized message, without the header of the frame.
+    pub fn payload(&self) -> &[u8] {
+        &self.0[FRAME_HEADER_SIZE..]
+    }
+
+    /// Copies the frame into `frame_pool`, for a connection to encrypt and write it.
+    pub fn to_frame(&self, frame_pool: &FramePool) -> SV2Frame {
+        frame_pool.copy(&self.0)
+    }
+}
+
+/// Frames of the messages of group channels, shared by the downstreams of a single distribution
+/// of jobs.
+///
+/// The messages are identified by their type, channel and job, which only tells them apart within
+/// the distribution of a single template or prev hash: a cache lives as long as that
+/// distribution.
+#[derive(Debug, Default)]
+pub struct SharedFrames(HashMap<(u8, u32, u32), SharedFrame>);
+
+impl SharedFrames {
+    /// Routes to `downstream_id` the message of type `message_type` for `job_id` of the group
+    /// channel `channel_id`, built by `message` and serialized on first use only.
+    ///
+    /// Returns `None` if the message cannot be serialized.
+    pub fn route<'a>(
+        &mut self,
+        downstream_id: usize,
+        message_type: u8,
+        channel_id: u32,
+        job_id: u32,
+        message: impl FnOnce() -> Mining<'a>,
+    ) -> Option<RouteMessageTo<'static>> {
+        let key = (message_type, channel_id, job_id);
+        let frame = match self.0.get(&key) {
+            Some(frame) => frame.clone(),
+            None => match SharedFrame::new(message()) {
+                Ok(frame) => self.0.entry(key).or_insert(frame).clone(),
+                Err(e) => {
+                    error!(
+                        ?e,
+                        "Failed to serialize message for downstream {downstream_id}"
+                    );
+                    return None;
+                }
+            },
+        };
+        Some(RouteMessageTo::Shared((downstream_id, frame)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_apps::stratum_core::common_messages_sv2::{
+        ChannelEndpointChanged, MESSAGE_TYPE_CHANNEL_ENDPOINT_CHANGED,
+    };
+
+    #[test]
+    fn common_messages_are_routed_serialized() {
+        let message =
+            CommonMessages::ChannelEndpointChanged(ChannelEndpointChanged { channel_id: 7 });
+        let Some((downstream_id, frame)) = RouteMessageTo::from((3, message)).into_shared() else {
+            panic!("common messages must serialize");
+        };
+        assert_eq!(downstream_id, 3);
+        assert_eq!(frame.message_type(), MESSAGE_TYPE_CHANNEL_ENDPOINT_CHANGED);
+        assert_eq!(frame.payload(), 7u32.to_le_bytes());
+    }
+}
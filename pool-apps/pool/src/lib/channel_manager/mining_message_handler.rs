@@ -1,28 +1,36 @@
-use std::sync::atomic::Ordering;
-
-use stratum_apps::stratum_core::{
-    binary_sv2::Str0255,
-    bitcoin::{consensus::Decodable, Amount, Target, TxOut},
-    channels_sv2::{
-        server::{
-            error::{ExtendedChannelError, StandardChannelError},
-            extended::ExtendedChannel,
-            group::GroupChannel,
-            jobs::job_store::DefaultJobStore,
-            share_accounting::{ShareValidationError, ShareValidationResult},
-            standard::StandardChannel,
+use std::{net::SocketAddr, sync::atomic::Ordering, time::Instant};
+
+use stratum_apps::{
+    events::{BlockFound, ShareValidated},
+    share_reject::ShareRejectReason,
+    stratum_core::{
+        binary_sv2::Str0255,
+        bitcoin::{hashes::Hash, Amount, Target, TxOut},
+        channels_sv2::{
+            server::{
+                error::{ExtendedChannelError, StandardChannelError},
+                extended::ExtendedChannel,
+                group::GroupChannel,
+                jobs::job_store::DefaultJobStore,
+                share_accounting::ShareValidationResult,
+                standard::StandardChannel,
+            },
+            Vardiff, VardiffState,
         },
-        Vardiff, VardiffState,
+        handlers_sv2::{HandleMiningMessagesFromClientAsync, SupportedChannelTypes},
+        mining_sv2::*,
+        parsers_sv2::{Mining, TemplateDistribution},
+        template_distribution_sv2::SubmitSolution,
     },
-    handlers_sv2::{HandleMiningMessagesFromClientAsync, SupportedChannelTypes},
-    mining_sv2::*,
-    parsers_sv2::{Mining, TemplateDistribution},
-    template_distribution_sv2::SubmitSolution,
+    worker_identity::WorkerIdentity,
 };
-use tracing::{error, info};
+use tracing::{error, info, instrument, warn};
 
 use crate::{
-    channel_manager::{ChannelManager, RouteMessageTo, FULL_EXTRANONCE_SIZE},
+    channel_manager::{
+        next_channel_id, ChannelManager, RateLimitOutcome, RouteMessageTo, FULL_EXTRANONCE_SIZE,
+    },
+    channel_updates::{ChannelUpdate, ObservedHashrate},
     error::PoolError,
 };
 
@@ -34,7 +42,7 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
     }
 
     fn is_work_selection_enabled_for_client(&self, _client_id: Option<usize>) -> bool {
-        true
+        self.setup_connection.work_selection()
     }
 
     fn is_client_authorized(
@@ -55,20 +63,20 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
             client_id.expect("client_id must be present for downstream_id extraction");
         self.channel_manager_data
             .super_safe_lock(|channel_manager_data| {
-                let Some(downstream) = channel_manager_data.downstream.get_mut(&downstream_id)
-                else {
+                if !channel_manager_data.downstream.contains_key(&downstream_id) {
                     return Err(PoolError::DownstreamNotFound(downstream_id));
-                };
-
-                downstream
-                    .downstream_data
-                    .super_safe_lock(|downstream_data| {
-                        downstream_data.standard_channels.remove(&msg.channel_id);
-                        downstream_data.extended_channels.remove(&msg.channel_id);
-                    });
-                channel_manager_data
-                    .vardiff
-                    .remove(&(downstream_id, msg.channel_id).into());
+                }
+                if !self.teardown_channel(
+                    channel_manager_data,
+                    downstream_id,
+                    msg.channel_id,
+                    &msg.reason_code.as_utf8_or_hex(),
+                ) {
+                    warn!(
+                        "CloseChannel: downstream {downstream_id} has no channel {}",
+                        msg.channel_id
+                    );
+                }
                 Ok(())
             })
     }
@@ -86,6 +94,18 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
         info!("Received OpenStandardMiningChannel: {}", msg);
 
         let messages = self.channel_manager_data.super_safe_lock(|channel_manager_data| {
+            if let Some(error_code) = self.user_identity_error(&user_identity).or_else(|| self.channel_limit_error(channel_manager_data, downstream_id)) {
+                error!("OpenMiningChannelError: {error_code}");
+                let open_standard_mining_channel_error = OpenMiningChannelError {
+                    request_id,
+                    error_code: error_code
+                        .to_string()
+                        .try_into()
+                        .expect("error code must be valid string"),
+                };
+                return Ok(vec![(downstream_id, Mining::OpenMiningChannelError(open_standard_mining_channel_error)).into()]);
+            }
+
             let Some(downstream) = channel_manager_data.downstream.get_mut(&downstream_id) else {
                 return Err(PoolError::DownstreamIdNotFound);
             };
@@ -121,7 +141,7 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                     let group_channel_id = downstream_data.channel_id_factory.fetch_add(1, Ordering::SeqCst);
                     let job_store = DefaultJobStore::new();
 
-                    let mut group_channel = match GroupChannel::new_for_pool(group_channel_id as u32, job_store, FULL_EXTRANONCE_SIZE, self.pool_tag_string.clone()) {
+                    let mut group_channel = match GroupChannel::new_for_pool(group_channel_id as u32, job_store, FULL_EXTRANONCE_SIZE, self.pool_tag(Some(&last_future_template))) {
                         Ok(channel) => channel,
                         Err(e) => {
                             error!(?e, "Failed to create group channel");
@@ -133,43 +153,72 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                     group_channel.on_set_new_prev_hash(last_set_new_prev_hash_tdp.clone())?;
                     downstream_data.group_channels = Some(group_channel);
                 }
-                let nominal_hash_rate = msg.nominal_hash_rate;
+                let resumed = self.sessions.resume(downstream.address.ip(), &user_identity, false);
+                if let Some(channel) = &resumed {
+                    info!("Resuming standard channel {} of {user_identity}", channel.channel_id);
+                }
+                let hashrate_floor = self.hashrate_floor(&user_identity);
+                let requested_hash_rate = resumed.as_ref().map_or_else(|| self.initial_hashrate(msg.nominal_hash_rate), |channel| channel.nominal_hash_rate);
+                let nominal_hash_rate = hashrate_floor.map_or(requested_hash_rate, |floor| requested_hash_rate.max(floor));
                 let requested_max_target = Target::from_le_bytes(msg.max_target.inner_as_ref().try_into().unwrap());
-                let extranonce_prefix = channel_manager_data.extranonce_prefix_factory_standard.next_prefix_standard()?;
+                let extranonce_prefix = match &resumed {
+                    Some(channel) => Ok(channel.extranonce_prefix.clone()),
+                    None => self.fresh_extranonce_prefix(|| channel_manager_data.extranonce_prefixes.next_standard()),
+                };
+                let extranonce_prefix = match extranonce_prefix {
+                    Ok(extranonce_prefix) => extranonce_prefix,
+                    Err(e) => {
+                        error!("OpenMiningChannelError: {}", e.error_code());
+                        let open_standard_mining_channel_error = OpenMiningChannelError {
+                            request_id,
+                            error_code: e
+                                .error_code()
+                                .to_string()
+                                .try_into()
+                                .expect("error code must be valid string"),
+                        };
+                        return Ok(vec![(downstream_id, Mining::OpenMiningChannelError(open_standard_mining_channel_error)).into()]);
+                    }
+                };
+                // Released on every failure to open the channel, before it is registered.
+                let prefix = extranonce_prefix.clone();
 
-                let channel_id = downstream_data.channel_id_factory.fetch_add(1, Ordering::SeqCst);
+                let channel_id = next_channel_id(&downstream_data.channel_id_factory, resumed.as_ref().map(|channel| channel.channel_id));
                 let job_store = DefaultJobStore::new();
 
-                let mut standard_channel = match StandardChannel::new_for_pool(channel_id as u32, user_identity.to_string(), extranonce_prefix.to_vec(), requested_max_target, nominal_hash_rate, self.share_batch_size, self.shares_per_minute, job_store, self.pool_tag_string.clone()) {
+                let mut standard_channel = match StandardChannel::new_for_pool(channel_id as u32, user_identity.to_string(), extranonce_prefix, requested_max_target, nominal_hash_rate, self.share_batch_size, self.shares_per_minute, job_store, self.pool_tag(Some(&last_future_template))) {
                     Ok(channel) => channel,
-                    Err(e) => match e {
-                        StandardChannelError::InvalidNominalHashrate => {
-                            error!("OpenMiningChannelError: invalid-nominal-hashrate");
-                            let open_standard_mining_channel_error = OpenMiningChannelError {
-                                request_id,
-                                error_code: "invalid-nominal-hashrate"
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            };
-                            return Ok(vec![(downstream_id, Mining::OpenMiningChannelError(open_standard_mining_channel_error)).into()]);
-                        }
-                        StandardChannelError::RequestedMaxTargetOutOfRange => {
-                            error!("OpenMiningChannelError: max-target-out-of-range");
-                            let open_standard_mining_channel_error = OpenMiningChannelError {
-                                request_id,
-                                error_code: "max-target-out-of-range"
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            };
-                            return Ok(vec![(downstream_id, Mining::OpenMiningChannelError(open_standard_mining_channel_error)).into()]);
-                        }
-                        _ => {
-                            error!("error in handle_open_standard_mining_channel: {:?}", e);
-                            return Err(PoolError::ChannelErrorSender);
+                    Err(e) => {
+                        channel_manager_data.extranonce_prefixes.release(&prefix);
+                        match e {
+                            StandardChannelError::InvalidNominalHashrate => {
+                                error!("OpenMiningChannelError: invalid-nominal-hashrate");
+                                let open_standard_mining_channel_error = OpenMiningChannelError {
+                                    request_id,
+                                    error_code: "invalid-nominal-hashrate"
+                                        .to_string()
+                                        .try_into()
+                                        .expect("error code must be valid string"),
+                                };
+                                return Ok(vec![(downstream_id, Mining::OpenMiningChannelError(open_standard_mining_channel_error)).into()]);
+                            }
+                            StandardChannelError::RequestedMaxTargetOutOfRange => {
+                                error!("OpenMiningChannelError: max-target-out-of-range");
+                                let open_standard_mining_channel_error = OpenMiningChannelError {
+                                    request_id,
+                                    error_code: "max-target-out-of-range"
+                                        .to_string()
+                                        .try_into()
+                                        .expect("error code must be valid string"),
+                                };
+                                return Ok(vec![(downstream_id, Mining::OpenMiningChannelError(open_standard_mining_channel_error)).into()]);
+                            }
+                            _ => {
+                                error!("error in handle_open_standard_mining_channel: {:?}", e);
+                                return Err(PoolError::ChannelErrorSender);
+                            }
                         }
-                    },
+                    }
                 };
 
                 let group_channel_id = downstream_data.group_channels.as_ref().map(|channel| channel.get_group_channel_id()).unwrap_or(0);
@@ -189,7 +238,9 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                 let template_id = last_future_template.template_id;
 
                 // create a future standard job based on the last future template
-                standard_channel.on_new_template(last_future_template, vec![pool_coinbase_output.clone()])?;
+                standard_channel
+                    .on_new_template(last_future_template, vec![pool_coinbase_output.clone()])
+                    .inspect_err(|_| channel_manager_data.extranonce_prefixes.release(&prefix))?;
                 let future_standard_job_id = standard_channel
                     .get_future_template_to_job_id()
                     .get(&template_id)
@@ -215,7 +266,8 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
 
 
                 standard_channel
-                .on_set_new_prev_hash(last_set_new_prev_hash_tdp.clone())?;
+                    .on_set_new_prev_hash(last_set_new_prev_hash_tdp.clone())
+                    .inspect_err(|_| channel_manager_data.extranonce_prefixes.release(&prefix))?;
 
                 messages.push((downstream_id, Mining::SetNewPrevHash(set_new_prev_hash_mining)).into());
 
@@ -225,6 +277,13 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                 }
                 let vardiff = VardiffState::new()?;
                 channel_manager_data.vardiff.insert((downstream_id, channel_id as u32).into(), vardiff);
+                if let Some(hashrate_floor) = hashrate_floor {
+                    channel_manager_data.hashrate_floors.insert((downstream_id, channel_id as u32).into(), hashrate_floor);
+                }
+                if let Some(share_rate_limiter) = self.share_rate_limiter() {
+                    channel_manager_data.share_rate_limiters.insert((downstream_id, channel_id as u32).into(), share_rate_limiter);
+                }
+                channel_manager_data.observed_hashrates.insert((downstream_id, channel_id as u32).into(), ObservedHashrate::new(Instant::now()));
 
                 Ok(messages)
             })
@@ -248,7 +307,7 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
             client_id.expect("client_id must be present for downstream_id extraction");
         info!("Received OpenExtendedMiningChannel: {}", msg);
 
-        let nominal_hash_rate = msg.nominal_hash_rate;
+        let hashrate_floor = self.hashrate_floor(&user_identity);
         let requested_max_target =
             Target::from_le_bytes(msg.max_target.inner_as_ref().try_into().unwrap());
         let requested_min_rollable_extranonce_size = msg.min_extranonce_size;
@@ -256,25 +315,80 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
         let messages = self
             .channel_manager_data
             .super_safe_lock(|channel_manager_data| {
+                if let Some(error_code) = self
+                    .user_identity_error(&user_identity)
+                    .or_else(|| self.channel_limit_error(channel_manager_data, downstream_id))
+                {
+                    error!("OpenMiningChannelError: {error_code}");
+                    let open_extended_mining_channel_error = OpenMiningChannelError {
+                        request_id,
+                        error_code: error_code
+                            .to_string()
+                            .try_into()
+                            .expect("error code must be valid string"),
+                    };
+                    return Ok(vec![(
+                        downstream_id,
+                        Mining::OpenMiningChannelError(open_extended_mining_channel_error),
+                    )
+                        .into()]);
+                }
+
                 let Some(downstream) = channel_manager_data.downstream.get_mut(&downstream_id)
                 else {
                     return Err(PoolError::DownstreamIdNotFound);
                 };
+                let resumed = self
+                    .sessions
+                    .resume(downstream.address.ip(), &user_identity, true);
+                if let Some(channel) = &resumed {
+                    info!(
+                        "Resuming extended channel {} of {user_identity}",
+                        channel.channel_id
+                    );
+                }
+                let requested_hash_rate = resumed
+                    .as_ref()
+                    .map_or_else(
+                        || self.initial_hashrate(msg.nominal_hash_rate),
+                        |channel| channel.nominal_hash_rate,
+                    );
+                let nominal_hash_rate = hashrate_floor.map_or(requested_hash_rate, |floor| {
+                    requested_hash_rate.max(floor)
+                });
                 downstream
                     .downstream_data
                     .super_safe_lock(|downstream_data| {
                         let mut messages: Vec<RouteMessageTo> = Vec::new();
 
-                        let extranonce_prefix = match channel_manager_data
-                            .extranonce_prefix_factory_extended
-                            .next_prefix_extended(requested_min_rollable_extranonce_size.into())
-                        {
-                            Ok(extranonce_prefix) => extranonce_prefix.to_vec(),
-                            Err(_) => {
-                                error!("OpenMiningChannelError: min-extranonce-size-too-large");
+                        let Some(last_set_new_prev_hash_tdp) =
+                            channel_manager_data.last_new_prev_hash.clone()
+                        else {
+                            return Err(PoolError::LastNewPrevhashNotFound);
+                        };
+
+                        let Some(last_future_template) =
+                            channel_manager_data.last_future_template.clone()
+                        else {
+                            return Err(PoolError::FutureTemplateNotPresent);
+                        };
+
+                        let extranonce_prefix = match &resumed {
+                            Some(channel) => Ok(channel.extranonce_prefix.clone()),
+                            None => self.fresh_extranonce_prefix(|| {
+                                channel_manager_data
+                                    .extranonce_prefixes
+                                    .next_extended(requested_min_rollable_extranonce_size.into())
+                            }),
+                        };
+                        let extranonce_prefix = match extranonce_prefix {
+                            Ok(extranonce_prefix) => extranonce_prefix,
+                            Err(e) => {
+                                let error_code = e.error_code();
+                                error!("OpenMiningChannelError: {error_code}");
                                 let open_extended_mining_channel_error = OpenMiningChannelError {
                                     request_id,
-                                    error_code: "min-extranonce-size-too-large"
+                                    error_code: error_code
                                         .to_string()
                                         .try_into()
                                         .expect("error code must be valid string"),
@@ -288,10 +402,13 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                                     .into()]);
                             }
                         };
+                        // Released on every failure to open the channel, before it is registered.
+                        let prefix = extranonce_prefix.clone();
 
-                        let channel_id = downstream_data
-                            .channel_id_factory
-                            .fetch_add(1, Ordering::SeqCst);
+                        let channel_id = next_channel_id(
+                            &downstream_data.channel_id_factory,
+                            resumed.as_ref().map(|channel| channel.channel_id),
+                        );
                         let job_store = DefaultJobStore::new();
 
                         let mut extended_channel = match ExtendedChannel::new_for_pool(
@@ -305,69 +422,72 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                             self.share_batch_size,
                             self.shares_per_minute,
                             job_store,
-                            self.pool_tag_string.clone(),
+                            self.pool_tag(channel_manager_data.last_future_template.as_ref()),
                         ) {
                             Ok(channel) => channel,
-                            Err(e) => match e {
-                                ExtendedChannelError::InvalidNominalHashrate => {
-                                    error!("OpenMiningChannelError: invalid-nominal-hashrate");
-                                    let open_extended_mining_channel_error =
-                                        OpenMiningChannelError {
-                                            request_id,
-                                            error_code: "invalid-nominal-hashrate"
-                                                .to_string()
-                                                .try_into()
-                                                .expect("error code must be valid string"),
-                                        };
-                                    return Ok(vec![(
-                                        downstream_id,
-                                        Mining::OpenMiningChannelError(
-                                            open_extended_mining_channel_error,
-                                        ),
-                                    )
-                                        .into()]);
-                                }
-                                ExtendedChannelError::RequestedMaxTargetOutOfRange => {
-                                    error!("OpenMiningChannelError: max-target-out-of-range");
-                                    let open_extended_mining_channel_error =
-                                        OpenMiningChannelError {
-                                            request_id,
-                                            error_code: "max-target-out-of-range"
-                                                .to_string()
-                                                .try_into()
-                                                .expect("error code must be valid string"),
-                                        };
-                                    return Ok(vec![(
-                                        downstream_id,
-                                        Mining::OpenMiningChannelError(
-                                            open_extended_mining_channel_error,
-                                        ),
-                                    )
-                                        .into()]);
-                                }
-                                ExtendedChannelError::RequestedMinExtranonceSizeTooLarge => {
-                                    error!("OpenMiningChannelError: min-extranonce-size-too-large");
-                                    let open_extended_mining_channel_error =
-                                        OpenMiningChannelError {
-                                            request_id,
-                                            error_code: "min-extranonce-size-too-large"
-                                                .to_string()
-                                                .try_into()
-                                                .expect("error code must be valid string"),
-                                        };
-                                    return Ok(vec![(
-                                        downstream_id,
-                                        Mining::OpenMiningChannelError(
-                                            open_extended_mining_channel_error,
-                                        ),
-                                    )
-                                        .into()]);
-                                }
-                                e => {
-                                    error!("error in handle_open_extended_mining_channel: {:?}", e);
-                                    return Err(e)?;
+                            Err(e) => {
+                                channel_manager_data.extranonce_prefixes.release(&prefix);
+                                match e {
+                                    ExtendedChannelError::InvalidNominalHashrate => {
+                                        error!("OpenMiningChannelError: invalid-nominal-hashrate");
+                                        let open_extended_mining_channel_error =
+                                            OpenMiningChannelError {
+                                                request_id,
+                                                error_code: "invalid-nominal-hashrate"
+                                                    .to_string()
+                                                    .try_into()
+                                                    .expect("error code must be valid string"),
+                                            };
+                                        return Ok(vec![(
+                                            downstream_id,
+                                            Mining::OpenMiningChannelError(
+                                                open_extended_mining_channel_error,
+                                            ),
+                                        )
+                                            .into()]);
+                                    }
+                                    ExtendedChannelError::RequestedMaxTargetOutOfRange => {
+                                        error!("OpenMiningChannelError: max-target-out-of-range");
+                                        let open_extended_mining_channel_error =
+                                            OpenMiningChannelError {
+                                                request_id,
+                                                error_code: "max-target-out-of-range"
+                                                    .to_string()
+                                                    .try_into()
+                                                    .expect("error code must be valid string"),
+                                            };
+                                        return Ok(vec![(
+                                            downstream_id,
+                                            Mining::OpenMiningChannelError(
+                                                open_extended_mining_channel_error,
+                                            ),
+                                        )
+                                            .into()]);
+                                    }
+                                    ExtendedChannelError::RequestedMinExtranonceSizeTooLarge => {
+                                        error!("OpenMiningChannelError: min-extranonce-size-too-large");
+                                        let open_extended_mining_channel_error =
+                                            OpenMiningChannelError {
+                                                request_id,
+                                                error_code: "min-extranonce-size-too-large"
+                                                    .to_string()
+                                                    .try_into()
+                                                    .expect("error code must be valid string"),
+                                            };
+                                        return Ok(vec![(
+                                            downstream_id,
+                                            Mining::OpenMiningChannelError(
+                                                open_extended_mining_channel_error,
+                                            ),
+                                        )
+                                            .into()]);
+                                    }
+                                    e => {
+                                        error!("error in handle_open_extended_mining_channel: {:?}", e);
+                                        return Err(e)?;
+                                    }
                                 }
-                            },
+                            }
                         };
 
                         let open_extended_mining_channel_success =
@@ -378,7 +498,10 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                                 extranonce_prefix: extended_channel
                                     .get_extranonce_prefix()
                                     .clone()
-                                    .try_into()?,
+                                    .try_into()
+                                    .inspect_err(|_| {
+                                        channel_manager_data.extranonce_prefixes.release(&prefix)
+                                    })?,
                                 extranonce_size: extended_channel.get_rollable_extranonce_size(),
                             }
                             .into_static();
@@ -394,23 +517,15 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                                 .into(),
                         );
 
-                        let Some(last_set_new_prev_hash_tdp) =
-                            channel_manager_data.last_new_prev_hash.clone()
-                        else {
-                            return Err(PoolError::LastNewPrevhashNotFound);
-                        };
-
-                        let Some(last_future_template) =
-                            channel_manager_data.last_future_template.clone()
-                        else {
-                            return Err(PoolError::FutureTemplateNotPresent);
-                        };
-
                         // if the client requires custom work, we don't need to send any extended
                         // jobs so we just process the SetNewPrevHash
                         // message
                         if downstream.requires_custom_work.load(Ordering::SeqCst) {
-                            extended_channel.on_set_new_prev_hash(last_set_new_prev_hash_tdp)?;
+                            extended_channel
+                                .on_set_new_prev_hash(last_set_new_prev_hash_tdp)
+                                .inspect_err(|_| {
+                                    channel_manager_data.extranonce_prefixes.release(&prefix)
+                                })?;
                             // if the client does not require custom work, we need to send the
                             // future extended job
                             // and the SetNewPrevHash message
@@ -422,10 +537,14 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                                 script_pubkey: self.coinbase_reward_script.script_pubkey(),
                             };
 
-                            extended_channel.on_new_template(
-                                last_future_template.clone(),
-                                vec![pool_coinbase_output],
-                            )?;
+                            extended_channel
+                                .on_new_template(
+                                    last_future_template.clone(),
+                                    vec![pool_coinbase_output],
+                                )
+                                .inspect_err(|_| {
+                                    channel_manager_data.extranonce_prefixes.release(&prefix)
+                                })?;
 
                             let future_extended_job_id = extended_channel
                                 .get_future_template_to_job_id()
@@ -462,7 +581,11 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                                 nbits: n_bits,
                             };
 
-                            extended_channel.on_set_new_prev_hash(last_set_new_prev_hash_tdp)?;
+                            extended_channel
+                                .on_set_new_prev_hash(last_set_new_prev_hash_tdp)
+                                .inspect_err(|_| {
+                                    channel_manager_data.extranonce_prefixes.release(&prefix)
+                                })?;
 
                             messages.push(
                                 (
@@ -480,6 +603,21 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                         channel_manager_data
                             .vardiff
                             .insert((downstream_id, channel_id as u32).into(), vardiff);
+                        if let Some(hashrate_floor) = hashrate_floor {
+                            channel_manager_data
+                                .hashrate_floors
+                                .insert((downstream_id, channel_id as u32).into(), hashrate_floor);
+                        }
+                        if let Some(share_rate_limiter) = self.share_rate_limiter() {
+                            channel_manager_data.share_rate_limiters.insert(
+                                (downstream_id, channel_id as u32).into(),
+                                share_rate_limiter,
+                            );
+                        }
+                        channel_manager_data.observed_hashrates.insert(
+                            (downstream_id, channel_id as u32).into(),
+                            ObservedHashrate::new(Instant::now()),
+                        );
 
                         Ok(messages)
                     })
@@ -492,6 +630,16 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
         Ok(())
     }
 
+    #[instrument(
+        name = "share",
+        skip_all,
+        fields(
+            channel_id = msg.channel_id,
+            sequence_number = msg.sequence_number,
+            job_id = msg.job_id,
+            nonce = msg.nonce
+        )
+    )]
     async fn handle_submit_shares_standard(
         &mut self,
         client_id: Option<usize>,
@@ -500,27 +648,51 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
         info!("Received SubmitSharesStandard: {msg}");
         let downstream_id =
             client_id.expect("client_id must be present for downstream_id extraction");
+        let share_event = |client: SocketAddr| ShareValidated {
+            connection_id: downstream_id,
+            client: client.to_string(),
+            channel_id: msg.channel_id,
+            user_identity: None,
+            account: None,
+            worker: None,
+            sequence_number: msg.sequence_number,
+            job_id: msg.job_id,
+            nonce: msg.nonce,
+            ntime: msg.ntime,
+            version: msg.version,
+            error_code: None,
+            target_difficulty: None,
+            share_difficulty: None,
+            best_share_difficulty: None,
+            share_hash: None,
+            notable: false,
+        };
 
         let messages = self.channel_manager_data.super_safe_lock(|channel_manager_data| {
             let channel_id = msg.channel_id;
+            let rate_limit = self.limit_share_rate(channel_manager_data, downstream_id, channel_id);
 
             let Some(downstream) = channel_manager_data.downstream.get(&downstream_id) else {
                 return Err(PoolError::DownstreamNotFound(downstream_id));
             };
+            let address = downstream.address;
+            if self.banlist.is_ip_banned(address.ip()) {
+                return Ok(vec![self.reject_share(downstream_id, share_event(address), ShareRejectReason::Banned)]);
+            }
+            match rate_limit {
+                RateLimitOutcome::Allowed => {}
+                RateLimitOutcome::Refused => {
+                    return Ok(vec![self.reject_share(downstream_id, share_event(address), ShareRejectReason::RateLimited)]);
+                }
+                RateLimitOutcome::Exceeded => {
+                    return Ok(self.close_flooding_channel(channel_manager_data, downstream_id, address, share_event(address)));
+                }
+            }
 
             downstream.downstream_data.super_safe_lock(|downstream_data| {
                 let mut messages: Vec<RouteMessageTo> = Vec::new();
                 let Some(standard_channel) = downstream_data.standard_channels.get_mut(&channel_id) else {
-                    let submit_shares_error = SubmitSharesError {
-                        channel_id,
-                        sequence_number: msg.sequence_number,
-                        error_code: "invalid-channel-id"
-                            .to_string()
-                            .try_into()
-                            .expect("error code must be valid string"),
-                    };
-                    error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: invalid-channel-id ❌", downstream_id, channel_id, msg.sequence_number);
-                    return Ok(vec![(downstream_id, Mining::SubmitSharesError(submit_shares_error)).into()]);
+                    return Ok(vec![self.reject_share(downstream_id, share_event(address), ShareRejectReason::InvalidChannelId)]);
                 };
 
                 let Some(vardiff) = channel_manager_data.vardiff.get_mut(&(downstream_id, channel_id).into()) else {
@@ -529,6 +701,11 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
 
                 let res = standard_channel.validate_share(msg.clone());
                 vardiff.increment_shares_since_last_update();
+                if matches!(res, Ok(ShareValidationResult::Valid(_) | ShareValidationResult::BlockFound(..))) {
+                    if let Some(observed_hashrate) = channel_manager_data.observed_hashrates.get_mut(&(downstream_id, channel_id).into()) {
+                        observed_hashrate.record(standard_channel.get_target().difficulty_float(), Instant::now());
+                    }
+                }
 
 
                 match res {
@@ -550,10 +727,11 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                                 downstream_id, channel_id, msg.sequence_number, share_hash, share_work
                             );
                         }
-
+                        self.accept_share(share_event(address), standard_channel.get_user_identity(), standard_channel.get_target(), share_hash.to_byte_array());
                     }
                     Ok(ShareValidationResult::BlockFound(share_hash, template_id, coinbase)) => {
                         info!("SubmitSharesStandard: 💰 Block Found!!! 💰{share_hash}");
+                        let reward = self.block_reward(&coinbase);
                         // if we have a template id (i.e.: this was not a custom job)
                         // we can propagate the solution to the TP
                         if let Some(template_id) = template_id {
@@ -575,70 +753,25 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                             new_shares_sum: share_accounting.get_last_batch_work_sum() as u64,
                         };
                         messages.push((downstream_id, Mining::SubmitSharesSuccess(success)).into());
-                    }
-                    Err(ShareValidationError::Invalid) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: invalid-share ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "invalid-share"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
-                    }
-                    Err(ShareValidationError::Stale) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: stale-share ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "stale-share"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
-                    }
-                    Err(ShareValidationError::InvalidJobId) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: invalid-job-id ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "invalid-job-id"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
-                    }
-                    Err(ShareValidationError::DoesNotMeetTarget) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: difficulty-too-low ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "difficulty-too-low"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
-                    }
-                    Err(ShareValidationError::DuplicateShare) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: duplicate-share ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "duplicate-share"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
+                        self.accept_share(share_event(address), standard_channel.get_user_identity(), standard_channel.get_target(), share_hash.to_byte_array());
+                        let WorkerIdentity { account, worker } = self.worker_identity.split(standard_channel.get_user_identity());
+                        self.block_found(BlockFound {
+                            connection_id: downstream_id,
+                            channel_id,
+                            user_identity: standard_channel.get_user_identity().to_string(),
+                            account,
+                            worker,
+                            block_hash: share_hash.to_string(),
+                            reward,
+                            template_id,
+                        });
                     }
                     Err(e) => {
-                        return Err(e)?;
+                        let Some(reason) = ShareRejectReason::from_validation_error(&e) else {
+                            return Err(e)?;
+                        };
+                        let event = self.share_on_channel(share_event(address), standard_channel.get_user_identity(), standard_channel.get_target());
+                        messages.push(self.reject_share(downstream_id, event, reason));
                     }
                 }
 
@@ -653,6 +786,16 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
         Ok(())
     }
 
+    #[instrument(
+        name = "share",
+        skip_all,
+        fields(
+            channel_id = msg.channel_id,
+            sequence_number = msg.sequence_number,
+            job_id = msg.job_id,
+            nonce = msg.nonce
+        )
+    )]
     async fn handle_submit_shares_extended(
         &mut self,
         client_id: Option<usize>,
@@ -661,25 +804,51 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
         info!("Received SubmitSharesExtended: {msg}");
         let downstream_id =
             client_id.expect("client_id must be present for downstream_id extraction");
+        let share_event = |client: SocketAddr| ShareValidated {
+            connection_id: downstream_id,
+            client: client.to_string(),
+            channel_id: msg.channel_id,
+            user_identity: None,
+            account: None,
+            worker: None,
+            sequence_number: msg.sequence_number,
+            job_id: msg.job_id,
+            nonce: msg.nonce,
+            ntime: msg.ntime,
+            version: msg.version,
+            error_code: None,
+            target_difficulty: None,
+            share_difficulty: None,
+            best_share_difficulty: None,
+            share_hash: None,
+            notable: false,
+        };
+
         let messages = self.channel_manager_data.super_safe_lock(|channel_manager_data| {
             let channel_id = msg.channel_id;
+            let rate_limit = self.limit_share_rate(channel_manager_data, downstream_id, channel_id);
+
             let Some(downstream) = channel_manager_data.downstream.get(&downstream_id) else {
                 return Err(PoolError::DownstreamNotFound(downstream_id));
             };
+            let address = downstream.address;
+            if self.banlist.is_ip_banned(address.ip()) {
+                return Ok(vec![self.reject_share(downstream_id, share_event(address), ShareRejectReason::Banned)]);
+            }
+            match rate_limit {
+                RateLimitOutcome::Allowed => {}
+                RateLimitOutcome::Refused => {
+                    return Ok(vec![self.reject_share(downstream_id, share_event(address), ShareRejectReason::RateLimited)]);
+                }
+                RateLimitOutcome::Exceeded => {
+                    return Ok(self.close_flooding_channel(channel_manager_data, downstream_id, address, share_event(address)));
+                }
+            }
 
             downstream.downstream_data.super_safe_lock(|downstream_data| {
                 let mut messages: Vec<RouteMessageTo> = Vec::new();
                 let Some(extended_channel) = downstream_data.extended_channels.get_mut(&channel_id) else {
-                    let error = SubmitSharesError {
-                        channel_id,
-                        sequence_number: msg.sequence_number,
-                        error_code: "invalid-channel-id"
-                            .to_string()
-                            .try_into()
-                            .expect("error code must be valid string"),
-                    };
-                    error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: invalid-channel-id ❌", downstream_id, channel_id, msg.sequence_number);
-                    return Ok(vec![(downstream_id, Mining::SubmitSharesError(error)).into()]);
+                    return Ok(vec![self.reject_share(downstream_id, share_event(address), ShareRejectReason::InvalidChannelId)]);
                 };
 
                 let Some(vardiff) = channel_manager_data.vardiff.get_mut(&(downstream_id, channel_id).into()) else {
@@ -688,6 +857,11 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
 
                 let res = extended_channel.validate_share(msg.clone());
                 vardiff.increment_shares_since_last_update();
+                if matches!(res, Ok(ShareValidationResult::Valid(_) | ShareValidationResult::BlockFound(..))) {
+                    if let Some(observed_hashrate) = channel_manager_data.observed_hashrates.get_mut(&(downstream_id, channel_id).into()) {
+                        observed_hashrate.record(extended_channel.get_target().difficulty_float(), Instant::now());
+                    }
+                }
 
                 match res {
                     Ok(ShareValidationResult::Valid(share_hash)) => {
@@ -708,9 +882,11 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                                 downstream_id, channel_id, msg.sequence_number, share_hash, share_work
                             );
                         }
+                        self.accept_share(share_event(address), extended_channel.get_user_identity(), extended_channel.get_target(), share_hash.to_byte_array());
                     }
                     Ok(ShareValidationResult::BlockFound(share_hash, template_id, coinbase)) => {
                         info!("SubmitSharesExtended: 💰 Block Found!!! 💰{share_hash}");
+                        let reward = self.block_reward(&coinbase);
                         // if we have a template id (i.e.: this was not a custom job)
                         // we can propagate the solution to the TP
                         if let Some(template_id) = template_id {
@@ -732,81 +908,25 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                             new_shares_sum: share_accounting.get_last_batch_work_sum() as u64,
                         };
                         messages.push((downstream_id, Mining::SubmitSharesSuccess(success)).into());
-                    }
-                    Err(ShareValidationError::Invalid) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: invalid-share ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "invalid-share"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
-                    }
-                    Err(ShareValidationError::Stale) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: stale-share ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "stale-share"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
-                    }
-                    Err(ShareValidationError::InvalidJobId) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: invalid-job-id ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "invalid-job-id"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
-                    }
-                    Err(ShareValidationError::DoesNotMeetTarget) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: difficulty-too-low ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "difficulty-too-low"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
-                    }
-                    Err(ShareValidationError::DuplicateShare) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: duplicate-share ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "duplicate-share"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
-                    }
-                    Err(ShareValidationError::BadExtranonceSize) => {
-                        error!("SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: bad-extranonce-size ❌", downstream_id, channel_id, msg.sequence_number);
-                        let error = SubmitSharesError {
-                            channel_id: msg.channel_id,
-                            sequence_number: msg.sequence_number,
-                            error_code: "bad-extranonce-size"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        messages.push((downstream_id, Mining::SubmitSharesError(error)).into());
+                        self.accept_share(share_event(address), extended_channel.get_user_identity(), extended_channel.get_target(), share_hash.to_byte_array());
+                        let WorkerIdentity { account, worker } = self.worker_identity.split(extended_channel.get_user_identity());
+                        self.block_found(BlockFound {
+                            connection_id: downstream_id,
+                            channel_id,
+                            user_identity: extended_channel.get_user_identity().to_string(),
+                            account,
+                            worker,
+                            block_hash: share_hash.to_string(),
+                            reward,
+                            template_id,
+                        });
                     }
                     Err(e) => {
-                        return Err(e)?;
+                        let Some(reason) = ShareRejectReason::from_validation_error(&e) else {
+                            return Err(e)?;
+                        };
+                        let event = self.share_on_channel(share_event(address), extended_channel.get_user_identity(), extended_channel.get_target());
+                        messages.push(self.reject_share(downstream_id, event, reason));
                     }
                 }
 
@@ -839,94 +959,48 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
             downstream.downstream_data.super_safe_lock(|downstream_data| {
                 let mut messages = Vec::new();
                 let channel_id = msg.channel_id;
-                let new_nominal_hash_rate = msg.nominal_hash_rate;
+                let hashrate_floor = channel_manager_data.hashrate_floors.get(&(downstream_id, channel_id).into()).copied();
+                let new_nominal_hash_rate = hashrate_floor.map_or(msg.nominal_hash_rate, |floor| msg.nominal_hash_rate.max(floor));
                 let requested_maximum_target = Target::from_le_bytes(msg.maximum_target.inner_as_ref().try_into().unwrap());
+                let now = Instant::now();
+                let observed_hashrate = channel_manager_data.observed_hashrates.get(&(downstream_id, channel_id).into());
+                let observed_hash_rate = observed_hashrate.and_then(|observed| observed.estimate(now));
+                let refused = observed_hashrate.and_then(|observed| observed.refuses(new_nominal_hash_rate, now));
+                if let Some(observed) = refused {
+                    warn!("UpdateChannel of channel {channel_id} announces {new_nominal_hash_rate} h/s while {observed} h/s are observed");
+                }
 
-                if let Some(standard_channel) = downstream_data.standard_channels.get_mut(&channel_id) {
-                    let res = standard_channel
-                                    .update_channel(new_nominal_hash_rate, Some(requested_maximum_target));
-                    match res {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("UpdateChannelError: {:?}", e);
-                            match e {
-                                StandardChannelError::InvalidNominalHashrate => {
-                                    error!("UpdateChannelError: invalid-nominal-hashrate");
-                                    let update_channel_error = UpdateChannelError {
-                                        channel_id,
-                                        error_code: "invalid-nominal-hashrate"
-                                            .to_string()
-                                            .try_into()
-                                            .expect("error code must be valid string"),
-                                    };
-                                    messages.push((downstream_id, Mining::UpdateChannelError(update_channel_error)).into());
-                                }
-                                StandardChannelError::RequestedMaxTargetOutOfRange => {
-                                    error!("UpdateChannelError: requested-max-target-out-of-range");
-                                    let update_channel_error = UpdateChannelError {
-                                        channel_id,
-                                        error_code: "requested-max-target-out-of-range"
-                                            .to_string()
-                                            .try_into()
-                                            .expect("error code must be valid string"),
-                                    };
-                                    messages.push((downstream_id, Mining::UpdateChannelError(update_channel_error)).into());
-                                }
-                                standard_channel_error => {
-                                    return Err(standard_channel_error)?;
-                                }
-                            }
-                        }
-                    }
-                    let new_target = standard_channel.get_target();
-                    let set_target = SetTarget {
-                        channel_id,
-                        maximum_target: new_target.to_le_bytes().into(),
+                // User identity, nominal hashrate before the update, error code refusing it and
+                // target after it.
+                let outcome = if let Some(standard_channel) = downstream_data.standard_channels.get_mut(&channel_id) {
+                    let previous_hash_rate = standard_channel.get_nominal_hashrate();
+                    let error_code = match refused {
+                        Some(_) => Some("invalid-nominal-hashrate"),
+                        None => match standard_channel.update_channel(new_nominal_hash_rate, Some(requested_maximum_target)) {
+                            Ok(_) => None,
+                            Err(StandardChannelError::InvalidNominalHashrate) => Some("invalid-nominal-hashrate"),
+                            Err(StandardChannelError::RequestedMaxTargetOutOfRange) => Some("requested-max-target-out-of-range"),
+                            Err(standard_channel_error) => return Err(standard_channel_error)?,
+                        },
                     };
-                    messages.push((downstream_id, Mining::SetTarget(set_target)).into());
+                    Some((standard_channel.get_user_identity().to_string(), previous_hash_rate, error_code, *standard_channel.get_target()))
                 } else if let Some(extended_channel) = downstream_data.extended_channels.get_mut(&channel_id) {
-                    let res = extended_channel
-                                    .update_channel(new_nominal_hash_rate, Some(requested_maximum_target));
-                    match res {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("UpdateChannelError: {:?}", e);
-                            match e {
-                                ExtendedChannelError::InvalidNominalHashrate => {
-                                    error!("UpdateChannelError: invalid-nominal-hashrate");
-                                    let update_channel_error = UpdateChannelError {
-                                        channel_id,
-                                        error_code: "invalid-nominal-hashrate"
-                                            .to_string()
-                                            .try_into()
-                                            .expect("error code must be valid string"),
-                                    };
-                                    messages.push((downstream_id, Mining::UpdateChannelError(update_channel_error)).into());
-                                }
-                                ExtendedChannelError::RequestedMaxTargetOutOfRange => {
-                                    error!("UpdateChannelError: max-target-out-of-range");
-                                    let update_channel_error = UpdateChannelError {
-                                        channel_id,
-                                        error_code: "max-target-out-of-range"
-                                            .to_string()
-                                            .try_into()
-                                            .expect("error code must be valid string"),
-                                    };
-                                    messages.push((downstream_id, Mining::UpdateChannelError(update_channel_error)).into());
-                                }
-                                extended_channel_error => {
-                                    return Err(extended_channel_error)?;
-                                }
-                            }
-                        }
-                    }
-                    let new_target = extended_channel.get_target();
-                    let set_target = SetTarget {
-                        channel_id,
-                        maximum_target: new_target.to_le_bytes().into(),
+                    let previous_hash_rate = extended_channel.get_nominal_hashrate();
+                    let error_code = match refused {
+                        Some(_) => Some("invalid-nominal-hashrate"),
+                        None => match extended_channel.update_channel(new_nominal_hash_rate, Some(requested_maximum_target)) {
+                            Ok(_) => None,
+                            Err(ExtendedChannelError::InvalidNominalHashrate) => Some("invalid-nominal-hashrate"),
+                            Err(ExtendedChannelError::RequestedMaxTargetOutOfRange) => Some("max-target-out-of-range"),
+                            Err(extended_channel_error) => return Err(extended_channel_error)?,
+                        },
                     };
-                    messages.push((downstream_id, Mining::SetTarget(set_target)).into());
+                    Some((extended_channel.get_user_identity().to_string(), previous_hash_rate, error_code, *extended_channel.get_target()))
                 } else {
+                    None
+                };
+
+                let Some((user_identity, previous_hash_rate, error_code, new_target)) = outcome else {
                     error!("UpdateChannelError: invalid-channel-id");
                     let update_channel_error = UpdateChannelError {
                         channel_id,
@@ -936,6 +1010,38 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
                             .expect("error code must be valid string"),
                     };
                     messages.push((downstream_id, Mining::UpdateChannelError(update_channel_error)).into());
+                    return Ok(messages);
+                };
+
+                self.channel_updates.record(ChannelUpdate {
+                    connection_id: downstream_id,
+                    channel_id,
+                    user_identity,
+                    previous_hash_rate,
+                    requested_hash_rate: msg.nominal_hash_rate,
+                    observed_hash_rate,
+                    difficulty: new_target.difficulty_float(),
+                    error_code: error_code.map(str::to_string),
+                });
+                match error_code {
+                    Some(error_code) => {
+                        error!("UpdateChannelError: {error_code}");
+                        let update_channel_error = UpdateChannelError {
+                            channel_id,
+                            error_code: error_code
+                                .to_string()
+                                .try_into()
+                                .expect("error code must be valid string"),
+                        };
+                        messages.push((downstream_id, Mining::UpdateChannelError(update_channel_error)).into());
+                    }
+                    None => {
+                        let set_target = SetTarget {
+                            channel_id,
+                            maximum_target: new_target.to_le_bytes().into(),
+                        };
+                        messages.push((downstream_id, Mining::SetTarget(set_target)).into());
+                    }
                 }
 
                 Ok(messages)
@@ -958,75 +1064,22 @@ impl HandleMiningMessagesFromClientAsync for ChannelManager {
         let downstream_id =
             client_id.expect("client_id must be present for downstream_id extraction");
 
-        // this is a naive implementation, but ideally we should check the SetCustomMiningJob
-        // message parameters, especially:
-        // - the mining_job_token
-        // - the amount of the pool payout output
-        let custom_job_coinbase_outputs = Vec::<TxOut>::consensus_decode(
-            &mut msg.coinbase_tx_outputs.inner_as_ref().to_vec().as_slice(),
-        )?;
+        #[cfg(feature = "jd")]
+        if self.setup_connection.work_selection() {
+            return self.set_custom_mining_job(downstream_id, msg).await;
+        }
 
+        error!("SetCustomMiningJobError: work-selection-disabled");
+        let error = SetCustomMiningJobError {
+            request_id: msg.request_id,
+            channel_id: msg.channel_id,
+            error_code: "work-selection-disabled"
+                .to_string()
+                .try_into()
+                .expect("error code must be valid string"),
+        };
         let message: RouteMessageTo =
-            self.channel_manager_data
-                .super_safe_lock(|channel_manager_data| {
-                    // check that the script_pubkey from self.coinbase_reward_script
-                    // is present in the custom job coinbase outputs
-                    let missing_script = !custom_job_coinbase_outputs.iter().any(|pool_output| {
-                        *pool_output.script_pubkey == *self.coinbase_reward_script.script_pubkey()
-                    });
-
-                    if missing_script {
-                        error!("SetCustomMiningJobError: pool-payout-script-missing");
-
-                        let error = SetCustomMiningJobError {
-                            request_id: msg.request_id,
-                            channel_id: msg.channel_id,
-                            error_code: "pool-payout-script-missing"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-
-                        return Ok((downstream_id, Mining::SetCustomMiningJobError(error)).into());
-                    }
-
-                    let Some(downstream) = channel_manager_data.downstream.get_mut(&downstream_id)
-                    else {
-                        return Err(PoolError::DownstreamNotFound(downstream_id));
-                    };
-
-                    downstream
-                        .downstream_data
-                        .super_safe_lock(|downstream_data| {
-                            let Some(extended_channel) =
-                                downstream_data.extended_channels.get_mut(&msg.channel_id)
-                            else {
-                                error!("SetCustomMiningJobError: invalid-channel-id");
-                                let error = SetCustomMiningJobError {
-                                    request_id: msg.request_id,
-                                    channel_id: msg.channel_id,
-                                    error_code: "invalid-channel-id"
-                                        .to_string()
-                                        .try_into()
-                                        .expect("error code must be valid string"),
-                                };
-                                return Ok(
-                                    (downstream_id, Mining::SetCustomMiningJobError(error)).into()
-                                );
-                            };
-
-                            let job_id = extended_channel
-                                .on_set_custom_mining_job(msg.clone().into_static())?;
-
-                            let success = SetCustomMiningJobSuccess {
-                                channel_id: msg.channel_id,
-                                request_id: msg.request_id,
-                                job_id,
-                            };
-                            Ok((downstream_id, Mining::SetCustomMiningJobSuccess(success)).into())
-                        })
-                })?;
-
+            (downstream_id, Mining::SetCustomMiningJobError(error)).into();
         message.forward(&self.channel_manager_channel).await;
         Ok(())
     }
@@ -1,17 +1,35 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::SocketAddr,
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc,
+    },
+    time::{Duration, SystemTime},
 };
 
 use async_channel::{Receiver, Sender};
 use core::sync::atomic::Ordering;
+#[cfg(feature = "jd")]
+use stratum_apps::key_utils::AuthorityPublicKeys;
 use stratum_apps::{
+    banlist::{BanList, BanTarget},
     config_helpers::CoinbaseRewardScript,
+    connections::{ConnectionInfo, ConnectionRegistry, ShutdownHandle},
     custom_mutex::Mutex,
-    key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
-    network_helpers::noise_stream::NoiseTcpStream,
+    events::{
+        BlockFound, ChannelClosed, ConnectionClosed, ConnectionOpened, EventBus, ShareValidated,
+    },
+    network_helpers::{
+        tcp::TcpOptions,
+        transport::{Encryption, Sv2TcpStream},
+    },
+    persistence::Persistence,
+    rate_limit::TokenBucket,
+    runtime::{HandshakeDeadline, IoChannelsConfig, TaskManager},
+    share_reject::{ShareRejectCounters, ShareRejectReason},
     stratum_core::{
+        bitcoin::{consensus::Decodable, Target, Transaction},
         channels_sv2::{
             server::{
                 extended::ExtendedChannel,
@@ -21,63 +39,170 @@ use stratum_apps::{
             Vardiff, VardiffState,
         },
         codec_sv2::HandshakeRole,
+        common_messages_sv2::ChannelEndpointChanged,
         handlers_sv2::{
             HandleMiningMessagesFromClientAsync, HandleTemplateDistributionMessagesFromServerAsync,
         },
-        mining_sv2::{ExtendedExtranonce, SetTarget},
-        noise_sv2::Responder,
-        parsers_sv2::{Mining, TemplateDistribution},
+        mining_sv2::{CloseChannel, SetTarget, SubmitSharesError},
+        parsers_sv2::{CommonMessages, Mining, TemplateDistribution},
         template_distribution_sv2::{NewTemplate, SetNewPrevHash},
     },
+    worker_identity::WorkerIdentityParser,
 };
-use tokio::{net::TcpListener, select, sync::broadcast};
-use tracing::{debug, error, info, warn};
+use tokio::{select, sync::broadcast};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 use crate::{
-    config::PoolConfig,
-    downstream::Downstream,
-    error::PoolResult,
+    authority::AuthorityKeys,
+    channel_updates::{ChannelUpdates, ObservedHashrate},
+    config::{
+        DeviceRule, DifficultyFloor, FutureJobMinNtime, InitialDifficultyConfig, PoolConfig,
+        SetupConnectionPolicy, ShareRateLimitConfig,
+    },
+    downstream::{outbound::OutboundQueues, Downstream, RemovedChannel},
+    error::{PoolError, PoolResult},
+    extranonce::ExtranonceAllocator,
+    monitoring::{BestShares, LatencyStats, ShareAckStats, TemplateStats},
+    notable_shares::NotableShares,
+    pool_signature::{bip34_height, PoolSignature},
+    rounds::Rounds,
+    sessions::{SavedChannel, Sessions},
     status::{handle_error, Status, StatusSender},
-    task_manager::TaskManager,
-    utils::{Message, ShutdownMessage, VardiffKey},
+    utils::{Message, SV2Frame, ShutdownMessage, VardiffKey},
+    workers::Workers,
 };
 
+pub mod activation;
+#[cfg(feature = "jd")]
+mod custom_jobs;
+pub mod job_distribution;
 mod mining_message_handler;
 mod template_distribution_message_handler;
+mod vardiff;
+
+use activation::ActivationFanout;
+use job_distribution::SharedFrame;
+pub use vardiff::{VardiffOverride, VardiffStatus};
+
+pub use crate::extranonce::FULL_EXTRANONCE_SIZE;
+
+/// Reason code of the channels closed for flooding the pool with shares.
+const SHARE_RATE_LIMIT_EXCEEDED: &str = "share-rate-limit-exceeded";
+/// Reason of the closing of the channels of a closed connection.
+const CONNECTION_CLOSED: &str = "connection-closed";
 
-const POOL_ALLOCATION_BYTES: usize = 4;
-const CLIENT_SEARCH_SPACE_BYTES: usize = 16;
-pub const FULL_EXTRANONCE_SIZE: usize = POOL_ALLOCATION_BYTES + CLIENT_SEARCH_SPACE_BYTES;
+// Event published for `channel`, removed from `downstream_id` connected from `address`.
+fn channel_closed(
+    downstream_id: usize,
+    address: SocketAddr,
+    channel: RemovedChannel,
+    reason: &str,
+) -> ChannelClosed {
+    ChannelClosed {
+        connection_id: downstream_id,
+        client: address.to_string(),
+        channel_id: channel.channel_id,
+        user_identity: channel.user_identity,
+        extended: channel.extended,
+        reason: reason.to_string(),
+    }
+}
+
+/// Allocates the id of a new channel of a downstream from `factory`, the `resumed` one if the
+/// downstream has not allocated it yet.
+fn next_channel_id(factory: &AtomicUsize, resumed: Option<u32>) -> usize {
+    match resumed.map(|channel_id| channel_id as usize) {
+        Some(channel_id) if factory.load(Ordering::SeqCst) <= channel_id => {
+            factory.store(channel_id + 1, Ordering::SeqCst);
+            channel_id
+        }
+        _ => factory.fetch_add(1, Ordering::SeqCst),
+    }
+}
+
+/// Outcome of applying the share rate limit of a channel to a submitted share.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RateLimitOutcome {
+    /// The share is within limits and can be validated.
+    Allowed,
+    /// The share is over the limit and must be rejected.
+    Refused,
+    /// Too many consecutive shares were rejected, the channel must be closed.
+    Exceeded,
+}
+
+// Token bucket limiting the shares submitted on a channel, along with the number of consecutive
+// shares it refused.
+struct ShareRateLimiter {
+    bucket: TokenBucket,
+    violations: u32,
+    max_violations: u32,
+}
+
+impl ShareRateLimiter {
+    fn new(config: &ShareRateLimitConfig, shares_per_minute: f32) -> Self {
+        Self {
+            bucket: TokenBucket::new(config.bucket(shares_per_minute)),
+            violations: 0,
+            max_violations: config.max_violations(),
+        }
+    }
+}
 
 pub struct ChannelManagerData {
-    // Mapping of `downstream_id` → `Downstream` object,
+    // Registry of the downstream connections by `downstream_id`, which also allocates their ids,
     // used by the channel manager to locate and interact with downstream clients.
-    downstream: HashMap<usize, Downstream>,
-    // Extranonce prefix factory for **extended downstream channels**.
-    // Each new extended downstream receives a unique extranonce prefix.
-    extranonce_prefix_factory_extended: ExtendedExtranonce,
-    // Extranonce prefix factory for **standard downstream channels**.
-    // Each new standard downstream receives a unique extranonce prefix.
-    extranonce_prefix_factory_standard: ExtendedExtranonce,
-    // Factory that assigns a unique ID to each new **downstream connection**.
-    downstream_id_factory: AtomicUsize,
+    downstream: ConnectionRegistry<usize, Downstream>,
+    // Allocator of the extranonce prefixes of the **standard and extended downstream channels**.
+    // Each new channel receives a unique extranonce prefix, released once it is closed.
+    extranonce_prefixes: ExtranonceAllocator,
     // Mapping of `(downstream_id, channel_id)` → vardiff controller.
     // Each entry manages variable difficulty for a specific downstream channel.
     vardiff: HashMap<VardiffKey, VardiffState>,
+    // Mapping of `(downstream_id, channel_id)` → minimum nominal hashrate the vardiff may set,
+    // for the channels whose user identity matches a difficulty floor.
+    hashrate_floors: HashMap<VardiffKey, f32>,
+    // Channels whose difficulty was pinned through the control service, left alone by the vardiff.
+    vardiff_pins: HashSet<VardiffKey>,
+    // Mapping of `(downstream_id, channel_id)` → limiter of the shares submitted on the channel.
+    share_rate_limiters: HashMap<VardiffKey, ShareRateLimiter>,
+    // Mapping of `(downstream_id, channel_id)` → hashrate estimated from the accepted shares,
+    // against which the `UpdateChannel` messages are validated.
+    observed_hashrates: HashMap<VardiffKey, ObservedHashrate>,
     // Coinbase outputs
     coinbase_outputs: Vec<u8>,
     // Last new prevhash
     last_new_prev_hash: Option<SetNewPrevHash<'static>>,
     // Last future template
     last_future_template: Option<NewTemplate<'static>>,
+    // Number of future templates whose jobs were sent to downstreams since the last new prevhash
+    future_templates_sent: usize,
+    // Future templates whose jobs are held back until they are activated
+    held_future_templates: HashSet<u64>,
+}
+
+impl ChannelManagerData {
+    // Forgets the vardiff and its pin, hashrate floor, share rate limiter and observed hashrate of
+    // a channel removed from `downstream_id`, and releases its extranonce prefix for the channels
+    // opened next.
+    fn reclaim_channel(&mut self, downstream_id: usize, channel: &RemovedChannel) {
+        let key: VardiffKey = (downstream_id, channel.channel_id).into();
+        self.vardiff.remove(&key);
+        self.vardiff_pins.remove(&key);
+        self.hashrate_floors.remove(&key);
+        self.share_rate_limiters.remove(&key);
+        self.observed_hashrates.remove(&key);
+        self.extranonce_prefixes.release(&channel.extranonce_prefix);
+    }
 }
 
 #[derive(Clone)]
 pub struct ChannelManagerChannel {
     tp_sender: Sender<TemplateDistribution<'static>>,
-    tp_receiver: Receiver<TemplateDistribution<'static>>,
-    downstream_sender: broadcast::Sender<(usize, Mining<'static>)>,
-    downstream_receiver: Receiver<(usize, Mining<'static>)>,
+    tp_receiver: Receiver<SV2Frame>,
+    downstream_queues: OutboundQueues,
+    downstream_receiver: Receiver<(usize, SV2Frame)>,
+    activations: ActivationFanout,
 }
 
 /// Contains all the state of mutable and immutable data required
@@ -87,10 +212,39 @@ pub struct ChannelManagerChannel {
 pub struct ChannelManager {
     channel_manager_data: Arc<Mutex<ChannelManagerData>>,
     channel_manager_channel: ChannelManagerChannel,
-    pool_tag_string: String,
+    pool_signature: PoolSignature,
+    server_id: u16,
     share_batch_size: usize,
     shares_per_minute: f32,
     coinbase_reward_script: CoinbaseRewardScript,
+    #[cfg(feature = "jd")]
+    jds_authority_public_key: Option<AuthorityPublicKeys>,
+    max_channels_per_connection: Option<usize>,
+    max_channels_per_ip: Option<usize>,
+    setup_connection: SetupConnectionPolicy,
+    device_rules: Arc<[DeviceRule]>,
+    downstream_channels: IoChannelsConfig,
+    banlist: Arc<BanList>,
+    persistence: Persistence,
+    events: EventBus,
+    share_rejects: Arc<ShareRejectCounters>,
+    future_jobs_ahead: Option<usize>,
+    future_jobs_min_ntime: FutureJobMinNtime,
+    initial_difficulty: InitialDifficultyConfig,
+    difficulty_floors: Vec<DifficultyFloor>,
+    worker_identity: WorkerIdentityParser,
+    share_rate_limit: Option<ShareRateLimitConfig>,
+    notable_shares: Option<Arc<NotableShares>>,
+    job_activations: Arc<LatencyStats>,
+    template_stats: Arc<TemplateStats>,
+    share_acks: Arc<ShareAckStats>,
+    best_shares: Arc<BestShares>,
+    rounds: Arc<Rounds>,
+    workers: Arc<Workers>,
+    channel_updates: Arc<ChannelUpdates>,
+    sessions: Arc<Sessions>,
+    // Whether new downstream connections are refused, see `drain`.
+    draining: Arc<AtomicBool>,
 }
 
 impl ChannelManager {
@@ -99,49 +253,52 @@ impl ChannelManager {
     pub async fn new(
         config: PoolConfig,
         tp_sender: Sender<TemplateDistribution<'static>>,
-        tp_receiver: Receiver<TemplateDistribution<'static>>,
-        downstream_sender: broadcast::Sender<(usize, Mining<'static>)>,
-        downstream_receiver: Receiver<(usize, Mining<'static>)>,
+        tp_receiver: Receiver<SV2Frame>,
+        downstream_receiver: Receiver<(usize, SV2Frame)>,
         coinbase_outputs: Vec<u8>,
+        banlist: Arc<BanList>,
+        share_rejects: Arc<ShareRejectCounters>,
+        job_activations: Arc<LatencyStats>,
+        template_stats: Arc<TemplateStats>,
+        share_acks: Arc<ShareAckStats>,
+        best_shares: Arc<BestShares>,
+        rounds: Arc<Rounds>,
+        workers: Arc<Workers>,
+        channel_updates: Arc<ChannelUpdates>,
+        persistence: Persistence,
+        events: EventBus,
+        sessions: Arc<Sessions>,
     ) -> PoolResult<Self> {
-        let range_0 = 0..0;
-        let range_1 = 0..POOL_ALLOCATION_BYTES;
-        let range_2 = POOL_ALLOCATION_BYTES..POOL_ALLOCATION_BYTES + CLIENT_SEARCH_SPACE_BYTES;
-
-        let make_extranonce_factory = || {
-            // simulating a scenario where there are multiple mining servers
-            // this static prefix allows unique extranonce_prefix allocation
-            // for this mining server
-            let static_prefix = config.server_id().to_be_bytes().to_vec();
-
-            ExtendedExtranonce::new(
-                range_0.clone(),
-                range_1.clone(),
-                range_2.clone(),
-                Some(static_prefix),
-            )
-            .expect("Failed to create ExtendedExtranonce with valid ranges")
+        // The prefixes start with the server id, so that the mining servers of a pool never
+        // hand out the same one.
+        let pool_signature =
+            PoolSignature::parse(config.pool_signature()).map_err(PoolError::Config)?;
+        let extranonce_prefixes = match config.extranonce_allocator() {
+            Some(allocator) => ExtranonceAllocator::load(config.server_id(), allocator.path())?,
+            None => ExtranonceAllocator::new(config.server_id()),
         };
 
-        let extranonce_prefix_factory_extended = make_extranonce_factory();
-        let extranonce_prefix_factory_standard = make_extranonce_factory();
-
         let channel_manager_data = Arc::new(Mutex::new(ChannelManagerData {
-            downstream: HashMap::new(),
-            extranonce_prefix_factory_extended,
-            extranonce_prefix_factory_standard,
-            downstream_id_factory: AtomicUsize::new(1),
+            downstream: ConnectionRegistry::new(1),
+            extranonce_prefixes,
             vardiff: HashMap::new(),
+            hashrate_floors: HashMap::new(),
+            vardiff_pins: HashSet::new(),
+            share_rate_limiters: HashMap::new(),
+            observed_hashrates: HashMap::new(),
             coinbase_outputs,
             last_future_template: None,
             last_new_prev_hash: None,
+            future_templates_sent: 0,
+            held_future_templates: HashSet::new(),
         }));
 
         let channel_manager_channel = ChannelManagerChannel {
             tp_sender,
             tp_receiver,
-            downstream_sender,
+            downstream_queues: OutboundQueues::default(),
             downstream_receiver,
+            activations: ActivationFanout::default(),
         };
 
         let channel_manager = ChannelManager {
@@ -149,37 +306,517 @@ impl ChannelManager {
             channel_manager_channel,
             share_batch_size: config.share_batch_size(),
             shares_per_minute: config.shares_per_minute(),
-            pool_tag_string: config.pool_signature().to_string(),
+            pool_signature,
+            server_id: config.server_id(),
             coinbase_reward_script: config.coinbase_reward_script().clone(),
+            #[cfg(feature = "jd")]
+            jds_authority_public_key: config.jds_authority_public_key().cloned(),
+            max_channels_per_connection: config.max_channels_per_connection(),
+            max_channels_per_ip: config.max_channels_per_ip(),
+            setup_connection: *config.setup_connection(),
+            device_rules: config.device_rules().into(),
+            downstream_channels: *config.downstream_channels(),
+            banlist,
+            persistence,
+            events,
+            share_rejects,
+            future_jobs_ahead: config.future_jobs_ahead(),
+            future_jobs_min_ntime: config.future_jobs_min_ntime(),
+            initial_difficulty: config.initial_difficulty().clone(),
+            difficulty_floors: config.difficulty_floors().to_vec(),
+            worker_identity: config.worker_identity().clone(),
+            share_rate_limit: config.share_rate_limit().cloned(),
+            notable_shares: config
+                .notable_shares()
+                .map(|notable_shares| Arc::new(NotableShares::new(notable_shares))),
+            job_activations,
+            template_stats,
+            share_acks,
+            best_shares,
+            rounds,
+            workers,
+            channel_updates,
+            sessions,
+            draining: Arc::new(AtomicBool::new(false)),
         };
 
         Ok(channel_manager)
     }
 
+    /// Returns the downstreams currently connected.
+    pub fn downstreams(&self) -> Vec<Downstream> {
+        self.channel_manager_data
+            .super_safe_lock(|data| data.downstream.values().cloned().collect())
+    }
+
+    /// Returns the downstreams currently connected along with their entry in the connection
+    /// registry, by id.
+    pub fn connections(&self) -> Vec<(ConnectionInfo, Downstream)> {
+        self.channel_manager_data.super_safe_lock(|data| {
+            data.downstream
+                .list()
+                .into_iter()
+                .filter_map(|info| {
+                    let downstream = data.downstream.get(&(info.id as usize))?.clone();
+                    Some((info, downstream))
+                })
+                .collect()
+        })
+    }
+
+    /// Closes the connection of `downstream_id`, the downstream being removed once its tasks
+    /// shut down.
+    ///
+    /// Returns `false` if no such downstream is connected.
+    pub fn disconnect(&self, downstream_id: usize) -> bool {
+        self.channel_manager_data
+            .super_safe_lock(|data| data.downstream.disconnect(&downstream_id))
+    }
+
+    /// Refuses the new downstream connections from now on, the connected downstreams being served
+    /// until they disconnect.
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    /// Closes the channel `channel_id` of `downstream_id` with the reason code `reason`, keeping
+    /// the connection open: the channel is torn down as if the downstream closed it, and the
+    /// downstream is sent a `CloseChannel`.
+    ///
+    /// Returns `false` if the downstream has no such channel. `reason` must fit a `Str0255`.
+    pub fn close_channel(&self, downstream_id: usize, channel_id: u32, reason: &str) -> bool {
+        let closed = self
+            .channel_manager_data
+            .super_safe_lock(|channel_manager_data| {
+                self.teardown_channel(channel_manager_data, downstream_id, channel_id, reason)
+            });
+        if !closed {
+            return false;
+        }
+        info!("Closing channel {channel_id} of downstream {downstream_id}: {reason}");
+        let close_channel: RouteMessageTo<'static> = (
+            downstream_id,
+            Mining::CloseChannel(CloseChannel {
+                channel_id,
+                reason_code: reason
+                    .to_string()
+                    .try_into()
+                    .expect("reason code must be valid string"),
+            }),
+        )
+            .into();
+        // Queued from a task of its own, as the control service closes channels from synchronous
+        // handlers.
+        let channel_manager_channel = self.channel_manager_channel.clone();
+        tokio::spawn(async move { close_channel.forward(&channel_manager_channel).await });
+        true
+    }
+
+    /// Sends `downstream_id` a `ChannelEndpointChanged` for its channel `channel_id`, standard,
+    /// extended or group, once the channel moved to another endpoint: the downstream is to reset
+    /// the sequence numbers of its shares, and to expect new jobs before submitting more.
+    ///
+    /// The pool itself never moves a channel: a group channel is created with the first standard
+    /// channel of a downstream and dropped with its last one, and no load balancing hands
+    /// channels over between handlers. Only channels moved from outside the pool, through the
+    /// control service, are announced for now.
+    ///
+    /// Returns `false` if the downstream has no such channel.
+    pub fn channel_endpoint_changed(&self, downstream_id: usize, channel_id: u32) -> bool {
+        let known =
+            self.channel_manager_data
+                .super_safe_lock(|channel_manager_data| {
+                    channel_manager_data
+                        .downstream
+                        .get(&downstream_id)
+                        .is_some_and(|downstream| {
+                            downstream.downstream_data.super_safe_lock(|data| {
+                                data.standard_channels.contains_key(&channel_id)
+                                    || data.extended_channels.contains_key(&channel_id)
+                                    || data.group_channels.as_ref().is_some_and(|group| {
+                                        group.get_group_channel_id() == channel_id
+                                    })
+                            })
+                        })
+                });
+        if !known {
+            return false;
+        }
+        info!("Endpoint of channel {channel_id} of downstream {downstream_id} changed");
+        let endpoint_changed: RouteMessageTo<'static> = (
+            downstream_id,
+            CommonMessages::ChannelEndpointChanged(ChannelEndpointChanged { channel_id }),
+        )
+            .into();
+        let channel_manager_channel = self.channel_manager_channel.clone();
+        tokio::spawn(async move { endpoint_changed.forward(&channel_manager_channel).await });
+        true
+    }
+
+    /// Tears down the channel `channel_id` of `downstream_id`, closed for `reason`: removes it
+    /// along with its vardiff state, hashrate floor and share rate limiter, releases its
+    /// extranonce prefix and publishes a [`ChannelClosed`] event.
+    ///
+    /// Returns `false` if the downstream has no such channel.
+    fn teardown_channel(
+        &self,
+        channel_manager_data: &mut ChannelManagerData,
+        downstream_id: usize,
+        channel_id: u32,
+        reason: &str,
+    ) -> bool {
+        let Some(downstream) = channel_manager_data.downstream.get(&downstream_id) else {
+            return false;
+        };
+        let address = downstream.address;
+        let Some(channel) = downstream
+            .downstream_data
+            .super_safe_lock(|data| data.remove_channel(channel_id))
+        else {
+            return false;
+        };
+        channel_manager_data.reclaim_channel(downstream_id, &channel);
+        self.events
+            .publish(channel_closed(downstream_id, address, channel, reason));
+        true
+    }
+
+    /// Returns the channels of the connected downstreams, to be resumed after a restart.
+    pub fn saved_channels(&self) -> Vec<SavedChannel> {
+        let mut channels = Vec::new();
+        for downstream in self.downstreams() {
+            let ip = downstream.address.ip();
+            downstream.downstream_data.super_safe_lock(|data| {
+                for (channel_id, channel) in &data.standard_channels {
+                    channels.push(SavedChannel {
+                        ip,
+                        extended: false,
+                        channel_id: *channel_id,
+                        user_identity: channel.get_user_identity().to_string(),
+                        extranonce_prefix: channel.get_extranonce_prefix().to_vec(),
+                        nominal_hash_rate: channel.get_nominal_hashrate(),
+                        target: channel.get_target().to_le_bytes().to_vec(),
+                    });
+                }
+                for (channel_id, channel) in &data.extended_channels {
+                    channels.push(SavedChannel {
+                        ip,
+                        extended: true,
+                        channel_id: *channel_id,
+                        user_identity: channel.get_user_identity().to_string(),
+                        extranonce_prefix: channel.get_extranonce_prefix().to_vec(),
+                        nominal_hash_rate: channel.get_nominal_hashrate(),
+                        target: channel.get_target().to_le_bytes().to_vec(),
+                    });
+                }
+            });
+        }
+        channels
+    }
+
+    // Draws extranonce prefixes from `next` until one is not saved for a resumed channel.
+    fn fresh_extranonce_prefix<E>(
+        &self,
+        mut next: impl FnMut() -> Result<Vec<u8>, E>,
+    ) -> Result<Vec<u8>, E> {
+        loop {
+            let prefix = next()?;
+            if !self.sessions.is_reserved(&prefix) {
+                return Ok(prefix);
+            }
+        }
+    }
+
+    /// Returns the error code refusing a new channel to `downstream_id`, if it would exceed the
+    /// cap on the channels of a connection or of the connections from the same IP address.
+    ///
+    /// Must be called before locking the data of any downstream.
+    fn channel_limit_error(
+        &self,
+        channel_manager_data: &ChannelManagerData,
+        downstream_id: usize,
+    ) -> Option<&'static str> {
+        let downstream = channel_manager_data.downstream.get(&downstream_id)?;
+        let open_channels = |downstream: &Downstream| {
+            downstream
+                .downstream_data
+                .super_safe_lock(|data| data.standard_channels.len() + data.extended_channels.len())
+        };
+        if let Some(max) = self.max_channels_per_connection {
+            if open_channels(downstream) >= max {
+                return Some("too-many-channels-per-connection");
+            }
+        }
+        if let Some(max) = self.max_channels_per_ip {
+            let ip = downstream.address.ip();
+            let from_ip: usize = channel_manager_data
+                .downstream
+                .values()
+                .filter(|downstream| downstream.address.ip() == ip)
+                .map(open_channels)
+                .sum();
+            if from_ip >= max {
+                return Some("too-many-channels-per-ip");
+            }
+        }
+        None
+    }
+
+    /// Returns the pool signature of a channel opened while `template` is mined, variables
+    /// expanded.
+    fn pool_tag(&self, template: Option<&NewTemplate<'_>>) -> String {
+        let height = template
+            .and_then(|template| bip34_height(template.coinbase_prefix.inner_as_ref()))
+            .unwrap_or_default();
+        self.pool_signature
+            .expand(height, self.server_id, SystemTime::now())
+    }
+
+    /// Returns the nominal hashrate a new channel announcing `nominal_hash_rate` is opened at,
+    /// within the bounds of the initial difficulty.
+    fn initial_hashrate(&self, nominal_hash_rate: f32) -> f32 {
+        self.initial_difficulty
+            .clamp(nominal_hash_rate, self.shares_per_minute)
+    }
+
+    /// Returns the minimum nominal hashrate of the channels opened by `user_identity`, from the
+    /// first difficulty floor matching the identity or its account.
+    fn hashrate_floor(&self, user_identity: &str) -> Option<f32> {
+        let account = self.worker_identity.split(user_identity).account;
+        self.difficulty_floors
+            .iter()
+            .find(|floor| floor.matches(user_identity) || floor.matches(&account))
+            .map(|floor| floor.min_hashrate(self.shares_per_minute))
+    }
+
+    /// Returns the error code of the `OpenMiningChannelError` refusing a channel to
+    /// `user_identity`, if it does not match the pattern of the worker identities.
+    fn user_identity_error(&self, user_identity: &str) -> Option<&'static str> {
+        self.worker_identity
+            .parse(user_identity)
+            .is_none()
+            .then_some("unknown-user")
+    }
+
+    /// Returns the limiter of the shares submitted on a new channel, if enabled.
+    fn share_rate_limiter(&self) -> Option<ShareRateLimiter> {
+        self.share_rate_limit
+            .as_ref()
+            .map(|config| ShareRateLimiter::new(config, self.shares_per_minute))
+    }
+
+    /// Applies the share rate limit of `channel_id` to a share submitted by `downstream_id`.
+    ///
+    /// Once the limit is exceeded, the vardiff and the limiter forget the channel, which must then
+    /// be closed with [`Self::close_flooding_channel`].
+    fn limit_share_rate(
+        &self,
+        channel_manager_data: &mut ChannelManagerData,
+        downstream_id: usize,
+        channel_id: u32,
+    ) -> RateLimitOutcome {
+        let key: VardiffKey = (downstream_id, channel_id).into();
+        let Some(limiter) = channel_manager_data.share_rate_limiters.get_mut(&key) else {
+            return RateLimitOutcome::Allowed;
+        };
+        if limiter.bucket.try_acquire() {
+            limiter.violations = 0;
+            return RateLimitOutcome::Allowed;
+        }
+        limiter.violations += 1;
+        if limiter.violations <= limiter.max_violations {
+            return RateLimitOutcome::Refused;
+        }
+        channel_manager_data.share_rate_limiters.remove(&key);
+        channel_manager_data.vardiff.remove(&key);
+        channel_manager_data.vardiff_pins.remove(&key);
+        channel_manager_data.hashrate_floors.remove(&key);
+        RateLimitOutcome::Exceeded
+    }
+
+    /// Closes the channel of a share that exceeded its share rate limit, rejecting the share and
+    /// banning the IP address of the downstream if configured.
+    fn close_flooding_channel(
+        &self,
+        channel_manager_data: &mut ChannelManagerData,
+        downstream_id: usize,
+        address: SocketAddr,
+        event: ShareValidated,
+    ) -> Vec<RouteMessageTo<'static>> {
+        let channel_id = event.channel_id;
+        error!(
+            "Share rate limit repeatedly exceeded on channel {channel_id} of downstream {downstream_id}, closing it"
+        );
+        self.teardown_channel(
+            channel_manager_data,
+            downstream_id,
+            channel_id,
+            SHARE_RATE_LIMIT_EXCEEDED,
+        );
+        let ban_duration = self
+            .share_rate_limit
+            .as_ref()
+            .and_then(ShareRateLimitConfig::ban_duration);
+        if let Some(ban_duration) = ban_duration {
+            let ip = address.ip();
+            info!("Banning {ip} for {ban_duration:?} after a share flood");
+            if let Err(e) = self.banlist.ban(
+                BanTarget::Ip(ip.into()),
+                Some(ban_duration),
+                Some("share flood".to_string()),
+            ) {
+                warn!(error = ?e, "Failed to save the ban of {ip}");
+            }
+        }
+        let close_channel = CloseChannel {
+            channel_id,
+            reason_code: SHARE_RATE_LIMIT_EXCEEDED
+                .to_string()
+                .try_into()
+                .expect("reason code must be valid string"),
+        };
+        vec![
+            self.reject_share(downstream_id, event, ShareRejectReason::RateLimited),
+            (downstream_id, Mining::CloseChannel(close_channel)).into(),
+        ]
+    }
+
+    /// Counts and publishes a share of `downstream_id` rejected for `reason`, and builds the
+    /// `SubmitSharesError` answering it.
+    fn reject_share(
+        &self,
+        downstream_id: usize,
+        mut event: ShareValidated,
+        reason: ShareRejectReason,
+    ) -> RouteMessageTo<'static> {
+        error!(
+            "SubmitSharesError: downstream_id: {}, channel_id: {}, sequence_number: {}, error_code: {reason} ❌",
+            downstream_id, event.channel_id, event.sequence_number
+        );
+        self.share_rejects.record(reason);
+        let error = SubmitSharesError {
+            channel_id: event.channel_id,
+            sequence_number: event.sequence_number,
+            error_code: reason.error_code(),
+        };
+        event.error_code = Some(reason.as_str().to_string());
+        self.events.publish(event);
+        (downstream_id, Mining::SubmitSharesError(error)).into()
+    }
+
+    /// Fills the share `event` submitted on a channel of `user_identity` at `target` with the
+    /// difficulties known before its validation.
+    fn share_on_channel(
+        &self,
+        mut event: ShareValidated,
+        user_identity: &str,
+        target: &Target,
+    ) -> ShareValidated {
+        event.target_difficulty = Some(target.difficulty_float());
+        event.best_share_difficulty = self.best_shares.best(user_identity);
+        event
+    }
+
+    /// Records a share of `user_identity` accepted at `target`, whose hash is `share_hash` in
+    /// little endian, in the current round, the best shares and the worker statistics, and
+    /// publishes it, flagged if notable.
+    fn accept_share(
+        &self,
+        mut event: ShareValidated,
+        user_identity: &str,
+        target: &Target,
+        share_hash: [u8; 32],
+    ) {
+        let identity = self.worker_identity.split(user_identity);
+        let work = target.difficulty_float();
+        self.rounds
+            .add_share(user_identity, &identity.account, work);
+        let difficulty = Target::from_le_bytes(share_hash).difficulty_float();
+        let best = self.best_shares.record(user_identity, difficulty);
+        self.workers
+            .record(user_identity, &identity, work, difficulty);
+        event.user_identity = Some(user_identity.to_string());
+        event.account = Some(identity.account);
+        event.worker = identity.worker;
+        event.target_difficulty = Some(work);
+        event.share_difficulty = Some(difficulty);
+        event.best_share_difficulty = Some(best);
+        event.share_hash = Some(
+            share_hash
+                .iter()
+                .rev()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+        );
+        let notable = self
+            .notable_shares
+            .as_ref()
+            .and_then(|notable_shares| notable_shares.fraction(difficulty));
+        if let Some(fraction) = notable {
+            info!(
+                "Notable share of {user_identity} on channel {}: difficulty {difficulty}, {:.2}% of the network difficulty",
+                event.channel_id,
+                fraction * 100.0
+            );
+            event.notable = true;
+        }
+        self.events.publish(event);
+    }
+
+    /// Returns the amount paid to the pool by the serialized `coinbase` of a block, in satoshis.
+    fn block_reward(&self, coinbase: &[u8]) -> u64 {
+        let pool_script = self.coinbase_reward_script.script_pubkey();
+        match Transaction::consensus_decode(&mut &coinbase[..]) {
+            Ok(coinbase) => coinbase
+                .output
+                .iter()
+                .filter(|output| output.script_pubkey == pool_script)
+                .map(|output| output.value.to_sat())
+                .sum(),
+            Err(e) => {
+                warn!("Failed to decode the coinbase of a block found, its reward is unknown: {e}");
+                0
+            }
+        }
+    }
+
+    /// Closes the current round on a `block` found by an accepted share, records it in the
+    /// persisted events and publishes the block.
+    fn block_found(&self, block: BlockFound) {
+        self.persistence
+            .record(self.rounds.close(block.block_hash.clone(), block.reward));
+        self.events.publish(block);
+    }
+
     /// Starts the downstream server, and accepts new connection request.
     #[allow(clippy::too_many_arguments)]
     pub async fn start_downstream_server(
         self,
-        authority_public_key: Secp256k1PublicKey,
-        authority_secret_key: Secp256k1SecretKey,
-        cert_validity_sec: u64,
+        authority_keys: AuthorityKeys,
         listening_address: SocketAddr,
+        tcp: TcpOptions,
+        encryption: Encryption,
+        max_frame_size: Option<usize>,
+        idle_timeout: Option<Duration>,
+        handshake_timeout: Duration,
         task_manager: Arc<TaskManager>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         status_sender: Sender<Status>,
-        channel_manager_sender: Sender<(usize, Mining<'static>)>,
-        channel_manager_receiver: broadcast::Sender<(usize, Mining<'static>)>,
+        channel_manager_sender: Sender<(usize, SV2Frame)>,
     ) -> PoolResult<()> {
         info!("Starting downstream server at {listening_address}");
-        let server = TcpListener::bind(listening_address).await.map_err(|e| {
+        if encryption == Encryption::None {
+            warn!("Downstream connections are not encrypted, only use this on a trusted network");
+        }
+        let server = tcp.bind(listening_address).map_err(|e| {
             error!(error = ?e, "Failed to bind downstream server at {listening_address}");
-            e
+            PoolError::Bind(listening_address, e)
         })?;
 
         let mut shutdown_rx = notify_shutdown.subscribe();
 
         let task_manager_clone = task_manager.clone();
-        task_manager.spawn(async move {
+        task_manager.spawn_critical("downstream_server", async move {
 
             loop {
                 select! {
@@ -199,59 +836,107 @@ impl ChannelManager {
                     res = server.accept() => {
                         match res {
                             Ok((stream, socket_address)) => {
+                                if self.banlist.is_ip_banned(socket_address.ip()) {
+                                    info!(%socket_address, "Refusing connection from a banned address");
+                                    continue;
+                                }
+                                if self.draining.load(Ordering::Relaxed) {
+                                    info!(%socket_address, "Refusing connection while draining");
+                                    continue;
+                                }
                                 info!(%socket_address, "New downstream connection");
-                                let responder = match Responder::from_authority_kp(
-                                    &authority_public_key.into_bytes(),
-                                    &authority_secret_key.into_bytes(),
-                                    std::time::Duration::from_secs(cert_validity_sec),
-                                ) {
-                                    Ok(r) => r,
-                                    Err(e) => {
-                                        error!(error = ?e, "Failed to create responder");
-                                        continue;
-                                    }
-                                };
-                                let noise_stream = match NoiseTcpStream::<Message>::new(
-                                    stream,
-                                    HandshakeRole::Responder(responder),
-                                )
-                                .await
-                                {
-                                    Ok(ns) => ns,
-                                    Err(e) => {
-                                        error!(error = ?e, "Noise handshake failed");
-                                        continue;
-                                    }
-                                };
-
-                                let downstream_id = self
-                                    .channel_manager_data
-                                    .super_safe_lock(|data| data.downstream_id_factory.fetch_add(1, Ordering::SeqCst));
+                                if let Err(e) = tcp.configure(&stream) {
+                                    warn!(%socket_address, error = ?e, "Failed to set the TCP options of the connection");
+                                }
+                                let deadline = HandshakeDeadline::start(socket_address, handshake_timeout);
+                                let channel_manager = self.clone();
+                                let authority_keys = authority_keys.clone();
+                                let notify_shutdown = notify_shutdown.clone();
+                                let status_sender = status_sender.clone();
+                                let channel_manager_sender = channel_manager_sender.clone();
+                                let task_manager = task_manager_clone.clone();
+                                // The handshake runs on its own task, so that a stalling peer
+                                // does not hold back the other connections.
+                                task_manager_clone.spawn_named("downstream_handshake", async move {
+                                    let responder = match authority_keys.responder() {
+                                        Ok(r) => r,
+                                        Err(e) => {
+                                            error!(error = ?e, "Failed to create responder");
+                                            return;
+                                        }
+                                    };
+                                    let handshake = Sv2TcpStream::<Message>::new(
+                                        stream,
+                                        encryption,
+                                        HandshakeRole::Responder(responder),
+                                    );
+                                    let stream = match deadline.run(handshake).await {
+                                        Some(Ok(stream)) => stream.with_max_frame_size(max_frame_size),
+                                        Some(Err(e)) => {
+                                            error!(error = ?e, "Noise handshake failed");
+                                            return;
+                                        }
+                                        None => return,
+                                    };
 
+                                    let downstream_id = channel_manager
+                                        .channel_manager_data
+                                        .super_safe_lock(|data| data.downstream.allocate_id());
+                                    if encryption == Encryption::Noise {
+                                        authority_keys.certificate_issued(downstream_id);
+                                    }
 
-                                let downstream = Downstream::new(
-                                    downstream_id,
-                                    channel_manager_sender.clone(),
-                                    channel_manager_receiver.clone(),
-                                    noise_stream,
-                                    notify_shutdown.clone(),
-                                    task_manager_clone.clone(),
-                                    status_sender.clone(),
-                                );
+                                    let span = info_span!("downstream", connection_id = downstream_id);
+                                    let downstream = span.in_scope(|| Downstream::new(
+                                        downstream_id,
+                                        socket_address,
+                                        channel_manager_sender,
+                                        stream,
+                                        notify_shutdown.clone(),
+                                        task_manager.clone(),
+                                        status_sender.clone(),
+                                        idle_timeout,
+                                        channel_manager.setup_connection,
+                                        channel_manager.device_rules.clone(),
+                                        channel_manager.events.clone(),
+                                        channel_manager.downstream_channels,
+                                        channel_manager.share_acks.clone(),
+                                    ));
 
+                                    let shutdown = {
+                                        let notify_shutdown = notify_shutdown.clone();
+                                        ShutdownHandle::new(move || {
+                                            let _ = notify_shutdown.send(
+                                                ShutdownMessage::DownstreamShutdown(downstream_id),
+                                            );
+                                        })
+                                    };
+                                    channel_manager.channel_manager_data.super_safe_lock(|data| {
+                                        data.downstream.register(
+                                            downstream_id,
+                                            socket_address,
+                                            downstream.clone(),
+                                            shutdown,
+                                        );
+                                    });
+                                    channel_manager
+                                        .channel_manager_channel
+                                        .downstream_queues
+                                        .insert(downstream_id, downstream.outbound_queue());
+                                    channel_manager.events.publish(ConnectionOpened {
+                                        connection_id: downstream_id,
+                                        address: socket_address.to_string(),
+                                    });
 
-                                self.channel_manager_data.super_safe_lock(|data| {
-                                    data.downstream.insert(downstream_id, downstream.clone());
+                                    let setup = downstream
+                                        .start(notify_shutdown.clone(), status_sender, task_manager)
+                                        .instrument(span);
+                                    if deadline.run(setup).await.is_none() {
+                                        let _ = notify_shutdown
+                                            .send(ShutdownMessage::DownstreamShutdown(downstream_id));
+                                    }
                                 });
-
-                                downstream
-                                    .start(
-                                        notify_shutdown.clone(),
-                                        status_sender.clone(),
-                                        task_manager_clone.clone(),
-                                    )
-                                    .await;
-                                }
+                            }
 
                                 Err(e) => {
                                     error!(error = ?e, "Failed to accept new downstream connection");
@@ -279,7 +964,17 @@ impl ChannelManager {
         let status_sender = StatusSender::ChannelManager(status_sender);
         let mut shutdown_rx = notify_shutdown.subscribe();
 
-        task_manager.spawn(async move {
+        let channels = &self.channel_manager_channel;
+        task_manager.spawn_critical(
+            "job_activation_fanout",
+            channels.activations.clone().run(
+                channels.downstream_queues.clone(),
+                self.job_activations.clone(),
+                notify_shutdown.clone(),
+            ),
+        );
+
+        task_manager.spawn_critical("channel_manager", async move {
             let cm = self.clone();
             let vardiff_future = self.run_vardiff_loop();
             tokio::pin!(vardiff_future);
@@ -316,7 +1011,7 @@ impl ChannelManager {
                             break;
                         }
                     }
-                    res = cm_downstreams.handle_downstream_mining_message() => {
+                    res = cm_downstreams.handle_downstream_mining_message(&notify_shutdown) => {
                         if let Err(e) = res {
                             error!(error = ?e, "Error handling Downstreams message");
                             handle_error(&status_sender, e).await;
@@ -332,42 +1027,114 @@ impl ChannelManager {
     // Removes a Downstream entry from the ChannelManager’s state.
     //
     // Given a `downstream_id`, this method:
-    // 1. Removes the corresponding Downstream from the `downstream` map.
-    // 2. Removes the channels of the corresponding Downstream from `vardiff` map.
+    // 1. Removes the corresponding Downstream from the `downstream` registry.
+    // 2. Removes the channels of the corresponding Downstream from `vardiff`, `vardiff_pins`,
+    //    `hashrate_floors` and `share_rate_limiters`, and releases their extranonce prefixes.
+    // 3. Publishes the closing of its channels and of the connection.
     #[allow(clippy::result_large_err)]
     fn remove_downstream(&self, downstream_id: usize) -> PoolResult<()> {
-        self.channel_manager_data.super_safe_lock(|cm_data| {
-            cm_data.downstream.remove(&downstream_id);
+        let (removed, channels) = self.channel_manager_data.super_safe_lock(|cm_data| {
+            let removed = cm_data.downstream.remove(&downstream_id);
+            let channels = removed
+                .as_ref()
+                .map(|downstream| {
+                    downstream
+                        .downstream_data
+                        .super_safe_lock(|data| data.remove_all_channels())
+                })
+                .unwrap_or_default();
+            for channel in &channels {
+                cm_data.reclaim_channel(downstream_id, channel);
+            }
             cm_data
                 .vardiff
                 .retain(|key, _| key.downstream_id != downstream_id);
+            cm_data
+                .vardiff_pins
+                .retain(|key| key.downstream_id != downstream_id);
+            cm_data
+                .hashrate_floors
+                .retain(|key, _| key.downstream_id != downstream_id);
+            cm_data
+                .share_rate_limiters
+                .retain(|key, _| key.downstream_id != downstream_id);
+            (removed, channels)
         });
+        self.channel_manager_channel
+            .downstream_queues
+            .remove(downstream_id);
+        if let Some(downstream) = removed {
+            for channel in channels {
+                self.events.publish(channel_closed(
+                    downstream_id,
+                    downstream.address,
+                    channel,
+                    CONNECTION_CLOSED,
+                ));
+            }
+            self.events.publish(ConnectionClosed {
+                connection_id: downstream_id,
+                address: downstream.address.to_string(),
+            });
+        }
         Ok(())
     }
 
     // Handles messages received from the TP subsystem.
     //
     // This method listens for incoming frames on the `tp_receiver` channel.
-    // - If the frame contains a TemplateDistribution message, it forwards it to the template
-    //   distribution message handler.
+    // - If the frame contains a TemplateDistribution message, it decodes it in place and passes it
+    //   to the template distribution message handler.
     // - If the frame contains any unsupported message type, an error is returned.
     async fn handle_template_provider_message(&mut self) -> PoolResult<()> {
-        if let Ok(message) = self.channel_manager_channel.tp_receiver.recv().await {
-            self.handle_template_distribution_message_from_server(None, message)
-                .await?;
+        if let Ok(mut frame) = self.channel_manager_channel.tp_receiver.recv().await {
+            let Some(message_type) = frame.get_header().map(|h| h.msg_type()) else {
+                return Ok(());
+            };
+            self.handle_template_distribution_message_frame_from_server(
+                None,
+                message_type,
+                frame.payload(),
+            )
+            .await?;
         }
         Ok(())
     }
 
-    async fn handle_downstream_mining_message(&mut self) -> PoolResult<()> {
-        if let Ok((downstream_id, message)) = self
+    // Handles the mining frames forwarded by the downstreams, decoded in place so that their
+    // payload is never copied.
+    //
+    // A payload that cannot be decoded is the fault of the downstream that sent it: its
+    // connection is closed, and the channel manager keeps serving the others.
+    async fn handle_downstream_mining_message(
+        &mut self,
+        notify_shutdown: &broadcast::Sender<ShutdownMessage>,
+    ) -> PoolResult<()> {
+        if let Ok((downstream_id, mut frame)) = self
             .channel_manager_channel
             .downstream_receiver
             .recv()
             .await
         {
-            self.handle_mining_message_from_client(Some(downstream_id), message)
-                .await?;
+            let Some(message_type) = frame.get_header().map(|h| h.msg_type()) else {
+                return Ok(());
+            };
+            let result = self
+                .handle_mining_message_frame_from_client(
+                    Some(downstream_id),
+                    message_type,
+                    frame.payload(),
+                )
+                .instrument(info_span!("downstream", connection_id = downstream_id))
+                .await;
+            match result {
+                Err(PoolError::Parser(e)) => {
+                    warn!(%downstream_id, error = ?e, "Malformed mining message from downstream");
+                    let _ =
+                        notify_shutdown.send(ShutdownMessage::DownstreamShutdown(downstream_id));
+                }
+                result => result?,
+            }
         }
 
         Ok(())
@@ -379,6 +1146,7 @@ impl ChannelManager {
         channel_id: u32,
         channel_state: &mut ExtendedChannel<'static, DefaultJobStore<ExtendedJob<'static>>>,
         vardiff_state: &mut VardiffState,
+        hashrate_floor: Option<f32>,
         updates: &mut Vec<RouteMessageTo>,
     ) {
         let (hashrate, target, shares_per_minute) = (
@@ -396,6 +1164,10 @@ impl ChannelManager {
         let Some(new_hashrate) = new_hashrate_opt else {
             return;
         };
+        let new_hashrate = hashrate_floor.map_or(new_hashrate, |floor| new_hashrate.max(floor));
+        if new_hashrate == hashrate {
+            return;
+        }
 
         match channel_state.update_channel(new_hashrate, None) {
             Ok(()) => {
@@ -424,6 +1196,7 @@ impl ChannelManager {
         channel_id: u32,
         channel: &mut StandardChannel<'static, DefaultJobStore<StandardJob<'static>>>,
         vardiff_state: &mut VardiffState,
+        hashrate_floor: Option<f32>,
         updates: &mut Vec<RouteMessageTo>,
     ) {
         let hashrate = channel.get_nominal_hashrate();
@@ -436,6 +1209,11 @@ impl ChannelManager {
             return;
         };
 
+        let new_hashrate_opt = new_hashrate_opt
+            .map(|new_hashrate| {
+                hashrate_floor.map_or(new_hashrate, |floor| new_hashrate.max(floor))
+            })
+            .filter(|new_hashrate| *new_hashrate != hashrate);
         if let Some(new_hashrate) = new_hashrate_opt {
             match channel.update_channel(new_hashrate, None) {
                 Ok(()) => {
@@ -466,6 +1244,7 @@ impl ChannelManager {
     // # Purpose
     // - Executes the vardiff cycle every 60 seconds for all downstreams.
     // - Delegates to [`Self::run_vardiff`] on each tick.
+    // - Logs the traffic of every downstream on each tick.
     async fn run_vardiff_loop(&self) -> PoolResult<()> {
         let mut ticker = tokio::time::interval(std::time::Duration::from_secs(60));
         loop {
@@ -475,13 +1254,52 @@ impl ChannelManager {
             if let Err(e) = self.run_vardiff().await {
                 error!(error = ?e, "Vardiff iteration failed");
             }
+            self.log_downstream_traffic();
+        }
+    }
+
+    // Logs the frame and byte counters of every downstream connection, so that chatty or stuck
+    // downstreams stand out, along with the occupancy of its outbound buffer pool and the high
+    // watermarks of its channels.
+    fn log_downstream_traffic(&self) {
+        let downstreams = self.channel_manager_data.super_safe_lock(|data| {
+            data.downstream
+                .iter()
+                .map(|(id, downstream)| {
+                    (
+                        *id,
+                        downstream.traffic.snapshot(),
+                        downstream.frame_pool.snapshot(),
+                        downstream.outbound_queue().dropped(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        });
+        for (downstream_id, traffic, frame_pool, outbound_dropped) in downstreams {
+            info!(
+                downstream_id,
+                frames_received = traffic.frames_received,
+                bytes_received = traffic.bytes_received,
+                frames_sent = traffic.frames_sent,
+                bytes_sent = traffic.bytes_sent,
+                frames_received_per_minute = traffic.frames_received_per_minute(),
+                last_received_secs_ago = ?traffic.last_received_secs_ago,
+                frame_pool_reuse_ratio = frame_pool.reuse_ratio(),
+                frame_pool_allocated = frame_pool.allocated,
+                frame_pool_saturated = frame_pool.saturated,
+                inbound_high_watermark = traffic.inbound_high_watermark,
+                outbound_high_watermark = traffic.outbound_high_watermark,
+                outbound_dropped,
+                "Downstream traffic"
+            );
         }
     }
 
     // Runs vardiff across **all channels** and generates updates.
     //
     // # Purpose
-    // - Iterates through all downstream channels (both standard and extended).
+    // - Iterates through all downstream channels (both standard and extended), except the
+    //   pinned ones.
     // - Runs vardiff for each channel and collects the resulting updates.
     // - Propagates difficulty changes to downstreams and also sends an `UpdateChannel` message
     //   upstream if applicable.
@@ -490,8 +1308,15 @@ impl ChannelManager {
         self.channel_manager_data
             .super_safe_lock(|channel_manager_data| {
                 for (vardiff_key, vardiff_state) in channel_manager_data.vardiff.iter_mut() {
+                    if channel_manager_data.vardiff_pins.contains(vardiff_key) {
+                        continue;
+                    }
                     let downstream_id = &vardiff_key.downstream_id;
                     let channel_id = &vardiff_key.channel_id;
+                    let hashrate_floor = channel_manager_data
+                        .hashrate_floors
+                        .get(vardiff_key)
+                        .copied();
 
                     let Some(downstream) = channel_manager_data.downstream.get_mut(downstream_id)
                     else {
@@ -504,6 +1329,7 @@ impl ChannelManager {
                                 *channel_id,
                                 standard_channel,
                                 vardiff_state,
+                                hashrate_floor,
                                 &mut messages,
                             );
                         }
@@ -513,6 +1339,7 @@ impl ChannelManager {
                                 *channel_id,
                                 extended_channel,
                                 vardiff_state,
+                                hashrate_floor,
                                 &mut messages,
                             );
                         }
@@ -535,6 +1362,10 @@ pub enum RouteMessageTo<'a> {
     TemplateProvider(TemplateDistribution<'a>),
     /// Route to a specific downstream client by ID, along with its mining message.
     Downstream((usize, Mining<'a>)),
+    /// Route to a specific downstream client by ID, along with its common message.
+    Common((usize, CommonMessages<'a>)),
+    /// Route to a specific downstream client by ID, along with a message already serialized.
+    Shared((usize, SharedFrame)),
 }
 
 impl<'a> From<TemplateDistribution<'a>> for RouteMessageTo<'a> {
@@ -549,20 +1380,152 @@ impl<'a> From<(usize, Mining<'a>)> for RouteMessageTo<'a> {
     }
 }
 
+impl<'a> From<(usize, CommonMessages<'a>)> for RouteMessageTo<'a> {
+    fn from(value: (usize, CommonMessages<'a>)) -> Self {
+        Self::Common(value)
+    }
+}
+
 impl RouteMessageTo<'_> {
     pub async fn forward(self, channel_manager_channel: &ChannelManagerChannel) {
         match self {
-            RouteMessageTo::Downstream((downstream_id, message)) => {
-                _ = channel_manager_channel
-                    .downstream_sender
-                    .send((downstream_id, message.into_static()));
-            }
             RouteMessageTo::TemplateProvider(message) => {
                 _ = channel_manager_channel
                     .tp_sender
                     .send(message.into_static())
                     .await;
             }
+            message => {
+                if let Some((downstream_id, frame)) = message.into_shared() {
+                    channel_manager_channel
+                        .downstream_queues
+                        .send(downstream_id, &frame)
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Returns the downstream a message goes to and its serialized frame, `None` for the
+    /// messages to the Template Provider and those failing to serialize.
+    pub fn into_shared(self) -> Option<(usize, SharedFrame)> {
+        match self {
+            RouteMessageTo::Downstream((downstream_id, message)) => {
+                match SharedFrame::new(message) {
+                    Ok(frame) => Some((downstream_id, frame)),
+                    Err(e) => {
+                        error!(
+                            ?e,
+                            "Failed to serialize message for downstream {downstream_id}"
+                        );
+                        None
+                    }
+                }
+            }
+            RouteMessageTo::Common((downstream_id, message)) => {
+                match SharedFrame::common(message) {
+                    Ok(frame) => Some((downstream_id, frame)),
+                    Err(e) => {
+                        error!(
+                            ?e,
+                            "Failed to serialize message for downstream {downstream_id}"
+                        );
+                        None
+                    }
+                }
+            }
+            RouteMessageTo::Shared(shared) => Some(shared),
+            RouteMessageTo::TemplateProvider(_) => None,
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{
+        difficulty_hashrate, AuthorityConfig, ConnectionConfig, TemplateProviderConfig,
+    };
+    use stratum_apps::{
+        banlist::BanListConfig,
+        key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+    };
+
+    /// Returns the channel manager of a pool without any downstream.
+    pub(crate) async fn channel_manager() -> ChannelManager {
+        let public_key: Secp256k1PublicKey = "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72"
+            .parse()
+            .unwrap();
+        let secret_key: Secp256k1SecretKey = "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n"
+            .parse()
+            .unwrap();
+        let config = PoolConfig::new(
+            ConnectionConfig::new(
+                "127.0.0.1:34254".parse().unwrap(),
+                3600,
+                "Stratum V2 SRI Pool".to_string(),
+            ),
+            TemplateProviderConfig::new("127.0.0.1:8442".to_string(), None),
+            AuthorityConfig::new(public_key, secret_key),
+            CoinbaseRewardScript::from_descriptor(
+                "addr(tb1qa0sm0hxzj0x25rh8gw5xlzwlsfvvyz8u96w3p8)",
+            )
+            .unwrap(),
+            6.0,
+            1,
+            0,
+        );
+        let (tp_sender, _) = async_channel::unbounded();
+        let (_, tp_receiver) = async_channel::unbounded();
+        let (_, downstream_receiver) = async_channel::unbounded();
+        ChannelManager::new(
+            config,
+            tp_sender,
+            tp_receiver,
+            downstream_receiver,
+            Vec::new(),
+            Arc::new(BanList::new(&BanListConfig::default()).unwrap()),
+            Arc::new(ShareRejectCounters::new()),
+            Arc::new(LatencyStats::new()),
+            Arc::new(TemplateStats::new(None)),
+            Arc::new(ShareAckStats::new(None)),
+            Arc::new(BestShares::new()),
+            Arc::new(Rounds::new()),
+            Arc::new(Workers::new()),
+            Arc::new(ChannelUpdates::new()),
+            Persistence::disabled(),
+            EventBus::default(),
+            Arc::new(Sessions::default()),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn endpoint_changes_of_unknown_channels_are_not_sent() {
+        let channel_manager = channel_manager().await;
+        assert!(!channel_manager.channel_endpoint_changed(1, 1));
+    }
+
+    #[tokio::test]
+    async fn hashrate_floor_comes_from_the_first_matching_floor() {
+        let mut channel_manager = channel_manager().await;
+        channel_manager.difficulty_floors = serde_json::from_value(serde_json::json!([
+            { "user_identity": "farm.rig*", "min_difficulty": 2000.0 },
+            { "user_identity": "farm", "min_difficulty": 1000.0 },
+        ]))
+        .unwrap();
+
+        let floor = |difficulty| difficulty_hashrate(difficulty, channel_manager.shares_per_minute);
+        assert_eq!(
+            channel_manager.hashrate_floor("farm.rig1"),
+            Some(floor(2000.0))
+        );
+        // Matched through its account.
+        assert_eq!(
+            channel_manager.hashrate_floor("farm.s19"),
+            Some(floor(1000.0))
+        );
+        assert_eq!(channel_manager.hashrate_floor("other.rig1"), None);
+    }
+}
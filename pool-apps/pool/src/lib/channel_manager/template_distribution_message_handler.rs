@@ -1,15 +1,28 @@
-use std::sync::atomic::Ordering;
-
-use stratum_apps::stratum_core::{
-    bitcoin::Amount, channels_sv2::outputs::deserialize_outputs,
-    handlers_sv2::HandleTemplateDistributionMessagesFromServerAsync,
-    mining_sv2::SetNewPrevHash as SetNewPrevHashMp, parsers_sv2::Mining,
-    template_distribution_sv2::*,
+use std::{sync::atomic::Ordering, time::Instant};
+
+use stratum_apps::{
+    events::TemplateReceived,
+    stratum_core::{
+        binary_sv2::Sv2Option,
+        bitcoin::Amount,
+        channels_sv2::outputs::deserialize_outputs,
+        handlers_sv2::HandleTemplateDistributionMessagesFromServerAsync,
+        mining_sv2::{
+            NewExtendedMiningJob, SetNewPrevHash as SetNewPrevHashMp,
+            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH, MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+        },
+        parsers_sv2::Mining,
+        template_distribution_sv2::*,
+    },
+    unix_time,
 };
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
 use crate::{
-    channel_manager::{ChannelManager, RouteMessageTo},
+    channel_manager::{
+        activation::JobActivation, job_distribution::SharedFrames, ChannelManager, RouteMessageTo,
+    },
+    config::FutureJobMinNtime,
     error::PoolError,
 };
 
@@ -22,13 +35,31 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
         msg: NewTemplate<'_>,
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
+        let received = Instant::now();
+        self.template_stats.received();
+        self.events.publish(TemplateReceived {
+            template_id: msg.template_id,
+            future_template: msg.future_template,
+            coinbase_value: msg.coinbase_tx_value_remaining,
+        });
 
         let messages = self.channel_manager_data.super_safe_lock(|channel_manager_data| {
             if msg.future_template {
                 channel_manager_data.last_future_template = Some(msg.clone().into_static());
             }
+            // Future jobs over the cap are added to the channels, but only sent to downstreams
+            // along with the `SetNewPrevHash` activating them.
+            let send_future_jobs = !msg.future_template
+                || self.future_jobs_ahead.is_none_or(|max| channel_manager_data.future_templates_sent < max);
+            if send_future_jobs {
+                channel_manager_data.future_templates_sent += usize::from(msg.future_template);
+            } else {
+                debug!("Holding back the future jobs of template {} until activated", msg.template_id);
+                channel_manager_data.held_future_templates.insert(msg.template_id);
+            }
 
             let mut messages: Vec<RouteMessageTo> = Vec::new();
+            let mut shared_frames = SharedFrames::default();
             let mut coinbase_output = deserialize_outputs(channel_manager_data.coinbase_outputs.clone()).expect("deserialization failed");
             coinbase_output[0].value = Amount::from_sat(msg.coinbase_tx_value_remaining);
 
@@ -76,7 +107,9 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                                     let standard_job_id = standard_channel.get_future_template_to_job_id().get(&msg.template_id).expect("job_id must exist");
                                     let standard_job = standard_channel.get_future_jobs().get(standard_job_id).expect("standard job must exist");
                                     let standard_job_message = standard_job.get_job_message();
-                                    messages.push((*downstream_id, Mining::NewMiningJob(standard_job_message.clone())).into());
+                                    if send_future_jobs {
+                                        messages.push((*downstream_id, Mining::NewMiningJob(standard_job_message.clone())).into());
+                                    }
                                 }
                                 if let Some(ref group_channel_job) = group_channel_job {
                                     if let Err(e) = standard_channel.on_new_template(msg.clone().into_static(), coinbase_output.clone()) {
@@ -87,9 +120,8 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                                     .on_group_channel_job(group_channel_job.clone());
                                 }
                             }
-                            if let Some(group_channel_job) = group_channel_job {
-                                let job_message = group_channel_job.get_job_message();
-                                messages.push((*downstream_id, Mining::NewExtendedMiningJob(job_message.clone())).into());
+                            if let Some(group_channel_job) = group_channel_job.filter(|_| send_future_jobs) {
+                                messages.extend(Self::group_job_message(&mut shared_frames, *downstream_id, group_channel_job.get_job_message()));
                             }
 
                             for (channel_id, extended_channel) in data.extended_channels.iter_mut() {
@@ -109,7 +141,9 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
 
                                 let extended_job_message = extended_job.get_job_message();
 
-                                messages.push((*downstream_id,Mining::NewExtendedMiningJob(extended_job_message.clone())).into());
+                                if send_future_jobs {
+                                    messages.push((*downstream_id,Mining::NewExtendedMiningJob(extended_job_message.clone())).into());
+                                }
                             }
                         }
                         false => {
@@ -133,8 +167,7 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                                 }
                             }
                             if let Some(group_channel_job) = group_channel_job {
-                                let job_message = group_channel_job.get_job_message();
-                                messages.push((*downstream_id, Mining::NewExtendedMiningJob(job_message.clone())).into());
+                                messages.extend(Self::group_job_message(&mut shared_frames, *downstream_id, group_channel_job.get_job_message()));
                             }
 
                             for (channel_id, extended_channel) in data.extended_channels.iter_mut() {
@@ -164,6 +197,12 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
         for message in messages {
             message.forward(&self.channel_manager_channel).await;
         }
+        let latency = received.elapsed();
+        self.template_stats.distributed(latency);
+        debug!(
+            "Distributed the jobs of template {} in {latency:?}",
+            msg.template_id
+        );
 
         Ok(())
     }
@@ -192,11 +231,25 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
         msg: SetNewPrevHash<'_>,
     ) -> Result<(), Self::Error> {
         info!("Received: {}", msg);
-
+        let received = Instant::now();
+        let min_ntime = match self.future_jobs_min_ntime {
+            FutureJobMinNtime::Template => msg.header_timestamp,
+            FutureJobMinNtime::Now => msg.header_timestamp.max(unix_time::now_secs() as u32),
+        };
+
+        if let Some(notable_shares) = &self.notable_shares {
+            notable_shares.set_network_target(msg.n_bits);
+        }
         let messages = self.channel_manager_data.super_safe_lock(|data| {
             data.last_new_prev_hash = Some(msg.clone().into_static());
+            // The jobs of a held back future template were never sent, downstreams learn them
+            // right before the `SetNewPrevHash` activating them.
+            let held = data.held_future_templates.contains(&msg.template_id);
+            data.held_future_templates.clear();
+            data.future_templates_sent = 0;
 
             let mut messages: Vec<RouteMessageTo> = vec![];
+            let mut shared_frames = SharedFrames::default();
 
             for (downstream_id, downstream) in data.downstream.iter_mut() {
                 let downstream_messages = downstream.downstream_data.super_safe_lock(|data| {
@@ -204,25 +257,35 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                     if let Some(ref mut group_channel) = data.group_channels {
                         _ = group_channel.on_set_new_prev_hash(msg.clone().into_static());
                         let group_channel_id = group_channel.get_group_channel_id();
-                        let activated_group_job_id = group_channel
+                        let activated_group_job = group_channel
                             .get_active_job()
-                            .expect("active job must exist")
-                            .get_job_id();
-
-                        let set_new_prev_hash_message = SetNewPrevHashMp {
-                            channel_id: group_channel_id,
-                            job_id: activated_group_job_id,
-                            prev_hash: msg.prev_hash.clone(),
-                            min_ntime: msg.header_timestamp,
-                            nbits: msg.n_bits,
-                        };
-                        messages.push(
-                            (
+                            .expect("active job must exist");
+                        let activated_group_job_id = activated_group_job.get_job_id();
+                        if held {
+                            let mut job_message = activated_group_job.get_job_message().clone();
+                            job_message.min_ntime = Sv2Option::new(None);
+                            messages.extend(Self::group_job_message(
+                                &mut shared_frames,
                                 *downstream_id,
-                                Mining::SetNewPrevHash(set_new_prev_hash_message),
-                            )
-                                .into(),
-                        );
+                                &job_message,
+                            ));
+                        }
+
+                        messages.extend(shared_frames.route(
+                            *downstream_id,
+                            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+                            group_channel_id,
+                            activated_group_job_id,
+                            || {
+                                Mining::SetNewPrevHash(SetNewPrevHashMp {
+                                    channel_id: group_channel_id,
+                                    job_id: activated_group_job_id,
+                                    prev_hash: msg.prev_hash.clone(),
+                                    min_ntime,
+                                    nbits: msg.n_bits,
+                                })
+                            },
+                        ));
                     }
 
                     for (channel_id, standard_channel) in data.standard_channels.iter_mut() {
@@ -235,15 +298,23 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                         // if yes, there's no group channel, so we need to send the SetNewPrevHashMp
                         // to each standard channel
                         if data.group_channels.is_none() {
-                            let activated_standard_job_id = standard_channel
+                            let activated_standard_job = standard_channel
                                 .get_active_job()
-                                .expect("active job must exist")
-                                .get_job_id();
+                                .expect("active job must exist");
+                            let activated_standard_job_id = activated_standard_job.get_job_id();
+                            if held {
+                                let mut job_message =
+                                    activated_standard_job.get_job_message().clone();
+                                job_message.min_ntime = Sv2Option::new(None);
+                                messages.push(
+                                    (*downstream_id, Mining::NewMiningJob(job_message)).into(),
+                                );
+                            }
                             let set_new_prev_hash_message = SetNewPrevHashMp {
                                 channel_id: *channel_id,
                                 job_id: activated_standard_job_id,
                                 prev_hash: msg.prev_hash.clone(),
-                                min_ntime: msg.header_timestamp,
+                                min_ntime,
                                 nbits: msg.n_bits,
                             };
                             messages.push(
@@ -268,15 +339,23 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
                             continue;
                         }
 
-                        let activated_extended_job_id = extended_channel
+                        let activated_extended_job = extended_channel
                             .get_active_job()
-                            .expect("active job must exist")
-                            .get_job_id();
+                            .expect("active job must exist");
+                        let activated_extended_job_id = activated_extended_job.get_job_id();
+                        if held {
+                            let mut job_message =
+                                activated_extended_job.get_job_message().clone();
+                            job_message.min_ntime = Sv2Option::new(None);
+                            messages.push(
+                                (*downstream_id, Mining::NewExtendedMiningJob(job_message)).into(),
+                            );
+                        }
                         let set_new_prev_hash_message = SetNewPrevHashMp {
                             channel_id: *channel_id,
                             job_id: activated_extended_job_id,
                             prev_hash: msg.prev_hash.clone(),
-                            min_ntime: msg.header_timestamp,
+                            min_ntime,
                             nbits: msg.n_bits,
                         };
                         messages.push(
@@ -297,10 +376,38 @@ impl HandleTemplateDistributionMessagesFromServerAsync for ChannelManager {
             messages
         });
 
-        for message in messages {
-            message.forward(&self.channel_manager_channel).await;
-        }
+        // Serialized here, the frames are only copied to the downstreams by the fan-out task.
+        let activation = JobActivation {
+            template_id: msg.template_id,
+            received,
+            frames: messages
+                .into_iter()
+                .filter_map(RouteMessageTo::into_shared)
+                .collect(),
+        };
+        self.channel_manager_channel
+            .activations
+            .activate(activation)
+            .await;
 
         Ok(())
     }
 }
+
+impl ChannelManager {
+    // Routes the job of a group channel to `downstream_id`. Group channels at the same job get the
+    // same message, serialized once for all of them.
+    fn group_job_message(
+        shared_frames: &mut SharedFrames,
+        downstream_id: usize,
+        job_message: &NewExtendedMiningJob<'_>,
+    ) -> Option<RouteMessageTo<'static>> {
+        shared_frames.route(
+            downstream_id,
+            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+            job_message.channel_id,
+            job_message.job_id,
+            || Mining::NewExtendedMiningJob(job_message.clone()),
+        )
+    }
+}
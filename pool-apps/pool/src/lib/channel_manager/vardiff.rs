@@ -0,0 +1,239 @@
+//! Inspection and manual overrides of the vardiff of the channels.
+//!
+//! The vardiff state of every channel (its current target, the rate of the shares submitted since
+//! its target was last adjusted, and when) is served under `vardiff` on the monitoring endpoint.
+//! An operator debugging a miner whose difficulty oscillates can pin the difficulty of its
+//! channel, which the vardiff then leaves alone until it is unpinned, or nudge it once by a
+//! factor, the vardiff carrying on from the new target. Both send the downstream a `SetTarget` and
+//! restart the measurement of the share rate of the channel.
+
+use serde::Serialize;
+use stratum_apps::{
+    stratum_core::{
+        bitcoin::Target,
+        channels_sv2::{Vardiff, VardiffState},
+        mining_sv2::SetTarget,
+        parsers_sv2::Mining,
+    },
+    unix_time,
+};
+use tracing::info;
+
+use crate::{
+    channel_manager::{ChannelManager, ChannelManagerData, RouteMessageTo},
+    config::difficulty_hashrate,
+    downstream::DownstreamData,
+    error::PoolResult,
+    utils::VardiffKey,
+};
+
+/// Manual override of the vardiff of a channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VardiffOverride {
+    /// Sets the difficulty of the channel, which the vardiff no longer adjusts.
+    Pin(f64),
+    /// Multiplies the difficulty of the channel once, e.g. by 0.5 or 2.
+    Nudge(f64),
+    /// Hands the channel back to the vardiff.
+    Unpin,
+}
+
+/// Vardiff state of a channel, as served on the monitoring endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct VardiffStatus {
+    pub connection_id: usize,
+    pub channel_id: u32,
+    pub user_identity: String,
+    pub extended: bool,
+    /// Nominal hashrate the target of the channel is derived from.
+    pub nominal_hash_rate: f32,
+    /// Difficulty of the current target of the channel.
+    pub difficulty: f64,
+    /// Shares per minute submitted since the last adjustment.
+    pub shares_per_minute: f64,
+    /// Shares per minute the vardiff aims for.
+    pub target_shares_per_minute: f32,
+    /// Unix time in seconds of the last adjustment, or of the opening of the channel.
+    pub last_adjustment_secs: u64,
+    /// Whether the vardiff is kept from adjusting the channel.
+    pub pinned: bool,
+}
+
+// Target of a standard or extended channel.
+struct ChannelTarget {
+    user_identity: String,
+    extended: bool,
+    nominal_hash_rate: f32,
+    target: Target,
+    shares_per_minute: f32,
+}
+
+fn channel_target(data: &DownstreamData, channel_id: u32) -> Option<ChannelTarget> {
+    if let Some(channel) = data.standard_channels.get(&channel_id) {
+        return Some(ChannelTarget {
+            user_identity: channel.get_user_identity().to_string(),
+            extended: false,
+            nominal_hash_rate: channel.get_nominal_hashrate(),
+            target: *channel.get_target(),
+            shares_per_minute: channel.get_shares_per_minute(),
+        });
+    }
+    let channel = data.extended_channels.get(&channel_id)?;
+    Some(ChannelTarget {
+        user_identity: channel.get_user_identity().to_string(),
+        extended: true,
+        nominal_hash_rate: channel.get_nominal_hashrate(),
+        target: *channel.get_target(),
+        shares_per_minute: channel.get_shares_per_minute(),
+    })
+}
+
+// Sets the nominal hashrate of the standard or extended channel `channel_id`, returning its new
+// target, or `None` if there is no such channel.
+fn set_nominal_hashrate(
+    data: &mut DownstreamData,
+    channel_id: u32,
+    nominal_hash_rate: f32,
+) -> PoolResult<Option<Target>> {
+    if let Some(channel) = data.standard_channels.get_mut(&channel_id) {
+        channel.update_channel(nominal_hash_rate, None)?;
+        return Ok(Some(*channel.get_target()));
+    }
+    if let Some(channel) = data.extended_channels.get_mut(&channel_id) {
+        channel.update_channel(nominal_hash_rate, None)?;
+        return Ok(Some(*channel.get_target()));
+    }
+    Ok(None)
+}
+
+// Sets the target of the standard or extended channel `channel_id` to the difficulty returned by
+// `difficulty` for its current one, returning the new target, or `None` if there is no such
+// channel.
+fn retarget(
+    data: &mut DownstreamData,
+    channel_id: u32,
+    difficulty: &dyn Fn(f64) -> f64,
+) -> PoolResult<Option<Target>> {
+    let Some(channel) = channel_target(data, channel_id) else {
+        return Ok(None);
+    };
+    let difficulty = difficulty(channel.target.difficulty_float());
+    let nominal_hash_rate = difficulty_hashrate(difficulty, channel.shares_per_minute);
+    set_nominal_hashrate(data, channel_id, nominal_hash_rate)
+}
+
+impl ChannelManagerData {
+    // Vardiff state of the channel of `key`, if it has one.
+    fn vardiff_status(&self, key: &VardiffKey, now_secs: u64) -> Option<VardiffStatus> {
+        let vardiff = self.vardiff.get(key)?;
+        let channel = self
+            .downstream
+            .get(&key.downstream_id)?
+            .downstream_data
+            .super_safe_lock(|data| channel_target(data, key.channel_id))?;
+        let last_adjustment_secs = vardiff.last_update_timestamp();
+        let elapsed_secs = now_secs.saturating_sub(last_adjustment_secs).max(1);
+        Some(VardiffStatus {
+            connection_id: key.downstream_id,
+            channel_id: key.channel_id,
+            user_identity: channel.user_identity,
+            extended: channel.extended,
+            nominal_hash_rate: channel.nominal_hash_rate,
+            difficulty: channel.target.difficulty_float(),
+            shares_per_minute: vardiff.shares_since_last_update() as f64 * 60.0
+                / elapsed_secs as f64,
+            target_shares_per_minute: channel.shares_per_minute,
+            last_adjustment_secs,
+            pinned: self.vardiff_pins.contains(key),
+        })
+    }
+}
+
+impl ChannelManager {
+    /// Returns the vardiff state of every channel, by connection and channel id.
+    pub fn vardiff_status(&self) -> Vec<VardiffStatus> {
+        let now_secs = unix_time::now_secs();
+        let mut status: Vec<VardiffStatus> = self.channel_manager_data.super_safe_lock(|data| {
+            data.vardiff
+                .keys()
+                .filter_map(|key| data.vardiff_status(key, now_secs))
+                .collect()
+        });
+        status.sort_by_key(|status| (status.connection_id, status.channel_id));
+        status
+    }
+
+    /// Applies `action` to the vardiff of the channel `channel_id` of `downstream_id`, sending the
+    /// downstream a `SetTarget` if its target changed, and returns the resulting vardiff state.
+    ///
+    /// A pinned difficulty is not bounded by the difficulty floors. Returns `None` if the
+    /// downstream has no such channel.
+    pub fn override_vardiff(
+        &self,
+        downstream_id: usize,
+        channel_id: u32,
+        action: VardiffOverride,
+    ) -> PoolResult<Option<VardiffStatus>> {
+        let key: VardiffKey = (downstream_id, channel_id).into();
+        let outcome = self.channel_manager_data.super_safe_lock(
+            |data| -> PoolResult<Option<(VardiffStatus, Option<Target>)>> {
+                if !data.vardiff.contains_key(&key) {
+                    return Ok(None);
+                }
+                let Some(downstream) = data.downstream.get(&downstream_id) else {
+                    return Ok(None);
+                };
+                let retarget_channel = |difficulty: &dyn Fn(f64) -> f64| {
+                    downstream
+                        .downstream_data
+                        .super_safe_lock(|downstream_data| {
+                            retarget(downstream_data, channel_id, difficulty)
+                        })
+                };
+                let target = match action {
+                    VardiffOverride::Pin(difficulty) => retarget_channel(&|_: f64| difficulty)?,
+                    VardiffOverride::Nudge(factor) => {
+                        retarget_channel(&|current: f64| current * factor)?
+                    }
+                    VardiffOverride::Unpin => None,
+                };
+                if target.is_some() {
+                    // The share rate is measured again from the new target.
+                    data.vardiff
+                        .insert((downstream_id, channel_id).into(), VardiffState::new()?);
+                    if let VardiffOverride::Pin(_) = action {
+                        data.vardiff_pins.insert((downstream_id, channel_id).into());
+                    }
+                }
+                if action == VardiffOverride::Unpin {
+                    data.vardiff_pins.remove(&key);
+                }
+                Ok(data
+                    .vardiff_status(&key, unix_time::now_secs())
+                    .map(|status| (status, target)))
+            },
+        )?;
+        let Some((status, target)) = outcome else {
+            return Ok(None);
+        };
+        info!(
+            "Vardiff of channel {channel_id} of downstream {downstream_id} overridden ({action:?}), difficulty {}",
+            status.difficulty
+        );
+        if let Some(target) = target {
+            let set_target: RouteMessageTo<'static> = (
+                downstream_id,
+                Mining::SetTarget(SetTarget {
+                    channel_id,
+                    maximum_target: target.to_le_bytes().into(),
+                }),
+            )
+                .into();
+            // Queued from a task of its own, as the control service overrides the vardiff from
+            // synchronous handlers.
+            let channel_manager_channel = self.channel_manager_channel.clone();
+            tokio::spawn(async move { set_target.forward(&channel_manager_channel).await });
+        }
+        Ok(Some(status))
+    }
+}
@@ -0,0 +1,159 @@
+//! ## Channel Updates Module
+//!
+//! Validation and history of the `UpdateChannel` messages of the downstreams. An `UpdateChannel`
+//! announces a new nominal hashrate, from which the pool derives the target of the channel. Once
+//! a channel submitted enough shares for its actual hashrate to be estimated, a new nominal
+//! hashrate more than [`MAX_HASHRATE_RATIO`] times off the [`ObservedHashrate`] is refused with
+//! `invalid-nominal-hashrate`, so that a downstream cannot have its difficulty brought far below
+//! its actual hashrate and flood the pool with shares. The last updates, applied or refused, are
+//! kept in [`ChannelUpdates`] and served under `channel_updates` on the monitoring endpoint.
+
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use stratum_apps::{custom_mutex::Mutex, unix_time};
+
+/// Factor by which a new nominal hashrate may differ from the observed one.
+pub const MAX_HASHRATE_RATIO: f32 = 10.0;
+/// Number of shares from which the hashrate of a channel is estimated.
+const MIN_OBSERVED_SHARES: u32 = 10;
+/// Duration of the windows the hashrate of a channel is estimated over.
+const OBSERVATION_WINDOW: Duration = Duration::from_secs(300);
+/// Number of updates kept in the history.
+const HISTORY_SIZE: usize = 256;
+
+// Shares accepted on a channel during a window.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    start: Instant,
+    shares: u32,
+    work: f64,
+}
+
+/// Hashrate of a channel, estimated from the work of the shares it had accepted over the current
+/// and the previous observation windows.
+#[derive(Debug)]
+pub struct ObservedHashrate {
+    current: Window,
+    // Previous window, along with its duration.
+    previous: Option<(Window, Duration)>,
+}
+
+impl ObservedHashrate {
+    /// Starts observing a channel opened at `now`.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            current: Window {
+                start: now,
+                shares: 0,
+                work: 0.0,
+            },
+            previous: None,
+        }
+    }
+
+    /// Records a share accepted at `now` at a target of difficulty `work`.
+    pub fn record(&mut self, work: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.current.start);
+        if elapsed >= OBSERVATION_WINDOW {
+            self.previous = Some((self.current, elapsed));
+            self.current = Window {
+                start: now,
+                shares: 0,
+                work: 0.0,
+            };
+        }
+        self.current.shares += 1;
+        self.current.work += work;
+    }
+
+    /// Returns the estimated hashrate at `now`, unless too few shares were accepted.
+    pub fn estimate(&self, now: Instant) -> Option<f32> {
+        let (mut shares, mut work) = (self.current.shares, self.current.work);
+        let mut elapsed = now.saturating_duration_since(self.current.start);
+        if let Some((previous, duration)) = &self.previous {
+            shares += previous.shares;
+            work += previous.work;
+            elapsed += *duration;
+        }
+        if shares < MIN_OBSERVED_SHARES || elapsed.is_zero() {
+            return None;
+        }
+        Some((work * 2f64.powi(32) / elapsed.as_secs_f64()) as f32)
+    }
+
+    /// Returns the estimated hashrate at `now` if `nominal_hash_rate` is more than
+    /// [`MAX_HASHRATE_RATIO`] times off it.
+    pub fn refuses(&self, nominal_hash_rate: f32, now: Instant) -> Option<f32> {
+        let observed = self.estimate(now)?;
+        let too_low = nominal_hash_rate * MAX_HASHRATE_RATIO < observed;
+        let too_high = nominal_hash_rate > observed * MAX_HASHRATE_RATIO;
+        (too_low || too_high).then_some(observed)
+    }
+}
+
+/// `UpdateChannel` received from a downstream, as served on the monitoring endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelUpdate {
+    pub connection_id: usize,
+    pub channel_id: u32,
+    pub user_identity: String,
+    /// Nominal hashrate of the channel before the update.
+    pub previous_hash_rate: f32,
+    /// Nominal hashrate announced by the update.
+    pub requested_hash_rate: f32,
+    /// Hashrate estimated from the shares of the channel, if enough were accepted.
+    pub observed_hash_rate: Option<f32>,
+    /// Difficulty of the target of the channel after the update.
+    pub difficulty: f64,
+    /// Error code of the `UpdateChannelError` refusing the update, if it was refused.
+    pub error_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RecordedUpdate {
+    timestamp_ms: u64,
+    #[serde(flatten)]
+    update: ChannelUpdate,
+}
+
+/// Last `UpdateChannel` messages received from the downstreams, newest last.
+#[derive(Debug)]
+pub struct ChannelUpdates {
+    updates: Mutex<VecDeque<RecordedUpdate>>,
+}
+
+impl ChannelUpdates {
+    pub fn new() -> Self {
+        Self {
+            updates: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Records `update`, forgetting the oldest one once the history is full.
+    pub fn record(&self, update: ChannelUpdate) {
+        let update = RecordedUpdate {
+            timestamp_ms: unix_time::now_ms(),
+            update,
+        };
+        self.updates.super_safe_lock(|updates| {
+            if updates.len() == HISTORY_SIZE {
+                updates.pop_front();
+            }
+            updates.push_back(update);
+        });
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<RecordedUpdate> {
+        self.updates
+            .super_safe_lock(|updates| updates.iter().cloned().collect())
+    }
+}
+
+impl Default for ChannelUpdates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -11,28 +11,86 @@
 use std::{
     net::SocketAddr,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
+#[cfg(feature = "control")]
+use stratum_apps::control::ControlConfig;
 use stratum_apps::{
-    config_helpers::CoinbaseRewardScript,
-    key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+    banlist::BanListConfig,
+    config_helpers::{logging::LoggingConfig, BitcoinNetwork, CoinbaseRewardScript},
+    events::DeviceInfo,
+    key_utils::{AuthorityPublicKeys, Secp256k1PublicKey, Secp256k1SecretKey},
+    monitoring::MonitoringConfig,
+    network_helpers::{tcp::TcpOptions, transport::Encryption},
+    persistence::PersistenceConfig,
+    rate_limit::TokenBucketConfig,
+    runtime::{IoChannelsConfig, DEFAULT_HANDSHAKE_TIMEOUT},
     stratum_core::bitcoin::{Amount, TxOut},
+    worker_identity::WorkerIdentityParser,
 };
 
 /// Configuration for the Pool, including connection, authority, and coinbase settings.
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct PoolConfig {
+    network: Option<BitcoinNetwork>,
     listen_address: SocketAddr,
+    #[serde(default)]
+    listen_encryption: Encryption,
+    #[serde(default)]
+    tcp: TcpOptions,
+    max_frame_size: Option<usize>,
+    downstream_idle_timeout_secs: Option<u64>,
+    handshake_timeout_secs: Option<u64>,
+    max_channels_per_connection: Option<usize>,
+    max_channels_per_ip: Option<usize>,
+    #[serde(default)]
+    setup_connection: SetupConnectionPolicy,
+    #[serde(default)]
+    device_rules: Vec<DeviceRule>,
+    #[serde(default)]
+    downstream_channels: IoChannelsConfig,
+    monitoring: Option<MonitoringConfig>,
+    #[cfg(feature = "control")]
+    control: Option<ControlConfig>,
+    #[serde(default)]
+    banlist: BanListConfig,
+    persistence: Option<PersistenceConfig>,
     tp_address: String,
-    tp_authority_public_key: Option<Secp256k1PublicKey>,
+    tp_authority_public_key: Option<AuthorityPublicKeys>,
+    jds_authority_public_key: Option<AuthorityPublicKeys>,
     authority_public_key: Secp256k1PublicKey,
     authority_secret_key: Secp256k1SecretKey,
     cert_validity_sec: u64,
+    #[serde(default)]
+    renew_expired_certificates: bool,
     coinbase_reward_script: CoinbaseRewardScript,
     pool_signature: String,
     shares_per_minute: f32,
     share_batch_size: usize,
+    future_jobs_ahead: Option<usize>,
+    #[serde(default)]
+    future_jobs_min_ntime: FutureJobMinNtime,
+    template_staleness_secs: Option<u64>,
+    share_ack_slo_ms: Option<u64>,
+    #[serde(default)]
+    initial_difficulty: InitialDifficultyConfig,
+    #[serde(default)]
+    difficulty_floors: Vec<DifficultyFloor>,
+    #[serde(default)]
+    worker_identity: WorkerIdentityParser,
+    share_rate_limit: Option<ShareRateLimitConfig>,
+    session_resumption: Option<SessionResumptionConfig>,
+    extranonce_allocator: Option<ExtranonceAllocatorConfig>,
+    accounting: Option<AccountingConfig>,
+    lifetime: Option<LifetimeConfig>,
+    coordination: Option<CoordinationConfig>,
+    notable_shares: Option<NotableSharesConfig>,
+    payout_addresses: Option<PathBuf>,
+    payments: Option<PaymentsConfig>,
     log_file: Option<PathBuf>,
+    #[serde(default)]
+    logging: LoggingConfig,
     server_id: u16,
 }
 
@@ -52,21 +110,71 @@ impl PoolConfig {
         server_id: u16,
     ) -> Self {
         Self {
+            network: None,
             listen_address: pool_connection.listen_address,
+            listen_encryption: Encryption::default(),
+            tcp: TcpOptions::default(),
+            max_frame_size: None,
+            downstream_idle_timeout_secs: None,
+            handshake_timeout_secs: None,
+            max_channels_per_connection: None,
+            max_channels_per_ip: None,
+            setup_connection: SetupConnectionPolicy::default(),
+            device_rules: Vec::new(),
+            downstream_channels: IoChannelsConfig::default(),
+            monitoring: None,
+            #[cfg(feature = "control")]
+            control: None,
+            banlist: BanListConfig::default(),
+            persistence: None,
             tp_address: template_provider.address,
-            tp_authority_public_key: template_provider.authority_public_key,
+            tp_authority_public_key: template_provider.authority_public_key.map(Into::into),
+            jds_authority_public_key: None,
             authority_public_key: authority_config.public_key,
             authority_secret_key: authority_config.secret_key,
             cert_validity_sec: pool_connection.cert_validity_sec,
+            renew_expired_certificates: false,
             coinbase_reward_script,
             pool_signature: pool_connection.signature,
             shares_per_minute,
             share_batch_size,
+            future_jobs_ahead: None,
+            future_jobs_min_ntime: FutureJobMinNtime::default(),
+            template_staleness_secs: None,
+            share_ack_slo_ms: None,
+            initial_difficulty: InitialDifficultyConfig::default(),
+            difficulty_floors: Vec::new(),
+            worker_identity: WorkerIdentityParser::default(),
+            share_rate_limit: None,
+            session_resumption: None,
+            extranonce_allocator: None,
+            accounting: None,
+            lifetime: None,
+            coordination: None,
+            notable_shares: None,
+            payout_addresses: None,
+            payments: None,
             log_file: None,
+            logging: LoggingConfig::default(),
             server_id,
         }
     }
 
+    /// Returns the Bitcoin network the Pool is deployed on, if set.
+    pub fn network(&self) -> Option<BitcoinNetwork> {
+        self.network
+    }
+
+    /// Checks the coinbase reward script and the Template Provider address against the network,
+    /// if set.
+    pub fn check_network(&self) -> Result<(), String> {
+        let Some(network) = self.network else {
+            return Ok(());
+        };
+        network.check_coinbase_reward_script(&self.coinbase_reward_script)?;
+        network.check_template_provider_address(&self.tp_address)
+    }
+
     /// Returns the coinbase output.
     pub fn coinbase_reward_script(&self) -> &CoinbaseRewardScript {
         &self.coinbase_reward_script
@@ -77,6 +185,77 @@ impl PoolConfig {
         &self.listen_address
     }
 
+    /// Returns the encryption of the downstream listener.
+    pub fn listen_encryption(&self) -> Encryption {
+        self.listen_encryption
+    }
+
+    /// Returns the options of the TCP sockets of the downstream listener and of the Template
+    /// Provider connection.
+    pub fn tcp(&self) -> &TcpOptions {
+        &self.tcp
+    }
+
+    /// Returns the maximum size of a frame received from a downstream, if any.
+    pub fn max_frame_size(&self) -> Option<usize> {
+        self.max_frame_size
+    }
+
+    /// Returns how long a downstream may stay silent before being disconnected, if set.
+    pub fn downstream_idle_timeout(&self) -> Option<Duration> {
+        self.downstream_idle_timeout_secs.map(Duration::from_secs)
+    }
+
+    /// Returns how long an accepted downstream may take to complete its Noise handshake and
+    /// `SetupConnection` before being disconnected.
+    pub fn handshake_timeout(&self) -> Duration {
+        self.handshake_timeout_secs
+            .map_or(DEFAULT_HANDSHAKE_TIMEOUT, Duration::from_secs)
+    }
+
+    /// Returns how many channels a downstream connection may have open at once, if capped.
+    pub fn max_channels_per_connection(&self) -> Option<usize> {
+        self.max_channels_per_connection
+    }
+
+    /// Returns how many channels the downstreams connected from the same IP address may have
+    /// open at once, if capped.
+    pub fn max_channels_per_ip(&self) -> Option<usize> {
+        self.max_channels_per_ip
+    }
+
+    /// Returns the protocol versions and `SetupConnection` flags accepted from downstreams.
+    pub fn setup_connection(&self) -> &SetupConnectionPolicy {
+        &self.setup_connection
+    }
+
+    /// Returns the capacities of the channels of the downstream connections, and what happens
+    /// when a downstream reads too slowly to keep its outbound channel from filling up.
+    pub fn downstream_channels(&self) -> &IoChannelsConfig {
+        &self.downstream_channels
+    }
+
+    /// Returns the configuration of the HTTP endpoint serving the pool health, if enabled.
+    pub fn monitoring(&self) -> Option<&MonitoringConfig> {
+        self.monitoring.as_ref()
+    }
+
+    /// Returns the configuration of the gRPC control service, if enabled.
+    #[cfg(feature = "control")]
+    pub fn control(&self) -> Option<&ControlConfig> {
+        self.control.as_ref()
+    }
+
+    /// Returns the ban list configuration.
+    pub fn banlist(&self) -> &BanListConfig {
+        &self.banlist
+    }
+
+    /// Returns the configuration of the backend the submitted shares are recorded to, if any.
+    pub fn persistence(&self) -> Option<&PersistenceConfig> {
+        self.persistence.as_ref()
+    }
+
     /// Returns the authority public key.
     pub fn authority_public_key(&self) -> &Secp256k1PublicKey {
         &self.authority_public_key
@@ -92,19 +271,35 @@ impl PoolConfig {
         self.cert_validity_sec
     }
 
+    /// Returns whether sessions are disconnected once their certificate expired, so that they
+    /// reconnect under a new one.
+    pub fn renew_expired_certificates(&self) -> bool {
+        self.renew_expired_certificates
+    }
+
     /// Returns the Pool signature.
     pub fn pool_signature(&self) -> &String {
         &self.pool_signature
     }
 
-    /// Return the Template Provider authority public key.
-    pub fn tp_authority_public_key(&self) -> Option<&Secp256k1PublicKey> {
+    /// Return the Template Provider authority public keys.
+    pub fn tp_authority_public_key(&self) -> Option<&AuthorityPublicKeys> {
         self.tp_authority_public_key.as_ref()
     }
 
-    /// Returns the Template Provider address.
-    pub fn tp_address(&self) -> &String {
-        &self.tp_address
+    /// Returns the authority public keys of the Job Declarator Server signing the mining job
+    /// tokens of custom jobs, if the tokens are verified.
+    pub fn jds_authority_public_key(&self) -> Option<&AuthorityPublicKeys> {
+        self.jds_authority_public_key.as_ref()
+    }
+
+    /// Returns the Template Provider address, on the default port of the network if it has no
+    /// port. Unix socket addresses (`unix:<path>`) are returned as is.
+    pub fn tp_address(&self) -> String {
+        match self.network {
+            Some(network) => network.template_provider_address(&self.tp_address),
+            None => self.tp_address.clone(),
+        }
     }
 
     /// Returns the share batch size.
@@ -122,6 +317,97 @@ impl PoolConfig {
         self.shares_per_minute
     }
 
+    /// Returns how many future jobs are sent to downstreams ahead of each `SetNewPrevHash`, if
+    /// capped. The jobs over the cap are only sent once activated.
+    pub fn future_jobs_ahead(&self) -> Option<usize> {
+        self.future_jobs_ahead
+    }
+
+    /// Returns how the `min_ntime` of the `SetNewPrevHash` activating future jobs is chosen.
+    pub fn future_jobs_min_ntime(&self) -> FutureJobMinNtime {
+        self.future_jobs_min_ntime
+    }
+
+    /// Returns how long the pool may go without a new template before warning that its
+    /// templates are stale, if set.
+    pub fn template_staleness(&self) -> Option<Duration> {
+        self.template_staleness_secs.map(Duration::from_secs)
+    }
+
+    /// Returns the latency the p99 of the share acknowledgements is expected to stay under, if
+    /// set.
+    pub fn share_ack_slo(&self) -> Option<Duration> {
+        self.share_ack_slo_ms.map(Duration::from_millis)
+    }
+
+    /// Returns the bounds of the difficulty new channels are opened at.
+    pub fn initial_difficulty(&self) -> &InitialDifficultyConfig {
+        &self.initial_difficulty
+    }
+
+    /// Returns the rules applied to the devices described by the `SetupConnection` of the
+    /// downstreams, in order.
+    pub fn device_rules(&self) -> &[DeviceRule] {
+        &self.device_rules
+    }
+
+    /// Returns the minimum difficulties of the channels opened by matching user identities.
+    pub fn difficulty_floors(&self) -> &[DifficultyFloor] {
+        &self.difficulty_floors
+    }
+
+    /// Returns how user identities are split into an account and a worker.
+    pub fn worker_identity(&self) -> &WorkerIdentityParser {
+        &self.worker_identity
+    }
+
+    /// Returns the per-channel limit on submitted shares, if enabled.
+    pub fn share_rate_limit(&self) -> Option<&ShareRateLimitConfig> {
+        self.share_rate_limit.as_ref()
+    }
+
+    /// Returns where the channels are saved on shutdown to be resumed after a restart, if
+    /// enabled.
+    pub fn session_resumption(&self) -> Option<&SessionResumptionConfig> {
+        self.session_resumption.as_ref()
+    }
+
+    /// Returns the state file the extranonce prefixes are leased from, if enabled.
+    pub fn extranonce_allocator(&self) -> Option<&ExtranonceAllocatorConfig> {
+        self.extranonce_allocator.as_ref()
+    }
+
+    /// Returns the file the current round is snapshotted to and restored from, if enabled.
+    pub fn accounting(&self) -> Option<&AccountingConfig> {
+        self.accounting.as_ref()
+    }
+
+    /// Returns the state file the lifetime statistics are saved to, if enabled.
+    pub fn lifetime(&self) -> Option<&LifetimeConfig> {
+        self.lifetime.as_ref()
+    }
+
+    /// Returns the shared backend coordinating the pool instances, if configured.
+    pub fn coordination(&self) -> Option<&CoordinationConfig> {
+        self.coordination.as_ref()
+    }
+
+    /// Returns the threshold of the notable shares, if configured.
+    pub fn notable_shares(&self) -> Option<&NotableSharesConfig> {
+        self.notable_shares.as_ref()
+    }
+
+    /// Returns the file mapping user identities to the addresses their payouts are exported to,
+    /// if set.
+    pub fn payout_addresses(&self) -> Option<&Path> {
+        self.payout_addresses.as_deref()
+    }
+
+    /// Returns the wallet paying the balances owed to the users, if configured.
+    pub fn payments(&self) -> Option<&PaymentsConfig> {
+        self.payments.as_ref()
+    }
+
     /// Change TP address.
     pub fn set_tp_address(&mut self, tp_address: String) {
         self.tp_address = tp_address;
@@ -138,6 +424,11 @@ impl PoolConfig {
         self.log_file.as_deref()
     }
 
+    /// Returns the format and filter of the logs.
+    pub fn logging(&self) -> &LoggingConfig {
+        &self.logging
+    }
+
     /// Returns the server id.
     pub fn server_id(&self) -> u16 {
         self.server_id
@@ -151,6 +442,568 @@ impl PoolConfig {
     }
 }
 
+/// `min_ntime` of the `SetNewPrevHash` activating future jobs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FutureJobMinNtime {
+    /// The header timestamp of the template, as sent by the Template Provider.
+    #[default]
+    Template,
+    /// The current time, if later than the header timestamp of the template, so that miners do
+    /// not start from an ntime that went stale while the future job was waiting.
+    Now,
+}
+
+/// Mining protocol versions and `SetupConnection` flags the pool accepts from downstreams.
+///
+/// A `SetupConnection` whose version range does not intersect `min_version..=max_version`, or
+/// which sets a flag the pool does not accept, is refused with a `SetupConnectionError`. By
+/// default only version 2 is accepted, along with every flag of the mining protocol.
+/// `REQUIRES_WORK_SELECTION` is never accepted by a pool built without the `jd` feature.
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct SetupConnectionPolicy {
+    min_version: u16,
+    max_version: u16,
+    standard_jobs: bool,
+    work_selection: bool,
+    version_rolling: bool,
+}
+
+impl Default for SetupConnectionPolicy {
+    fn default() -> Self {
+        Self {
+            min_version: 2,
+            max_version: 2,
+            standard_jobs: true,
+            work_selection: true,
+            version_rolling: true,
+        }
+    }
+}
+
+impl SetupConnectionPolicy {
+    /// `REQUIRES_STANDARD_JOBS` flag of a mining protocol `SetupConnection`.
+    pub const REQUIRES_STANDARD_JOBS: u32 = 1;
+    /// `REQUIRES_WORK_SELECTION` flag of a mining protocol `SetupConnection`.
+    pub const REQUIRES_WORK_SELECTION: u32 = 1 << 1;
+    /// `REQUIRES_VERSION_ROLLING` flag of a mining protocol `SetupConnection`.
+    pub const REQUIRES_VERSION_ROLLING: u32 = 1 << 2;
+
+    /// Returns the lowest accepted protocol version.
+    pub fn min_version(&self) -> u16 {
+        self.min_version
+    }
+
+    /// Returns the highest accepted protocol version.
+    pub fn max_version(&self) -> u16 {
+        self.max_version
+    }
+
+    /// Returns the highest accepted version within `min_version..=max_version`, if any.
+    pub fn negotiate_version(&self, min_version: u16, max_version: u16) -> Option<u16> {
+        let version = max_version.min(self.max_version);
+        (version >= min_version.max(self.min_version)).then_some(version)
+    }
+
+    /// Returns whether downstreams may select their work, sending custom jobs.
+    pub fn work_selection(&self) -> bool {
+        cfg!(feature = "jd") && self.work_selection
+    }
+
+    /// Returns the mask of the accepted flags.
+    pub fn accepted_flags(&self) -> u32 {
+        [
+            (self.standard_jobs, Self::REQUIRES_STANDARD_JOBS),
+            (self.work_selection(), Self::REQUIRES_WORK_SELECTION),
+            (self.version_rolling, Self::REQUIRES_VERSION_ROLLING),
+        ]
+        .into_iter()
+        .filter(|(accepted, _)| *accepted)
+        .fold(0, |mask, (_, flag)| mask | flag)
+    }
+
+    /// Returns the flags of `flags` which are not accepted, zero if all are.
+    pub fn unsupported_flags(&self, flags: u32) -> u32 {
+        flags & !self.accepted_flags()
+    }
+}
+
+/// Minimum difficulty of the shares of the channels whose user identity matches a pattern.
+///
+/// Keeps known large farms from being started at the difficulty of their announced hashrate and
+/// from flooding the pool with shares until the vardiff catches up.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct DifficultyFloor {
+    user_identity: String,
+    min_difficulty: f64,
+}
+
+impl DifficultyFloor {
+    /// Returns the pattern matched against user identities, where `*` matches any sequence of
+    /// characters.
+    pub fn user_identity(&self) -> &str {
+        &self.user_identity
+    }
+
+    /// Returns the minimum share difficulty.
+    pub fn min_difficulty(&self) -> f64 {
+        self.min_difficulty
+    }
+
+    /// Returns whether `user_identity` matches the pattern.
+    pub fn matches(&self, user_identity: &str) -> bool {
+        matches_pattern(&self.user_identity, user_identity)
+    }
+
+    /// Returns the nominal hashrate at which a channel submitting `shares_per_minute` shares is
+    /// given a target of `min_difficulty`.
+    pub fn min_hashrate(&self, shares_per_minute: f32) -> f32 {
+        difficulty_hashrate(self.min_difficulty, shares_per_minute)
+    }
+}
+
+/// What the pool does with a downstream whose device matches a [`DeviceRule`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceAction {
+    /// The connection is accepted, with a warning logged.
+    Warn,
+    /// The connection is refused with a `SetupConnectionError`.
+    Reject,
+}
+
+/// Rule matching the devices described by the `SetupConnection` of the downstreams, e.g. to refuse
+/// a firmware version known to be broken.
+///
+/// Each of `vendor`, `hardware_version`, `firmware` and `device_id` is an optional pattern, where
+/// `*` matches any sequence of characters: a device matches the rule if it matches every pattern
+/// set. The first rule a device matches applies.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct DeviceRule {
+    vendor: Option<String>,
+    hardware_version: Option<String>,
+    firmware: Option<String>,
+    device_id: Option<String>,
+    action: DeviceAction,
+}
+
+impl DeviceRule {
+    /// Returns what is done with the matching devices.
+    pub fn action(&self) -> DeviceAction {
+        self.action
+    }
+
+    /// Returns whether the rule sets at least one pattern, a rule without any matching every
+    /// device.
+    pub fn has_patterns(&self) -> bool {
+        [
+            &self.vendor,
+            &self.hardware_version,
+            &self.firmware,
+            &self.device_id,
+        ]
+        .into_iter()
+        .any(Option::is_some)
+    }
+
+    /// Returns whether `device` matches every pattern of the rule.
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        [
+            (&self.vendor, &device.vendor),
+            (&self.hardware_version, &device.hardware_version),
+            (&self.firmware, &device.firmware),
+            (&self.device_id, &device.device_id),
+        ]
+        .into_iter()
+        .all(|(pattern, value)| {
+            pattern
+                .as_deref()
+                .is_none_or(|pattern| matches_pattern(pattern, value))
+        })
+    }
+}
+
+// Returns whether `value` matches `pattern`, where `*` matches any sequence of characters.
+fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = value.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(suffix) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(suffix)
+}
+
+/// Bounds of the difficulty a new channel is opened at.
+///
+/// A channel starts at the target matching the `nominal_hash_rate` of its `OpenMiningChannel`,
+/// clamped between `min_difficulty` and `max_difficulty`, so that a miner announcing a bogus
+/// hashrate (e.g. zero) does not start far from its actual one. Set in an `[initial_difficulty]`
+/// table, both bounds being optional.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct InitialDifficultyConfig {
+    min_difficulty: Option<f64>,
+    max_difficulty: Option<f64>,
+}
+
+impl InitialDifficultyConfig {
+    /// Returns the minimum difficulty of new channels, if bounded.
+    pub fn min_difficulty(&self) -> Option<f64> {
+        self.min_difficulty
+    }
+
+    /// Returns the maximum difficulty of new channels, if bounded.
+    pub fn max_difficulty(&self) -> Option<f64> {
+        self.max_difficulty
+    }
+
+    /// Returns the nominal hashrate a channel announcing `nominal_hash_rate` is opened at, given
+    /// the difficulty bounds at `shares_per_minute`. A hashrate which is not a number is raised to
+    /// the minimum.
+    pub fn clamp(&self, nominal_hash_rate: f32, shares_per_minute: f32) -> f32 {
+        let hash_rate = self.min_difficulty.map_or(nominal_hash_rate, |min| {
+            nominal_hash_rate.max(difficulty_hashrate(min, shares_per_minute))
+        });
+        self.max_difficulty.map_or(hash_rate, |max| {
+            hash_rate.min(difficulty_hashrate(max, shares_per_minute))
+        })
+    }
+}
+
+/// Returns the nominal hashrate at which a channel submitting `shares_per_minute` shares is given
+/// a target of `difficulty`.
+pub(crate) fn difficulty_hashrate(difficulty: f64, shares_per_minute: f32) -> f32 {
+    (difficulty * 2f64.powi(32) * shares_per_minute as f64 / 60.0) as f32
+}
+
+/// Per-channel limit on the shares submitted, far above the rate the vardiff aims for.
+///
+/// Each channel gets a token bucket refilled at `rate_multiplier` times `shares_per_minute`.
+/// Shares over the limit are rejected with `rate-limited`, and the channel is closed once
+/// `max_violations` consecutive shares have been rejected, the IP address of the downstream being
+/// also banned for `ban_secs` if set. This protects share validation from buggy or malicious
+/// firmware. Enabled by a `[share_rate_limit]` table, whose missing fields take their default.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct ShareRateLimitConfig {
+    rate_multiplier: f64,
+    burst: u32,
+    max_violations: u32,
+    ban_secs: Option<u64>,
+}
+
+impl Default for ShareRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            rate_multiplier: 20.0,
+            burst: 100,
+            max_violations: 100,
+            ban_secs: None,
+        }
+    }
+}
+
+impl ShareRateLimitConfig {
+    /// Returns the token bucket applied to the shares of a channel expected to submit
+    /// `shares_per_minute` shares.
+    pub fn bucket(&self, shares_per_minute: f32) -> TokenBucketConfig {
+        TokenBucketConfig::new(
+            self.rate_multiplier * shares_per_minute as f64 / 60.0,
+            self.burst,
+        )
+    }
+
+    /// Returns the number of consecutive rejected shares after which a channel is closed.
+    pub fn max_violations(&self) -> u32 {
+        self.max_violations
+    }
+
+    /// Returns how long the IP address of a downstream whose channel was closed is banned, if
+    /// it is.
+    pub fn ban_duration(&self) -> Option<Duration> {
+        self.ban_secs.map(Duration::from_secs)
+    }
+}
+
+/// File the channels of the downstreams are saved to on a graceful shutdown, and resumed from by
+/// the channels reopened within `max_age_secs` of it, see the `sessions` module.
+///
+/// Enabled by a `[session_resumption]` table.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct SessionResumptionConfig {
+    path: PathBuf,
+    #[serde(default = "SessionResumptionConfig::default_max_age")]
+    max_age_secs: u64,
+}
+
+impl SessionResumptionConfig {
+    fn default_max_age() -> u64 {
+        300
+    }
+
+    /// Returns the file the channels are saved to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns how long after the shutdown the saved channels can be resumed.
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_secs)
+    }
+}
+
+/// State file the extranonce prefixes are leased from, so that the next run of the pool does not
+/// hand out the prefixes of this one, see the `extranonce` module.
+///
+/// Enabled by an `[extranonce_allocator]` table.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ExtranonceAllocatorConfig {
+    path: PathBuf,
+}
+
+impl ExtranonceAllocatorConfig {
+    /// Returns the state file of the allocator.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// File the work of the current round is snapshotted to every `snapshot_interval_secs`, whenever
+/// a round closes and on shutdown, and restored from at startup, see the `rounds` module.
+///
+/// Enabled by an `[accounting]` table.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct AccountingConfig {
+    path: PathBuf,
+    #[serde(default = "AccountingConfig::default_snapshot_interval")]
+    snapshot_interval_secs: u64,
+}
+
+impl AccountingConfig {
+    fn default_snapshot_interval() -> u64 {
+        60
+    }
+
+    /// Returns the snapshot file of the current round.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns how often the current round is snapshotted, at least every second.
+    pub fn snapshot_interval(&self) -> Duration {
+        Duration::from_secs(self.snapshot_interval_secs.max(1))
+    }
+}
+
+/// State file the lifetime statistics of the pool are saved to every `save_interval_secs` and on
+/// shutdown, and restored from at startup, see the `lifetime` module.
+///
+/// Enabled by a `[lifetime]` table.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct LifetimeConfig {
+    path: PathBuf,
+    #[serde(default = "LifetimeConfig::default_save_interval")]
+    save_interval_secs: u64,
+}
+
+impl LifetimeConfig {
+    fn default_save_interval() -> u64 {
+        60
+    }
+
+    /// Returns the state file of the lifetime statistics.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns how often the lifetime statistics are saved, at least every second.
+    pub fn save_interval(&self) -> Duration {
+        Duration::from_secs(self.save_interval_secs.max(1))
+    }
+}
+
+/// Backend shared by the pool instances scaled horizontally, see the `coordination` module.
+///
+/// Enabled by a `[coordination]` table.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct CoordinationConfig {
+    backend: CoordinationBackend,
+    address: String,
+    password: Option<String>,
+    #[serde(default = "CoordinationConfig::default_key_prefix")]
+    key_prefix: String,
+    #[serde(default = "CoordinationConfig::default_duplicate_window")]
+    duplicate_window_secs: u64,
+    #[serde(default = "CoordinationConfig::default_reconnect_interval")]
+    reconnect_interval_secs: u64,
+}
+
+/// Backend of the coordination, selected by the `backend` key of the `[coordination]` table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoordinationBackend {
+    Redis,
+}
+
+impl CoordinationConfig {
+    fn default_key_prefix() -> String {
+        "pool".to_string()
+    }
+
+    fn default_duplicate_window() -> u64 {
+        600
+    }
+
+    fn default_reconnect_interval() -> u64 {
+        5
+    }
+
+    /// Returns the backend of the coordination.
+    pub fn backend(&self) -> CoordinationBackend {
+        self.backend
+    }
+
+    /// Returns the address of the backend, as `host:port`.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns the password of the backend, if it requires one.
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+
+    /// Returns the key `name` of the backend, under the prefix shared by the pool instances.
+    pub fn key(&self, name: &str) -> String {
+        format!("{}:{name}", self.key_prefix)
+    }
+
+    /// Returns how long an accepted share is remembered to detect its duplicates.
+    pub fn duplicate_window(&self) -> Duration {
+        Duration::from_secs(self.duplicate_window_secs.max(1))
+    }
+
+    /// Returns how long to wait before reconnecting to the backend, at least a second.
+    pub fn reconnect_interval(&self) -> Duration {
+        Duration::from_secs(self.reconnect_interval_secs.max(1))
+    }
+}
+
+/// Shares notable for meeting a fraction of the network difficulty, see the `notable_shares`
+/// module.
+///
+/// Enabled by a `[notable_shares]` table.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct NotableSharesConfig {
+    #[serde(default = "NotableSharesConfig::default_threshold")]
+    threshold: f64,
+    webhook_url: Option<String>,
+}
+
+impl NotableSharesConfig {
+    fn default_threshold() -> f64 {
+        0.01
+    }
+
+    /// Returns the fraction of the network difficulty from which a share is notable.
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// Returns the URL the notable shares are POSTed to, if set.
+    pub fn webhook_url(&self) -> Option<&str> {
+        self.webhook_url.as_deref()
+    }
+}
+
+/// Wallet of a bitcoind node paying the balances owed to the users, see the `payments` module.
+///
+/// Enabled by a `[payments]` table. The payouts are funded by `utxos` if listed, and by the
+/// spendable outputs of the wallet otherwise, and signed by the wallet in both cases.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct PaymentsConfig {
+    rpc_url: String,
+    rpc_user: String,
+    rpc_password: String,
+    change_address: String,
+    #[serde(default = "PaymentsConfig::default_fee_rate")]
+    fee_rate_sat_vb: f64,
+    #[serde(default = "PaymentsConfig::default_min_payout")]
+    min_payout_sat: u64,
+    #[serde(default)]
+    utxos: Vec<PaymentUtxo>,
+}
+
+impl PaymentsConfig {
+    fn default_fee_rate() -> f64 {
+        2.0
+    }
+
+    fn default_min_payout() -> u64 {
+        10_000
+    }
+
+    /// Returns the URL of the wallet RPC, e.g. `http://127.0.0.1:8332/wallet/payouts`.
+    pub fn rpc_url(&self) -> &str {
+        &self.rpc_url
+    }
+
+    /// Returns the user and password of the RPC.
+    pub fn rpc_credentials(&self) -> (&str, &str) {
+        (&self.rpc_user, &self.rpc_password)
+    }
+
+    /// Returns the address the change of the payouts is returned to.
+    pub fn change_address(&self) -> &str {
+        &self.change_address
+    }
+
+    /// Returns the fee rate of the payout transactions, in sat/vB.
+    pub fn fee_rate(&self) -> f64 {
+        self.fee_rate_sat_vb
+    }
+
+    /// Returns the smallest balance paid, smaller balances are carried over to the next payment.
+    pub fn min_payout(&self) -> u64 {
+        self.min_payout_sat
+    }
+
+    /// Returns the outputs set aside to fund the payouts, the outputs of the wallet are used if
+    /// empty.
+    pub fn utxos(&self) -> &[PaymentUtxo] {
+        &self.utxos
+    }
+}
+
+/// An output set aside to fund the payouts.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct PaymentUtxo {
+    txid: String,
+    vout: u32,
+    amount_sat: u64,
+}
+
+impl PaymentUtxo {
+    /// Returns the outpoint of the output, as `txid:vout`.
+    pub fn outpoint(&self) -> String {
+        format!("{}:{}", self.txid, self.vout)
+    }
+
+    /// Returns the value of the output, in satoshis.
+    pub fn amount(&self) -> u64 {
+        self.amount_sat
+    }
+}
+
 /// Configuration for connecting to a Template Provider.
 pub struct TemplateProviderConfig {
     address: String,
@@ -197,3 +1050,65 @@ impl ConnectionConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floor(user_identity: &str, min_difficulty: f64) -> DifficultyFloor {
+        DifficultyFloor {
+            user_identity: user_identity.to_string(),
+            min_difficulty,
+        }
+    }
+
+    #[test]
+    fn patterns_without_wildcard_match_exactly() {
+        assert!(matches_pattern("farm.rig1", "farm.rig1"));
+        assert!(!matches_pattern("farm.rig1", "farm.rig10"));
+        assert!(!matches_pattern("farm.rig1", "farm"));
+        assert!(!matches_pattern("farm.rig1", "xfarm.rig1"));
+        assert!(matches_pattern("", ""));
+        assert!(!matches_pattern("", "farm"));
+    }
+
+    #[test]
+    fn wildcards_match_any_sequence() {
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("*", "farm.rig1"));
+        assert!(matches_pattern("farm.*", "farm.rig1"));
+        assert!(matches_pattern("farm.*", "farm."));
+        assert!(matches_pattern("*.rig1", "farm.rig1"));
+        assert!(matches_pattern("f*.*1", "farm.rig1"));
+        assert!(matches_pattern("farm*rig*", "farm.rig1"));
+        assert!(matches_pattern("a*a", "aa"));
+    }
+
+    #[test]
+    fn wildcards_do_not_match_other_identities() {
+        assert!(!matches_pattern("farm.*", "farm"));
+        assert!(!matches_pattern("farm.*", "other.rig1"));
+        assert!(!matches_pattern("*.rig1", "farm.rig2"));
+        assert!(!matches_pattern("f*.*1", "farm.rig2"));
+        assert!(!matches_pattern("a*a", "a"));
+        assert!(!matches_pattern("farm*rig*", "farm.miner"));
+    }
+
+    #[test]
+    fn difficulty_floors_match_their_pattern() {
+        let floor = floor("farm.*", 1000.0);
+        assert!(floor.matches("farm.rig1"));
+        assert!(!floor.matches("other.rig1"));
+    }
+
+    #[test]
+    fn min_hashrate_gives_the_min_difficulty() {
+        let floor = floor("farm", 1000.0);
+        // A difficulty of 1000 is 1000 * 2^32 hashes per share, 6 shares per minute.
+        assert_eq!(
+            floor.min_hashrate(6.0),
+            (1000.0 * 2f64.powi(32) / 10.0) as f32
+        );
+        assert_eq!(floor.min_hashrate(12.0), 2.0 * floor.min_hashrate(6.0));
+    }
+}
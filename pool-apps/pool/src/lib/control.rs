@@ -0,0 +1,443 @@
+//! ## Control Module
+//!
+//! Handles the requests of the gRPC control service (see [`stratum_apps::control`]), in Pools
+//! built with the `control` feature. The status and health are those served by the monitoring
+//! endpoint, and the connections are those registered by the channel manager. Draining refuses
+//! the new downstream connections and reports the pool as not ready. A reload rotates the
+//! authority keypair to the one of the configuration file, as `SIGHUP` does.
+//!
+//! The ban list is managed through the `list_bans`, `ban` (`{"target": ..., "ttl_secs": ...,
+//! "reason": ...}`) and `unban` (`{"target": ...}`) extensions. The `close_channel` extension
+//! (`{"connection_id": ..., "channel_id": ..., "reason": ...}`) closes a single channel of a
+//! downstream, keeping its connection and other channels open. The `channel_endpoint_changed`
+//! extension (`{"connection_id": ..., "channel_id": ...}`) sends a downstream a
+//! `ChannelEndpointChanged` for a channel moved behind the pool, e.g. by an orchestrator.
+//!
+//! The vardiff of a channel is overridden through the `vardiff_pin` (`{"connection_id": ...,
+//! "channel_id": ..., "difficulty": ...}`), `vardiff_nudge` (`{"connection_id": ...,
+//! "channel_id": ..., "factor": ...}`) and `vardiff_unpin` (`{"connection_id": ..., "channel_id":
+//! ...}`) extensions, which answer the resulting vardiff state of the channel.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use stratum_apps::{
+    banlist::{BanList, BanTarget},
+    control::{Connection, ControlError, ControlHandler},
+    monitoring::{ban_json, StatusProvider},
+    status::{HealthAggregator, HealthReport},
+};
+
+use crate::{
+    authority::AuthorityKeys,
+    channel_manager::{ChannelManager, VardiffOverride},
+    config::PoolConfig,
+    monitoring::PoolStatusProvider,
+};
+
+/// Loads the configuration file of the Pool again, for the control service to reload it.
+#[derive(Clone)]
+pub struct ConfigLoader(Arc<dyn Fn() -> Result<PoolConfig, String> + Send + Sync>);
+
+impl ConfigLoader {
+    pub fn new(load: impl Fn() -> Result<PoolConfig, String> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(load))
+    }
+}
+
+impl fmt::Debug for ConfigLoader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ConfigLoader")
+    }
+}
+
+#[derive(Deserialize)]
+struct BanParams {
+    target: String,
+    ttl_secs: Option<u64>,
+    reason: Option<String>,
+}
+
+/// Reason code of the channels closed through the control service without a reason.
+const CLOSED_BY_OPERATOR: &str = "closed-by-operator";
+
+#[derive(Deserialize)]
+struct CloseChannelParams {
+    connection_id: u64,
+    channel_id: u32,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VardiffParams {
+    connection_id: u64,
+    channel_id: u32,
+    difficulty: Option<f64>,
+    factor: Option<f64>,
+}
+
+/// [`ControlHandler`] of the pool.
+pub struct PoolControl {
+    status: Arc<PoolStatusProvider>,
+    channel_manager: ChannelManager,
+    health: Arc<HealthAggregator>,
+    banlist: Arc<BanList>,
+    authority_keys: AuthorityKeys,
+    config_loader: Option<ConfigLoader>,
+}
+
+impl PoolControl {
+    pub fn new(
+        status: Arc<PoolStatusProvider>,
+        channel_manager: ChannelManager,
+        health: Arc<HealthAggregator>,
+        banlist: Arc<BanList>,
+        authority_keys: AuthorityKeys,
+        config_loader: Option<ConfigLoader>,
+    ) -> Self {
+        Self {
+            status,
+            channel_manager,
+            health,
+            banlist,
+            authority_keys,
+            config_loader,
+        }
+    }
+
+    fn ban(&self, params: serde_json::Value) -> Result<serde_json::Value, ControlError> {
+        let params: BanParams = serde_json::from_value(params)
+            .map_err(|e| ControlError::InvalidArgument(format!("invalid `ban` parameters: {e}")))?;
+        let target = parse_target(&params.target)?;
+        let ban = self
+            .banlist
+            .ban(
+                target,
+                params.ttl_secs.map(Duration::from_secs),
+                params.reason,
+            )
+            .map_err(|e| {
+                ControlError::Internal(format!(
+                    "ban in effect but the ban list could not be saved: {e}"
+                ))
+            })?;
+        Ok(ban_json(&ban))
+    }
+
+    fn unban(&self, params: serde_json::Value) -> Result<serde_json::Value, ControlError> {
+        let target = params
+            .get("target")
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| ControlError::InvalidArgument("missing `target`".to_string()))?;
+        let target = parse_target(target)?;
+        match self.banlist.unban(&target) {
+            Ok(true) => Ok(serde_json::json!({ "removed": target.to_string() })),
+            Ok(false) => Err(ControlError::NotFound(format!("`{target}` is not banned"))),
+            Err(e) => Err(ControlError::Internal(format!(
+                "ban lifted but the ban list could not be saved: {e}"
+            ))),
+        }
+    }
+
+    fn close_channel(&self, params: serde_json::Value) -> Result<serde_json::Value, ControlError> {
+        let params: CloseChannelParams = serde_json::from_value(params).map_err(|e| {
+            ControlError::InvalidArgument(format!("invalid `close_channel` parameters: {e}"))
+        })?;
+        let reason = params
+            .reason
+            .unwrap_or_else(|| CLOSED_BY_OPERATOR.to_string());
+        if reason.len() > 255 {
+            return Err(ControlError::InvalidArgument(
+                "`reason` is longer than 255 bytes".to_string(),
+            ));
+        }
+        let (connection_id, channel_id) = (params.connection_id, params.channel_id);
+        let closed = usize::try_from(connection_id).is_ok_and(|downstream_id| {
+            self.channel_manager
+                .close_channel(downstream_id, channel_id, &reason)
+        });
+        if !closed {
+            return Err(ControlError::NotFound(format!(
+                "no channel {channel_id} on downstream {connection_id}"
+            )));
+        }
+        Ok(serde_json::json!({
+            "connection_id": connection_id,
+            "channel_id": channel_id,
+            "reason": reason,
+        }))
+    }
+
+    fn channel_endpoint_changed(
+        &self,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, ControlError> {
+        // The reason of the parameters of `close_channel`, if any, is ignored.
+        let params: CloseChannelParams = serde_json::from_value(params).map_err(|e| {
+            ControlError::InvalidArgument(format!(
+                "invalid `channel_endpoint_changed` parameters: {e}"
+            ))
+        })?;
+        let (connection_id, channel_id) = (params.connection_id, params.channel_id);
+        let notified = usize::try_from(connection_id).is_ok_and(|downstream_id| {
+            self.channel_manager
+                .channel_endpoint_changed(downstream_id, channel_id)
+        });
+        if !notified {
+            return Err(ControlError::NotFound(format!(
+                "no channel {channel_id} on downstream {connection_id}"
+            )));
+        }
+        Ok(serde_json::json!({
+            "connection_id": connection_id,
+            "channel_id": channel_id,
+        }))
+    }
+
+    fn override_vardiff(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, ControlError> {
+        let params: VardiffParams = serde_json::from_value(params).map_err(|e| {
+            ControlError::InvalidArgument(format!("invalid `{method}` parameters: {e}"))
+        })?;
+        let positive = |name: &str, value: Option<f64>| match value {
+            Some(value) if value.is_finite() && value > 0.0 => Ok(value),
+            _ => Err(ControlError::InvalidArgument(format!(
+                "`{name}` must be a positive number"
+            ))),
+        };
+        let action = match method {
+            "vardiff_pin" => VardiffOverride::Pin(positive("difficulty", params.difficulty)?),
+            "vardiff_nudge" => VardiffOverride::Nudge(positive("factor", params.factor)?),
+            _ => VardiffOverride::Unpin,
+        };
+        let (connection_id, channel_id) = (params.connection_id, params.channel_id);
+        let status = match usize::try_from(connection_id) {
+            Ok(downstream_id) => self
+                .channel_manager
+                .override_vardiff(downstream_id, channel_id, action)
+                .map_err(|e| {
+                    ControlError::InvalidArgument(format!(
+                        "cannot set the target of channel {channel_id}: {e}"
+                    ))
+                })?,
+            Err(_) => None,
+        };
+        let Some(status) = status else {
+            return Err(ControlError::NotFound(format!(
+                "no channel {channel_id} on downstream {connection_id}"
+            )));
+        };
+        Ok(serde_json::to_value(status).expect("vardiff state is always serializable"))
+    }
+}
+
+impl ControlHandler for PoolControl {
+    fn role(&self) -> &str {
+        "pool"
+    }
+
+    fn version(&self) -> &str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn status(&self) -> serde_json::Value {
+        self.status.status()
+    }
+
+    fn health(&self) -> HealthReport {
+        self.status.health()
+    }
+
+    fn connections(&self) -> Result<Vec<Connection>, ControlError> {
+        let connections = self
+            .channel_manager
+            .connections()
+            .into_iter()
+            .map(|(info, downstream)| {
+                let user_identities: Vec<String> =
+                    downstream.downstream_data.super_safe_lock(|data| {
+                        data.standard_channels
+                            .values()
+                            .map(|channel| channel.get_user_identity().to_string())
+                            .chain(
+                                data.extended_channels
+                                    .values()
+                                    .map(|channel| channel.get_user_identity().to_string()),
+                            )
+                            .collect()
+                    });
+                let traffic = downstream.traffic.snapshot();
+                let device = downstream.device.get().cloned().unwrap_or_default();
+                Connection {
+                    user_identities,
+                    bytes_received: traffic.bytes_received,
+                    bytes_sent: traffic.bytes_sent,
+                    vendor: device.vendor,
+                    hardware_version: device.hardware_version,
+                    firmware: device.firmware,
+                    device_id: device.device_id,
+                    ..info.into()
+                }
+            })
+            .collect();
+        Ok(connections)
+    }
+
+    fn disconnect(&self, id: u64) -> Result<(), ControlError> {
+        let disconnected = usize::try_from(id)
+            .is_ok_and(|downstream_id| self.channel_manager.disconnect(downstream_id));
+        if !disconnected {
+            return Err(ControlError::NotFound(format!("no downstream {id}")));
+        }
+        Ok(())
+    }
+
+    fn drain(&self) -> Result<(), ControlError> {
+        self.channel_manager.drain();
+        self.health.set_ready(false);
+        Ok(())
+    }
+
+    fn reload_config(&self) -> Result<String, ControlError> {
+        let Some(config_loader) = &self.config_loader else {
+            return Err(ControlError::Unimplemented(
+                "the Pool was not started from a configuration file".to_string(),
+            ));
+        };
+        let config = (config_loader.0)().map_err(ControlError::Internal)?;
+        self.authority_keys
+            .rotate(
+                *config.authority_public_key(),
+                *config.authority_secret_key(),
+            )
+            .map_err(|e| {
+                ControlError::InvalidArgument(format!(
+                    "invalid authority keypair, keeping the current one: {e:?}"
+                ))
+            })?;
+        Ok(format!("authority key {}", config.authority_public_key()))
+    }
+
+    fn extensions(&self) -> Vec<String> {
+        [
+            "list_bans",
+            "ban",
+            "unban",
+            "close_channel",
+            "channel_endpoint_changed",
+            "vardiff_pin",
+            "vardiff_nudge",
+            "vardiff_unpin",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect()
+    }
+
+    fn call_extension(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, ControlError> {
+        match method {
+            "list_bans" => Ok(serde_json::json!({
+                "bans": self.banlist.list().iter().map(ban_json).collect::<Vec<_>>()
+            })),
+            "ban" => self.ban(params),
+            "unban" => self.unban(params),
+            "close_channel" => self.close_channel(params),
+            "channel_endpoint_changed" => self.channel_endpoint_changed(params),
+            "vardiff_pin" | "vardiff_nudge" | "vardiff_unpin" => {
+                self.override_vardiff(method, params)
+            }
+            _ => Err(ControlError::NotFound(format!("no extension `{method}`"))),
+        }
+    }
+}
+
+fn parse_target(target: &str) -> Result<BanTarget, ControlError> {
+    target
+        .parse::<BanTarget>()
+        .map_err(ControlError::InvalidArgument)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        channel_manager::tests::channel_manager,
+        channel_updates::ChannelUpdates,
+        monitoring::{BestShares, LatencyStats, ShareAckStats, TemplateStats},
+        rounds::Rounds,
+        workers::Workers,
+    };
+    use stratum_apps::{
+        banlist::BanListConfig, runtime::TaskManager, share_reject::ShareRejectCounters,
+    };
+
+    async fn pool_control() -> PoolControl {
+        let health = Arc::new(HealthAggregator::new());
+        let banlist = Arc::new(BanList::new(&BanListConfig::default()).unwrap());
+        let status = PoolStatusProvider::new(
+            health.clone(),
+            banlist.clone(),
+            Arc::new(ShareRejectCounters::new()),
+            Arc::new(LatencyStats::new()),
+            Arc::new(TemplateStats::new(None)),
+            Arc::new(ShareAckStats::new(None)),
+            Arc::new(BestShares::new()),
+            Arc::new(Rounds::new()),
+            Arc::new(Workers::new()),
+            Arc::new(ChannelUpdates::new()),
+            None,
+            ".".to_string(),
+            Arc::new(TaskManager::new()),
+        );
+        let authority_keys = AuthorityKeys::new(
+            "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72"
+                .parse()
+                .unwrap(),
+            "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n"
+                .parse()
+                .unwrap(),
+            Duration::from_secs(3600),
+        );
+        PoolControl::new(
+            Arc::new(status),
+            channel_manager().await,
+            health,
+            banlist,
+            authority_keys,
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn channel_endpoint_changed_refuses_invalid_parameters_and_unknown_channels() {
+        let control = pool_control().await;
+        let error = control
+            .call_extension(
+                "channel_endpoint_changed",
+                serde_json::json!({ "connection_id": 1 }),
+            )
+            .unwrap_err();
+        assert!(
+            matches!(error, ControlError::InvalidArgument(_)),
+            "{error:?}"
+        );
+
+        let error = control
+            .call_extension(
+                "channel_endpoint_changed",
+                serde_json::json!({ "connection_id": 1, "channel_id": 2 }),
+            )
+            .unwrap_err();
+        assert_eq!(
+            error,
+            ControlError::NotFound("no channel 2 on downstream 1".to_string())
+        );
+    }
+}
@@ -0,0 +1,574 @@
+//! ## Coordination Module
+//!
+//! Coordination of the pool instances scaled horizontally behind a load balancer, each with its
+//! own `server_id`, through a shared Redis server. The [`Coordinator`] is attached to the event
+//! bus of the pool and, from the events published by the instance:
+//! - records the hash of every accepted share under `<key_prefix>:share:<hash>` for
+//!   `duplicate_window_secs`, so that a share already accepted by another instance (or on another
+//!   channel of this one) is detected. Shares are acknowledged before they reach Redis, so a
+//!   duplicate is counted and logged rather than rejected.
+//! - adds the shares and the work of every share recorded for the first time to the aggregates
+//!   of its user and of its account, the `<key_prefix>:user:<user_identity>` and
+//!   `<key_prefix>:account:<account>` hashes, along with the time of their last share and the
+//!   `server_id` of the instance which accepted it.
+//! - announces the blocks found by the instance on the `<key_prefix>:blocks` channel, and logs
+//!   the blocks announced by the other instances.
+//!
+//! The commands are pipelined from a bounded queue by a task of their own, so that Redis never
+//! slows down the validation of the shares: events published while the queue is full, e.g.
+//! while Redis is unreachable, are dropped and counted. Rounds and payouts remain per instance.
+//! The state of the coordination is served under `coordination` on the monitoring endpoint.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use stratum_apps::{
+    custom_mutex::Mutex,
+    events::{Event, EventSubscriber},
+    runtime::TaskManager,
+    unix_time,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::broadcast,
+};
+use tracing::{info, warn};
+
+use crate::{config::CoordinationConfig, utils::ShutdownMessage};
+
+/// Number of commands queued for Redis before events are dropped.
+const QUEUE_SIZE: usize = 4096;
+/// Number of commands sent to Redis at once.
+const MAX_PIPELINE: usize = 256;
+/// How long connecting to Redis may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long Redis may take to answer a command.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Size of the largest bulk string accepted from Redis.
+const MAX_BULK_LEN: usize = 1 << 20;
+
+// Records a share (KEYS[1]) for ARGV[2] milliseconds unless already recorded, and adds it to the
+// aggregates of its user (KEYS[2]) and account (KEYS[3]). Returns nil if the share is new, and
+// the `server_id` of the instance which recorded it first otherwise.
+const RECORD_SHARE: &str = r#"
+if not redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+  return redis.call('GET', KEYS[1])
+end
+for i = 2, 3 do
+  redis.call('HINCRBY', KEYS[i], 'shares', 1)
+  redis.call('HINCRBYFLOAT', KEYS[i], 'work', ARGV[3])
+  redis.call('HSET', KEYS[i], 'last_share_ms', ARGV[4], 'last_server_id', ARGV[1])
+end
+return false
+"#;
+
+/// Block found by a pool instance, as announced to the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockAnnouncement {
+    pub server_id: u16,
+    pub block_hash: String,
+    pub user_identity: String,
+    pub account: String,
+    /// Amount paid to the pool by the block, in satoshis.
+    pub reward: u64,
+    pub timestamp_ms: u64,
+}
+
+// Command queued for Redis.
+enum Command {
+    Share {
+        hash: String,
+        user_identity: String,
+        account: String,
+        work: f64,
+    },
+    Block(BlockAnnouncement),
+}
+
+/// State of the coordination, as served on the monitoring endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct CoordinationStatus {
+    pub connected: bool,
+    /// Accepted shares recorded in Redis for the first time.
+    pub shares_recorded: u64,
+    /// Accepted shares Redis already had a record of.
+    pub duplicate_shares: u64,
+    /// Events dropped because the queue of commands was full.
+    pub events_dropped: u64,
+    /// Blocks found by this instance and announced to the others.
+    pub blocks_announced: u64,
+    /// Blocks announced by the other instances.
+    pub remote_blocks: u64,
+    /// Last block announced by another instance.
+    pub last_remote_block: Option<BlockAnnouncement>,
+}
+
+/// Counters of the coordination.
+#[derive(Debug)]
+pub struct CoordinationStats {
+    connected: AtomicBool,
+    shares_recorded: AtomicU64,
+    duplicate_shares: AtomicU64,
+    events_dropped: AtomicU64,
+    blocks_announced: AtomicU64,
+    remote_blocks: AtomicU64,
+    last_remote_block: Mutex<Option<BlockAnnouncement>>,
+}
+
+impl CoordinationStats {
+    pub fn new() -> Self {
+        Self {
+            connected: AtomicBool::new(false),
+            shares_recorded: AtomicU64::new(0),
+            duplicate_shares: AtomicU64::new(0),
+            events_dropped: AtomicU64::new(0),
+            blocks_announced: AtomicU64::new(0),
+            remote_blocks: AtomicU64::new(0),
+            last_remote_block: Mutex::new(None),
+        }
+    }
+
+    pub fn snapshot(&self) -> CoordinationStatus {
+        CoordinationStatus {
+            connected: self.connected.load(Ordering::Relaxed),
+            shares_recorded: self.shares_recorded.load(Ordering::Relaxed),
+            duplicate_shares: self.duplicate_shares.load(Ordering::Relaxed),
+            events_dropped: self.events_dropped.load(Ordering::Relaxed),
+            blocks_announced: self.blocks_announced.load(Ordering::Relaxed),
+            remote_blocks: self.remote_blocks.load(Ordering::Relaxed),
+            last_remote_block: self
+                .last_remote_block
+                .super_safe_lock(|block| block.clone()),
+        }
+    }
+}
+
+impl Default for CoordinationStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// [`EventSubscriber`] sharing the shares and blocks of the pool instance with the others.
+#[derive(Clone)]
+pub struct Coordinator {
+    server_id: u16,
+    sender: async_channel::Sender<Command>,
+    stats: Arc<CoordinationStats>,
+}
+
+impl Coordinator {
+    /// Spawns on `task_manager` the tasks sending the commands of the instance of `server_id` to
+    /// Redis and listening to the blocks announced by the other instances, until shutdown.
+    pub fn spawn(
+        config: CoordinationConfig,
+        server_id: u16,
+        task_manager: &TaskManager,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    ) -> Self {
+        let (sender, receiver) = async_channel::bounded(QUEUE_SIZE);
+        let stats = Arc::new(CoordinationStats::new());
+        let config = Arc::new(config);
+        task_manager.spawn_named("coordination_writer", {
+            let (config, stats) = (config.clone(), stats.clone());
+            let shutdown = shutdown_all(notify_shutdown.subscribe());
+            async move {
+                tokio::select! {
+                    _ = shutdown => {}
+                    _ = run_writer(&config, server_id, &receiver, &stats) => {}
+                }
+            }
+        });
+        task_manager.spawn_named("coordination_listener", {
+            let stats = stats.clone();
+            let shutdown = shutdown_all(notify_shutdown.subscribe());
+            async move {
+                tokio::select! {
+                    _ = shutdown => {}
+                    _ = run_listener(&config, server_id, &stats) => {}
+                }
+            }
+        });
+        Self {
+            server_id,
+            sender,
+            stats,
+        }
+    }
+
+    /// Returns the counters of the coordination.
+    pub fn stats(&self) -> Arc<CoordinationStats> {
+        self.stats.clone()
+    }
+
+    fn queue(&self, command: Command) {
+        if self.sender.try_send(command).is_err() {
+            self.stats.events_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl EventSubscriber for Coordinator {
+    fn handle(&mut self, event: &Event) {
+        match event {
+            Event::ShareValidated(share) if share.is_accepted() => {
+                let (Some(hash), Some(user_identity), Some(account), Some(work)) = (
+                    &share.share_hash,
+                    &share.user_identity,
+                    &share.account,
+                    share.target_difficulty,
+                ) else {
+                    return;
+                };
+                self.queue(Command::Share {
+                    hash: hash.clone(),
+                    user_identity: user_identity.clone(),
+                    account: account.clone(),
+                    work,
+                });
+            }
+            Event::BlockFound(block) => self.queue(Command::Block(BlockAnnouncement {
+                server_id: self.server_id,
+                block_hash: block.block_hash.clone(),
+                user_identity: block.user_identity.clone(),
+                account: block.account.clone(),
+                reward: block.reward,
+                timestamp_ms: unix_time::now_ms(),
+            })),
+            _ => {}
+        }
+    }
+}
+
+// Completes on `ShutdownAll`, ignoring the shutdown of single downstreams.
+async fn shutdown_all(mut shutdown_rx: broadcast::Receiver<ShutdownMessage>) {
+    loop {
+        match shutdown_rx.recv().await {
+            Ok(ShutdownMessage::ShutdownAll) | Err(_) => return,
+            Ok(_) => {}
+        }
+    }
+}
+
+// Sends the queued commands to Redis, reconnecting whenever the connection is lost.
+async fn run_writer(
+    config: &CoordinationConfig,
+    server_id: u16,
+    receiver: &async_channel::Receiver<Command>,
+    stats: &CoordinationStats,
+) {
+    loop {
+        match write_commands(config, server_id, receiver, stats).await {
+            Ok(()) => return,
+            Err(e) => warn!(
+                "Coordination through Redis at {} interrupted: {e}",
+                config.address()
+            ),
+        }
+        stats.connected.store(false, Ordering::Relaxed);
+        tokio::time::sleep(config.reconnect_interval()).await;
+    }
+}
+
+// Connects to Redis and pipelines the queued commands until the connection is lost, or until
+// the queue is closed.
+async fn write_commands(
+    config: &CoordinationConfig,
+    server_id: u16,
+    receiver: &async_channel::Receiver<Command>,
+    stats: &CoordinationStats,
+) -> io::Result<()> {
+    let mut connection = Connection::connect(config).await?;
+    info!(
+        "Coordinating with the other pool instances through Redis at {}",
+        config.address()
+    );
+    stats.connected.store(true, Ordering::Relaxed);
+    let server_id = server_id.to_string();
+    let duplicate_window = config.duplicate_window().as_millis().to_string();
+    let blocks = config.key("blocks");
+    let mut batch = Vec::with_capacity(MAX_PIPELINE);
+    let mut buffer = Vec::new();
+    loop {
+        let Ok(command) = receiver.recv().await else {
+            return Ok(());
+        };
+        batch.push(command);
+        while batch.len() < MAX_PIPELINE {
+            match receiver.try_recv() {
+                Ok(command) => batch.push(command),
+                Err(_) => break,
+            }
+        }
+        buffer.clear();
+        for command in &batch {
+            match command {
+                Command::Share {
+                    hash,
+                    user_identity,
+                    account,
+                    work,
+                } => encode(
+                    &[
+                        "EVAL",
+                        RECORD_SHARE,
+                        "3",
+                        &config.key(&format!("share:{hash}")),
+                        &config.key(&format!("user:{user_identity}")),
+                        &config.key(&format!("account:{account}")),
+                        &server_id,
+                        &duplicate_window,
+                        &work.to_string(),
+                        &unix_time::now_ms().to_string(),
+                    ],
+                    &mut buffer,
+                ),
+                Command::Block(block) => encode(
+                    &[
+                        "PUBLISH",
+                        &blocks,
+                        &serde_json::to_string(block).map_err(io::Error::other)?,
+                    ],
+                    &mut buffer,
+                ),
+            }
+        }
+        connection.send(&buffer).await?;
+        for command in batch.drain(..) {
+            let reply = tokio::time::timeout(REPLY_TIMEOUT, connection.reply())
+                .await
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::TimedOut, "Redis stopped answering")
+                })??;
+            match (command, reply) {
+                (Command::Share { .. }, Reply::Bulk(None)) => {
+                    stats.shares_recorded.fetch_add(1, Ordering::Relaxed);
+                }
+                (
+                    Command::Share {
+                        hash,
+                        user_identity,
+                        ..
+                    },
+                    Reply::Bulk(Some(first)),
+                ) => {
+                    stats.duplicate_shares.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "Duplicate share {hash} of {user_identity}, already accepted by pool instance {}",
+                        String::from_utf8_lossy(&first)
+                    );
+                }
+                (Command::Block(_), Reply::Integer(_)) => {
+                    stats.blocks_announced.fetch_add(1, Ordering::Relaxed);
+                }
+                (_, Reply::Error(e)) => warn!("Redis refused a coordination command: {e}"),
+                (_, reply) => {
+                    warn!("Unexpected reply of Redis to a coordination command: {reply:?}")
+                }
+            }
+        }
+    }
+}
+
+// Listens to the blocks announced by the other instances, reconnecting whenever the connection
+// is lost.
+async fn run_listener(config: &CoordinationConfig, server_id: u16, stats: &CoordinationStats) {
+    loop {
+        if let Err(e) = listen_blocks(config, server_id, stats).await {
+            warn!("Listening to the blocks of the other pool instances interrupted: {e}");
+        }
+        tokio::time::sleep(config.reconnect_interval()).await;
+    }
+}
+
+async fn listen_blocks(
+    config: &CoordinationConfig,
+    server_id: u16,
+    stats: &CoordinationStats,
+) -> io::Result<()> {
+    let mut connection = Connection::connect(config).await?;
+    let mut buffer = Vec::new();
+    encode(&["SUBSCRIBE", &config.key("blocks")], &mut buffer);
+    connection.send(&buffer).await?;
+    loop {
+        let Reply::Array(Some(items)) = connection.reply().await? else {
+            continue;
+        };
+        let [Reply::Bulk(Some(kind)), _, Reply::Bulk(Some(payload))] = items.as_slice() else {
+            continue;
+        };
+        if kind != b"message" {
+            continue;
+        }
+        let block: BlockAnnouncement = match serde_json::from_slice(payload) {
+            Ok(block) => block,
+            Err(e) => {
+                warn!("Invalid block announced by a pool instance: {e}");
+                continue;
+            }
+        };
+        if block.server_id == server_id {
+            continue;
+        }
+        info!(
+            "Block {} found by pool instance {} for {}",
+            block.block_hash, block.server_id, block.user_identity
+        );
+        stats.remote_blocks.fetch_add(1, Ordering::Relaxed);
+        stats
+            .last_remote_block
+            .super_safe_lock(|last| *last = Some(block));
+    }
+}
+
+// Reply of Redis, arrays only holding scalar replies.
+#[derive(Debug)]
+enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+// Connection to Redis, speaking RESP2.
+struct Connection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    // Connects to the Redis of `config`, authenticating if it has a password.
+    async fn connect(config: &CoordinationConfig) -> io::Result<Self> {
+        let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(config.address()))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connection timed out"))??;
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        let mut connection = Self {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        if let Some(password) = config.password() {
+            let mut buffer = Vec::new();
+            encode(&["AUTH", password], &mut buffer);
+            connection.send(&buffer).await?;
+            let reply = tokio::time::timeout(REPLY_TIMEOUT, connection.reply())
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "AUTH timed out"))??;
+            match reply {
+                Reply::Status(status) if status == "OK" => {}
+                Reply::Error(e) => return Err(io::Error::new(io::ErrorKind::PermissionDenied, e)),
+                reply => {
+                    return Err(invalid_data(&format!(
+                        "unexpected reply to AUTH: {reply:?}"
+                    )))
+                }
+            }
+        }
+        Ok(connection)
+    }
+
+    async fn send(&mut self, commands: &[u8]) -> io::Result<()> {
+        self.writer.write_all(commands).await
+    }
+
+    async fn reply(&mut self) -> io::Result<Reply> {
+        let line = self.line().await?;
+        if line.first() != Some(&b'*') {
+            return self.scalar(line).await;
+        }
+        let Some(len) = parse_len(&line[1..])? else {
+            return Ok(Reply::Array(None));
+        };
+        let mut items = Vec::with_capacity(len.min(16));
+        for _ in 0..len {
+            let line = self.line().await?;
+            if line.first() == Some(&b'*') {
+                return Err(invalid_data("nested arrays are not supported"));
+            }
+            items.push(self.scalar(line).await?);
+        }
+        Ok(Reply::Array(Some(items)))
+    }
+
+    async fn scalar(&mut self, line: Vec<u8>) -> io::Result<Reply> {
+        let Some((kind, rest)) = line.split_first() else {
+            return Err(invalid_data("empty reply"));
+        };
+        match kind {
+            b'+' => Ok(Reply::Status(String::from_utf8_lossy(rest).into_owned())),
+            b'-' => Ok(Reply::Error(String::from_utf8_lossy(rest).into_owned())),
+            b':' => std::str::from_utf8(rest)
+                .ok()
+                .and_then(|integer| integer.parse().ok())
+                .map(Reply::Integer)
+                .ok_or_else(|| invalid_data("invalid integer reply")),
+            b'$' => {
+                let Some(len) = parse_len(rest)? else {
+                    return Ok(Reply::Bulk(None));
+                };
+                if len > MAX_BULK_LEN {
+                    return Err(invalid_data("bulk string too long"));
+                }
+                let mut data = vec![0; len + 2];
+                self.reader.read_exact(&mut data).await?;
+                if !data.ends_with(b"\r\n") {
+                    return Err(invalid_data("unterminated bulk string"));
+                }
+                data.truncate(len);
+                Ok(Reply::Bulk(Some(data)))
+            }
+            _ => Err(invalid_data("unknown reply type")),
+        }
+    }
+
+    // Reads a line, without its CRLF terminator.
+    async fn line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if !line.ends_with(b"\r\n") {
+            return Err(invalid_data("unterminated line"));
+        }
+        line.truncate(line.len() - 2);
+        Ok(line)
+    }
+}
+
+// Appends `command` to `buffer`, as an array of bulk strings.
+fn encode(command: &[&str], buffer: &mut Vec<u8>) {
+    buffer.extend_from_slice(format!("*{}\r\n", command.len()).as_bytes());
+    for argument in command {
+        buffer.extend_from_slice(format!("${}\r\n", argument.len()).as_bytes());
+        buffer.extend_from_slice(argument.as_bytes());
+        buffer.extend_from_slice(b"\r\n");
+    }
+}
+
+// Parses the length of a bulk string or of an array, `-1` standing for nil.
+fn parse_len(digits: &[u8]) -> io::Result<Option<usize>> {
+    if digits == b"-1" {
+        return Ok(None);
+    }
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|len| len.parse().ok())
+        .map(Some)
+        .ok_or_else(|| invalid_data("invalid length"))
+}
+
+fn invalid_data(error: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
@@ -1,41 +1,206 @@
-use crate::{downstream::Downstream, error::PoolError, utils::StdFrame};
-use std::{convert::TryInto, sync::atomic::Ordering};
-use stratum_apps::stratum_core::{
-    common_messages_sv2::{
-        has_requires_std_job, has_work_selection, SetupConnection, SetupConnectionSuccess,
+use crate::{
+    config::{DeviceAction, DeviceRule, SetupConnectionPolicy},
+    downstream::message_handler::DownstreamMessageHandler,
+    error::{PoolError, PoolResult},
+    utils::{SV2Frame, StdFrame},
+};
+use std::{
+    convert::TryInto,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+};
+use stratum_apps::{
+    events::{ConnectionSetUp, DeviceInfo, EventBus},
+    runtime::PrioritySender,
+    stratum_core::{
+        common_messages_sv2::{
+            has_requires_std_job, has_work_selection, Protocol, SetupConnection,
+            SetupConnectionError, SetupConnectionSuccess, MESSAGE_TYPE_SETUP_CONNECTION,
+        },
+        handlers_sv2::HandleCommonMessagesFromClientAsync,
+        parsers_sv2::AnyMessage,
     },
-    handlers_sv2::HandleCommonMessagesFromClientAsync,
-    parsers_sv2::AnyMessage,
 };
-use tracing::info;
+use tracing::{info, warn};
+
+/// Handles the messages of the common subprotocol sent by a downstream.
+///
+/// Only the `SetupConnection` opening the connection is handled, the flags and the device it
+/// describes are shared with the [`Downstream`](crate::downstream::Downstream) the handler belongs
+/// to.
+pub struct CommonMessageHandler {
+    downstream_id: usize,
+    address: SocketAddr,
+    downstream_sender: PrioritySender,
+    setup_connection: SetupConnectionPolicy,
+    device_rules: Arc<[DeviceRule]>,
+    events: EventBus,
+    requires_standard_jobs: Arc<AtomicBool>,
+    requires_custom_work: Arc<AtomicBool>,
+    device: Arc<OnceLock<DeviceInfo>>,
+    connection_set_up: bool,
+}
+
+impl CommonMessageHandler {
+    /// Creates a handler answering the downstream through `downstream_sender`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        downstream_id: usize,
+        address: SocketAddr,
+        downstream_sender: PrioritySender,
+        setup_connection: SetupConnectionPolicy,
+        device_rules: Arc<[DeviceRule]>,
+        events: EventBus,
+        requires_standard_jobs: Arc<AtomicBool>,
+        requires_custom_work: Arc<AtomicBool>,
+        device: Arc<OnceLock<DeviceInfo>>,
+    ) -> Self {
+        Self {
+            downstream_id,
+            address,
+            downstream_sender,
+            setup_connection,
+            device_rules,
+            events,
+            requires_standard_jobs,
+            requires_custom_work,
+            device,
+            connection_set_up: false,
+        }
+    }
+
+    // Sends a `SetupConnectionError` with `error_code` and the offending `flags` to the
+    // downstream, and fails with the error closing its connection.
+    async fn reject_setup_connection(
+        &self,
+        error_code: &'static str,
+        flags: u32,
+    ) -> Result<(), PoolError> {
+        let response = SetupConnectionError {
+            flags,
+            error_code: error_code
+                .to_string()
+                .try_into()
+                .expect("error code must be valid string"),
+        };
+        let frame: StdFrame = AnyMessage::Common(response.into_static().into()).try_into()?;
+        _ = self.downstream_sender.send(frame).await;
+        Err(PoolError::SetupConnectionRejected(error_code))
+    }
+}
+
+impl DownstreamMessageHandler for CommonMessageHandler {
+    // A `SetupConnection` is only handled as the first message of the connection: the common
+    // messages received afterwards are ignored.
+    async fn handle_frame(&mut self, message_type: u8, mut frame: SV2Frame) -> PoolResult<()> {
+        if message_type != MESSAGE_TYPE_SETUP_CONNECTION || self.connection_set_up {
+            warn!(
+                ?message_type,
+                "Received unsupported message type from downstream."
+            );
+            return Ok(());
+        }
+        self.handle_common_message_frame_from_client(None, message_type, frame.payload())
+            .await?;
+        self.connection_set_up = true;
+        Ok(())
+    }
+}
 
-impl HandleCommonMessagesFromClientAsync for Downstream {
+impl HandleCommonMessagesFromClientAsync for CommonMessageHandler {
     type Error = PoolError;
 
+    // Handles the [`SetupConnection`] opening a downstream connection, checked against the
+    // [`SetupConnectionPolicy`](crate::config::SetupConnectionPolicy) of the pool:
+    //
+    // 1. Only the `MiningProtocol` is supported, otherwise the connection is rejected with
+    //    `unsupported-protocol`.
+    // 2. The version range of the downstream must intersect the accepted one, otherwise the
+    //    connection is rejected with `protocol-version-mismatch`.
+    // 3. Every flag set must be accepted, otherwise the connection is rejected with
+    //    `unsupported-feature-flags`, the `SetupConnectionError` carrying the unsupported flags.
+    // 4. The first [`DeviceRule`] the device described matches applies: the connection is
+    //    accepted with a warning, or rejected with `unsupported-device`.
+    //
+    // A [`SetupConnectionSuccess`] then reports the highest version both sides support, and the
+    // connection is published along with its device.
     async fn handle_setup_connection(
         &mut self,
         _client_id: Option<usize>,
         msg: SetupConnection<'_>,
     ) -> Result<(), Self::Error> {
         info!(
-            "Received `SetupConnection`: version={}, flags={:b}",
-            msg.min_version, msg.flags
+            "Received `SetupConnection`: versions={}..={}, flags={:b}",
+            msg.min_version, msg.max_version, msg.flags
         );
 
+        if msg.protocol != Protocol::MiningProtocol {
+            info!("Rejecting connection: `SetupConnection` for another protocol than mining");
+            return self
+                .reject_setup_connection("unsupported-protocol", 0)
+                .await;
+        }
+
+        let policy = self.setup_connection;
+        let Some(used_version) = policy.negotiate_version(msg.min_version, msg.max_version) else {
+            info!(
+                "Rejecting connection: versions {}..={} not in the accepted {}..={}",
+                msg.min_version,
+                msg.max_version,
+                policy.min_version(),
+                policy.max_version()
+            );
+            return self
+                .reject_setup_connection("protocol-version-mismatch", 0)
+                .await;
+        };
+
+        let unsupported_flags = policy.unsupported_flags(msg.flags);
+        if unsupported_flags != 0 {
+            info!("Rejecting connection: unsupported flags {unsupported_flags:b}");
+            return self
+                .reject_setup_connection("unsupported-feature-flags", unsupported_flags)
+                .await;
+        }
+
+        let device = DeviceInfo {
+            vendor: msg.vendor.as_utf8_or_hex(),
+            hardware_version: msg.hardware_version.as_utf8_or_hex(),
+            firmware: msg.firmware.as_utf8_or_hex(),
+            device_id: msg.device_id.as_utf8_or_hex(),
+        };
+        match self.device_rules.iter().find(|rule| rule.matches(&device)) {
+            Some(rule) if rule.action() == DeviceAction::Reject => {
+                info!("Rejecting connection: device {device:?} matches a device rule");
+                return self.reject_setup_connection("unsupported-device", 0).await;
+            }
+            Some(_) => warn!("Device {device:?} matches a device rule, accepting it anyway"),
+            None => {}
+        }
+
         self.requires_custom_work
             .store(has_work_selection(msg.flags), Ordering::SeqCst);
         self.requires_standard_jobs
             .store(has_requires_std_job(msg.flags), Ordering::SeqCst);
 
         let response = SetupConnectionSuccess {
-            used_version: 2,
+            used_version,
             flags: msg.flags,
         };
         let frame: StdFrame = AnyMessage::Common(response.into_static().into()).try_into()?;
-        self.downstream_channel
-            .downstream_sender
-            .send(frame)
-            .await?;
+        self.downstream_sender.send(frame).await?;
+
+        self.events.publish(ConnectionSetUp {
+            connection_id: self.downstream_id,
+            address: self.address.to_string(),
+            version: used_version,
+            flags: msg.flags,
+            device: device.clone(),
+        });
+        _ = self.device.set(device);
 
         Ok(())
     }
@@ -0,0 +1,171 @@
+//! Dispatch of the messages received from a downstream.
+//!
+//! The I/O loop of a [`Downstream`](super::Downstream) reads the frames of the connection and
+//! hands each of them to a [`DownstreamMessageHandler`], only looking at its header. The default
+//! one, [`DownstreamDispatcher`], routes the frames by subprotocol to a sub-handler each:
+//!
+//! - [`CommonMessageHandler`] answers the `SetupConnection` opening the connection.
+//! - [`MiningMessageHandler`] forwards the mining messages to the channel manager.
+//! - [`JobDeclarationPassthrough`] forwards the job declaration messages, if given where to.
+//!
+//! The sub-handlers only talk to channels, so that they can be driven without a socket, and any
+//! of them can be replaced by another implementation of the trait to extend the role.
+
+use std::future::Future;
+
+use async_channel::Sender;
+use stratum_apps::{
+    runtime::message_type::{protocol_message_type, MessageType},
+    stratum_core::mining_sv2::{
+        MESSAGE_TYPE_CLOSE_CHANNEL, MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL, MESSAGE_TYPE_SET_CUSTOM_MINING_JOB,
+        MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED, MESSAGE_TYPE_SUBMIT_SHARES_STANDARD,
+        MESSAGE_TYPE_UPDATE_CHANNEL,
+    },
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+    error::{PoolError, PoolResult},
+    utils::SV2Frame,
+};
+
+pub use super::common_message_handler::CommonMessageHandler;
+
+/// Handles the frames received from a downstream.
+pub trait DownstreamMessageHandler: Send {
+    /// Handles `frame`, a message of type `message_type`.
+    ///
+    /// An error closes the connection of the downstream.
+    fn handle_frame(
+        &mut self,
+        message_type: u8,
+        frame: SV2Frame,
+    ) -> impl Future<Output = PoolResult<()>> + Send;
+}
+
+/// Routes the frames of a downstream to the sub-handler of their subprotocol.
+///
+/// Template distribution messages, and messages of unknown type, are ignored.
+pub struct DownstreamDispatcher<
+    C = CommonMessageHandler,
+    M = MiningMessageHandler,
+    J = JobDeclarationPassthrough,
+> {
+    /// Handler of the common messages.
+    pub common: C,
+    /// Handler of the mining messages.
+    pub mining: M,
+    /// Handler of the job declaration messages.
+    pub job_declaration: J,
+}
+
+impl<C, M, J> DownstreamMessageHandler for DownstreamDispatcher<C, M, J>
+where
+    C: DownstreamMessageHandler,
+    M: DownstreamMessageHandler,
+    J: DownstreamMessageHandler,
+{
+    async fn handle_frame(&mut self, message_type: u8, frame: SV2Frame) -> PoolResult<()> {
+        match protocol_message_type(message_type) {
+            MessageType::Common => self.common.handle_frame(message_type, frame).await,
+            MessageType::Mining => self.mining.handle_frame(message_type, frame).await,
+            MessageType::JobDeclaration => {
+                self.job_declaration.handle_frame(message_type, frame).await
+            }
+            MessageType::TemplateDistribution | MessageType::Unknown => {
+                warn!(
+                    ?message_type,
+                    "Received unsupported message type from downstream."
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Forwards the mining messages of a downstream to the channel manager.
+///
+/// The messages are forwarded as the frames they came in: the channel manager decodes them in
+/// place, so that relaying a message costs neither a decoding nor a copy of its payload. Only the
+/// messages a client sends to a server are forwarded: any other mining message closes the
+/// connection of the downstream instead of reaching the channel manager.
+pub struct MiningMessageHandler {
+    downstream_id: usize,
+    channel_manager_sender: Sender<(usize, SV2Frame)>,
+}
+
+impl MiningMessageHandler {
+    /// Creates a handler forwarding the messages of `downstream_id` to `channel_manager_sender`.
+    pub fn new(downstream_id: usize, channel_manager_sender: Sender<(usize, SV2Frame)>) -> Self {
+        Self {
+            downstream_id,
+            channel_manager_sender,
+        }
+    }
+}
+
+impl DownstreamMessageHandler for MiningMessageHandler {
+    async fn handle_frame(&mut self, message_type: u8, frame: SV2Frame) -> PoolResult<()> {
+        if !is_client_mining_message(message_type) {
+            return Err(PoolError::UnexpectedMessage(message_type));
+        }
+        debug!("Received mining SV2 frame from downstream.");
+        self.channel_manager_sender
+            .send((self.downstream_id, frame))
+            .await
+            .map_err(|e| {
+                error!(error=?e, "Failed to send mining message to channel manager.");
+                PoolError::ChannelErrorSender
+            })
+    }
+}
+
+/// Returns whether `message_type` is a mining message sent by a client to a server.
+fn is_client_mining_message(message_type: u8) -> bool {
+    matches!(
+        message_type,
+        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL
+            | MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL
+            | MESSAGE_TYPE_UPDATE_CHANNEL
+            | MESSAGE_TYPE_CLOSE_CHANNEL
+            | MESSAGE_TYPE_SUBMIT_SHARES_STANDARD
+            | MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED
+            | MESSAGE_TYPE_SET_CUSTOM_MINING_JOB
+    )
+}
+
+/// Forwards the job declaration messages of a downstream, as is, to another role.
+///
+/// The pool does not declare jobs itself: without a sender, e.g. to a co-located JDS, the messages
+/// are ignored.
+pub struct JobDeclarationPassthrough {
+    downstream_id: usize,
+    sender: Option<Sender<(usize, SV2Frame)>>,
+}
+
+impl JobDeclarationPassthrough {
+    /// Creates a handler forwarding the messages of `downstream_id` to `sender`, if any.
+    pub fn new(downstream_id: usize, sender: Option<Sender<(usize, SV2Frame)>>) -> Self {
+        Self {
+            downstream_id,
+            sender,
+        }
+    }
+}
+
+impl DownstreamMessageHandler for JobDeclarationPassthrough {
+    async fn handle_frame(&mut self, message_type: u8, frame: SV2Frame) -> PoolResult<()> {
+        let Some(sender) = &self.sender else {
+            warn!(
+                ?message_type,
+                "Received unsupported message type from downstream."
+            );
+            return Ok(());
+        };
+        sender.send((self.downstream_id, frame)).await.map_err(|e| {
+            error!(error=?e, "Failed to forward job declaration message.");
+            PoolError::ChannelErrorSender
+        })
+    }
+}
@@ -1,15 +1,20 @@
 use std::{
     collections::HashMap,
+    net::SocketAddr,
     sync::{
         atomic::{AtomicBool, AtomicUsize},
-        Arc,
+        Arc, OnceLock,
     },
+    time::Duration,
 };
 
-use async_channel::{unbounded, Receiver, Sender};
+use async_channel::{Receiver, Sender};
 use stratum_apps::{
+    connections::ConnectionMetadata,
     custom_mutex::Mutex,
-    network_helpers::noise_stream::NoiseTcpStream,
+    events::{DeviceInfo, EventBus},
+    network_helpers::{frame_pool::FramePool, traffic::ConnectionTraffic, transport::Sv2TcpStream},
+    runtime::{spawn_io_tasks, IoChannelsConfig, PrioritySender, TaskManager},
     stratum_core::{
         channels_sv2::server::{
             extended::ExtendedChannel,
@@ -18,25 +23,29 @@ use stratum_apps::{
             standard::StandardChannel,
         },
         common_messages_sv2::MESSAGE_TYPE_SETUP_CONNECTION,
-        handlers_sv2::HandleCommonMessagesFromClientAsync,
-        noise_sv2::Error,
-        parsers_sv2::{AnyMessage, Mining},
+        mining_sv2::{MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED, MESSAGE_TYPE_SUBMIT_SHARES_STANDARD},
     },
 };
 use tokio::sync::broadcast;
 use tracing::{debug, error, warn};
 
 use crate::{
+    config::{DeviceRule, SetupConnectionPolicy},
     error::{PoolError, PoolResult},
+    monitoring::ShareAckStats,
     status::{handle_error, Status, StatusSender},
-    task_manager::TaskManager,
-    utils::{
-        protocol_message_type, spawn_io_tasks, Message, MessageType, SV2Frame, ShutdownMessage,
-        StdFrame,
-    },
+    utils::{Message, SV2Frame, ShutdownMessage},
 };
 
 mod common_message_handler;
+pub mod message_handler;
+pub mod outbound;
+
+use message_handler::{
+    CommonMessageHandler, DownstreamDispatcher, DownstreamMessageHandler,
+    JobDeclarationPassthrough, MiningMessageHandler,
+};
+use outbound::OutboundQueue;
 
 /// Holds state related to a downstream connection's mining channels.
 ///
@@ -45,6 +54,10 @@ mod common_message_handler;
 /// - An optional [`GroupChannel`] if group channeling is used.
 /// - Active [`ExtendedChannel`]s keyed by channel ID.
 /// - Active [`StandardChannel`]s keyed by channel ID.
+///
+/// A downstream may hold standard and extended channels at the same time. Its standard channels
+/// are the members of its group channel, and receive its jobs through the group unless the
+/// downstream requires standard jobs, while each extended channel receives its own jobs.
 pub struct DownstreamData {
     pub group_channels: Option<GroupChannel<'static, DefaultJobStore<ExtendedJob<'static>>>>,
     pub extended_channels:
@@ -54,19 +67,75 @@ pub struct DownstreamData {
     pub channel_id_factory: AtomicUsize,
 }
 
+/// A channel removed from a downstream, whose resources are to be reclaimed.
+#[derive(Debug, Clone)]
+pub struct RemovedChannel {
+    pub channel_id: u32,
+    /// Whether the channel was an extended channel.
+    pub extended: bool,
+    pub user_identity: String,
+    pub extranonce_prefix: Vec<u8>,
+}
+
+impl DownstreamData {
+    /// Removes the channel `channel_id`, standard or extended, detaching it from the group
+    /// channel. The group channel is dropped along with the last standard channel, so that no
+    /// group jobs are sent to a downstream only left with extended channels.
+    ///
+    /// Returns the removed channel, `None` if the downstream has no such channel.
+    pub fn remove_channel(&mut self, channel_id: u32) -> Option<RemovedChannel> {
+        if let Some(channel) = self.extended_channels.remove(&channel_id) {
+            return Some(RemovedChannel {
+                channel_id,
+                extended: true,
+                user_identity: channel.get_user_identity().to_string(),
+                extranonce_prefix: channel.get_extranonce_prefix().to_vec(),
+            });
+        }
+        let channel = self.standard_channels.remove(&channel_id)?;
+        if let Some(group_channel) = self.group_channels.as_mut() {
+            group_channel.remove_standard_channel_id(channel_id);
+        }
+        if self.standard_channels.is_empty() {
+            self.group_channels = None;
+        }
+        Some(RemovedChannel {
+            channel_id,
+            extended: false,
+            user_identity: channel.get_user_identity().to_string(),
+            extranonce_prefix: channel.get_extranonce_prefix().to_vec(),
+        })
+    }
+
+    /// Removes every channel of the downstream, as [`Self::remove_channel`] does.
+    pub fn remove_all_channels(&mut self) -> Vec<RemovedChannel> {
+        let channel_ids: Vec<u32> = self
+            .standard_channels
+            .keys()
+            .chain(self.extended_channels.keys())
+            .copied()
+            .collect();
+        channel_ids
+            .into_iter()
+            .filter_map(|channel_id| self.remove_channel(channel_id))
+            .collect()
+    }
+}
+
 /// Communication layer for a downstream connection.
 ///
 /// Provides the messaging primitives for interacting with the
 /// channel manager and the downstream peer:
 /// - `channel_manager_sender`: sends frames to the channel manager.
-/// - `channel_manager_receiver`: receives messages from the channel manager.
 /// - `downstream_sender`: sends frames to the downstream.
 /// - `downstream_receiver`: receives frames from the downstream.
+///
+/// The channel manager queues its messages to the downstream through the [`OutboundQueue`] of
+/// the connection.
 #[derive(Clone)]
 pub struct DownstreamChannel {
-    channel_manager_sender: Sender<(usize, Mining<'static>)>,
-    channel_manager_receiver: broadcast::Sender<(usize, Mining<'static>)>,
-    downstream_sender: Sender<SV2Frame>,
+    channel_manager_sender: Sender<(usize, SV2Frame)>,
+    downstream_sender: PrioritySender,
     downstream_receiver: Receiver<SV2Frame>,
 }
 
@@ -76,40 +145,72 @@ pub struct Downstream {
     pub downstream_data: Arc<Mutex<DownstreamData>>,
     downstream_channel: DownstreamChannel,
     pub downstream_id: usize,
+    /// Address the downstream is connected from.
+    pub address: SocketAddr,
     pub requires_standard_jobs: Arc<AtomicBool>,
     pub requires_custom_work: Arc<AtomicBool>,
+    /// Device described by the `SetupConnection` of the downstream, once accepted.
+    pub device: Arc<OnceLock<DeviceInfo>>,
+    /// Frame and byte counters of the connection.
+    pub traffic: Arc<ConnectionTraffic>,
+    /// Buffer pool the messages of the channel manager are serialized into.
+    pub frame_pool: Arc<FramePool>,
+    /// Latencies of the acknowledgements of the shares of the downstream.
+    share_acks: Arc<ShareAckStats>,
+    /// Queue of the messages of the channel manager to the downstream.
+    outbound: OutboundQueue,
+    /// Protocol versions and flags accepted in the `SetupConnection` of the downstream.
+    setup_connection: SetupConnectionPolicy,
+    /// Rules applied to the device described in the `SetupConnection` of the downstream.
+    device_rules: Arc<[DeviceRule]>,
+    /// Bus the connection is published on once set up.
+    events: EventBus,
 }
 
 impl Downstream {
     /// Creates a new [`Downstream`] instance and spawns the necessary I/O tasks.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         downstream_id: usize,
-        channel_manager_sender: Sender<(usize, Mining<'static>)>,
-        channel_manager_receiver: broadcast::Sender<(usize, Mining<'static>)>,
-        noise_stream: NoiseTcpStream<Message>,
+        address: SocketAddr,
+        channel_manager_sender: Sender<(usize, SV2Frame)>,
+        stream: Sv2TcpStream<Message>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         task_manager: Arc<TaskManager>,
         status_sender: Sender<Status>,
+        idle_timeout: Option<Duration>,
+        setup_connection: SetupConnectionPolicy,
+        device_rules: Arc<[DeviceRule]>,
+        events: EventBus,
+        channels: IoChannelsConfig,
+        share_acks: Arc<ShareAckStats>,
     ) -> Self {
-        let (noise_stream_reader, noise_stream_writer) = noise_stream.into_split();
+        let (stream_reader, stream_writer) = stream.into_split();
         let status_sender = StatusSender::Downstream {
             downstream_id,
             tx: status_sender,
         };
-        let (inbound_tx, inbound_rx) = unbounded::<SV2Frame>();
-        let (outbound_tx, outbound_rx) = unbounded::<SV2Frame>();
-        spawn_io_tasks(
+        let (inbound_tx, inbound_rx) = channels.inbound_channel();
+        let (outbound_tx, outbound_rx) = channels.outbound_channel();
+        let traffic = spawn_io_tasks(
             task_manager,
-            noise_stream_reader,
-            noise_stream_writer,
+            stream_reader,
+            stream_writer,
             outbound_rx,
             inbound_tx,
             notify_shutdown,
             status_sender,
+            idle_timeout,
         );
 
+        let frame_pool = Arc::new(FramePool::default());
+        let outbound = OutboundQueue::new(
+            downstream_id,
+            outbound_tx.clone(),
+            frame_pool.clone(),
+            share_acks.clone(),
+        );
         let downstream_channel = DownstreamChannel {
-            channel_manager_receiver,
             channel_manager_sender,
             downstream_sender: outbound_tx,
             downstream_receiver: inbound_rx,
@@ -124,19 +225,73 @@ impl Downstream {
             downstream_channel,
             downstream_data,
             downstream_id,
+            address,
+            traffic,
+            frame_pool,
+            share_acks,
+            outbound,
             requires_standard_jobs: Arc::new(AtomicBool::new(false)),
             requires_custom_work: Arc::new(AtomicBool::new(false)),
+            device: Arc::new(OnceLock::new()),
+            setup_connection,
+            device_rules,
+            events,
+        }
+    }
+
+    /// Returns the queue the channel manager sends its messages to the downstream through.
+    pub fn outbound_queue(&self) -> OutboundQueue {
+        self.outbound.clone()
+    }
+
+    /// Returns the default handler of the messages of the downstream, dispatching them to the
+    /// sub-handlers of the pool.
+    pub fn message_handler(&self) -> DownstreamDispatcher {
+        DownstreamDispatcher {
+            common: CommonMessageHandler::new(
+                self.downstream_id,
+                self.address,
+                self.downstream_channel.downstream_sender.clone(),
+                self.setup_connection,
+                self.device_rules.clone(),
+                self.events.clone(),
+                self.requires_standard_jobs.clone(),
+                self.requires_custom_work.clone(),
+                self.device.clone(),
+            ),
+            mining: MiningMessageHandler::new(
+                self.downstream_id,
+                self.downstream_channel.channel_manager_sender.clone(),
+            ),
+            job_declaration: JobDeclarationPassthrough::new(self.downstream_id, None),
         }
     }
 
-    /// Starts the downstream loop.
+    /// Starts the downstream loop with the default [`message_handler`](Self::message_handler).
+    pub async fn start(
+        self,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+        status_sender: Sender<Status>,
+        task_manager: Arc<TaskManager>,
+    ) {
+        let message_handler = self.message_handler();
+        self.start_with_handler(
+            message_handler,
+            notify_shutdown,
+            status_sender,
+            task_manager,
+        )
+        .await
+    }
+
+    /// Starts the downstream loop, with `message_handler` handling the messages of the downstream.
     ///
     /// Responsibilities:
     /// - Performs the initial `SetupConnection` handshake with the downstream.
-    /// - Forwards mining-related messages to the channel manager.
-    /// - Forwards channel manager messages back to the downstream peer.
-    pub async fn start(
-        mut self,
+    /// - Hands the messages of the downstream to `message_handler`.
+    pub async fn start_with_handler<H: DownstreamMessageHandler + 'static>(
+        self,
+        mut message_handler: H,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         status_sender: Sender<Status>,
         task_manager: Arc<TaskManager>,
@@ -149,18 +304,19 @@ impl Downstream {
         let mut shutdown_rx = notify_shutdown.subscribe();
 
         // Setup initial connection
-        if let Err(e) = self.setup_connection_with_downstream().await {
+        if let Err(e) = self
+            .setup_connection_with_downstream(&mut message_handler)
+            .await
+        {
             error!(?e, "Failed to set up downstream connection");
             handle_error(&status_sender, e).await;
             return;
         }
 
-        let mut receiver = self.downstream_channel.channel_manager_receiver.subscribe();
         task_manager.spawn(async move {
             loop {
-                let self_clone_1 = self.clone();
-                let downstream_id = self_clone_1.downstream_id;
-                let self_clone_2 = self.clone();
+                let self_clone = self.clone();
+                let downstream_id = self_clone.downstream_id;
                 tokio::select! {
                     message = shutdown_rx.recv() => {
                         match message {
@@ -175,21 +331,13 @@ impl Downstream {
                             _ => {}
                         }
                     }
-                    res = self_clone_1.handle_downstream_mining_message() => {
+                    res = self_clone.handle_downstream_message(&mut message_handler) => {
                         if let Err(e) = res {
                             error!(?e, "Error handling downstream message for {downstream_id}");
                             handle_error(&status_sender, e).await;
                             break;
                         }
                     }
-                    res = self_clone_2.handle_channel_manager_message(&mut receiver) => {
-                        if let Err(e) = res {
-                            error!(?e, "Error handling channel manager message for {downstream_id}");
-                            handle_error(&status_sender, e).await;
-                            break;
-                        }
-                    }
-
                 }
             }
             warn!("Downstream: unified message loop exited.");
@@ -197,8 +345,11 @@ impl Downstream {
     }
 
     // Performs the initial handshake with a downstream peer.
-    async fn setup_connection_with_downstream(&mut self) -> PoolResult<()> {
-        let mut frame = self.downstream_channel.downstream_receiver.recv().await?;
+    async fn setup_connection_with_downstream(
+        &self,
+        message_handler: &mut impl DownstreamMessageHandler,
+    ) -> PoolResult<()> {
+        let frame = self.downstream_channel.downstream_receiver.recv().await?;
 
         let Some(message_type) = frame.get_header().map(|m| m.msg_type()) else {
             return Err(PoolError::UnexpectedMessage(0));
@@ -207,77 +358,57 @@ impl Downstream {
         // The first ever message received on a new downstream connection
         // should always be a setup connection message.
         if message_type == MESSAGE_TYPE_SETUP_CONNECTION {
-            self.handle_common_message_frame_from_client(None, message_type, frame.payload())
-                .await?;
-            return Ok(());
+            return message_handler.handle_frame(message_type, frame).await;
         }
         Err(PoolError::UnexpectedMessage(message_type))
     }
 
-    // Handles messages sent from the channel manager to this downstream.
-    async fn handle_channel_manager_message(
+    // Reads the next message of the downstream peer and hands it to `message_handler`, only
+    // looking at its header.
+    async fn handle_downstream_message(
         self,
-        receiver: &mut broadcast::Receiver<(usize, Mining<'static>)>,
+        message_handler: &mut impl DownstreamMessageHandler,
     ) -> PoolResult<()> {
-        let (downstream_id, msg) = match receiver.recv().await {
-            Ok(msg) => msg,
-            Err(e) => {
-                warn!(?e, "Broadcast receive failed");
-                return Ok(());
-            }
-        };
-
-        if downstream_id != self.downstream_id {
-            debug!(
-                ?downstream_id,
-                "Message ignored for non-matching downstream"
-            );
-            return Ok(());
-        }
-
-        let message = AnyMessage::Mining(msg);
-        let std_frame: StdFrame = message.try_into()?;
-
-        self.downstream_channel
-            .downstream_sender
-            .send(std_frame)
-            .await
-            .map_err(|e| {
-                error!(?e, "Downstream send failed");
-                PoolError::Noise(Error::ExpectedIncomingHandshakeMessage)
-            })?;
-
-        Ok(())
-    }
-
-    // Handles incoming messages from the downstream peer.
-    async fn handle_downstream_mining_message(self) -> PoolResult<()> {
         let mut sv2_frame = self.downstream_channel.downstream_receiver.recv().await?;
 
         let Some(message_type) = sv2_frame.get_header().map(|h| h.msg_type()) else {
             return Ok(());
         };
 
-        if protocol_message_type(message_type) != MessageType::Mining {
-            warn!(
-                ?message_type,
-                "Received unsupported message type from downstream."
-            );
-            return Ok(());
+        if matches!(
+            message_type,
+            MESSAGE_TYPE_SUBMIT_SHARES_STANDARD | MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED
+        ) {
+            if let Some((channel_id, sequence_number)) = share_ids(sv2_frame.payload()) {
+                self.share_acks
+                    .received(self.downstream_id, channel_id, sequence_number);
+            }
         }
 
-        let mining = Mining::try_from((message_type, sv2_frame.payload()))?.into_static();
-
-        debug!("Received mining SV2 frame from downstream.");
-        self.downstream_channel
-            .channel_manager_sender
-            .send((self.downstream_id, mining))
-            .await
-            .map_err(|e| {
-                error!(error=?e, "Failed to send mining message to channel manager.");
-                PoolError::ChannelErrorSender
-            })?;
+        message_handler.handle_frame(message_type, sv2_frame).await
+    }
+}
 
-        Ok(())
+// The group channels are not listed, only those the downstream opened.
+impl ConnectionMetadata for Downstream {
+    fn channels(&self) -> Vec<u32> {
+        self.downstream_data.super_safe_lock(|data| {
+            data.standard_channels
+                .keys()
+                .chain(data.extended_channels.keys())
+                .copied()
+                .collect()
+        })
     }
 }
+
+// Returns the channel id and sequence number leading the payload of the share messages and of
+// their acknowledgements.
+fn share_ids(payload: &[u8]) -> Option<(u32, u32)> {
+    let channel_id = payload.get(0..4)?.try_into().ok()?;
+    let sequence_number = payload.get(4..8)?.try_into().ok()?;
+    Some((
+        u32::from_le_bytes(channel_id),
+        u32::from_le_bytes(sequence_number),
+    ))
+}
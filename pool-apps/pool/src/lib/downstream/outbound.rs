@@ -0,0 +1,147 @@
+//! Outbound queues of the downstream connections.
+//!
+//! The channel manager queues its messages straight to the writer task of each connection,
+//! through the [`OutboundQueue`] registered for it in [`OutboundQueues`]. The frames of a
+//! connection are written in the order they were queued, whichever task queued them, except for
+//! share acknowledgements: a backlog of them is written after the jobs, targets and channel
+//! closures queued since.
+//!
+//! A frame queued to a full queue is handled as set by `downstream_channels.overflow`: the
+//! connection is closed, the frame is dropped if it is a share acknowledgement, or the channel
+//! manager waits for the downstream to catch up. The activations of the jobs of a new block are
+//! queued on the priority lane of the queues instead, which they never wait on nor overflow.
+
+use std::{collections::HashMap, sync::Arc};
+
+use stratum_apps::{
+    custom_mutex::Mutex,
+    network_helpers::frame_pool::FramePool,
+    runtime::PrioritySender,
+    stratum_core::mining_sv2::{
+        MESSAGE_TYPE_SUBMIT_SHARES_ERROR, MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+    },
+};
+use tracing::warn;
+
+use super::share_ids;
+use crate::{channel_manager::job_distribution::SharedFrame, monitoring::ShareAckStats};
+
+/// Queue of the frames to write to a downstream connection.
+#[derive(Clone)]
+pub struct OutboundQueue {
+    downstream_id: usize,
+    sender: PrioritySender,
+    frame_pool: Arc<FramePool>,
+    share_acks: Arc<ShareAckStats>,
+}
+
+impl OutboundQueue {
+    pub(crate) fn new(
+        downstream_id: usize,
+        sender: PrioritySender,
+        frame_pool: Arc<FramePool>,
+        share_acks: Arc<ShareAckStats>,
+    ) -> Self {
+        Self {
+            downstream_id,
+            sender,
+            frame_pool,
+            share_acks,
+        }
+    }
+
+    /// Copies `frame` into the buffer pool of the connection and queues it to its writer,
+    /// waiting for room in a full queue whose overflow policy is backpressure.
+    ///
+    /// Returns `false` if the connection is closed, or if `frame` overflowed its queue.
+    pub async fn send(&self, frame: &SharedFrame) -> bool {
+        if self
+            .sender
+            .send(frame.to_frame(&self.frame_pool))
+            .await
+            .is_err()
+        {
+            if self.sender.overflowed() {
+                warn!(
+                    "Outbound queue of downstream {} full, disconnecting it",
+                    self.downstream_id
+                );
+            }
+            return false;
+        }
+        // The sequence number of a success is the last one of the batch it acknowledges.
+        if matches!(
+            frame.message_type(),
+            MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS | MESSAGE_TYPE_SUBMIT_SHARES_ERROR
+        ) {
+            if let Some((channel_id, sequence_number)) = share_ids(frame.payload()) {
+                self.share_acks
+                    .acknowledged(self.downstream_id, channel_id, sequence_number);
+            }
+        }
+        true
+    }
+
+    /// Copies `frame` into the buffer pool of the connection and queues it to its writer on the
+    /// priority lane, without waiting, full queue or not.
+    ///
+    /// Returns `false` if the connection is closed.
+    pub fn send_priority(&self, frame: &SharedFrame) -> bool {
+        self.sender
+            .send_priority(frame.to_frame(&self.frame_pool))
+            .is_ok()
+    }
+
+    /// Returns how many share acknowledgements were dropped for overflowing the queue.
+    pub fn dropped(&self) -> u64 {
+        self.sender.dropped()
+    }
+}
+
+/// Outbound queues of the connected downstreams, by downstream id.
+#[derive(Clone, Default)]
+pub struct OutboundQueues(Arc<Mutex<HashMap<usize, OutboundQueue>>>);
+
+impl OutboundQueues {
+    /// Registers the queue of a new connection.
+    pub fn insert(&self, downstream_id: usize, queue: OutboundQueue) {
+        self.0
+            .super_safe_lock(|queues| queues.insert(downstream_id, queue));
+    }
+
+    /// Forgets the queue of a closed connection.
+    pub fn remove(&self, downstream_id: usize) {
+        self.0
+            .super_safe_lock(|queues| queues.remove(&downstream_id));
+    }
+
+    /// Queues `frame` to `downstream_id`, returns `false` if it is not connected.
+    pub async fn send(&self, downstream_id: usize, frame: &SharedFrame) -> bool {
+        let queue = self
+            .0
+            .super_safe_lock(|queues| queues.get(&downstream_id).cloned());
+        match queue {
+            Some(queue) => queue.send(frame).await,
+            None => false,
+        }
+    }
+
+    /// Queues each frame of `frames` to its downstream on the priority lane, in order and
+    /// without waiting, and returns to how many downstreams at least one frame was queued.
+    pub fn send_all_priority(&self, frames: &[(usize, SharedFrame)]) -> usize {
+        // Nothing waits, the lock is held for the whole activation.
+        let mut reached = Vec::new();
+        self.0.super_safe_lock(|queues| {
+            for (downstream_id, frame) in frames {
+                if let Some(queue) = queues.get(downstream_id) {
+                    if queue.send_priority(frame) {
+                        reached.push(*downstream_id);
+                    }
+                }
+            }
+        });
+        reached.sort_unstable();
+        reached.dedup();
+        reached.len()
+    }
+}
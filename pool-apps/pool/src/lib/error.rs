@@ -4,22 +4,27 @@ use std::{
     sync::{MutexGuard, PoisonError},
 };
 
-use stratum_apps::stratum_core::{
-    binary_sv2, bitcoin,
-    channels_sv2::{
-        server::{
-            error::{ExtendedChannelError, GroupChannelError, StandardChannelError},
-            share_accounting::ShareValidationError,
+use stratum_apps::{
+    exit::ExitStatus,
+    stratum_core::{
+        binary_sv2, bitcoin,
+        channels_sv2::{
+            server::{
+                error::{ExtendedChannelError, GroupChannelError, StandardChannelError},
+                share_accounting::ShareValidationError,
+            },
+            vardiff::error::VardiffError,
         },
-        vardiff::error::VardiffError,
+        codec_sv2, framing_sv2,
+        handlers_sv2::HandlerErrorType,
+        mining_sv2::ExtendedExtranonceError,
+        noise_sv2,
+        parsers_sv2::{Mining, ParserError},
     },
-    codec_sv2, framing_sv2,
-    handlers_sv2::HandlerErrorType,
-    mining_sv2::ExtendedExtranonceError,
-    noise_sv2,
-    parsers_sv2::{Mining, ParserError},
 };
 
+use crate::extranonce::ExtranonceError;
+
 pub type PoolResult<T> = Result<T, PoolError>;
 
 #[derive(Debug)]
@@ -89,6 +94,36 @@ pub enum PoolError {
     ParseInt(std::num::ParseIntError),
     /// Failed to create group channel
     FailedToCreateGroupChannel(GroupChannelError),
+    /// No frame received from the peer within the idle timeout
+    IdleTimeout(std::time::Duration),
+    /// Error opening the persistence backend
+    Persistence(stratum_apps::persistence::Error),
+    /// `SetupConnection` of a downstream refused with the given error code
+    SetupConnectionRejected(&'static str),
+    /// The coinbase additions of the pool exceed a limit
+    CoinbaseConstraints(String),
+    /// The configuration is invalid
+    Config(String),
+    /// The listening address cannot be bound
+    Bind(std::net::SocketAddr, std::io::Error),
+    /// No connection to the Template Provider could be established
+    TemplateProviderUnreachable(String),
+    /// No extranonce prefix could be allocated
+    Extranonce(ExtranonceError),
+}
+
+impl PoolError {
+    /// Returns the exit status of the Pool process when it stops on this error.
+    pub fn exit_status(&self) -> ExitStatus {
+        use PoolError::*;
+        match self {
+            Config(_) | CoinbaseOutput(_) | CoinbaseConstraints(_) => ExitStatus::Config,
+            Noise(_) => ExitStatus::Key,
+            Bind(..) => ExitStatus::Bind,
+            TemplateProviderUnreachable(_) => ExitStatus::Unreachable,
+            _ => ExitStatus::Failure,
+        }
+    }
 }
 
 impl std::fmt::Display for PoolError {
@@ -139,6 +174,16 @@ impl std::fmt::Display for PoolError {
             FailedToCreateGroupChannel(ref e) => {
                 write!(f, "Failed to create group channel: {e:?}")
             }
+            IdleTimeout(timeout) => write!(f, "No frame received for {timeout:?}"),
+            Persistence(e) => write!(f, "Persistence error: `{e}`"),
+            SetupConnectionRejected(code) => write!(f, "SetupConnection rejected: `{code}`"),
+            CoinbaseConstraints(e) => write!(f, "Coinbase additions do not fit: {e}"),
+            Config(e) => write!(f, "Invalid configuration: {e}"),
+            Bind(address, e) => write!(f, "Failed to bind {address}: {e}"),
+            TemplateProviderUnreachable(address) => {
+                write!(f, "Template Provider unreachable at {address}")
+            }
+            Extranonce(e) => write!(f, "Extranonce prefix allocation failed: {e}"),
         }
     }
 }
@@ -149,6 +194,12 @@ impl From<std::io::Error> for PoolError {
     }
 }
 
+impl From<stratum_apps::persistence::Error> for PoolError {
+    fn from(e: stratum_apps::persistence::Error) -> PoolError {
+        PoolError::Persistence(e)
+    }
+}
+
 impl From<async_channel::RecvError> for PoolError {
     fn from(e: async_channel::RecvError) -> PoolError {
         PoolError::ChannelRecv(e)
@@ -248,6 +299,12 @@ impl From<ExtendedExtranonceError> for PoolError {
     }
 }
 
+impl From<ExtranonceError> for PoolError {
+    fn from(value: ExtranonceError) -> Self {
+        PoolError::Extranonce(value)
+    }
+}
+
 impl From<VardiffError> for PoolError {
     fn from(value: VardiffError) -> Self {
         PoolError::Vardiff(value)
@@ -0,0 +1,279 @@
+//! ## Extranonce Module
+//!
+//! Allocation of the extranonce prefixes of the downstream channels. A prefix is made of the
+//! `server_id` of the pool, big-endian, followed by a 4 byte index: pools of distinct server ids
+//! never hand out overlapping prefixes, and each of them can serve 2^32 channels at once.
+//! Extended channels roll the [`CLIENT_SEARCH_SPACE_BYTES`] following their prefix, while the
+//! prefix of a standard channel is padded with zeros to the [`FULL_EXTRANONCE_SIZE`]. Both kinds
+//! draw their index from the same space, so that their extranonces never overlap.
+//!
+//! The index of a closed channel is handed out again, lowest first, before any fresh one. With an
+//! `[extranonce_allocator]` table, the fresh indices are leased by blocks of [`LEASE_SIZE`] from
+//! a state file, and the next run of the pool starts after the last block leased, even after a
+//! crash. The miners of a previous run still submitting work on their old prefixes then cannot
+//! collide with the channels of the new one.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fmt,
+    path::{Path, PathBuf},
+};
+use stratum_apps::state_file::write_json_atomically;
+use tracing::{info, warn};
+
+use crate::error::PoolError;
+
+/// Size of the index following the server id in a prefix.
+const INDEX_BYTES: usize = 4;
+/// Size of the prefix of the extended channels, the server id followed by the index.
+pub const PREFIX_SIZE: usize = 2 + INDEX_BYTES;
+/// Size of the extranonce rolled by the extended channels after their prefix.
+pub const CLIENT_SEARCH_SPACE_BYTES: usize = 16;
+/// Size of the extranonce of every channel.
+pub const FULL_EXTRANONCE_SIZE: usize = PREFIX_SIZE + CLIENT_SEARCH_SPACE_BYTES;
+/// Number of fresh indices leased from the state file at once.
+pub const LEASE_SIZE: u64 = 1 << 16;
+/// Number of indices of a server id.
+const INDEX_SPACE: u64 = 1 << (8 * INDEX_BYTES);
+
+/// Error allocating an extranonce prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtranonceError {
+    /// The extranonce size requested by an extended channel exceeds the space left after the
+    /// prefix.
+    MinExtranonceSizeTooLarge,
+    /// Every index of the server id is in use.
+    Exhausted,
+}
+
+impl ExtranonceError {
+    /// Returns the error code of the `OpenMiningChannelError` refusing the channel.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Self::MinExtranonceSizeTooLarge => "min-extranonce-size-too-large",
+            Self::Exhausted => "extranonce-prefixes-exhausted",
+        }
+    }
+}
+
+impl fmt::Display for ExtranonceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MinExtranonceSizeTooLarge => write!(
+                f,
+                "requested extranonce size exceeds the {CLIENT_SEARCH_SPACE_BYTES} bytes available"
+            ),
+            Self::Exhausted => write!(f, "every extranonce prefix is in use"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AllocatorState {
+    server_id: u16,
+    // First index the next run of the pool may hand out.
+    next_index: u64,
+}
+
+// State file the fresh indices are leased from.
+#[derive(Debug)]
+struct Lease {
+    path: PathBuf,
+    // Index up to which fresh indices are leased, excluded.
+    leased_until: u64,
+}
+
+/// Allocator of the extranonce prefixes of the pool of `server_id`.
+#[derive(Debug)]
+pub struct ExtranonceAllocator {
+    server_id: u16,
+    // Number of indices of the server id, `INDEX_SPACE` but in tests.
+    index_space: u64,
+    // Next fresh index.
+    next_index: u64,
+    // Indices of the closed channels, handed out again first.
+    released: BTreeSet<u32>,
+    lease: Option<Lease>,
+}
+
+impl ExtranonceAllocator {
+    /// Creates an allocator handing out the indices of `server_id` from the first one.
+    pub fn new(server_id: u16) -> Self {
+        Self {
+            server_id,
+            index_space: INDEX_SPACE,
+            next_index: 0,
+            released: BTreeSet::new(),
+            lease: None,
+        }
+    }
+
+    /// Creates an allocator handing out the indices of `server_id` after the last ones leased
+    /// from the state file at `path`, which is created if missing.
+    ///
+    /// Fails if the state file cannot be read, parsed or written.
+    pub fn load(server_id: u16, path: &Path) -> Result<Self, PoolError> {
+        let next_index = match std::fs::read(path) {
+            Ok(bytes) => {
+                let state: AllocatorState = serde_json::from_slice(&bytes).map_err(|e| {
+                    PoolError::Config(format!(
+                        "invalid extranonce allocator state {}: {e}",
+                        path.display()
+                    ))
+                })?;
+                if state.server_id == server_id {
+                    state.next_index
+                } else {
+                    warn!(
+                        "Extranonce allocator state {} is for server id {}, starting over",
+                        path.display(),
+                        state.server_id
+                    );
+                    0
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(PoolError::Io(e)),
+        };
+        let mut allocator = Self {
+            server_id,
+            index_space: INDEX_SPACE,
+            next_index,
+            released: BTreeSet::new(),
+            lease: Some(Lease {
+                path: path.to_path_buf(),
+                leased_until: next_index,
+            }),
+        };
+        allocator.extend_lease().map_err(PoolError::Io)?;
+        info!("Allocating extranonce prefixes from index {next_index}");
+        Ok(allocator)
+    }
+
+    /// Returns the prefix of a new extended channel, leaving at least `min_rollable_size` bytes
+    /// of extranonce to roll.
+    pub fn next_extended(&mut self, min_rollable_size: usize) -> Result<Vec<u8>, ExtranonceError> {
+        if min_rollable_size > CLIENT_SEARCH_SPACE_BYTES {
+            return Err(ExtranonceError::MinExtranonceSizeTooLarge);
+        }
+        let index = self.next_index()?;
+        Ok(self.prefix(index))
+    }
+
+    /// Returns the prefix of a new standard channel, the whole extranonce.
+    pub fn next_standard(&mut self) -> Result<Vec<u8>, ExtranonceError> {
+        let index = self.next_index()?;
+        let mut prefix = self.prefix(index);
+        prefix.resize(FULL_EXTRANONCE_SIZE, 0);
+        Ok(prefix)
+    }
+
+    /// Hands the index of the prefix of a closed channel out again. Prefixes not allocated by
+    /// this allocator are ignored.
+    pub fn release(&mut self, prefix: &[u8]) {
+        if prefix.len() != PREFIX_SIZE && prefix.len() != FULL_EXTRANONCE_SIZE {
+            return;
+        }
+        if prefix[..2] != self.server_id.to_be_bytes() {
+            return;
+        }
+        let index = u32::from_be_bytes(
+            prefix[2..PREFIX_SIZE]
+                .try_into()
+                .expect("index must be 4 bytes"),
+        );
+        // Indices never handed out by this allocator, e.g. of resumed channels, would be handed
+        // out twice.
+        if u64::from(index) < self.next_index {
+            self.released.insert(index);
+        }
+    }
+
+    fn prefix(&self, index: u32) -> Vec<u8> {
+        let mut prefix = Vec::with_capacity(FULL_EXTRANONCE_SIZE);
+        prefix.extend_from_slice(&self.server_id.to_be_bytes());
+        prefix.extend_from_slice(&index.to_be_bytes());
+        prefix
+    }
+
+    fn next_index(&mut self) -> Result<u32, ExtranonceError> {
+        if let Some(index) = self.released.pop_first() {
+            return Ok(index);
+        }
+        if self.next_index >= self.index_space {
+            return Err(ExtranonceError::Exhausted);
+        }
+        if self
+            .lease
+            .as_ref()
+            .is_some_and(|lease| self.next_index >= lease.leased_until)
+        {
+            if let Err(e) = self.extend_lease() {
+                warn!("Failed to lease the next extranonce prefixes: {e}");
+            }
+        }
+        let index = self.next_index as u32;
+        self.next_index += 1;
+        Ok(index)
+    }
+
+    // Leases the next block of fresh indices from the state file.
+    fn extend_lease(&mut self) -> std::io::Result<()> {
+        let Some(lease) = self.lease.as_mut() else {
+            return Ok(());
+        };
+        let leased_until = (self.next_index + LEASE_SIZE).min(self.index_space);
+        let state = AllocatorState {
+            server_id: self.server_id,
+            next_index: leased_until,
+        };
+        write_json_atomically(&lease.path, &state)?;
+        lease.leased_until = leased_until;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_channels_exhaust_a_small_prefix_space() {
+        let mut allocator = ExtranonceAllocator {
+            index_space: 2,
+            ..ExtranonceAllocator::new(7)
+        };
+        let first = allocator.next_standard().unwrap();
+        let second = allocator.next_standard().unwrap();
+        assert_eq!(first.len(), FULL_EXTRANONCE_SIZE);
+        assert_eq!(first[..2], 7u16.to_be_bytes());
+        assert_ne!(first, second);
+
+        let error = allocator.next_standard().unwrap_err();
+        assert_eq!(error, ExtranonceError::Exhausted);
+        assert_eq!(error.error_code(), "extranonce-prefixes-exhausted");
+        assert_eq!(allocator.next_extended(0), Err(ExtranonceError::Exhausted));
+
+        // The prefix of a channel failing to open is handed out again.
+        allocator.release(&second);
+        assert_eq!(allocator.next_standard().unwrap(), second);
+        assert_eq!(allocator.next_standard(), Err(ExtranonceError::Exhausted));
+    }
+
+    #[test]
+    fn extended_channels_cannot_roll_more_than_the_client_search_space() {
+        let mut allocator = ExtranonceAllocator::new(0);
+        let error = allocator
+            .next_extended(CLIENT_SEARCH_SPACE_BYTES + 1)
+            .unwrap_err();
+        assert_eq!(error.error_code(), "min-extranonce-size-too-large");
+        assert_eq!(
+            allocator
+                .next_extended(CLIENT_SEARCH_SPACE_BYTES)
+                .unwrap()
+                .len(),
+            PREFIX_SIZE
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! ## Lifetime Module
+//!
+//! Statistics of the pool over its whole lifetime rather than since startup, for "mining since"
+//! displays: when the pool first ran, the shares it accepted and their work, the blocks it found,
+//! and when every user identity got its first share accepted. [`Lifetime`] records them from the
+//! events of the pool, and with a `[lifetime]` table saves them to a state file every
+//! `save_interval_secs` and on shutdown, restoring them from it at startup. They are served in the
+//! `lifetime` object of the monitoring endpoint.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use stratum_apps::{
+    custom_mutex::Mutex, events::Event, state_file::write_json_atomically, unix_time,
+};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::{error::PoolError, utils::ShutdownMessage};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Counters {
+    since_ms: u64,
+    shares: u64,
+    work: f64,
+    blocks: u64,
+    first_seen_ms: BTreeMap<String, u64>,
+}
+
+impl Counters {
+    fn new() -> Self {
+        Self {
+            since_ms: unix_time::now_ms(),
+            shares: 0,
+            work: 0.0,
+            blocks: 0,
+            first_seen_ms: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LifetimeFile {
+    saved_ms: u64,
+    #[serde(flatten)]
+    counters: Counters,
+}
+
+/// Lifetime statistics of the pool, saved to a state file.
+#[derive(Debug)]
+pub struct Lifetime {
+    counters: Mutex<Counters>,
+    // State file, locked while written.
+    path: Mutex<PathBuf>,
+}
+
+impl Lifetime {
+    /// Restores the statistics from the state file at `path`, or starts them from now if there is
+    /// none, and saves them there from then on.
+    ///
+    /// Fails if the state file cannot be read or parsed, rather than resetting the statistics.
+    pub fn load(path: &Path) -> Result<Self, PoolError> {
+        let counters = match std::fs::read(path) {
+            Ok(bytes) => {
+                let file: LifetimeFile = serde_json::from_slice(&bytes).map_err(|e| {
+                    PoolError::Config(format!(
+                        "invalid lifetime statistics {}: {e}",
+                        path.display()
+                    ))
+                })?;
+                info!(
+                    "Restored the lifetime statistics saved {}s ago: {} shares, work {}, {} blocks, {} users",
+                    unix_time::now_ms().saturating_sub(file.saved_ms) / 1000,
+                    file.counters.shares,
+                    file.counters.work,
+                    file.counters.blocks,
+                    file.counters.first_seen_ms.len()
+                );
+                file.counters
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Counters::new(),
+            Err(e) => return Err(PoolError::Io(e)),
+        };
+        Ok(Self {
+            counters: Mutex::new(counters),
+            path: Mutex::new(path.to_path_buf()),
+        })
+    }
+
+    /// Writes the statistics to the state file.
+    pub fn save(&self) -> std::io::Result<()> {
+        // The statistics are read while the file is locked, so that the last ones written are
+        // the latest.
+        self.path.super_safe_lock(|path| {
+            let file = LifetimeFile {
+                saved_ms: unix_time::now_ms(),
+                counters: self.counters.super_safe_lock(|counters| counters.clone()),
+            };
+            write_json_atomically(path, &file)
+        })
+    }
+
+    /// Saves the statistics every `interval` until the pool shuts down.
+    pub async fn save_periodically(
+        self: Arc<Self>,
+        interval: Duration,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    ) {
+        let mut shutdown_rx = notify_shutdown.subscribe();
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                message = shutdown_rx.recv() => match message {
+                    Ok(ShutdownMessage::ShutdownAll) | Err(_) => break,
+                    Ok(_) => {}
+                },
+                _ = interval.tick() => {
+                    if let Err(e) = self.save() {
+                        warn!("Failed to save the lifetime statistics: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Counts the accepted shares and the blocks found among the events of the pool.
+    pub fn record(&self, event: &Event) {
+        match event {
+            Event::ShareValidated(share) if share.is_accepted() => {
+                self.counters.super_safe_lock(|counters| {
+                    counters.shares += 1;
+                    counters.work += share.target_difficulty.unwrap_or_default();
+                    if let Some(user_identity) = &share.user_identity {
+                        if !counters.first_seen_ms.contains_key(user_identity) {
+                            counters
+                                .first_seen_ms
+                                .insert(user_identity.clone(), unix_time::now_ms());
+                        }
+                    }
+                })
+            }
+            Event::BlockFound(_) => self
+                .counters
+                .super_safe_lock(|counters| counters.blocks += 1),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> LifetimeStatus {
+        self.counters.super_safe_lock(|counters| LifetimeStatus {
+            since_ms: counters.since_ms,
+            shares: counters.shares,
+            work: counters.work,
+            blocks: counters.blocks,
+            users: counters.first_seen_ms.len(),
+            first_seen_ms: counters.first_seen_ms.clone(),
+        })
+    }
+}
+
+/// Lifetime statistics, as reported on the monitoring endpoint.
+#[derive(Debug, Serialize)]
+pub(crate) struct LifetimeStatus {
+    since_ms: u64,
+    shares: u64,
+    work: f64,
+    blocks: u64,
+    users: usize,
+    first_seen_ms: BTreeMap<String, u64>,
+}
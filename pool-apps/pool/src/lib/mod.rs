@@ -1,49 +1,140 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc, time::Duration};
 
 use async_channel::unbounded;
-use stratum_apps::stratum_core::{
-    bitcoin::consensus::Encodable, parsers_sv2::TemplateDistribution,
+use stratum_apps::{
+    banlist::BanList,
+    events::{Event, EventBus},
+    network_helpers::transport::Encryption,
+    persistence::Persistence,
+    runtime::TaskManager,
+    share_reject::ShareRejectCounters,
+    status::HealthAggregator,
+    stratum_core::{bitcoin::consensus::Encodable, parsers_sv2::TemplateDistribution},
+    webhook::Webhook,
 };
 use tokio::sync::broadcast;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 
+#[cfg(feature = "control")]
+use crate::control::{ConfigLoader, PoolControl};
 use crate::{
-    channel_manager::ChannelManager,
-    config::PoolConfig,
-    error::PoolResult,
-    status::{State, Status},
-    task_manager::TaskManager,
-    template_receiver::TemplateReceiver,
-    utils::ShutdownMessage,
+    authority::AuthorityKeys,
+    channel_manager::{ChannelManager, FULL_EXTRANONCE_SIZE},
+    channel_updates::ChannelUpdates,
+    config::{NotableSharesConfig, PoolConfig},
+    coordination::Coordinator,
+    error::{PoolError, PoolResult},
+    lifetime::Lifetime,
+    monitoring::{BestShares, LatencyStats, PoolStatusProvider, ShareAckStats, TemplateStats},
+    pool_signature::PoolSignature,
+    rounds::Rounds,
+    sessions::Sessions,
+    status::{report_task_panics, State, Status},
+    template_receiver::{CoinbaseConstraints, TemplateReceiver},
+    utils::{SV2Frame, ShutdownMessage},
+    workers::Workers,
 };
 
+pub mod authority;
 pub mod channel_manager;
+pub mod channel_updates;
 pub mod config;
+#[cfg(feature = "control")]
+pub mod control;
+pub mod coordination;
 pub mod downstream;
 pub mod error;
+pub mod extranonce;
+pub mod lifetime;
+pub mod monitoring;
+pub mod notable_shares;
+#[cfg(feature = "payments")]
+pub mod payments;
+pub mod pool_signature;
+pub mod rounds;
+pub mod sessions;
 pub mod status;
-pub mod task_manager;
 pub mod template_receiver;
 pub mod utils;
+pub mod workers;
+
+// Components reported to the health aggregator
+const TEMPLATE_RECEIVER: &str = "template_receiver";
+const CHANNEL_MANAGER: &str = "channel_manager";
+const SHARE_ACKS: &str = "share_acks";
+
+// Conditions the pool is ready on, reported to the health aggregator
+const TP_CONNECTED: &str = "tp_connected";
+const FRESH_TEMPLATE: &str = "fresh_template";
+const LISTENER_BOUND: &str = "listener_bound";
 
 #[derive(Debug, Clone)]
 pub struct PoolSv2 {
     config: PoolConfig,
     notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    authority_keys: AuthorityKeys,
+    events: EventBus,
+    #[cfg(feature = "control")]
+    config_loader: Option<ConfigLoader>,
 }
 
 impl PoolSv2 {
     pub fn new(config: PoolConfig) -> Self {
         let (notify_shutdown, _) = tokio::sync::broadcast::channel::<ShutdownMessage>(100);
+        let authority_keys = AuthorityKeys::new(
+            *config.authority_public_key(),
+            *config.authority_secret_key(),
+            Duration::from_secs(config.cert_validity_sec()),
+        );
         Self {
             config,
             notify_shutdown,
+            authority_keys,
+            events: EventBus::default(),
+            #[cfg(feature = "control")]
+            config_loader: None,
         }
     }
 
+    /// Sets how the configuration is loaded again when the control service reloads it.
+    #[cfg(feature = "control")]
+    pub fn with_config_loader(mut self, config_loader: ConfigLoader) -> Self {
+        self.config_loader = Some(config_loader);
+        self
+    }
+
+    /// Returns the handle used to rotate the authority keypair while the Pool is running.
+    pub fn authority_keys(&self) -> AuthorityKeys {
+        self.authority_keys.clone()
+    }
+
+    /// Returns the bus on which the Pool publishes its shares, blocks, connections and templates,
+    /// for subscribers attached before it is started.
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
     /// Starts the Pool main loop.
     pub async fn start(&self) -> PoolResult<()> {
+        self.config.check_network().map_err(|e| {
+            error!("Configuration does not match its network: {e}");
+            PoolError::Config(e)
+        })?;
+        self.authority_keys.responder().map_err(|e| {
+            error!("Invalid authority keypair: {e:?}");
+            PoolError::Noise(e)
+        })?;
+        let pool_signature = PoolSignature::parse(self.config.pool_signature()).map_err(|e| {
+            error!("Invalid pool signature: {e}");
+            PoolError::Config(e)
+        })?;
         let coinbase_outputs = vec![self.config.get_txout()];
+        let coinbase_constraints =
+            CoinbaseConstraints::new(&coinbase_outputs, &pool_signature, FULL_EXTRANONCE_SIZE);
+        if let Err(e) = coinbase_constraints.to_message() {
+            error!("Coinbase additions of the pool do not fit: {e} ({coinbase_constraints})");
+            return Err(PoolError::CoinbaseConstraints(e));
+        }
         let mut encoded_outputs = vec![];
 
         coinbase_outputs
@@ -55,16 +146,103 @@ impl PoolSv2 {
         let task_manager = Arc::new(TaskManager::new());
 
         let (status_sender, status_receiver) = async_channel::unbounded::<Status>();
+        report_task_panics(&task_manager, status_sender.clone());
+
+        let health = Arc::new(HealthAggregator::new());
+        // The pool is not ready until it can hand jobs to the downstreams, nor once drained.
+        for condition in [TP_CONNECTED, FRESH_TEMPLATE, LISTENER_BOUND] {
+            health.set_condition(condition, false);
+        }
+        health.set_ready(true);
+        let banlist = Arc::new(BanList::new(self.config.banlist())?);
+        let share_rejects = Arc::new(ShareRejectCounters::new());
+        let job_activations = Arc::new(LatencyStats::new());
+        let template_stats = Arc::new(TemplateStats::new(self.config.template_staleness()));
+        let share_acks = Arc::new(ShareAckStats::new(self.config.share_ack_slo()));
+        let best_shares = Arc::new(BestShares::new());
+        let rounds = Arc::new(match self.config.accounting() {
+            Some(accounting) => Rounds::load(accounting.path())?,
+            None => Rounds::new(),
+        });
+        let workers = Arc::new(Workers::new());
+        let lifetime = match self.config.lifetime() {
+            Some(config) => Some(Arc::new(Lifetime::load(config.path())?)),
+            None => None,
+        };
+        let channel_updates = Arc::new(ChannelUpdates::new());
+        let sessions = Arc::new(
+            self.config
+                .session_resumption()
+                .map_or_else(Sessions::default, |config| {
+                    Sessions::load(config, self.config.server_id())
+                }),
+        );
+        let persistence =
+            Persistence::new(self.config.persistence())?.with_network(self.config.network());
+        if persistence.is_enabled() {
+            self.events
+                .attach(&task_manager, "persistence", persistence.clone());
+        }
+        if let Some(url) = self
+            .config
+            .notable_shares()
+            .and_then(NotableSharesConfig::webhook_url)
+        {
+            let webhook = Webhook::spawn(&task_manager, "notable_share_webhook", url)
+                .map_err(PoolError::Config)?;
+            self.events
+                .attach(&task_manager, "notable_shares", move |event: &Event| {
+                    if let Event::ShareValidated(share) = event {
+                        if share.notable {
+                            webhook.notify(share);
+                        }
+                    }
+                });
+        }
+        let coordination = self.config.coordination().cloned().map(|config| {
+            let coordinator = Coordinator::spawn(
+                config,
+                self.config.server_id(),
+                &task_manager,
+                notify_shutdown.clone(),
+            );
+            self.events
+                .attach(&task_manager, "coordination", coordinator.clone());
+            coordinator.stats()
+        });
+        let mut status_provider = PoolStatusProvider::new(
+            health.clone(),
+            banlist.clone(),
+            share_rejects.clone(),
+            job_activations.clone(),
+            template_stats.clone(),
+            share_acks.clone(),
+            best_shares.clone(),
+            rounds.clone(),
+            workers.clone(),
+            channel_updates.clone(),
+            self.config.payout_addresses().map(Path::to_path_buf),
+            self.config.worker_identity().separator().to_string(),
+            task_manager.clone(),
+        );
+        if let Some(stats) = coordination {
+            status_provider = status_provider.with_coordination(stats);
+        }
+        if let Some(lifetime) = lifetime.clone() {
+            self.events.attach(&task_manager, "lifetime", {
+                let lifetime = lifetime.clone();
+                move |event: &Event| lifetime.record(event)
+            });
+            status_provider = status_provider.with_lifetime(lifetime);
+        }
 
-        let (channel_manager_to_downstream_sender, _channel_manager_to_downstream_receiver) =
-            broadcast::channel(10);
         let (downstream_to_channel_manager_sender, downstream_to_channel_manager_receiver) =
             unbounded();
 
         let (channel_manager_to_tp_sender, channel_manager_to_tp_receiver) =
             unbounded::<TemplateDistribution<'static>>();
         let (tp_to_channel_manager_sender, tp_to_channel_manager_receiver) =
-            unbounded::<TemplateDistribution<'static>>();
+            unbounded::<SV2Frame>();
 
         debug!("Channels initialized.");
 
@@ -72,20 +250,63 @@ impl PoolSv2 {
             self.config.clone(),
             channel_manager_to_tp_sender,
             tp_to_channel_manager_receiver,
-            channel_manager_to_downstream_sender.clone(),
             downstream_to_channel_manager_receiver,
-            encoded_outputs.clone(),
+            encoded_outputs,
+            banlist.clone(),
+            share_rejects,
+            job_activations,
+            template_stats.clone(),
+            share_acks.clone(),
+            best_shares,
+            rounds.clone(),
+            workers,
+            channel_updates,
+            persistence,
+            self.events.clone(),
+            sessions,
         )
         .await?;
 
+        // Served once the channel manager exists, for the vardiff state of its channels.
+        let status_provider =
+            Arc::new(status_provider.with_channel_manager(channel_manager.clone()));
+        if let Some(monitoring_config) = self.config.monitoring().cloned() {
+            stratum_apps::monitoring::spawn(
+                &task_manager,
+                monitoring_config,
+                status_provider.clone(),
+                notify_shutdown.subscribe(),
+                |message| matches!(message, ShutdownMessage::ShutdownAll),
+            );
+        }
+
+        #[cfg(feature = "control")]
+        if let Some(control_config) = self.config.control().cloned() {
+            stratum_apps::control::spawn(
+                &task_manager,
+                control_config,
+                Arc::new(PoolControl::new(
+                    status_provider,
+                    channel_manager.clone(),
+                    health.clone(),
+                    banlist,
+                    self.authority_keys.clone(),
+                    self.config_loader.clone(),
+                )),
+                notify_shutdown.subscribe(),
+                |message| matches!(message, ShutdownMessage::ShutdownAll),
+            );
+        }
+
         let channel_manager_clone = channel_manager.clone();
 
         // Initialize the template Receiver
-        let tp_address = self.config.tp_address().to_string();
-        let tp_pubkey = self.config.tp_authority_public_key().copied();
+        let tp_address = self.config.tp_address();
+        let tp_pubkey = self.config.tp_authority_public_key().cloned();
 
         let template_receiver = TemplateReceiver::new(
             tp_address.clone(),
+            self.config.tcp().clone(),
             tp_pubkey,
             channel_manager_to_tp_receiver,
             tp_to_channel_manager_sender,
@@ -103,37 +324,91 @@ impl PoolSv2 {
                 notify_shutdown.clone(),
                 status_sender.clone(),
                 task_manager.clone(),
-                encoded_outputs,
+                coinbase_constraints,
             )
             .await?;
+        health.healthy(TEMPLATE_RECEIVER);
+        health.set_condition(TP_CONNECTED, true);
+        task_manager.spawn_named(
+            "template_staleness_monitor",
+            template_stats.monitor_staleness(health.clone(), notify_shutdown.clone()),
+        );
+        task_manager.spawn_named(
+            "share_ack_monitor",
+            share_acks.monitor(health.clone(), notify_shutdown.clone()),
+        );
+        if let (Some(config), Some(lifetime)) = (self.config.lifetime(), &lifetime) {
+            task_manager.spawn_named(
+                "lifetime_save",
+                lifetime
+                    .clone()
+                    .save_periodically(config.save_interval(), notify_shutdown.clone()),
+            );
+        }
+        if let Some(accounting) = self.config.accounting() {
+            task_manager.spawn_named(
+                "accounting_snapshot",
+                rounds
+                    .clone()
+                    .snapshot_periodically(accounting.snapshot_interval(), notify_shutdown.clone()),
+            );
+        }
 
         channel_manager
+            .clone()
             .start(
                 notify_shutdown.clone(),
                 status_sender.clone(),
                 task_manager.clone(),
             )
             .await?;
+        health.healthy(CHANNEL_MANAGER);
+
+        if self.config.listen_encryption() == Encryption::Noise {
+            task_manager.spawn_named(
+                "certificate_monitor",
+                self.authority_keys.clone().monitor_certificates(
+                    self.config.renew_expired_certificates(),
+                    health.clone(),
+                    notify_shutdown.clone(),
+                ),
+            );
+        }
 
         channel_manager_clone
             .start_downstream_server(
-                *self.config.authority_public_key(),
-                *self.config.authority_secret_key(),
-                self.config.cert_validity_sec(),
+                self.authority_keys.clone(),
                 *self.config.listen_address(),
+                self.config.tcp().clone(),
+                self.config.listen_encryption(),
+                self.config.max_frame_size(),
+                self.config.downstream_idle_timeout(),
+                self.config.handshake_timeout(),
                 task_manager.clone(),
                 notify_shutdown.clone(),
                 status_sender,
                 downstream_to_channel_manager_sender,
-                channel_manager_to_downstream_sender,
             )
             .await?;
+        health.set_condition(LISTENER_BOUND, true);
 
         info!("Spawning status listener task...");
+        let mut result = Ok(());
         loop {
             tokio::select! {
                 _ = tokio::signal::ctrl_c() => {
                     info!("Ctrl+C received — initiating graceful shutdown...");
+                    if let Some(config) = self.config.session_resumption() {
+                        sessions::save(config, self.config.server_id(), channel_manager.saved_channels());
+                    }
+                    let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
+                    break;
+                }
+                task = task_manager.critical_failure() => {
+                    warn!("Critical task {task} failed — initiating full shutdown.");
+                    health.failed(task, "critical task failed");
+                    let reason = format!("critical task {task} failed");
+                    result = Err(PoolError::ComponentShutdown(reason));
                     let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
                     break;
                 }
@@ -144,16 +419,24 @@ impl PoolSv2 {
                                 warn!("Downstream {downstream_id:?} disconnected — Channel manager.");
                                 let _ = notify_shutdown.send(ShutdownMessage::DownstreamShutdown(downstream_id));
                             }
-                            State::TemplateReceiverShutdown(_) => {
+                            State::TemplateReceiverShutdown(e) => {
                                 warn!("Template Receiver shutdown requested — initiating full shutdown.");
+                                health.failed(TEMPLATE_RECEIVER, format!("{e:?}"));
+                                health.set_condition(TP_CONNECTED, false);
+                                result = Err(e);
                                 let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
                                 break;
                             }
-                            State::ChannelManagerShutdown(_) => {
+                            State::ChannelManagerShutdown(e) => {
                                 warn!("Channel Manager shutdown requested — initiating full shutdown.");
+                                health.failed(CHANNEL_MANAGER, format!("{e:?}"));
+                                result = Err(e);
                                 let _ = notify_shutdown.send(ShutdownMessage::ShutdownAll);
                                 break;
                             }
+                            State::TaskPanicked(panic) => {
+                                warn!("Reported panic: {panic}");
+                            }
                         }
                     }
                 }
@@ -161,11 +444,28 @@ impl PoolSv2 {
         }
 
         warn!("Graceful shutdown");
+        if let Err(e) = rounds.save() {
+            error!("Failed to snapshot the current round: {e}");
+        }
+        if let Some(Err(e)) = lifetime.as_ref().map(|lifetime| lifetime.save()) {
+            error!("Failed to save the lifetime statistics: {e}");
+        }
+        let running_tasks = task_manager.running_tasks();
+        info!("Aborting {} tasks still running", running_tasks.len());
+        for task in running_tasks {
+            debug!(
+                task = task.name,
+                spawned_at = task.spawned_at,
+                uptime_secs = task.uptime_secs,
+                restarts = task.restarts,
+                "Aborting task"
+            );
+        }
         task_manager.abort_all().await;
         info!("Joining remaining tasks...");
         task_manager.join_all().await;
         info!("Pool shutdown complete.");
-        Ok(())
+        result
     }
 }
 
@@ -0,0 +1,839 @@
+//! ## Monitoring Module
+//!
+//! Builds the document served by the pool's HTTP monitoring endpoint
+//! (see [`stratum_apps::monitoring`]): the health of the pool components, the number of shares
+//! rejected per error code, the latency of job activations, the freshness of the templates, the
+//! latency of the share acknowledgements, the best shares of the pool and of every user, the
+//! work of the current round, the last `UpdateChannel` messages of the downstreams, the vardiff
+//! state of every channel, the connections of the downstreams with their traffic, the tasks
+//! currently running, the number of downstreams dropped for not completing their handshake in
+//! time and the number of frames refused for exceeding the maximum frame size, along with the
+//! state of the coordination with the other pool instances and the lifetime statistics of the
+//! pool if configured. The payouts of the rounds closed since startup are served on `/payouts`,
+//! and the statistics of every account and of its workers on `/workers`.
+//! The health also answers the `/healthz` and `/readyz` probes, and the ban list is managed on
+//! `/bans`.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use stratum_apps::{
+    banlist::BanList,
+    connections::ConnectionInfo,
+    custom_mutex::Mutex,
+    monitoring::StatusProvider,
+    network_helpers::{self, traffic::TrafficSnapshot},
+    persistence::{PayoutAddresses, PayoutBatch},
+    runtime::{self, TaskInfo, TaskManager},
+    share_reject::ShareRejectCounters,
+    status::{HealthAggregator, HealthReport, HealthTransition},
+};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::{
+    channel_manager::{ChannelManager, VardiffStatus},
+    channel_updates::{ChannelUpdates, RecordedUpdate},
+    coordination::{CoordinationStats, CoordinationStatus},
+    lifetime::{Lifetime, LifetimeStatus},
+    rounds::{RoundStatus, Rounds},
+    utils::ShutdownMessage,
+    workers::Workers,
+    FRESH_TEMPLATE, SHARE_ACKS, TEMPLATE_RECEIVER,
+};
+
+/// Latencies of a step of the pool, e.g. the time taken to switch its downstreams to new jobs,
+/// from the reception of a `SetNewPrevHash` from the Template Provider to the `SetNewPrevHash`
+/// (and held back future jobs) being queued to every downstream.
+#[derive(Debug, Default)]
+pub struct LatencyStats {
+    count: AtomicU64,
+    total_us: AtomicU64,
+    last_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+impl LatencyStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a latency.
+    pub fn record(&self, latency: Duration) {
+        let latency_us = latency.as_micros() as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(latency_us, Ordering::Relaxed);
+        self.last_us.store(latency_us, Ordering::Relaxed);
+        self.max_us.fetch_max(latency_us, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LatencyStatus {
+        let count = self.count.load(Ordering::Relaxed);
+        LatencyStatus {
+            count,
+            last_us: self.last_us.load(Ordering::Relaxed),
+            max_us: self.max_us.load(Ordering::Relaxed),
+            mean_us: self
+                .total_us
+                .load(Ordering::Relaxed)
+                .checked_div(count)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LatencyStatus {
+    count: u64,
+    last_us: u64,
+    max_us: u64,
+    mean_us: u64,
+}
+
+/// Interval at which the freshness of the templates is checked without a staleness window.
+const FRESHNESS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Templates received from the Template Provider: the age of the last one, and the time taken to
+/// distribute each of them, from the reception of the `NewTemplate` to its jobs being queued to
+/// every downstream.
+///
+/// The templates are stale once none was received for the staleness window, if set, and fresh
+/// once one was received and they are not stale.
+#[derive(Debug)]
+pub struct TemplateStats {
+    staleness: Option<Duration>,
+    started: Instant,
+    received: AtomicU64,
+    // Milliseconds from `started` to the reception of the last template.
+    last_received_ms: AtomicU64,
+    distribution: LatencyStats,
+}
+
+impl TemplateStats {
+    pub fn new(staleness: Option<Duration>) -> Self {
+        Self {
+            staleness,
+            started: Instant::now(),
+            received: AtomicU64::new(0),
+            last_received_ms: AtomicU64::new(0),
+            distribution: LatencyStats::new(),
+        }
+    }
+
+    /// Records the reception of a `NewTemplate`.
+    pub fn received(&self) {
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        self.last_received_ms.store(elapsed_ms, Ordering::Relaxed);
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the time taken to distribute a template to the downstreams.
+    pub fn distributed(&self, latency: Duration) {
+        self.distribution.record(latency);
+    }
+
+    /// Returns the time elapsed since the last template was received, `None` before the first.
+    pub fn last_template_age(&self) -> Option<Duration> {
+        if self.received.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let last_received = Duration::from_millis(self.last_received_ms.load(Ordering::Relaxed));
+        Some(self.started.elapsed().saturating_sub(last_received))
+    }
+
+    /// Returns for how long no template was received, the startup counting as a reception, if
+    /// longer than the staleness window.
+    pub fn stale_for(&self) -> Option<Duration> {
+        let staleness = self.staleness?;
+        let age = self
+            .last_template_age()
+            .unwrap_or_else(|| self.started.elapsed());
+        (age > staleness).then_some(age)
+    }
+
+    /// Whether a template was received and the templates are not stale.
+    pub fn is_fresh(&self) -> bool {
+        self.last_template_age().is_some() && self.stale_for().is_none()
+    }
+
+    /// Reports whether the templates are fresh as the `fresh_template` readiness condition.
+    /// Warns while no template was received for the staleness window, if set, once per window,
+    /// and degrades the health of the template receiver meanwhile.
+    pub async fn monitor_staleness(
+        self: Arc<Self>,
+        health: Arc<HealthAggregator>,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    ) {
+        let mut shutdown_rx = notify_shutdown.subscribe();
+        let mut interval = tokio::time::interval(
+            self.staleness
+                .map_or(FRESHNESS_CHECK_INTERVAL, |staleness| {
+                    (staleness / 4).clamp(Duration::from_millis(100), Duration::from_secs(10))
+                }),
+        );
+        let mut last_warning: Option<Instant> = None;
+        loop {
+            tokio::select! {
+                message = shutdown_rx.recv() => match message {
+                    Ok(ShutdownMessage::ShutdownAll) | Err(_) => break,
+                    Ok(_) => {}
+                },
+                _ = interval.tick() => {
+                    health.set_condition(FRESH_TEMPLATE, self.is_fresh());
+                    let Some(staleness) = self.staleness else {
+                        continue;
+                    };
+                    match self.stale_for() {
+                        Some(age) => {
+                            if last_warning.is_none_or(|warned| warned.elapsed() >= staleness) {
+                                warn!(
+                                    "No template received from the Template Provider for {}s",
+                                    age.as_secs()
+                                );
+                                last_warning = Some(Instant::now());
+                            }
+                            health.degraded(
+                                TEMPLATE_RECEIVER,
+                                format!("no template received for {}s", age.as_secs()),
+                            );
+                        }
+                        None => {
+                            if last_warning.take().is_some() {
+                                info!("Receiving templates from the Template Provider again");
+                                health.healthy(TEMPLATE_RECEIVER);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn snapshot(&self) -> TemplateStatus {
+        TemplateStatus {
+            received: self.received.load(Ordering::Relaxed),
+            last_template_age_ms: self.last_template_age().map(|age| age.as_millis() as u64),
+            stale: self.stale_for().is_some(),
+            distribution: self.distribution.snapshot(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TemplateStatus {
+    received: u64,
+    last_template_age_ms: Option<u64>,
+    stale: bool,
+    distribution: LatencyStatus,
+}
+
+/// Upper bounds of the buckets of the share acknowledgement latencies, in microseconds. Longer
+/// latencies fall in a last, unbounded bucket.
+const SHARE_ACK_BUCKETS_US: [u64; 12] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 1_000_000,
+];
+/// Window over which the share acknowledgements are checked against their SLO.
+const SHARE_ACK_SLO_WINDOW: Duration = Duration::from_secs(60);
+/// Shares of a channel waiting for their acknowledgement past which the oldest are forgotten.
+const MAX_PENDING_SHARE_ACKS: usize = 1024;
+
+/// Histogram of latencies over [`SHARE_ACK_BUCKETS_US`].
+#[derive(Debug, Default)]
+struct LatencyHistogram {
+    counts: [u64; SHARE_ACK_BUCKETS_US.len() + 1],
+    total_us: u64,
+    max_us: u64,
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency_us: u64) {
+        let bucket = SHARE_ACK_BUCKETS_US.partition_point(|bound| *bound < latency_us);
+        self.counts[bucket] += 1;
+        self.total_us += latency_us;
+        self.max_us = self.max_us.max(latency_us);
+    }
+
+    fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // Returns the upper bound of the bucket holding the `quantile` of the latencies, capped by
+    // the longest one, `None` without any latency.
+    fn quantile_us(&self, quantile: f64) -> Option<u64> {
+        let rank = (quantile * self.count() as f64).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank.max(1) {
+                let bound = SHARE_ACK_BUCKETS_US.get(bucket).copied();
+                return Some(bound.map_or(self.max_us, |bound| bound.min(self.max_us)));
+            }
+        }
+        None
+    }
+
+    fn snapshot(&self) -> LatencyHistogramStatus {
+        let count = self.count();
+        LatencyHistogramStatus {
+            count,
+            mean_us: self.total_us.checked_div(count).unwrap_or_default(),
+            max_us: self.max_us,
+            p50_us: self.quantile_us(0.5),
+            p99_us: self.quantile_us(0.99),
+            buckets: self
+                .counts
+                .iter()
+                .enumerate()
+                .map(|(bucket, count)| LatencyBucketStatus {
+                    le_us: SHARE_ACK_BUCKETS_US.get(bucket).copied(),
+                    count: *count,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LatencyHistogramStatus {
+    count: u64,
+    mean_us: u64,
+    max_us: u64,
+    p50_us: Option<u64>,
+    p99_us: Option<u64>,
+    buckets: Vec<LatencyBucketStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct LatencyBucketStatus {
+    // `None` for the last, unbounded bucket.
+    le_us: Option<u64>,
+    count: u64,
+}
+
+/// Acknowledgements of the shares of a channel.
+#[derive(Debug, Default)]
+struct ChannelShareAcks {
+    // Sequence number and reception of the shares not acknowledged yet, oldest first.
+    pending: VecDeque<(u32, Instant)>,
+    latencies: LatencyHistogram,
+}
+
+#[derive(Debug, Default)]
+struct ShareAckData {
+    latencies: LatencyHistogram,
+    channels: HashMap<(usize, u32), ChannelShareAcks>,
+    // Acknowledgements over the current SLO window, and how many of them took longer than the
+    // SLO.
+    window_acks: u64,
+    window_over_slo: u64,
+}
+
+/// Time taken to acknowledge the shares, from the reception of a `SubmitSharesStandard` or
+/// `SubmitSharesExtended` to the `SubmitSharesSuccess` or `SubmitSharesError` answering it being
+/// queued to the writer of its connection, pool-wide and per channel.
+///
+/// A `SubmitSharesSuccess` acknowledges a whole batch of shares, only the latency of the share it
+/// was sent for is recorded: the other shares of the batch were accepted without an answer.
+///
+/// With an SLO, the p99 of the latencies is expected to stay under it.
+#[derive(Debug)]
+pub struct ShareAckStats {
+    slo: Option<Duration>,
+    data: Mutex<ShareAckData>,
+}
+
+impl ShareAckStats {
+    pub fn new(slo: Option<Duration>) -> Self {
+        Self {
+            slo,
+            data: Mutex::new(ShareAckData::default()),
+        }
+    }
+
+    /// Records the reception of the share `sequence_number` of the channel `channel_id` of
+    /// `downstream_id`.
+    pub fn received(&self, downstream_id: usize, channel_id: u32, sequence_number: u32) {
+        let received = Instant::now();
+        self.data.super_safe_lock(|data| {
+            let pending = &mut data
+                .channels
+                .entry((downstream_id, channel_id))
+                .or_default()
+                .pending;
+            if pending.len() == MAX_PENDING_SHARE_ACKS {
+                pending.pop_front();
+            }
+            pending.push_back((sequence_number, received));
+        });
+    }
+
+    /// Records the acknowledgement of the share `sequence_number` of the channel `channel_id` of
+    /// `downstream_id`, and of the shares received before it.
+    pub fn acknowledged(&self, downstream_id: usize, channel_id: u32, sequence_number: u32) {
+        self.data.super_safe_lock(|data| {
+            let Some(channel) = data.channels.get_mut(&(downstream_id, channel_id)) else {
+                return;
+            };
+            let Some(position) = channel
+                .pending
+                .iter()
+                .position(|(pending, _)| *pending == sequence_number)
+            else {
+                return;
+            };
+            channel.pending.drain(..position);
+            let Some((_, received)) = channel.pending.pop_front() else {
+                return;
+            };
+            let latency = received.elapsed();
+            let latency_us = latency.as_micros() as u64;
+            channel.latencies.record(latency_us);
+            data.latencies.record(latency_us);
+            data.window_acks += 1;
+            if self.slo.is_some_and(|slo| latency > slo) {
+                data.window_over_slo += 1;
+            }
+        });
+    }
+
+    // Forgets the channels of `downstream_id`, once disconnected.
+    fn remove_downstream(&self, downstream_id: usize) {
+        self.data.super_safe_lock(|data| {
+            data.channels
+                .retain(|(downstream, _), _| *downstream != downstream_id)
+        });
+    }
+
+    // Returns the acknowledgements over the window ending now, and how many of them took longer
+    // than the SLO, and starts the next window.
+    fn end_window(&self) -> (u64, u64) {
+        self.data.super_safe_lock(|data| {
+            (
+                std::mem::take(&mut data.window_acks),
+                std::mem::take(&mut data.window_over_slo),
+            )
+        })
+    }
+
+    /// Forgets the channels of the downstreams disconnecting and, with an SLO, checks the
+    /// acknowledgements of every window against it: while more than 1% of them took longer than
+    /// the SLO, a warning is logged and the health of the share acknowledgements is degraded.
+    pub async fn monitor(
+        self: Arc<Self>,
+        health: Arc<HealthAggregator>,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    ) {
+        let mut shutdown_rx = notify_shutdown.subscribe();
+        let mut interval = tokio::time::interval(SHARE_ACK_SLO_WINDOW);
+        // The first tick completes right away.
+        interval.tick().await;
+        if self.slo.is_some() {
+            health.healthy(SHARE_ACKS);
+        }
+        let mut exceeded = false;
+        loop {
+            tokio::select! {
+                message = shutdown_rx.recv() => match message {
+                    Ok(ShutdownMessage::DownstreamShutdown(downstream_id)) => {
+                        self.remove_downstream(downstream_id);
+                    }
+                    Ok(ShutdownMessage::DownstreamShutdownAll) => {
+                        self.data.super_safe_lock(|data| data.channels.clear());
+                    }
+                    Ok(ShutdownMessage::ShutdownAll) | Err(_) => break,
+                },
+                _ = interval.tick() => {
+                    let (acks, over_slo) = self.end_window();
+                    let Some(slo) = self.slo else {
+                        continue;
+                    };
+                    if over_slo * 100 > acks {
+                        warn!(
+                            "p99 of the share acknowledgements over the last {}s exceeds the SLO of {}ms: {over_slo} of {acks} took longer",
+                            SHARE_ACK_SLO_WINDOW.as_secs(),
+                            slo.as_millis()
+                        );
+                        health.degraded(
+                            SHARE_ACKS,
+                            format!(
+                                "{over_slo} of {acks} shares acknowledged in more than {}ms",
+                                slo.as_millis()
+                            ),
+                        );
+                        exceeded = true;
+                    } else if exceeded {
+                        info!("Share acknowledgements are back within their SLO");
+                        health.healthy(SHARE_ACKS);
+                        exceeded = false;
+                    }
+                }
+            }
+        }
+    }
+
+    fn snapshot(&self) -> ShareAckStatus {
+        self.data.super_safe_lock(|data| {
+            let mut channels: Vec<ChannelShareAckStatus> = data
+                .channels
+                .iter()
+                .filter(|(_, channel)| channel.latencies.count() > 0)
+                .map(
+                    |((downstream_id, channel_id), channel)| ChannelShareAckStatus {
+                        downstream_id: *downstream_id,
+                        channel_id: *channel_id,
+                        pending: channel.pending.len(),
+                        latencies: channel.latencies.snapshot(),
+                    },
+                )
+                .collect();
+            channels.sort_by_key(|channel| (channel.downstream_id, channel.channel_id));
+            ShareAckStatus {
+                slo_ms: self.slo.map(|slo| slo.as_millis() as u64),
+                latencies: data.latencies.snapshot(),
+                channels,
+            }
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ShareAckStatus {
+    slo_ms: Option<u64>,
+    #[serde(flatten)]
+    latencies: LatencyHistogramStatus,
+    channels: Vec<ChannelShareAckStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChannelShareAckStatus {
+    downstream_id: usize,
+    channel_id: u32,
+    pending: usize,
+    #[serde(flatten)]
+    latencies: LatencyHistogramStatus,
+}
+
+/// Duration of the buckets the rolling windows of the best shares are made of, the windows are
+/// exact to a bucket.
+const BEST_SHARE_BUCKET: Duration = Duration::from_secs(300);
+/// Buckets in the last hour.
+const BEST_SHARE_HOUR_BUCKETS: u64 = 12;
+/// Buckets in the last day, the longest window.
+const BEST_SHARE_DAY_BUCKETS: u64 = 288;
+
+/// Best share, the accepted share of highest difficulty, since startup and over rolling windows.
+#[derive(Debug, Default)]
+struct BestShare {
+    since_startup: f64,
+    // Index and best difficulty of the buckets with a share over the last day, oldest first.
+    buckets: VecDeque<(u64, f64)>,
+}
+
+impl BestShare {
+    // Records a share of `difficulty` in `bucket`, returns the best difficulty since startup.
+    fn record(&mut self, bucket: u64, difficulty: f64) -> f64 {
+        match self.buckets.back_mut() {
+            Some((index, best)) if *index == bucket => *best = best.max(difficulty),
+            _ => self.buckets.push_back((bucket, difficulty)),
+        }
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(index, _)| index + BEST_SHARE_DAY_BUCKETS <= bucket)
+        {
+            self.buckets.pop_front();
+        }
+        self.since_startup = self.since_startup.max(difficulty);
+        self.since_startup
+    }
+
+    // Returns the best difficulty over the `buckets` last buckets up to `bucket`, if any share.
+    fn best_over(&self, bucket: u64, buckets: u64) -> Option<f64> {
+        self.buckets
+            .iter()
+            .filter(|(index, _)| index + buckets > bucket)
+            .map(|(_, best)| *best)
+            .reduce(f64::max)
+    }
+
+    fn snapshot(&self, bucket: u64) -> BestShareStatus {
+        BestShareStatus {
+            since_startup: self.since_startup,
+            last_hour: self.best_over(bucket, BEST_SHARE_HOUR_BUCKETS),
+            last_day: self.best_over(bucket, BEST_SHARE_DAY_BUCKETS),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BestSharesData {
+    pool: BestShare,
+    users: BTreeMap<String, BestShare>,
+}
+
+/// Best shares of the pool and of every user identity, the difficulty of a share being the one
+/// met by its hash rather than the target of its channel.
+#[derive(Debug)]
+pub struct BestShares {
+    started: Instant,
+    data: Mutex<BestSharesData>,
+}
+
+impl BestShares {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            data: Mutex::new(BestSharesData::default()),
+        }
+    }
+
+    /// Records an accepted share of `difficulty` submitted by `user_identity`, and returns the
+    /// best difficulty of the user since startup.
+    pub fn record(&self, user_identity: &str, difficulty: f64) -> f64 {
+        let bucket = self.bucket();
+        self.data.super_safe_lock(|data| {
+            let pool_best = data.pool.since_startup;
+            if data.pool.record(bucket, difficulty) > pool_best {
+                info!("New best share of the pool: difficulty {difficulty} by {user_identity}");
+            }
+            data.users
+                .entry(user_identity.to_string())
+                .or_default()
+                .record(bucket, difficulty)
+        })
+    }
+
+    /// Returns the best difficulty of `user_identity` since startup, if it has had a share
+    /// accepted.
+    pub fn best(&self, user_identity: &str) -> Option<f64> {
+        self.data
+            .super_safe_lock(|data| data.users.get(user_identity).map(|best| best.since_startup))
+    }
+
+    // Index of the current bucket since startup.
+    fn bucket(&self) -> u64 {
+        self.started.elapsed().as_secs() / BEST_SHARE_BUCKET.as_secs()
+    }
+
+    fn snapshot(&self) -> BestSharesStatus {
+        let bucket = self.bucket();
+        self.data.super_safe_lock(|data| BestSharesStatus {
+            pool: data.pool.snapshot(bucket),
+            users: data
+                .users
+                .iter()
+                .map(|(user, best)| (user.clone(), best.snapshot(bucket)))
+                .collect(),
+        })
+    }
+}
+
+impl Default for BestShares {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BestShareStatus {
+    since_startup: f64,
+    last_hour: Option<f64>,
+    last_day: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct BestSharesStatus {
+    pool: BestShareStatus,
+    users: BTreeMap<String, BestShareStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct ConnectionStatus {
+    #[serde(flatten)]
+    info: ConnectionInfo,
+    traffic: TrafficSnapshot,
+}
+
+#[derive(Debug, Serialize)]
+struct PoolStatus {
+    health: HealthReport,
+    shares_rejected: BTreeMap<&'static str, u64>,
+    job_activation: LatencyStatus,
+    templates: TemplateStatus,
+    share_acks: ShareAckStatus,
+    best_shares: BestSharesStatus,
+    round: RoundStatus,
+    channel_updates: Vec<RecordedUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vardiff: Option<Vec<VardiffStatus>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connections: Option<Vec<ConnectionStatus>>,
+    tasks: Vec<TaskInfo>,
+    handshake_timeouts: u64,
+    oversized_frames: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    coordination: Option<CoordinationStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lifetime: Option<LifetimeStatus>,
+}
+
+/// [`StatusProvider`] of the pool.
+pub struct PoolStatusProvider {
+    health: Arc<HealthAggregator>,
+    banlist: Arc<BanList>,
+    share_rejects: Arc<ShareRejectCounters>,
+    job_activations: Arc<LatencyStats>,
+    template_stats: Arc<TemplateStats>,
+    share_acks: Arc<ShareAckStats>,
+    best_shares: Arc<BestShares>,
+    rounds: Arc<Rounds>,
+    workers: Arc<Workers>,
+    channel_updates: Arc<ChannelUpdates>,
+    payout_addresses: Option<PathBuf>,
+    worker_separator: String,
+    task_manager: Arc<TaskManager>,
+    coordination: Option<Arc<CoordinationStats>>,
+    lifetime: Option<Arc<Lifetime>>,
+    channel_manager: Option<ChannelManager>,
+}
+
+impl PoolStatusProvider {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        health: Arc<HealthAggregator>,
+        banlist: Arc<BanList>,
+        share_rejects: Arc<ShareRejectCounters>,
+        job_activations: Arc<LatencyStats>,
+        template_stats: Arc<TemplateStats>,
+        share_acks: Arc<ShareAckStats>,
+        best_shares: Arc<BestShares>,
+        rounds: Arc<Rounds>,
+        workers: Arc<Workers>,
+        channel_updates: Arc<ChannelUpdates>,
+        payout_addresses: Option<PathBuf>,
+        worker_separator: String,
+        task_manager: Arc<TaskManager>,
+    ) -> Self {
+        Self {
+            health,
+            banlist,
+            share_rejects,
+            job_activations,
+            template_stats,
+            share_acks,
+            best_shares,
+            rounds,
+            workers,
+            channel_updates,
+            payout_addresses,
+            worker_separator,
+            task_manager,
+            coordination: None,
+            lifetime: None,
+            channel_manager: None,
+        }
+    }
+
+    /// Also serves the state of the coordination with the other pool instances.
+    pub fn with_coordination(mut self, coordination: Arc<CoordinationStats>) -> Self {
+        self.coordination = Some(coordination);
+        self
+    }
+
+    /// Also serves the lifetime statistics of the pool.
+    pub fn with_lifetime(mut self, lifetime: Arc<Lifetime>) -> Self {
+        self.lifetime = Some(lifetime);
+        self
+    }
+
+    /// Also serves the vardiff state of the channels of `channel_manager`, and the connections of
+    /// its downstreams with their traffic.
+    pub fn with_channel_manager(mut self, channel_manager: ChannelManager) -> Self {
+        self.channel_manager = Some(channel_manager);
+        self
+    }
+}
+
+impl StatusProvider for PoolStatusProvider {
+    fn status(&self) -> serde_json::Value {
+        serde_json::to_value(PoolStatus {
+            health: self.health.snapshot(),
+            shares_rejected: self.share_rejects.snapshot(),
+            job_activation: self.job_activations.snapshot(),
+            templates: self.template_stats.snapshot(),
+            share_acks: self.share_acks.snapshot(),
+            best_shares: self.best_shares.snapshot(),
+            round: self.rounds.snapshot(),
+            channel_updates: self.channel_updates.snapshot(),
+            vardiff: self
+                .channel_manager
+                .as_ref()
+                .map(ChannelManager::vardiff_status),
+            connections: self.channel_manager.as_ref().map(|channel_manager| {
+                channel_manager
+                    .connections()
+                    .into_iter()
+                    .map(|(info, downstream)| ConnectionStatus {
+                        info,
+                        traffic: downstream.traffic.snapshot(),
+                    })
+                    .collect()
+            }),
+            tasks: self.task_manager.running_tasks(),
+            handshake_timeouts: runtime::handshake_timeouts(),
+            oversized_frames: network_helpers::oversized_frames(),
+            coordination: self.coordination.as_ref().map(|stats| stats.snapshot()),
+            lifetime: self.lifetime.as_ref().map(|lifetime| lifetime.snapshot()),
+        })
+        .expect("pool status is always serializable")
+    }
+
+    fn health(&self) -> HealthReport {
+        self.health.snapshot()
+    }
+
+    fn health_transitions(&self) -> Vec<HealthTransition> {
+        self.health.transitions()
+    }
+
+    fn banlist(&self) -> Option<&BanList> {
+        Some(&self.banlist)
+    }
+
+    // The address file is read on every request, so that edits apply without a restart.
+    fn payouts(&self) -> Option<Result<Vec<PayoutBatch>, String>> {
+        let addresses = match &self.payout_addresses {
+            Some(path) => match PayoutAddresses::load(path) {
+                Ok(addresses) => addresses,
+                Err(e) => {
+                    return Some(Err(format!(
+                        "failed to load the payout addresses from {}: {e}",
+                        path.display()
+                    )))
+                }
+            },
+            None => PayoutAddresses::default(),
+        }
+        .with_separator(&self.worker_separator);
+        Some(Ok(self
+            .rounds
+            .closed()
+            .iter()
+            .map(|round| PayoutBatch::new(round, &addresses))
+            .collect()))
+    }
+
+    fn workers(&self) -> Option<serde_json::Value> {
+        Some(
+            serde_json::to_value(self.workers.snapshot())
+                .expect("worker statistics are always serializable"),
+        )
+    }
+}
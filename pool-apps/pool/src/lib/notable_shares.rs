@@ -0,0 +1,49 @@
+//! ## Notable Shares Module
+//!
+//! Shares meeting a difficulty of at least `threshold` times the network difficulty, e.g. 1%,
+//! are notable: the pool gets statistically close to finding a block. A notable share is logged
+//! at INFO level, published with `notable` set, so that it is flagged in the persisted share
+//! events, and POSTed to the `webhook_url` of the `[notable_shares]` table if set. The network
+//! difficulty is that of the `nbits` of the last `SetNewPrevHash` of the Template Provider.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use stratum_apps::stratum_core::bitcoin::{CompactTarget, Target};
+
+use crate::config::NotableSharesConfig;
+
+/// Threshold of the notable shares, along with the current network difficulty.
+#[derive(Debug)]
+pub struct NotableShares {
+    threshold: f64,
+    // Bits of the network difficulty, 0 until the first `SetNewPrevHash`.
+    network_difficulty: AtomicU64,
+}
+
+impl NotableShares {
+    pub fn new(config: &NotableSharesConfig) -> Self {
+        Self {
+            threshold: config.threshold(),
+            network_difficulty: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the network difficulty from the `nbits` of a new previous hash.
+    pub fn set_network_target(&self, n_bits: u32) {
+        let difficulty =
+            Target::from_compact(CompactTarget::from_consensus(n_bits)).difficulty_float();
+        self.network_difficulty
+            .store(difficulty.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Returns the current network difficulty, unless no previous hash was received yet.
+    pub fn network_difficulty(&self) -> Option<f64> {
+        let difficulty = f64::from_bits(self.network_difficulty.load(Ordering::Relaxed));
+        (difficulty > 0.0).then_some(difficulty)
+    }
+
+    /// Returns the fraction of the network difficulty a share of `difficulty` met, if notable.
+    pub fn fraction(&self, difficulty: f64) -> Option<f64> {
+        let fraction = difficulty / self.network_difficulty()?;
+        (fraction >= self.threshold).then_some(fraction)
+    }
+}
@@ -0,0 +1,277 @@
+//! ## Payments Module
+//!
+//! Opt-in payment of the balances owed to the users, for pools small enough to pay from a single
+//! wallet, built with the `payments` feature.
+//!
+//! [`PaymentEngine`] builds one transaction paying every address owed at least `min_payout_sat`,
+//! funded by the outputs set aside in the configuration or by the spendable outputs of the wallet,
+//! with the change returned to `change_address`. The wallet of the bitcoind node signs it and the
+//! node broadcasts it. A dry run builds the unsigned transaction only. The resulting
+//! [`PaymentEvent`] is meant to be persisted next to the rounds, so that the next payment only
+//! pays what is still owed.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+use stratum_apps::{
+    config_helpers::BitcoinNetwork,
+    persistence::PaymentEvent,
+    rpc::{
+        mini_rpc_client::{Auth, MiniRpcClient, RpcError},
+        Uri,
+    },
+    stratum_core::bitcoin::{
+        absolute::LockTime, address::NetworkUnchecked, consensus::encode::serialize_hex,
+        transaction::Version, Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction,
+        TxIn, TxOut, Witness,
+    },
+};
+use tracing::info;
+
+use crate::config::PaymentsConfig;
+
+/// Virtual size of a transaction without its inputs and outputs.
+const TX_OVERHEAD_VSIZE: u64 = 11;
+/// Virtual size of a signed P2WPKH input, the inputs being assumed to be P2WPKH.
+const P2WPKH_INPUT_VSIZE: u64 = 68;
+/// Smallest change output created, smaller change is left to the fee.
+const DUST_LIMIT: u64 = 546;
+
+/// Errors of a payment.
+#[derive(Debug)]
+pub enum PaymentError {
+    /// The `[payments]` table or `network` is invalid.
+    Config(String),
+    /// An address owed a balance is invalid on the network.
+    InvalidAddress(String, String),
+    /// The funding outputs do not cover the payouts and the fee.
+    InsufficientFunds { needed: u64, available: u64 },
+    /// The wallet could not sign every input.
+    IncompleteSignature,
+    /// The RPC of the bitcoind node failed.
+    Rpc(RpcError),
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentError::Config(e) => write!(f, "Invalid payments configuration: {e}"),
+            PaymentError::InvalidAddress(address, e) => {
+                write!(f, "Invalid payout address `{address}`: {e}")
+            }
+            PaymentError::InsufficientFunds { needed, available } => write!(
+                f,
+                "Insufficient funds: {needed} sats needed, {available} sats available"
+            ),
+            PaymentError::IncompleteSignature => {
+                write!(f, "The wallet could not sign every input of the payment")
+            }
+            PaymentError::Rpc(e) => write!(f, "Wallet RPC error: {e:?}"),
+        }
+    }
+}
+
+impl From<RpcError> for PaymentError {
+    fn from(e: RpcError) -> Self {
+        PaymentError::Rpc(e)
+    }
+}
+
+/// Builds, signs and broadcasts the payout transactions.
+pub struct PaymentEngine {
+    config: PaymentsConfig,
+    network: Network,
+    change_address: Address,
+    rpc: MiniRpcClient,
+}
+
+impl PaymentEngine {
+    /// Creates an engine paying on `network` from the wallet of `config`.
+    pub fn new(
+        config: &PaymentsConfig,
+        network: Option<BitcoinNetwork>,
+    ) -> Result<Self, PaymentError> {
+        let network = network
+            .ok_or_else(|| PaymentError::Config("`network` must be set to pay".to_string()))?
+            .bitcoin_network();
+        if !(config.fee_rate().is_finite() && config.fee_rate() > 0.0) {
+            return Err(PaymentError::Config(
+                "`fee_rate_sat_vb` must be positive".to_string(),
+            ));
+        }
+        let change_address = parse_address(config.change_address(), network)?;
+        let url = Uri::from_str(config.rpc_url())
+            .map_err(|e| PaymentError::Config(format!("invalid `rpc_url`: {e}")))?;
+        let (user, password) = config.rpc_credentials();
+        Ok(Self {
+            config: config.clone(),
+            network,
+            change_address,
+            rpc: MiniRpcClient::new(url, Auth::new(user.to_string(), password.to_string())),
+        })
+    }
+
+    /// Pays the `owed` balances of at least `min_payout_sat`, without spending the `spent`
+    /// outpoints of previous payments. Only builds the unsigned transaction if `dry_run`.
+    ///
+    /// Returns `None` if no balance is large enough to be paid.
+    pub async fn pay(
+        &self,
+        owed: &BTreeMap<String, u64>,
+        spent: &HashSet<String>,
+        dry_run: bool,
+    ) -> Result<Option<PaymentEvent>, PaymentError> {
+        let payouts: BTreeMap<String, u64> = owed
+            .iter()
+            .filter(|(_, amount)| **amount >= self.config.min_payout())
+            .map(|(address, amount)| (address.clone(), *amount))
+            .collect();
+        if payouts.is_empty() {
+            return Ok(None);
+        }
+        let mut outputs = Vec::with_capacity(payouts.len() + 1);
+        for (address, amount) in &payouts {
+            outputs.push(TxOut {
+                value: Amount::from_sat(*amount),
+                script_pubkey: parse_address(address, self.network)?.script_pubkey(),
+            });
+        }
+        let mut utxos: Vec<(OutPoint, u64)> = self
+            .utxos()
+            .await?
+            .into_iter()
+            .filter(|(outpoint, _)| !spent.contains(&outpoint.to_string()))
+            .collect();
+        utxos.sort_by(|a, b| b.1.cmp(&a.1));
+
+        let payout_total: u64 = payouts.values().sum();
+        let change_output = TxOut {
+            value: Amount::ZERO,
+            script_pubkey: self.change_address.script_pubkey(),
+        };
+        let outputs_vsize: u64 = outputs
+            .iter()
+            .chain([&change_output])
+            .map(|output| output.size() as u64)
+            .sum();
+        let mut inputs = Vec::new();
+        let mut funds = 0;
+        let mut fee = 0;
+        for (outpoint, amount) in &utxos {
+            inputs.push(*outpoint);
+            funds += amount;
+            fee = self.fee(inputs.len(), outputs_vsize);
+            if funds >= payout_total + fee {
+                break;
+            }
+        }
+        if funds < payout_total + fee || inputs.is_empty() {
+            return Err(PaymentError::InsufficientFunds {
+                needed: payout_total + self.fee(inputs.len().max(1), outputs_vsize),
+                available: funds,
+            });
+        }
+        let change = funds - payout_total - fee;
+        let change = if change >= DUST_LIMIT {
+            outputs.push(TxOut {
+                value: Amount::from_sat(change),
+                ..change_output
+            });
+            change
+        } else {
+            fee += change;
+            0
+        };
+
+        let transaction = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|outpoint| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: outputs,
+        };
+        let mut event = PaymentEvent {
+            txid: transaction.compute_txid().to_string(),
+            submitted: false,
+            payouts,
+            inputs: inputs.iter().map(OutPoint::to_string).collect(),
+            fee,
+            change,
+            transaction: serialize_hex(&transaction),
+        };
+        if dry_run {
+            return Ok(Some(event));
+        }
+
+        let signed = self
+            .rpc
+            .sign_raw_transaction_with_wallet(&event.transaction)
+            .await?;
+        if !signed.complete {
+            return Err(PaymentError::IncompleteSignature);
+        }
+        event.txid = self.rpc.send_raw_transaction(&signed.hex).await?;
+        event.transaction = signed.hex;
+        event.submitted = true;
+        info!(
+            "Broadcast payment {}: {payout_total} sats to {} addresses, fee {fee} sats",
+            event.txid,
+            event.payouts.len()
+        );
+        Ok(Some(event))
+    }
+
+    // Returns the outputs funding the payouts with their value in satoshis, the configured ones
+    // or else the spendable outputs of the wallet.
+    async fn utxos(&self) -> Result<Vec<(OutPoint, u64)>, PaymentError> {
+        if !self.config.utxos().is_empty() {
+            return self
+                .config
+                .utxos()
+                .iter()
+                .map(|utxo| match OutPoint::from_str(&utxo.outpoint()) {
+                    Ok(outpoint) => Ok((outpoint, utxo.amount())),
+                    Err(e) => Err(PaymentError::Config(format!(
+                        "invalid utxo `{}`: {e}",
+                        utxo.outpoint()
+                    ))),
+                })
+                .collect();
+        }
+        Ok(self
+            .rpc
+            .list_unspent()
+            .await?
+            .into_iter()
+            .filter(|unspent| unspent.spendable)
+            .filter_map(|unspent| {
+                let outpoint = OutPoint::from_str(&format!("{}:{}", unspent.txid, unspent.vout));
+                let amount = Amount::from_btc(unspent.amount).ok()?;
+                Some((outpoint.ok()?, amount.to_sat()))
+            })
+            .collect())
+    }
+
+    // Fee of a transaction with `inputs` P2WPKH inputs and outputs of `outputs_vsize`.
+    fn fee(&self, inputs: usize, outputs_vsize: u64) -> u64 {
+        let vsize = TX_OVERHEAD_VSIZE + inputs as u64 * P2WPKH_INPUT_VSIZE + outputs_vsize;
+        (vsize as f64 * self.config.fee_rate()).ceil() as u64
+    }
+}
+
+fn parse_address(address: &str, network: Network) -> Result<Address, PaymentError> {
+    address
+        .parse::<Address<NetworkUnchecked>>()
+        .and_then(|address| address.require_network(network))
+        .map_err(|e| PaymentError::InvalidAddress(address.to_string(), e.to_string()))
+}
@@ -0,0 +1,187 @@
+//! ## Pool Signature Module
+//!
+//! Expansion of the `pool_signature` the pool pushes to the coinbase scriptSig. The signature is a
+//! template in which the following variables are replaced:
+//! - `{height}`: the height of the block mined, read from the BIP34 `coinbase_prefix` of the
+//!   template
+//! - `{server_id}`: the `server_id` of the pool
+//! - `{date}`: the UTC date, as `YYYY-MM-DD`
+//!
+//! `{{` and `}}` stand for literal braces. A channel builds the coinbase of all its jobs from the
+//! signature it was given when opened, so the variables are expanded when a channel opens:
+//! `{height}` is the height of the block mined at that time. The room reserved for the signature
+//! in the scriptSig is that of its longest expansion, [`PoolSignature::max_len`], so that the
+//! signature of every channel fits within the coinbase constraints checked at startup.
+
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Variable of the signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    Height,
+    ServerId,
+    Date,
+}
+
+impl Variable {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "height" => Some(Self::Height),
+            "server_id" => Some(Self::ServerId),
+            "date" => Some(Self::Date),
+            _ => None,
+        }
+    }
+
+    // Size of the longest expansion of the variable.
+    fn max_len(self) -> usize {
+        match self {
+            // u32::MAX
+            Self::Height => 10,
+            // u16::MAX
+            Self::ServerId => 5,
+            // YYYY-MM-DD
+            Self::Date => 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Variable(Variable),
+}
+
+/// `pool_signature` of the configuration, parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolSignature {
+    template: String,
+    parts: Vec<Part>,
+}
+
+impl PoolSignature {
+    /// Parses `template`, failing on an unknown variable or an unmatched brace.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' | '}' if chars.as_str().starts_with(c) => {
+                    chars.next();
+                    literal.push(c);
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        return Err(format!("unclosed `{{` in `pool_signature` \"{template}\""));
+                    };
+                    let name = &rest[..end];
+                    let variable = Variable::parse(name).ok_or_else(|| {
+                        format!(
+                            "unknown variable `{{{name}}}` in `pool_signature`, expected `{{height}}`, `{{server_id}}` or `{{date}}`"
+                        )
+                    })?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Variable(variable));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("unmatched `}}` in `pool_signature` \"{template}\"")),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self {
+            template: template.to_string(),
+            parts,
+        })
+    }
+
+    /// Returns the signature as configured, variables unexpanded.
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Returns the size of the longest expansion of the signature, in bytes.
+    pub fn max_len(&self) -> usize {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.len(),
+                Part::Variable(variable) => variable.max_len(),
+            })
+            .sum()
+    }
+
+    /// Expands the signature of a channel opened at `now` by the pool of `server_id`, while the
+    /// block at `height` is mined.
+    pub fn expand(&self, height: u32, server_id: u16, now: SystemTime) -> String {
+        let mut signature = String::with_capacity(self.max_len());
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => signature.push_str(literal),
+                Part::Variable(Variable::Height) => signature.push_str(&height.to_string()),
+                Part::Variable(Variable::ServerId) => signature.push_str(&server_id.to_string()),
+                Part::Variable(Variable::Date) => signature.push_str(&utc_date(now)),
+            }
+        }
+        signature
+    }
+}
+
+impl fmt::Display for PoolSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+/// Returns the block height pushed at the start of a coinbase scriptSig, as required by BIP34.
+pub fn bip34_height(coinbase_prefix: &[u8]) -> Option<u32> {
+    match *coinbase_prefix.first()? {
+        // OP_0
+        0x00 => Some(0),
+        // OP_1 to OP_16
+        op @ 0x51..=0x60 => Some(u32::from(op - 0x50)),
+        len @ 1..=4 => {
+            let bytes = coinbase_prefix.get(1..=usize::from(len))?;
+            Some(
+                bytes
+                    .iter()
+                    .rev()
+                    .fold(0, |height, byte| height << 8 | u32::from(*byte)),
+            )
+        }
+        _ => None,
+    }
+}
+
+// Returns the UTC date of `time`, as `YYYY-MM-DD`.
+fn utc_date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 86_400) as i64;
+    // Civil date of a number of days since 1970-01-01, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
@@ -0,0 +1,229 @@
+//! ## Rounds Module
+//!
+//! A round gathers the shares accepted since the previous block found by the pool. [`Rounds`]
+//! sums the work of every user, and of every account, in the current round, and closes it when a
+//! share finds a block, returning a [`RoundEvent`] to persist and starting the next round. The
+//! work of a share is the difficulty of the target of its channel, so that the round totals are
+//! what proportional payout schemes split the block reward by. The rounds closed since startup
+//! are kept to export their payouts.
+//!
+//! With an `[accounting]` table, the current round is snapshotted to a file every
+//! `snapshot_interval_secs`, whenever a round closes and on shutdown, and restored from it at
+//! startup, so that a restart of the pool does not wipe the work the users accrued in the round.
+//! The rounds closed are not part of the snapshot, they are recorded by the persistence.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use stratum_apps::{
+    custom_mutex::Mutex,
+    persistence::{RoundEvent, RoundWork},
+    state_file::write_json_atomically,
+    unix_time,
+};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::{error::PoolError, utils::ShutdownMessage};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Round {
+    round: u64,
+    started_ms: u64,
+    total: RoundWork,
+    users: BTreeMap<String, RoundWork>,
+    accounts: BTreeMap<String, RoundWork>,
+}
+
+impl Round {
+    fn new(round: u64) -> Self {
+        Self {
+            round,
+            started_ms: unix_time::now_ms(),
+            total: RoundWork::default(),
+            users: BTreeMap::new(),
+            accounts: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RoundsSnapshot {
+    saved_ms: u64,
+    current: Round,
+}
+
+/// Work of every user in the current round of the pool, and the rounds closed since startup.
+#[derive(Debug)]
+pub struct Rounds {
+    current: Mutex<Round>,
+    closed: Mutex<Vec<RoundEvent>>,
+    // Snapshot file of the current round, locked while written.
+    snapshot: Option<Mutex<PathBuf>>,
+}
+
+impl Rounds {
+    pub fn new() -> Self {
+        Self {
+            current: Mutex::new(Round::new(1)),
+            closed: Mutex::new(Vec::new()),
+            snapshot: None,
+        }
+    }
+
+    /// Restores the current round from the snapshot file at `path`, or starts the first round if
+    /// there is none, and snapshots the current round there from then on.
+    ///
+    /// Fails if the snapshot cannot be read or parsed, rather than wiping the work it holds.
+    pub fn load(path: &Path) -> Result<Self, PoolError> {
+        let current = match std::fs::read(path) {
+            Ok(bytes) => {
+                let snapshot: RoundsSnapshot = serde_json::from_slice(&bytes).map_err(|e| {
+                    PoolError::Config(format!(
+                        "invalid accounting snapshot {}: {e}",
+                        path.display()
+                    ))
+                })?;
+                let round = snapshot.current;
+                info!(
+                    "Restored round {} from the snapshot saved {}s ago: {} shares, work {}, {} users",
+                    round.round,
+                    unix_time::now_ms().saturating_sub(snapshot.saved_ms) / 1000,
+                    round.total.shares,
+                    round.total.work,
+                    round.users.len()
+                );
+                round
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Round::new(1),
+            Err(e) => return Err(PoolError::Io(e)),
+        };
+        Ok(Self {
+            current: Mutex::new(current),
+            closed: Mutex::new(Vec::new()),
+            snapshot: Some(Mutex::new(path.to_path_buf())),
+        })
+    }
+
+    /// Writes the current round to the snapshot file, if any.
+    pub fn save(&self) -> std::io::Result<()> {
+        let Some(snapshot) = &self.snapshot else {
+            return Ok(());
+        };
+        // The round is read while the file is locked, so that the last snapshot written is the
+        // latest one.
+        snapshot.super_safe_lock(|path| {
+            let snapshot = RoundsSnapshot {
+                saved_ms: unix_time::now_ms(),
+                current: self.current.super_safe_lock(|round| round.clone()),
+            };
+            write_json_atomically(path, &snapshot)
+        })
+    }
+
+    /// Snapshots the current round every `interval` until the pool shuts down.
+    pub async fn snapshot_periodically(
+        self: Arc<Self>,
+        interval: Duration,
+        notify_shutdown: broadcast::Sender<ShutdownMessage>,
+    ) {
+        let mut shutdown_rx = notify_shutdown.subscribe();
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                message = shutdown_rx.recv() => match message {
+                    Ok(ShutdownMessage::ShutdownAll) | Err(_) => break,
+                    Ok(_) => {}
+                },
+                _ = interval.tick() => {
+                    if let Err(e) = self.save() {
+                        warn!("Failed to snapshot the current round: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds an accepted share of `user_identity`, of `account`, with `work` to the current round.
+    pub fn add_share(&self, user_identity: &str, account: &str, work: f64) {
+        self.current.super_safe_lock(|round| {
+            round.total.shares += 1;
+            round.total.work += work;
+            let user = round.users.entry(user_identity.to_string()).or_default();
+            user.shares += 1;
+            user.work += work;
+            let account = round.accounts.entry(account.to_string()).or_default();
+            account.shares += 1;
+            account.work += work;
+        });
+    }
+
+    /// Closes the current round on a block of `block_hash` paying `reward` satoshis to the pool,
+    /// and starts the next one.
+    ///
+    /// The share finding the block must have been added to the round beforehand.
+    pub fn close(&self, block_hash: String, reward: u64) -> RoundEvent {
+        let round = self.current.super_safe_lock(|round| {
+            let next = Round::new(round.round + 1);
+            std::mem::replace(round, next)
+        });
+        info!(
+            "Round {} closed by block {block_hash} paying {reward} sats: {} shares, work {}, {} users",
+            round.round,
+            round.total.shares,
+            round.total.work,
+            round.users.len()
+        );
+        let event = RoundEvent {
+            round: round.round,
+            block_hash,
+            started_ms: round.started_ms,
+            reward,
+            total: round.total,
+            users: round.users,
+            accounts: round.accounts,
+        };
+        self.closed
+            .super_safe_lock(|closed| closed.push(event.clone()));
+        // The closed round must not be restored by the next run of the pool.
+        if let Err(e) = self.save() {
+            warn!("Failed to snapshot the current round: {e}");
+        }
+        event
+    }
+
+    /// Returns the rounds closed since startup, oldest first.
+    pub fn closed(&self) -> Vec<RoundEvent> {
+        self.closed.super_safe_lock(|closed| closed.clone())
+    }
+
+    pub(crate) fn snapshot(&self) -> RoundStatus {
+        self.current.super_safe_lock(|round| RoundStatus {
+            round: round.round,
+            started_ms: round.started_ms,
+            total: round.total.clone(),
+            users: round.users.clone(),
+            accounts: round.accounts.clone(),
+        })
+    }
+}
+
+impl Default for Rounds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Totals of the current round, as reported on the monitoring endpoint.
+#[derive(Debug, Serialize)]
+pub(crate) struct RoundStatus {
+    round: u64,
+    started_ms: u64,
+    total: RoundWork,
+    users: BTreeMap<String, RoundWork>,
+    accounts: BTreeMap<String, RoundWork>,
+}
@@ -0,0 +1,163 @@
+//! ## Sessions Module
+//!
+//! Resumption of the downstream channels across a quick restart of the pool, e.g. a planned
+//! upgrade. On a graceful shutdown, the channels of every downstream are saved to the file of the
+//! `[session_resumption]` table: their id, extranonce prefix, user identity, nominal hashrate and
+//! target. When the pool starts again within `max_age_secs`, a channel of the same kind opened
+//! from the same IP address with the same user identity is given back its channel id (if still
+//! free on the new connection), its extranonce prefix and its nominal hashrate, hence its target,
+//! so that the work it was given before the restart keeps being valid. The saved prefixes are
+//! never handed out to other channels.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, net::IpAddr, time::Duration};
+use stratum_apps::{custom_mutex::Mutex, state_file::write_atomically, unix_time};
+use tracing::{info, warn};
+
+use crate::{channel_manager::FULL_EXTRANONCE_SIZE, config::SessionResumptionConfig};
+
+/// A channel saved on shutdown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedChannel {
+    /// IP address of the downstream.
+    pub ip: IpAddr,
+    /// Whether the channel is an extended channel.
+    pub extended: bool,
+    pub channel_id: u32,
+    pub user_identity: String,
+    #[serde(with = "hex_bytes")]
+    pub extranonce_prefix: Vec<u8>,
+    pub nominal_hash_rate: f32,
+    /// Target of the channel, in little endian.
+    #[serde(with = "hex_bytes")]
+    pub target: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SessionsFile {
+    saved_ms: u64,
+    // The prefixes of the channels only fit a pool with the same server id and extranonce size.
+    server_id: u16,
+    extranonce_size: usize,
+    channels: Vec<SavedChannel>,
+}
+
+/// Channels saved by the previous run of the pool, waiting to be resumed.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    pending: Mutex<Vec<SavedChannel>>,
+    reserved: HashSet<Vec<u8>>,
+}
+
+impl Sessions {
+    /// Loads the channels saved to the file of `config` by a pool of `server_id`, unless older
+    /// than `max_age_secs`. The file is removed, so that the channels are resumed only once.
+    pub fn load(config: &SessionResumptionConfig, server_id: u16) -> Self {
+        let path = config.path();
+        let file = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                warn!(
+                    "Failed to read the saved sessions from {}: {e}",
+                    path.display()
+                );
+                return Self::default();
+            }
+        };
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!(
+                "Failed to remove the saved sessions {}: {e}",
+                path.display()
+            );
+        }
+        let file: SessionsFile = match serde_json::from_slice(&file) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!(
+                    "Ignoring the invalid saved sessions {}: {e}",
+                    path.display()
+                );
+                return Self::default();
+            }
+        };
+        let age = Duration::from_millis(unix_time::now_ms().saturating_sub(file.saved_ms));
+        if age > config.max_age() {
+            info!(
+                "Ignoring the sessions saved {}s ago, more than {}s",
+                age.as_secs(),
+                config.max_age().as_secs()
+            );
+            return Self::default();
+        }
+        if file.server_id != server_id || file.extranonce_size != FULL_EXTRANONCE_SIZE {
+            warn!(
+                "Ignoring the sessions saved by a pool with another server id or extranonce size"
+            );
+            return Self::default();
+        }
+        info!(
+            "Resuming {} channels saved {}s ago",
+            file.channels.len(),
+            age.as_secs()
+        );
+        Self {
+            reserved: file
+                .channels
+                .iter()
+                .map(|channel| channel.extranonce_prefix.clone())
+                .collect(),
+            pending: Mutex::new(file.channels),
+        }
+    }
+
+    /// Takes the saved channel of `user_identity` opened from `ip`, if any.
+    pub fn resume(&self, ip: IpAddr, user_identity: &str, extended: bool) -> Option<SavedChannel> {
+        if self.reserved.is_empty() {
+            return None;
+        }
+        self.pending.super_safe_lock(|pending| {
+            let index = pending.iter().position(|channel| {
+                channel.ip == ip
+                    && channel.extended == extended
+                    && channel.user_identity == user_identity
+            })?;
+            Some(pending.swap_remove(index))
+        })
+    }
+
+    /// Whether `extranonce_prefix` belongs to a saved channel, and must not be given to others.
+    pub fn is_reserved(&self, extranonce_prefix: &[u8]) -> bool {
+        self.reserved.contains(extranonce_prefix)
+    }
+}
+
+/// Saves `channels` to the file of `config`, for the next run of the pool of `server_id`.
+pub fn save(config: &SessionResumptionConfig, server_id: u16, channels: Vec<SavedChannel>) {
+    let path = config.path();
+    let file = SessionsFile {
+        saved_ms: unix_time::now_ms(),
+        server_id,
+        extranonce_size: FULL_EXTRANONCE_SIZE,
+        channels,
+    };
+    let count = file.channels.len();
+    let json = serde_json::to_vec_pretty(&file).map_err(std::io::Error::other);
+    match json.and_then(|json| write_atomically(path, &json)) {
+        Ok(()) => info!("Saved {count} channels to {}", path.display()),
+        Err(e) => warn!("Failed to save the sessions to {}: {e}", path.display()),
+    }
+}
+
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        hex::decode(hex).map_err(serde::de::Error::custom)
+    }
+}
@@ -6,9 +6,12 @@
 //! and receive status updates via typed channels. Errors are automatically
 //! converted into shutdown signals, allowing coordinated teardown of tasks.
 
+use std::time::Duration;
+
+use stratum_apps::runtime::{IoTaskStatus, TaskManager, TaskPanic};
 use tracing::{debug, error, warn};
 
-use crate::error::PoolError;
+use crate::{error::PoolError, utils::ShutdownMessage};
 
 /// Sender type for propagating status updates from different system components.
 #[derive(Debug, Clone)]
@@ -71,6 +74,22 @@ impl StatusSender {
     }
 }
 
+impl IoTaskStatus<ShutdownMessage> for StatusSender {
+    fn stops_on(&self, message: &ShutdownMessage) -> bool {
+        match message {
+            ShutdownMessage::ShutdownAll => true,
+            ShutdownMessage::DownstreamShutdown(down_id) => {
+                matches!(StatusType::from(self), StatusType::Downstream(id) if id == *down_id)
+            }
+            ShutdownMessage::DownstreamShutdownAll => false,
+        }
+    }
+
+    async fn idle_timeout(&self, idle_timeout: Duration) {
+        handle_error(self, PoolError::IdleTimeout(idle_timeout)).await;
+    }
+}
+
 /// Represents the state of a component, typically triggered by an error or shutdown event.
 #[derive(Debug)]
 pub enum State {
@@ -83,6 +102,8 @@ pub enum State {
     TemplateReceiverShutdown(PoolError),
     /// Channel manager has shut down with a reason.
     ChannelManagerShutdown(PoolError),
+    /// A task panicked, see [`TaskManager::on_panic`].
+    TaskPanicked(TaskPanic),
 }
 
 /// Wrapper around a component’s state, sent as status updates across the system.
@@ -122,3 +143,17 @@ pub async fn handle_error(sender: &StatusSender, e: PoolError) {
     error!("Error in {:?}: {:?}", sender, e);
     send_status(sender, e).await;
 }
+
+/// Forwards the panics caught in the tasks of `task_manager` to the main loop, as
+/// [`State::TaskPanicked`] statuses sent on `tx`.
+pub fn report_task_panics(task_manager: &TaskManager, tx: async_channel::Sender<Status>) {
+    task_manager.on_panic(move |panic| {
+        let tx = tx.clone();
+        async move {
+            let state = State::TaskPanicked(panic);
+            if let Err(e) = tx.send(Status { state }).await {
+                error!("Failed to send task panic status: {e:?}");
+            }
+        }
+    });
+}
@@ -0,0 +1,194 @@
+//! Room taken by the pool in the coinbase of every template.
+//!
+//! The pool appends its outputs to the coinbase of the templates, and its signature and the
+//! extranonce of the channels to the coinbase scriptSig. [`CoinbaseConstraints`] accounts for
+//! each of them, so that the [`CoinbaseOutputConstraints`] sent to the Template Provider reserve
+//! exactly the size and sigops of the outputs, and so that a configuration which cannot fit in a
+//! coinbase is refused naming the item pushing it over the limit.
+
+use std::fmt::{self, Display};
+
+use stratum_apps::stratum_core::{
+    bitcoin::{
+        absolute::LockTime, transaction::Version, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+        TxOut, Witness,
+    },
+    template_distribution_sv2::CoinbaseOutputConstraints,
+};
+
+use crate::pool_signature::PoolSignature;
+
+/// Maximum size of a coinbase scriptSig, by consensus.
+const MAX_SCRIPT_SIG_SIZE: usize = 100;
+/// Bytes of the scriptSig taken by the `coinbase_prefix` of the templates, which carries the
+/// BIP34 block height.
+const COINBASE_PREFIX_SIZE: usize = 8;
+/// Maximum sigop cost of a block, by consensus.
+const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+/// Maximum size of the coinbase outputs, a quarter of the block weight being left to the
+/// transactions of the template.
+const MAX_OUTPUTS_SIZE: usize = 1_000_000;
+
+/// An item added by the pool to the coinbase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CoinbaseItem {
+    name: String,
+    size: usize,
+    sigops: usize,
+}
+
+/// Size and sigops of the items added by the pool to the coinbase of every template.
+#[derive(Debug, Clone)]
+pub struct CoinbaseConstraints {
+    outputs: Vec<CoinbaseItem>,
+    script_sig: Vec<CoinbaseItem>,
+}
+
+impl CoinbaseConstraints {
+    /// Accounts for the coinbase `outputs` of the pool, and for its `pool_signature`, at its
+    /// longest expansion, and an extranonce of `extranonce_size` bytes pushed to the coinbase
+    /// scriptSig.
+    pub fn new(outputs: &[TxOut], pool_signature: &PoolSignature, extranonce_size: usize) -> Self {
+        let outputs = outputs
+            .iter()
+            .enumerate()
+            .map(|(index, output)| CoinbaseItem {
+                name: format!("output {index} ({})", output.script_pubkey),
+                size: output.size(),
+                sigops: sigop_cost(output.clone()),
+            })
+            .collect();
+        let script_sig = vec![
+            CoinbaseItem {
+                name: "`coinbase_prefix` of the Template Provider".to_string(),
+                size: COINBASE_PREFIX_SIZE,
+                sigops: 0,
+            },
+            CoinbaseItem {
+                name: format!("`pool_signature` \"{pool_signature}\""),
+                size: push_size(pool_signature.max_len()),
+                sigops: 0,
+            },
+            CoinbaseItem {
+                name: "extranonce".to_string(),
+                size: push_size(extranonce_size),
+                sigops: 0,
+            },
+        ];
+        Self {
+            outputs,
+            script_sig,
+        }
+    }
+
+    /// Returns the serialized size of the coinbase outputs of the pool.
+    pub fn outputs_size(&self) -> usize {
+        self.outputs.iter().map(|item| item.size).sum()
+    }
+
+    /// Returns the sigop cost of the coinbase outputs of the pool.
+    pub fn outputs_sigops(&self) -> usize {
+        self.outputs.iter().map(|item| item.sigops).sum()
+    }
+
+    /// Returns the size taken in the coinbase scriptSig, the `coinbase_prefix` included.
+    pub fn script_sig_size(&self) -> usize {
+        self.script_sig.iter().map(|item| item.size).sum()
+    }
+
+    /// Returns the constraints to send to the Template Provider, or an error naming the item
+    /// pushing the coinbase over a limit.
+    pub fn to_message(&self) -> Result<CoinbaseOutputConstraints, String> {
+        check_limit(&self.script_sig, |item| item.size, MAX_SCRIPT_SIG_SIZE).map_err(|item| {
+            format!(
+                "the coinbase scriptSig exceeds {MAX_SCRIPT_SIG_SIZE} bytes with the {} bytes of the {}, shorten `pool_signature`",
+                item.size, item.name
+            )
+        })?;
+        check_limit(&self.outputs, |item| item.size, MAX_OUTPUTS_SIZE).map_err(|item| {
+            format!(
+                "the coinbase outputs exceed {MAX_OUTPUTS_SIZE} bytes with the {} bytes of {}",
+                item.size, item.name
+            )
+        })?;
+        let max_sigops = MAX_BLOCK_SIGOPS_COST.min(u16::MAX.into());
+        check_limit(&self.outputs, |item| item.sigops, max_sigops).map_err(|item| {
+            format!(
+                "the coinbase outputs exceed {max_sigops} sigops with the {} sigops of {}",
+                item.sigops, item.name
+            )
+        })?;
+        Ok(CoinbaseOutputConstraints {
+            coinbase_output_max_additional_size: self.outputs_size() as u32,
+            coinbase_output_max_additional_sigops: self.outputs_sigops() as u16,
+        })
+    }
+}
+
+impl Display for CoinbaseConstraints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "outputs of {} bytes and {} sigops",
+            self.outputs_size(),
+            self.outputs_sigops()
+        )?;
+        for item in &self.outputs {
+            write!(
+                f,
+                ", {}: {} bytes, {} sigops",
+                item.name, item.size, item.sigops
+            )?;
+        }
+        write!(
+            f,
+            "; scriptSig of {} of {MAX_SCRIPT_SIG_SIZE} bytes",
+            self.script_sig_size()
+        )?;
+        for item in &self.script_sig {
+            write!(f, ", {}: {} bytes", item.name, item.size)?;
+        }
+        Ok(())
+    }
+}
+
+// Returns the first item at which the running total of `value` exceeds `max`.
+fn check_limit<'a>(
+    items: &'a [CoinbaseItem],
+    value: impl Fn(&CoinbaseItem) -> usize,
+    max: usize,
+) -> Result<(), &'a CoinbaseItem> {
+    let mut total = 0;
+    for item in items {
+        total += value(item);
+        if total > max {
+            return Err(item);
+        }
+    }
+    Ok(())
+}
+
+// Size of a push of `len` bytes in a script.
+fn push_size(len: usize) -> usize {
+    match len {
+        0..=75 => 1 + len,
+        76..=255 => 2 + len,
+        _ => 3 + len,
+    }
+}
+
+// Sigop cost of `output` in a coinbase transaction.
+fn sigop_cost(output: TxOut) -> usize {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::from(vec![vec![0; 32]]),
+        }],
+        output: vec![output],
+    }
+    .total_sigop_cost(|_| None)
+}
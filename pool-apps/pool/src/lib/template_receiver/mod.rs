@@ -1,38 +1,42 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+mod coinbase_constraints;
 mod common_message_handler;
 use async_channel::{unbounded, Receiver, Sender};
 use stratum_apps::{
-    key_utils::Secp256k1PublicKey,
-    network_helpers::noise_stream::NoiseTcpStream,
+    key_utils::AuthorityPublicKeys,
+    network_helpers::{
+        tcp::TcpOptions,
+        transport::{connect_address_with, Encryption},
+    },
+    runtime::{
+        message_type::{protocol_message_type, MessageType},
+        spawn_io_tasks, TaskManager,
+    },
     stratum_core::{
-        bitcoin::{
-            self, absolute::LockTime, transaction::Version, OutPoint, ScriptBuf, Sequence,
-            Transaction, TxIn, TxOut, Witness,
-        },
-        codec_sv2::HandshakeRole,
         framing_sv2,
         handlers_sv2::HandleCommonMessagesFromServerAsync,
-        noise_sv2::{Error, Initiator},
+        noise_sv2::Error,
         parsers_sv2::{AnyMessage, TemplateDistribution},
-        template_distribution_sv2::CoinbaseOutputConstraints,
+        template_distribution_sv2::MESSAGE_TYPE_NEW_TEMPLATE,
     },
 };
-use tokio::{net::TcpStream, sync::broadcast};
+use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
 
+pub use coinbase_constraints::CoinbaseConstraints;
+
 use crate::{
     error::{PoolError, PoolResult},
     status::{handle_error, Status, StatusSender},
-    task_manager::TaskManager,
-    utils::{
-        get_setup_connection_message_tp, protocol_message_type, spawn_io_tasks, Message,
-        MessageType, SV2Frame, ShutdownMessage, StdFrame,
-    },
+    utils::{get_setup_connection_message_tp, Message, SV2Frame, ShutdownMessage, StdFrame},
 };
 
 #[derive(Clone)]
 pub struct TemplateReceiverChannel {
-    channel_manager_sender: Sender<TemplateDistribution<'static>>,
+    channel_manager_sender: Sender<SV2Frame>,
     channel_manager_receiver: Receiver<TemplateDistribution<'static>>,
     tp_sender: Sender<SV2Frame>,
     tp_receiver: Receiver<SV2Frame>,
@@ -41,21 +45,27 @@ pub struct TemplateReceiverChannel {
 #[derive(Clone)]
 pub struct TemplateReceiver {
     template_receiver_channel: TemplateReceiverChannel,
+    // Whether a template was received, a Template Provider closing the connection before the
+    // first one may have refused the coinbase constraints.
+    template_received: Arc<AtomicBool>,
 }
 
 impl TemplateReceiver {
     /// Establish a new connection to a Template Provider.
     ///
-    /// - Opens a TCP connection
-    /// - Performs Noise handshake
+    /// - Opens a TCP connection with the `tcp` options, or a unix socket connection for a
+    ///   `unix:<path>` address
+    /// - Performs Noise handshake, accepting any of `public_keys`
     /// - Spawns IO tasks for inbound/outbound frames
     ///
-    /// Retries up to 3 times before returning [`PoolError::Shutdown`].
+    /// Retries up to 3 times before returning [`PoolError::TemplateProviderUnreachable`].
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         tp_address: String,
-        public_key: Option<Secp256k1PublicKey>,
+        tcp: TcpOptions,
+        public_keys: Option<AuthorityPublicKeys>,
         channel_manager_receiver: Receiver<TemplateDistribution<'static>>,
-        channel_manager_sender: Sender<TemplateDistribution<'static>>,
+        channel_manager_sender: Sender<SV2Frame>,
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         task_manager: Arc<TaskManager>,
         status_sender: Sender<Status>,
@@ -65,67 +75,47 @@ impl TemplateReceiver {
         for attempt in 1..=MAX_RETRIES {
             info!(attempt, MAX_RETRIES, "Connecting to template provider");
 
-            let initiator = match public_key {
-                Some(pub_key) => {
-                    debug!(attempt, "Using public key for initiator handshake");
-                    Initiator::from_raw_k(pub_key.into_bytes())
-                }
-                None => {
-                    debug!(attempt, "Using anonymous initiator (no public key)");
-                    Initiator::without_pk()
-                }
-            }?;
-
-            match TcpStream::connect(tp_address.as_str()).await {
+            match connect_address_with::<Message>(
+                &tp_address,
+                &tcp,
+                Encryption::Noise,
+                public_keys.as_ref(),
+            )
+            .await
+            {
                 Ok(stream) => {
-                    info!(
-                        attempt,
-                        "TCP connection established, starting Noise handshake"
+                    info!(attempt, "Noise handshake completed successfully");
+
+                    let (reader, writer) = stream.into_split();
+
+                    let status_sender = StatusSender::TemplateReceiver(status_sender);
+                    let (inbound_tx, inbound_rx) = unbounded::<SV2Frame>();
+                    let (outbound_tx, outbound_rx) = unbounded::<SV2Frame>();
+
+                    info!(attempt, "Spawning IO tasks for template receiver");
+                    spawn_io_tasks(
+                        task_manager.clone(),
+                        reader,
+                        writer,
+                        outbound_rx,
+                        inbound_tx,
+                        notify_shutdown,
+                        status_sender,
+                        None,
                     );
 
-                    match NoiseTcpStream::<Message>::new(
-                        stream,
-                        HandshakeRole::Initiator(initiator),
-                    )
-                    .await
-                    {
-                        Ok(noise_stream) => {
-                            info!(attempt, "Noise handshake completed successfully");
-
-                            let (noise_stream_reader, noise_stream_writer) =
-                                noise_stream.into_split();
-
-                            let status_sender = StatusSender::TemplateReceiver(status_sender);
-                            let (inbound_tx, inbound_rx) = unbounded::<SV2Frame>();
-                            let (outbound_tx, outbound_rx) = unbounded::<SV2Frame>();
-
-                            info!(attempt, "Spawning IO tasks for template receiver");
-                            spawn_io_tasks(
-                                task_manager.clone(),
-                                noise_stream_reader,
-                                noise_stream_writer,
-                                outbound_rx,
-                                inbound_tx,
-                                notify_shutdown,
-                                status_sender,
-                            );
-
-                            let template_receiver_channel = TemplateReceiverChannel {
-                                channel_manager_receiver,
-                                channel_manager_sender,
-                                tp_receiver: inbound_rx,
-                                tp_sender: outbound_tx,
-                            };
-
-                            info!(attempt, "TemplateReceiver initialized successfully");
-                            return Ok(TemplateReceiver {
-                                template_receiver_channel,
-                            });
-                        }
-                        Err(e) => {
-                            error!(attempt, error = ?e, "Noise handshake failed");
-                        }
-                    }
+                    let template_receiver_channel = TemplateReceiverChannel {
+                        channel_manager_receiver,
+                        channel_manager_sender,
+                        tp_receiver: inbound_rx,
+                        tp_sender: outbound_tx,
+                    };
+
+                    info!(attempt, "TemplateReceiver initialized successfully");
+                    return Ok(TemplateReceiver {
+                        template_receiver_channel,
+                        template_received: Arc::new(AtomicBool::new(false)),
+                    });
                 }
                 Err(e) => {
                     warn!(attempt, MAX_RETRIES, error = ?e, "Failed to connect to template provider");
@@ -139,14 +129,14 @@ impl TemplateReceiver {
         }
 
         error!("Exhausted all connection attempts, shutting down TemplateReceiver");
-        Err(PoolError::Shutdown)
+        Err(PoolError::TemplateProviderUnreachable(tp_address))
     }
 
     /// Start unified message loop for TemplateReceiver.
     ///
     /// Responsibilities:
     /// - Run handshake (`setup_connection`)
-    /// - Send [`CoinbaseOutputConstraints`]
+    /// - Send `CoinbaseOutputConstraints`
     /// - Handle:
     ///   - Messages from Template Provider
     ///   - Messages from ChannelManager
@@ -157,7 +147,7 @@ impl TemplateReceiver {
         notify_shutdown: broadcast::Sender<ShutdownMessage>,
         status_sender: Sender<Status>,
         task_manager: Arc<TaskManager>,
-        coinbase_outputs: Vec<u8>,
+        coinbase_constraints: CoinbaseConstraints,
     ) -> PoolResult<()> {
         let status_sender = StatusSender::TemplateReceiver(status_sender);
         let mut shutdown_rx = notify_shutdown.subscribe();
@@ -165,10 +155,11 @@ impl TemplateReceiver {
         info!("Initialized state for starting template receiver");
         self.setup_connection(socket_address).await?;
 
-        self.coinbase_constraints(coinbase_outputs).await?;
+        self.coinbase_constraints(&coinbase_constraints).await?;
 
         info!("Setup Connection done. connection with template receiver is now done");
-        task_manager.spawn(
+        task_manager.spawn_critical(
+            "template_receiver",
             async move {
                 loop {
                     let mut self_clone_1 = self.clone();
@@ -190,6 +181,11 @@ impl TemplateReceiver {
                         res = self_clone_1.handle_template_provider_message() => {
                             if let Err(e) = res {
                                 error!("TemplateReceiver template provider handler failed: {e:?}");
+                                if !self.template_received.load(Ordering::Relaxed) {
+                                    error!(
+                                        "The Template Provider closed the connection before sending a template, it may have refused the coinbase constraints of the pool ({coinbase_constraints})"
+                                    );
+                                }
                                 handle_error(&status_sender, e).await;
                                 break;
                             }
@@ -213,7 +209,7 @@ impl TemplateReceiver {
     ///
     /// Routes:
     /// - `Common` messages → handled locally
-    /// - `TemplateDistribution` messages → forwarded to ChannelManager
+    /// - `TemplateDistribution` messages → forwarded to ChannelManager as their frames
     /// - Unsupported messages → logged and ignored
     pub async fn handle_template_provider_message(&mut self) -> PoolResult<()> {
         let mut sv2_frame = self.template_receiver_channel.tp_receiver.recv().await?;
@@ -237,12 +233,14 @@ impl TemplateReceiver {
                 .await?;
             }
             MessageType::TemplateDistribution => {
-                let message = TemplateDistribution::try_from((message_type, sv2_frame.payload()))?
-                    .into_static();
+                // Forwarded as is, the channel manager decodes it in place.
+                if message_type == MESSAGE_TYPE_NEW_TEMPLATE {
+                    self.template_received.store(true, Ordering::Relaxed);
+                }
 
                 self.template_receiver_channel
                     .channel_manager_sender
-                    .send(message)
+                    .send(sv2_frame)
                     .await
                     .map_err(|e| {
                         error!(error=?e, "Failed to send template distribution message to channel manager.");
@@ -278,41 +276,25 @@ impl TemplateReceiver {
         Ok(())
     }
 
-    /// Build and send [`CoinbaseOutputConstraints`] to the TP.
-    pub async fn coinbase_constraints(&mut self, coinbase_outputs: Vec<u8>) -> PoolResult<()> {
-        debug!(
-            "Deserializing coinbase outputs ({} bytes)",
-            coinbase_outputs.len()
-        );
-        let outputs: Vec<TxOut> = bitcoin::consensus::deserialize(&coinbase_outputs)?;
-
-        let max_size: u32 = outputs.iter().map(|o| o.size() as u32).sum();
+    /// Build and send `CoinbaseOutputConstraints` to the TP.
+    ///
+    /// Fails with [`PoolError::CoinbaseConstraints`] if the coinbase additions of the pool exceed
+    /// a limit, naming the item responsible.
+    pub async fn coinbase_constraints(
+        &mut self,
+        coinbase_constraints: &CoinbaseConstraints,
+    ) -> PoolResult<()> {
+        debug!("Coinbase additions of the pool: {coinbase_constraints}");
+        let constraints = coinbase_constraints.to_message().map_err(|e| {
+            error!("Coinbase additions of the pool do not fit: {e} ({coinbase_constraints})");
+            PoolError::CoinbaseConstraints(e)
+        })?;
         debug!(
-            max_size,
-            outputs_count = outputs.len(),
-            "Calculated max coinbase output size"
+            max_size = constraints.coinbase_output_max_additional_size,
+            max_sigops = constraints.coinbase_output_max_additional_sigops,
+            "Calculated coinbase output constraints"
         );
 
-        let dummy_coinbase = Transaction {
-            version: Version::TWO,
-            lock_time: LockTime::ZERO,
-            input: vec![TxIn {
-                previous_output: OutPoint::null(),
-                script_sig: ScriptBuf::new(),
-                sequence: Sequence::MAX,
-                witness: Witness::from(vec![vec![0; 32]]),
-            }],
-            output: outputs,
-        };
-
-        let max_sigops = dummy_coinbase.total_sigop_cost(|_| None) as u16;
-        debug!(max_sigops, "Calculated max sigops for coinbase");
-
-        let constraints = CoinbaseOutputConstraints {
-            coinbase_output_max_additional_size: max_size,
-            coinbase_output_max_additional_sigops: max_sigops,
-        };
-
         let msg = AnyMessage::TemplateDistribution(
             TemplateDistribution::CoinbaseOutputConstraints(constraints),
         );
@@ -333,13 +315,11 @@ impl TemplateReceiver {
 
     // Performs the initial handshake with Template Provider.
     pub async fn setup_connection(&mut self, addr: String) -> PoolResult<()> {
-        let socket: SocketAddr = addr.parse().map_err(|_| {
+        debug!(%addr, "Building SetupConnection message to the Template Provider");
+        let setup_msg = get_setup_connection_message_tp(&addr).ok_or_else(|| {
             error!(%addr, "Invalid socket address");
             PoolError::InvalidSocketAddress(addr.clone())
         })?;
-
-        debug!(%socket, "Building SetupConnection message to the Template Provider");
-        let setup_msg = get_setup_connection_message_tp(socket);
         let frame: StdFrame = Message::Common(setup_msg.into()).try_into()?;
 
         info!("Sending SetupConnection message to the Template Provider");
@@ -0,0 +1,195 @@
+//! ## Workers Module
+//!
+//! Statistics of the workers mining on the pool, rolled up per account, for the frontends showing
+//! miners the breakdown of their farm per rig. [`Workers`] keeps, for every user identity, the
+//! shares accepted since startup and their work, the time of the last one, the best share and
+//! the hashrate, estimated from the work of the shares accepted over the last ten minutes. An
+//! account sums up the shares, work and hashrate of its workers, and reports the latest share and
+//! the best one of any of them. They are served on `/workers` of the monitoring endpoint.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+use stratum_apps::{custom_mutex::Mutex, unix_time, worker_identity::WorkerIdentity};
+
+/// Duration of the buckets the hashrate window is made of.
+const HASHRATE_BUCKET: Duration = Duration::from_secs(60);
+/// Buckets in the window the hashrate is estimated over.
+const HASHRATE_BUCKETS: u64 = 10;
+
+#[derive(Debug)]
+struct WorkerStats {
+    worker: Option<String>,
+    shares: u64,
+    work: f64,
+    last_share_ms: u64,
+    best_share_difficulty: f64,
+    // Index and work of the buckets with a share in the hashrate window, oldest first.
+    buckets: VecDeque<(u64, f64)>,
+}
+
+impl WorkerStats {
+    fn new(worker: Option<String>) -> Self {
+        Self {
+            worker,
+            shares: 0,
+            work: 0.0,
+            last_share_ms: 0,
+            best_share_difficulty: 0.0,
+            buckets: VecDeque::new(),
+        }
+    }
+
+    // Records a share of `work` meeting `difficulty`, accepted in `bucket`.
+    fn record(&mut self, bucket: u64, work: f64, difficulty: f64) {
+        self.shares += 1;
+        self.work += work;
+        self.last_share_ms = unix_time::now_ms();
+        self.best_share_difficulty = self.best_share_difficulty.max(difficulty);
+        match self.buckets.back_mut() {
+            Some((index, bucket_work)) if *index == bucket => *bucket_work += work,
+            _ => self.buckets.push_back((bucket, work)),
+        }
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(index, _)| index + HASHRATE_BUCKETS <= bucket)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    // Returns the hashrate over the window up to `bucket`, `window_secs` long.
+    fn hashrate(&self, bucket: u64, window_secs: f64) -> f64 {
+        let work: f64 = self
+            .buckets
+            .iter()
+            .filter(|(index, _)| index + HASHRATE_BUCKETS > bucket)
+            .map(|(_, work)| work)
+            .sum();
+        work * 2f64.powi(32) / window_secs
+    }
+}
+
+/// Statistics of the workers of every account since startup.
+#[derive(Debug)]
+pub struct Workers {
+    started: Instant,
+    accounts: Mutex<BTreeMap<String, BTreeMap<String, WorkerStats>>>,
+}
+
+impl Workers {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            accounts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records a share of `user_identity`, whose account and worker are `identity`, accepted at
+    /// a target of difficulty `work` with a hash meeting `difficulty`.
+    pub fn record(
+        &self,
+        user_identity: &str,
+        identity: &WorkerIdentity,
+        work: f64,
+        difficulty: f64,
+    ) {
+        let bucket = self.bucket();
+        self.accounts.super_safe_lock(|accounts| {
+            let workers = accounts.entry(identity.account.clone()).or_default();
+            let stats = match workers.get_mut(user_identity) {
+                Some(stats) => stats,
+                None => workers
+                    .entry(user_identity.to_string())
+                    .or_insert_with(|| WorkerStats::new(identity.worker.clone())),
+            };
+            stats.record(bucket, work, difficulty);
+        });
+    }
+
+    // Index of the current bucket since startup.
+    fn bucket(&self) -> u64 {
+        self.started.elapsed().as_secs() / HASHRATE_BUCKET.as_secs()
+    }
+
+    pub(crate) fn snapshot(&self) -> BTreeMap<String, AccountStatus> {
+        let bucket = self.bucket();
+        // Shorter than the window until the pool has been running for as long.
+        let window_secs = self
+            .started
+            .elapsed()
+            .as_secs_f64()
+            .clamp(1.0, (HASHRATE_BUCKET.as_secs() * HASHRATE_BUCKETS) as f64);
+        self.accounts.super_safe_lock(|accounts| {
+            accounts
+                .iter()
+                .map(|(account, workers)| {
+                    let workers: Vec<WorkerStatus> = workers
+                        .iter()
+                        .map(|(user_identity, stats)| WorkerStatus {
+                            user_identity: user_identity.clone(),
+                            worker: stats.worker.clone(),
+                            hashrate: stats.hashrate(bucket, window_secs),
+                            shares: stats.shares,
+                            work: stats.work,
+                            last_share_ms: stats.last_share_ms,
+                            best_share_difficulty: stats.best_share_difficulty,
+                        })
+                        .collect();
+                    (account.clone(), AccountStatus::new(workers))
+                })
+                .collect()
+        })
+    }
+}
+
+impl Default for Workers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Statistics of an account and of its workers, as served on `/workers`.
+#[derive(Debug, Serialize)]
+pub(crate) struct AccountStatus {
+    hashrate: f64,
+    shares: u64,
+    work: f64,
+    last_share_ms: u64,
+    best_share_difficulty: f64,
+    workers: Vec<WorkerStatus>,
+}
+
+impl AccountStatus {
+    fn new(workers: Vec<WorkerStatus>) -> Self {
+        Self {
+            hashrate: workers.iter().map(|worker| worker.hashrate).sum(),
+            shares: workers.iter().map(|worker| worker.shares).sum(),
+            work: workers.iter().map(|worker| worker.work).sum(),
+            last_share_ms: workers
+                .iter()
+                .map(|worker| worker.last_share_ms)
+                .max()
+                .unwrap_or_default(),
+            best_share_difficulty: workers
+                .iter()
+                .map(|worker| worker.best_share_difficulty)
+                .fold(0.0, f64::max),
+            workers,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WorkerStatus {
+    user_identity: String,
+    worker: Option<String>,
+    hashrate: f64,
+    shares: u64,
+    work: f64,
+    last_share_ms: u64,
+    best_share_difficulty: f64,
+}
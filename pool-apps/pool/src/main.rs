@@ -1,15 +1,80 @@
+use std::process::ExitCode;
+
 use pool_sv2::PoolSv2;
-use stratum_apps::config_helpers::logging::init_logging;
+use stratum_apps::{
+    config_helpers::logging::{init_logging, shutdown_logging},
+    exit::ExitStatus,
+};
 
 use crate::args::process_cli_args;
 
 mod args;
 
 #[tokio::main]
-async fn main() {
-    let config = process_cli_args();
-    init_logging(config.log_dir());
-    if let Err(e) = PoolSv2::new(config).start().await {
-        tracing::error!("Pool Error'ed out: {e}");
+async fn main() -> ExitCode {
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    let (config, config_path) = process_cli_args().await;
+    init_logging(config.log_dir(), config.logging());
+    let pool = PoolSv2::new(config);
+    #[cfg(feature = "control")]
+    let pool = {
+        let config_path = config_path.clone();
+        pool.with_config_loader(pool_sv2::control::ConfigLoader::new(move || {
+            args::load_config(&config_path, &[]).map_err(|e| e.to_string())
+        }))
+    };
+    #[cfg(unix)]
+    tokio::spawn(reload_authority_keys_on_sighup(
+        config_path,
+        pool.authority_keys(),
+    ));
+    let status = match pool.start().await {
+        Ok(()) => ExitStatus::Success,
+        Err(e) => {
+            let status = e.exit_status();
+            tracing::error!("Pool Error'ed out: {e}, exiting with {status}");
+            status
+        }
+    };
+    shutdown_logging();
+    status.into()
+}
+
+/// Rotates the authority keypair to the one found in the config file every time the process
+/// receives `SIGHUP`.
+#[cfg(unix)]
+async fn reload_authority_keys_on_sighup(
+    config_path: std::path::PathBuf,
+    authority_keys: pool_sv2::authority::AuthorityKeys,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::warn!(
+                "Failed to install SIGHUP handler, authority keys cannot be reloaded: {e}"
+            );
+            return;
+        }
     };
+    while hangup.recv().await.is_some() {
+        tracing::info!(
+            "SIGHUP received — reloading authority keys from {}",
+            config_path.display()
+        );
+        match args::load_config(&config_path, &[]) {
+            Ok(config) => {
+                if let Err(e) = authority_keys.rotate(
+                    *config.authority_public_key(),
+                    *config.authority_secret_key(),
+                ) {
+                    tracing::error!("Invalid authority keypair, keeping the current one: {e:?}");
+                }
+            }
+            Err(e) => {
+                tracing::error!("Failed to reload config, keeping the current authority keys: {e}")
+            }
+        }
+    }
 }
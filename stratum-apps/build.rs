@@ -0,0 +1,12 @@
+//! Compiles the protobuf definitions of the control service, with the `control` feature.
+
+fn main() {
+    #[cfg(feature = "control")]
+    {
+        // Use a vendored protoc, so that building does not require one to be installed
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc");
+        std::env::set_var("PROTOC", protoc);
+        tonic_build::compile_protos("proto/control.proto")
+            .expect("failed to compile proto/control.proto");
+    }
+}
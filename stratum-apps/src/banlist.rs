@@ -0,0 +1,517 @@
+//! Ban list of the peers refused by a role
+//!
+//! A [`BanList`] holds bans on IP addresses, CIDR ranges and peer public keys, each one either
+//! permanent or expiring after a TTL. It is shared by the accept loop of a role, which drops new
+//! connections from banned addresses, and by its admin surface, which adds, removes and lists
+//! bans.
+//!
+//! With a `file` in its [`BanListConfig`], usually embedded in the role configuration as an
+//! optional `[banlist]` table, the bans are loaded from the file at startup and written back on
+//! every change, so that they survive restarts. The file holds one ban per line and can be edited
+//! by hand, [`BanList::reload_if_modified`] picking up the changes:
+//!
+//! ```text
+//! # target expires_at reason
+//! 203.0.113.7 - flooding
+//! 198.51.100.0/24 1767225600 abusive range
+//! 9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72 - leaked key
+//! ```
+//!
+//! `expires_at` is a UNIX timestamp in seconds, `-` for a permanent ban.
+//!
+//! Noise initiators present no static key, so listeners only enforce address bans: public key
+//! bans apply to the peers authenticated by a static key.
+
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    custom_mutex::Mutex, key_utils::Secp256k1PublicKey, state_file::write_atomically, unix_time,
+};
+
+/// Ban list configuration.
+///
+/// ```toml
+/// [banlist]
+/// file = "./banlist.txt"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct BanListConfig {
+    /// File the bans are loaded from and saved to. Bans are only kept in memory if unset.
+    pub file: Option<PathBuf>,
+}
+
+impl BanListConfig {
+    /// Creates a new [`BanListConfig`].
+    pub fn new(file: Option<PathBuf>) -> Self {
+        Self { file }
+    }
+}
+
+/// A range of IP addresses, written `address/prefix_len`, or just `address` for a single one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Creates the range of the addresses sharing the first `prefix_len` bits of `address`.
+    ///
+    /// Returns `None` if `prefix_len` is longer than the addresses of the family of `address`.
+    pub fn new(address: IpAddr, prefix_len: u8) -> Option<Self> {
+        let address = address.to_canonical();
+        if prefix_len > max_prefix_len(address) {
+            return None;
+        }
+        Some(Self {
+            network: masked(address, prefix_len),
+            prefix_len,
+        })
+    }
+
+    /// Returns whether `address` is in the range. IPv4-mapped IPv6 addresses, as accepted by a
+    /// dual-stack listener, match the ranges of their IPv4 address.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = address.to_canonical();
+        address.is_ipv4() == self.network.is_ipv4()
+            && masked(address, self.prefix_len) == self.network
+    }
+}
+
+impl From<IpAddr> for IpRange {
+    fn from(address: IpAddr) -> Self {
+        let address = address.to_canonical();
+        Self {
+            network: address,
+            prefix_len: max_prefix_len(address),
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((address, prefix_len)) = s.split_once('/') else {
+            return s
+                .parse::<IpAddr>()
+                .map(Self::from)
+                .map_err(|e| format!("invalid IP address `{s}`: {e}"));
+        };
+        let address = address
+            .parse::<IpAddr>()
+            .map_err(|e| format!("invalid IP address `{address}`: {e}"))?;
+        prefix_len
+            .parse::<u8>()
+            .ok()
+            .and_then(|prefix_len| Self::new(address, prefix_len))
+            .ok_or_else(|| format!("invalid prefix length in `{s}`"))
+    }
+}
+
+impl Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix_len == max_prefix_len(self.network) {
+            write!(f, "{}", self.network)
+        } else {
+            write!(f, "{}/{}", self.network, self.prefix_len)
+        }
+    }
+}
+
+fn max_prefix_len(address: IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn masked(address: IpAddr, prefix_len: u8) -> IpAddr {
+    let host_bits = (max_prefix_len(address) - prefix_len) as u32;
+    match address {
+        IpAddr::V4(address) => {
+            let mask = u32::MAX.checked_shl(host_bits).unwrap_or(0);
+            IpAddr::V4((u32::from(address) & mask).into())
+        }
+        IpAddr::V6(address) => {
+            let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+            IpAddr::V6((u128::from(address) & mask).into())
+        }
+    }
+}
+
+/// What a ban applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BanTarget {
+    /// An IP address or a CIDR range.
+    Ip(IpRange),
+    /// The static public key of a peer.
+    PublicKey(Secp256k1PublicKey),
+}
+
+impl FromStr for BanTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(range) = s.parse::<IpRange>() {
+            return Ok(Self::Ip(range));
+        }
+        match s.parse::<Secp256k1PublicKey>() {
+            Ok(key) => Ok(Self::PublicKey(key)),
+            Err(_) => Err(format!(
+                "`{s}` is neither an IP address, a CIDR range nor a public key"
+            )),
+        }
+    }
+}
+
+impl Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(range) => range.fmt(f),
+            Self::PublicKey(key) => key.fmt(f),
+        }
+    }
+}
+
+/// A ban of a [`BanTarget`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    pub target: BanTarget,
+    /// When the ban is lifted, `None` for a permanent ban.
+    pub expires_at: Option<SystemTime>,
+    pub reason: Option<String>,
+}
+
+impl Ban {
+    fn is_active(&self, now: SystemTime) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    fn parse_line(line: &str) -> Result<Self, String> {
+        let (target, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim_start();
+        let (expires_at, reason) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let expires_at = match expires_at {
+            "" | "-" => None,
+            secs => Some(
+                secs.parse::<u64>()
+                    .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                    .map_err(|_| format!("invalid expiry `{secs}`"))?,
+            ),
+        };
+        let reason = reason.trim();
+        Ok(Self {
+            target: target.parse()?,
+            expires_at,
+            reason: (!reason.is_empty()).then(|| reason.to_string()),
+        })
+    }
+}
+
+/// Formats the ban as a line of the ban list file.
+impl Display for Ban {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.target)?;
+        match self.expires_at {
+            Some(expires_at) => write!(f, " {}", unix_time::secs(expires_at))?,
+            None => f.write_str(" -")?,
+        }
+        match &self.reason {
+            Some(reason) => write!(f, " {reason}"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    bans: HashMap<BanTarget, Ban>,
+    // Modification time of the file when it was last loaded or saved
+    modified: Option<SystemTime>,
+}
+
+/// Bans shared by the accept loop and the admin surface of a role.
+#[derive(Debug)]
+pub struct BanList {
+    file: Option<PathBuf>,
+    inner: Mutex<Inner>,
+}
+
+impl BanList {
+    /// Creates the ban list of `config`, with the bans of its file if it exists.
+    ///
+    /// Returns an error if the file cannot be read or holds an invalid line.
+    pub fn new(config: &BanListConfig) -> io::Result<Self> {
+        let mut inner = Inner::default();
+        if let Some(file) = &config.file {
+            if let Some((bans, modified)) = load(file)? {
+                inner.bans = bans;
+                inner.modified = modified;
+            }
+        }
+        Ok(Self {
+            file: config.file.clone(),
+            inner: Mutex::new(inner),
+        })
+    }
+
+    /// Returns the file the bans are saved to, if any.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Bans `target`, for `ttl` or permanently if `None`, replacing any previous ban of it.
+    ///
+    /// The ban is in effect even if it cannot be saved to the file, in which case the error is
+    /// returned.
+    pub fn ban(
+        &self,
+        target: BanTarget,
+        ttl: Option<Duration>,
+        reason: Option<String>,
+    ) -> io::Result<Ban> {
+        let ban = Ban {
+            target,
+            expires_at: ttl.map(|ttl| SystemTime::now() + ttl),
+            // The reason ends the line of the ban in the file
+            reason: reason
+                .map(|reason| reason.replace(['\r', '\n'], " ").trim().to_string())
+                .filter(|reason| !reason.is_empty()),
+        };
+        self.inner.super_safe_lock(|inner| {
+            inner.bans.insert(target, ban.clone());
+            self.save(inner)
+        })?;
+        Ok(ban)
+    }
+
+    /// Lifts the ban of `target`, returning whether it was banned.
+    pub fn unban(&self, target: &BanTarget) -> io::Result<bool> {
+        self.inner.super_safe_lock(|inner| {
+            let now = SystemTime::now();
+            match inner.bans.remove(target) {
+                Some(ban) => self.save(inner).map(|()| ban.is_active(now)),
+                None => Ok(false),
+            }
+        })
+    }
+
+    /// Returns the bans in effect, sorted by target.
+    pub fn list(&self) -> Vec<Ban> {
+        let now = SystemTime::now();
+        let mut bans: Vec<Ban> = self.inner.super_safe_lock(|inner| {
+            inner
+                .bans
+                .values()
+                .filter(|ban| ban.is_active(now))
+                .cloned()
+                .collect()
+        });
+        bans.sort_by_key(|ban| ban.target.to_string());
+        bans
+    }
+
+    /// Returns whether `address` is in a banned address or range.
+    pub fn is_ip_banned(&self, address: IpAddr) -> bool {
+        self.is_banned(|target| matches!(target, BanTarget::Ip(range) if range.contains(address)))
+    }
+
+    /// Returns whether `key` is banned.
+    pub fn is_key_banned(&self, key: &Secp256k1PublicKey) -> bool {
+        self.is_banned(|target| target == &BanTarget::PublicKey(*key))
+    }
+
+    /// Reloads the bans from the file if it was modified since it was last loaded or saved,
+    /// returning whether it was reloaded.
+    ///
+    /// The bans in memory are kept if the file was removed or is invalid.
+    pub fn reload_if_modified(&self) -> io::Result<bool> {
+        let Some(file) = &self.file else {
+            return Ok(false);
+        };
+        let modified = match fs::metadata(file) {
+            Ok(metadata) => metadata.modified().ok(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if self
+            .inner
+            .super_safe_lock(|inner| inner.modified == modified)
+        {
+            return Ok(false);
+        }
+        let Some((bans, modified)) = load(file)? else {
+            return Ok(false);
+        };
+        self.inner.super_safe_lock(|inner| {
+            inner.bans = bans;
+            inner.modified = modified;
+        });
+        Ok(true)
+    }
+
+    fn is_banned(&self, matches: impl Fn(&BanTarget) -> bool) -> bool {
+        let now = SystemTime::now();
+        self.inner.super_safe_lock(|inner| {
+            inner
+                .bans
+                .values()
+                .any(|ban| matches(&ban.target) && ban.is_active(now))
+        })
+    }
+
+    // Writes the bans in effect to a temporary file renamed over the ban list file, so that a
+    // crash never leaves it half written.
+    fn save(&self, inner: &mut Inner) -> io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let now = SystemTime::now();
+        inner.bans.retain(|_, ban| ban.is_active(now));
+        let mut lines: Vec<String> = inner.bans.values().map(Ban::to_string).collect();
+        lines.sort();
+        let mut contents = String::from("# target expires_at reason\n");
+        for line in lines {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        write_atomically(file, contents.as_bytes())?;
+        inner.modified = fs::metadata(file)?.modified().ok();
+        Ok(())
+    }
+}
+
+type LoadedBans = (HashMap<BanTarget, Ban>, Option<SystemTime>);
+
+// Returns the bans in effect in `file` with its modification time, `None` if it does not exist.
+fn load(file: &Path) -> io::Result<Option<LoadedBans>> {
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let modified = fs::metadata(file)?.modified().ok();
+    let now = SystemTime::now();
+    let mut bans = HashMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let ban = Ban::parse_line(line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {e}", file.display(), index + 1),
+            )
+        })?;
+        if ban.is_active(now) {
+            bans.insert(ban.target, ban);
+        }
+    }
+    Ok(Some((bans, modified)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC_KEY: &str = "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72";
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn ranges_match_their_addresses() {
+        let range: IpRange = "198.51.100.77/24".parse().unwrap();
+        assert_eq!(range.to_string(), "198.51.100.0/24");
+        assert!(range.contains(ip("198.51.100.1")));
+        assert!(range.contains(ip("::ffff:198.51.100.1")));
+        assert!(!range.contains(ip("198.51.101.1")));
+        assert!(!range.contains(ip("2001:db8::1")));
+
+        let single: IpRange = "2001:db8::1".parse().unwrap();
+        assert_eq!(single.to_string(), "2001:db8::1");
+        assert!(single.contains(ip("2001:db8::1")));
+        assert!(!single.contains(ip("2001:db8::2")));
+
+        assert!("0.0.0.0/0"
+            .parse::<IpRange>()
+            .unwrap()
+            .contains(ip("1.2.3.4")));
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("not an address".parse::<BanTarget>().is_err());
+        assert_eq!(
+            PUBLIC_KEY.parse::<BanTarget>().unwrap().to_string(),
+            PUBLIC_KEY
+        );
+    }
+
+    #[test]
+    fn expired_bans_are_lifted() {
+        let banlist = BanList::new(&BanListConfig::default()).unwrap();
+        let target = "203.0.113.7".parse().unwrap();
+        banlist
+            .ban(target, Some(Duration::from_secs(3600)), None)
+            .unwrap();
+        assert!(banlist.is_ip_banned(ip("203.0.113.7")));
+        assert!(!banlist.is_ip_banned(ip("203.0.113.8")));
+
+        banlist.ban(target, Some(Duration::ZERO), None).unwrap();
+        assert!(!banlist.is_ip_banned(ip("203.0.113.7")));
+        assert!(banlist.list().is_empty());
+        assert!(!banlist.unban(&target).unwrap());
+    }
+
+    #[test]
+    fn bans_are_saved_and_reloaded() {
+        let file = std::env::temp_dir().join(format!("sv2-banlist-test-{}", std::process::id()));
+        let config = BanListConfig::new(Some(file.clone()));
+        let banlist = BanList::new(&config).unwrap();
+        let key = PUBLIC_KEY.parse().unwrap();
+        banlist
+            .ban(
+                "198.51.100.0/24".parse().unwrap(),
+                None,
+                Some("abusive\nrange".to_string()),
+            )
+            .unwrap();
+        banlist
+            .ban(key, Some(Duration::from_secs(3600)), None)
+            .unwrap();
+
+        let reloaded = BanList::new(&config).unwrap();
+        assert_eq!(reloaded.list().len(), 2);
+        assert_eq!(reloaded.list()[0].reason.as_deref(), Some("abusive range"));
+        assert!(reloaded.is_ip_banned(ip("198.51.100.9")));
+        assert!(reloaded.is_key_banned(&PUBLIC_KEY.parse().unwrap()));
+
+        // A hand edit of the file is picked up by the running list
+        fs::write(&file, "# edited\n203.0.113.7 - flooding\n").unwrap();
+        let modified = SystemTime::now() + Duration::from_secs(1);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(banlist.reload_if_modified().unwrap());
+        assert!(!banlist.reload_if_modified().unwrap());
+        assert!(banlist.is_ip_banned(ip("203.0.113.7")));
+        assert!(!banlist.is_key_banned(&PUBLIC_KEY.parse().unwrap()));
+
+        fs::write(&file, "203.0.113.7 soon\n").unwrap();
+        assert!(BanList::new(&config).is_err());
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+//! Generates, inspects and converts the Secp256k1 authority keys of the Stratum V2 roles.
+//!
+//! Keys are printed in the encoding of the role configurations: the base58check of the 32 secret
+//! bytes for `authority_secret_key`, and the base58check of a 2 bytes version followed by the 32
+//! bytes x-only public key for `authority_public_key` and the `authority_pubkey` of downstreams.
+
+use clap::{Parser, Subcommand};
+use stratum_apps::key_utils::{Secp256k1PublicKey, Secp256k1SecretKey};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "key-utils",
+    version,
+    about = "Generate, inspect and convert Stratum V2 authority keys"
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a new authority keypair, printed as configuration lines
+    Generate,
+    /// Print the public key of a secret key, as expected by downstream configurations
+    PublicKey {
+        #[arg(value_name = "SECRET_KEY")]
+        secret_key: Secp256k1SecretKey,
+    },
+    /// Check that a key is valid and print its kind and its raw bytes in hex
+    Inspect {
+        #[arg(value_name = "KEY")]
+        key: String,
+    },
+    /// Encode the 32 bytes of a key given in hex
+    FromHex {
+        #[arg(value_name = "HEX")]
+        hex: String,
+        /// The bytes are a secret key, an x-only public key otherwise
+        #[arg(long)]
+        secret: bool,
+    },
+}
+
+fn main() {
+    let args = Args::parse();
+    match run(args.command) {
+        Ok(output) => println!("{output}"),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run(command: Command) -> Result<String, String> {
+    match command {
+        Command::Generate => {
+            let secret_key = Secp256k1SecretKey::generate();
+            Ok(keypair_lines(secret_key))
+        }
+        Command::PublicKey { secret_key } => Ok(Secp256k1PublicKey::from(secret_key).to_string()),
+        Command::Inspect { key } => inspect(&key),
+        Command::FromHex { hex, secret } => {
+            let bytes = decode_hex(&hex)?;
+            if secret {
+                let secret_key = secp256k1::SecretKey::from_slice(&bytes)
+                    .map_err(|e| format!("invalid secret key: {e}"))?;
+                Ok(keypair_lines(Secp256k1SecretKey(secret_key)))
+            } else {
+                let public_key = secp256k1::XOnlyPublicKey::from_slice(&bytes)
+                    .map_err(|e| format!("invalid x-only public key: {e}"))?;
+                Ok(format!(
+                    "authority_public_key = \"{}\"",
+                    Secp256k1PublicKey(public_key)
+                ))
+            }
+        }
+    }
+}
+
+fn keypair_lines(secret_key: Secp256k1SecretKey) -> String {
+    format!(
+        "authority_public_key = \"{}\"\nauthority_secret_key = \"{secret_key}\"",
+        Secp256k1PublicKey::from(secret_key)
+    )
+}
+
+// Public keys are tried first: their version prefix makes them longer than secret keys, so a
+// string never decodes as both.
+fn inspect(key: &str) -> Result<String, String> {
+    if let Ok(public_key) = key.parse::<Secp256k1PublicKey>() {
+        return Ok(format!(
+            "public key\nx-only: {}",
+            encode_hex(&public_key.into_bytes())
+        ));
+    }
+    match key.parse::<Secp256k1SecretKey>() {
+        Ok(secret_key) => Ok(format!(
+            "secret key\nsecret: {}\npublic key: {}",
+            encode_hex(&secret_key.into_bytes()),
+            Secp256k1PublicKey::from(secret_key)
+        )),
+        Err(e) => Err(format!("`{key}` is neither a public nor a secret key: {e}")),
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<[u8; 32], String> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(format!("expected 64 hex characters, got `{hex}`"));
+    }
+    let mut bytes = [0; 32];
+    for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).expect("hex is ascii");
+        *byte = u8::from_str_radix(digits, 16).map_err(|e| format!("invalid hex `{hex}`: {e}"))?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC_KEY: &str = "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72";
+    const SECRET_KEY: &str = "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n";
+
+    #[test]
+    fn keys_are_inspected_and_converted_back() {
+        let inspected = inspect(SECRET_KEY).unwrap();
+        assert!(inspected.ends_with(&format!("public key: {PUBLIC_KEY}")));
+        let secret_hex = inspected
+            .lines()
+            .nth(1)
+            .unwrap()
+            .trim_start_matches("secret: ");
+        let converted = run(Command::FromHex {
+            hex: secret_hex.to_string(),
+            secret: true,
+        })
+        .unwrap();
+        assert!(converted.contains(SECRET_KEY), "{converted}");
+
+        let inspected = inspect(PUBLIC_KEY).unwrap();
+        let public_hex = inspected.trim_start_matches("public key\nx-only: ");
+        let converted = run(Command::FromHex {
+            hex: public_hex.to_string(),
+            secret: false,
+        })
+        .unwrap();
+        assert_eq!(
+            converted,
+            format!("authority_public_key = \"{PUBLIC_KEY}\"")
+        );
+
+        assert!(inspect("not a key").is_err());
+        assert!(decode_hex("abcd").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! Serves synthetic templates to the Stratum V2 roles, in place of a Bitcoin node and its
+//! Template Provider.
+//!
+//! Without `--script`, a new template is sent every `--interval-secs`, every
+//! `--new-block-every`th of them for a new block. With `--script`, the given steps are run once a
+//! first downstream is ready, e.g. `new-block,wait=5000,new-template,malformed-frame,disconnect`.
+
+use std::{net::SocketAddr, process::ExitCode, time::Duration};
+
+use clap::Parser;
+use stratum_apps::{
+    exit::ExitStatus,
+    key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+    mock_tp::{MockTemplateProvider, MockTpConfig, Schedule, Step},
+};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "mock-tp",
+    version,
+    about = "Serve synthetic templates to Stratum V2 roles"
+)]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8442")]
+    listen: SocketAddr,
+    /// Seconds between two templates, without a script
+    #[arg(long, default_value_t = 30)]
+    interval_secs: u64,
+    /// Number of templates per block, without a script
+    #[arg(long, default_value_t = 10)]
+    new_block_every: u32,
+    /// Comma-separated steps among new-block, new-template, wait=<ms>, delay=<ms>, disconnect
+    /// and malformed-frame
+    #[arg(long, value_delimiter = ',')]
+    script: Option<Vec<Step>>,
+    /// Authority secret key of the Noise handshake, a new one is generated by default
+    #[arg(long)]
+    secret_key: Option<Secp256k1SecretKey>,
+    /// Value of the coinbase left to the roles, in satoshis
+    #[arg(long, default_value_t = 5_000_000_000)]
+    coinbase_value: u64,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+    let args = Args::parse();
+
+    let schedule = match args.script {
+        Some(steps) => Schedule::Scripted(steps),
+        None => Schedule::Periodic {
+            interval: Duration::from_secs(args.interval_secs),
+            new_block_every: args.new_block_every,
+        },
+    };
+    let mut config =
+        MockTpConfig::new(args.listen, schedule).with_coinbase_value(args.coinbase_value);
+    if let Some(secret_key) = args.secret_key {
+        config = config.with_authority_keys(Secp256k1PublicKey::from(secret_key), secret_key);
+    }
+
+    let mock = match MockTemplateProvider::start(config).await {
+        Ok(mock) => mock,
+        Err(e) => {
+            error!("Failed to bind {}: {e}", args.listen);
+            return ExitStatus::Bind.into();
+        }
+    };
+    let _ = tokio::signal::ctrl_c().await;
+    info!(
+        "Shutting down, {} solutions received",
+        mock.solutions().len()
+    );
+    mock.stop().await;
+    ExitStatus::Success.into()
+}
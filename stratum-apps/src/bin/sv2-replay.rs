@@ -0,0 +1,143 @@
+//! Replays a connection recorded by `sv2-sniffer` against a role, checking that the role answers
+//! as it did when captured.
+//!
+//! With `--as downstream`, the replay connects to the role at `--address` and sends the messages
+//! of the captured downstream. With `--as upstream`, it listens on `--address` for the role and
+//! sends the messages of the captured upstream. Exits with a failure on the first message of the
+//! role not matching the capture.
+
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+use clap::{Parser, ValueEnum};
+use stratum_apps::{
+    exit::ExitStatus,
+    key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+    sniffer::{read_capture, Replay, ReplayError},
+};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Side {
+    Downstream,
+    Upstream,
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "sv2-replay",
+    version,
+    about = "Replay a Stratum V2 session captured by sv2-sniffer against a role"
+)]
+struct Args {
+    /// Capture file written by sv2-sniffer
+    #[arg(long)]
+    capture: PathBuf,
+    /// Connection of the capture to replay, numbered from 1
+    #[arg(long, default_value_t = 1)]
+    connection: u64,
+    /// Side of the connection played by the replay
+    #[arg(long = "as", value_enum)]
+    side: Side,
+    /// Address of the role as downstream, address to listen on as upstream
+    #[arg(long)]
+    address: String,
+    /// Authority public key of the role, as downstream; the role is not authenticated by default
+    #[arg(long)]
+    authority_public_key: Option<Secp256k1PublicKey>,
+    /// Authority secret key presented to the role, as upstream; a new one is generated by default
+    #[arg(long)]
+    secret_key: Option<Secp256k1SecretKey>,
+    /// Times faster than captured the messages are sent, 0 for no wait at all
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+    /// Seconds each message of the role is waited for
+    #[arg(long, default_value_t = 10)]
+    response_timeout_secs: u64,
+    /// Comma-separated message types compared on their type only, e.g. 0x1f,0x20
+    #[arg(long, value_delimiter = ',', value_parser = parse_message_type)]
+    type_only: Vec<u8>,
+}
+
+fn parse_message_type(s: &str) -> Result<u8, String> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|e| format!("invalid message type {s}: {e}"))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+    let args = Args::parse();
+
+    let records = match read_capture(&args.capture) {
+        Ok(records) => records,
+        Err(e) => {
+            error!("Failed to read {}: {e}", args.capture.display());
+            return ExitStatus::Config.into();
+        }
+    };
+    if args.speed < 0.0 || args.speed.is_nan() {
+        error!("The speed must be positive, or 0 for no wait at all");
+        return ExitStatus::Config.into();
+    }
+    let speed = if args.speed == 0.0 {
+        f64::INFINITY
+    } else {
+        args.speed
+    };
+    let mut replay = Replay::new(records, args.connection)
+        .with_speed(speed)
+        .with_response_timeout(Duration::from_secs(args.response_timeout_secs));
+    for message_type in args.type_only {
+        replay = replay.compare_type_only(message_type);
+    }
+
+    let result = match args.side {
+        Side::Downstream => {
+            replay
+                .run_as_downstream(&args.address, args.authority_public_key)
+                .await
+        }
+        Side::Upstream => {
+            let listener = match TcpListener::bind(&args.address).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("Failed to bind {}: {e}", args.address);
+                    return ExitStatus::Bind.into();
+                }
+            };
+            let secret_key = args.secret_key.unwrap_or_else(Secp256k1SecretKey::generate);
+            let public_key = Secp256k1PublicKey::from(secret_key);
+            info!(
+                "Listening on {}, authority public key {public_key}",
+                args.address
+            );
+            replay
+                .run_as_upstream(listener, public_key, secret_key)
+                .await
+        }
+    };
+    match result {
+        Ok(report) => {
+            info!(
+                "Session reproduced: {} messages sent, {} matched",
+                report.sent, report.matched
+            );
+            ExitStatus::Success.into()
+        }
+        Err(e) => {
+            error!("Replay failed: {e}");
+            match e {
+                ReplayError::EmptyCapture(_) => ExitStatus::Config.into(),
+                ReplayError::Connection(_) => ExitStatus::Unreachable.into(),
+                _ => ExitStatus::Failure.into(),
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Relays the SV2 messages between a downstream and an upstream role, logging each of them.
+//!
+//! The downstreams connect to `--listen` and authenticate the sniffer with the public key of
+//! `--secret-key`, printed at startup, while the sniffer connects to `--upstream`. With
+//! `--capture`, the messages are also recorded to a file, one JSON object per line.
+
+use std::{net::SocketAddr, path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+use stratum_apps::{
+    exit::ExitStatus,
+    key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+    sniffer::{SnifferConfig, Sv2Sniffer},
+};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "sv2-sniffer",
+    version,
+    about = "Decode and record the Stratum V2 messages between two roles"
+)]
+struct Args {
+    /// Address the downstream connects to
+    #[arg(long, default_value = "127.0.0.1:34265")]
+    listen: SocketAddr,
+    /// Address of the upstream, a host:port or a unix: path
+    #[arg(long)]
+    upstream: String,
+    /// Authority secret key presented to the downstream, a new one is generated by default
+    #[arg(long)]
+    secret_key: Option<Secp256k1SecretKey>,
+    /// Authority public key of the upstream, the upstream is not authenticated by default
+    #[arg(long)]
+    upstream_authority_public_key: Option<Secp256k1PublicKey>,
+    /// File recording the messages, one JSON object per line
+    #[arg(long)]
+    capture: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+    let args = Args::parse();
+
+    let secret_key = args.secret_key.unwrap_or_else(Secp256k1SecretKey::generate);
+    let mut config = SnifferConfig::new(
+        args.listen,
+        args.upstream,
+        Secp256k1PublicKey::from(secret_key),
+        secret_key,
+    );
+    if let Some(public_key) = args.upstream_authority_public_key {
+        config = config.with_upstream_authority_public_key(public_key);
+    }
+    if let Some(capture) = args.capture {
+        config = config.with_capture(capture);
+    }
+
+    let sniffer = match Sv2Sniffer::start(config).await {
+        Ok(sniffer) => sniffer,
+        Err(e) => {
+            error!("Failed to start the sniffer on {}: {e}", args.listen);
+            return ExitStatus::Bind.into();
+        }
+    };
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Shutting down");
+    sniffer.stop().await;
+    ExitStatus::Success.into()
+}
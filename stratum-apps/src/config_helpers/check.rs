@@ -0,0 +1,274 @@
+//! Validation of role configurations, behind the `--check-config` flag of the role binaries.
+//!
+//! A role loads its configuration, runs its checks on a [`ConfigCheck`] and exits with the
+//! report, without starting any network service. Checks are dry runs: addresses are resolved but
+//! never bound or connected to, and files are never written.
+
+use std::{
+    fmt::{self, Display},
+    fs::{Metadata, OpenOptions},
+    net::{IpAddr, ToSocketAddrs},
+    path::Path,
+};
+
+use crate::{
+    banlist::{BanList, BanListConfig},
+    config_helpers::CoinbaseRewardScript,
+    key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+};
+
+// Prefix of the addresses designating a unix socket, as dialed by the network helpers.
+const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+#[cfg(unix)]
+fn is_socket(metadata: &Metadata) -> bool {
+    std::os::unix::fs::FileTypeExt::is_socket(&metadata.file_type())
+}
+
+#[cfg(not(unix))]
+fn is_socket(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// Outcome of the checks run on a configuration.
+#[derive(Debug, Default)]
+pub struct ConfigCheck {
+    results: Vec<(String, Result<String, String>)>,
+}
+
+impl ConfigCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a check named `name` that passed, with a `detail` on what was found.
+    pub fn pass(&mut self, name: impl Into<String>, detail: impl Display) {
+        self.results.push((name.into(), Ok(detail.to_string())));
+    }
+
+    /// Records a check named `name` that failed because of `reason`.
+    pub fn fail(&mut self, name: impl Into<String>, reason: impl Display) {
+        self.results.push((name.into(), Err(reason.to_string())));
+    }
+
+    /// Records the outcome of a check named `name`.
+    pub fn check<T: Display, E: Display>(&mut self, name: impl Into<String>, result: Result<T, E>) {
+        match result {
+            Ok(detail) => self.pass(name, detail),
+            Err(reason) => self.fail(name, reason),
+        }
+    }
+
+    /// Checks that `address` (`host:port`) resolves to at least one socket address.
+    pub fn resolvable_address(&mut self, name: impl Into<String>, address: &str) {
+        let resolved = address
+            .to_socket_addrs()
+            .map_err(|e| format!("`{address}` does not resolve: {e}"))
+            .and_then(|addresses| {
+                let addresses = addresses.map(|a| a.to_string()).collect::<Vec<_>>();
+                match addresses.is_empty() {
+                    true => Err(format!("`{address}` resolves to no address")),
+                    false => Ok(format!("{address} -> {}", addresses.join(", "))),
+                }
+            });
+        self.check(name, resolved);
+    }
+
+    /// Checks an upstream `address` which may designate a unix socket as `unix:<path>`: the
+    /// socket must exist on this host, other addresses must resolve (see
+    /// [`Self::resolvable_address`]).
+    pub fn socket_address(&mut self, name: impl Into<String>, address: &str) {
+        let Some(path) = address.strip_prefix(UNIX_SOCKET_PREFIX) else {
+            return self.resolvable_address(name, address);
+        };
+        let socket = Path::new(path)
+            .metadata()
+            .map_err(|e| format!("unix socket `{path}` is not accessible: {e}"))
+            .and_then(|metadata| match is_socket(&metadata) {
+                true => Ok(format!("unix socket {path}")),
+                false => Err(format!("`{path}` is not a unix socket")),
+            });
+        self.check(name, socket);
+    }
+
+    /// Checks that `address` is an IP address, as required where roles do not resolve names.
+    pub fn ip_address(&mut self, name: impl Into<String>, address: &str) {
+        let parsed = address
+            .parse::<IpAddr>()
+            .map_err(|e| format!("`{address}` is not an IP address: {e}"));
+        self.check(name, parsed);
+    }
+
+    /// Checks that `secret_key` is the secret key of `public_key`.
+    pub fn authority_keypair(
+        &mut self,
+        public_key: &Secp256k1PublicKey,
+        secret_key: &Secp256k1SecretKey,
+    ) {
+        let derived = Secp256k1PublicKey::from(*secret_key);
+        if derived.into_bytes() == public_key.into_bytes() {
+            self.pass("authority keypair", public_key);
+        } else {
+            self.fail(
+                "authority keypair",
+                format!("the secret key belongs to {derived}, not to the public key {public_key}"),
+            );
+        }
+    }
+
+    /// Reports the script of a coinbase output, valid since it was parsed with the configuration.
+    pub fn coinbase_reward_script(&mut self, script: &CoinbaseRewardScript) {
+        let mainnet = match script.ok_for_mainnet() {
+            true => "",
+            false => " (not for mainnet)",
+        };
+        self.pass(
+            "coinbase reward script",
+            format!(
+                "scriptPubKey {}{mainnet}",
+                script.script_pubkey().to_hex_string()
+            ),
+        );
+    }
+
+    /// Checks that the file at `path` can be appended to, without creating or writing it.
+    pub fn appendable_file(&mut self, name: impl Into<String>, path: &Path) {
+        let result = if path.exists() {
+            OpenOptions::new()
+                .append(true)
+                .open(path)
+                .map(|_| format!("{} is writable", path.display()))
+                .map_err(|e| format!("cannot open {} for writing: {e}", path.display()))
+        } else {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            match parent.metadata() {
+                Ok(metadata) if !metadata.is_dir() => {
+                    Err(format!("{} is not a directory", parent.display()))
+                }
+                Ok(metadata) if metadata.permissions().readonly() => {
+                    Err(format!("{} is read-only", parent.display()))
+                }
+                Ok(_) => Ok(format!("{} will be created", path.display())),
+                Err(e) => Err(format!("cannot access {}: {e}", parent.display())),
+            }
+        };
+        self.check(name, result);
+    }
+
+    /// Checks that the ban list file, if any, holds valid bans and can be saved to.
+    pub fn banlist(&mut self, config: &BanListConfig) {
+        let Some(file) = &config.file else {
+            self.pass("ban list", "in memory");
+            return;
+        };
+        match BanList::new(config) {
+            Ok(banlist) => {
+                self.pass("ban list", format!("{} ban(s)", banlist.list().len()));
+                self.appendable_file("ban list file", file);
+            }
+            Err(e) => self.fail("ban list", e),
+        }
+    }
+
+    /// Whether every check passed.
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// Prints the report and exits the process, with status `0` if every check passed and `1`
+    /// otherwise.
+    pub fn exit(self) -> ! {
+        println!("{self}");
+        std::process::exit(if self.is_ok() { 0 } else { 1 })
+    }
+}
+
+impl Display for ConfigCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, result) in &self.results {
+            match result {
+                Ok(detail) => writeln!(f, "[ok]     {name}: {detail}")?,
+                Err(reason) => writeln!(f, "[failed] {name}: {reason}")?,
+            }
+        }
+        let failed = self.results.iter().filter(|(_, r)| r.is_err()).count();
+        match failed {
+            0 => write!(f, "Configuration is valid ({} checks)", self.results.len()),
+            _ => write!(
+                f,
+                "Configuration is invalid: {failed} of {} checks failed",
+                self.results.len()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_failed_checks() {
+        let mut report = ConfigCheck::new();
+        report.resolvable_address("listen address", "127.0.0.1:34254");
+        report.ip_address("upstream address", "127.0.0.1");
+        assert!(report.is_ok());
+
+        report.ip_address("downstream address", "localhost");
+        report.resolvable_address("template provider", "127.0.0.1");
+        assert!(!report.is_ok());
+        let rendered = report.to_string();
+        assert!(
+            rendered.contains("[failed] downstream address"),
+            "{rendered}"
+        );
+        assert!(rendered.ends_with("2 of 4 checks failed"), "{rendered}");
+    }
+
+    #[test]
+    fn unix_socket_addresses_must_exist() {
+        let mut report = ConfigCheck::new();
+        report.socket_address("template provider", "127.0.0.1:8442");
+        assert!(report.is_ok());
+
+        report.socket_address("template provider", "unix:/nonexistent/sv2.sock");
+        let rendered = report.to_string();
+        assert!(rendered.contains("is not accessible"), "{rendered}");
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn authority_keypair_must_match() {
+        let public_key: Secp256k1PublicKey = "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72"
+            .parse()
+            .unwrap();
+        let secret_key: Secp256k1SecretKey = "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n"
+            .parse()
+            .unwrap();
+        let mut report = ConfigCheck::new();
+        report.authority_keypair(&public_key, &secret_key);
+        assert!(report.is_ok(), "{report}");
+
+        let other_public_key: Secp256k1PublicKey =
+            "9bwHCYnjhbHm4AS3pWg9MtAH83mzWohoJJJDELYBqZhDNqszDLc"
+                .parse()
+                .unwrap();
+        report.authority_keypair(&other_public_key, &secret_key);
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn appendable_file_is_not_created() {
+        let path = std::env::temp_dir().join(format!("sv2-check-test-{}", std::process::id()));
+        let mut report = ConfigCheck::new();
+        report.appendable_file("persistence", &path);
+        assert!(report.is_ok(), "{report}");
+        assert!(!path.exists());
+
+        report.appendable_file("persistence", Path::new("/nonexistent-dir/events.jsonl"));
+        assert!(!report.is_ok());
+    }
+}
@@ -16,6 +16,7 @@ pub use errors::Error;
 pub struct CoinbaseRewardScript {
     script_pubkey: ScriptBuf,
     ok_for_mainnet: bool,
+    address: Option<Address<NetworkUnchecked>>,
 }
 
 impl CoinbaseRewardScript {
@@ -31,6 +32,7 @@ impl CoinbaseRewardScript {
                 // Descriptors don't have a way to specify a network, so we assume
                 // they are OK to be used on mainnet.
                 ok_for_mainnet: true,
+                address: None,
             });
         }
 
@@ -65,6 +67,7 @@ impl CoinbaseRewardScript {
                 Ok(Self {
                     script_pubkey: addr.assume_checked_ref().script_pubkey(),
                     ok_for_mainnet: addr.is_valid_for_network(Network::Bitcoin),
+                    address: Some(addr),
                 })
             }
             "raw" => {
@@ -83,6 +86,7 @@ impl CoinbaseRewardScript {
                     script_pubkey: ScriptBuf::from(bytes),
                     // Users of hex scriptpubkeys are on their own.
                     ok_for_mainnet: true,
+                    address: None,
                 })
             }
             _ => {
@@ -92,6 +96,7 @@ impl CoinbaseRewardScript {
                     // Descriptors don't have a way to specify a network, so we assume
                     // they are OK to be used on mainnet.
                     ok_for_mainnet: true,
+                    address: None,
                 })
             }
         }
@@ -106,6 +111,15 @@ impl CoinbaseRewardScript {
         self.ok_for_mainnet
     }
 
+    /// Whether this coinbase output can be paid on `network`.
+    ///
+    /// Like [`Self::ok_for_mainnet`], only an addr() descriptor is checked against the network.
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        self.address
+            .as_ref()
+            .is_none_or(|address| address.is_valid_for_network(network))
+    }
+
     /// The `scriptPubKey` associated with the coinbase output
     pub fn script_pubkey(&self) -> ScriptBuf {
         self.script_pubkey.clone()
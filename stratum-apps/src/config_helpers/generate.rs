@@ -0,0 +1,157 @@
+//! Scaffolding of role configuration files, behind the `--generate-config` flag of the role
+//! binaries.
+//!
+//! Each role renders its own commented TOML for a [`ConfigProfile`], which provides the values
+//! depending on the Bitcoin network, and signs it with a fresh authority keypair from
+//! [`generate_authority_keys`]. The result is written by [`write_generated_config`].
+
+use std::{
+    fmt::{self, Display},
+    fs,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use super::BitcoinNetwork;
+use crate::key_utils::{Secp256k1PublicKey, Secp256k1SecretKey};
+
+/// Bitcoin network a generated configuration targets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConfigProfile {
+    Mainnet,
+    /// Testnet4.
+    #[default]
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl ConfigProfile {
+    /// Bitcoin network of the profile, written as the `network` of the configuration.
+    pub fn network(self) -> BitcoinNetwork {
+        match self {
+            Self::Mainnet => BitcoinNetwork::Mainnet,
+            Self::Testnet => BitcoinNetwork::Testnet4,
+            Self::Signet => BitcoinNetwork::Signet,
+            Self::Regtest => BitcoinNetwork::Regtest,
+        }
+    }
+
+    /// Default port of the Template Provider (Bitcoin Core `-sv2port`) on this network.
+    pub fn template_provider_port(self) -> u16 {
+        self.network().template_provider_port()
+    }
+
+    /// Default port of the Bitcoin Core RPC interface on this network.
+    pub fn bitcoin_rpc_port(self) -> u16 {
+        self.network().bitcoin_rpc_port()
+    }
+
+    /// Placeholder coinbase reward descriptor, which operators must replace with their own.
+    ///
+    /// On mainnet it holds no address at all, so that a configuration still carrying it fails to
+    /// load rather than paying the block rewards to someone else. On the test networks it is a
+    /// valid address of the network, so that the generated configuration runs as is.
+    pub fn coinbase_reward_script(self) -> &'static str {
+        match self {
+            Self::Mainnet => "addr(REPLACE_WITH_YOUR_MAINNET_ADDRESS)",
+            Self::Testnet | Self::Signet => "addr(tb1qa0sm0hxzj0x25rh8gw5xlzwlsfvvyz8u96w3p8)",
+            Self::Regtest => "addr(bcrt1qa0sm0hxzj0x25rh8gw5xlzwlsfvvyz8u8nhukw)",
+        }
+    }
+}
+
+impl FromStr for ConfigProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mainnet" => Ok(Self::Mainnet),
+            "testnet" | "testnet4" => Ok(Self::Testnet),
+            "signet" => Ok(Self::Signet),
+            "regtest" => Ok(Self::Regtest),
+            _ => Err(format!(
+                "unknown profile `{s}`, expected `mainnet`, `testnet`, `signet` or `regtest`"
+            )),
+        }
+    }
+}
+
+impl Display for ConfigProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Mainnet => "mainnet",
+            Self::Testnet => "testnet",
+            Self::Signet => "signet",
+            Self::Regtest => "regtest",
+        })
+    }
+}
+
+/// Generates a fresh authority keypair for a new configuration.
+pub fn generate_authority_keys() -> (Secp256k1PublicKey, Secp256k1SecretKey) {
+    let secret_key = Secp256k1SecretKey::generate();
+    (secret_key.into(), secret_key)
+}
+
+/// Writes a generated configuration to the file at `output`, or to stdout if unset.
+///
+/// An existing file is never overwritten, since it may hold the only copy of an authority key.
+pub fn write_generated_config(contents: &str, output: Option<&Path>) -> io::Result<()> {
+    match output {
+        Some(path) => fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)?
+            .write_all(contents.as_bytes()),
+        None => io::stdout().write_all(contents.as_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_helpers::CoinbaseRewardScript;
+
+    #[test]
+    fn profile_scripts_match_their_network() {
+        for profile in [
+            ConfigProfile::Testnet,
+            ConfigProfile::Signet,
+            ConfigProfile::Regtest,
+        ] {
+            let script =
+                CoinbaseRewardScript::from_descriptor(profile.coinbase_reward_script()).unwrap();
+            assert!(!script.ok_for_mainnet(), "{profile}");
+            assert!(
+                script.is_valid_for_network(profile.network().bitcoin_network()),
+                "{profile}"
+            );
+            assert_eq!(profile.to_string().parse::<ConfigProfile>(), Ok(profile));
+        }
+        assert!("simnet".parse::<ConfigProfile>().is_err());
+    }
+
+    #[test]
+    fn mainnet_placeholder_must_be_replaced() {
+        assert!(CoinbaseRewardScript::from_descriptor(
+            ConfigProfile::Mainnet.coinbase_reward_script()
+        )
+        .is_err());
+        assert_eq!(ConfigProfile::Mainnet.to_string(), "mainnet");
+        assert_eq!(
+            "mainnet".parse::<ConfigProfile>(),
+            Ok(ConfigProfile::Mainnet)
+        );
+    }
+
+    #[test]
+    fn existing_files_are_not_overwritten() {
+        let path = std::env::temp_dir().join(format!("sv2-generate-test-{}", std::process::id()));
+        write_generated_config("a = 1\n", Some(&path)).unwrap();
+        assert!(write_generated_config("a = 2\n", Some(&path)).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a = 1\n");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+//! Loading of role configuration files, with environment variable overrides.
+//!
+//! The format of the file is [detected from its extension](file_format): `.yaml`/`.yml` files are
+//! parsed as YAML and `.json` files as JSON, any other file as TOML. All formats deserialize into
+//! the same configuration structures, with the same setting names.
+//!
+//! Every setting of the file can be overridden by an environment variable named after the
+//! role prefix and the path of the setting, uppercased and separated by double underscores:
+//!
+//! ```text
+//! SV2_POOL__LISTEN_ADDRESS=0.0.0.0:34254        # listen_address
+//! SV2_TRANSLATOR__MONITORING__LISTEN_ADDRESS=...  # listen_address of the [monitoring] table
+//! ```
+//!
+//! Settings absent from the file can be set the same way. Arrays of tables (e.g. the
+//! `[[upstreams]]` of the translator) cannot be overridden and must come from the file.
+//! [Command line flags](load_config_with_overrides) take precedence over the environment.
+//!
+//! The [secrets](SECRET_SETTINGS) can also be given indirectly, so that they never sit in a file
+//! under version control: `<secret>_file` names a file holding the value (e.g. a Docker or
+//! Kubernetes secret) and `<secret>_env` an environment variable holding it. The secrets of a
+//! table are given in the table.
+//!
+//! ```toml
+//! authority_secret_key_file = "/run/secrets/pool_key"
+//! core_rpc_pass_env = "BITCOIN_RPC_PASSWORD"
+//!
+//! [payments]
+//! rpc_password_file = "/run/secrets/wallet_password"
+//! ```
+
+use std::path::Path;
+
+use ext_config::{Config, ConfigError, Environment, File, FileFormat};
+use serde::de::DeserializeOwned;
+
+/// Separator between the prefix and the segments of the setting path in environment variables.
+pub const ENV_SEPARATOR: &str = "__";
+
+/// Settings, given by their path, that can be read from a file (`<setting>_file`) or from an
+/// environment variable (`<setting>_env`) instead of being written in the configuration.
+pub const SECRET_SETTINGS: &[&str] = &[
+    "authority_secret_key",
+    "core_rpc_user",
+    "core_rpc_pass",
+    "payments.rpc_password",
+    "control.auth_token",
+    "coordination.password",
+];
+
+/// Loads the configuration of a role from the file at `path`, overridden by the environment
+/// variables starting with `env_prefix` followed by [`ENV_SEPARATOR`] (e.g. `SV2_POOL__`).
+///
+/// [Secrets](SECRET_SETTINGS) given indirectly are resolved before deserializing.
+pub fn load_config<T: DeserializeOwned>(path: &Path, env_prefix: &str) -> Result<T, ConfigError> {
+    load_config_with_overrides(path, env_prefix, &[])
+}
+
+/// Same as [`load_config`], with the settings of `overrides`, given by their path (e.g.
+/// `logging.filter`) and value, taking precedence over both the file and the environment. Used by
+/// the command line flags of the roles.
+pub fn load_config_with_overrides<T: DeserializeOwned>(
+    path: &Path,
+    env_prefix: &str,
+    overrides: &[(&str, String)],
+) -> Result<T, ConfigError> {
+    let mut builder = Config::builder()
+        .add_source(File::from(path).format(file_format(path)))
+        .add_source(
+            Environment::with_prefix(env_prefix)
+                .prefix_separator(ENV_SEPARATOR)
+                .separator(ENV_SEPARATOR),
+        );
+    let config = builder.build_cloned()?;
+    for secret in SECRET_SETTINGS {
+        if let Some(value) = resolve_secret(&config, secret)? {
+            builder = builder.set_override(*secret, value)?;
+        }
+    }
+    for (setting, value) in overrides {
+        builder = builder.set_override(*setting, value.clone())?;
+    }
+    builder.build()?.try_deserialize()
+}
+
+/// Format of the configuration file at `path`, from its extension. TOML unless the extension is
+/// `json`, `yaml` or `yml`.
+pub fn file_format(path: &Path) -> FileFormat {
+    match path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        Some("json") => FileFormat::Json,
+        Some("yaml" | "yml") => FileFormat::Yaml,
+        _ => FileFormat::Toml,
+    }
+}
+
+/// Reads the value of `secret` from the file or environment variable the configuration points
+/// to, if any.
+fn resolve_secret(config: &Config, secret: &str) -> Result<Option<String>, ConfigError> {
+    let file = optional_string(config, &format!("{secret}_file"))?;
+    let env = optional_string(config, &format!("{secret}_env"))?;
+    let inline = optional_string(config, secret)?.is_some();
+    let sources = [inline, file.is_some(), env.is_some()];
+    if sources.iter().filter(|set| **set).count() > 1 {
+        return Err(ConfigError::Message(format!(
+            "only one of `{secret}`, `{secret}_file` and `{secret}_env` can be set"
+        )));
+    }
+    if let Some(file) = file {
+        let value = std::fs::read_to_string(&file).map_err(|e| {
+            ConfigError::Message(format!("cannot read `{secret}` from {file}: {e}"))
+        })?;
+        // Files written with `echo` end with a newline that is not part of the secret.
+        return Ok(Some(value.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    if let Some(env) = env {
+        return std::env::var(&env)
+            .map(Some)
+            .map_err(|e| ConfigError::Message(format!("cannot read `{secret}` from ${env}: {e}")));
+    }
+    Ok(None)
+}
+
+fn optional_string(config: &Config, key: &str) -> Result<Option<String>, ConfigError> {
+    match config.get_string(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct TestConfig {
+        listen_address: String,
+        shares_per_minute: f32,
+        server_id: u16,
+        monitoring: Option<Monitoring>,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Monitoring {
+        listen_address: String,
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        let path =
+            std::env::temp_dir().join(format!("sv2-loader-test-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "listen_address = \"0.0.0.0:34254\"\nshares_per_minute = 6.0\nserver_id = 1\n",
+        )
+        .unwrap();
+        std::env::set_var("SV2_LOADER_TEST__SERVER_ID", "7");
+        std::env::set_var(
+            "SV2_LOADER_TEST__MONITORING__LISTEN_ADDRESS",
+            "127.0.0.1:9090",
+        );
+
+        let config: TestConfig = load_config(&path, "SV2_LOADER_TEST").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.listen_address, "0.0.0.0:34254");
+        assert_eq!(config.shares_per_minute, 6.0);
+        assert_eq!(config.server_id, 7);
+        assert_eq!(config.monitoring.unwrap().listen_address, "127.0.0.1:9090");
+    }
+
+    #[test]
+    fn overrides_take_precedence_over_environment() {
+        let path = std::env::temp_dir().join(format!(
+            "sv2-loader-override-test-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "listen_address = \"0.0.0.0:34254\"\nshares_per_minute = 6.0\nserver_id = 1\n",
+        )
+        .unwrap();
+        std::env::set_var("SV2_LOADER_OVERRIDE_TEST__SERVER_ID", "7");
+
+        let overrides = [
+            ("server_id", "9".to_string()),
+            ("monitoring.listen_address", "127.0.0.1:9090".to_string()),
+        ];
+        let config: TestConfig =
+            load_config_with_overrides(&path, "SV2_LOADER_OVERRIDE_TEST", &overrides).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.listen_address, "0.0.0.0:34254");
+        assert_eq!(config.server_id, 9);
+        assert_eq!(config.monitoring.unwrap().listen_address, "127.0.0.1:9090");
+    }
+
+    #[test]
+    fn yaml_and_json_files_are_parsed() {
+        let contents = [
+            (
+                "yaml",
+                "listen_address: 0.0.0.0:34254\nshares_per_minute: 6.0\nserver_id: 1\nmonitoring:\n  listen_address: 127.0.0.1:9090\n",
+            ),
+            (
+                "json",
+                r#"{"listen_address": "0.0.0.0:34254", "shares_per_minute": 6.0, "server_id": 1, "monitoring": {"listen_address": "127.0.0.1:9090"}}"#,
+            ),
+        ];
+        for (extension, contents) in contents {
+            let path = std::env::temp_dir().join(format!(
+                "sv2-loader-format-test-{}.{extension}",
+                std::process::id()
+            ));
+            std::fs::write(&path, contents).unwrap();
+            let config: TestConfig = load_config(&path, "SV2_LOADER_FORMAT_TEST").unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(config.listen_address, "0.0.0.0:34254", "{extension}");
+            assert_eq!(config.server_id, 1, "{extension}");
+            assert_eq!(
+                config.monitoring.unwrap().listen_address,
+                "127.0.0.1:9090",
+                "{extension}"
+            );
+        }
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    struct SecretConfig {
+        authority_secret_key: String,
+        core_rpc_pass: String,
+    }
+
+    #[test]
+    fn secrets_are_read_from_files_and_environment() {
+        let dir = std::env::temp_dir();
+        let key_path = dir.join(format!("sv2-loader-secret-{}", std::process::id()));
+        let path = dir.join(format!(
+            "sv2-loader-secret-test-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &key_path,
+            "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &path,
+            format!(
+                "authority_secret_key_file = {:?}\ncore_rpc_pass_env = \"SV2_LOADER_SECRET_PASS\"\n",
+                key_path.display().to_string()
+            ),
+        )
+        .unwrap();
+        std::env::set_var("SV2_LOADER_SECRET_PASS", "hunter2");
+
+        let config: SecretConfig = load_config(&path, "SV2_LOADER_SECRET_TEST").unwrap();
+        assert_eq!(
+            config.authority_secret_key,
+            "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n"
+        );
+        assert_eq!(config.core_rpc_pass, "hunter2");
+
+        // A secret given both inline and indirectly is ambiguous.
+        std::env::set_var("SV2_LOADER_SECRET_TEST__CORE_RPC_PASS", "inline");
+        let error = load_config::<SecretConfig>(&path, "SV2_LOADER_SECRET_TEST").unwrap_err();
+        assert!(error.to_string().contains("only one of"), "{error}");
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&key_path).unwrap();
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    struct TableSecretConfig {
+        control: Control,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Control {
+        listen_address: String,
+        auth_token: String,
+    }
+
+    #[test]
+    fn secrets_of_tables_are_read_from_the_environment() {
+        let path = std::env::temp_dir().join(format!(
+            "sv2-loader-table-secret-test-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "[control]\nlisten_address = \"127.0.0.1:50051\"\nauth_token_env = \"SV2_LOADER_CONTROL_TOKEN\"\n",
+        )
+        .unwrap();
+        std::env::set_var("SV2_LOADER_CONTROL_TOKEN", "s3cr3t");
+
+        let config: TableSecretConfig = load_config(&path, "SV2_LOADER_TABLE_SECRET_TEST").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.control.listen_address, "127.0.0.1:50051");
+        assert_eq!(config.control.auth_token, "s3cr3t");
+    }
+}
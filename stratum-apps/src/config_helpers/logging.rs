@@ -1,49 +1,184 @@
+use serde::Deserialize;
 use std::{
     fs::OpenOptions,
     io::{self, IsTerminal},
     path::Path,
-    str::FromStr,
 };
-use tracing::level_filters::LevelFilter;
-use tracing_subscriber::{fmt, prelude::*, EnvFilter, Registry};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter, Layer, Registry};
+
+use crate::history::{log_history, HistoryLayer};
+
+/// Format of the log lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines, colored on a terminal.
+    #[default]
+    Pretty,
+    /// One JSON object per line, with the fields of the event and of its spans (connection id,
+    /// channel id, user identity, ...) at the top level, for ingestion by Loki, ELK, ...
+    Json,
+}
+
+/// Logging settings of a role, the `[logging]` table of its configuration.
+///
+/// ```toml
+/// [logging]
+/// format = "json"
+/// filter = "info,pool_sv2::channel_manager=debug"
+/// otlp_endpoint = "http://localhost:4317"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Filter directives (`level` or `target=level`, comma separated), `info` if unset. The
+    /// `RUST_LOG` environment variable takes precedence.
+    pub filter: Option<String>,
+    /// OTLP/gRPC endpoint spans are exported to, if the `otel` feature is enabled. Spans are not
+    /// exported if unset.
+    pub otlp_endpoint: Option<String>,
+    /// Name of the role in the exported spans, the name of the executable if unset.
+    pub service_name: Option<String>,
+}
 
 /// Initialize logging to stdout and optionally to a file.
 ///
-/// If `log_file` is Some, logs will be written to both stdout and the file.
-/// If `log_level` is not provided or is invalid, it defaults to "info".
-pub fn init_logging(log_file: Option<&Path>) {
-    let rust_log = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
-    let log_level_filter = LevelFilter::from_str(&rust_log).unwrap_or(LevelFilter::INFO);
-    let env_filter = EnvFilter::new(log_level_filter.to_string());
-    let stdout_layer = fmt::layer()
-        .with_writer(io::stdout)
-        .with_ansi(io::stdout().is_terminal());
-
-    let subscriber: Box<dyn tracing::Subscriber + Send + Sync> = match log_file {
-        Some(path) => {
-            // Log to both file and stdout
-            let path = path.to_owned();
-            let file_layer = fmt::layer()
-                .with_writer(move || {
-                    OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(&path)
-                        .expect("Failed to open log file")
-                })
-                .with_ansi(false);
-            Box::new(
-                Registry::default()
-                    .with(env_filter)
-                    .with(stdout_layer)
-                    .with(file_layer),
-            )
-        }
-        None => {
-            // Log only to stdout
-            Box::new(Registry::default().with(env_filter).with(stdout_layer))
+/// If `log_file` is Some, logs will be written to both stdout and the file, in the format of
+/// `config`. Events are filtered by the `RUST_LOG` environment variable if set, by the filter of
+/// `config` otherwise. An invalid filter falls back to "info". The warnings and errors are also
+/// kept in the [`log_history`] ring.
+///
+/// With the `otel` feature, spans are also exported to the OTLP endpoint of `config`, if any,
+/// with the events logged in them. Stratum V2 messages carry no trace context, so the spans of
+/// different roles are not linked: a share is followed across roles through the `channel_id`,
+/// `sequence_number` and `nonce` fields of their `share` spans.
+pub fn init_logging(log_file: Option<&Path>, config: &LoggingConfig) {
+    let directives = std::env::var("RUST_LOG")
+        .ok()
+        .or_else(|| config.filter.clone());
+    let env_filter = env_filter(directives.as_deref());
+    let mut layers = vec![
+        format_layer(config.format, io::stdout, io::stdout().is_terminal()),
+        HistoryLayer::new(log_history().clone()).boxed(),
+    ];
+    if let Some(path) = log_file {
+        // Log to both file and stdout
+        let path = path.to_owned();
+        layers.push(format_layer(
+            config.format,
+            move || {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .expect("Failed to open log file")
+            },
+            false,
+        ));
+    }
+    #[cfg(feature = "otel")]
+    let otlp = config.otlp_endpoint.as_deref().map(|endpoint| {
+        otel_layer(endpoint, config.service_name.as_deref()).map(|layer| layers.push(layer))
+    });
+    let subscriber = Registry::default().with(layers).with(env_filter);
+
+    tracing::subscriber::set_global_default(subscriber).expect("Failed to set global subscriber");
+
+    #[cfg(feature = "otel")]
+    if let Some(Err(e)) = otlp {
+        tracing::error!("Failed to set up the OTLP exporter, spans are not exported: {e}");
+    }
+    #[cfg(not(feature = "otel"))]
+    if config.otlp_endpoint.is_some() {
+        tracing::warn!(
+            "`otlp_endpoint` is ignored, spans are only exported with the `otel` feature"
+        );
+    }
+}
+
+/// Exports the spans not exported yet, to be called before the process exits.
+pub fn shutdown_logging() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Builds the filter from `directives`, "info" if unset or invalid.
+fn env_filter(directives: Option<&str>) -> EnvFilter {
+    match directives.map(EnvFilter::try_new) {
+        Some(Ok(filter)) => filter,
+        Some(Err(e)) => {
+            eprintln!("Invalid log filter, logging at info level: {e}");
+            EnvFilter::new("info")
         }
+        None => EnvFilter::new("info"),
+    }
+}
+
+fn format_layer<W>(
+    format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'w> fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Pretty => fmt::layer().with_writer(writer).with_ansi(ansi).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+#[cfg(feature = "otel")]
+fn otel_layer(
+    endpoint: &str,
+    service_name: Option<&str>,
+) -> Result<Box<dyn Layer<Registry> + Send + Sync>, opentelemetry::trace::TraceError> {
+    use opentelemetry::{trace::TracerProvider as _, KeyValue};
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    let service_name = match service_name {
+        Some(service_name) => service_name.to_string(),
+        None => std::env::current_exe()
+            .ok()
+            .and_then(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "stratum-apps".to_string()),
     };
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new("service.name", service_name)]))
+        .build();
+    let tracer = provider.tracer("stratum-apps");
+    opentelemetry::global::set_tracer_provider(provider);
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer).boxed())
+}
 
-    tracing::subscriber::set_global_default(subscriber).expect("Failed to set global subscriber");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::level_filters::LevelFilter;
+
+    #[test]
+    fn invalid_filters_fall_back_to_info() {
+        assert_eq!(env_filter(None).max_level_hint(), Some(LevelFilter::INFO));
+        assert_eq!(
+            env_filter(Some("warn,pool_sv2::channel_manager=debug")).max_level_hint(),
+            Some(LevelFilter::DEBUG)
+        );
+        assert_eq!(
+            env_filter(Some("pool_sv2=loud")).max_level_hint(),
+            Some(LevelFilter::INFO)
+        );
+    }
 }
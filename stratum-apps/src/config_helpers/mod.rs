@@ -1,16 +1,34 @@
 //! Configuration management helpers for SV2 applications
 //!
 //! This module provides utilities for:
-//! - Parsing configuration files (TOML, etc.)
+//! - Parsing configuration files (TOML, YAML or JSON), with environment variable overrides and
+//!   secrets read from files or environment variables
+//! - Validating configurations without starting the role (`--check-config`)
+//! - Generating example configurations with fresh authority keys (`--generate-config`)
 //! - Handling coinbase output specifications
+//! - Checking the settings of a role against its Bitcoin network
 //! - Setting up logging and tracing
 //!
 //! Originally from the `config_helpers_sv2` crate.
 
+mod check;
+pub use check::ConfigCheck;
+
 mod coinbase_output;
 pub use coinbase_output::{CoinbaseRewardScript, Error as CoinbaseOutputError};
 
+mod generate;
+pub use generate::{generate_authority_keys, write_generated_config, ConfigProfile};
+
+mod loader;
+pub use loader::{
+    file_format, load_config, load_config_with_overrides, ENV_SEPARATOR, SECRET_SETTINGS,
+};
+
 pub mod logging;
 
+mod network;
+pub use network::BitcoinNetwork;
+
 mod toml;
 pub use toml::duration_from_toml;
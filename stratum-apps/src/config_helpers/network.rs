@@ -0,0 +1,209 @@
+//! Bitcoin network a role is deployed on, the `network` setting of its configuration.
+//!
+//! Setting it guards against cross-network deployments: the coinbase reward address must belong
+//! to the network, and endpoints on the default port of another network are refused. Endpoints
+//! given without a port get the default port of the network, except unix sockets (`unix:<path>`)
+//! which have none.
+
+use std::{
+    fmt::{self, Display},
+    net::{IpAddr, SocketAddr},
+};
+
+use miniscript::bitcoin::Network;
+use serde::Deserialize;
+
+use super::CoinbaseRewardScript;
+
+/// Bitcoin network of a role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BitcoinNetwork {
+    #[serde(alias = "bitcoin")]
+    Mainnet,
+    #[serde(alias = "testnet")]
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl BitcoinNetwork {
+    const ALL: [Self; 4] = [Self::Mainnet, Self::Testnet4, Self::Signet, Self::Regtest];
+
+    /// Name of the network, as written in configurations and persisted events.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Mainnet => "mainnet",
+            Self::Testnet4 => "testnet4",
+            Self::Signet => "signet",
+            Self::Regtest => "regtest",
+        }
+    }
+
+    /// Network of the addresses paying on this network.
+    pub fn bitcoin_network(self) -> Network {
+        match self {
+            Self::Mainnet => Network::Bitcoin,
+            Self::Testnet4 => Network::Testnet4,
+            Self::Signet => Network::Signet,
+            Self::Regtest => Network::Regtest,
+        }
+    }
+
+    /// Default port of the Template Provider (Bitcoin Core `-sv2port`) on this network.
+    pub fn template_provider_port(self) -> u16 {
+        match self {
+            Self::Mainnet => 8442,
+            Self::Testnet4 => 48442,
+            Self::Signet => 38442,
+            Self::Regtest => 18447,
+        }
+    }
+
+    /// Default port of the Bitcoin Core RPC interface on this network.
+    pub fn bitcoin_rpc_port(self) -> u16 {
+        match self {
+            Self::Mainnet => 8332,
+            Self::Testnet4 => 48332,
+            Self::Signet => 38332,
+            Self::Regtest => 18443,
+        }
+    }
+
+    /// Checks that `script` can be paid on this network, failing on an `addr()` descriptor of
+    /// another network. Other descriptors carry no network and are accepted.
+    pub fn check_coinbase_reward_script(self, script: &CoinbaseRewardScript) -> Result<(), String> {
+        match script.is_valid_for_network(self.bitcoin_network()) {
+            true => Ok(()),
+            false => Err(format!(
+                "the address of `coinbase_reward_script` is not a {self} address"
+            )),
+        }
+    }
+
+    /// Checks that the Template Provider at `address` is not on the default port of another
+    /// network.
+    pub fn check_template_provider_address(self, address: &str) -> Result<(), String> {
+        self.check_port(
+            "template provider",
+            address_port(address),
+            Self::template_provider_port,
+        )
+    }
+
+    /// Checks that the Bitcoin Core RPC `port` is not the default port of another network.
+    pub fn check_bitcoin_rpc_port(self, port: u16) -> Result<(), String> {
+        self.check_port("Bitcoin Core RPC", Some(port), Self::bitcoin_rpc_port)
+    }
+
+    /// Returns `address` with the default Template Provider port of this network if it has no
+    /// port.
+    pub fn template_provider_address(self, address: &str) -> String {
+        match address_port(address) {
+            None if !is_unix_socket(address) => {
+                format!("{address}:{}", self.template_provider_port())
+            }
+            _ => address.to_string(),
+        }
+    }
+
+    fn check_port(
+        self,
+        endpoint: &str,
+        port: Option<u16>,
+        default_port: fn(Self) -> u16,
+    ) -> Result<(), String> {
+        let other = Self::ALL
+            .into_iter()
+            .find(|network| *network != self && port == Some(default_port(*network)));
+        match other {
+            Some(other) => Err(format!(
+                "the {endpoint} port {} is the default port of {other}, not of {self}",
+                default_port(other)
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Display for BitcoinNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Unix socket addresses, dialed by the network helpers, carry no port.
+fn is_unix_socket(address: &str) -> bool {
+    address.starts_with("unix:")
+}
+
+/// Returns the port of a `host:port` address, if it has one.
+fn address_port(address: &str) -> Option<u16> {
+    if is_unix_socket(address) {
+        return None;
+    }
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return Some(address.port());
+    }
+    if address.parse::<IpAddr>().is_ok() {
+        // A bare IPv6 address, whose last group is not a port.
+        return None;
+    }
+    address.rsplit_once(':')?.1.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_on_the_port_of_another_network_are_refused() {
+        let network = BitcoinNetwork::Testnet4;
+        assert!(network
+            .check_template_provider_address("127.0.0.1:48442")
+            .is_ok());
+        assert!(network
+            .check_template_provider_address("tp.example.com:9000")
+            .is_ok());
+        let error = network
+            .check_template_provider_address("127.0.0.1:8442")
+            .unwrap_err();
+        assert!(error.contains("mainnet"), "{error}");
+        assert!(network.check_bitcoin_rpc_port(18443).is_err());
+    }
+
+    #[test]
+    fn addresses_without_port_get_the_default_port() {
+        let network = BitcoinNetwork::Regtest;
+        assert_eq!(
+            network.template_provider_address("127.0.0.1"),
+            "127.0.0.1:18447"
+        );
+        assert_eq!(
+            network.template_provider_address("localhost"),
+            "localhost:18447"
+        );
+        assert_eq!(
+            network.template_provider_address("localhost:8442"),
+            "localhost:8442"
+        );
+        assert_eq!(
+            network.template_provider_address("unix:/run/bitcoin/sv2.sock"),
+            "unix:/run/bitcoin/sv2.sock"
+        );
+    }
+
+    #[test]
+    fn coinbase_address_must_belong_to_the_network() {
+        let script = CoinbaseRewardScript::from_descriptor(
+            "addr(bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4)",
+        )
+        .unwrap();
+        assert!(BitcoinNetwork::Mainnet
+            .check_coinbase_reward_script(&script)
+            .is_ok());
+        assert!(BitcoinNetwork::Testnet4
+            .check_coinbase_reward_script(&script)
+            .is_err());
+    }
+}
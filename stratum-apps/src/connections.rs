@@ -0,0 +1,358 @@
+//! Registry of the downstream connections of a role
+//!
+//! A [`ConnectionRegistry`] allocates the ids of the connections accepted by a role and holds,
+//! for each one, its peer address, when it was registered, the role-specific metadata of the
+//! connection (usually the handle of its downstream) and a [`ShutdownHandle`] closing it. Roles
+//! keep the registry behind the lock of the state it is looked up with, so that registering and
+//! removing a connection stay atomic with the rest of that state.
+//!
+//! [`ConnectionRegistry::list`] describes the connections the same way in every role, with the
+//! channels reported by their [`ConnectionMetadata`], for the status endpoints and the control
+//! service, and [`ConnectionRegistry::disconnect`] closes one of them by id, for admin
+//! disconnects. Removing the connection from the registry once closed is left to the role.
+
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Id of the connections of a role.
+pub trait ConnectionId: Copy + Eq + Ord + Hash + fmt::Display + Send + Sync + 'static {
+    /// Returns the id allocated after `self`.
+    fn next(self) -> Self;
+
+    /// Returns the id as reported by [`ConnectionInfo`].
+    fn as_u64(self) -> u64;
+}
+
+impl ConnectionId for u32 {
+    fn next(self) -> Self {
+        self.wrapping_add(1)
+    }
+
+    fn as_u64(self) -> u64 {
+        self as u64
+    }
+}
+
+impl ConnectionId for usize {
+    fn next(self) -> Self {
+        self.wrapping_add(1)
+    }
+
+    fn as_u64(self) -> u64 {
+        self as u64
+    }
+}
+
+/// Role-specific metadata of a registered connection.
+pub trait ConnectionMetadata {
+    /// Returns the ids of the channels open on the connection, none by default.
+    fn channels(&self) -> Vec<u32> {
+        Vec::new()
+    }
+}
+
+impl<M: ConnectionMetadata + ?Sized> ConnectionMetadata for Arc<M> {
+    fn channels(&self) -> Vec<u32> {
+        (**self).channels()
+    }
+}
+
+/// Closes a registered connection, e.g. by notifying the shutdown of its downstream to the tasks
+/// of the role.
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<dyn Fn() + Send + Sync>);
+
+impl ShutdownHandle {
+    /// Creates a handle calling `shutdown` to close the connection.
+    pub fn new(shutdown: impl Fn() + Send + Sync + 'static) -> Self {
+        Self(Arc::new(shutdown))
+    }
+
+    /// Closes the connection.
+    pub fn shutdown(&self) {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ShutdownHandle")
+    }
+}
+
+/// A connection of the registry.
+#[derive(Debug, Clone)]
+pub struct RegisteredConnection<M> {
+    address: SocketAddr,
+    registered_at: Instant,
+    metadata: M,
+    shutdown: ShutdownHandle,
+}
+
+impl<M> RegisteredConnection<M> {
+    /// Returns the address of the peer.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns how long ago the connection was registered.
+    pub fn connected_for(&self) -> Duration {
+        self.registered_at.elapsed()
+    }
+
+    /// Returns the role-specific metadata of the connection.
+    pub fn metadata(&self) -> &M {
+        &self.metadata
+    }
+
+    /// Returns the handle closing the connection.
+    pub fn shutdown_handle(&self) -> &ShutdownHandle {
+        &self.shutdown
+    }
+}
+
+/// A registered connection, as listed by status endpoints and the control service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub address: String,
+    pub connected_secs: u64,
+    pub channels: Vec<u32>,
+}
+
+/// Connections of a role, by id.
+#[derive(Debug, Clone)]
+pub struct ConnectionRegistry<I, M> {
+    connections: HashMap<I, RegisteredConnection<M>>,
+    next_id: I,
+}
+
+impl<I: ConnectionId, M> ConnectionRegistry<I, M> {
+    /// Creates an empty registry, allocating ids from `first_id` on.
+    pub fn new(first_id: I) -> Self {
+        Self {
+            connections: HashMap::new(),
+            next_id: first_id,
+        }
+    }
+
+    /// Allocates the id of a new connection, to be [registered](Self::register) under it.
+    pub fn allocate_id(&mut self) -> I {
+        let id = self.next_id;
+        self.next_id = id.next();
+        id
+    }
+
+    /// Registers the connection `id` from `address`, replacing any connection registered under
+    /// the same id.
+    pub fn register(
+        &mut self,
+        id: I,
+        address: SocketAddr,
+        metadata: M,
+        shutdown: ShutdownHandle,
+    ) -> Option<M> {
+        self.connections
+            .insert(
+                id,
+                RegisteredConnection {
+                    address,
+                    registered_at: Instant::now(),
+                    metadata,
+                    shutdown,
+                },
+            )
+            .map(|connection| connection.metadata)
+    }
+
+    /// Removes the connection `id`, returning its metadata if it was registered.
+    pub fn remove(&mut self, id: &I) -> Option<M> {
+        self.connections
+            .remove(id)
+            .map(|connection| connection.metadata)
+    }
+
+    /// Removes every connection, without closing them.
+    pub fn clear(&mut self) {
+        self.connections.clear();
+    }
+
+    /// Returns the connection `id`, if registered.
+    pub fn connection(&self, id: &I) -> Option<&RegisteredConnection<M>> {
+        self.connections.get(id)
+    }
+
+    /// Returns the metadata of the connection `id`, if registered.
+    pub fn get(&self, id: &I) -> Option<&M> {
+        self.connections
+            .get(id)
+            .map(|connection| &connection.metadata)
+    }
+
+    /// Returns the metadata of the connection `id` mutably, if registered.
+    pub fn get_mut(&mut self, id: &I) -> Option<&mut M> {
+        self.connections
+            .get_mut(id)
+            .map(|connection| &mut connection.metadata)
+    }
+
+    /// Returns whether the connection `id` is registered.
+    pub fn contains_key(&self, id: &I) -> bool {
+        self.connections.contains_key(id)
+    }
+
+    /// Returns the number of registered connections.
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Returns whether no connection is registered.
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Iterates over the ids of the connections, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &I> {
+        self.connections.keys()
+    }
+
+    /// Iterates over the ids and metadata of the connections, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&I, &M)> {
+        self.connections
+            .iter()
+            .map(|(id, connection)| (id, &connection.metadata))
+    }
+
+    /// Iterates over the ids and mutable metadata of the connections, in no particular order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&I, &mut M)> {
+        self.connections
+            .iter_mut()
+            .map(|(id, connection)| (id, &mut connection.metadata))
+    }
+
+    /// Iterates over the metadata of the connections, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &M> {
+        self.connections
+            .values()
+            .map(|connection| &connection.metadata)
+    }
+
+    /// Closes the connection `id` through its [`ShutdownHandle`], leaving its removal to the role.
+    ///
+    /// Returns `false` if no such connection is registered.
+    pub fn disconnect(&self, id: &I) -> bool {
+        match self.connections.get(id) {
+            Some(connection) => {
+                connection.shutdown.shutdown();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<I: ConnectionId, M: ConnectionMetadata> ConnectionRegistry<I, M> {
+    /// Describes the registered connections, by id.
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<(I, ConnectionInfo)> = self
+            .connections
+            .iter()
+            .map(|(id, connection)| {
+                let mut channels = connection.metadata.channels();
+                channels.sort_unstable();
+                let info = ConnectionInfo {
+                    id: id.as_u64(),
+                    address: connection.address.to_string(),
+                    connected_secs: connection.connected_for().as_secs(),
+                    channels,
+                };
+                (*id, info)
+            })
+            .collect();
+        connections.sort_by_key(|(id, _)| *id);
+        connections.into_iter().map(|(_, info)| info).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Miner(Vec<u32>);
+
+    impl ConnectionMetadata for Miner {
+        fn channels(&self) -> Vec<u32> {
+            self.0.clone()
+        }
+    }
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn noop() -> ShutdownHandle {
+        ShutdownHandle::new(|| {})
+    }
+
+    #[test]
+    fn ids_are_allocated_in_sequence() {
+        let mut registry: ConnectionRegistry<u32, Miner> = ConnectionRegistry::new(1);
+        assert_eq!(registry.allocate_id(), 1);
+        assert_eq!(registry.allocate_id(), 2);
+
+        let mut registry: ConnectionRegistry<u32, Miner> = ConnectionRegistry::new(u32::MAX);
+        assert_eq!(registry.allocate_id(), u32::MAX);
+        assert_eq!(registry.allocate_id(), 0);
+    }
+
+    #[test]
+    fn connections_are_listed_by_id() {
+        let mut registry = ConnectionRegistry::new(0usize);
+        for channels in [vec![7, 3], vec![]] {
+            let id = registry.allocate_id();
+            registry.register(id, address(4000 + id as u16), Miner(channels), noop());
+        }
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.get(&0).map(|miner| miner.0.len()), Some(2));
+
+        let listed = registry.list();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].id, 0);
+        assert_eq!(listed[0].address, "127.0.0.1:4000");
+        assert_eq!(listed[0].channels, vec![3, 7]);
+        assert_eq!(listed[1].id, 1);
+        assert!(listed[1].channels.is_empty());
+
+        assert!(registry.remove(&0).is_some());
+        assert!(!registry.contains_key(&0));
+        assert_eq!(registry.list().len(), 1);
+    }
+
+    #[test]
+    fn disconnect_uses_the_shutdown_handle() {
+        let closed = Arc::new(AtomicUsize::new(0));
+        let mut registry = ConnectionRegistry::new(1usize);
+        let id = registry.allocate_id();
+        let shutdown = {
+            let closed = closed.clone();
+            ShutdownHandle::new(move || {
+                closed.fetch_add(1, Ordering::Relaxed);
+            })
+        };
+        registry.register(id, address(4000), Miner(Vec::new()), shutdown);
+
+        assert!(registry.disconnect(&id));
+        assert!(!registry.disconnect(&(id + 1)));
+        assert_eq!(closed.load(Ordering::Relaxed), 1);
+        // Closing a connection leaves its removal to the role.
+        assert!(registry.contains_key(&id));
+    }
+}
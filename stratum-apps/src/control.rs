@@ -0,0 +1,566 @@
+//! gRPC control service
+//!
+//! Fleet-management tooling drives every role through the same `sv2.control.v1.Control` service,
+//! defined in `proto/control.proto`, instead of scraping logs. Roles implement
+//! [`ControlHandler`] and run [`serve`] on the address configured through a [`ControlConfig`],
+//! usually embedded in the role configuration as an optional `[control]` table:
+//!
+//! ```toml
+//! [control]
+//! listen_address = "127.0.0.1:50051"
+//! auth_token = "..." # optional
+//! ```
+//!
+//! The methods shared by the roles are:
+//! - `GetStatus`: the role, its version, its [`HealthReport`] and its status document as JSON.
+//! - `ListConnections`: the downstream connections of the role.
+//! - `Disconnect`: closes a downstream connection by id.
+//! - `Drain`: stops accepting new connections, the current ones being served until they close.
+//! - `ReloadConfig`: applies the settings of the configuration file which can change at runtime.
+//!
+//! Role-specific methods are extensions, listed by `ListExtensions` and called by name with JSON
+//! parameters through `CallExtension`. Methods a role does not support answer `UNIMPLEMENTED`.
+//!
+//! [`spawn`] runs the service as a task of the role, restarted if it fails.
+//!
+//! With an `auth_token`, every request must carry the `authorization: Bearer <token>` metadata,
+//! and is otherwise answered `UNAUTHENTICATED`. The service is plaintext, so the token does not
+//! protect it from an eavesdropper: it must only listen on a trusted interface either way.
+
+use serde::Deserialize;
+use std::{fmt, future::Future, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::{
+    connections::ConnectionInfo,
+    runtime::{RestartPolicy, TaskManager},
+    status::HealthReport,
+};
+
+/// Messages, server and client generated from `proto/control.proto`.
+pub mod proto {
+    tonic::include_proto!("sv2.control.v1");
+}
+
+pub use proto::Connection;
+use proto::{
+    control_server::{Control, ControlServer},
+    CallExtensionRequest, CallExtensionResponse, DisconnectRequest, DisconnectResponse,
+    DrainRequest, DrainResponse, GetStatusRequest, GetStatusResponse, ListConnectionsRequest,
+    ListConnectionsResponse, ListExtensionsRequest, ListExtensionsResponse, ReloadConfigRequest,
+    ReloadConfigResponse,
+};
+
+/// Restarts of the service spawned by [`spawn`], e.g. while its address is still in use.
+pub const RESTART_POLICY: RestartPolicy = RestartPolicy {
+    max_retries: 5,
+    initial_backoff: Duration::from_secs(1),
+    max_backoff: Duration::from_secs(30),
+};
+
+/// The fields shared by every role, the others being left empty for the role to fill in.
+impl From<ConnectionInfo> for Connection {
+    fn from(info: ConnectionInfo) -> Self {
+        Connection {
+            id: info.id,
+            address: info.address,
+            channels: info.channels.len() as u32,
+            connected_secs: info.connected_secs,
+            ..Default::default()
+        }
+    }
+}
+
+/// Control service configuration.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ControlConfig {
+    /// Address the gRPC server listens on.
+    pub listen_address: SocketAddr,
+    /// Bearer token the requests must carry, if any.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+}
+
+impl ControlConfig {
+    /// Creates a new [`ControlConfig`], without authentication.
+    pub fn new(listen_address: SocketAddr) -> Self {
+        Self {
+            listen_address,
+            auth_token: None,
+        }
+    }
+
+    /// Requires the requests to carry `auth_token` as a bearer token.
+    pub fn with_auth_token(mut self, auth_token: impl Into<String>) -> Self {
+        self.auth_token = Some(auth_token.into());
+        self
+    }
+}
+
+// Lets `request` through if it carries `expected` as its `authorization` metadata, compared in
+// constant time.
+fn authorize(request: Request<()>, expected: &str) -> Result<Request<()>, Status> {
+    let authorized = request
+        .metadata()
+        .get("authorization")
+        .is_some_and(|value| {
+            let (value, expected) = (value.as_bytes(), expected.as_bytes());
+            value.len() == expected.len()
+                && value
+                    .iter()
+                    .zip(expected)
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        });
+    if !authorized {
+        return Err(Status::unauthenticated("missing or invalid control token"));
+    }
+    Ok(request)
+}
+
+/// Error of a control method, answered with the matching gRPC status code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlError {
+    /// The role does not support the method.
+    Unimplemented(String),
+    /// The target of the method (connection, extension, ...) does not exist.
+    NotFound(String),
+    /// The parameters of the method are invalid.
+    InvalidArgument(String),
+    /// The method failed.
+    Internal(String),
+}
+
+impl ControlError {
+    fn unsupported(method: &str) -> Self {
+        ControlError::Unimplemented(format!("`{method}` is not supported by this role"))
+    }
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlError::Unimplemented(e)
+            | ControlError::NotFound(e)
+            | ControlError::InvalidArgument(e)
+            | ControlError::Internal(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ControlError> for Status {
+    fn from(e: ControlError) -> Self {
+        match e {
+            ControlError::Unimplemented(e) => Status::unimplemented(e),
+            ControlError::NotFound(e) => Status::not_found(e),
+            ControlError::InvalidArgument(e) => Status::invalid_argument(e),
+            ControlError::Internal(e) => Status::internal(e),
+        }
+    }
+}
+
+/// Role side of the control service.
+///
+/// Called on every request, implementations should only take short-lived locks. Every method but
+/// [`role`](Self::role) and [`status`](Self::status) is optional.
+pub trait ControlHandler: Send + Sync + 'static {
+    /// Returns the name of the role, e.g. `pool`.
+    fn role(&self) -> &str;
+
+    /// Returns the version of the role, empty by default.
+    fn version(&self) -> &str {
+        ""
+    }
+
+    /// Returns the current state of the role, usually the document of its monitoring endpoint.
+    fn status(&self) -> serde_json::Value;
+
+    /// Returns the health of the role. Always live and ready by default.
+    fn health(&self) -> HealthReport {
+        HealthReport::default()
+    }
+
+    /// Returns the downstream connections of the role, usually built from the [`ConnectionInfo`]
+    /// listed by its [`ConnectionRegistry`](crate::connections::ConnectionRegistry).
+    fn connections(&self) -> Result<Vec<Connection>, ControlError> {
+        Err(ControlError::unsupported("ListConnections"))
+    }
+
+    /// Closes the downstream connection `id`.
+    fn disconnect(&self, id: u64) -> Result<(), ControlError> {
+        let _ = id;
+        Err(ControlError::unsupported("Disconnect"))
+    }
+
+    /// Stops accepting new connections.
+    fn drain(&self) -> Result<(), ControlError> {
+        Err(ControlError::unsupported("Drain"))
+    }
+
+    /// Applies the settings of the configuration file which can change at runtime, and returns
+    /// what was applied.
+    fn reload_config(&self) -> Result<String, ControlError> {
+        Err(ControlError::unsupported("ReloadConfig"))
+    }
+
+    /// Returns the names of the role-specific methods, none by default.
+    fn extensions(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Calls the role-specific `method` with `params`, `null` if none were given.
+    fn call_extension(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, ControlError> {
+        let _ = params;
+        Err(ControlError::NotFound(format!("no extension `{method}`")))
+    }
+}
+
+// Serves the methods of the generated `Control` service with a handler.
+struct ControlService<H: ?Sized> {
+    handler: Arc<H>,
+}
+
+#[tonic::async_trait]
+impl<H: ControlHandler + ?Sized> Control for ControlService<H> {
+    async fn get_status(
+        &self,
+        _request: Request<GetStatusRequest>,
+    ) -> Result<Response<GetStatusResponse>, Status> {
+        let health = self.handler.health();
+        Ok(Response::new(GetStatusResponse {
+            role: self.handler.role().to_string(),
+            version: self.handler.version().to_string(),
+            live: health.is_live(),
+            ready: health.is_ready(),
+            health_json: serde_json::to_string(&health)
+                .expect("health report is always serializable"),
+            status_json: self.handler.status().to_string(),
+        }))
+    }
+
+    async fn list_connections(
+        &self,
+        _request: Request<ListConnectionsRequest>,
+    ) -> Result<Response<ListConnectionsResponse>, Status> {
+        Ok(Response::new(ListConnectionsResponse {
+            connections: self.handler.connections()?,
+        }))
+    }
+
+    async fn disconnect(
+        &self,
+        request: Request<DisconnectRequest>,
+    ) -> Result<Response<DisconnectResponse>, Status> {
+        let id = request.into_inner().id;
+        self.handler.disconnect(id)?;
+        info!("Connection {id} closed through the control service");
+        Ok(Response::new(DisconnectResponse {}))
+    }
+
+    async fn drain(
+        &self,
+        _request: Request<DrainRequest>,
+    ) -> Result<Response<DrainResponse>, Status> {
+        self.handler.drain()?;
+        info!("Draining: new connections are refused");
+        Ok(Response::new(DrainResponse {}))
+    }
+
+    async fn reload_config(
+        &self,
+        _request: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigResponse>, Status> {
+        let summary = self.handler.reload_config()?;
+        info!("Configuration reloaded through the control service: {summary}");
+        Ok(Response::new(ReloadConfigResponse { summary }))
+    }
+
+    async fn list_extensions(
+        &self,
+        _request: Request<ListExtensionsRequest>,
+    ) -> Result<Response<ListExtensionsResponse>, Status> {
+        Ok(Response::new(ListExtensionsResponse {
+            methods: self.handler.extensions(),
+        }))
+    }
+
+    async fn call_extension(
+        &self,
+        request: Request<CallExtensionRequest>,
+    ) -> Result<Response<CallExtensionResponse>, Status> {
+        let request = request.into_inner();
+        let params = match request.params_json.trim() {
+            "" => serde_json::Value::Null,
+            params => serde_json::from_str(params).map_err(|e| {
+                ControlError::InvalidArgument(format!("parameters are not valid JSON: {e}"))
+            })?,
+        };
+        let result = self.handler.call_extension(&request.method, params)?;
+        Ok(Response::new(CallExtensionResponse {
+            result_json: result.to_string(),
+        }))
+    }
+}
+
+/// Serves the control service on `config.listen_address` until `shutdown` completes, requiring
+/// the `config.auth_token` if set.
+///
+/// Returns an error if the address cannot be bound.
+pub async fn serve<H, F>(
+    config: &ControlConfig,
+    handler: Arc<H>,
+    shutdown: F,
+) -> Result<(), tonic::transport::Error>
+where
+    H: ControlHandler + ?Sized,
+    F: Future<Output = ()>,
+{
+    info!(
+        "Control service listening on grpc://{}",
+        config.listen_address
+    );
+    let service = ControlService { handler };
+    let mut server = tonic::transport::Server::builder();
+    match &config.auth_token {
+        Some(token) => {
+            let expected = format!("Bearer {token}");
+            server
+                .add_service(ControlServer::with_interceptor(
+                    service,
+                    move |request: Request<()>| authorize(request, &expected),
+                ))
+                .serve_with_shutdown(config.listen_address, shutdown)
+                .await
+        }
+        None => {
+            server
+                .add_service(ControlServer::new(service))
+                .serve_with_shutdown(config.listen_address, shutdown)
+                .await
+        }
+    }
+}
+
+/// Spawns the service as the `control` task of `task_manager`, restarted according to
+/// [`RESTART_POLICY`] if it fails. The role keeps running without it once the retries are
+/// exhausted.
+///
+/// The service stops on the first message received on `shutdown_rx` matching `stops_on`, or
+/// once the shutdown channel is closed.
+pub fn spawn<H, M>(
+    task_manager: &TaskManager,
+    config: ControlConfig,
+    handler: Arc<H>,
+    shutdown_rx: broadcast::Receiver<M>,
+    stops_on: fn(&M) -> bool,
+) where
+    H: ControlHandler + ?Sized,
+    M: Clone + Send + 'static,
+{
+    // Shared by the successive runs, so a shutdown sent while restarting is not missed
+    let shutdown_rx = Arc::new(tokio::sync::Mutex::new(shutdown_rx));
+    task_manager.spawn_restart_on_failure("control", RESTART_POLICY, move || {
+        let config = config.clone();
+        let handler = handler.clone();
+        let shutdown_rx = shutdown_rx.clone();
+        async move {
+            let shutdown = async move {
+                let mut shutdown_rx = shutdown_rx.lock().await;
+                loop {
+                    match shutdown_rx.recv().await {
+                        Ok(message) if stops_on(&message) => break,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    }
+                }
+            };
+            serve(&config, handler, shutdown).await
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::control_client::ControlClient;
+    use std::sync::Mutex;
+    use tokio::net::TcpListener;
+    use tonic::{transport::Channel, Code};
+
+    struct TestHandler {
+        connections: Mutex<Vec<u64>>,
+    }
+
+    impl ControlHandler for TestHandler {
+        fn role(&self) -> &str {
+            "test"
+        }
+
+        fn status(&self) -> serde_json::Value {
+            serde_json::json!({ "miners": 2 })
+        }
+
+        fn connections(&self) -> Result<Vec<Connection>, ControlError> {
+            Ok(self
+                .connections
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|id| Connection {
+                    id: *id,
+                    address: "127.0.0.1:34254".to_string(),
+                    ..Default::default()
+                })
+                .collect())
+        }
+
+        fn disconnect(&self, id: u64) -> Result<(), ControlError> {
+            let mut connections = self.connections.lock().unwrap();
+            let before = connections.len();
+            connections.retain(|connection| *connection != id);
+            if connections.len() == before {
+                return Err(ControlError::NotFound(format!("no connection {id}")));
+            }
+            Ok(())
+        }
+
+        fn extensions(&self) -> Vec<String> {
+            vec!["echo".to_string()]
+        }
+
+        fn call_extension(
+            &self,
+            method: &str,
+            params: serde_json::Value,
+        ) -> Result<serde_json::Value, ControlError> {
+            match method {
+                "echo" => Ok(params),
+                _ => Err(ControlError::NotFound(format!("no extension `{method}`"))),
+            }
+        }
+    }
+
+    async fn connect(address: SocketAddr) -> ControlClient<Channel> {
+        for _ in 0..50 {
+            if let Ok(client) = ControlClient::connect(format!("http://{address}")).await {
+                return client;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("control service did not start");
+    }
+
+    #[tokio::test]
+    async fn serves_the_handler() {
+        // Reserve a free port, then release it for the server.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = ControlConfig::new(address);
+        let handler = Arc::new(TestHandler {
+            connections: Mutex::new(vec![1, 2]),
+        });
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve(&config, handler, async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+        });
+        let mut client = connect(address).await;
+
+        let status = client
+            .get_status(GetStatusRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.role, "test");
+        assert!(status.live && status.ready);
+        assert_eq!(status.status_json, r#"{"miners":2}"#);
+
+        client
+            .disconnect(DisconnectRequest { id: 1 })
+            .await
+            .unwrap();
+        let missing = client
+            .disconnect(DisconnectRequest { id: 1 })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+        let connections = client
+            .list_connections(ListConnectionsRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .connections;
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].id, 2);
+
+        // Not implemented by the handler
+        let drain = client.drain(DrainRequest {}).await.unwrap_err();
+        assert_eq!(drain.code(), Code::Unimplemented);
+
+        let echo = client
+            .call_extension(CallExtensionRequest {
+                method: "echo".to_string(),
+                params_json: r#"{"a":1}"#.to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(echo.result_json, r#"{"a":1}"#);
+        let invalid = client
+            .call_extension(CallExtensionRequest {
+                method: "echo".to_string(),
+                params_json: "{".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.code(), Code::InvalidArgument);
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn requests_require_the_auth_token() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = ControlConfig::new(address).with_auth_token("secret");
+        let handler = Arc::new(TestHandler {
+            connections: Mutex::new(Vec::new()),
+        });
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve(&config, handler, async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+        });
+        let mut client = connect(address).await;
+
+        let missing = client.get_status(GetStatusRequest {}).await.unwrap_err();
+        assert_eq!(missing.code(), Code::Unauthenticated);
+        for (token, authorized) in [("Bearer secret", true), ("Bearer secreT", false)] {
+            let mut request = Request::new(GetStatusRequest {});
+            request
+                .metadata_mut()
+                .insert("authorization", token.parse().unwrap());
+            assert_eq!(client.get_status(request).await.is_ok(), authorized);
+        }
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,24 @@
+//! Encryption setting of the SV2 listeners and upstreams of the roles
+//!
+//! Roles pick the transport of each listener or upstream through an [`Encryption`] setting,
+//! usually embedded in the role configuration:
+//!
+//! ```toml
+//! encryption = "none" # defaults to "noise"
+//! ```
+//!
+//! The setting does not depend on the `network` feature, so that the roles running their
+//! connections on the crates.io SV2 crates, like the JDS, share it.
+
+use serde::Deserialize;
+
+/// Encryption of an SV2 connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    /// Noise handshake and encrypted transport, as mandated by the SV2 specification.
+    #[default]
+    Noise,
+    /// Plaintext SV2 frames, only for peers on a trusted network. Both ends must agree.
+    None,
+}
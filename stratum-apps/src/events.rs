@@ -0,0 +1,346 @@
+//! Domain events published by the roles
+//!
+//! Roles publish typed [`Event`]s (shares validated, blocks found, connections opened, set up and
+//! closed, channels closed, templates received) on an [`EventBus`], and every consumer of these
+//! events (persistence, metrics, webhooks, accounting, ...) subscribes to the bus independently of
+//! the others.
+//!
+//! - [`EventBus::publish`] never blocks: events are broadcast to the subscribers through a bounded
+//!   channel, and a subscriber which cannot keep up misses the oldest events (with a warning)
+//!   rather than slowing down the role.
+//! - [`EventBus::attach`] runs an [`EventSubscriber`] on its own task, handing it the events in
+//!   the order they were published.
+//! - [`EventBus::subscribe`] returns the raw receiver, for subscribers driving their own loop.
+
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+use crate::runtime::TaskManager;
+
+/// Number of events a subscriber may lag behind before it misses events.
+pub const DEFAULT_EVENT_BUS_CAPACITY: usize = 4096;
+
+/// A domain event published by a role.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A submitted share has been accepted or rejected.
+    ShareValidated(ShareValidated),
+    /// A share found a block.
+    BlockFound(BlockFound),
+    /// A downstream connection has been opened.
+    ConnectionOpened(ConnectionOpened),
+    /// The `SetupConnection` of a downstream connection has been accepted.
+    ConnectionSetUp(ConnectionSetUp),
+    /// A downstream connection has been closed.
+    ConnectionClosed(ConnectionClosed),
+    /// A channel of a downstream connection has been closed.
+    ChannelClosed(ChannelClosed),
+    /// A template has been received from the Template Provider.
+    TemplateReceived(TemplateReceived),
+}
+
+/// A submitted share, accepted or rejected.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareValidated {
+    /// Connection the share was submitted on.
+    pub connection_id: usize,
+    /// Identifier of the submitting client (usually its peer address).
+    pub client: String,
+    /// Channel the share was submitted on.
+    pub channel_id: u32,
+    /// User identity of the channel, if the share was accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_identity: Option<String>,
+    /// Account of the user identity, if the share was accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// Worker of the user identity, if the share was accepted and the identity names one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker: Option<String>,
+    /// `sequence_number` of the submit message.
+    pub sequence_number: u32,
+    /// Job the share was mined on.
+    pub job_id: u32,
+    /// Block header nonce.
+    pub nonce: u32,
+    /// Block header timestamp.
+    pub ntime: u32,
+    /// Block header version.
+    pub version: u32,
+    /// Error code sent back in `SubmitSharesError`, if the share was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    /// Difficulty of the channel target, i.e. the work credited to the user if the share was
+    /// accepted, unless the share was submitted on an unknown channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_difficulty: Option<f64>,
+    /// Difficulty met by the hash of the share, if it was accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share_difficulty: Option<f64>,
+    /// Best difficulty met by the shares of the same user since startup, this share included if
+    /// it was accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best_share_difficulty: Option<f64>,
+    /// Hash of the block header of the share, as displayed by block explorers, if it was
+    /// accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share_hash: Option<String>,
+    /// Whether the share was accepted with a difficulty notably close to the network difficulty,
+    /// as configured by the role.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub notable: bool,
+}
+
+impl ShareValidated {
+    /// Whether the share was accepted.
+    pub fn is_accepted(&self) -> bool {
+        self.error_code.is_none()
+    }
+}
+
+/// A block found by a share.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockFound {
+    /// Connection the share was submitted on.
+    pub connection_id: usize,
+    /// Channel the share was submitted on.
+    pub channel_id: u32,
+    /// User identity of the channel.
+    pub user_identity: String,
+    /// Account of the user identity.
+    pub account: String,
+    /// Worker of the user identity, if it names one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker: Option<String>,
+    /// Hash of the block.
+    pub block_hash: String,
+    /// Amount paid to the pool by the coinbase of the block, in satoshis.
+    pub reward: u64,
+    /// Template the block was built on, if it was not a custom job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_id: Option<u64>,
+}
+
+/// A downstream connection, once its handshake succeeded.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionOpened {
+    /// Identifier of the connection.
+    pub connection_id: usize,
+    /// Peer address of the connection.
+    pub address: String,
+}
+
+/// Device of a downstream, as described by the `SetupConnection` opening its connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeviceInfo {
+    pub vendor: String,
+    pub hardware_version: String,
+    pub firmware: String,
+    pub device_id: String,
+}
+
+/// A downstream connection whose `SetupConnection` was accepted.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionSetUp {
+    /// Identifier of the connection.
+    pub connection_id: usize,
+    /// Peer address of the connection.
+    pub address: String,
+    /// Protocol version negotiated.
+    pub version: u16,
+    /// Flags of the `SetupConnection`.
+    pub flags: u32,
+    /// Device of the downstream.
+    #[serde(flatten)]
+    pub device: DeviceInfo,
+}
+
+/// A downstream connection removed from the role.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionClosed {
+    /// Identifier of the connection.
+    pub connection_id: usize,
+    /// Peer address of the connection.
+    pub address: String,
+}
+
+/// A channel of a downstream connection, closed by the downstream, by the role or along with its
+/// connection.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelClosed {
+    /// Connection the channel was opened on.
+    pub connection_id: usize,
+    /// Peer address of the connection.
+    pub client: String,
+    /// Identifier of the channel.
+    pub channel_id: u32,
+    /// User identity of the channel.
+    pub user_identity: String,
+    /// Whether the channel was an extended channel.
+    pub extended: bool,
+    /// Reason code of the closing, as in `CloseChannel`.
+    pub reason: String,
+}
+
+/// A `NewTemplate` received from the Template Provider.
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateReceived {
+    /// Identifier of the template.
+    pub template_id: u64,
+    /// Whether the template is for a future block.
+    pub future_template: bool,
+    /// Value of the coinbase left to the role, in satoshis.
+    pub coinbase_value: u64,
+}
+
+macro_rules! impl_from_event {
+    ($($variant:ident),*) => {
+        $(
+            impl From<$variant> for Event {
+                fn from(event: $variant) -> Self {
+                    Event::$variant(event)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_event!(
+    ShareValidated,
+    BlockFound,
+    ConnectionOpened,
+    ConnectionSetUp,
+    ConnectionClosed,
+    ChannelClosed,
+    TemplateReceived
+);
+
+/// Consumer of the [`Event`]s of a bus, run by [`EventBus::attach`].
+pub trait EventSubscriber: Send + 'static {
+    /// Handles an event. Events are handled one at a time, in the order they were published, so
+    /// implementations should not block.
+    fn handle(&mut self, event: &Event);
+}
+
+impl<F: FnMut(&Event) + Send + 'static> EventSubscriber for F {
+    fn handle(&mut self, event: &Event) {
+        self(event)
+    }
+}
+
+/// Cheap-to-clone handle publishing [`Event`]s to the subscribers of a bus.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<Event>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_BUS_CAPACITY)
+    }
+}
+
+impl EventBus {
+    /// Creates a bus on which subscribers may lag `capacity` events behind.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Publishes `event` to the current subscribers. Events published without any subscriber
+    /// are dropped.
+    pub fn publish(&self, event: impl Into<Event>) {
+        if self.sender.receiver_count() > 0 {
+            let _ = self.sender.send(Arc::new(event.into()));
+        }
+    }
+
+    /// Returns a receiver of the events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.sender.subscribe()
+    }
+
+    /// Runs `subscriber` on a task of `task_manager` named `name`, handing it the events
+    /// published from now on, until every handle of the bus is dropped.
+    pub fn attach(
+        &self,
+        task_manager: &TaskManager,
+        name: &'static str,
+        mut subscriber: impl EventSubscriber,
+    ) {
+        let mut receiver = self.subscribe();
+        task_manager.spawn_named(name, async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => subscriber.handle(&event),
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Event subscriber `{name}` lagging behind, missed {missed} events");
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn template(template_id: u64) -> TemplateReceived {
+        TemplateReceived {
+            template_id,
+            future_template: false,
+            coinbase_value: 312_500_000,
+        }
+    }
+
+    fn template_id(event: &Event) -> u64 {
+        match event {
+            Event::TemplateReceived(template) => template.template_id,
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_the_events_in_order() {
+        let bus = EventBus::default();
+        // Dropped, as there is no subscriber yet.
+        bus.publish(template(0));
+
+        let task_manager = TaskManager::new();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let attached = received.clone();
+        bus.attach(&task_manager, "test_subscriber", move |event: &Event| {
+            attached.lock().unwrap().push(template_id(event));
+        });
+        let mut receiver = bus.subscribe();
+
+        for id in 1..=3 {
+            bus.publish(template(id));
+        }
+        for id in 1..=3 {
+            assert_eq!(template_id(&receiver.recv().await.unwrap()), id);
+        }
+        drop(bus);
+        task_manager.join_all().await;
+        assert_eq!(*received.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn lagging_subscribers_miss_the_oldest_events() {
+        let bus = EventBus::new(2);
+        let mut receiver = bus.subscribe();
+        for id in 1..=3 {
+            bus.publish(template(id));
+        }
+        assert!(matches!(receiver.recv().await, Err(RecvError::Lagged(1))));
+        assert_eq!(template_id(&receiver.recv().await.unwrap()), 2);
+        assert_eq!(template_id(&receiver.recv().await.unwrap()), 3);
+    }
+}
@@ -0,0 +1,111 @@
+//! Process exit statuses of the roles
+//!
+//! Every role binary exits with the [`ExitStatus`] of the error it stopped on, so that
+//! orchestrators (systemd, Kubernetes, supervisors, ...) can tell the failures worth restarting
+//! the role for from those which need an operator. The codes follow `sysexits.h` where one
+//! applies.
+//!
+//! | Status        | Code | Retryable | Cause                                                  |
+//! |---------------|------|-----------|--------------------------------------------------------|
+//! | `Success`     | 0    | -         | Clean shutdown, e.g. on Ctrl+C                         |
+//! | `Failure`     | 1    | yes       | Any other failure while running                        |
+//! | `Key`         | 65   | no        | Invalid authority keypair, certificate or TLS key      |
+//! | `Unreachable` | 69   | yes       | Template Provider, node or upstream not reachable      |
+//! | `Bind`        | 71   | yes       | Listening address cannot be bound, e.g. already in use |
+//! | `Config`      | 78   | no        | Configuration missing, unreadable or invalid           |
+
+use std::fmt;
+
+/// Outcome of a role binary, mapped to its process exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The role shut down cleanly.
+    Success,
+    /// The role failed while running, for a reason not covered by the other statuses.
+    Failure,
+    /// A key of the configuration is invalid, e.g. an authority secret key not matching its
+    /// public key.
+    Key,
+    /// A peer the role depends on (Template Provider, Bitcoin node, upstream) cannot be reached.
+    Unreachable,
+    /// A listening address of the role cannot be bound.
+    Bind,
+    /// The configuration is missing, unreadable or invalid.
+    Config,
+}
+
+impl ExitStatus {
+    /// Returns the process exit code of the status.
+    pub const fn code(self) -> u8 {
+        match self {
+            ExitStatus::Success => 0,
+            ExitStatus::Failure => 1,
+            ExitStatus::Key => 65,
+            ExitStatus::Unreachable => 69,
+            ExitStatus::Bind => 71,
+            ExitStatus::Config => 78,
+        }
+    }
+
+    /// Whether restarting the role as is may succeed. Configuration and key errors need the
+    /// configuration to be fixed first.
+    pub const fn is_retryable(self) -> bool {
+        !matches!(self, ExitStatus::Key | ExitStatus::Config)
+    }
+
+    /// Exits the process with the code of the status.
+    pub fn exit(self) -> ! {
+        std::process::exit(self.code().into())
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExitStatus::Success => "success",
+            ExitStatus::Failure => "failure",
+            ExitStatus::Key => "key error",
+            ExitStatus::Unreachable => "unreachable",
+            ExitStatus::Bind => "bind failure",
+            ExitStatus::Config => "configuration error",
+        };
+        write!(f, "{name} (exit code {})", self.code())
+    }
+}
+
+impl From<ExitStatus> for std::process::ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        std::process::ExitCode::from(status.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ExitStatus; 6] = [
+        ExitStatus::Success,
+        ExitStatus::Failure,
+        ExitStatus::Key,
+        ExitStatus::Unreachable,
+        ExitStatus::Bind,
+        ExitStatus::Config,
+    ];
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let mut codes: Vec<u8> = ALL.iter().map(|status| status.code()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), ALL.len());
+    }
+
+    #[test]
+    fn only_config_and_key_errors_are_fatal() {
+        let fatal: Vec<ExitStatus> = ALL
+            .into_iter()
+            .filter(|status| !status.is_retryable())
+            .collect();
+        assert_eq!(fatal, vec![ExitStatus::Key, ExitStatus::Config]);
+    }
+}
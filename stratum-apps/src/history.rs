@@ -0,0 +1,262 @@
+//! Recent history of a role
+//!
+//! Operators diagnosing a role from a dashboard usually have no shell on its host to read its
+//! logs. The [`HistoryLayer`], installed by `init_logging`, keeps the last warnings and errors
+//! logged by the process in the [`log_history`] ring, with the fields of the spans they were
+//! logged in (connection id, channel id, ...), while the
+//! [`HealthAggregator`](crate::status::HealthAggregator) of a role keeps the last transitions of
+//! its components. With the `monitoring` feature, both are served on `GET /history`.
+//!
+//! Only the events passing the log filter of the role are kept.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{self, Write},
+    sync::{Arc, Mutex, OnceLock},
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::unix_time;
+
+/// Log records kept by the [`log_history`] ring, the oldest are dropped first.
+pub const LOG_HISTORY_CAPACITY: usize = 512;
+
+/// Warning or error logged by the role.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogRecord {
+    /// When the event was logged, in UNIX milliseconds.
+    pub at_ms: u64,
+    /// `WARN` or `ERROR`.
+    pub level: String,
+    /// Module the event was logged from.
+    pub target: String,
+    /// Spans the event was logged in, outermost first, with their fields, e.g.
+    /// `downstream{connection_id=3}`.
+    pub spans: Vec<String>,
+    pub message: String,
+    /// Fields of the event other than its message.
+    pub fields: BTreeMap<String, String>,
+}
+
+impl LogRecord {
+    /// Whether the record was logged at `ERROR`.
+    pub fn is_error(&self) -> bool {
+        self.level == Level::ERROR.as_str()
+    }
+}
+
+/// Bounded ring of the last warnings and errors logged.
+#[derive(Debug)]
+pub struct LogHistory {
+    capacity: usize,
+    records: Mutex<VecDeque<LogRecord>>,
+}
+
+impl LogHistory {
+    /// Creates an empty ring keeping up to `capacity` records.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Records `record`, dropping the oldest one if the ring is full.
+    pub fn record(&self, record: LogRecord) {
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Returns the records kept, oldest first.
+    pub fn snapshot(&self) -> Vec<LogRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Returns the ring the [`HistoryLayer`] installed by `init_logging` records to.
+pub fn log_history() -> &'static Arc<LogHistory> {
+    static LOG_HISTORY: OnceLock<Arc<LogHistory>> = OnceLock::new();
+    LOG_HISTORY.get_or_init(|| Arc::new(LogHistory::new(LOG_HISTORY_CAPACITY)))
+}
+
+/// Layer recording the warnings and errors logged to a [`LogHistory`].
+pub struct HistoryLayer {
+    history: Arc<LogHistory>,
+}
+
+impl HistoryLayer {
+    /// Creates a layer recording to `history`.
+    pub fn new(history: Arc<LogHistory>) -> Self {
+        Self { history }
+    }
+}
+
+// Fields of a span, formatted as `name=value` pairs, stored in its extensions.
+struct SpanFields(String);
+
+impl<S> Layer<S> for HistoryLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = FieldsVisitor::default();
+        attrs.record(&mut fields);
+        span.extensions_mut()
+            .insert(SpanFields(fields.into_pairs()));
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = FieldsVisitor::default();
+        values.record(&mut fields);
+        let recorded = fields.into_pairs();
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<SpanFields>() {
+            Some(SpanFields(pairs)) if !pairs.is_empty() => {
+                pairs.push(' ');
+                pairs.push_str(&recorded);
+            }
+            Some(SpanFields(pairs)) => *pairs = recorded,
+            None => extensions.insert(SpanFields(recorded)),
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        // More verbose levels compare greater.
+        if level > Level::WARN {
+            return;
+        }
+        let spans = ctx
+            .event_scope(event)
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .map(|span| match span.extensions().get::<SpanFields>() {
+                        Some(SpanFields(pairs)) if !pairs.is_empty() => {
+                            format!("{}{{{pairs}}}", span.name())
+                        }
+                        _ => span.name().to_string(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut fields = FieldsVisitor::default();
+        event.record(&mut fields);
+        self.history.record(LogRecord {
+            at_ms: unix_time::now_ms(),
+            level: level.as_str().to_string(),
+            target: event.metadata().target().to_string(),
+            spans,
+            message: fields.message,
+            fields: fields.fields,
+        });
+    }
+}
+
+#[derive(Default)]
+struct FieldsVisitor {
+    message: String,
+    fields: BTreeMap<String, String>,
+}
+
+impl FieldsVisitor {
+    // Formats the fields as space separated `name=value` pairs.
+    fn into_pairs(self) -> String {
+        let mut pairs = String::new();
+        for (name, value) in self.fields {
+            if !pairs.is_empty() {
+                pairs.push(' ');
+            }
+            _ = write!(pairs, "{name}={value}");
+        }
+        pairs
+    }
+}
+
+impl Visit for FieldsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            self.fields
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{error, info, info_span, warn};
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    #[test]
+    fn keeps_the_last_warnings_and_errors() {
+        let history = Arc::new(LogHistory::new(2));
+        let subscriber = Registry::default().with(HistoryLayer::new(history.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let _downstream = info_span!("downstream", connection_id = 3).entered();
+            info!("Not kept");
+            warn!(channel_id = 7, "Share rejected: {}", "stale");
+            error!(user = "alice", "Connection lost");
+            error!("Channel closed");
+        });
+
+        let records = history.snapshot();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].message, "Connection lost");
+        assert!(records[0].is_error());
+        assert_eq!(records[0].fields["user"], "alice");
+        assert_eq!(records[0].spans, ["downstream{connection_id=3}"]);
+        assert_eq!(records[1].message, "Channel closed");
+        assert!(records[1].fields.is_empty());
+    }
+
+    #[test]
+    fn records_the_fields_of_the_spans() {
+        let history = Arc::new(LogHistory::new(LOG_HISTORY_CAPACITY));
+        let subscriber = Registry::default().with(HistoryLayer::new(history.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let upstream = info_span!("upstream", address = tracing::field::Empty);
+            upstream.record("address", "10.0.0.1:3333");
+            let _upstream = upstream.entered();
+            let _share = info_span!("share", channel_id = 1, sequence_number = 5).entered();
+            warn!(code = "stale-share", "Share rejected");
+        });
+
+        let records = history.snapshot();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, "WARN");
+        assert_eq!(
+            records[0].spans,
+            [
+                "upstream{address=10.0.0.1:3333}",
+                "share{channel_id=1 sequence_number=5}"
+            ]
+        );
+        assert_eq!(records[0].fields["code"], "stale-share");
+    }
+}
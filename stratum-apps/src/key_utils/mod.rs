@@ -20,6 +20,7 @@ pub enum Error {
     Secp256k1(secp256k1::Error),
     KeyVersion(u16),
     KeyLength,
+    NoAuthorityKey,
     Custom(String),
 }
 
@@ -32,6 +33,7 @@ impl Display for Error {
                 write!(f, "Unknown public key version. version found: {obtained}")
             }
             Self::KeyLength => write!(f, "Bad key length"),
+            Self::NoAuthorityKey => write!(f, "At least one authority public key is required"),
             Self::Custom(error) => write!(f, "Custom error: {error}"),
         }
     }
@@ -90,7 +92,7 @@ impl Display for Secp256k1SecretKey {
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(into = "String", try_from = "String")]
 pub struct Secp256k1PublicKey(pub XOnlyPublicKey);
 
@@ -145,6 +147,12 @@ impl Secp256k1SecretKey {
     pub fn into_bytes(self) -> [u8; 32] {
         self.0.secret_bytes()
     }
+
+    /// Generates a fresh random secret key, e.g. for a new authority keypair.
+    #[cfg(feature = "std")]
+    pub fn generate() -> Self {
+        Self(SecretKey::new(&mut rand::thread_rng()))
+    }
 }
 
 impl From<Secp256k1SecretKey> for Secp256k1PublicKey {
@@ -155,6 +163,79 @@ impl From<Secp256k1SecretKey> for Secp256k1PublicKey {
     }
 }
 
+/// Authority public keys accepted from a peer, in order of preference.
+///
+/// Lets an upstream rotate its authority keypair without a flag day: its clients accept both the
+/// current and the next key until the rotation is over. Deserializes from a single key, as
+/// before, or from a non-empty list of keys.
+#[derive(Debug, Clone, Serialize)]
+#[serde(into = "Vec<Secp256k1PublicKey>")]
+pub struct AuthorityPublicKeys(Vec<Secp256k1PublicKey>);
+
+impl AuthorityPublicKeys {
+    /// Returns the keys, most preferred first.
+    pub fn keys(&self) -> &[Secp256k1PublicKey] {
+        &self.0
+    }
+}
+
+impl From<Secp256k1PublicKey> for AuthorityPublicKeys {
+    fn from(key: Secp256k1PublicKey) -> Self {
+        Self(Vec::from([key]))
+    }
+}
+
+impl TryFrom<Vec<Secp256k1PublicKey>> for AuthorityPublicKeys {
+    type Error = Error;
+
+    fn try_from(keys: Vec<Secp256k1PublicKey>) -> Result<Self, Self::Error> {
+        match keys.is_empty() {
+            true => Err(Error::NoAuthorityKey),
+            false => Ok(Self(keys)),
+        }
+    }
+}
+
+impl From<AuthorityPublicKeys> for Vec<Secp256k1PublicKey> {
+    fn from(keys: AuthorityPublicKeys) -> Self {
+        keys.0
+    }
+}
+
+impl<'de> Deserialize<'de> for AuthorityPublicKeys {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeysVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for KeysVisitor {
+            type Value = AuthorityPublicKeys;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.write_str("an authority public key or a list of authority public keys")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                value
+                    .parse::<Secp256k1PublicKey>()
+                    .map(Into::into)
+                    .map_err(E::custom)
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut keys = Vec::new();
+                while let Some(key) = seq.next_element::<Secp256k1PublicKey>()? {
+                    keys.push(key);
+                }
+                AuthorityPublicKeys::try_from(keys).map_err(serde::de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_any(KeysVisitor)
+    }
+}
+
 pub struct SignatureService {
     secp_sign: Secp256k1<SignOnly>,
     secp_verify: Secp256k1<VerifyOnly>,
@@ -213,6 +294,52 @@ impl Default for SignatureService {
     }
 }
 
+/// Length of the Schnorr signature closing a signed mining job token.
+const MINING_JOB_TOKEN_SIGNATURE_LEN: usize = 64;
+
+/// Signs a mining job token, so that a pool can check that a custom job was declared to a Job
+/// Declarator Server holding `private_key`.
+///
+/// The signed token is the token followed by a Schnorr signature of the token padded with zeros
+/// to 32 bytes. Returns `None` if the token is longer than 32 bytes.
+#[cfg(feature = "std")]
+pub fn sign_mining_job_token(token: &[u8], private_key: &Secp256k1SecretKey) -> Option<Vec<u8>> {
+    let message = mining_job_token_message(token)?;
+    let signature = SignatureService::default().sign(message.to_vec(), private_key.0);
+    let mut signed_token = token.to_vec();
+    signed_token.extend_from_slice(signature.as_ref());
+    Some(signed_token)
+}
+
+/// Checks that a mining job token was signed by [`sign_mining_job_token`] with the secret key of
+/// one of `public_keys`.
+pub fn verify_mining_job_token(signed_token: &[u8], public_keys: &AuthorityPublicKeys) -> bool {
+    let Some(token_len) = signed_token
+        .len()
+        .checked_sub(MINING_JOB_TOKEN_SIGNATURE_LEN)
+    else {
+        return false;
+    };
+    let (token, signature) = signed_token.split_at(token_len);
+    let (Some(message), Ok(signature)) = (
+        mining_job_token_message(token),
+        Signature::from_slice(signature),
+    ) else {
+        return false;
+    };
+    let service = SignatureService::default();
+    public_keys
+        .keys()
+        .iter()
+        .any(|key| service.verify(message.to_vec(), signature, key.0).is_ok())
+}
+
+fn mining_job_token_message(token: &[u8]) -> Option<[u8; 32]> {
+    let mut message = [0_u8; 32];
+    message.get_mut(..token.len())?.copy_from_slice(token);
+    Some(message)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -262,4 +389,66 @@ mod test {
             .expect("Invalid test pubkey");
         assert_eq!(calculated_public_key.0, parsed_public_key.0);
     }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn generated_keys_round_trip() {
+        let secret_key = Secp256k1SecretKey::generate();
+        let public_key = Secp256k1PublicKey::from(secret_key);
+
+        let parsed_secret_key: Secp256k1SecretKey = secret_key.to_string().parse().unwrap();
+        let parsed_public_key: Secp256k1PublicKey = public_key.to_string().parse().unwrap();
+        assert_eq!(parsed_secret_key.into_bytes(), secret_key.into_bytes());
+        assert_eq!(parsed_public_key.into_bytes(), public_key.into_bytes());
+        assert_ne!(
+            Secp256k1SecretKey::generate().into_bytes(),
+            secret_key.into_bytes()
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn mining_job_tokens_are_verified_against_the_signing_key() {
+        let secret_key = Secp256k1SecretKey::generate();
+        let keys = AuthorityPublicKeys::from(Secp256k1PublicKey::from(secret_key));
+        let other_keys =
+            AuthorityPublicKeys::from(Secp256k1PublicKey::from(Secp256k1SecretKey::generate()));
+
+        let signed_token = sign_mining_job_token(&7_u32.to_le_bytes(), &secret_key).unwrap();
+        assert_eq!(signed_token.len(), 4 + MINING_JOB_TOKEN_SIGNATURE_LEN);
+        assert!(verify_mining_job_token(&signed_token, &keys));
+        assert!(!verify_mining_job_token(&signed_token, &other_keys));
+
+        let mut forged_token = signed_token.clone();
+        forged_token[0] ^= 1;
+        assert!(!verify_mining_job_token(&forged_token, &keys));
+        assert!(!verify_mining_job_token(&7_u32.to_le_bytes(), &keys));
+        assert!(sign_mining_job_token(&[0; 33], &secret_key).is_none());
+    }
+
+    #[test]
+    fn authority_keys_deserialize_from_one_or_many_keys() {
+        use serde::de::value::{Error as ValueError, SeqDeserializer, StrDeserializer};
+
+        let current = "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72";
+        let next = "9bwHCYnjhbHm4AS3pWg9MtAH83mzWohoJJJDELYBqZhDNqszDLc";
+
+        let keys =
+            AuthorityPublicKeys::deserialize(StrDeserializer::<ValueError>::new(current)).unwrap();
+        assert_eq!(keys.keys().len(), 1);
+
+        let keys = AuthorityPublicKeys::deserialize(SeqDeserializer::<_, ValueError>::new(
+            [current, next].into_iter(),
+        ))
+        .unwrap();
+        let keys: Vec<String> = keys.keys().iter().map(|key| key.to_string()).collect();
+        assert_eq!(keys, [current, next]);
+
+        assert!(
+            AuthorityPublicKeys::deserialize(SeqDeserializer::<_, ValueError>::new(
+                core::iter::empty::<&str>()
+            ))
+            .is_err()
+        );
+    }
 }
@@ -10,12 +10,21 @@
 //! - `network` - High-level networking utilities (enabled by default)
 //! - `config` - Configuration management helpers (enabled by default)
 //! - `rpc` - RPC utilities with custom types for JSON-RPC communication (optional)
+//! - `persistence` - Durable recording of protocol events (optional)
+//! - `tls` - TLS server side configuration for SV1 listeners (optional)
+//! - `monitoring` - HTTP endpoint exposing the runtime state and health of a role (optional)
+//! - `control` - gRPC service driving a role from fleet-management tooling (optional)
+//! - `otel` - Export of the logging spans to an OpenTelemetry collector over OTLP (optional)
+//! - `mock_tp` - Template Provider serving synthetic templates, for tests and demos (optional)
+//! - `sniffer` - Proxy decoding and recording the SV2 messages between two roles, and replay of
+//!   its captures (optional)
 //!
 //! ### Role-Specific Feature Bundles
-//! - `pool` - Everything needed for pool applications
-//! - `jd_client` - Everything needed for JD client applications
-//! - `jd_server` - Everything needed for JD server applications (includes RPC)
-//! - `translator` - Everything needed for translator applications (includes SV1)
+//! - `pool` - Everything needed for pool applications (includes monitoring and persistence)
+//! - `jd_client` - Everything needed for JD client applications (includes monitoring)
+//! - `jd_server` - Everything needed for JD server applications (includes RPC and persistence)
+//! - `translator` - Everything needed for translator applications (includes SV1, TLS,
+//!   monitoring and persistence)
 //! - `mining_device` - Everything needed for mining device applications
 //!
 //! ## Modules
@@ -23,6 +32,24 @@
 //! - [`network_helpers`] - High-level networking utilities for SV2 connections
 //! - [`config_helpers`] - Configuration management and parsing utilities
 //! - [`rpc`] - RPC utilities with custom serializable types (`Hash`, `BlockHash`, `Amount`)
+//! - [`persistence`] - Durable, non-blocking event recording with pluggable backends
+//! - [`unix_time`] - Unix timestamps of the events, statistics and state files of the roles
+//! - [`events`] - Bus of the domain events published by the roles to independent subscribers
+//! - [`rate_limit`] - Token bucket rate limiting
+//! - [`banlist`] - IP, CIDR range and public key bans shared by the listeners of a role
+//! - [`encryption`] - Noise or plaintext transport of the listeners and upstreams of a role
+//! - [`state_file`] - Atomic writes of the state files of the roles
+//! - [`connections`] - Registry of the downstream connections of a role, listed and closed by id
+//! - [`share_reject`] - Error codes of rejected shares and their counters
+//! - [`worker_identity`] - Account and worker of `account.worker` user identities
+//! - [`monitoring`] - HTTP endpoint serving a role's status as JSON
+//! - [`control`] - gRPC control service shared by the roles
+//! - [`runtime`] - Task management and connection I/O tasks shared by the roles
+//! - [`status`] - Health of the components of a role, for liveness and readiness probes
+//! - [`history`] - Last warnings and errors logged by a role and transitions of its health
+//! - [`exit`] - Process exit statuses of the roles, telling retryable from fatal failures
+//! - [`mock_tp`] - Mock Template Provider with scripted templates and fault injection
+//! - [`sniffer`] - Proxy logging and capturing the SV2 messages exchanged by two roles
 
 /// Re-export all the modules from `stratum_core`
 #[cfg(feature = "core")]
@@ -46,6 +73,45 @@ pub mod config_helpers;
 ///
 /// A wrapper around std::sync::Mutex
 pub mod custom_mutex;
+
+/// Token bucket rate limiting
+///
+/// Used by roles to bound how often a peer may send expensive messages.
+pub mod rate_limit;
+
+/// Ban list of the peers refused by a role
+///
+/// IP address, CIDR range and public key bans with optional expiry, persisted to a file.
+pub mod banlist;
+
+/// Encryption of SV2 connections
+///
+/// The Noise or plaintext transport setting of the listeners and upstreams of a role.
+pub mod encryption;
+
+/// Atomic state file writes
+///
+/// Writes of the state files of the roles through a temporary file renamed over them, so that a
+/// crash never leaves a truncated file.
+pub mod state_file;
+
+/// Registry of the downstream connections of a role
+///
+/// Id allocation, peer address, role-specific metadata and shutdown handle of every connection,
+/// listed the same way by every role.
+pub mod connections;
+
+/// Reasons a share is rejected
+///
+/// The error codes of `SubmitSharesError`, shared by the roles, and counters per reason.
+pub mod share_reject;
+
+/// Account and worker of user identities
+///
+/// Splits the `account.worker` user identities of SV1-era miners, at a configurable separator
+/// and after an optional regex check.
+pub mod worker_identity;
+
 /// RPC utilities for Job Declaration Server
 ///
 /// HTTP-based RPC server implementation for JD Server functionality.
@@ -58,3 +124,80 @@ pub mod rpc;
 /// Provides Secp256k1 key management, serialization/deserialization, and signature services.
 /// Supports both standard and no_std environments.
 pub mod key_utils;
+
+/// Durable event recording
+///
+/// Non-blocking recording of protocol events (job declarations, solutions, ...) to a
+/// configurable storage backend.
+#[cfg(feature = "persistence")]
+pub mod persistence;
+
+/// Unix timestamps
+///
+/// Seconds and milliseconds since the UNIX epoch, as recorded in the events, statistics and state
+/// files of the roles.
+pub mod unix_time;
+
+/// Domain events of the roles
+///
+/// Shares validated, blocks found, connections and templates, published on a bus to which
+/// persistence, metrics and hooks subscribe independently.
+pub mod events;
+
+/// HTTP monitoring endpoint
+///
+/// Serves the runtime state of a role (connections, shares, upstream health, ...) as JSON.
+#[cfg(feature = "monitoring")]
+pub mod monitoring;
+
+/// JSON webhooks
+///
+/// Non-blocking notification of JSON documents (notable shares, ...) to an HTTP endpoint.
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+/// gRPC control service
+///
+/// Status, connection management, draining and configuration reloads of a role, with
+/// role-specific extensions.
+#[cfg(feature = "control")]
+pub mod control;
+
+/// Runtime building blocks shared by the roles
+///
+/// Task tracking, the reader and writer tasks of SV2 connections and message type
+/// classification, so every role runs on the same implementation.
+pub mod runtime;
+
+/// Health of a role
+///
+/// Aggregates the health reported by the components of a role into a machine-readable report,
+/// served to liveness and readiness probes by the monitoring endpoint.
+pub mod status;
+
+/// Recent history of a role
+///
+/// Bounded rings of the last warnings and errors logged and of the health transitions of the
+/// components, served by the monitoring endpoint.
+pub mod history;
+
+/// Process exit statuses of the roles
+///
+/// Distinct exit codes for configuration, key, bind and connectivity failures, so that
+/// orchestrators tell the retryable failures from the fatal ones.
+pub mod exit;
+
+/// Mock Template Provider
+///
+/// Serves scripted or periodic synthetic templates, records the solutions and injects faults, to
+/// test the roles without a Bitcoin node.
+#[cfg(feature = "mock_tp")]
+pub mod mock_tp;
+
+/// SV2 sniffer
+///
+/// Performs the Noise handshakes with a downstream and an upstream role, relays their messages
+/// and logs them decoded, optionally recording them to a capture file which can be replayed
+/// against a role.
+#[cfg(feature = "sniffer")]
+pub mod sniffer;
@@ -0,0 +1,784 @@
+//! Mock Template Provider
+//!
+//! [`MockTemplateProvider`] serves synthetic templates over the Template Distribution Protocol,
+//! for tests and demos of the roles without a Bitcoin node:
+//!
+//! - The templates follow a [`Schedule`]: a new template every interval with a new block every
+//!   few templates, or a script of [`Step`]s run once a first downstream is ready.
+//! - The chain is shared by every downstream, so that a pool and a JDC connected to the same mock
+//!   agree on the previous block hash. Downstreams connecting later are sent the template of the
+//!   current block, its `SetNewPrevHash` and the latest template for it.
+//! - `SubmitSolution`s are recorded, see [`MockTemplateProvider::solutions`], and move the chain
+//!   to a new block, as a node would.
+//! - Scripted steps inject faults to test the resilience of the roles: delays before every
+//!   message, disconnections and frames which cannot be decoded.
+//!
+//! `RequestTransactionData` is answered with an empty transaction list, the templates having no
+//! transaction but the coinbase.
+
+use std::{fmt, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+
+use stratum_core::{
+    binary_sv2::{Seq0255, Seq064K},
+    buffer_sv2,
+    codec_sv2::{HandshakeRole, StandardEitherFrame},
+    common_messages_sv2::{Protocol, SetupConnectionError, SetupConnectionSuccess},
+    framing_sv2::framing::{Frame, Sv2Frame},
+    noise_sv2::Responder,
+    parsers_sv2::{AnyMessage, CommonMessages, TemplateDistribution},
+    template_distribution_sv2::{
+        NewTemplate, RequestTransactionDataSuccess, SetNewPrevHash, MESSAGE_TYPE_NEW_TEMPLATE,
+    },
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, Notify},
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    custom_mutex::Mutex,
+    key_utils::{Secp256k1PublicKey, Secp256k1SecretKey},
+    network_helpers::{
+        noise_stream::{NoiseTcpStream, NoiseTcpWriteHalf},
+        Error,
+    },
+    runtime::TaskManager,
+    unix_time,
+};
+
+type Message = AnyMessage<'static>;
+type EitherFrame = StandardEitherFrame<Message>;
+
+/// `nBits` of regtest, whose target is met by about every other hash.
+pub const REGTEST_N_BITS: u32 = 0x207f_ffff;
+
+/// Reward of a block at height 0, in satoshis.
+const DEFAULT_COINBASE_VALUE: u64 = 5_000_000_000;
+
+/// Number of updates a downstream may lag behind before it misses some.
+const UPDATES_CAPACITY: usize = 64;
+
+/// When the templates are sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// A new template every `interval`, every `new_block_every`th of them for a new block.
+    Periodic {
+        interval: Duration,
+        new_block_every: u32,
+    },
+    /// The steps, run once when a first downstream is ready. Without any step, the chain only
+    /// moves on solutions and on calls to [`MockTemplateProvider::new_block`] and
+    /// [`MockTemplateProvider::new_template`].
+    Scripted(Vec<Step>),
+}
+
+/// A step of a [`Schedule::Scripted`] schedule.
+///
+/// Steps are parsed from `new-block`, `new-template`, `wait=<ms>`, `delay=<ms>`, `disconnect`
+/// and `malformed-frame`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Sends a future template for a new block, then the `SetNewPrevHash` activating it.
+    NewBlock,
+    /// Sends a template for the current block, as on new transactions in the mempool.
+    NewTemplate,
+    /// Waits before the next step.
+    Wait(Duration),
+    /// Delays every message sent from now on, `Duration::ZERO` to stop.
+    Delay(Duration),
+    /// Closes the connections of the downstreams.
+    Disconnect,
+    /// Sends a `NewTemplate` frame whose payload cannot be decoded.
+    MalformedFrame,
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let millis = |value: &str| {
+            value
+                .parse()
+                .map(Duration::from_millis)
+                .map_err(|e| format!("invalid milliseconds in step `{s}`: {e}"))
+        };
+        match s.trim().split_once('=') {
+            None => match s.trim() {
+                "new-block" => Ok(Step::NewBlock),
+                "new-template" => Ok(Step::NewTemplate),
+                "disconnect" => Ok(Step::Disconnect),
+                "malformed-frame" => Ok(Step::MalformedFrame),
+                _ => Err(format!("unknown step `{s}`")),
+            },
+            Some(("wait", value)) => millis(value).map(Step::Wait),
+            Some(("delay", value)) => millis(value).map(Step::Delay),
+            Some(_) => Err(format!("unknown step `{s}`")),
+        }
+    }
+}
+
+/// A `SubmitSolution` received by the mock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    pub template_id: u64,
+    pub version: u32,
+    pub header_timestamp: u32,
+    pub header_nonce: u32,
+    pub coinbase_tx: Vec<u8>,
+}
+
+/// Configuration of a [`MockTemplateProvider`].
+#[derive(Debug, Clone)]
+pub struct MockTpConfig {
+    listen_address: SocketAddr,
+    schedule: Schedule,
+    authority_public_key: Secp256k1PublicKey,
+    authority_secret_key: Secp256k1SecretKey,
+    coinbase_value: u64,
+    n_bits: u32,
+}
+
+impl MockTpConfig {
+    /// Creates the configuration of a mock listening on `listen_address` (port 0 for a random
+    /// one) with a freshly generated authority keypair, 50 BTC templates and the regtest target.
+    pub fn new(listen_address: SocketAddr, schedule: Schedule) -> Self {
+        let authority_secret_key = Secp256k1SecretKey::generate();
+        Self {
+            listen_address,
+            schedule,
+            authority_public_key: authority_secret_key.into(),
+            authority_secret_key,
+            coinbase_value: DEFAULT_COINBASE_VALUE,
+            n_bits: REGTEST_N_BITS,
+        }
+    }
+
+    /// Sets the authority keypair of the Noise handshake.
+    pub fn with_authority_keys(
+        mut self,
+        public_key: Secp256k1PublicKey,
+        secret_key: Secp256k1SecretKey,
+    ) -> Self {
+        self.authority_public_key = public_key;
+        self.authority_secret_key = secret_key;
+        self
+    }
+
+    /// Sets the `coinbase_tx_value_remaining` of the templates, in satoshis.
+    pub fn with_coinbase_value(mut self, coinbase_value: u64) -> Self {
+        self.coinbase_value = coinbase_value;
+        self
+    }
+
+    /// Sets the `nBits` of the blocks.
+    pub fn with_n_bits(mut self, n_bits: u32) -> Self {
+        self.n_bits = n_bits;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Update {
+    Message(Message),
+    Delay(Duration),
+    Disconnect,
+    MalformedFrame,
+}
+
+#[derive(Debug, Default)]
+struct Chain {
+    height: u32,
+    prev_hash: [u8; 32],
+    next_template_id: u64,
+    // Updates bringing a new downstream up to date with the current block.
+    current: Vec<Update>,
+}
+
+struct Shared {
+    config: MockTpConfig,
+    chain: Mutex<Chain>,
+    updates: broadcast::Sender<Update>,
+    solutions: Mutex<Vec<Solution>>,
+    downstream_ready: Notify,
+}
+
+impl Shared {
+    fn new_block(&self) {
+        self.chain.super_safe_lock(|chain| {
+            chain.height += 1;
+            chain.prev_hash = rand::random();
+            let template = chain.template(&self.config, true);
+            let prev_hash = SetNewPrevHash {
+                template_id: template.template_id,
+                prev_hash: chain.prev_hash.into(),
+                header_timestamp: unix_time::now_secs() as u32,
+                n_bits: self.config.n_bits,
+                target: target_from_compact(self.config.n_bits).into(),
+            };
+            info!(
+                "New block at height {}, template {}",
+                chain.height, template.template_id
+            );
+            chain.current = vec![
+                Update::Message(AnyMessage::TemplateDistribution(
+                    TemplateDistribution::NewTemplate(template),
+                )),
+                Update::Message(AnyMessage::TemplateDistribution(
+                    TemplateDistribution::SetNewPrevHash(prev_hash),
+                )),
+            ];
+            for update in &chain.current {
+                let _ = self.updates.send(update.clone());
+            }
+        });
+    }
+
+    fn new_template(&self) {
+        self.chain.super_safe_lock(|chain| {
+            if chain.current.is_empty() {
+                warn!("No block yet, cannot send a template for the current one");
+                return;
+            }
+            let template = chain.template(&self.config, false);
+            debug!("New template {}", template.template_id);
+            let update = Update::Message(AnyMessage::TemplateDistribution(
+                TemplateDistribution::NewTemplate(template),
+            ));
+            chain.current.truncate(2);
+            chain.current.push(update.clone());
+            let _ = self.updates.send(update);
+        });
+    }
+
+    fn publish(&self, update: Update) {
+        let _ = self.updates.send(update);
+    }
+
+    // Subscribes to the updates, along with those bringing a downstream up to date.
+    fn subscribe(&self) -> (Vec<Update>, broadcast::Receiver<Update>) {
+        self.chain
+            .super_safe_lock(|chain| (chain.current.clone(), self.updates.subscribe()))
+    }
+}
+
+impl Chain {
+    fn template(&mut self, config: &MockTpConfig, future_template: bool) -> NewTemplate<'static> {
+        self.next_template_id += 1;
+        NewTemplate {
+            template_id: self.next_template_id,
+            future_template,
+            version: 0x2000_0000,
+            coinbase_tx_version: 2,
+            coinbase_prefix: bip34_height_push(self.height)
+                .try_into()
+                .expect("a height push fits a B0255"),
+            coinbase_tx_input_sequence: u32::MAX,
+            coinbase_tx_value_remaining: config.coinbase_value,
+            coinbase_tx_outputs_count: 0,
+            coinbase_tx_outputs: Vec::new().try_into().expect("empty B064K"),
+            coinbase_tx_locktime: 0,
+            merkle_path: Seq0255::new(Vec::new()).expect("empty Seq0255"),
+        }
+    }
+}
+
+/// A Template Provider serving synthetic templates, stopped by [`MockTemplateProvider::stop`].
+#[derive(Clone)]
+pub struct MockTemplateProvider {
+    shared: Arc<Shared>,
+    local_address: SocketAddr,
+    task_manager: Arc<TaskManager>,
+}
+
+impl MockTemplateProvider {
+    /// Binds the listening address of `config` and starts serving templates.
+    pub async fn start(config: MockTpConfig) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(config.listen_address).await?;
+        let local_address = listener.local_addr()?;
+        let schedule = config.schedule.clone();
+        let shared = Arc::new(Shared {
+            config,
+            chain: Mutex::new(Chain::default()),
+            updates: broadcast::channel(UPDATES_CAPACITY).0,
+            solutions: Mutex::new(Vec::new()),
+            downstream_ready: Notify::new(),
+        });
+        let task_manager = Arc::new(TaskManager::new());
+        info!(
+            "Mock Template Provider listening on {local_address}, authority public key {}",
+            shared.config.authority_public_key
+        );
+
+        task_manager.spawn_named("mock_tp_schedule", run_schedule(shared.clone(), schedule));
+        let accept_shared = shared.clone();
+        let accept_task_manager = task_manager.clone();
+        task_manager.spawn_named("mock_tp_accept", async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => accept_task_manager.spawn_named(
+                        "mock_tp_connection",
+                        serve(accept_shared.clone(), stream, peer),
+                    ),
+                    Err(e) => warn!("Failed to accept a connection: {e}"),
+                }
+            }
+        });
+
+        Ok(Self {
+            shared,
+            local_address,
+            task_manager,
+        })
+    }
+
+    /// Address the mock listens on.
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Public key the downstreams may authenticate the mock with.
+    pub fn authority_public_key(&self) -> Secp256k1PublicKey {
+        self.shared.config.authority_public_key
+    }
+
+    /// Moves the chain to a new block.
+    pub fn new_block(&self) {
+        self.shared.new_block()
+    }
+
+    /// Sends a new template for the current block.
+    pub fn new_template(&self) {
+        self.shared.new_template()
+    }
+
+    /// Returns the solutions received so far, oldest first.
+    pub fn solutions(&self) -> Vec<Solution> {
+        self.shared
+            .solutions
+            .super_safe_lock(|solutions| solutions.clone())
+    }
+
+    /// Stops listening and closes every connection.
+    pub async fn stop(&self) {
+        self.task_manager.abort_all().await;
+    }
+}
+
+impl fmt::Debug for MockTemplateProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockTemplateProvider")
+            .field("local_address", &self.local_address)
+            .finish_non_exhaustive()
+    }
+}
+
+async fn run_schedule(shared: Arc<Shared>, schedule: Schedule) {
+    match schedule {
+        Schedule::Periodic {
+            interval,
+            new_block_every,
+        } => {
+            shared.new_block();
+            let mut templates_left = new_block_every;
+            loop {
+                tokio::time::sleep(interval).await;
+                templates_left = templates_left.saturating_sub(1);
+                if templates_left == 0 {
+                    shared.new_block();
+                    templates_left = new_block_every;
+                } else {
+                    shared.new_template();
+                }
+            }
+        }
+        Schedule::Scripted(steps) => {
+            shared.downstream_ready.notified().await;
+            for step in steps {
+                match step {
+                    Step::NewBlock => shared.new_block(),
+                    Step::NewTemplate => shared.new_template(),
+                    Step::Wait(duration) => tokio::time::sleep(duration).await,
+                    Step::Delay(delay) => shared.publish(Update::Delay(delay)),
+                    Step::Disconnect => shared.publish(Update::Disconnect),
+                    Step::MalformedFrame => shared.publish(Update::MalformedFrame),
+                }
+            }
+        }
+    }
+}
+
+async fn serve(shared: Arc<Shared>, stream: TcpStream, peer: SocketAddr) {
+    let config = &shared.config;
+    let responder = match Responder::from_authority_kp(
+        &config.authority_public_key.into_bytes(),
+        &config.authority_secret_key.into_bytes(),
+        Duration::from_secs(3600),
+    ) {
+        Ok(responder) => responder,
+        Err(e) => {
+            warn!("Invalid authority keypair: {e:?}");
+            return;
+        }
+    };
+    let stream =
+        match NoiseTcpStream::<Message>::new(stream, HandshakeRole::Responder(responder)).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Handshake with {peer} failed: {e:?}");
+                return;
+            }
+        };
+    info!("Downstream {peer} connected");
+    let (mut reader, writer) = stream.into_split();
+    // Reading is not cancellation safe, so frames are read on their own task.
+    let (frames_tx, frames_rx) = async_channel::bounded(16);
+    let reader_task = tokio::spawn(async move {
+        while let Ok(frame) = reader.read_frame().await {
+            if frames_tx.send(frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut downstream = Downstream {
+        shared: shared.clone(),
+        writer,
+        delay: Duration::ZERO,
+        updates: None,
+    };
+    let result = loop {
+        let next = match downstream.updates.as_mut() {
+            Some(updates) => tokio::select! {
+                frame = frames_rx.recv() => Next::Frame(frame.ok()),
+                update = updates.recv() => Next::Update(update),
+            },
+            None => Next::Frame(frames_rx.recv().await.ok()),
+        };
+        let step = match next {
+            Next::Frame(Some(frame)) => downstream.handle_frame(frame).await,
+            Next::Frame(None) => break Ok(()),
+            Next::Update(Ok(update)) => downstream.apply(update).await,
+            Next::Update(Err(broadcast::error::RecvError::Lagged(missed))) => {
+                warn!("Downstream {peer} missed {missed} updates");
+                Ok(true)
+            }
+            Next::Update(Err(broadcast::error::RecvError::Closed)) => break Ok(()),
+        };
+        match step {
+            Ok(true) => continue,
+            Ok(false) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    reader_task.abort();
+    let _ = downstream.writer.shutdown().await;
+    match result {
+        Ok(()) => info!("Downstream {peer} disconnected"),
+        Err(e) => warn!("Downstream {peer} disconnected: {e:?}"),
+    }
+}
+
+enum Next {
+    Frame(Option<EitherFrame>),
+    Update(Result<Update, broadcast::error::RecvError>),
+}
+
+struct Downstream {
+    shared: Arc<Shared>,
+    writer: NoiseTcpWriteHalf<Message>,
+    delay: Duration,
+    // Set once the downstream sent its coinbase output constraints.
+    updates: Option<broadcast::Receiver<Update>>,
+}
+
+impl Downstream {
+    // Handles a frame of the downstream, returns whether to keep the connection open.
+    async fn handle_frame(&mut self, frame: EitherFrame) -> Result<bool, Error> {
+        let Frame::Sv2(mut frame) = frame else {
+            return Err(Error::HandshakeRemoteInvalidMessage);
+        };
+        let Some(header) = frame.get_header() else {
+            return Err(Error::HandshakeRemoteInvalidMessage);
+        };
+        let message_type = header.msg_type();
+        let message = match AnyMessage::try_from((message_type, frame.payload())) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Ignoring undecodable message of type {message_type:#x}: {e:?}");
+                return Ok(true);
+            }
+        };
+        match message {
+            AnyMessage::Common(CommonMessages::SetupConnection(setup)) => {
+                if setup.protocol != Protocol::TemplateDistributionProtocol {
+                    let error = SetupConnectionError {
+                        flags: 0,
+                        error_code: "unsupported-protocol"
+                            .to_string()
+                            .try_into()
+                            .expect("a short error code fits a Str0255"),
+                    };
+                    self.send(AnyMessage::Common(CommonMessages::SetupConnectionError(
+                        error,
+                    )))
+                    .await?;
+                    return Ok(false);
+                }
+                let success = SetupConnectionSuccess {
+                    used_version: 2,
+                    flags: 0,
+                };
+                self.send(AnyMessage::Common(CommonMessages::SetupConnectionSuccess(
+                    success,
+                )))
+                .await?;
+            }
+            AnyMessage::TemplateDistribution(TemplateDistribution::CoinbaseOutputConstraints(
+                constraints,
+            )) => {
+                debug!(
+                    "Coinbase output constraints: {} bytes, {} sigops",
+                    constraints.coinbase_output_max_additional_size,
+                    constraints.coinbase_output_max_additional_sigops
+                );
+                if self.updates.is_none() {
+                    let (current, updates) = self.shared.subscribe();
+                    self.updates = Some(updates);
+                    self.shared.downstream_ready.notify_one();
+                    for update in current {
+                        self.apply(update).await?;
+                    }
+                }
+            }
+            AnyMessage::TemplateDistribution(TemplateDistribution::RequestTransactionData(
+                request,
+            )) => {
+                let success = RequestTransactionDataSuccess {
+                    template_id: request.template_id,
+                    excess_data: Vec::new().try_into().expect("empty B064K"),
+                    transaction_list: Seq064K::new(Vec::new()).expect("empty Seq064K"),
+                };
+                self.send(AnyMessage::TemplateDistribution(
+                    TemplateDistribution::RequestTransactionDataSuccess(success),
+                ))
+                .await?;
+            }
+            AnyMessage::TemplateDistribution(TemplateDistribution::SubmitSolution(solution)) => {
+                let solution = Solution {
+                    template_id: solution.template_id,
+                    version: solution.version,
+                    header_timestamp: solution.header_timestamp,
+                    header_nonce: solution.header_nonce,
+                    coinbase_tx: solution.coinbase_tx.inner_as_ref().to_vec(),
+                };
+                info!("Solution received for template {}", solution.template_id);
+                self.shared
+                    .solutions
+                    .super_safe_lock(|solutions| solutions.push(solution));
+                self.shared.new_block();
+            }
+            message => warn!("Ignoring unexpected message: {message:?}"),
+        }
+        Ok(true)
+    }
+
+    // Applies an update to the connection, returns whether to keep it open.
+    async fn apply(&mut self, update: Update) -> Result<bool, Error> {
+        match update {
+            Update::Message(message) => self.send(message).await?,
+            Update::Delay(delay) => self.delay = delay,
+            Update::Disconnect => return Ok(false),
+            Update::MalformedFrame => {
+                // A `NewTemplate` header announcing 3 bytes, too short for any template.
+                let bytes = vec![0, 0, MESSAGE_TYPE_NEW_TEMPLATE, 3, 0, 0, 0xff, 0xff, 0xff];
+                let frame = Sv2Frame::from_bytes_unchecked(buffer_sv2::Slice::from(bytes));
+                self.write(Frame::Sv2(frame)).await?;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn send(&mut self, message: Message) -> Result<(), Error> {
+        let frame: Sv2Frame<Message, buffer_sv2::Slice> = message
+            .try_into()
+            .expect("synthetic messages always fit a frame");
+        self.write(Frame::Sv2(frame)).await
+    }
+
+    async fn write(&mut self, frame: EitherFrame) -> Result<(), Error> {
+        if !self.delay.is_zero() {
+            tokio::time::sleep(self.delay).await;
+        }
+        self.writer.write_frame(frame).await
+    }
+}
+
+/// Returns the little endian target encoded by `n_bits`.
+pub fn target_from_compact(n_bits: u32) -> [u8; 32] {
+    let exponent = (n_bits >> 24) as usize;
+    let mantissa = (n_bits & 0x007f_ffff).to_le_bytes();
+    let mut target = [0u8; 32];
+    for (i, byte) in mantissa.iter().take(3).enumerate() {
+        if let Some(position) = (exponent + i).checked_sub(3) {
+            if position < 32 {
+                target[position] = *byte;
+            }
+        }
+    }
+    target
+}
+
+// BIP34 push of the height, as the scriptSig of a coinbase starts with.
+fn bip34_height_push(height: u32) -> Vec<u8> {
+    let mut number: Vec<u8> = height.to_le_bytes().into_iter().collect();
+    while number.len() > 1 && number.last() == Some(&0) {
+        number.pop();
+    }
+    // Keep the number positive.
+    if number.last().is_some_and(|byte| byte & 0x80 != 0) {
+        number.push(0);
+    }
+    let mut push = vec![number.len() as u8];
+    push.extend(number);
+    push
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_helpers::noise_stream::NoiseTcpReadHalf;
+    use stratum_core::{
+        common_messages_sv2::SetupConnection,
+        noise_sv2::Initiator,
+        template_distribution_sv2::{CoinbaseOutputConstraints, SubmitSolution},
+    };
+
+    #[test]
+    fn regtest_target() {
+        let mut expected = [0u8; 32];
+        expected[29] = 0xff;
+        expected[30] = 0xff;
+        expected[31] = 0x7f;
+        assert_eq!(target_from_compact(REGTEST_N_BITS), expected);
+    }
+
+    #[test]
+    fn height_pushes() {
+        assert_eq!(bip34_height_push(1), vec![1, 1]);
+        assert_eq!(bip34_height_push(128), vec![2, 0x80, 0]);
+        assert_eq!(bip34_height_push(840_000), vec![3, 0x40, 0xd1, 0x0c]);
+    }
+
+    #[test]
+    fn steps_parse() {
+        let steps: Vec<Step> =
+            "new-block, wait=500,delay=20,new-template,malformed-frame,disconnect"
+                .split(',')
+                .map(|step| step.parse().unwrap())
+                .collect();
+        assert_eq!(
+            steps,
+            vec![
+                Step::NewBlock,
+                Step::Wait(Duration::from_millis(500)),
+                Step::Delay(Duration::from_millis(20)),
+                Step::NewTemplate,
+                Step::MalformedFrame,
+                Step::Disconnect,
+            ]
+        );
+        assert!("wait=soon".parse::<Step>().is_err());
+        assert!("mine".parse::<Step>().is_err());
+    }
+
+    async fn next_message(reader: &mut NoiseTcpReadHalf<Message>) -> (u8, Vec<u8>) {
+        let Frame::Sv2(mut frame) = reader.read_frame().await.unwrap() else {
+            panic!("unexpected handshake frame");
+        };
+        let message_type = frame.get_header().unwrap().msg_type();
+        (message_type, frame.payload().to_vec())
+    }
+
+    #[tokio::test]
+    async fn serves_templates_and_records_solutions() {
+        let config = MockTpConfig::new(
+            "127.0.0.1:0".parse().unwrap(),
+            Schedule::Scripted(vec![Step::NewBlock]),
+        );
+        let mock = MockTemplateProvider::start(config).await.unwrap();
+        let stream = TcpStream::connect(mock.local_address()).await.unwrap();
+        let initiator = Initiator::without_pk().unwrap();
+        let (mut reader, mut writer) =
+            NoiseTcpStream::<Message>::new(stream, HandshakeRole::Initiator(initiator))
+                .await
+                .unwrap()
+                .into_split();
+
+        let setup = SetupConnection {
+            protocol: Protocol::TemplateDistributionProtocol,
+            min_version: 2,
+            max_version: 2,
+            flags: 0,
+            endpoint_host: b"127.0.0.1".to_vec().try_into().unwrap(),
+            endpoint_port: 8442,
+            vendor: b"test".to_vec().try_into().unwrap(),
+            hardware_version: b"".to_vec().try_into().unwrap(),
+            firmware: b"".to_vec().try_into().unwrap(),
+            device_id: b"".to_vec().try_into().unwrap(),
+        };
+        let constraints = CoinbaseOutputConstraints {
+            coinbase_output_max_additional_size: 100,
+            coinbase_output_max_additional_sigops: 1,
+        };
+        for message in [
+            AnyMessage::Common(CommonMessages::SetupConnection(setup)),
+            AnyMessage::TemplateDistribution(TemplateDistribution::CoinbaseOutputConstraints(
+                constraints,
+            )),
+        ] {
+            let frame: Sv2Frame<Message, buffer_sv2::Slice> = message.try_into().unwrap();
+            writer.write_frame(Frame::Sv2(frame)).await.unwrap();
+        }
+
+        let mut messages = Vec::new();
+        for _ in 0..3 {
+            let (message_type, mut payload) = next_message(&mut reader).await;
+            let message = AnyMessage::try_from((message_type, payload.as_mut_slice())).unwrap();
+            messages.push(match message {
+                AnyMessage::Common(CommonMessages::SetupConnectionSuccess(_)) => "success",
+                AnyMessage::TemplateDistribution(TemplateDistribution::NewTemplate(template)) => {
+                    assert!(template.future_template);
+                    assert_eq!(template.coinbase_tx_value_remaining, DEFAULT_COINBASE_VALUE);
+                    "template"
+                }
+                AnyMessage::TemplateDistribution(TemplateDistribution::SetNewPrevHash(prev)) => {
+                    assert_eq!(prev.template_id, 1);
+                    "prev_hash"
+                }
+                message => panic!("unexpected message {message:?}"),
+            });
+        }
+        assert_eq!(messages, vec!["success", "template", "prev_hash"]);
+
+        let solution = SubmitSolution {
+            template_id: 1,
+            version: 0x2000_0000,
+            header_timestamp: 1,
+            header_nonce: 42,
+            coinbase_tx: vec![1, 2, 3].try_into().unwrap(),
+        };
+        let message =
+            AnyMessage::TemplateDistribution(TemplateDistribution::SubmitSolution(solution));
+        let frame: Sv2Frame<Message, buffer_sv2::Slice> = message.try_into().unwrap();
+        writer.write_frame(Frame::Sv2(frame)).await.unwrap();
+        // The solution moves the chain to a new block, with a template 2.
+        let (message_type, _) = next_message(&mut reader).await;
+        assert_eq!(message_type, MESSAGE_TYPE_NEW_TEMPLATE);
+        let solutions = mock.solutions();
+        assert_eq!(solutions.len(), 1);
+        assert_eq!(solutions[0].header_nonce, 42);
+        assert_eq!(solutions[0].coinbase_tx, vec![1, 2, 3]);
+        mock.stop().await;
+    }
+}
@@ -0,0 +1,739 @@
+//! HTTP monitoring endpoint
+//!
+//! Roles expose their runtime state by implementing [`StatusProvider`] and running [`serve`] on
+//! the address configured through a [`MonitoringConfig`], usually embedded in the role
+//! configuration as an optional `[monitoring]` table:
+//!
+//! ```toml
+//! [monitoring]
+//! listen_address = "127.0.0.1:9090"
+//! ```
+//!
+//! `GET /` and `GET /status` return the JSON document built by the provider.
+//!
+//! [`spawn`] runs the endpoint as a task of the role, restarted if it fails.
+//!
+//! `GET /healthz` and `GET /readyz` answer liveness and readiness probes (e.g. from Kubernetes)
+//! with the [`HealthReport`] of the provider, listing its components and readiness conditions:
+//! `200 OK` when the role is live (respectively ready), `503 Service Unavailable` otherwise.
+//!
+//! Roles whose provider returns a [`BanList`] also manage it on `/bans`: `GET` lists the bans in
+//! effect, `POST /bans?target=<target>[&ttl_secs=<secs>][&reason=<reason>]` bans an IP address, a
+//! CIDR range or a public key, and `DELETE /bans?target=<target>` lifts a ban.
+//!
+//! Roles whose provider returns payout batches serve them on
+//! `GET /payouts[?format=csv|json][&round=<round>]`, JSON by default.
+//!
+//! Roles whose provider returns the statistics of their workers serve them per account on
+//! `GET /workers`, and those of a single account on `GET /workers?account=<account>`.
+//!
+//! `GET /history[?level=error][&limit=<n>]` returns the last warnings and errors logged by the
+//! process (`logs`) and the last [transitions](HealthTransition) of the health of the components
+//! of the role (`health`), oldest first: only the errors with `level=error`, and only the `n` most
+//! recent of each with `limit`.
+//!
+//! The endpoint has no authentication, so it must only listen on a trusted interface.
+
+use http_body_util::Full;
+use hyper::{
+    body::{Bytes, Incoming},
+    header::CONTENT_TYPE,
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use std::{
+    borrow::Cow, convert::Infallible, future::Future, net::SocketAddr, sync::Arc, time::Duration,
+};
+use tokio::{net::TcpListener, sync::broadcast};
+use tracing::{debug, info, warn};
+
+#[cfg(feature = "persistence")]
+use crate::persistence::{PayoutBatch, PayoutFormat};
+use crate::{
+    banlist::{Ban, BanList, BanTarget},
+    history::{log_history, LogRecord},
+    runtime::{RestartPolicy, TaskManager},
+    status::{HealthAggregator, HealthReport, HealthTransition},
+    unix_time,
+};
+
+/// Restarts of the endpoint spawned by [`spawn`], e.g. while its address is still in use.
+pub const RESTART_POLICY: RestartPolicy = RestartPolicy {
+    max_retries: 5,
+    initial_backoff: Duration::from_secs(1),
+    max_backoff: Duration::from_secs(30),
+};
+
+/// Monitoring endpoint configuration.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MonitoringConfig {
+    /// Address the HTTP server listens on.
+    pub listen_address: SocketAddr,
+}
+
+impl MonitoringConfig {
+    /// Creates a new [`MonitoringConfig`].
+    pub fn new(listen_address: SocketAddr) -> Self {
+        Self { listen_address }
+    }
+}
+
+/// Source of the document served by the monitoring endpoint.
+///
+/// Called on every request, implementations should only take short-lived locks.
+pub trait StatusProvider: Send + Sync + 'static {
+    /// Returns the current state of the role.
+    fn status(&self) -> serde_json::Value;
+
+    /// Returns the health of the role. Always live and ready by default.
+    fn health(&self) -> HealthReport {
+        HealthReport::default()
+    }
+
+    /// Returns the ban list managed on `/bans`, which is not served if `None` (the default).
+    fn banlist(&self) -> Option<&BanList> {
+        None
+    }
+
+    /// Returns the payout batches served on `/payouts`, which is not served if `None` (the
+    /// default).
+    #[cfg(feature = "persistence")]
+    fn payouts(&self) -> Option<Result<Vec<PayoutBatch>, String>> {
+        None
+    }
+
+    /// Returns the statistics of the workers served on `/workers`, as an object keyed by
+    /// account, which is not served if `None` (the default).
+    fn workers(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Returns the transitions of the health of the components served on `/history`, none by
+    /// default.
+    fn health_transitions(&self) -> Vec<HealthTransition> {
+        Vec::new()
+    }
+}
+
+/// Serves the health report as status, for roles without a richer status document.
+impl StatusProvider for HealthAggregator {
+    fn status(&self) -> serde_json::Value {
+        serde_json::to_value(self.snapshot()).expect("health report is always serializable")
+    }
+
+    fn health(&self) -> HealthReport {
+        self.snapshot()
+    }
+
+    fn health_transitions(&self) -> Vec<HealthTransition> {
+        self.transitions()
+    }
+}
+
+/// Adds the management of a [`BanList`] to the endpoint of another provider.
+pub struct WithBanList<P: ?Sized> {
+    banlist: Arc<BanList>,
+    provider: Arc<P>,
+}
+
+impl<P: ?Sized> WithBanList<P> {
+    /// Creates a provider serving the status and health of `provider` and managing `banlist`.
+    pub fn new(provider: Arc<P>, banlist: Arc<BanList>) -> Self {
+        Self { banlist, provider }
+    }
+}
+
+impl<P: StatusProvider + ?Sized> StatusProvider for WithBanList<P> {
+    fn status(&self) -> serde_json::Value {
+        self.provider.status()
+    }
+
+    fn health(&self) -> HealthReport {
+        self.provider.health()
+    }
+
+    fn banlist(&self) -> Option<&BanList> {
+        Some(&self.banlist)
+    }
+
+    #[cfg(feature = "persistence")]
+    fn payouts(&self) -> Option<Result<Vec<PayoutBatch>, String>> {
+        self.provider.payouts()
+    }
+
+    fn workers(&self) -> Option<serde_json::Value> {
+        self.provider.workers()
+    }
+
+    fn health_transitions(&self) -> Vec<HealthTransition> {
+        self.provider.health_transitions()
+    }
+}
+
+/// Serves the monitoring endpoint on `config.listen_address` until `shutdown` completes.
+///
+/// Returns an error if the address cannot be bound.
+pub async fn serve<P, F>(
+    config: &MonitoringConfig,
+    provider: Arc<P>,
+    shutdown: F,
+) -> Result<(), std::io::Error>
+where
+    P: StatusProvider + ?Sized,
+    F: Future<Output = ()>,
+{
+    let listener = TcpListener::bind(config.listen_address).await?;
+    info!(
+        "Monitoring endpoint listening on http://{}",
+        config.listen_address
+    );
+
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => {
+                debug!("Monitoring endpoint shutting down");
+                return Ok(());
+            }
+            accepted = listener.accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Monitoring endpoint failed to accept connection: {e}");
+                        continue;
+                    }
+                };
+                let provider = provider.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| {
+                        let provider = provider.clone();
+                        async move { Ok::<_, Infallible>(respond(&request, provider.as_ref())) }
+                    });
+                    if let Err(e) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                    {
+                        debug!("Monitoring connection with {peer} failed: {e}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Spawns the endpoint as the `monitoring` task of `task_manager`, restarted according to
+/// [`RESTART_POLICY`] if it fails. The role keeps running without it once the retries are
+/// exhausted.
+///
+/// The endpoint stops on the first message received on `shutdown_rx` matching `stops_on`, or
+/// once the shutdown channel is closed.
+pub fn spawn<P, M>(
+    task_manager: &TaskManager,
+    config: MonitoringConfig,
+    provider: Arc<P>,
+    shutdown_rx: broadcast::Receiver<M>,
+    stops_on: fn(&M) -> bool,
+) where
+    P: StatusProvider + ?Sized,
+    M: Clone + Send + 'static,
+{
+    // Shared by the successive runs, so a shutdown sent while restarting is not missed
+    let shutdown_rx = Arc::new(tokio::sync::Mutex::new(shutdown_rx));
+    task_manager.spawn_restart_on_failure("monitoring", RESTART_POLICY, move || {
+        let config = config.clone();
+        let provider = provider.clone();
+        let shutdown_rx = shutdown_rx.clone();
+        async move {
+            let shutdown = async move {
+                let mut shutdown_rx = shutdown_rx.lock().await;
+                loop {
+                    match shutdown_rx.recv().await {
+                        Ok(message) if stops_on(&message) => break,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    }
+                }
+            };
+            serve(&config, provider, shutdown).await
+        }
+    });
+}
+
+fn respond<P: StatusProvider + ?Sized>(
+    request: &Request<Incoming>,
+    provider: &P,
+) -> Response<Full<Bytes>> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/") | (&Method::GET, "/status") => {
+            json_response(StatusCode::OK, &provider.status())
+        }
+        (&Method::GET, "/healthz") => {
+            let health = provider.health();
+            probe_response(health.is_live(), &health)
+        }
+        (&Method::GET, "/readyz") => {
+            let health = provider.health();
+            probe_response(health.is_ready(), &health)
+        }
+        (method, "/bans") if provider.banlist().is_some() => bans_response(
+            method,
+            request.uri().query().unwrap_or_default(),
+            provider.banlist().expect("checked by the guard"),
+        ),
+        #[cfg(feature = "persistence")]
+        (&Method::GET, "/payouts") => match provider.payouts() {
+            Some(batches) => payouts_response(request.uri().query().unwrap_or_default(), batches),
+            None => error_response(StatusCode::NOT_FOUND, "not found"),
+        },
+        (&Method::GET, "/workers") => match provider.workers() {
+            Some(accounts) => workers_response(request.uri().query().unwrap_or_default(), accounts),
+            None => error_response(StatusCode::NOT_FOUND, "not found"),
+        },
+        (&Method::GET, "/history") => history_response(
+            request.uri().query().unwrap_or_default(),
+            log_history().snapshot(),
+            provider.health_transitions(),
+        ),
+        (_, "/") | (_, "/status") | (_, "/healthz") | (_, "/readyz") | (_, "/history") => {
+            json_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                &serde_json::json!({ "error": "method not allowed" }),
+            )
+        }
+        _ => json_response(
+            StatusCode::NOT_FOUND,
+            &serde_json::json!({ "error": "not found" }),
+        ),
+    }
+}
+
+fn bans_response(method: &Method, query: &str, banlist: &BanList) -> Response<Full<Bytes>> {
+    if method == Method::GET {
+        let bans: Vec<_> = banlist.list().iter().map(ban_json).collect();
+        return json_response(StatusCode::OK, &serde_json::json!({ "bans": bans }));
+    }
+    if method != Method::POST && method != Method::DELETE {
+        return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+    let target = match query_param(query, "target").map(|target| target.parse::<BanTarget>()) {
+        Some(Ok(target)) => target,
+        Some(Err(e)) => return error_response(StatusCode::BAD_REQUEST, &e),
+        None => return error_response(StatusCode::BAD_REQUEST, "missing `target` parameter"),
+    };
+    if method == Method::DELETE {
+        return match banlist.unban(&target) {
+            Ok(true) => {
+                info!("Lifted the ban of {target}");
+                json_response(
+                    StatusCode::OK,
+                    &serde_json::json!({ "removed": target.to_string() }),
+                )
+            }
+            Ok(false) => {
+                error_response(StatusCode::NOT_FOUND, &format!("`{target}` is not banned"))
+            }
+            Err(e) => error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("ban lifted but the ban list could not be saved: {e}"),
+            ),
+        };
+    }
+    let ttl = match query_param(query, "ttl_secs").map(|ttl| ttl.parse::<u64>()) {
+        Some(Ok(secs)) => Some(Duration::from_secs(secs)),
+        Some(Err(_)) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "`ttl_secs` must be a number of seconds",
+            )
+        }
+        None => None,
+    };
+    match banlist.ban(
+        target,
+        ttl,
+        query_param(query, "reason").map(Cow::into_owned),
+    ) {
+        Ok(ban) => {
+            info!(
+                expires_at = ?ban.expires_at.map(unix_time::secs),
+                "Banned {target}"
+            );
+            json_response(StatusCode::OK, &ban_json(&ban))
+        }
+        Err(e) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("ban in effect but the ban list could not be saved: {e}"),
+        ),
+    }
+}
+
+#[cfg(feature = "persistence")]
+fn payouts_response(
+    query: &str,
+    batches: Result<Vec<PayoutBatch>, String>,
+) -> Response<Full<Bytes>> {
+    let format = match query_param(query, "format").map(|format| format.parse::<PayoutFormat>()) {
+        Some(Ok(format)) => format,
+        Some(Err(e)) => return error_response(StatusCode::BAD_REQUEST, &e),
+        None => PayoutFormat::Json,
+    };
+    let round = match query_param(query, "round").map(|round| round.parse::<u64>()) {
+        Some(Ok(round)) => Some(round),
+        Some(Err(_)) => {
+            return error_response(StatusCode::BAD_REQUEST, "`round` must be a round number")
+        }
+        None => None,
+    };
+    let mut batches = match batches {
+        Ok(batches) => batches,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    };
+    if let Some(round) = round {
+        batches.retain(|batch| batch.round == round);
+    }
+    let mut response = Response::new(Full::new(Bytes::from(format.render(&batches))));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, format.content_type().parse().unwrap());
+    response
+}
+
+fn workers_response(query: &str, mut accounts: serde_json::Value) -> Response<Full<Bytes>> {
+    let Some(account) = query_param(query, "account") else {
+        return json_response(StatusCode::OK, &accounts);
+    };
+    match accounts.get_mut(account.as_ref()) {
+        Some(workers) => json_response(StatusCode::OK, &workers.take()),
+        None => error_response(
+            StatusCode::NOT_FOUND,
+            &format!("unknown account `{account}`"),
+        ),
+    }
+}
+
+fn history_response(
+    query: &str,
+    mut logs: Vec<LogRecord>,
+    mut health: Vec<HealthTransition>,
+) -> Response<Full<Bytes>> {
+    match query_param(query, "level").as_deref() {
+        Some("error") => logs.retain(LogRecord::is_error),
+        Some("warn") | None => {}
+        Some(_) => {
+            return error_response(StatusCode::BAD_REQUEST, "`level` must be `warn` or `error`")
+        }
+    }
+    match query_param(query, "limit").map(|limit| limit.parse::<usize>()) {
+        Some(Ok(limit)) => {
+            logs.drain(..logs.len().saturating_sub(limit));
+            health.drain(..health.len().saturating_sub(limit));
+        }
+        Some(Err(_)) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "`limit` must be a number of records",
+            )
+        }
+        None => {}
+    }
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({ "logs": logs, "health": health }),
+    )
+}
+
+/// JSON of `ban`, as listed on `/bans` and by the control services: its target, when it expires
+/// (Unix time in seconds, `null` if permanent) and its reason.
+pub fn ban_json(ban: &Ban) -> serde_json::Value {
+    serde_json::json!({
+        "target": ban.target.to_string(),
+        "expires_at": ban.expires_at.map(unix_time::secs),
+        "reason": ban.reason,
+    })
+}
+
+/// Returns the percent-decoded value of the `name` parameter of `query`.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<Cow<'a, str>> {
+    let value = query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (key == name).then_some(value)
+    })?;
+    if !value.contains(['%', '+']) {
+        return Some(Cow::Borrowed(value));
+    }
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let digits = [input.next()?, input.next()?];
+                let digits = std::str::from_utf8(&digits).ok()?;
+                bytes.push(u8::from_str_radix(digits, 16).ok()?);
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    Some(Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+fn error_response(status: StatusCode, error: &str) -> Response<Full<Bytes>> {
+    json_response(status, &serde_json::json!({ "error": error }))
+}
+
+fn probe_response(ok: bool, health: &HealthReport) -> Response<Full<Bytes>> {
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    json_response(
+        status,
+        &serde_json::to_value(health).expect("health report is always serializable"),
+    )
+}
+
+fn json_response(status: StatusCode, body: &serde_json::Value) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tracing::Level;
+
+    struct StaticStatus;
+
+    impl StatusProvider for StaticStatus {
+        fn status(&self) -> serde_json::Value {
+            serde_json::json!({ "miners": 2 })
+        }
+    }
+
+    fn log(level: Level, message: &str) -> LogRecord {
+        LogRecord {
+            at_ms: 0,
+            level: level.as_str().to_string(),
+            target: "pool_sv2".to_string(),
+            spans: Vec::new(),
+            message: message.to_string(),
+            fields: Default::default(),
+        }
+    }
+
+    #[test]
+    fn serves_the_recent_history() {
+        let logs = vec![
+            log(Level::ERROR, "Connection lost"),
+            log(Level::WARN, "Share rejected"),
+            log(Level::ERROR, "Channel closed"),
+        ];
+        let health = HealthAggregator::new();
+        health.healthy("upstream");
+        health.degraded("upstream", "reconnecting");
+
+        let body = |response: Response<Full<Bytes>>| {
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = futures::executor::block_on(response.into_body().collect()).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body.to_bytes()).unwrap()
+        };
+        let all = body(history_response("", logs.clone(), health.transitions()));
+        assert_eq!(all["logs"].as_array().unwrap().len(), 3);
+        assert_eq!(all["health"][1]["to"], "degraded");
+
+        let errors = body(history_response(
+            "level=error&limit=1",
+            logs.clone(),
+            health.transitions(),
+        ));
+        assert_eq!(errors["logs"].as_array().unwrap().len(), 1);
+        assert_eq!(errors["logs"][0]["message"], "Channel closed");
+        assert_eq!(errors["health"][0]["reason"], "reconnecting");
+
+        assert_eq!(
+            history_response("level=info", logs, Vec::new()).status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn serves_the_workers_of_an_account() {
+        let accounts = serde_json::json!({
+            "alice": { "hashrate": 1e12, "workers": [{ "worker": "rig1" }] },
+            "bob": { "hashrate": 2e12, "workers": [] },
+        });
+        assert_eq!(
+            workers_response("", accounts.clone()).status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            workers_response("account=alice", accounts.clone()).status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            workers_response("account=carol", accounts).status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    async fn get(address: SocketAddr, path: &str) -> String {
+        send(address, "GET", path).await
+    }
+
+    async fn send(address: SocketAddr, method: &str, path: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let request =
+            format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_provider_status() {
+        // Reserve a free port, then release it for the server.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = MonitoringConfig::new(address);
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve(&config, Arc::new(StaticStatus), async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+        });
+
+        let mut response = String::new();
+        for _ in 0..50 {
+            if let Ok(stream) = tokio::net::TcpStream::connect(address).await {
+                drop(stream);
+                response = get(address, "/status").await;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with(r#"{"miners":2}"#), "{response}");
+
+        let not_found = get(address, "/nope").await;
+        assert!(not_found.starts_with("HTTP/1.1 404"), "{not_found}");
+
+        // No health tracked: always live and ready
+        let ready = get(address, "/readyz").await;
+        assert!(ready.starts_with("HTTP/1.1 200 OK"), "{ready}");
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn probes_reflect_health() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = MonitoringConfig::new(address);
+        let health = Arc::new(HealthAggregator::new());
+        health.healthy("upstream");
+        let provider = health.clone();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve(&config, provider, async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+        });
+
+        let mut live = String::new();
+        for _ in 0..50 {
+            if let Ok(stream) = tokio::net::TcpStream::connect(address).await {
+                drop(stream);
+                live = get(address, "/healthz").await;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(live.starts_with("HTTP/1.1 200 OK"), "{live}");
+        let ready = get(address, "/readyz").await;
+        assert!(ready.starts_with("HTTP/1.1 503"), "{ready}");
+
+        health.set_ready(true);
+        let ready = get(address, "/readyz").await;
+        assert!(ready.starts_with("HTTP/1.1 200 OK"), "{ready}");
+
+        health.failed("upstream", "connection lost");
+        let live = get(address, "/healthz").await;
+        assert!(live.starts_with("HTTP/1.1 503"), "{live}");
+        assert!(live.contains(r#""state":"failed""#), "{live}");
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn manages_bans() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = MonitoringConfig::new(address);
+        let banlist = Arc::new(BanList::new(&Default::default()).unwrap());
+        let provider = Arc::new(WithBanList::new(Arc::new(StaticStatus), banlist.clone()));
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve(&config, provider, async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+        });
+
+        let mut banned = String::new();
+        for _ in 0..50 {
+            if let Ok(stream) = tokio::net::TcpStream::connect(address).await {
+                drop(stream);
+                banned = send(
+                    address,
+                    "POST",
+                    "/bans?target=198.51.100.0%2F24&ttl_secs=600&reason=too+many+shares",
+                )
+                .await;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(banned.starts_with("HTTP/1.1 200 OK"), "{banned}");
+        assert!(banlist.is_ip_banned("198.51.100.7".parse().unwrap()));
+
+        let listed = get(address, "/bans").await;
+        assert!(listed.contains(r#""target":"198.51.100.0/24""#), "{listed}");
+        assert!(listed.contains(r#""reason":"too many shares""#), "{listed}");
+
+        let invalid = send(address, "POST", "/bans?target=nobody").await;
+        assert!(invalid.starts_with("HTTP/1.1 400"), "{invalid}");
+
+        let removed = send(address, "DELETE", "/bans?target=198.51.100.0/24").await;
+        assert!(removed.starts_with("HTTP/1.1 200 OK"), "{removed}");
+        assert!(!banlist.is_ip_banned("198.51.100.7".parse().unwrap()));
+        let missing = send(address, "DELETE", "/bans?target=198.51.100.0/24").await;
+        assert!(missing.starts_with("HTTP/1.1 404"), "{missing}");
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
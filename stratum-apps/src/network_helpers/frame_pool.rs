@@ -0,0 +1,212 @@
+//! Per-connection buffer pool for outbound frames
+//!
+//! A role broadcasting the same kind of message to many connections (jobs, new prev hashes)
+//! serializes it once per connection. A [`FramePool`] serializes the frames of a connection into
+//! the memory of a [`BufferPool`], which is handed out again once the writer is done with a frame,
+//! instead of allocating a buffer for each of them. When every slot of the pool is taken, frames
+//! fall back to system memory, which the counters of the pool tell apart.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use stratum_core::{
+    binary_sv2::{GetSize, Serialize as Sv2Serialize},
+    buffer_sv2::{Buffer, BufferFromSystemMemory, BufferPool, Slice},
+    framing_sv2::{framing::Sv2Frame, Error},
+};
+
+use crate::custom_mutex::Mutex;
+
+/// Default capacity of a [`FramePool`], in bytes, enough for the jobs of a connection with a
+/// large coinbase.
+pub const DEFAULT_FRAME_POOL_CAPACITY: usize = 64 * 1024;
+
+/// Serializes the outbound frames of a single connection into reusable memory.
+pub struct FramePool {
+    capacity: usize,
+    buffers: Mutex<BufferPool<BufferFromSystemMemory>>,
+    frames: AtomicU64,
+    allocated: AtomicU64,
+    bytes: AtomicU64,
+    // Whether the last frame found every slot of the pool taken
+    saturated: AtomicBool,
+}
+
+impl Default for FramePool {
+    fn default() -> Self {
+        Self::new(DEFAULT_FRAME_POOL_CAPACITY)
+    }
+}
+
+impl std::fmt::Debug for FramePool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FramePool")
+            .field("snapshot", &self.snapshot())
+            .finish()
+    }
+}
+
+impl FramePool {
+    /// Creates a pool of `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            buffers: Mutex::new(BufferPool::new(capacity)),
+            frames: AtomicU64::new(0),
+            allocated: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            saturated: AtomicBool::new(false),
+        }
+    }
+
+    /// Serializes `frame` into the pool, returning a frame holding the bytes to write.
+    ///
+    /// The memory goes back to the pool when the returned frame is dropped, usually by the writer
+    /// of the connection once the frame is written.
+    pub fn serialize<Message>(
+        &self,
+        frame: Sv2Frame<Message, Slice>,
+    ) -> Result<Sv2Frame<Message, Slice>, Error>
+    where
+        Message: Sv2Serialize + GetSize,
+    {
+        let length = frame.encoded_length();
+        let bytes = self.write(length, |dst| frame.serialize(dst))?;
+        Ok(Sv2Frame::from_bytes_unchecked(bytes))
+    }
+
+    /// Copies `bytes`, a frame serialized beforehand (header included), into the pool.
+    ///
+    /// Lets a frame serialized once be sent to many connections, each writing its own copy.
+    pub fn copy<Message>(&self, bytes: &[u8]) -> Sv2Frame<Message, Slice>
+    where
+        Message: Sv2Serialize + GetSize,
+    {
+        let bytes = self
+            .write(bytes.len(), |dst| {
+                dst.copy_from_slice(bytes);
+                Ok(())
+            })
+            .expect("copying bytes cannot fail");
+        Sv2Frame::from_bytes_unchecked(bytes)
+    }
+
+    // Writes `length` bytes into the pool with `write`, and returns them.
+    fn write(
+        &self,
+        length: usize,
+        write: impl FnOnce(&mut [u8]) -> Result<(), Error>,
+    ) -> Result<Slice, Error> {
+        let (bytes, saturated) = self.buffers.super_safe_lock(|buffers| {
+            write(buffers.get_writable(length))?;
+            let saturated = buffers.is_alloc_mode();
+            Ok::<_, Error>((buffers.get_data_owned(), saturated))
+        })?;
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(length as u64, Ordering::Relaxed);
+        if saturated {
+            self.allocated.fetch_add(1, Ordering::Relaxed);
+        }
+        self.saturated.store(saturated, Ordering::Relaxed);
+        Ok(bytes)
+    }
+
+    /// Returns the current counters.
+    pub fn snapshot(&self) -> FramePoolSnapshot {
+        FramePoolSnapshot {
+            capacity: self.capacity,
+            frames: self.frames.load(Ordering::Relaxed),
+            allocated: self.allocated.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            saturated: self.saturated.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point-in-time view of a [`FramePool`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FramePoolSnapshot {
+    /// Size of the pool, in bytes.
+    pub capacity: usize,
+    /// Frames serialized through the pool.
+    pub frames: u64,
+    /// Frames which found the pool full and were serialized into system memory instead.
+    pub allocated: u64,
+    /// Bytes serialized through the pool.
+    pub bytes: u64,
+    /// Whether the pool was full when the last frame was serialized.
+    pub saturated: bool,
+}
+
+impl FramePoolSnapshot {
+    /// Share of the frames served from the memory of the pool, 1 if no frame was serialized yet.
+    pub fn reuse_ratio(&self) -> f64 {
+        if self.frames == 0 {
+            return 1.0;
+        }
+        (self.frames - self.allocated) as f64 / self.frames as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_core::{
+        mining_sv2::SetTarget,
+        parsers_sv2::{AnyMessage, Mining},
+    };
+
+    fn frame(channel_id: u32) -> Sv2Frame<AnyMessage<'static>, Slice> {
+        let message = AnyMessage::Mining(Mining::SetTarget(SetTarget {
+            channel_id,
+            maximum_target: [0xff; 32].into(),
+        }));
+        message.try_into().unwrap()
+    }
+
+    #[test]
+    fn serializes_frames_byte_for_byte() {
+        let pool = FramePool::default();
+        let expected = frame(7);
+        let mut expected_bytes = vec![0; expected.encoded_length()];
+        expected.serialize(&mut expected_bytes).unwrap();
+
+        let mut pooled = pool.serialize(frame(7)).unwrap();
+        assert_eq!(pooled.encoded_length(), expected_bytes.len());
+        assert_eq!(pooled.get_header().unwrap().msg_type(), expected_bytes[2]);
+        let mut pooled_bytes = vec![0; pooled.encoded_length()];
+        pooled.serialize(&mut pooled_bytes).unwrap();
+        assert_eq!(pooled_bytes, expected_bytes);
+
+        let snapshot = pool.snapshot();
+        assert_eq!(snapshot.frames, 1);
+        assert_eq!(snapshot.bytes, expected_bytes.len() as u64);
+    }
+
+    #[test]
+    fn copies_serialized_frames() {
+        let pool = FramePool::default();
+        let expected = frame(3);
+        let mut bytes = vec![0; expected.encoded_length()];
+        expected.serialize(&mut bytes).unwrap();
+
+        let copy: Sv2Frame<AnyMessage<'static>, Slice> = pool.copy(&bytes);
+        let mut copied_bytes = vec![0; copy.encoded_length()];
+        copy.serialize(&mut copied_bytes).unwrap();
+        assert_eq!(copied_bytes, bytes);
+        assert_eq!(pool.snapshot().frames, 1);
+    }
+
+    #[test]
+    fn reuses_memory_of_written_frames() {
+        let pool = FramePool::default();
+        for channel_id in 0..1_000 {
+            // Dropped right away, as by a writer keeping up with the connection.
+            drop(pool.serialize(frame(channel_id)).unwrap());
+        }
+        let snapshot = pool.snapshot();
+        assert_eq!(snapshot.frames, 1_000);
+        assert_eq!(snapshot.allocated, 0);
+        assert_eq!(snapshot.reuse_ratio(), 1.0);
+        assert!(!snapshot.saturated);
+    }
+}
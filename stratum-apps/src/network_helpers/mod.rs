@@ -4,18 +4,63 @@
 //! for Stratum V2 applications. It includes support for:
 //!
 //! - Noise-encrypted connections ([`noise_connection`], [`noise_stream`])
-//! - SV1 protocol connections ([`sv1_connection`]) - when `sv1` feature is enabled
+//! - Plaintext connections for trusted networks ([`plain_stream`]), selected per connection
+//!   through [`transport`]
+//! - Unix socket connections to co-located peers ([`socket`])
+//! - TCP socket options of listeners and outbound connections ([`tcp`]): `SO_REUSEPORT`,
+//!   `TCP_NODELAY`, keepalive and bind-to-device
+//! - Per-connection frame and byte counters ([`traffic`])
+//! - Per-connection buffer pools serializing outbound frames into reused memory ([`frame_pool`])
+//! - SV1 protocol connections ([`sv1_connection`]), closing peers sending oversized or malformed
+//!   messages - when `sv1` feature is enabled
+//! - TLS listeners ([`tls`]) - when `tls` feature is enabled
+//!
+//! The SV2 streams accept an optional maximum inbound frame size. A peer declaring a larger frame
+//! gets its connection aborted with [`Error::FrameTooLarge`] before any payload is buffered, and
+//! the process-wide [`oversized_frames`] counter is incremented.
 //!
 //! Originally from the `network_helpers_sv2` crate.
 
+pub mod frame_pool;
 pub mod noise_connection;
 pub mod noise_stream;
+pub mod plain_stream;
+pub mod socket;
+pub mod tcp;
+pub mod traffic;
+pub mod transport;
 
 #[cfg(feature = "sv1")]
 pub mod sv1_connection;
 
+#[cfg(feature = "tls")]
+pub mod tls;
+
 use async_channel::{RecvError, SendError};
+use std::sync::atomic::{AtomicU64, Ordering};
 use stratum_core::codec_sv2::Error as CodecError;
+use tracing::warn;
+
+static OVERSIZED_FRAMES: AtomicU64 = AtomicU64::new(0);
+
+/// Number of inbound frames rejected by any stream of this process for exceeding their maximum
+/// frame size.
+pub fn oversized_frames() -> u64 {
+    OVERSIZED_FRAMES.load(Ordering::Relaxed)
+}
+
+/// Fails with [`Error::FrameTooLarge`] if a peer is about to send `size` bytes while at most
+/// `max_frame_size` are allowed.
+pub(crate) fn check_frame_size(size: usize, max_frame_size: Option<usize>) -> Result<(), Error> {
+    match max_frame_size {
+        Some(max) if size > max => {
+            OVERSIZED_FRAMES.fetch_add(1, Ordering::Relaxed);
+            warn!("Rejecting inbound frame of {size} bytes, the maximum is {max} bytes");
+            Err(Error::FrameTooLarge { size, max })
+        }
+        _ => Ok(()),
+    }
+}
 
 /// Networking errors that can occur in SV2 connections
 #[derive(Debug)]
@@ -30,6 +75,12 @@ pub enum Error {
     SendError,
     /// Socket was closed, likely by the peer
     SocketClosed,
+    /// A handshake frame was written to a plaintext connection
+    UnexpectedHandshakeFrame,
+    /// The peer declared a frame larger than the maximum inbound frame size
+    FrameTooLarge { size: usize, max: usize },
+    /// The TCP or unix socket connection to the peer could not be established
+    Io(std::io::Error),
 }
 
 impl From<CodecError> for Error {
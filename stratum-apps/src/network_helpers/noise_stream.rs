@@ -1,24 +1,23 @@
 //! A Noise-encrypted wrapper around a `TcpStream`, providing framed read/write I/O using the SV2
 //! protocol and a stateful Noise handshake.
 //!
-//! This module provides `NoiseTcpStream`, which wraps a `TcpStream` and performs a Noise-based
-//! authenticated key exchange based on the provided [`HandshakeRole`].
+//! This module provides `NoiseTcpStream`, which wraps a `TcpStream`, or any other [`Socket`], and
+//! performs a Noise-based authenticated key exchange based on the provided [`HandshakeRole`].
 //!
 //! After a successful handshake, the stream can be split into a `NoiseTcpReadHalf` and
 //! `NoiseTcpWriteHalf`, which support frame-based encoding/decoding of SV2 messages with optional
 //! non-blocking behavior.
 
-use crate::network_helpers::Error;
+use crate::network_helpers::{
+    check_frame_size,
+    socket::{Socket, SocketReadHalf, SocketWriteHalf},
+    Error,
+};
 use stratum_core::{
     binary_sv2::{Deserialize, GetSize, Serialize},
     codec_sv2::{HandshakeRole, NoiseEncoder, StandardNoiseDecoder, State},
     noise_sv2::INITIATOR_EXPECTED_HANDSHAKE_MESSAGE_SIZE,
 };
-use tokio::net::{
-    tcp::{OwnedReadHalf, OwnedWriteHalf},
-    TcpStream,
-};
-
 use stratum_core::{
     codec_sv2::StandardEitherFrame, framing_sv2::framing::HandShakeFrame,
     noise_sv2::ELLSWIFT_ENCODING_SIZE,
@@ -46,11 +45,12 @@ pub struct NoiseTcpStream<Message: Serialize + Deserialize<'static> + GetSize +
 /// It buffers incoming encrypted bytes, attempts to decode full Noise frames,
 /// and exposes a method to retrieve structured messages of type `Message`.
 pub struct NoiseTcpReadHalf<Message: Serialize + Deserialize<'static> + GetSize + Send + 'static> {
-    reader: OwnedReadHalf,
+    reader: SocketReadHalf,
     decoder: StandardNoiseDecoder<Message>,
     state: State,
     current_frame_buf: Vec<u8>,
     bytes_read: usize,
+    max_frame_size: Option<usize>,
 }
 
 /// The writing half of a `NoiseTcpStream`.
@@ -58,7 +58,7 @@ pub struct NoiseTcpReadHalf<Message: Serialize + Deserialize<'static> + GetSize
 /// It accepts structured messages, encodes them via the Noise protocol,
 /// and writes the result to the socket.
 pub struct NoiseTcpWriteHalf<Message: Serialize + Deserialize<'static> + GetSize + Send + 'static> {
-    writer: OwnedWriteHalf,
+    writer: SocketWriteHalf,
     encoder: NoiseEncoder<Message>,
     state: State,
 }
@@ -67,12 +67,12 @@ impl<Message> NoiseTcpStream<Message>
 where
     Message: Serialize + Deserialize<'static> + GetSize + Send + 'static,
 {
-    /// Constructs a new `NoiseTcpStream` over the given TCP stream,
+    /// Constructs a new `NoiseTcpStream` over the given TCP stream or unix socket,
     /// performing the Noise handshake in the given `role`.
     ///
     /// On success, returns a stream with encrypted communication channels.
-    pub async fn new(stream: TcpStream, role: HandshakeRole) -> Result<Self, Error> {
-        let (mut reader, mut writer) = stream.into_split();
+    pub async fn new(stream: impl Into<Socket>, role: HandshakeRole) -> Result<Self, Error> {
+        let (mut reader, mut writer) = stream.into().into_split();
 
         let mut decoder = StandardNoiseDecoder::<Message>::new();
         let mut encoder = NoiseEncoder::<Message>::new();
@@ -150,6 +150,7 @@ where
                 state: state.clone(),
                 current_frame_buf: vec![],
                 bytes_read: 0,
+                max_frame_size: None,
             },
             writer: NoiseTcpWriteHalf {
                 writer,
@@ -159,6 +160,12 @@ where
         })
     }
 
+    /// Sets the maximum size of inbound frames, see [`NoiseTcpReadHalf::set_max_frame_size`].
+    pub fn with_max_frame_size(mut self, max_frame_size: Option<usize>) -> Self {
+        self.reader.set_max_frame_size(max_frame_size);
+        self
+    }
+
     /// Consumes the stream and returns its reader and writer halves.
     pub fn into_split(self) -> (NoiseTcpReadHalf<Message>, NoiseTcpWriteHalf<Message>) {
         (self.reader, self.writer)
@@ -215,6 +222,15 @@ impl<Message> NoiseTcpReadHalf<Message>
 where
     Message: Serialize + Deserialize<'static> + GetSize + Send + 'static,
 {
+    /// Sets the maximum size (in bytes) of an inbound frame payload, `None` for no limit.
+    ///
+    /// The size is checked against the length declared in the frame header, reads of larger
+    /// frames fail with [`Error::FrameTooLarge`] before the payload is buffered. Encrypted
+    /// payloads count their MAC overhead.
+    pub fn set_max_frame_size(&mut self, max_frame_size: Option<usize>) {
+        self.max_frame_size = max_frame_size;
+    }
+
     /// Reads and decodes a complete frame from the socket.
     ///
     /// This method blocks until a full frame is read and decoded,
@@ -224,6 +240,7 @@ where
     pub async fn read_frame(&mut self) -> Result<StandardEitherFrame<Message>, Error> {
         loop {
             let expected = self.decoder.writable_len();
+            check_frame_size(expected, self.max_frame_size)?;
 
             if self.current_frame_buf.len() != expected {
                 self.current_frame_buf.resize(expected, 0);
@@ -269,6 +286,7 @@ where
     /// - `Err(_)` on socket or decoding errors.
     pub fn try_read_frame(&mut self) -> Result<Option<StandardEitherFrame<Message>>, Error> {
         let expected = self.decoder.writable_len();
+        check_frame_size(expected, self.max_frame_size)?;
 
         if self.current_frame_buf.len() != expected {
             self.current_frame_buf.resize(expected, 0);
@@ -304,7 +322,7 @@ where
 }
 
 async fn send_message<Message: Serialize + Deserialize<'static> + GetSize + Send + 'static>(
-    writer: &mut OwnedWriteHalf,
+    writer: &mut SocketWriteHalf,
     msg: StandardEitherFrame<Message>,
     state: &mut State,
     encoder: &mut NoiseEncoder<Message>,
@@ -318,7 +336,7 @@ async fn send_message<Message: Serialize + Deserialize<'static> + GetSize + Send
 }
 
 async fn receive_message<Message: Serialize + Deserialize<'static> + GetSize + Send + 'static>(
-    reader: &mut OwnedReadHalf,
+    reader: &mut SocketReadHalf,
     state: &mut State,
     decoder: &mut StandardNoiseDecoder<Message>,
 ) -> Result<StandardEitherFrame<Message>, Error> {
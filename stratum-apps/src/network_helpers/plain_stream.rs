@@ -0,0 +1,235 @@
+//! An unencrypted wrapper around a `TcpStream`, providing framed read/write I/O using the SV2
+//! protocol without the Noise layer.
+//!
+//! This module provides `PlainTcpStream`, the plaintext counterpart of
+//! [`NoiseTcpStream`](super::noise_stream::NoiseTcpStream), meant for deployments where every
+//! peer sits inside a trusted network (private LAN, WireGuard tunnel, ...) and the Noise layer is
+//! redundant overhead. No handshake takes place: SV2 frames are exchanged as soon as the TCP
+//! connection is established, and nothing authenticates the remote peer.
+//!
+//! The stream can be split into a `PlainTcpReadHalf` and `PlainTcpWriteHalf`, exposing the same
+//! frame-based methods as the Noise halves.
+
+use crate::network_helpers::{
+    check_frame_size,
+    socket::{Socket, SocketReadHalf, SocketWriteHalf},
+    Error,
+};
+use stratum_core::{
+    binary_sv2::{Deserialize, GetSize, Serialize},
+    codec_sv2::{Encoder, StandardDecoder, StandardEitherFrame, StandardSv2Frame},
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Size of the SV2 frame header, the first chunk the decoder asks for.
+const FRAME_HEADER_SIZE: usize = 6;
+
+/// An unencrypted duplex stream over TCP exchanging SV2 frames.
+///
+/// **Note:** Like [`NoiseTcpStream`](super::noise_stream::NoiseTcpStream), this struct is **not
+/// cancellation-safe**.
+pub struct PlainTcpStream<Message: Serialize + Deserialize<'static> + GetSize + Send + 'static> {
+    reader: PlainTcpReadHalf<Message>,
+    writer: PlainTcpWriteHalf<Message>,
+}
+
+/// The reading half of a `PlainTcpStream`.
+///
+/// It buffers incoming bytes until a full SV2 frame is available.
+pub struct PlainTcpReadHalf<Message: Serialize + Deserialize<'static> + GetSize + Send + 'static> {
+    reader: SocketReadHalf,
+    decoder: StandardDecoder<Message>,
+    // Number of bytes the decoder needs for the next step (frame header or payload)
+    expected: usize,
+    current_frame_buf: Vec<u8>,
+    bytes_read: usize,
+    max_frame_size: Option<usize>,
+}
+
+/// The writing half of a `PlainTcpStream`.
+pub struct PlainTcpWriteHalf<Message: Serialize + Deserialize<'static> + GetSize + Send + 'static> {
+    writer: SocketWriteHalf,
+    encoder: Encoder<Message>,
+}
+
+impl<Message> PlainTcpStream<Message>
+where
+    Message: Serialize + Deserialize<'static> + GetSize + Send + 'static,
+{
+    /// Wraps the given TCP stream or unix socket. No handshake is performed.
+    pub fn new(stream: impl Into<Socket>) -> Self {
+        let (reader, writer) = stream.into().into_split();
+        Self {
+            reader: PlainTcpReadHalf {
+                reader,
+                decoder: StandardDecoder::<Message>::new(),
+                expected: FRAME_HEADER_SIZE,
+                current_frame_buf: vec![],
+                bytes_read: 0,
+                max_frame_size: None,
+            },
+            writer: PlainTcpWriteHalf {
+                writer,
+                encoder: Encoder::<Message>::new(),
+            },
+        }
+    }
+
+    /// Sets the maximum size of inbound frames, see [`PlainTcpReadHalf::set_max_frame_size`].
+    pub fn with_max_frame_size(mut self, max_frame_size: Option<usize>) -> Self {
+        self.reader.set_max_frame_size(max_frame_size);
+        self
+    }
+
+    /// Consumes the stream and returns its reader and writer halves.
+    pub fn into_split(self) -> (PlainTcpReadHalf<Message>, PlainTcpWriteHalf<Message>) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<Message> PlainTcpWriteHalf<Message>
+where
+    Message: Serialize + Deserialize<'static> + GetSize + Send + 'static,
+{
+    // Handshake frames only exist on Noise connections
+    fn sv2_frame(frame: StandardEitherFrame<Message>) -> Result<StandardSv2Frame<Message>, Error> {
+        frame
+            .try_into()
+            .map_err(|_| Error::UnexpectedHandshakeFrame)
+    }
+
+    /// Writes a full message frame to the socket.
+    ///
+    /// Returns an error if the socket is closed, if the message cannot be encoded or if `frame` is
+    /// a handshake frame.
+    ///
+    /// Not cancellation-safe: A canceled write may cause partial writes.
+    pub async fn write_frame(&mut self, frame: StandardEitherFrame<Message>) -> Result<(), Error> {
+        let buf = self.encoder.encode(Self::sv2_frame(frame)?)?;
+        self.writer
+            .write_all(buf)
+            .await
+            .map_err(|_| Error::SocketClosed)?;
+        Ok(())
+    }
+
+    /// Attempts to write a message without blocking.
+    ///
+    /// Returns:
+    /// - `Ok(true)` if the entire frame was written successfully.
+    /// - `Ok(false)` if the socket is not ready (would block).
+    /// - `Err(_)` on socket or encoding errors.
+    pub fn try_write_frame(&mut self, frame: StandardEitherFrame<Message>) -> Result<bool, Error> {
+        let buf = self.encoder.encode(Self::sv2_frame(frame)?)?;
+
+        match self.writer.try_write(buf) {
+            Ok(n) if n == buf.len() => Ok(true),
+            Ok(_) => Err(Error::SocketClosed),
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(false),
+            Err(_) => Err(Error::SocketClosed),
+        }
+    }
+
+    /// Gracefully shuts down the writing half of the stream.
+    ///
+    /// Returns an error if the shutdown fails.
+    pub async fn shutdown(&mut self) -> Result<(), Error> {
+        self.writer
+            .shutdown()
+            .await
+            .map_err(|_| Error::SocketClosed)
+    }
+}
+
+impl<Message> PlainTcpReadHalf<Message>
+where
+    Message: Serialize + Deserialize<'static> + GetSize + Send + 'static,
+{
+    /// Sets the maximum size (in bytes) of an inbound frame payload, `None` for no limit.
+    ///
+    /// The size is checked against the length declared in the frame header, reads of larger
+    /// frames fail with [`Error::FrameTooLarge`] before the payload is buffered.
+    pub fn set_max_frame_size(&mut self, max_frame_size: Option<usize>) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    /// Hands the buffered bytes to the decoder once `expected` bytes are available.
+    fn decode(&mut self) -> Result<Option<StandardEitherFrame<Message>>, Error> {
+        self.decoder
+            .writable()
+            .copy_from_slice(&self.current_frame_buf[..]);
+        self.bytes_read = 0;
+
+        match self.decoder.next_frame() {
+            Ok(frame) => {
+                self.expected = FRAME_HEADER_SIZE;
+                Ok(Some(frame.into()))
+            }
+            Err(stratum_core::codec_sv2::Error::MissingBytes(missing)) => {
+                check_frame_size(missing, self.max_frame_size)?;
+                self.expected = missing;
+                Ok(None)
+            }
+            Err(e) => Err(Error::CodecError(e)),
+        }
+    }
+
+    /// Reads and decodes a complete frame from the socket.
+    ///
+    /// Not cancellation-safe: Cancellation may leave partially-read state behind.
+    pub async fn read_frame(&mut self) -> Result<StandardEitherFrame<Message>, Error> {
+        loop {
+            if self.current_frame_buf.len() != self.expected {
+                self.current_frame_buf.resize(self.expected, 0);
+                self.bytes_read = 0;
+            }
+
+            while self.bytes_read < self.expected {
+                let n = self
+                    .reader
+                    .read(&mut self.current_frame_buf[self.bytes_read..])
+                    .await
+                    .map_err(|_| Error::SocketClosed)?;
+
+                if n == 0 {
+                    return Err(Error::SocketClosed);
+                }
+
+                self.bytes_read += n;
+            }
+
+            if let Some(frame) = self.decode()? {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Attempts to read and decode a frame without blocking.
+    ///
+    /// Returns:
+    /// - `Ok(Some(frame))` if a full frame is successfully decoded.
+    /// - `Ok(None)` if not enough data is available yet.
+    /// - `Err(_)` on socket or decoding errors.
+    pub fn try_read_frame(&mut self) -> Result<Option<StandardEitherFrame<Message>>, Error> {
+        if self.current_frame_buf.len() != self.expected {
+            self.current_frame_buf.resize(self.expected, 0);
+            self.bytes_read = 0;
+        }
+
+        match self
+            .reader
+            .try_read(&mut self.current_frame_buf[self.bytes_read..])
+        {
+            Ok(0) => return Err(Error::SocketClosed),
+            Ok(n) => self.bytes_read += n,
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+            Err(_) => return Err(Error::SocketClosed),
+        }
+
+        if self.bytes_read < self.expected {
+            return Ok(None);
+        }
+
+        self.decode()
+    }
+}
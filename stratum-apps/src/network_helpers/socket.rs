@@ -0,0 +1,214 @@
+//! Byte streams carrying SV2 connections: TCP, or a unix domain socket for peers on the same host.
+//!
+//! Addresses starting with [`UNIX_SOCKET_PREFIX`], e.g. `unix:/run/bitcoin/sv2.sock`, designate
+//! a unix socket, any other address a TCP `host:port`. Co-located peers, like a Template Provider
+//! running next to the pool, skip the TCP stack over a unix socket.
+//!
+//! [`Socket`] and its halves hide the difference from the SV2 streams layered on top of them.
+
+use std::{
+    io,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+#[cfg(unix)]
+use tokio::net::{unix, UnixStream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{tcp, TcpStream},
+};
+
+use crate::network_helpers::tcp::TcpOptions;
+
+/// Prefix of the addresses designating a unix socket.
+pub const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// Returns the path of the unix socket designated by `address`, if it does designate one.
+pub fn unix_socket_path(address: &str) -> Option<&Path> {
+    address.strip_prefix(UNIX_SOCKET_PREFIX).map(Path::new)
+}
+
+/// A connected TCP or unix socket.
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// The reading half of a [`Socket`].
+#[derive(Debug)]
+pub enum SocketReadHalf {
+    Tcp(tcp::OwnedReadHalf),
+    #[cfg(unix)]
+    Unix(unix::OwnedReadHalf),
+}
+
+/// The writing half of a [`Socket`].
+#[derive(Debug)]
+pub enum SocketWriteHalf {
+    Tcp(tcp::OwnedWriteHalf),
+    #[cfg(unix)]
+    Unix(unix::OwnedWriteHalf),
+}
+
+impl Socket {
+    /// Connects to `address`, a unix socket if it starts with [`UNIX_SOCKET_PREFIX`] and a TCP
+    /// `host:port` otherwise.
+    ///
+    /// Unix sockets are refused with [`io::ErrorKind::Unsupported`] on other platforms.
+    pub async fn connect(address: &str) -> io::Result<Self> {
+        Self::connect_with(address, &TcpOptions::default()).await
+    }
+
+    /// Like [`Self::connect`], dialing TCP addresses with `options`.
+    pub async fn connect_with(address: &str, options: &TcpOptions) -> io::Result<Self> {
+        match unix_socket_path(address) {
+            #[cfg(unix)]
+            Some(path) => Ok(Self::Unix(UnixStream::connect(path).await?)),
+            #[cfg(not(unix))]
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix sockets are not supported on this platform",
+            )),
+            None => Ok(Self::Tcp(options.connect(address).await?)),
+        }
+    }
+
+    /// Consumes the socket and returns its reader and writer halves.
+    pub fn into_split(self) -> (SocketReadHalf, SocketWriteHalf) {
+        match self {
+            Self::Tcp(stream) => {
+                let (reader, writer) = stream.into_split();
+                (SocketReadHalf::Tcp(reader), SocketWriteHalf::Tcp(writer))
+            }
+            #[cfg(unix)]
+            Self::Unix(stream) => {
+                let (reader, writer) = stream.into_split();
+                (SocketReadHalf::Unix(reader), SocketWriteHalf::Unix(writer))
+            }
+        }
+    }
+}
+
+impl From<TcpStream> for Socket {
+    fn from(stream: TcpStream) -> Self {
+        Self::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Socket {
+    fn from(stream: UnixStream) -> Self {
+        Self::Unix(stream)
+    }
+}
+
+impl SocketReadHalf {
+    /// Reads into `buf` without waiting, see [`tcp::OwnedReadHalf::try_read`].
+    pub fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(reader) => reader.try_read(buf),
+            #[cfg(unix)]
+            Self::Unix(reader) => reader.try_read(buf),
+        }
+    }
+}
+
+impl SocketWriteHalf {
+    /// Writes `buf` without waiting, see [`tcp::OwnedWriteHalf::try_write`].
+    pub fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(writer) => writer.try_write(buf),
+            #[cfg(unix)]
+            Self::Unix(writer) => writer.try_write(buf),
+        }
+    }
+}
+
+impl AsyncRead for SocketReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(reader) => Pin::new(reader).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(reader) => Pin::new(reader).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for SocketWriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(writer) => Pin::new(writer).poll_write(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(writer) => Pin::new(writer).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(writer) => Pin::new(writer).poll_flush(cx),
+            #[cfg(unix)]
+            Self::Unix(writer) => Pin::new(writer).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(writer) => Pin::new(writer).poll_shutdown(cx),
+            #[cfg(unix)]
+            Self::Unix(writer) => Pin::new(writer).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixListener,
+    };
+
+    #[test]
+    fn unix_addresses_are_recognized() {
+        assert_eq!(
+            unix_socket_path("unix:/run/bitcoin/sv2.sock"),
+            Some(Path::new("/run/bitcoin/sv2.sock"))
+        );
+        assert_eq!(unix_socket_path("127.0.0.1:8442"), None);
+    }
+
+    #[tokio::test]
+    async fn connects_to_unix_socket() {
+        let dir = std::env::temp_dir().join(format!("sv2-socket-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sv2.sock");
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            buf
+        });
+
+        let address = format!("{UNIX_SOCKET_PREFIX}{}", path.display());
+        let socket = Socket::connect(&address).await.unwrap();
+        assert!(matches!(socket, Socket::Unix(_)));
+        let (_reader, mut writer) = socket.into_split();
+        writer.write_all(b"sv2!").await.unwrap();
+
+        assert_eq!(&server.await.unwrap(), b"sv2!");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,69 @@
 use async_channel::{unbounded, Receiver, Sender};
 use futures::StreamExt;
-use stratum_core::sv1_api::json_rpc;
-use tokio::{
-    io::{AsyncWriteExt, BufReader, BufWriter},
-    net::TcpStream,
+use std::{
+    fmt,
+    sync::{Arc, OnceLock},
 };
-use tokio_util::codec::{FramedRead, LinesCodec};
+use stratum_core::sv1_api::json_rpc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
 use tracing::{error, trace, warn};
 
+/// Longest line read from the peer by default, in bytes.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1 << 16;
+
+/// Limits on the lines read from the peer of a [`ConnectionSV1`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sv1ReadLimits {
+    /// Longest line accepted, in bytes. The connection is closed on a longer one.
+    pub max_message_size: usize,
+    /// Consecutive lines that are not JSON-RPC messages tolerated, the connection is closed on
+    /// the next one. Unlimited if `None`.
+    pub max_malformed_messages: Option<u32>,
+}
+
+impl Default for Sv1ReadLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_malformed_messages: None,
+        }
+    }
+}
+
+/// Limit of [`Sv1ReadLimits`] broken by the peer of a [`ConnectionSV1`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sv1Violation {
+    /// A line was longer than `max_message_size`.
+    OversizedMessage,
+    /// More than `max_malformed_messages` consecutive lines were not JSON-RPC messages.
+    MalformedMessages,
+}
+
+impl fmt::Display for Sv1Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OversizedMessage => f.write_str("oversized SV1 message"),
+            Self::MalformedMessages => f.write_str("malformed SV1 messages"),
+        }
+    }
+}
+
+/// Records the limit the peer of a [`ConnectionSV1`] was disconnected for breaking, if any.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionViolation(Arc<OnceLock<Sv1Violation>>);
+
+impl ConnectionViolation {
+    /// Returns the limit broken by the peer, `None` while it broke none.
+    pub fn get(&self) -> Option<Sv1Violation> {
+        self.0.get().copied()
+    }
+
+    fn set(&self, violation: Sv1Violation) {
+        _ = self.0.set(violation);
+    }
+}
+
 /// Represents a connection between two roles communicating using SV1 protocol.
 ///
 /// This struct can be used to read and write messages to the other side of the connection.  The
@@ -18,6 +74,7 @@ use tracing::{error, trace, warn};
 pub struct ConnectionSV1 {
     receiver: Receiver<json_rpc::Message>,
     sender: Sender<json_rpc::Message>,
+    violation: ConnectionViolation,
 }
 
 struct ConnectionState {
@@ -50,16 +107,30 @@ impl ConnectionState {
     }
 }
 
-const MAX_LINE_LENGTH: usize = 1 << 16;
-
 impl ConnectionSV1 {
-    pub async fn new(stream: TcpStream) -> Self {
-        let (read_half, write_half) = stream.into_split();
+    /// Starts reading and writing SV1 messages on `stream`, with the default [`Sv1ReadLimits`].
+    ///
+    /// Any byte stream can be used, e.g. a plain `TcpStream` or a TLS stream wrapping it.
+    pub async fn new<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self::with_limits(stream, Sv1ReadLimits::default()).await
+    }
+
+    /// Starts reading and writing SV1 messages on `stream`, closing the connection once the
+    /// peer breaks one of `limits`.
+    pub async fn with_limits<S>(stream: S, limits: Sv1ReadLimits) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (read_half, write_half) = tokio::io::split(stream);
         let (sender_incoming, receiver_incoming) = unbounded();
         let (sender_outgoing, receiver_outgoing) = unbounded();
 
         let buffer_read_half = BufReader::new(read_half);
         let buffer_write_half = BufWriter::new(write_half);
+        let violation = ConnectionViolation::default();
 
         let connection_state = ConnectionState::new(
             receiver_outgoing.clone(),
@@ -68,9 +139,15 @@ impl ConnectionSV1 {
             sender_incoming.clone(),
         );
 
+        let reader = Self::run_reader(
+            buffer_read_half,
+            sender_incoming.clone(),
+            limits,
+            violation.clone(),
+        );
         tokio::spawn(async move {
             tokio::select! {
-                _ = Self::run_reader(buffer_read_half, sender_incoming.clone()) => {
+                _ = reader => {
                     trace!("Reader task exited. Closing writer sender.");
                     connection_state.close();
                 }
@@ -84,18 +161,26 @@ impl ConnectionSV1 {
         Self {
             receiver: receiver_incoming,
             sender: sender_outgoing,
+            violation,
         }
     }
 
-    async fn run_reader(
-        reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
+    async fn run_reader<R: AsyncRead + Unpin>(
+        reader: BufReader<R>,
         sender: Sender<json_rpc::Message>,
+        limits: Sv1ReadLimits,
+        violation: ConnectionViolation,
     ) {
-        let mut lines = FramedRead::new(reader, LinesCodec::new_with_max_length(MAX_LINE_LENGTH));
+        let mut lines = FramedRead::new(
+            reader,
+            LinesCodec::new_with_max_length(limits.max_message_size),
+        );
+        let mut malformed_messages = 0;
         while let Some(result) = lines.next().await {
             match result {
                 Ok(line) => match serde_json::from_str::<json_rpc::Message>(&line) {
                     Ok(msg) => {
+                        malformed_messages = 0;
                         if sender.send(msg).await.is_err() {
                             warn!("Receiver dropped, stopping reader");
                             break;
@@ -103,8 +188,27 @@ impl ConnectionSV1 {
                     }
                     Err(e) => {
                         error!("Failed to deserialize message: {e:?}");
+                        malformed_messages += 1;
+                        if limits
+                            .max_malformed_messages
+                            .is_some_and(|max| malformed_messages > max)
+                        {
+                            warn!(
+                                "{malformed_messages} consecutive malformed messages, closing the connection"
+                            );
+                            violation.set(Sv1Violation::MalformedMessages);
+                            break;
+                        }
                     }
                 },
+                Err(LinesCodecError::MaxLineLengthExceeded) => {
+                    warn!(
+                        "Message longer than {} bytes, closing the connection",
+                        limits.max_message_size
+                    );
+                    violation.set(Sv1Violation::OversizedMessage);
+                    break;
+                }
                 Err(e) => {
                     error!("Error reading from stream: {e:?}");
                     break;
@@ -113,8 +217,8 @@ impl ConnectionSV1 {
         }
     }
 
-    async fn run_writer(
-        mut writer: BufWriter<tokio::net::tcp::OwnedWriteHalf>,
+    async fn run_writer<W: AsyncWrite + Unpin>(
+        mut writer: BufWriter<W>,
         receiver: Receiver<json_rpc::Message>,
     ) {
         while let Ok(msg) = receiver.recv().await {
@@ -157,14 +261,64 @@ impl ConnectionSV1 {
     pub fn sender(&self) -> Sender<json_rpc::Message> {
         self.sender.clone()
     }
+
+    /// Returns the record of the limit the peer is disconnected for breaking.
+    pub fn violation(&self) -> ConnectionViolation {
+        self.violation.clone()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use tokio::net::TcpListener;
+    use tokio::net::{TcpListener, TcpStream};
 
     use super::*;
 
+    async fn connected_pair(limits: Sv1ReadLimits) -> (ConnectionSV1, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        (ConnectionSV1::with_limits(stream, limits).await, peer)
+    }
+
+    #[tokio::test]
+    async fn oversized_message_closes_the_connection() {
+        let limits = Sv1ReadLimits {
+            max_message_size: 64,
+            max_malformed_messages: None,
+        };
+        let (connection, mut peer) = connected_pair(limits).await;
+        let line = format!("{}\n", "x".repeat(128));
+        peer.write_all(line.as_bytes()).await.unwrap();
+
+        assert!(connection.receive().await.is_none());
+        assert_eq!(
+            connection.violation().get(),
+            Some(Sv1Violation::OversizedMessage)
+        );
+    }
+
+    #[tokio::test]
+    async fn consecutive_malformed_messages_close_the_connection() {
+        let limits = Sv1ReadLimits {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_malformed_messages: Some(1),
+        };
+        let (connection, mut peer) = connected_pair(limits).await;
+        let request = r#"{"id":1,"method":"mining.subscribe","params":[]}"#;
+        let lines = format!("not json\n{request}\nnot json\nnot json\n");
+        peer.write_all(lines.as_bytes()).await.unwrap();
+
+        // The valid request resets the count of malformed messages
+        assert!(connection.receive().await.is_some());
+        assert!(connection.receive().await.is_none());
+        assert_eq!(
+            connection.violation().get(),
+            Some(Sv1Violation::MalformedMessages)
+        );
+    }
+
     #[tokio::test]
     async fn test_sv1_connection() {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -0,0 +1,279 @@
+//! TCP socket options
+//!
+//! [`TcpOptions`] tunes the TCP sockets a role listens on and dials from, usually through a
+//! `[tcp]` table of the role configuration:
+//!
+//! ```toml
+//! [tcp]
+//! reuse_port = true            # SO_REUSEPORT, several processes accepting on the same port
+//! nodelay = true               # TCP_NODELAY, no Nagle delay on the small SV2 messages
+//! keepalive_secs = 60          # SO_KEEPALIVE, probing a peer idle for a minute
+//! keepalive_interval_secs = 10 # between unanswered probes
+//! keepalive_retries = 6        # unanswered probes before the connection is dropped
+//! bind_device = "eth1"         # SO_BINDTODEVICE, Linux only
+//! ```
+//!
+//! Every option defaults to the behavior of the operating system. Listeners are bound through
+//! [`TcpOptions::bind`], the connections they accept set up with [`TcpOptions::configure`], and
+//! outbound connections dialed through [`TcpOptions::connect`]. `reuse_port` and `bind_device`
+//! fail with [`io::ErrorKind::Unsupported`] on the platforms lacking them rather than being
+//! silently ignored, while the keepalive probes keep the system settings where they cannot be
+//! tuned.
+
+use serde::Deserialize;
+use socket2::{SockRef, TcpKeepalive};
+use std::{fmt, io, net::SocketAddr, time::Duration};
+use tokio::net::{lookup_host, TcpListener, TcpSocket, TcpStream, ToSocketAddrs};
+
+/// Backlog of the listening sockets.
+const LISTEN_BACKLOG: u32 = 1024;
+
+/// Options of the TCP sockets of a role.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TcpOptions {
+    reuse_port: bool,
+    nodelay: bool,
+    keepalive_secs: Option<u64>,
+    keepalive_interval_secs: Option<u64>,
+    keepalive_retries: Option<u32>,
+    bind_device: Option<String>,
+}
+
+impl TcpOptions {
+    /// Returns whether several sockets may listen on the same address, e.g. one per process.
+    pub fn reuse_port(&self) -> bool {
+        self.reuse_port
+    }
+
+    /// Returns whether Nagle's algorithm is disabled.
+    pub fn nodelay(&self) -> bool {
+        self.nodelay
+    }
+
+    /// Returns how long a connection stays idle before keepalive probes are sent, `None` if
+    /// keepalive is disabled.
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.keepalive_secs.map(Duration::from_secs)
+    }
+
+    /// Returns the network interface the sockets are bound to, if any.
+    pub fn bind_device(&self) -> Option<&str> {
+        self.bind_device.as_deref()
+    }
+
+    /// Fails if the keepalive probes are tuned while keepalive is disabled, or if an option is
+    /// set to zero or empty.
+    pub fn check(&self) -> Result<(), String> {
+        if self.keepalive_secs.is_none()
+            && (self.keepalive_interval_secs.is_some() || self.keepalive_retries.is_some())
+        {
+            return Err(
+                "`keepalive_interval_secs` and `keepalive_retries` require `keepalive_secs`"
+                    .to_string(),
+            );
+        }
+        if [self.keepalive_secs, self.keepalive_interval_secs].contains(&Some(0))
+            || self.keepalive_retries == Some(0)
+        {
+            return Err("keepalive times and retries must be positive".to_string());
+        }
+        if self.bind_device.as_deref() == Some("") {
+            return Err("`bind_device` must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// Binds a listener on `address`, with `SO_REUSEADDR` set as [`TcpListener::bind`] does on
+    /// unix, and `SO_REUSEPORT` and the device of the options.
+    pub fn bind(&self, address: SocketAddr) -> io::Result<TcpListener> {
+        let socket = new_socket(address)?;
+        #[cfg(unix)]
+        socket.set_reuseaddr(true)?;
+        if self.reuse_port {
+            set_reuse_port(&socket)?;
+        }
+        self.bind_to_device(&socket)?;
+        socket.bind(address)?;
+        socket.listen(LISTEN_BACKLOG)
+    }
+
+    /// Dials `address`, trying each of its resolved addresses in turn, from a socket bound to the
+    /// device of the options, and [configures](Self::configure) the connection.
+    pub async fn connect(&self, address: impl ToSocketAddrs) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for address in lookup_host(address).await? {
+            let socket = new_socket(address)?;
+            self.bind_to_device(&socket)?;
+            match socket.connect(address).await {
+                Ok(stream) => {
+                    self.configure(&stream)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+
+    /// Sets `TCP_NODELAY` and the keepalive of the options on a connected `stream`, e.g. one
+    /// accepted by a listener bound through [`Self::bind`].
+    pub fn configure(&self, stream: &TcpStream) -> io::Result<()> {
+        if self.nodelay {
+            stream.set_nodelay(true)?;
+        }
+        if let Some(time) = self.keepalive() {
+            #[allow(unused_mut)]
+            let mut keepalive = TcpKeepalive::new().with_time(time);
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "freebsd",
+                windows
+            ))]
+            if let Some(interval) = self.keepalive_interval_secs {
+                keepalive = keepalive.with_interval(Duration::from_secs(interval));
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+            if let Some(retries) = self.keepalive_retries {
+                keepalive = keepalive.with_retries(retries);
+            }
+            SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(())
+    }
+
+    fn bind_to_device(&self, socket: &TcpSocket) -> io::Result<()> {
+        match &self.bind_device {
+            Some(device) => bind_device(socket, device),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for TcpOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut options = Vec::new();
+        if self.reuse_port {
+            options.push("reuse_port".to_string());
+        }
+        if self.nodelay {
+            options.push("nodelay".to_string());
+        }
+        if let Some(secs) = self.keepalive_secs {
+            options.push(format!("keepalive after {secs}s"));
+        }
+        if let Some(device) = &self.bind_device {
+            options.push(format!("bound to {device}"));
+        }
+        if options.is_empty() {
+            write!(f, "system defaults")
+        } else {
+            write!(f, "{}", options.join(", "))
+        }
+    }
+}
+
+fn new_socket(address: SocketAddr) -> io::Result<TcpSocket> {
+    match address {
+        SocketAddr::V4(_) => TcpSocket::new_v4(),
+        SocketAddr::V6(_) => TcpSocket::new_v6(),
+    }
+}
+
+#[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+fn set_reuse_port(socket: &TcpSocket) -> io::Result<()> {
+    socket.set_reuseport(true)
+}
+
+#[cfg(not(all(unix, not(target_os = "solaris"), not(target_os = "illumos"))))]
+fn set_reuse_port(_socket: &TcpSocket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &TcpSocket, device: &str) -> io::Result<()> {
+    socket.bind_device(Some(device.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_socket: &TcpSocket, device: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("binding to the device `{device}` is not supported on this platform"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(toml: &str) -> TcpOptions {
+        ext_config::Config::builder()
+            .add_source(ext_config::File::from_str(
+                toml,
+                ext_config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn options_default_to_the_operating_system() {
+        assert_eq!(options(""), TcpOptions::default());
+        let tuned = options("nodelay = true\nkeepalive_secs = 60\nbind_device = \"lo\"");
+        assert!(tuned.nodelay());
+        assert!(!tuned.reuse_port());
+        assert_eq!(tuned.keepalive(), Some(Duration::from_secs(60)));
+        assert_eq!(tuned.bind_device(), Some("lo"));
+        assert_eq!(
+            tuned.to_string(),
+            "nodelay, keepalive after 60s, bound to lo"
+        );
+    }
+
+    #[test]
+    fn keepalive_probes_require_keepalive() {
+        assert!(options("keepalive_secs = 60\nkeepalive_retries = 3")
+            .check()
+            .is_ok());
+        assert!(options("keepalive_interval_secs = 10").check().is_err());
+        assert!(options("keepalive_secs = 0").check().is_err());
+        assert!(options("bind_device = \"\"").check().is_err());
+    }
+
+    #[tokio::test]
+    async fn connections_are_configured() {
+        let options = options("nodelay = true\nkeepalive_secs = 60");
+        let listener = options.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = options.connect(address).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        options.configure(&server).unwrap();
+        for stream in [&client, &server] {
+            assert!(stream.nodelay().unwrap());
+            assert!(SockRef::from(stream).keepalive().unwrap());
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+    #[tokio::test]
+    async fn listeners_share_a_port_with_reuse_port() {
+        let options = options("reuse_port = true");
+        let first = options.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = first.local_addr().unwrap();
+        assert!(options.bind(address).is_ok());
+        assert!(TcpOptions::default().bind(address).is_err());
+    }
+}
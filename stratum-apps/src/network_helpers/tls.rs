@@ -0,0 +1,132 @@
+//! TLS support for listeners
+//!
+//! [`TlsConfig`] is meant to be embedded in role configuration files as an optional table and
+//! points to a PEM encoded certificate chain and private key. [`TlsConfig::acceptor`] turns it
+//! into a [`TlsAcceptor`] that wraps accepted TCP streams:
+//!
+//! ```toml
+//! [downstream_tls]
+//! cert_path = "./cert.pem"
+//! key_path = "./key.pem"
+//! alpn = ["stratum"]
+//! ```
+
+use serde::Deserialize;
+use std::{path::PathBuf, sync::Arc};
+use tokio_rustls::rustls::{
+    self,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig,
+};
+
+pub use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+/// TLS errors
+#[derive(Debug)]
+pub enum Error {
+    /// The certificate chain could not be read or parsed
+    Certificate(PathBuf, String),
+    /// The private key could not be read or parsed
+    PrivateKey(PathBuf, String),
+    /// The certificate file does not contain any certificate
+    NoCertificate(PathBuf),
+    /// The certificate and private key were rejected by rustls
+    Rustls(rustls::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Certificate(path, e) => {
+                write!(f, "Invalid TLS certificate `{}`: {e}", path.display())
+            }
+            Error::PrivateKey(path, e) => {
+                write!(f, "Invalid TLS private key `{}`: {e}", path.display())
+            }
+            Error::NoCertificate(path) => {
+                write!(f, "No certificate found in `{}`", path.display())
+            }
+            Error::Rustls(e) => write!(f, "TLS configuration error: `{e:?}`"),
+        }
+    }
+}
+
+impl From<rustls::Error> for Error {
+    fn from(e: rustls::Error) -> Self {
+        Error::Rustls(e)
+    }
+}
+
+/// Server side TLS configuration.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TlsConfig {
+    /// Path to the PEM encoded certificate chain, leaf certificate first.
+    pub cert_path: PathBuf,
+    /// Path to the PEM encoded private key (PKCS#8, PKCS#1 or SEC1).
+    pub key_path: PathBuf,
+    /// ALPN protocols offered to clients, in order of preference. No ALPN if empty.
+    #[serde(default)]
+    pub alpn: Vec<String>,
+}
+
+impl TlsConfig {
+    /// Creates a new [`TlsConfig`].
+    pub fn new(cert_path: PathBuf, key_path: PathBuf, alpn: Vec<String>) -> Self {
+        Self {
+            cert_path,
+            key_path,
+            alpn,
+        }
+    }
+
+    /// Loads the certificate chain and private key and builds a [`TlsAcceptor`].
+    pub fn acceptor(&self) -> Result<TlsAcceptor, Error> {
+        let certs = CertificateDer::pem_file_iter(&self.cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| Error::Certificate(self.cert_path.clone(), e.to_string()))?;
+        if certs.is_empty() {
+            return Err(Error::NoCertificate(self.cert_path.clone()));
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key_path)
+            .map_err(|e| Error::PrivateKey(self.key_path.clone(), e.to_string()))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        config.alpn_protocols = self
+            .alpn
+            .iter()
+            .map(|protocol| protocol.as_bytes().to_vec())
+            .collect();
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_files_are_reported() {
+        let config = TlsConfig::new(
+            PathBuf::from("/nonexistent/cert.pem"),
+            PathBuf::from("/nonexistent/key.pem"),
+            vec![],
+        );
+        assert!(matches!(config.acceptor(), Err(Error::Certificate(..))));
+    }
+
+    #[test]
+    fn empty_certificate_file_is_rejected() {
+        let path =
+            std::env::temp_dir().join(format!("stratum-apps-tls-empty-{}.pem", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let config = TlsConfig::new(path.clone(), path.clone(), vec![]);
+        let result = config.acceptor();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(Error::NoCertificate(_))));
+    }
+}
@@ -0,0 +1,168 @@
+//! Per-connection traffic accounting
+//!
+//! A [`ConnectionTraffic`] is shared between the I/O tasks of a connection, which record every
+//! frame they receive or send, and whoever reports on the connection (logs, status endpoint).
+//! It also keeps the high watermarks of the channels between the I/O tasks and the role, the
+//! most frames ever waiting in each of them.
+//! Counters are lock-free so recording a frame never contends with a reader taking a
+//! [`TrafficSnapshot`].
+
+use serde::Serialize;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Frame and byte counters of a single connection, per direction.
+#[derive(Debug)]
+pub struct ConnectionTraffic {
+    started_at: Instant,
+    frames_received: AtomicU64,
+    bytes_received: AtomicU64,
+    frames_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    // Milliseconds since `started_at` of the last received frame, 0 if none was received yet
+    last_received_ms: AtomicU64,
+    inbound_high_watermark: AtomicU64,
+    outbound_high_watermark: AtomicU64,
+}
+
+impl Default for ConnectionTraffic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConnectionTraffic {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            frames_received: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            frames_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            last_received_ms: AtomicU64::new(0),
+            inbound_high_watermark: AtomicU64::new(0),
+            outbound_high_watermark: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a frame of `bytes` bytes received from the peer.
+    pub fn record_received(&self, bytes: usize) {
+        self.frames_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        let elapsed_ms = self.started_at.elapsed().as_millis() as u64;
+        self.last_received_ms
+            .store(elapsed_ms.max(1), Ordering::Relaxed);
+    }
+
+    /// Records a frame of `bytes` bytes sent to the peer.
+    pub fn record_sent(&self, bytes: usize) {
+        self.frames_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records that `queued` received frames are waiting to be handled by the role.
+    pub fn record_inbound_queued(&self, queued: usize) {
+        self.inbound_high_watermark
+            .fetch_max(queued as u64, Ordering::Relaxed);
+    }
+
+    /// Records that `queued` frames are waiting to be written to the peer.
+    pub fn record_outbound_queued(&self, queued: usize) {
+        self.outbound_high_watermark
+            .fetch_max(queued as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the current counters.
+    pub fn snapshot(&self) -> TrafficSnapshot {
+        let elapsed = self.started_at.elapsed();
+        let last_received_ms = self.last_received_ms.load(Ordering::Relaxed);
+        TrafficSnapshot {
+            connected_secs: elapsed.as_secs(),
+            frames_received: self.frames_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            frames_sent: self.frames_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            last_received_secs_ago: (last_received_ms != 0).then(|| {
+                elapsed
+                    .saturating_sub(Duration::from_millis(last_received_ms))
+                    .as_secs()
+            }),
+            inbound_high_watermark: self.inbound_high_watermark.load(Ordering::Relaxed),
+            outbound_high_watermark: self.outbound_high_watermark.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point-in-time view of a [`ConnectionTraffic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TrafficSnapshot {
+    /// Seconds since the connection was established.
+    pub connected_secs: u64,
+    pub frames_received: u64,
+    pub bytes_received: u64,
+    pub frames_sent: u64,
+    pub bytes_sent: u64,
+    /// Seconds since the last frame was received, `None` if the peer never sent one.
+    pub last_received_secs_ago: Option<u64>,
+    /// Most received frames ever waiting to be handled by the role.
+    pub inbound_high_watermark: u64,
+    /// Most frames ever waiting to be written to the peer.
+    pub outbound_high_watermark: u64,
+}
+
+impl TrafficSnapshot {
+    /// Average number of frames received per minute since the connection was established.
+    pub fn frames_received_per_minute(&self) -> f64 {
+        Self::per_minute(self.frames_received, self.connected_secs)
+    }
+
+    /// Average number of frames sent per minute since the connection was established.
+    pub fn frames_sent_per_minute(&self) -> f64 {
+        Self::per_minute(self.frames_sent, self.connected_secs)
+    }
+
+    fn per_minute(count: u64, secs: u64) -> f64 {
+        count as f64 * 60.0 / secs.max(1) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_frames_and_bytes_per_direction() {
+        let traffic = ConnectionTraffic::new();
+        assert_eq!(traffic.snapshot().last_received_secs_ago, None);
+
+        traffic.record_received(100);
+        traffic.record_received(28);
+        traffic.record_sent(64);
+
+        let snapshot = traffic.snapshot();
+        assert_eq!(snapshot.frames_received, 2);
+        assert_eq!(snapshot.bytes_received, 128);
+        assert_eq!(snapshot.frames_sent, 1);
+        assert_eq!(snapshot.bytes_sent, 64);
+        assert_eq!(snapshot.last_received_secs_ago, Some(0));
+        // Rates are computed over at least one second
+        assert_eq!(snapshot.frames_received_per_minute(), 120.0);
+        assert_eq!(snapshot.frames_sent_per_minute(), 60.0);
+    }
+
+    #[test]
+    fn keeps_the_high_watermarks_of_the_channels() {
+        let traffic = ConnectionTraffic::new();
+        traffic.record_inbound_queued(3);
+        traffic.record_inbound_queued(1);
+        traffic.record_outbound_queued(7);
+        traffic.record_outbound_queued(2);
+
+        let snapshot = traffic.snapshot();
+        assert_eq!(snapshot.inbound_high_watermark, 3);
+        assert_eq!(snapshot.outbound_high_watermark, 7);
+    }
+}
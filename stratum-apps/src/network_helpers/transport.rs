@@ -0,0 +1,349 @@
+//! Transport selection for SV2 connections
+//!
+//! Roles pick the transport of each listener or upstream through an [`Encryption`] setting (see
+//! [`encryption`](crate::encryption)), usually embedded in the role configuration.
+//!
+//! [`Sv2TcpStream`] and its halves wrap either a [`NoiseTcpStream`] or a [`PlainTcpStream`] behind
+//! the same frame-based methods, so that the I/O tasks of a role do not depend on the transport.
+//!
+//! Roles dialing an upstream use [`connect`], which accepts a set of authority keys so that the
+//! upstream can rotate its keypair without a flag day across its clients. [`connect_address`] also
+//! dials unix sockets, for upstreams configured with a `unix:` address (see [`socket`]), and
+//! [`connect_address_with`] applies the [`TcpOptions`] of the role to TCP upstreams.
+//!
+//! [`socket`]: crate::network_helpers::socket
+
+use std::{future::Future, io};
+use stratum_core::{
+    binary_sv2::{Deserialize as Sv2Deserialize, GetSize, Serialize},
+    codec_sv2::{HandshakeRole, StandardEitherFrame},
+    noise_sv2::Initiator,
+};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tracing::{debug, info};
+
+pub use crate::encryption::Encryption;
+
+use crate::{
+    key_utils::AuthorityPublicKeys,
+    network_helpers::{
+        noise_stream::{NoiseTcpReadHalf, NoiseTcpStream, NoiseTcpWriteHalf},
+        plain_stream::{PlainTcpReadHalf, PlainTcpStream, PlainTcpWriteHalf},
+        socket::Socket,
+        tcp::TcpOptions,
+        Error,
+    },
+};
+
+/// An SV2 TCP stream, encrypted with Noise or plaintext.
+pub enum Sv2TcpStream<Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static> {
+    Noise(NoiseTcpStream<Message>),
+    Plain(PlainTcpStream<Message>),
+}
+
+/// The reading half of a [`Sv2TcpStream`].
+pub enum Sv2TcpReadHalf<Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static> {
+    Noise(NoiseTcpReadHalf<Message>),
+    Plain(PlainTcpReadHalf<Message>),
+}
+
+/// The writing half of a [`Sv2TcpStream`].
+pub enum Sv2TcpWriteHalf<Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static> {
+    Noise(NoiseTcpWriteHalf<Message>),
+    Plain(PlainTcpWriteHalf<Message>),
+}
+
+impl<Message> Sv2TcpStream<Message>
+where
+    Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static,
+{
+    /// Establishes the SV2 transport over `stream`.
+    ///
+    /// With [`Encryption::Noise`] the handshake is performed in the given `role`, with
+    /// [`Encryption::None`] the role is ignored and the stream is ready right away.
+    pub async fn new(
+        stream: impl Into<Socket>,
+        encryption: Encryption,
+        role: HandshakeRole,
+    ) -> Result<Self, Error> {
+        match encryption {
+            Encryption::Noise => Ok(Self::Noise(NoiseTcpStream::new(stream, role).await?)),
+            Encryption::None => Ok(Self::Plain(PlainTcpStream::new(stream))),
+        }
+    }
+
+    /// Sets the maximum size (in bytes) of inbound frames, `None` for no limit.
+    ///
+    /// See [`NoiseTcpReadHalf::set_max_frame_size`].
+    pub fn with_max_frame_size(self, max_frame_size: Option<usize>) -> Self {
+        match self {
+            Self::Noise(stream) => Self::Noise(stream.with_max_frame_size(max_frame_size)),
+            Self::Plain(stream) => Self::Plain(stream.with_max_frame_size(max_frame_size)),
+        }
+    }
+
+    /// Consumes the stream and returns its reader and writer halves.
+    pub fn into_split(self) -> (Sv2TcpReadHalf<Message>, Sv2TcpWriteHalf<Message>) {
+        match self {
+            Self::Noise(stream) => {
+                let (reader, writer) = stream.into_split();
+                (
+                    Sv2TcpReadHalf::Noise(reader),
+                    Sv2TcpWriteHalf::Noise(writer),
+                )
+            }
+            Self::Plain(stream) => {
+                let (reader, writer) = stream.into_split();
+                (
+                    Sv2TcpReadHalf::Plain(reader),
+                    Sv2TcpWriteHalf::Plain(writer),
+                )
+            }
+        }
+    }
+}
+
+/// Connects to `address` and establishes the SV2 transport as initiator, accepting a responder
+/// certified by any of `authority_keys`, or by any key if `None`.
+///
+/// A Noise handshake checks the certificate against a single key, so the keys are tried in turn,
+/// each on a new TCP connection, until a handshake succeeds. The error of the last handshake is
+/// returned if none does. With [`Encryption::None`] the keys are ignored.
+pub async fn connect<Message>(
+    address: impl ToSocketAddrs + Copy,
+    encryption: Encryption,
+    authority_keys: Option<&AuthorityPublicKeys>,
+) -> Result<Sv2TcpStream<Message>, Error>
+where
+    Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static,
+{
+    let dial = || async move { TcpStream::connect(address).await.map(Socket::from) };
+    connect_with(dial, encryption, authority_keys).await
+}
+
+/// Like [`connect`], dialing a unix socket if `address` starts with
+/// [`UNIX_SOCKET_PREFIX`](crate::network_helpers::socket::UNIX_SOCKET_PREFIX) and a TCP
+/// `host:port` otherwise.
+pub async fn connect_address<Message>(
+    address: &str,
+    encryption: Encryption,
+    authority_keys: Option<&AuthorityPublicKeys>,
+) -> Result<Sv2TcpStream<Message>, Error>
+where
+    Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static,
+{
+    connect_address_with(address, &TcpOptions::default(), encryption, authority_keys).await
+}
+
+/// Like [`connect_address`], dialing TCP addresses with `options`.
+pub async fn connect_address_with<Message>(
+    address: &str,
+    options: &TcpOptions,
+    encryption: Encryption,
+    authority_keys: Option<&AuthorityPublicKeys>,
+) -> Result<Sv2TcpStream<Message>, Error>
+where
+    Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static,
+{
+    let dial = || Socket::connect_with(address, options);
+    connect_with(dial, encryption, authority_keys).await
+}
+
+// Performs the handshakes of `connect`, each over a new connection opened by `dial`.
+async fn connect_with<Message, F, Fut>(
+    dial: F,
+    encryption: Encryption,
+    authority_keys: Option<&AuthorityPublicKeys>,
+) -> Result<Sv2TcpStream<Message>, Error>
+where
+    Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static,
+    F: Fn() -> Fut,
+    Fut: Future<Output = io::Result<Socket>>,
+{
+    let keys = match authority_keys {
+        Some(keys) if encryption == Encryption::Noise => keys.keys().iter().map(Some).collect(),
+        _ => vec![None],
+    };
+    let mut last_error = None;
+    for (index, key) in keys.into_iter().enumerate() {
+        let stream = dial().await.map_err(Error::Io)?;
+        let initiator = Initiator::new(key.map(|key| key.0));
+        match Sv2TcpStream::new(stream, encryption, HandshakeRole::Initiator(initiator)).await {
+            Ok(stream) => {
+                if let Some(key) = key.filter(|_| index > 0) {
+                    info!(
+                        "Upstream certified by the authority key {key}, not by the preferred one"
+                    );
+                }
+                return Ok(stream);
+            }
+            Err(e) => {
+                debug!(error = ?e, "Handshake failed with authority key {index}");
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.expect("at least one handshake is attempted"))
+}
+
+macro_rules! impl_from_transport {
+    ($sv2:ident :: $variant:ident, $inner:ident) => {
+        impl<Message> From<$inner<Message>> for $sv2<Message>
+        where
+            Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static,
+        {
+            fn from(inner: $inner<Message>) -> Self {
+                Self::$variant(inner)
+            }
+        }
+    };
+}
+
+impl_from_transport!(Sv2TcpStream::Noise, NoiseTcpStream);
+impl_from_transport!(Sv2TcpStream::Plain, PlainTcpStream);
+impl_from_transport!(Sv2TcpReadHalf::Noise, NoiseTcpReadHalf);
+impl_from_transport!(Sv2TcpReadHalf::Plain, PlainTcpReadHalf);
+impl_from_transport!(Sv2TcpWriteHalf::Noise, NoiseTcpWriteHalf);
+impl_from_transport!(Sv2TcpWriteHalf::Plain, PlainTcpWriteHalf);
+
+impl<Message> Sv2TcpWriteHalf<Message>
+where
+    Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static,
+{
+    /// See [`NoiseTcpWriteHalf::write_frame`].
+    pub async fn write_frame(&mut self, frame: StandardEitherFrame<Message>) -> Result<(), Error> {
+        match self {
+            Self::Noise(writer) => writer.write_frame(frame).await,
+            Self::Plain(writer) => writer.write_frame(frame).await,
+        }
+    }
+
+    /// See [`NoiseTcpWriteHalf::try_write_frame`].
+    pub fn try_write_frame(&mut self, frame: StandardEitherFrame<Message>) -> Result<bool, Error> {
+        match self {
+            Self::Noise(writer) => writer.try_write_frame(frame),
+            Self::Plain(writer) => writer.try_write_frame(frame),
+        }
+    }
+
+    /// See [`NoiseTcpWriteHalf::shutdown`].
+    pub async fn shutdown(&mut self) -> Result<(), Error> {
+        match self {
+            Self::Noise(writer) => writer.shutdown().await,
+            Self::Plain(writer) => writer.shutdown().await,
+        }
+    }
+}
+
+impl<Message> Sv2TcpReadHalf<Message>
+where
+    Message: Serialize + Sv2Deserialize<'static> + GetSize + Send + 'static,
+{
+    /// See [`NoiseTcpReadHalf::read_frame`].
+    pub async fn read_frame(&mut self) -> Result<StandardEitherFrame<Message>, Error> {
+        match self {
+            Self::Noise(reader) => reader.read_frame().await,
+            Self::Plain(reader) => reader.read_frame().await,
+        }
+    }
+
+    /// See [`NoiseTcpReadHalf::try_read_frame`].
+    pub fn try_read_frame(&mut self) -> Result<Option<StandardEitherFrame<Message>>, Error> {
+        match self {
+            Self::Noise(reader) => reader.try_read_frame(),
+            Self::Plain(reader) => reader.try_read_frame(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_core::{
+        codec_sv2::StandardSv2Frame,
+        common_messages_sv2::{Protocol, SetupConnection},
+        parsers_sv2::AnyMessage,
+    };
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn encryption_defaults_to_noise() {
+        assert_eq!(Encryption::default(), Encryption::Noise);
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_is_rejected() {
+        let oversized_frames_before = crate::network_helpers::oversized_frames();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (mut reader, _writer) =
+                Sv2TcpStream::<AnyMessage<'static>>::from(PlainTcpStream::new(stream))
+                    .with_max_frame_size(Some(1024))
+                    .into_split();
+            reader.read_frame().await
+        });
+
+        // Header declaring a 65535 bytes payload, which is never sent
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut stream, &[0, 0, 0, 0xff, 0xff, 0])
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            server.await.unwrap(),
+            Err(Error::FrameTooLarge {
+                size: 65535,
+                max: 1024
+            })
+        ));
+        assert!(crate::network_helpers::oversized_frames() > oversized_frames_before);
+    }
+
+    #[tokio::test]
+    async fn plaintext_frames_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (mut reader, _writer) =
+                Sv2TcpStream::<AnyMessage<'static>>::from(PlainTcpStream::new(stream)).into_split();
+            reader.read_frame().await.unwrap()
+        });
+
+        let stream = TcpStream::connect(address).await.unwrap();
+        let role = HandshakeRole::Initiator(stratum_core::noise_sv2::Initiator::new(None));
+        let (_reader, mut writer) =
+            Sv2TcpStream::<AnyMessage<'static>>::new(stream, Encryption::None, role)
+                .await
+                .unwrap()
+                .into_split();
+        let setup_connection = SetupConnection {
+            protocol: Protocol::MiningProtocol,
+            min_version: 2,
+            max_version: 2,
+            flags: 0,
+            endpoint_host: "127.0.0.1".to_string().into_bytes().try_into().unwrap(),
+            endpoint_port: address.port(),
+            vendor: "test".to_string().into_bytes().try_into().unwrap(),
+            hardware_version: "".to_string().into_bytes().try_into().unwrap(),
+            firmware: "".to_string().into_bytes().try_into().unwrap(),
+            device_id: "".to_string().into_bytes().try_into().unwrap(),
+        };
+        let frame: StandardSv2Frame<AnyMessage<'static>> =
+            AnyMessage::Common(setup_connection.into())
+                .try_into()
+                .unwrap();
+        writer.write_frame(frame.into()).await.unwrap();
+
+        let received = server.await.unwrap();
+        let stratum_core::framing_sv2::framing::Frame::Sv2(mut received) = received else {
+            panic!("expected an SV2 frame");
+        };
+        assert_eq!(
+            received.get_header().unwrap().msg_type(),
+            stratum_core::common_messages_sv2::MESSAGE_TYPE_SETUP_CONNECTION
+        );
+    }
+}
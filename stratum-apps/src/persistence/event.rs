@@ -0,0 +1,366 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::events::{ChannelClosed, ConnectionSetUp, ShareValidated};
+
+/// An event recorded by the persistence subsystem.
+///
+/// Every variant is serialized with a `type` tag so that heterogeneous events can share the
+/// same storage backend and still be told apart when read back.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PersistenceEvent {
+    /// A `DeclareMiningJob` has been processed by a Job Declarator Server.
+    JobDeclaration(JobDeclarationEvent),
+    /// A `PushSolution` has been received by a Job Declarator Server.
+    Solution(SolutionEvent),
+    /// A share has been submitted to a Pool.
+    Share(ShareEvent),
+    /// An SV1 share has been validated by a Translator, before being forwarded upstream.
+    Sv1Share(Sv1ShareEvent),
+    /// A `SetCustomMiningJob` has been validated by a Pool.
+    CustomJob(CustomJobEvent),
+    /// A round of a Pool has been closed by a block.
+    Round(RoundEvent),
+    /// A payout transaction has been built by a Pool.
+    Payment(PaymentEvent),
+    /// A channel of a Pool has been closed.
+    ChannelClosed(ChannelClosedEvent),
+    /// The `SetupConnection` of a downstream of a Pool has been accepted.
+    Connection(ConnectionEvent),
+}
+
+impl From<JobDeclarationEvent> for PersistenceEvent {
+    fn from(event: JobDeclarationEvent) -> Self {
+        PersistenceEvent::JobDeclaration(event)
+    }
+}
+
+impl From<SolutionEvent> for PersistenceEvent {
+    fn from(event: SolutionEvent) -> Self {
+        PersistenceEvent::Solution(event)
+    }
+}
+
+impl From<ShareEvent> for PersistenceEvent {
+    fn from(event: ShareEvent) -> Self {
+        PersistenceEvent::Share(event)
+    }
+}
+
+impl From<Sv1ShareEvent> for PersistenceEvent {
+    fn from(event: Sv1ShareEvent) -> Self {
+        PersistenceEvent::Sv1Share(event)
+    }
+}
+
+impl From<CustomJobEvent> for PersistenceEvent {
+    fn from(event: CustomJobEvent) -> Self {
+        PersistenceEvent::CustomJob(event)
+    }
+}
+
+impl From<RoundEvent> for PersistenceEvent {
+    fn from(event: RoundEvent) -> Self {
+        PersistenceEvent::Round(event)
+    }
+}
+
+impl From<PaymentEvent> for PersistenceEvent {
+    fn from(event: PaymentEvent) -> Self {
+        PersistenceEvent::Payment(event)
+    }
+}
+
+impl From<ChannelClosedEvent> for PersistenceEvent {
+    fn from(event: ChannelClosedEvent) -> Self {
+        PersistenceEvent::ChannelClosed(event)
+    }
+}
+
+impl From<ConnectionEvent> for PersistenceEvent {
+    fn from(event: ConnectionEvent) -> Self {
+        PersistenceEvent::Connection(event)
+    }
+}
+
+/// Outcome of a job declaration, as answered to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobDeclarationDecision {
+    /// The job was accepted and `DeclareMiningJobSuccess` was sent.
+    Accepted,
+    /// Some transactions were unknown and `ProvideMissingTransactions` was sent.
+    MissingTransactions,
+    /// The job was refused and `DeclareMiningJobError` was sent.
+    Rejected,
+}
+
+/// A job declaration processed by a Job Declarator Server.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobDeclarationEvent {
+    /// Identifier of the declaring client (usually its peer address).
+    pub client: String,
+    /// `request_id` of the `DeclareMiningJob` message.
+    pub request_id: u32,
+    /// Hex encoded mining job token used by the declaration.
+    pub token: String,
+    /// Hex encoded hash committing to the declared template (coinbase and transaction ids).
+    pub template_hash: String,
+    /// Number of transactions declared in the job.
+    pub tx_count: usize,
+    /// Decision taken for this declaration.
+    pub decision: JobDeclarationDecision,
+    /// Error code sent back to the client, if the declaration was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    /// Time spent handling the message, in microseconds.
+    pub processing_time_us: u64,
+}
+
+/// A block solution received by a Job Declarator Server.
+#[derive(Debug, Clone, Serialize)]
+pub struct SolutionEvent {
+    /// Identifier of the client that pushed the solution (usually its peer address).
+    pub client: String,
+    /// Hex encoded previous block hash the solution builds on.
+    pub prev_hash: String,
+    /// Hex encoded extranonce used in the coinbase.
+    pub extranonce: String,
+    /// Block header version.
+    pub version: u32,
+    /// Block header timestamp.
+    pub ntime: u32,
+    /// Block header nonce.
+    pub nonce: u32,
+    /// Block header compact target.
+    pub nbits: u32,
+}
+
+/// A share submitted to a Pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareEvent {
+    /// Identifier of the submitting client (usually its peer address).
+    pub client: String,
+    /// Channel the share was submitted on.
+    pub channel_id: u32,
+    /// Account of the user identity of the channel, if the share was accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// Worker of the user identity of the channel, if the share was accepted and the identity
+    /// names one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker: Option<String>,
+    /// `sequence_number` of the submit message.
+    pub sequence_number: u32,
+    /// Job the share was mined on.
+    pub job_id: u32,
+    /// Block header nonce.
+    pub nonce: u32,
+    /// Block header timestamp.
+    pub ntime: u32,
+    /// Block header version.
+    pub version: u32,
+    /// Whether the share was accepted.
+    pub accepted: bool,
+    /// Error code sent back in `SubmitSharesError`, if the share was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    /// Difficulty met by the hash of the share, if it was accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share_difficulty: Option<f64>,
+    /// Best difficulty met by the shares of the same user since startup, this share included if
+    /// it was accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best_share_difficulty: Option<f64>,
+    /// Whether the share was notably close to the network difficulty.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub notable: bool,
+}
+
+impl From<&ShareValidated> for ShareEvent {
+    fn from(share: &ShareValidated) -> Self {
+        Self {
+            client: share.client.clone(),
+            channel_id: share.channel_id,
+            account: share.account.clone(),
+            worker: share.worker.clone(),
+            sequence_number: share.sequence_number,
+            job_id: share.job_id,
+            nonce: share.nonce,
+            ntime: share.ntime,
+            version: share.version,
+            accepted: share.is_accepted(),
+            error_code: share.error_code.clone(),
+            share_difficulty: share.share_difficulty,
+            best_share_difficulty: share.best_share_difficulty,
+            notable: share.notable,
+        }
+    }
+}
+
+/// An SV1 share validated by a Translator against the target of the miner, before the upstream
+/// acknowledges it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Sv1ShareEvent {
+    /// Address the miner is connected from.
+    pub client: String,
+    /// Identifier of the miner connection in the Translator.
+    pub downstream_id: u32,
+    /// Worker name the miner authorized with.
+    pub worker: String,
+    /// Upstream SV2 channel the share is submitted on.
+    pub channel_id: u32,
+    /// `job_id` of the `mining.submit` message.
+    pub sv1_job_id: String,
+    /// SV2 job the SV1 job maps to, if the SV1 job id is a valid one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<u32>,
+    /// Hex encoded full extranonce of the share: the `extranonce1` of the miner followed by its
+    /// `extranonce2`.
+    pub extranonce: String,
+    /// Block header nonce.
+    pub nonce: u32,
+    /// Block header timestamp.
+    pub ntime: u32,
+    /// Version bits rolled by the miner, if it submitted any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_bits: Option<u32>,
+    /// Whether the share met the target of the miner and is forwarded upstream.
+    pub accepted: bool,
+    /// Reason the share was rejected, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A custom job set on a Pool channel with `SetCustomMiningJob`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CustomJobEvent {
+    /// Identifier of the client setting the job (usually its peer address).
+    pub client: String,
+    /// Channel the job was set on.
+    pub channel_id: u32,
+    /// `request_id` of the `SetCustomMiningJob` message.
+    pub request_id: u32,
+    /// Hex encoded mining job token of the job.
+    pub token: String,
+    /// Hex encoded previous block hash the job builds on.
+    pub prev_hash: String,
+    /// Hex encoded coinbase outputs of the job.
+    pub coinbase_outputs: String,
+    /// Amount paid to the pool by the coinbase, in satoshis.
+    pub pool_payout: u64,
+    /// Whether the job was accepted.
+    pub accepted: bool,
+    /// Error code sent back in `SetCustomMiningJobError`, if the job was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+}
+
+/// Shares accepted by a Pool from a user in a round.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoundWork {
+    /// Number of shares accepted.
+    pub shares: u64,
+    /// Sum of the difficulty of the channel targets the shares were accepted at.
+    pub work: f64,
+}
+
+/// A round of a Pool: the shares accepted since the previous block found, up to the share
+/// finding a block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundEvent {
+    /// Number of the round since the Pool started, from 1.
+    pub round: u64,
+    /// Hex encoded hash of the block closing the round.
+    pub block_hash: String,
+    /// Unix time at which the round started, in milliseconds.
+    pub started_ms: u64,
+    /// Amount paid to the Pool by the coinbase of the block, in satoshis.
+    pub reward: u64,
+    /// Shares accepted in the round, the one finding the block included.
+    pub total: RoundWork,
+    /// Shares accepted in the round per user identity.
+    pub users: BTreeMap<String, RoundWork>,
+    /// Shares accepted in the round per account, the users of the same account summed up.
+    #[serde(default)]
+    pub accounts: BTreeMap<String, RoundWork>,
+}
+
+/// A transaction paying the users of a Pool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentEvent {
+    /// Id of the transaction.
+    pub txid: String,
+    /// Whether the transaction was submitted, `false` for a dry run.
+    pub submitted: bool,
+    /// Amount paid to each address, in satoshis.
+    pub payouts: BTreeMap<String, u64>,
+    /// Outpoints spent by the transaction, as `txid:vout`.
+    pub inputs: Vec<String>,
+    /// Fee paid by the transaction, in satoshis.
+    pub fee: u64,
+    /// Amount returned to the change address, in satoshis.
+    pub change: u64,
+    /// Hex encoded transaction, signed if it was submitted.
+    pub transaction: String,
+}
+
+/// A channel closed by a Pool, whether the downstream, the Pool or an operator closed it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelClosedEvent {
+    /// Identifier of the client the channel was opened by (usually its peer address).
+    pub client: String,
+    /// Identifier of the channel.
+    pub channel_id: u32,
+    /// User identity of the channel.
+    pub user_identity: String,
+    /// Whether the channel was an extended channel.
+    pub extended: bool,
+    /// Reason code of the closing, as in `CloseChannel`.
+    pub reason: String,
+}
+
+impl From<&ChannelClosed> for ChannelClosedEvent {
+    fn from(channel: &ChannelClosed) -> Self {
+        Self {
+            client: channel.client.clone(),
+            channel_id: channel.channel_id,
+            user_identity: channel.user_identity.clone(),
+            extended: channel.extended,
+            reason: channel.reason.clone(),
+        }
+    }
+}
+
+/// A downstream connection set up with a Pool, along with the device it described.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionEvent {
+    /// Identifier of the client (usually its peer address).
+    pub client: String,
+    /// Identifier of the connection.
+    pub connection_id: usize,
+    /// Protocol version negotiated.
+    pub version: u16,
+    /// Flags of the `SetupConnection`.
+    pub flags: u32,
+    pub vendor: String,
+    pub hardware_version: String,
+    pub firmware: String,
+    pub device_id: String,
+}
+
+impl From<&ConnectionSetUp> for ConnectionEvent {
+    fn from(connection: &ConnectionSetUp) -> Self {
+        Self {
+            client: connection.address.clone(),
+            connection_id: connection.connection_id,
+            version: connection.version,
+            flags: connection.flags,
+            vendor: connection.device.vendor.clone(),
+            hardware_version: connection.device.hardware_version.clone(),
+            firmware: connection.device.firmware.clone(),
+            device_id: connection.device.device_id.clone(),
+        }
+    }
+}
@@ -0,0 +1,616 @@
+//! Durable event recording for SV2 applications
+//!
+//! This module provides a small, non-blocking persistence layer that roles can use to keep a
+//! durable record of protocol level events (job declarations, custom jobs, solutions, shares,
+//! ...).
+//!
+//! - [`PersistenceConfig`] is meant to be embedded in role configuration files as an optional
+//!   `[persistence]` table and selects the storage backend.
+//! - [`Persistence`] is a cheap-to-clone handle used on the hot path. Recording an event never
+//!   blocks: events are handed over to a dedicated writer thread through a bounded channel and are
+//!   dropped (with a warning) if the writer cannot keep up.
+//! - [`PersistenceBackend`] abstracts the storage. [`FileBackend`] appends one JSON object per
+//!   line to a file.
+//! - [`Persistence`] is an [`EventSubscriber`] recording the shares validated, the connections
+//!   set up and the channels closed on an [`EventBus`](crate::events::EventBus).
+//! - [`PayoutBatch`] splits the reward of a persisted round between its users, to export payouts
+//!   to a wallet, and [`owed_balances`] deducts the [`PaymentEvent`]s already made.
+
+mod event;
+mod payout;
+
+pub use event::{
+    ChannelClosedEvent, ConnectionEvent, CustomJobEvent, JobDeclarationDecision,
+    JobDeclarationEvent, PaymentEvent, PersistenceEvent, RoundEvent, RoundWork, ShareEvent,
+    SolutionEvent, Sv1ShareEvent,
+};
+pub use payout::{
+    owed_balances, read_payments, read_rounds, Payout, PayoutAddresses, PayoutBatch, PayoutFormat,
+    UnmappedPayout,
+};
+
+use crate::{
+    events::{Event, EventSubscriber},
+    unix_time,
+};
+use async_channel::{Receiver, Sender, TrySendError};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{error, info, warn};
+
+/// Maximum number of events waiting to be written before new events are dropped.
+const PERSISTENCE_CHANNEL_SIZE: usize = 10_000;
+
+/// Persistence errors
+#[derive(Debug)]
+pub enum Error {
+    /// I/O error while opening or writing the storage
+    Io(std::io::Error),
+    /// Error serializing an event
+    Serialize(serde_json::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "Persistence I/O error: `{e:?}`"),
+            Error::Serialize(e) => write!(f, "Persistence serialization error: `{e:?}`"),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Serialize(e)
+    }
+}
+
+/// Persistence backend configuration.
+///
+/// Deserialized from a `[persistence]` table, with the `backend` key selecting the variant:
+///
+/// ```toml
+/// [persistence]
+/// backend = "file"
+/// path = "./events.jsonl"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum PersistenceConfig {
+    /// Append events as JSON lines to the file at `path`.
+    File { path: PathBuf },
+}
+
+/// A persisted event, stamped with the time it was recorded.
+#[derive(Debug, Clone, Serialize)]
+pub struct PersistenceRecord {
+    /// Milliseconds since the UNIX epoch at which the event was recorded.
+    pub timestamp_ms: u64,
+    /// Bitcoin network of the role which recorded the event, if configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<Arc<str>>,
+    #[serde(flatten)]
+    pub event: PersistenceEvent,
+}
+
+impl PersistenceRecord {
+    /// Stamps `event` with the current time and `network`, for roles writing to a backend
+    /// directly rather than through a [`Persistence`] handle.
+    pub fn new(event: impl Into<PersistenceEvent>, network: Option<impl Display>) -> Self {
+        Self {
+            timestamp_ms: unix_time::now_ms(),
+            network: network.map(|network| network.to_string().into()),
+            event: event.into(),
+        }
+    }
+}
+
+/// Storage for [`PersistenceRecord`]s.
+///
+/// Backends are driven from a dedicated thread, so implementations are free to block.
+pub trait PersistenceBackend: Send + 'static {
+    /// Stores a single record.
+    fn persist(&mut self, record: &PersistenceRecord) -> Result<(), Error>;
+
+    /// Flushes any buffered record to the underlying storage.
+    fn flush(&mut self) -> Result<(), Error>;
+}
+
+/// Backend appending one JSON object per line to a file.
+pub struct FileBackend {
+    writer: BufWriter<File>,
+}
+
+impl FileBackend {
+    /// Opens (or creates) the file at `path` in append mode.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl PersistenceBackend for FileBackend {
+    fn persist(&mut self, record: &PersistenceRecord) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Handle used to record [`PersistenceEvent`]s.
+///
+/// A disabled handle (the [`Default`]) silently discards every event, so roles can hold a
+/// `Persistence` unconditionally and only pay for it when it is configured.
+#[derive(Debug, Clone, Default)]
+pub struct Persistence {
+    sender: Option<Sender<PersistenceRecord>>,
+    network: Option<Arc<str>>,
+}
+
+impl Persistence {
+    /// Builds a handle from an optional configuration.
+    ///
+    /// Returns a disabled handle if `config` is `None`.
+    pub fn new(config: Option<&PersistenceConfig>) -> Result<Self, Error> {
+        match config {
+            Some(PersistenceConfig::File { path }) => {
+                info!("Persisting events to {}", path.display());
+                Ok(Self::with_backend(FileBackend::open(path)?))
+            }
+            None => Ok(Self::disabled()),
+        }
+    }
+
+    /// Returns a handle that discards every event.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Builds a handle writing to a custom backend.
+    ///
+    /// The backend is moved to a dedicated writer thread which runs until every handle is
+    /// dropped.
+    pub fn with_backend<B: PersistenceBackend>(backend: B) -> Self {
+        let (sender, receiver) = async_channel::bounded(PERSISTENCE_CHANNEL_SIZE);
+        std::thread::Builder::new()
+            .name("persistence".to_string())
+            .spawn(move || run_writer(backend, receiver))
+            .expect("Failed to spawn persistence writer thread");
+        Self {
+            sender: Some(sender),
+            network: None,
+        }
+    }
+
+    /// Tags the events recorded through this handle with `network`, so that the records of
+    /// roles deployed on different networks cannot be mixed up.
+    pub fn with_network(mut self, network: Option<impl Display>) -> Self {
+        self.network = network.map(|network| network.to_string().into());
+        self
+    }
+
+    /// Returns `true` if events recorded through this handle are stored.
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Records an event without blocking.
+    pub fn record(&self, event: impl Into<PersistenceEvent>) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let record = PersistenceRecord {
+            timestamp_ms: unix_time::now_ms(),
+            network: self.network.clone(),
+            event: event.into(),
+        };
+        match sender.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(record)) => {
+                warn!("Persistence queue is full, dropping event: {:?}", record);
+            }
+            Err(TrySendError::Closed(record)) => {
+                error!("Persistence writer is gone, dropping event: {:?}", record);
+            }
+        }
+    }
+}
+
+impl EventSubscriber for Persistence {
+    fn handle(&mut self, event: &Event) {
+        match event {
+            Event::ShareValidated(share) => self.record(ShareEvent::from(share)),
+            Event::ChannelClosed(channel) => self.record(ChannelClosedEvent::from(channel)),
+            Event::ConnectionSetUp(connection) => self.record(ConnectionEvent::from(connection)),
+            _ => {}
+        }
+    }
+}
+
+fn run_writer<B: PersistenceBackend>(mut backend: B, receiver: Receiver<PersistenceRecord>) {
+    while let Ok(record) = receiver.recv_blocking() {
+        if let Err(e) = backend.persist(&record) {
+            error!("Failed to persist event: {e}");
+        }
+        // Batch writes while events keep coming, flush as soon as the queue drains.
+        if receiver.is_empty() {
+            if let Err(e) = backend.flush() {
+                error!("Failed to flush persisted events: {e}");
+            }
+        }
+    }
+    if let Err(e) = backend.flush() {
+        error!("Failed to flush persisted events: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct MemoryBackend(Arc<Mutex<Vec<PersistenceRecord>>>);
+
+    impl PersistenceBackend for MemoryBackend {
+        fn persist(&mut self, record: &PersistenceRecord) -> Result<(), Error> {
+            self.0.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn solution() -> SolutionEvent {
+        SolutionEvent {
+            client: "127.0.0.1:3333".to_string(),
+            prev_hash: "00".repeat(32),
+            extranonce: "abcd".to_string(),
+            version: 0x2000_0000,
+            ntime: 1_700_000_000,
+            nonce: 42,
+            nbits: 0x1d00_ffff,
+        }
+    }
+
+    #[test]
+    fn record_serializes_as_tagged_json_line() {
+        let record = PersistenceRecord {
+            timestamp_ms: 1,
+            network: None,
+            event: JobDeclarationEvent {
+                client: "peer".to_string(),
+                request_id: 7,
+                token: "01000000".to_string(),
+                template_hash: "ff".to_string(),
+                tx_count: 2,
+                decision: JobDeclarationDecision::Accepted,
+                error_code: None,
+                processing_time_us: 10,
+            }
+            .into(),
+        };
+        let json: serde_json::Value =
+            serde_json::from_slice(&serde_json::to_vec(&record).unwrap()).unwrap();
+        assert_eq!(json["timestamp_ms"], 1);
+        assert_eq!(json["type"], "job_declaration");
+        assert_eq!(json["decision"], "accepted");
+        assert_eq!(json["request_id"], 7);
+        assert!(json.get("error_code").is_none());
+        assert!(json.get("network").is_none());
+    }
+
+    #[test]
+    fn rejected_share_records_its_error_code() {
+        let record = PersistenceRecord {
+            timestamp_ms: 1,
+            network: Some("testnet4".into()),
+            event: ShareEvent {
+                client: "127.0.0.1:3333".to_string(),
+                channel_id: 1,
+                account: None,
+                worker: None,
+                sequence_number: 2,
+                job_id: 3,
+                nonce: 42,
+                ntime: 1_700_000_000,
+                version: 0x2000_0000,
+                accepted: false,
+                error_code: Some("stale-share".to_string()),
+                share_difficulty: None,
+                best_share_difficulty: None,
+                notable: false,
+            }
+            .into(),
+        };
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["type"], "share");
+        assert_eq!(json["accepted"], false);
+        assert_eq!(json["error_code"], "stale-share");
+        assert_eq!(json["network"], "testnet4");
+        assert!(json.get("share_difficulty").is_none());
+        assert!(json.get("notable").is_none());
+    }
+
+    #[test]
+    fn accepted_share_records_its_difficulty() {
+        let record = PersistenceRecord {
+            timestamp_ms: 1,
+            network: None,
+            event: ShareEvent {
+                client: "127.0.0.1:3333".to_string(),
+                channel_id: 1,
+                account: Some("alice".to_string()),
+                worker: Some("worker1".to_string()),
+                sequence_number: 2,
+                job_id: 3,
+                nonce: 42,
+                ntime: 1_700_000_000,
+                version: 0x2000_0000,
+                accepted: true,
+                error_code: None,
+                share_difficulty: Some(1500.0),
+                best_share_difficulty: Some(2048.0),
+                notable: true,
+            }
+            .into(),
+        };
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["accepted"], true);
+        assert_eq!(json["account"], "alice");
+        assert_eq!(json["worker"], "worker1");
+        assert_eq!(json["share_difficulty"], 1500.0);
+        assert_eq!(json["best_share_difficulty"], 2048.0);
+        assert_eq!(json["notable"], true);
+    }
+
+    #[test]
+    fn sv1_share_records_its_sv2_identifiers() {
+        let record = PersistenceRecord {
+            timestamp_ms: 1,
+            network: None,
+            event: Sv1ShareEvent {
+                client: "10.0.0.2:50123".to_string(),
+                downstream_id: 4,
+                worker: "alice.worker1".to_string(),
+                channel_id: 1,
+                sv1_job_id: "12".to_string(),
+                job_id: Some(12),
+                extranonce: "0000000100000002".to_string(),
+                nonce: 42,
+                ntime: 1_700_000_000,
+                version_bits: None,
+                accepted: false,
+                error: Some("Job not found during share validation".to_string()),
+            }
+            .into(),
+        };
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["type"], "sv1_share");
+        assert_eq!(json["channel_id"], 1);
+        assert_eq!(json["sv1_job_id"], "12");
+        assert_eq!(json["job_id"], 12);
+        assert_eq!(json["accepted"], false);
+        assert_eq!(json["error"], "Job not found during share validation");
+        assert!(json.get("version_bits").is_none());
+    }
+
+    #[test]
+    fn round_records_the_work_of_its_users() {
+        let record = PersistenceRecord {
+            timestamp_ms: 2,
+            network: None,
+            event: RoundEvent {
+                round: 1,
+                block_hash: "00".repeat(32),
+                started_ms: 1,
+                reward: 312_500_000,
+                total: RoundWork {
+                    shares: 3,
+                    work: 3072.0,
+                },
+                users: [(
+                    "alice.worker1".to_string(),
+                    RoundWork {
+                        shares: 3,
+                        work: 3072.0,
+                    },
+                )]
+                .into(),
+                accounts: [(
+                    "alice".to_string(),
+                    RoundWork {
+                        shares: 3,
+                        work: 3072.0,
+                    },
+                )]
+                .into(),
+            }
+            .into(),
+        };
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["type"], "round");
+        assert_eq!(json["total"]["shares"], 3);
+        assert_eq!(json["users"]["alice.worker1"]["work"], 3072.0);
+        assert_eq!(json["accounts"]["alice"]["shares"], 3);
+    }
+
+    #[test]
+    fn file_backend_appends_lines() {
+        let path = std::env::temp_dir().join(format!(
+            "stratum-apps-persistence-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        {
+            let mut backend = FileBackend::open(&path).unwrap();
+            for _ in 0..2 {
+                let record = PersistenceRecord {
+                    timestamp_ms: unix_time::now_ms(),
+                    network: None,
+                    event: solution().into(),
+                };
+                backend.persist(&record).unwrap();
+            }
+            backend.flush().unwrap();
+        }
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let json: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(json["type"], "solution");
+            assert_eq!(json["nonce"], 42);
+        }
+    }
+
+    #[test]
+    fn disabled_handle_discards_events() {
+        let persistence = Persistence::new(None).unwrap();
+        assert!(!persistence.is_enabled());
+        persistence.record(solution());
+    }
+
+    #[test]
+    fn handle_forwards_events_to_backend() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let persistence = Persistence::with_backend(MemoryBackend(records.clone()));
+        assert!(persistence.is_enabled());
+        persistence.record(solution());
+        persistence.record(solution());
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while records.lock().unwrap().len() < 2 && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(records.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn subscriber_records_the_validated_shares() {
+        use crate::events::{ShareValidated, TemplateReceived};
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut persistence = Persistence::with_backend(MemoryBackend(records.clone()));
+        persistence.handle(&Event::TemplateReceived(TemplateReceived {
+            template_id: 1,
+            future_template: false,
+            coinbase_value: 312_500_000,
+        }));
+        persistence.handle(&Event::ShareValidated(ShareValidated {
+            connection_id: 1,
+            client: "127.0.0.1:3333".to_string(),
+            channel_id: 1,
+            user_identity: None,
+            account: None,
+            worker: None,
+            sequence_number: 2,
+            job_id: 3,
+            nonce: 42,
+            ntime: 1_700_000_000,
+            version: 0x2000_0000,
+            error_code: Some("stale-share".to_string()),
+            target_difficulty: None,
+            share_difficulty: None,
+            best_share_difficulty: None,
+            share_hash: None,
+            notable: false,
+        }));
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while records.lock().unwrap().is_empty() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        let PersistenceEvent::Share(share) = &records[0].event else {
+            panic!("unexpected event {:?}", records[0].event);
+        };
+        assert!(!share.accepted);
+        assert_eq!(share.error_code.as_deref(), Some("stale-share"));
+    }
+    #[test]
+    fn subscriber_records_the_closed_channels() {
+        use crate::events::ChannelClosed;
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut persistence = Persistence::with_backend(MemoryBackend(records.clone()));
+        persistence.handle(&Event::ChannelClosed(ChannelClosed {
+            connection_id: 1,
+            client: "127.0.0.1:3333".to_string(),
+            channel_id: 2,
+            user_identity: "alice.worker1".to_string(),
+            extended: true,
+            reason: "share-rate-limit-exceeded".to_string(),
+        }));
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while records.lock().unwrap().is_empty() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        let json: serde_json::Value =
+            serde_json::from_slice(&serde_json::to_vec(&records[0]).unwrap()).unwrap();
+        assert_eq!(json["type"], "channel_closed");
+        assert_eq!(json["channel_id"], 2);
+        assert_eq!(json["user_identity"], "alice.worker1");
+        assert_eq!(json["reason"], "share-rate-limit-exceeded");
+    }
+
+    #[test]
+    fn subscriber_records_the_devices_of_the_connections() {
+        use crate::events::{ConnectionSetUp, DeviceInfo};
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut persistence = Persistence::with_backend(MemoryBackend(records.clone()));
+        persistence.handle(&Event::ConnectionSetUp(ConnectionSetUp {
+            connection_id: 3,
+            address: "127.0.0.1:3333".to_string(),
+            version: 2,
+            flags: 0b100,
+            device: DeviceInfo {
+                vendor: "Bitmain".to_string(),
+                hardware_version: "S19".to_string(),
+                firmware: "2.1.0".to_string(),
+                device_id: "rig12".to_string(),
+            },
+        }));
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while records.lock().unwrap().is_empty() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        let json: serde_json::Value =
+            serde_json::from_slice(&serde_json::to_vec(&records[0]).unwrap()).unwrap();
+        assert_eq!(json["type"], "connection");
+        assert_eq!(json["connection_id"], 3);
+        assert_eq!(json["vendor"], "Bitmain");
+        assert_eq!(json["firmware"], "2.1.0");
+        assert_eq!(json["device_id"], "rig12");
+    }
+}
@@ -0,0 +1,435 @@
+//! Payout batches built from the rounds of a Pool
+//!
+//! A [`PayoutBatch`] splits the reward of the block closing a [`RoundEvent`] between the users of
+//! the round, in proportion to their work, and pays each share to the address [`PayoutAddresses`]
+//! maps the user to. The batches are rendered as CSV or JSON by [`PayoutFormat`], to be fed to
+//! the wallet or payment service actually paying them.
+//!
+//! The address file holds one user per line and can be edited by hand:
+//!
+//! ```text
+//! # user_identity address
+//! alice tb1qa0sm0hxzj0x25rh8gw5xlzwlsfvvyz8u96w3p8
+//! bob.rig1 tb1q3rpmh0c7ex8f4x0kdn8fh6zr6u4ws8w0lxz2ml
+//! ```
+//!
+//! A user identity is paid to its own address if listed, and otherwise to the address of its
+//! account, the part before the first separator (`.` unless set with
+//! [`PayoutAddresses::with_separator`]) of `account.worker` identities.
+//!
+//! [`owed_balances`] nets the batches against the [`PaymentEvent`]s already submitted, so that a
+//! payment only pays what is still owed to each address.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display},
+    fs,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+use super::{PaymentEvent, RoundEvent};
+use crate::worker_identity::DEFAULT_WORKER_SEPARATOR;
+
+/// Payout addresses of the users of a Pool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayoutAddresses {
+    addresses: HashMap<String, String>,
+    separator: String,
+}
+
+impl Default for PayoutAddresses {
+    fn default() -> Self {
+        Self {
+            addresses: HashMap::new(),
+            separator: DEFAULT_WORKER_SEPARATOR.to_string(),
+        }
+    }
+}
+
+impl PayoutAddresses {
+    /// Loads the addresses from the file at `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Sets the separator between the account and the worker of user identities.
+    pub fn with_separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// Returns the address `user_identity` is paid to, its own or the one of its account.
+    pub fn address(&self, user_identity: &str) -> Option<&str> {
+        self.addresses
+            .get(user_identity)
+            .or_else(|| {
+                let (account, _) = user_identity.split_once(self.separator.as_str())?;
+                self.addresses.get(account)
+            })
+            .map(String::as_str)
+    }
+
+    /// Returns the users and accounts with an address, and their address.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.addresses
+            .iter()
+            .map(|(user, address)| (user.as_str(), address.as_str()))
+    }
+}
+
+impl FromStr for PayoutAddresses {
+    type Err = String;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        let mut addresses = HashMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(user), Some(address), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(format!(
+                    "line {}: expected `<user_identity> <address>`",
+                    index + 1
+                ));
+            };
+            if addresses
+                .insert(user.to_string(), address.to_string())
+                .is_some()
+            {
+                return Err(format!("line {}: `{user}` is listed twice", index + 1));
+            }
+        }
+        Ok(Self {
+            addresses,
+            ..Self::default()
+        })
+    }
+}
+
+/// Amount paid to an address.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Payout {
+    /// Address paid.
+    pub address: String,
+    /// Amount paid, in satoshis.
+    pub amount: u64,
+    /// User identities whose work is paid to the address.
+    pub users: Vec<String>,
+}
+
+/// Amount owed to a user without a payout address.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnmappedPayout {
+    /// User identity without an address.
+    pub user_identity: String,
+    /// Amount owed, in satoshis.
+    pub amount: u64,
+}
+
+/// Reward of the block closing a round, split between the users of the round.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PayoutBatch {
+    /// Number of the round.
+    pub round: u64,
+    /// Hex encoded hash of the block closing the round.
+    pub block_hash: String,
+    /// Amount paid to the Pool by the coinbase of the block, in satoshis.
+    pub reward: u64,
+    /// Amounts paid to each address, in address order.
+    pub payouts: Vec<Payout>,
+    /// Amounts owed to the users without an address, left out of the payouts.
+    pub unmapped: Vec<UnmappedPayout>,
+    /// Satoshis left over by rounding every amount down.
+    pub remainder: u64,
+}
+
+impl PayoutBatch {
+    /// Splits the reward of `round` between its users in proportion to their work, and pays
+    /// each amount to the address of the user in `addresses`.
+    pub fn new(round: &RoundEvent, addresses: &PayoutAddresses) -> Self {
+        let mut payouts: BTreeMap<&str, Payout> = BTreeMap::new();
+        let mut unmapped = Vec::new();
+        let mut paid = 0;
+        for (user_identity, work) in &round.users {
+            let amount = if round.total.work > 0.0 {
+                (round.reward as f64 * work.work / round.total.work) as u64
+            } else {
+                0
+            };
+            paid += amount;
+            match addresses.address(user_identity) {
+                Some(address) => {
+                    let payout = payouts.entry(address).or_insert_with(|| Payout {
+                        address: address.to_string(),
+                        amount: 0,
+                        users: Vec::new(),
+                    });
+                    payout.amount += amount;
+                    payout.users.push(user_identity.clone());
+                }
+                None => unmapped.push(UnmappedPayout {
+                    user_identity: user_identity.clone(),
+                    amount,
+                }),
+            }
+        }
+        Self {
+            round: round.round,
+            block_hash: round.block_hash.clone(),
+            reward: round.reward,
+            payouts: payouts.into_values().collect(),
+            unmapped,
+            remainder: round.reward.saturating_sub(paid),
+        }
+    }
+}
+
+/// Returns the amount still owed to each address by `batches`, once the submitted `payments`
+/// are deducted.
+pub fn owed_balances(batches: &[PayoutBatch], payments: &[PaymentEvent]) -> BTreeMap<String, u64> {
+    let mut owed: BTreeMap<String, u64> = BTreeMap::new();
+    for payout in batches.iter().flat_map(|batch| &batch.payouts) {
+        *owed.entry(payout.address.clone()).or_default() += payout.amount;
+    }
+    for payment in payments.iter().filter(|payment| payment.submitted) {
+        for (address, amount) in &payment.payouts {
+            if let Some(balance) = owed.get_mut(address) {
+                *balance = balance.saturating_sub(*amount);
+            }
+        }
+    }
+    owed.retain(|_, balance| *balance > 0);
+    owed
+}
+
+/// Reads the rounds persisted to the file at `path`, skipping the other events and, if
+/// `network` is set, the rounds recorded on another network.
+pub fn read_rounds(path: &Path, network: Option<&str>) -> io::Result<Vec<RoundEvent>> {
+    read_events(path, "round", network)
+}
+
+/// Reads the payments persisted to the file at `path`, skipping the other events and, if
+/// `network` is set, the payments recorded on another network.
+pub fn read_payments(path: &Path, network: Option<&str>) -> io::Result<Vec<PaymentEvent>> {
+    read_events(path, "payment", network)
+}
+
+// Reads the events of type `kind` persisted to the file at `path`.
+fn read_events<T: DeserializeOwned>(
+    path: &Path,
+    kind: &str,
+    network: Option<&str>,
+) -> io::Result<Vec<T>> {
+    let mut events = Vec::new();
+    for (index, line) in BufReader::new(fs::File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |e: serde_json::Error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {e}", index + 1),
+            )
+        };
+        let record: serde_json::Value = serde_json::from_str(&line).map_err(invalid)?;
+        if record["type"] != kind {
+            continue;
+        }
+        if let (Some(network), Some(recorded)) = (network, record["network"].as_str()) {
+            if network != recorded {
+                continue;
+            }
+        }
+        events.push(serde_json::from_value(record).map_err(invalid)?);
+    }
+    Ok(events)
+}
+
+/// Format of exported payout batches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PayoutFormat {
+    /// One `round,block_hash,address,amount_sat` line per payout, after a header line.
+    #[default]
+    Csv,
+    /// An array of [`PayoutBatch`] objects, the unmapped users included.
+    Json,
+}
+
+impl PayoutFormat {
+    /// Renders `batches` in this format.
+    pub fn render(self, batches: &[PayoutBatch]) -> String {
+        match self {
+            PayoutFormat::Csv => {
+                let mut csv = String::from("round,block_hash,address,amount_sat\n");
+                for batch in batches {
+                    for payout in &batch.payouts {
+                        csv.push_str(&format!(
+                            "{},{},{},{}\n",
+                            batch.round, batch.block_hash, payout.address, payout.amount
+                        ));
+                    }
+                }
+                csv
+            }
+            PayoutFormat::Json => serde_json::to_string_pretty(batches)
+                .expect("payout batches are always serializable"),
+        }
+    }
+
+    /// Returns the MIME type of the rendered batches.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            PayoutFormat::Csv => "text/csv",
+            PayoutFormat::Json => "application/json",
+        }
+    }
+}
+
+impl FromStr for PayoutFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "csv" => Ok(PayoutFormat::Csv),
+            "json" => Ok(PayoutFormat::Json),
+            _ => Err(format!(
+                "unknown payout format `{format}`, expected `csv` or `json`"
+            )),
+        }
+    }
+}
+
+impl Display for PayoutFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PayoutFormat::Csv => "csv",
+            PayoutFormat::Json => "json",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{PersistenceRecord, RoundWork};
+
+    fn round() -> RoundEvent {
+        let work = |shares, work| RoundWork { shares, work };
+        RoundEvent {
+            round: 4,
+            block_hash: "00ab".to_string(),
+            started_ms: 1,
+            reward: 1_000,
+            total: work(4, 3.0),
+            users: [
+                ("alice.rig1".to_string(), work(1, 1.0)),
+                ("alice.rig2".to_string(), work(1, 1.0)),
+                ("carol".to_string(), work(2, 1.0)),
+            ]
+            .into(),
+            accounts: [
+                ("alice".to_string(), work(2, 2.0)),
+                ("carol".to_string(), work(2, 1.0)),
+            ]
+            .into(),
+        }
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let addresses: PayoutAddresses = "# user address\nalice addr-a\n\nbob.rig1 addr-b\n"
+            .parse()
+            .unwrap();
+        assert_eq!(addresses.address("alice"), Some("addr-a"));
+        assert_eq!(addresses.address("alice.rig7"), Some("addr-a"));
+        assert_eq!(addresses.address("bob.rig1"), Some("addr-b"));
+        assert_eq!(addresses.address("bob.rig2"), None);
+        assert!("alice".parse::<PayoutAddresses>().is_err());
+        assert!("alice a\nalice b".parse::<PayoutAddresses>().is_err());
+
+        let addresses = addresses.with_separator("+");
+        assert_eq!(addresses.address("alice+rig7"), Some("addr-a"));
+        assert_eq!(addresses.address("alice.rig7"), None);
+    }
+
+    #[test]
+    fn splits_the_reward_by_work() {
+        let addresses: PayoutAddresses = "alice addr-a".parse().unwrap();
+        let batch = PayoutBatch::new(&round(), &addresses);
+        assert_eq!(
+            batch.payouts,
+            vec![Payout {
+                address: "addr-a".to_string(),
+                amount: 666,
+                users: vec!["alice.rig1".to_string(), "alice.rig2".to_string()],
+            }]
+        );
+        assert_eq!(
+            batch.unmapped,
+            vec![UnmappedPayout {
+                user_identity: "carol".to_string(),
+                amount: 333,
+            }]
+        );
+        assert_eq!(batch.remainder, 1);
+        assert_eq!(
+            PayoutFormat::Csv.render(std::slice::from_ref(&batch)),
+            "round,block_hash,address,amount_sat\n4,00ab,addr-a,666\n"
+        );
+
+        let payment = |amount, submitted| PaymentEvent {
+            txid: "11".repeat(32),
+            submitted,
+            payouts: [("addr-a".to_string(), amount)].into(),
+            inputs: Vec::new(),
+            fee: 0,
+            change: 0,
+            transaction: String::new(),
+        };
+        let batches = [batch.clone(), batch];
+        assert_eq!(
+            owed_balances(&batches, &[]),
+            [("addr-a".to_string(), 1332)].into()
+        );
+        assert_eq!(
+            owed_balances(&batches, &[payment(1000, true), payment(332, false)]),
+            [("addr-a".to_string(), 332)].into()
+        );
+        assert!(owed_balances(&batches, &[payment(1332, true)]).is_empty());
+    }
+
+    #[test]
+    fn reads_persisted_rounds() {
+        let path =
+            std::env::temp_dir().join(format!("stratum-apps-payout-{}.jsonl", std::process::id()));
+        let round = serde_json::to_value(PersistenceRecord {
+            timestamp_ms: 1,
+            network: Some("testnet4".into()),
+            event: round().into(),
+        })
+        .unwrap();
+        let mut other_network = round.clone();
+        other_network["network"] = "signet".into();
+        fs::write(
+            &path,
+            format!("{{\"type\":\"share\"}}\n{round}\n{other_network}\n"),
+        )
+        .unwrap();
+
+        assert_eq!(read_rounds(&path, None).unwrap().len(), 2);
+        let rounds = read_rounds(&path, Some("testnet4")).unwrap();
+        assert_eq!(rounds.len(), 1);
+        assert_eq!(rounds[0].reward, 1_000);
+        assert_eq!(rounds[0].users["carol"].shares, 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
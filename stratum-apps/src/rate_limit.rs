@@ -0,0 +1,126 @@
+//! Token bucket rate limiting
+//!
+//! A [`TokenBucket`] holds up to `burst` tokens and is refilled continuously at `rate` tokens
+//! per second. Every accepted message consumes one token; when the bucket is empty the message
+//! is over the limit.
+
+use serde::Deserialize;
+use std::time::Instant;
+
+/// Configuration of a [`TokenBucket`].
+///
+/// ```toml
+/// rate = 5.0   # tokens refilled per second
+/// burst = 10   # bucket capacity
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct TokenBucketConfig {
+    /// Number of tokens refilled per second.
+    pub rate: f64,
+    /// Maximum number of tokens the bucket can hold.
+    pub burst: u32,
+}
+
+impl TokenBucketConfig {
+    /// Creates a new [`TokenBucketConfig`].
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self { rate, burst }
+    }
+}
+
+/// A token bucket rate limiter.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket from its configuration.
+    pub fn new(config: TokenBucketConfig) -> Self {
+        let capacity = config.burst as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            rate: config.rate.max(0.0),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Consumes one token if available.
+    ///
+    /// Returns `false` if the caller is over the limit.
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    /// Returns the number of whole tokens currently available.
+    pub fn available(&mut self) -> u32 {
+        self.refill(Instant::now());
+        self.tokens as u32
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn allows_burst_then_limits() {
+        let mut bucket = TokenBucket::new(TokenBucketConfig::new(1.0, 3));
+        let now = bucket.last_refill;
+        assert!(bucket.try_acquire_at(now));
+        assert!(bucket.try_acquire_at(now));
+        assert!(bucket.try_acquire_at(now));
+        assert!(!bucket.try_acquire_at(now));
+    }
+
+    #[test]
+    fn refills_over_time_up_to_capacity() {
+        let mut bucket = TokenBucket::new(TokenBucketConfig::new(2.0, 2));
+        let start = bucket.last_refill;
+        assert!(bucket.try_acquire_at(start));
+        assert!(bucket.try_acquire_at(start));
+        assert!(!bucket.try_acquire_at(start));
+
+        // Half a second at 2 tokens/s gives back exactly one token.
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.try_acquire_at(later));
+        assert!(!bucket.try_acquire_at(later));
+
+        // A long idle period never overfills the bucket.
+        let much_later = later + Duration::from_secs(60);
+        assert!(bucket.try_acquire_at(much_later));
+        assert!(bucket.try_acquire_at(much_later));
+        assert!(!bucket.try_acquire_at(much_later));
+    }
+
+    #[test]
+    fn zero_rate_never_refills() {
+        let mut bucket = TokenBucket::new(TokenBucketConfig::new(0.0, 1));
+        let start = bucket.last_refill;
+        assert!(bucket.try_acquire_at(start));
+        assert!(!bucket.try_acquire_at(start + Duration::from_secs(3600)));
+    }
+}
@@ -12,7 +12,7 @@ use hyper_util::{
     client::legacy::{connect::HttpConnector, Client},
     rt::TokioExecutor,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
 use stratum_core::bitcoin::{consensus::encode::deserialize as consensus_decode, Transaction};
 
@@ -99,6 +99,38 @@ impl MiniRpcClient {
         }
     }
 
+    /// Lists the unspent outputs of the wallet the client is connected to.
+    pub async fn list_unspent(&self) -> Result<Vec<Unspent>, RpcError> {
+        self.call("listunspent", json!([])).await
+    }
+
+    /// Signs the inputs of the hex encoded `transaction` the wallet has the keys of.
+    pub async fn sign_raw_transaction_with_wallet(
+        &self,
+        transaction: &str,
+    ) -> Result<SignedTransaction, RpcError> {
+        self.call("signrawtransactionwithwallet", json!([transaction]))
+            .await
+    }
+
+    /// Broadcasts the hex encoded signed `transaction`, and returns its txid.
+    pub async fn send_raw_transaction(&self, transaction: &str) -> Result<String, RpcError> {
+        self.call("sendrawtransaction", json!([transaction])).await
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<T, RpcError> {
+        let response = self.send_json_rpc_request(method, params).await?;
+        let result: JsonRpcResult<T> = serde_json::from_str(&response)
+            .map_err(|e| RpcError::Deserialization(e.to_string()))?;
+        result
+            .result
+            .ok_or_else(|| RpcError::Other("Result not found".to_string()))
+    }
+
     async fn send_json_rpc_request(
         &self,
         method: &str,
@@ -176,6 +208,25 @@ impl Auth {
     }
 }
 
+/// An unspent output listed by `listunspent`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Unspent {
+    pub txid: String,
+    pub vout: u32,
+    /// Value of the output, in BTC.
+    pub amount: f64,
+    pub spendable: bool,
+}
+
+/// A transaction signed by `signrawtransactionwithwallet`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignedTransaction {
+    /// Hex encoded transaction.
+    pub hex: String,
+    /// Whether every input is signed.
+    pub complete: bool,
+}
+
 #[derive(Debug, Serialize)]
 struct JsonRpcRequest {
     jsonrpc: String,
@@ -0,0 +1,89 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::time::{timeout_at, Instant};
+use tracing::warn;
+
+/// Time given by default to an accepted connection to complete its handshake.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+static HANDSHAKE_TIMEOUTS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of accepted connections dropped by any listener of this process for not completing
+/// their handshake in time.
+pub fn handshake_timeouts() -> u64 {
+    HANDSHAKE_TIMEOUTS.load(Ordering::Relaxed)
+}
+
+/// Deadline for an accepted connection to complete its handshake (Noise handshake and
+/// `SetupConnection`, or their SV1 equivalent), started on TCP accept.
+///
+/// The handshake usually spans several steps, each run through [`HandshakeDeadline::run`]: the
+/// deadline is shared by all of them, so a peer sending its messages one byte at a time cannot
+/// hold the connection open longer than the timeout.
+#[derive(Debug, Clone, Copy)]
+pub struct HandshakeDeadline {
+    peer: SocketAddr,
+    timeout: Duration,
+    deadline: Instant,
+}
+
+impl HandshakeDeadline {
+    /// Starts the deadline of the connection accepted from `peer`.
+    pub fn start(peer: SocketAddr, timeout: Duration) -> Self {
+        Self {
+            peer,
+            timeout,
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    /// Runs a step of the handshake, or returns `None` if the deadline passes first, in which
+    /// case the connection is counted as timed out and should be dropped.
+    pub async fn run<F: Future>(&self, step: F) -> Option<F::Output> {
+        match timeout_at(self.deadline, step).await {
+            Ok(output) => Some(output),
+            Err(_) => {
+                self.timed_out();
+                None
+            }
+        }
+    }
+
+    /// Waits for the deadline to pass, for handshakes completed outside of
+    /// [`HandshakeDeadline::run`].
+    pub async fn expired(&self) {
+        tokio::time::sleep_until(self.deadline).await
+    }
+
+    /// Counts the connection as timed out.
+    pub fn timed_out(&self) {
+        HANDSHAKE_TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+        warn!(
+            "Dropping connection from {}: handshake not completed within {:?}",
+            self.peer, self.timeout
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn steps_share_the_deadline() {
+        let deadline =
+            HandshakeDeadline::start("127.0.0.1:3333".parse().unwrap(), Duration::from_millis(50));
+        let before = handshake_timeouts();
+
+        let step = tokio::time::sleep(Duration::from_millis(30));
+        assert_eq!(deadline.run(step).await, Some(()));
+        // Would complete within the timeout on its own, but not within what is left of it.
+        let step = tokio::time::sleep(Duration::from_millis(30));
+        assert_eq!(deadline.run(step).await, None);
+        assert!(handshake_timeouts() > before);
+    }
+}
@@ -0,0 +1,323 @@
+//! Reader and writer tasks of an SV2 connection.
+//!
+//! [`spawn_io_tasks`] moves frames between the two halves of an [`Sv2TcpStream`] and a pair of
+//! channels, so that the rest of a role only deals with channels. Each role has its own shutdown
+//! message and status reporting, which the tasks reach through [`IoTaskStatus`]. The writer
+//! writes the frames in the order its [`OutboundFrames`] queue hands them out. The capacities of
+//! the channels and the behavior of a full outbound one are set by an [`IoChannelsConfig`].
+//!
+//! [`Sv2TcpStream`]: crate::network_helpers::transport::Sv2TcpStream
+
+use std::{future::Future, num::NonZeroUsize, sync::Arc, time::Duration};
+
+use async_channel::{bounded, unbounded, Receiver, Sender};
+use serde::Deserialize;
+use stratum_core::{
+    buffer_sv2,
+    framing_sv2::framing::{Frame, Sv2Frame},
+    parsers_sv2::AnyMessage,
+};
+use tokio::{sync::broadcast, time::Instant};
+use tracing::{error, trace, warn, Instrument};
+
+use crate::{
+    network_helpers::{
+        traffic::ConnectionTraffic,
+        transport::{Sv2TcpReadHalf, Sv2TcpWriteHalf},
+    },
+    runtime::{
+        bounded_priority_channel, priority_channel, OutboundFrames, OverflowPolicy,
+        PriorityReceiver, PrioritySender, TaskManager,
+    },
+};
+
+type Message = AnyMessage<'static>;
+type SV2Frame = Sv2Frame<Message, buffer_sv2::Slice>;
+
+/// Capacities of the channels between the I/O tasks of a connection and its role, and behavior
+/// of a full outbound channel. Both channels are unbounded by default.
+///
+/// ```toml
+/// inbound_capacity = 256
+/// outbound_capacity = 1024
+/// overflow = "disconnect"
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct IoChannelsConfig {
+    /// Most received frames waiting to be handled by the role. The reader stops reading from the
+    /// connection while the channel is full.
+    pub inbound_capacity: Option<NonZeroUsize>,
+    /// Most frames waiting to be written to the peer.
+    pub outbound_capacity: Option<NonZeroUsize>,
+    /// What happens to a frame queued to a full outbound channel.
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+}
+
+impl IoChannelsConfig {
+    /// Creates a new [`IoChannelsConfig`].
+    pub fn new(
+        inbound_capacity: Option<NonZeroUsize>,
+        outbound_capacity: Option<NonZeroUsize>,
+        overflow: OverflowPolicy,
+    ) -> Self {
+        Self {
+            inbound_capacity,
+            outbound_capacity,
+            overflow,
+        }
+    }
+
+    /// Creates the channel the reader hands the received frames to the role through.
+    pub fn inbound_channel(&self) -> (Sender<SV2Frame>, Receiver<SV2Frame>) {
+        match self.inbound_capacity {
+            Some(capacity) => bounded(capacity.get()),
+            None => unbounded(),
+        }
+    }
+
+    /// Creates the [priority channel](priority_channel) the writer writes the frames of.
+    pub fn outbound_channel(&self) -> (PrioritySender, PriorityReceiver) {
+        match self.outbound_capacity {
+            Some(capacity) => bounded_priority_channel(capacity, self.overflow),
+            None => priority_channel(),
+        }
+    }
+}
+
+/// Ties the I/O tasks of a connection to the shutdown messages and status reporting of a role.
+///
+/// Usually implemented by the status sender of the component owning the connection, which knows
+/// which `Shutdown` messages concern it.
+pub trait IoTaskStatus<Shutdown>: Clone + Send + Sync + 'static {
+    /// Whether `message` must stop the I/O tasks of this connection.
+    fn stops_on(&self, message: &Shutdown) -> bool;
+
+    /// Reports that the reader closed the connection after `idle_timeout` without a frame.
+    ///
+    /// Does nothing by default: the closed channels are enough for the owner to notice.
+    fn idle_timeout(&self, _idle_timeout: Duration) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+/// Spawns async reader and writer tasks for handling framed I/O with shutdown support.
+/// Returns the traffic counters of the connection, updated by the tasks.
+///
+/// The tasks stop on the `Shutdown` messages `status` [stops on](IoTaskStatus::stops_on), or when
+/// either the connection or one of the channels is closed.
+///
+/// If `idle_timeout` is set, the connection is closed when no frame is received for that long.
+/// SV2 has no liveness probe, so the timeout must exceed the longest expected silence of the peer.
+#[track_caller]
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_io_tasks<Shutdown, Status, Outbound>(
+    task_manager: Arc<TaskManager>,
+    mut reader: Sv2TcpReadHalf<Message>,
+    mut writer: Sv2TcpWriteHalf<Message>,
+    outbound_rx: Outbound,
+    inbound_tx: Sender<SV2Frame>,
+    notify_shutdown: broadcast::Sender<Shutdown>,
+    status: Status,
+    idle_timeout: Option<Duration>,
+) -> Arc<ConnectionTraffic>
+where
+    Shutdown: Clone + std::fmt::Debug + Send + 'static,
+    Status: IoTaskStatus<Shutdown>,
+    Outbound: OutboundFrames,
+{
+    let caller = std::panic::Location::caller();
+    let traffic = Arc::new(ConnectionTraffic::new());
+    let inbound_tx_clone = inbound_tx.clone();
+    let outbound_rx_clone = outbound_rx.clone();
+    {
+        let mut shutdown_rx = notify_shutdown.subscribe();
+        let reader_traffic = traffic.clone();
+        let status = status.clone();
+
+        task_manager.spawn_named("reader", async move {
+            trace!("Reader task started");
+            let mut last_frame_at = Instant::now();
+            loop {
+                tokio::select! {
+                    message = shutdown_rx.recv() => {
+                        if let Ok(message) = message {
+                            if status.stops_on(&message) {
+                                trace!(?message, "Received shutdown");
+                                inbound_tx.close();
+                                break;
+                            }
+                        }
+                    }
+                    idle_timeout = idle_deadline(idle_timeout, last_frame_at) => {
+                        warn!(?idle_timeout, "No frame received, closing idle connection");
+                        status.idle_timeout(idle_timeout).await;
+                        inbound_tx.close();
+                        break;
+                    }
+                    res = reader.read_frame() => {
+                        match res {
+                            Ok(frame) => {
+                                last_frame_at = Instant::now();
+                                match frame {
+                                    Frame::HandShake(frame) => {
+                                        error!(?frame, "Received handshake frame");
+                                        drop(frame);
+                                        break;
+                                    },
+                                    Frame::Sv2(sv2_frame) => {
+                                        trace!("Received inbound frame");
+                                        reader_traffic.record_received(sv2_frame.encoded_length());
+                                        if let Err(e) = inbound_tx.send(sv2_frame).await {
+                                            inbound_tx.close();
+                                            error!(error=?e, "Failed to forward inbound frame");
+                                            break;
+                                        }
+                                        reader_traffic.record_inbound_queued(inbound_tx.len());
+                                    },
+                                }
+                            }
+                            Err(e) => {
+                                error!(error=?e, "Reader error");
+                                inbound_tx.close();
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            inbound_tx.close();
+            outbound_rx_clone.close();
+            drop(inbound_tx);
+            drop(outbound_rx_clone);
+            warn!("Reader task exited.");
+        }.instrument(tracing::trace_span!(
+            "reader_task",
+            spawned_at = %format!("{}:{}", caller.file(), caller.line())
+        )));
+    }
+
+    {
+        let mut shutdown_rx = notify_shutdown.subscribe();
+        let writer_traffic = traffic.clone();
+
+        task_manager.spawn_named(
+            "writer",
+            async move {
+                trace!("Writer task started");
+                loop {
+                    tokio::select! {
+                        message = shutdown_rx.recv() => {
+                            if let Ok(message) = message {
+                                if status.stops_on(&message) {
+                                    trace!(?message, "Received shutdown");
+                                    outbound_rx.close();
+                                    break;
+                                }
+                            }
+                        }
+                        res = outbound_rx.recv() => {
+                            match res {
+                                Some(frame) => {
+                                    trace!("Sending outbound frame");
+                                    writer_traffic.record_outbound_queued(outbound_rx.queued() + 1);
+                                    let frame_length = frame.encoded_length();
+                                    if let Err(e) = writer.write_frame(frame.into()).await {
+                                        error!(error=?e, "Writer error");
+                                        outbound_rx.close();
+                                        break;
+                                    }
+                                    writer_traffic.record_sent(frame_length);
+                                }
+                                None => {
+                                    outbound_rx.close();
+                                    warn!("Outbound channel closed");
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                outbound_rx.close();
+                inbound_tx_clone.close();
+                drop(outbound_rx);
+                drop(inbound_tx_clone);
+                warn!("Writer task exited.");
+            }
+            .instrument(tracing::trace_span!(
+                "writer_task",
+                spawned_at = %format!("{}:{}", caller.file(), caller.line())
+            )),
+        );
+    }
+    traffic
+}
+
+/// Completes once `idle_timeout` elapsed since `last_frame_at`, returning the timeout. Never
+/// completes if there is no timeout.
+async fn idle_deadline(idle_timeout: Option<Duration>, last_frame_at: Instant) -> Duration {
+    match idle_timeout {
+        Some(idle_timeout) => {
+            tokio::time::sleep_until(last_frame_at + idle_timeout).await;
+            idle_timeout
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_helpers::plain_stream::PlainTcpStream;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[derive(Debug, Clone)]
+    enum Shutdown {
+        All,
+        Other,
+    }
+
+    #[derive(Clone)]
+    struct StopOnAll;
+
+    impl IoTaskStatus<Shutdown> for StopOnAll {
+        fn stops_on(&self, message: &Shutdown) -> bool {
+            matches!(message, Shutdown::All)
+        }
+    }
+
+    #[tokio::test]
+    async fn io_tasks_stop_on_matching_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let _client = TcpStream::connect(address).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, writer) = crate::network_helpers::transport::Sv2TcpStream::<Message>::from(
+            PlainTcpStream::new(stream),
+        )
+        .into_split();
+
+        let task_manager = Arc::new(TaskManager::new());
+        let (notify_shutdown, _) = broadcast::channel(10);
+        let (_outbound_tx, outbound_rx) = async_channel::unbounded::<SV2Frame>();
+        let (inbound_tx, inbound_rx) = async_channel::unbounded();
+        spawn_io_tasks(
+            task_manager.clone(),
+            reader,
+            writer,
+            outbound_rx,
+            inbound_tx,
+            notify_shutdown.clone(),
+            StopOnAll,
+            None,
+        );
+
+        notify_shutdown.send(Shutdown::Other).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!inbound_rx.is_closed());
+
+        notify_shutdown.send(Shutdown::All).unwrap();
+        task_manager.join_all().await;
+        assert!(inbound_rx.is_closed());
+    }
+}
@@ -0,0 +1,154 @@
+//! Classification of SV2 message types by subprotocol.
+//!
+//! Roles use it to route an inbound frame to the handler of its subprotocol before parsing it.
+
+use stratum_core::{
+    common_messages_sv2::{
+        MESSAGE_TYPE_CHANNEL_ENDPOINT_CHANGED, MESSAGE_TYPE_RECONNECT,
+        MESSAGE_TYPE_SETUP_CONNECTION, MESSAGE_TYPE_SETUP_CONNECTION_ERROR,
+        MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+    },
+    job_declaration_sv2::{
+        MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN, MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS,
+        MESSAGE_TYPE_DECLARE_MINING_JOB, MESSAGE_TYPE_DECLARE_MINING_JOB_ERROR,
+        MESSAGE_TYPE_DECLARE_MINING_JOB_SUCCESS, MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS,
+        MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS_SUCCESS, MESSAGE_TYPE_PUSH_SOLUTION,
+    },
+    mining_sv2::{
+        MESSAGE_TYPE_CLOSE_CHANNEL, MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+        MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB, MESSAGE_TYPE_NEW_MINING_JOB,
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS, MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR,
+        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL,
+        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS, MESSAGE_TYPE_SET_CUSTOM_MINING_JOB,
+        MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_ERROR, MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_SUCCESS,
+        MESSAGE_TYPE_SET_EXTRANONCE_PREFIX, MESSAGE_TYPE_SET_GROUP_CHANNEL,
+        MESSAGE_TYPE_SET_TARGET, MESSAGE_TYPE_SUBMIT_SHARES_ERROR,
+        MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED, MESSAGE_TYPE_SUBMIT_SHARES_STANDARD,
+        MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS, MESSAGE_TYPE_UPDATE_CHANNEL,
+        MESSAGE_TYPE_UPDATE_CHANNEL_ERROR,
+    },
+    template_distribution_sv2::{
+        MESSAGE_TYPE_COINBASE_OUTPUT_CONSTRAINTS, MESSAGE_TYPE_NEW_TEMPLATE,
+        MESSAGE_TYPE_REQUEST_TRANSACTION_DATA, MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_ERROR,
+        MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_SUCCESS, MESSAGE_TYPE_SET_NEW_PREV_HASH,
+        MESSAGE_TYPE_SUBMIT_SOLUTION,
+    },
+};
+
+pub fn is_common_message(message_type: u8) -> bool {
+    matches!(
+        message_type,
+        MESSAGE_TYPE_SETUP_CONNECTION
+            | MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS
+            | MESSAGE_TYPE_SETUP_CONNECTION_ERROR
+            | MESSAGE_TYPE_CHANNEL_ENDPOINT_CHANGED
+            | MESSAGE_TYPE_RECONNECT
+    )
+}
+
+pub fn is_mining_message(message_type: u8) -> bool {
+    matches!(
+        message_type,
+        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL
+            | MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS
+            | MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR
+            | MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL
+            | MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCESS
+            | MESSAGE_TYPE_NEW_MINING_JOB
+            | MESSAGE_TYPE_UPDATE_CHANNEL
+            | MESSAGE_TYPE_UPDATE_CHANNEL_ERROR
+            | MESSAGE_TYPE_CLOSE_CHANNEL
+            | MESSAGE_TYPE_SET_EXTRANONCE_PREFIX
+            | MESSAGE_TYPE_SUBMIT_SHARES_STANDARD
+            | MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED
+            | MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS
+            | MESSAGE_TYPE_SUBMIT_SHARES_ERROR
+            // | MESSAGE_TYPE_RESERVED
+            | 0x1e
+            | MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB
+            | MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH
+            | MESSAGE_TYPE_SET_TARGET
+            | MESSAGE_TYPE_SET_CUSTOM_MINING_JOB
+            | MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_SUCCESS
+            | MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_ERROR
+            | MESSAGE_TYPE_SET_GROUP_CHANNEL
+    )
+}
+
+pub fn is_job_declaration_message(message_type: u8) -> bool {
+    matches!(
+        message_type,
+        MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN
+            | MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS
+            | MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS
+            | MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS_SUCCESS
+            | MESSAGE_TYPE_DECLARE_MINING_JOB
+            | MESSAGE_TYPE_DECLARE_MINING_JOB_SUCCESS
+            | MESSAGE_TYPE_DECLARE_MINING_JOB_ERROR
+            | MESSAGE_TYPE_PUSH_SOLUTION
+    )
+}
+
+pub fn is_template_distribution_message(message_type: u8) -> bool {
+    matches!(
+        message_type,
+        MESSAGE_TYPE_COINBASE_OUTPUT_CONSTRAINTS
+            | MESSAGE_TYPE_NEW_TEMPLATE
+            | MESSAGE_TYPE_SET_NEW_PREV_HASH
+            | MESSAGE_TYPE_REQUEST_TRANSACTION_DATA
+            | MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_SUCCESS
+            | MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_ERROR
+            | MESSAGE_TYPE_SUBMIT_SOLUTION
+    )
+}
+
+/// The SV2 subprotocol a message type belongs to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MessageType {
+    Common,
+    Mining,
+    JobDeclaration,
+    TemplateDistribution,
+    Unknown,
+}
+
+pub fn protocol_message_type(message_type: u8) -> MessageType {
+    if is_common_message(message_type) {
+        MessageType::Common
+    } else if is_mining_message(message_type) {
+        MessageType::Mining
+    } else if is_job_declaration_message(message_type) {
+        MessageType::JobDeclaration
+    } else if is_template_distribution_message(message_type) {
+        MessageType::TemplateDistribution
+    } else {
+        MessageType::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_message_types_by_subprotocol() {
+        assert_eq!(
+            protocol_message_type(MESSAGE_TYPE_SETUP_CONNECTION),
+            MessageType::Common
+        );
+        assert_eq!(
+            protocol_message_type(MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED),
+            MessageType::Mining
+        );
+        assert_eq!(
+            protocol_message_type(MESSAGE_TYPE_DECLARE_MINING_JOB),
+            MessageType::JobDeclaration
+        );
+        assert_eq!(
+            protocol_message_type(MESSAGE_TYPE_NEW_TEMPLATE),
+            MessageType::TemplateDistribution
+        );
+        assert_eq!(protocol_message_type(0xff), MessageType::Unknown);
+    }
+}
@@ -0,0 +1,36 @@
+//! Runtime building blocks shared by the roles
+//!
+//! - [`TaskManager`] tracks and supervises the tasks spawned by a role, to restart them, shut the
+//!   role down when a critical one fails, and join or abort them on shutdown
+//! - [`spawn_io_tasks`] runs the reader and writer tasks of an SV2 connection, stopping them on
+//!   the role's own shutdown messages through [`IoTaskStatus`] - when `network` feature is enabled
+//! - [`priority_channel`] queues the outbound frames of a connection, critical ones ahead of share
+//!   submissions and acknowledgements, bounded and with an [`OverflowPolicy`] when created from an
+//!   [`IoChannelsConfig`] - when `network` feature is enabled
+//! - [`HandshakeDeadline`] bounds the time an accepted connection may take to complete its
+//!   handshake, counting the connections dropped for stalling in [`handshake_timeouts`]
+//! - [`message_type`] classifies SV2 message types by subprotocol - when `core` feature is enabled
+//!
+//! The JDS only uses the [`TaskManager`] and the [`HandshakeDeadline`]: it builds on the crates.io
+//! releases of the SV2 crates, whose frames and noise connections are not the ones of
+//! `stratum-core`, so its connections keep the reader and writer tasks of `network_helpers_sv2`,
+//! or its own in plaintext, rather than [`spawn_io_tasks`].
+
+mod handshake;
+#[cfg(feature = "network")]
+mod io_tasks;
+#[cfg(feature = "core")]
+pub mod message_type;
+#[cfg(feature = "network")]
+mod outbound;
+mod task_manager;
+
+pub use handshake::{handshake_timeouts, HandshakeDeadline, DEFAULT_HANDSHAKE_TIMEOUT};
+#[cfg(feature = "network")]
+pub use io_tasks::{spawn_io_tasks, IoChannelsConfig, IoTaskStatus};
+#[cfg(feature = "network")]
+pub use outbound::{
+    bounded_priority_channel, is_bulk_message, priority_channel, OutboundFrames, OverflowPolicy,
+    PriorityReceiver, PrioritySender,
+};
+pub use task_manager::{RestartPolicy, Supervision, TaskInfo, TaskManager, TaskPanic, TaskResult};
@@ -0,0 +1,508 @@
+//! Outbound queues of the writer task of an SV2 connection.
+//!
+//! [`spawn_io_tasks`](super::spawn_io_tasks) writes the frames of an [`OutboundFrames`] queue in
+//! the order it hands them out. A plain `async_channel` writes them in the order they were sent.
+//! A [`priority_channel`] writes the critical frames first: everything but share submissions and
+//! their acknowledgements, which are bulk. A backlog of share acknowledgements then never delays
+//! a `SetNewPrevHash`, a `SetTarget` or a `CloseChannel`. Within each class the frames keep their
+//! order, so that a `SetNewPrevHash` never overtakes the job or the channel it refers to.
+//!
+//! A [`bounded_priority_channel`] holds at most a given number of frames of both classes, and
+//! applies its [`OverflowPolicy`] to the frames queued while it is full. Frames sent on its
+//! priority lane, with [`PrioritySender::send_priority`], are exempt from the bound: they are
+//! queued at once, like the switch of the downstreams to a new block, which must neither wait
+//! for a slow peer nor be lost. They are written in order with the other critical frames, so that
+//! they still never overtake the job or the channel they refer to.
+
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use async_channel::{unbounded, Receiver, SendError, Sender, TrySendError};
+use serde::Deserialize;
+use stratum_core::{
+    buffer_sv2,
+    framing_sv2::framing::Sv2Frame,
+    mining_sv2::{
+        MESSAGE_TYPE_SUBMIT_SHARES_ERROR, MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED,
+        MESSAGE_TYPE_SUBMIT_SHARES_STANDARD, MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+    },
+    parsers_sv2::AnyMessage,
+};
+use tokio::sync::Notify;
+use tracing::warn;
+
+type SV2Frame = Sv2Frame<AnyMessage<'static>, buffer_sv2::Slice>;
+
+/// Queue of the frames the writer task of a connection writes.
+pub trait OutboundFrames: Clone + Send + Sync + 'static {
+    /// Waits for the next frame to write, `None` once the queue is closed and empty.
+    fn recv(&self) -> impl Future<Output = Option<SV2Frame>> + Send;
+
+    /// Closes the queue, the frames already queued are still handed out.
+    fn close(&self);
+
+    /// Returns the number of frames waiting to be written.
+    fn queued(&self) -> usize;
+}
+
+impl OutboundFrames for Receiver<SV2Frame> {
+    async fn recv(&self) -> Option<SV2Frame> {
+        Receiver::recv(self).await.ok()
+    }
+
+    fn close(&self) {
+        Receiver::close(self);
+    }
+
+    fn queued(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Whether frames of `message_type` are bulk, written after the critical ones.
+pub fn is_bulk_message(message_type: u8) -> bool {
+    matches!(
+        message_type,
+        MESSAGE_TYPE_SUBMIT_SHARES_STANDARD
+            | MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED
+            | MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS
+            | MESSAGE_TYPE_SUBMIT_SHARES_ERROR
+    )
+}
+
+/// What happens to a frame queued to a full [`bounded_priority_channel`].
+///
+/// ```toml
+/// overflow = "disconnect" # or "drop", "backpressure"
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// The queue is closed, which closes the connection: its peer does not read as fast as it
+    /// is sent frames.
+    #[default]
+    Disconnect,
+    /// Bulk frames are dropped, the other frames close the queue as with
+    /// [`Disconnect`](Self::Disconnect): a peer can live without some share acknowledgements, not
+    /// without its jobs.
+    Drop,
+    /// The sender waits for room in the queue, and is slowed down to the pace of the peer.
+    Backpressure,
+}
+
+/// Creates an unbounded outbound queue writing the critical frames ahead of the bulk ones.
+pub fn priority_channel() -> (PrioritySender, PriorityReceiver) {
+    new_priority_channel(None, OverflowPolicy::default())
+}
+
+/// Creates an outbound queue writing the critical frames ahead of the bulk ones and holding up to
+/// `capacity` frames, applying `overflow` to the frames queued while it is full.
+pub fn bounded_priority_channel(
+    capacity: NonZeroUsize,
+    overflow: OverflowPolicy,
+) -> (PrioritySender, PriorityReceiver) {
+    new_priority_channel(Some(capacity), overflow)
+}
+
+fn new_priority_channel(
+    capacity: Option<NonZeroUsize>,
+    overflow: OverflowPolicy,
+) -> (PrioritySender, PriorityReceiver) {
+    let (critical_tx, critical_rx) = unbounded();
+    let (bulk_tx, bulk_rx) = unbounded();
+    let state = Arc::new(QueueState {
+        capacity,
+        overflow,
+        queued: AtomicUsize::new(0),
+        dropped: AtomicU64::new(0),
+        overflowed: AtomicBool::new(false),
+        room: Notify::new(),
+    });
+    (
+        PrioritySender {
+            critical: critical_tx,
+            bulk: bulk_tx,
+            state: state.clone(),
+        },
+        PriorityReceiver {
+            critical: critical_rx,
+            bulk: bulk_rx,
+            state,
+        },
+    )
+}
+
+// State shared by the halves of a priority channel.
+#[derive(Debug)]
+struct QueueState {
+    capacity: Option<NonZeroUsize>,
+    overflow: OverflowPolicy,
+    // Frames of both classes in the queue.
+    queued: AtomicUsize,
+    dropped: AtomicU64,
+    // Whether a frame overflowed the queue and closed it.
+    overflowed: AtomicBool,
+    // Notified when a frame leaves the queue, or when it is closed.
+    room: Notify,
+}
+
+impl QueueState {
+    // Reserves a place in the queue for a frame, `false` if it is full.
+    fn reserve(&self) -> bool {
+        match self.capacity {
+            Some(capacity) => self
+                .queued
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+                    (queued < capacity.get()).then_some(queued + 1)
+                })
+                .is_ok(),
+            None => {
+                self.queued.fetch_add(1, Ordering::AcqRel);
+                true
+            }
+        }
+    }
+
+    // Frees the place of a frame leaving the queue.
+    fn release(&self) {
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+        if self.capacity.is_some() {
+            self.room.notify_waiters();
+        }
+    }
+}
+
+/// Sending half of a [`priority_channel`].
+#[derive(Debug, Clone)]
+pub struct PrioritySender {
+    critical: Sender<SV2Frame>,
+    bulk: Sender<SV2Frame>,
+    state: Arc<QueueState>,
+}
+
+impl PrioritySender {
+    // Returns the queue of the class of `frame`.
+    fn queue(&self, frame: &SV2Frame) -> &Sender<SV2Frame> {
+        if is_bulk(frame) {
+            &self.bulk
+        } else {
+            &self.critical
+        }
+    }
+
+    /// Queues `frame`, waiting for room in a full queue whose policy is
+    /// [`Backpressure`](OverflowPolicy::Backpressure).
+    ///
+    /// Fails if the queue is closed, or if `frame` overflowed the queue and closed it.
+    pub async fn send(&self, mut frame: SV2Frame) -> Result<(), SendError<SV2Frame>> {
+        loop {
+            // Registered before trying, so that no frame leaves the queue unnoticed in between.
+            let room = self.state.room.notified();
+            match self.try_send(frame) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(full)) => frame = full,
+                Err(TrySendError::Closed(frame)) => return Err(SendError(frame)),
+            }
+            room.await;
+        }
+    }
+
+    /// Queues `frame` without waiting.
+    ///
+    /// Fails with [`TrySendError::Full`] if the queue is full and its policy is
+    /// [`Backpressure`](OverflowPolicy::Backpressure), with [`TrySendError::Closed`] if the queue
+    /// is closed or if `frame` overflowed the queue and closed it.
+    pub fn try_send(&self, frame: SV2Frame) -> Result<(), TrySendError<SV2Frame>> {
+        if self.is_closed() {
+            return Err(TrySendError::Closed(frame));
+        }
+        if self.state.reserve() {
+            return self.queue(&frame).try_send(frame).inspect_err(|_| {
+                self.state.release();
+            });
+        }
+        match self.state.overflow {
+            OverflowPolicy::Backpressure => Err(TrySendError::Full(frame)),
+            OverflowPolicy::Drop if is_bulk(&frame) => {
+                self.state.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            OverflowPolicy::Drop | OverflowPolicy::Disconnect => {
+                if !self.state.overflowed.swap(true, Ordering::AcqRel) {
+                    warn!(
+                        capacity = self.state.capacity.map_or(0, NonZeroUsize::get),
+                        "Outbound queue full, closing the connection"
+                    );
+                }
+                self.close();
+                Err(TrySendError::Closed(frame))
+            }
+        }
+    }
+
+    /// Queues `frame` on the priority lane, without waiting and whether or not the queue is full.
+    ///
+    /// Fails if the queue is closed.
+    pub fn send_priority(&self, frame: SV2Frame) -> Result<(), TrySendError<SV2Frame>> {
+        if self.is_closed() {
+            return Err(TrySendError::Closed(frame));
+        }
+        self.state.queued.fetch_add(1, Ordering::AcqRel);
+        self.critical.try_send(frame).inspect_err(|_| {
+            self.state.release();
+        })
+    }
+
+    /// Closes the queue.
+    pub fn close(&self) {
+        self.critical.close();
+        self.bulk.close();
+        self.state.room.notify_waiters();
+    }
+
+    /// Whether the queue is closed.
+    pub fn is_closed(&self) -> bool {
+        self.critical.is_closed()
+    }
+
+    /// Whether a frame overflowed the queue and closed it.
+    pub fn overflowed(&self) -> bool {
+        self.state.overflowed.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of critical and bulk frames waiting to be written.
+    pub fn len(&self) -> (usize, usize) {
+        (self.critical.len(), self.bulk.len())
+    }
+
+    /// Whether no frame is waiting to be written.
+    pub fn is_empty(&self) -> bool {
+        self.critical.is_empty() && self.bulk.is_empty()
+    }
+
+    /// Returns how many bulk frames were dropped by the [`Drop`](OverflowPolicy::Drop) policy.
+    pub fn dropped(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+}
+
+fn is_bulk(frame: &SV2Frame) -> bool {
+    frame
+        .get_header()
+        .is_some_and(|header| is_bulk_message(header.msg_type()))
+}
+
+/// Receiving half of a [`priority_channel`], handed to the writer task.
+#[derive(Debug, Clone)]
+pub struct PriorityReceiver {
+    critical: Receiver<SV2Frame>,
+    bulk: Receiver<SV2Frame>,
+    state: Arc<QueueState>,
+}
+
+impl OutboundFrames for PriorityReceiver {
+    async fn recv(&self) -> Option<SV2Frame> {
+        // The frames left in an overflowed queue are not worth writing to a peer about to be
+        // disconnected.
+        if self.state.overflowed.load(Ordering::Acquire) {
+            return None;
+        }
+        // A closed queue still hands out the frames of both classes, critical ones first.
+        let frame = tokio::select! {
+            biased;
+            Ok(frame) = self.critical.recv() => Some(frame),
+            frame = self.bulk.recv() => match frame {
+                Ok(frame) => Some(frame),
+                Err(_) => self.critical.try_recv().ok(),
+            },
+        }?;
+        self.state.release();
+        Some(frame)
+    }
+
+    fn close(&self) {
+        self.critical.close();
+        self.bulk.close();
+        self.state.room.notify_waiters();
+    }
+
+    fn queued(&self) -> usize {
+        self.state.queued.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_core::{
+        mining_sv2::{
+            SetNewPrevHash, SetTarget, SubmitSharesSuccess, MESSAGE_TYPE_SET_NEW_PREV_HASH,
+            MESSAGE_TYPE_SET_TARGET,
+        },
+        parsers_sv2::Mining,
+    };
+
+    fn frame(message: Mining<'static>) -> SV2Frame {
+        AnyMessage::Mining(message).try_into().unwrap()
+    }
+
+    fn set_target() -> SV2Frame {
+        frame(Mining::SetTarget(SetTarget {
+            channel_id: 1,
+            maximum_target: [0xff; 32].into(),
+        }))
+    }
+
+    fn set_new_prev_hash() -> SV2Frame {
+        frame(Mining::SetNewPrevHash(SetNewPrevHash {
+            channel_id: 1,
+            job_id: 1,
+            prev_hash: [0; 32].into(),
+            min_ntime: 0,
+            nbits: 0,
+        }))
+    }
+
+    fn success() -> SV2Frame {
+        frame(Mining::SubmitSharesSuccess(SubmitSharesSuccess {
+            channel_id: 1,
+            last_sequence_number: 0,
+            new_submits_accepted_count: 1,
+            new_shares_sum: 1,
+        }))
+    }
+
+    // Drains `receiver`, returning the message types of its frames in the order handed out.
+    async fn drain(receiver: &PriorityReceiver) -> Vec<u8> {
+        let mut message_types = Vec::new();
+        while let Some(frame) = receiver.recv().await {
+            message_types.push(frame.get_header().unwrap().msg_type());
+        }
+        message_types
+    }
+
+    #[tokio::test]
+    async fn hands_critical_frames_out_first_in_order() {
+        let (sender, receiver) = priority_channel();
+        sender.try_send(success()).unwrap();
+        sender.try_send(set_target()).unwrap();
+        sender.try_send(success()).unwrap();
+        sender.send(set_new_prev_hash()).await.unwrap();
+        assert_eq!(sender.len(), (2, 2));
+        sender.close();
+
+        assert_eq!(
+            drain(&receiver).await,
+            [
+                MESSAGE_TYPE_SET_TARGET,
+                MESSAGE_TYPE_SET_NEW_PREV_HASH,
+                MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+                MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
+            ]
+        );
+        assert!(sender.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_frames_once_closed() {
+        let (sender, receiver) = priority_channel();
+        sender.try_send(success()).unwrap();
+        OutboundFrames::close(&receiver);
+
+        assert!(sender.is_closed());
+        assert!(sender.try_send(set_target()).is_err());
+        assert_eq!(drain(&receiver).await, [MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS]);
+    }
+
+    fn bounded(capacity: usize, overflow: OverflowPolicy) -> (PrioritySender, PriorityReceiver) {
+        bounded_priority_channel(NonZeroUsize::new(capacity).unwrap(), overflow)
+    }
+
+    #[tokio::test]
+    async fn disconnects_on_overflow() {
+        let (sender, receiver) = bounded(2, OverflowPolicy::Disconnect);
+        sender.try_send(set_target()).unwrap();
+        sender.try_send(success()).unwrap();
+        assert_eq!(receiver.queued(), 2);
+
+        assert!(matches!(
+            sender.try_send(success()),
+            Err(TrySendError::Closed(_))
+        ));
+        assert!(sender.overflowed());
+        assert!(sender.send(set_target()).await.is_err());
+        // The backlog is not written to a peer about to be disconnected.
+        assert!(drain(&receiver).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drops_bulk_frames_on_overflow() {
+        let (sender, receiver) = bounded(1, OverflowPolicy::Drop);
+        sender.try_send(set_target()).unwrap();
+        sender.try_send(success()).unwrap();
+        sender.send(success()).await.unwrap();
+        assert_eq!(sender.dropped(), 2);
+        assert!(!sender.overflowed());
+
+        assert_eq!(
+            receiver
+                .recv()
+                .await
+                .map(|frame| frame.get_header().unwrap().msg_type()),
+            Some(MESSAGE_TYPE_SET_TARGET)
+        );
+        sender.try_send(success()).unwrap();
+        sender.try_send(set_new_prev_hash()).unwrap_err();
+        assert!(sender.overflowed());
+    }
+
+    #[tokio::test]
+    async fn waits_for_room_on_backpressure() {
+        let (sender, receiver) = bounded(1, OverflowPolicy::Backpressure);
+        sender.try_send(success()).unwrap();
+        assert!(matches!(
+            sender.try_send(set_target()),
+            Err(TrySendError::Full(_))
+        ));
+
+        let blocked = {
+            let sender = sender.clone();
+            tokio::spawn(async move { sender.send(set_target()).await })
+        };
+        tokio::task::yield_now().await;
+        assert!(!blocked.is_finished());
+
+        receiver.recv().await.unwrap();
+        blocked.await.unwrap().unwrap();
+        sender.close();
+        assert_eq!(drain(&receiver).await, [MESSAGE_TYPE_SET_TARGET]);
+    }
+
+    #[tokio::test]
+    async fn priority_lane_bypasses_the_bound_in_order() {
+        for overflow in [
+            OverflowPolicy::Backpressure,
+            OverflowPolicy::Disconnect,
+            OverflowPolicy::Drop,
+        ] {
+            let (sender, receiver) = bounded(1, overflow);
+            sender.try_send(set_target()).unwrap();
+            sender.send_priority(set_new_prev_hash()).unwrap();
+            assert!(!sender.overflowed());
+            assert_eq!(receiver.queued(), 2);
+            sender.close();
+
+            assert_eq!(
+                drain(&receiver).await,
+                [MESSAGE_TYPE_SET_TARGET, MESSAGE_TYPE_SET_NEW_PREV_HASH]
+            );
+            assert!(sender.send_priority(set_new_prev_hash()).is_err());
+        }
+    }
+}
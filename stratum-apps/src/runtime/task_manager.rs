@@ -0,0 +1,572 @@
+//! Tracking and supervision of the tasks spawned by a role.
+//!
+//! Every task is registered with a [`Supervision`] strategy deciding what happens when it fails,
+//! that is when it panics or returns an error:
+//!
+//! - [`Supervision::Transient`] tasks ([`TaskManager::spawn`]) are connection or request scoped,
+//!   their failure is only logged.
+//! - [`Supervision::Critical`] tasks ([`TaskManager::spawn_critical`]) are the ones a role cannot
+//!   run without. Their failure completes [`TaskManager::critical_failure`], on which the role
+//!   shuts down instead of staying half-alive.
+//! - [`Supervision::RestartOnFailure`] tasks ([`TaskManager::spawn_restart_on_failure`]) are
+//!   restarted with exponential backoff, until their retries are exhausted.
+//!
+//! Panics are caught and logged with the spawn location of the task, and forwarded as a
+//! [`TaskPanic`] to the reporter registered with [`TaskManager::on_panic`], so that a role can
+//! surface them through its status channel.
+//!
+//! [`TaskManager::running_tasks`] lists the tasks still running, to track down leaked tasks or
+//! check which ones hold up a shutdown.
+//!
+//! Tasks run in the span current where they are spawned, so that their logs carry its fields
+//! (e.g. the id of the connection they serve).
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    panic::{AssertUnwindSafe, Location},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use serde::Serialize;
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{error, warn, Instrument};
+
+/// What the [`TaskManager`] does when a task fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Supervision {
+    /// The failure is logged, nothing else happens.
+    Transient,
+    /// The failure is reported through [`TaskManager::critical_failure`].
+    Critical,
+    /// The task is restarted as configured by the [`RestartPolicy`].
+    RestartOnFailure(RestartPolicy),
+}
+
+/// Restart strategy of a [`Supervision::RestartOnFailure`] task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Number of restarts before giving up on the task.
+    pub max_retries: u32,
+    /// Delay before the first restart, doubled after every failure.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between two restarts.
+    pub max_backoff: Duration,
+}
+
+impl RestartPolicy {
+    /// Delay before restarting a task that failed `failures` times in a row.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Output of a supervised task, telling whether it failed.
+pub trait TaskResult {
+    /// Returns the reason of the failure, `None` if the task completed successfully.
+    fn failure(self) -> Option<String>;
+}
+
+impl TaskResult for () {
+    fn failure(self) -> Option<String> {
+        None
+    }
+}
+
+impl<E: std::fmt::Debug> TaskResult for Result<(), E> {
+    fn failure(self) -> Option<String> {
+        self.err().map(|e| format!("{e:?}"))
+    }
+}
+
+/// Point-in-time view of a task running under a [`TaskManager`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaskInfo {
+    /// Name given when spawning the task, `"task"` for unnamed transient tasks.
+    pub name: &'static str,
+    /// `file:line` of the spawn call.
+    pub spawned_at: String,
+    /// `transient`, `critical` or `restart_on_failure`.
+    pub supervision: &'static str,
+    /// Seconds since the task was spawned.
+    pub uptime_secs: u64,
+    /// Number of times the task was restarted after failing.
+    pub restarts: u32,
+}
+
+/// A panic caught in a task spawned by a [`TaskManager`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskPanic {
+    /// Name of the task.
+    pub task: &'static str,
+    /// `file:line` of the spawn call.
+    pub spawned_at: String,
+    /// Panic message, when the payload is a string.
+    pub message: String,
+}
+
+impl std::fmt::Display for TaskPanic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "task `{}` spawned at {} panicked: {}",
+            self.task, self.spawned_at, self.message
+        )
+    }
+}
+
+type PanicReporter = Arc<dyn Fn(TaskPanic) -> BoxFuture<'static, ()> + Send + Sync>;
+
+struct RunningTask {
+    name: &'static str,
+    location: &'static Location<'static>,
+    supervision: Supervision,
+    spawned_at: Instant,
+    restarts: Arc<AtomicU32>,
+}
+
+type Registry = Arc<StdMutex<BTreeMap<u64, RunningTask>>>;
+
+/// Removes a task from the registry once its supervisor completes or is aborted.
+struct Registration {
+    registry: Registry,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Manages a collection of spawned tokio tasks.
+///
+/// This struct provides a centralized way to spawn, track, supervise and manage the lifecycle
+/// of async tasks of a role. It maintains a list of join handles that can
+/// be used to wait for all tasks to complete or abort them during shutdown.
+pub struct TaskManager {
+    tasks: StdMutex<Vec<JoinHandle<()>>>,
+    // Tasks still running, by spawn order
+    running: Registry,
+    next_id: AtomicU64,
+    panic_reporter: Arc<StdMutex<Option<PanicReporter>>>,
+    // Name of the first critical task that failed
+    critical_failure: Arc<watch::Sender<Option<&'static str>>>,
+}
+
+impl Default for TaskManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TaskManager {
+    /// Creates a new TaskManager instance.
+    ///
+    /// Initializes an empty task manager ready to spawn and track tasks.
+    pub fn new() -> Self {
+        Self {
+            tasks: StdMutex::new(Vec::new()),
+            running: Arc::new(StdMutex::new(BTreeMap::new())),
+            next_id: AtomicU64::new(0),
+            panic_reporter: Arc::new(StdMutex::new(None)),
+            critical_failure: Arc::new(watch::channel(None).0),
+        }
+    }
+
+    /// Spawns a new [transient](Supervision::Transient) async task and adds it to the managed
+    /// collection.
+    ///
+    /// The task will be tracked by this manager and can be waited for or aborted
+    /// using the other methods.
+    ///
+    /// # Arguments
+    /// * `fut` - The future to spawn as a task
+    #[track_caller]
+    pub fn spawn<F>(&self, fut: F)
+    where
+        F: Future + Send + 'static,
+        F::Output: TaskResult,
+    {
+        self.spawn_named("task", fut);
+    }
+
+    /// Same as [`TaskManager::spawn`], with the `name` reported by
+    /// [`TaskManager::running_tasks`] and in the logs.
+    #[track_caller]
+    pub fn spawn_named<F>(&self, name: &'static str, fut: F)
+    where
+        F: Future + Send + 'static,
+        F::Output: TaskResult,
+    {
+        let mut fut = Some(fut);
+        self.spawn_supervised(name, Supervision::Transient, move || {
+            fut.take().expect("transient tasks are never restarted")
+        });
+    }
+
+    /// Spawns a [critical](Supervision::Critical) task: if it fails,
+    /// [`TaskManager::critical_failure`] completes with its `name`.
+    #[track_caller]
+    pub fn spawn_critical<F>(&self, name: &'static str, fut: F)
+    where
+        F: Future + Send + 'static,
+        F::Output: TaskResult,
+    {
+        let mut fut = Some(fut);
+        self.spawn_supervised(name, Supervision::Critical, move || {
+            fut.take().expect("critical tasks are never restarted")
+        });
+    }
+
+    /// Spawns a task restarted according to `policy` when it fails. `task` creates a new run of
+    /// the task for every (re)start.
+    #[track_caller]
+    pub fn spawn_restart_on_failure<T, F>(&self, name: &'static str, policy: RestartPolicy, task: T)
+    where
+        T: FnMut() -> F + Send + 'static,
+        F: Future + Send + 'static,
+        F::Output: TaskResult,
+    {
+        self.spawn_supervised(name, Supervision::RestartOnFailure(policy), task);
+    }
+
+    /// Registers the `reporter` awaited with every panic caught in a task, including the tasks
+    /// spawned before. Replaces the previous reporter.
+    pub fn on_panic<R, F>(&self, reporter: R)
+    where
+        R: Fn(TaskPanic) -> F + Send + Sync + 'static,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let reporter: PanicReporter = Arc::new(move |panic| reporter(panic).boxed());
+        *self.panic_reporter.lock().unwrap() = Some(reporter);
+    }
+
+    #[track_caller]
+    fn spawn_supervised<T, F>(&self, name: &'static str, supervision: Supervision, mut task: T)
+    where
+        T: FnMut() -> F + Send + 'static,
+        F: Future + Send + 'static,
+        F::Output: TaskResult,
+    {
+        let location = Location::caller();
+        let span = tracing::trace_span!(
+            "task",
+            task = name,
+            file = location.file(),
+            line = location.line(),
+            column = location.column(),
+        );
+        let critical_failure = self.critical_failure.clone();
+        let panic_reporter = self.panic_reporter.clone();
+        let restarts = Arc::new(AtomicU32::new(0));
+        let registration = self.register(RunningTask {
+            name,
+            location,
+            supervision,
+            spawned_at: Instant::now(),
+            restarts: restarts.clone(),
+        });
+
+        let supervisor = async move {
+            let _registration = registration;
+            let mut failures = 0;
+            loop {
+                let reason = match AssertUnwindSafe(task()).catch_unwind().await {
+                    Ok(output) => output.failure(),
+                    Err(payload) => {
+                        let panic = TaskPanic {
+                            task: name,
+                            spawned_at: format!("{}:{}", location.file(), location.line()),
+                            message: panic_message(payload.as_ref()),
+                        };
+                        error!(
+                            task = name,
+                            spawned_at = %panic.spawned_at,
+                            message = %panic.message,
+                            "Task panicked"
+                        );
+                        let reason = format!("panicked: {}", panic.message);
+                        let reporter = panic_reporter.lock().unwrap().clone();
+                        if let Some(reporter) = reporter {
+                            reporter(panic).await;
+                        }
+                        Some(reason)
+                    }
+                };
+                let Some(reason) = reason else {
+                    break;
+                };
+                failures += 1;
+
+                match supervision {
+                    Supervision::Transient => {
+                        warn!(task = name, %reason, "Task failed");
+                        break;
+                    }
+                    Supervision::Critical => {
+                        error!(task = name, %reason, "Critical task failed");
+                        report_critical_failure(&critical_failure, name);
+                        break;
+                    }
+                    Supervision::RestartOnFailure(policy) if failures > policy.max_retries => {
+                        error!(task = name, %reason, failures, "Task failed too many times, giving up");
+                        break;
+                    }
+                    Supervision::RestartOnFailure(policy) => {
+                        let backoff = policy.backoff(failures);
+                        warn!(task = name, %reason, failures, ?backoff, "Task failed, restarting");
+                        tokio::time::sleep(backoff).await;
+                        restarts.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        };
+
+        // Nested in the span current at spawn time, e.g. the span of the connection a task serves.
+        let handle = tokio::spawn(supervisor.instrument(span).in_current_span());
+        self.tasks.lock().unwrap().push(handle);
+    }
+
+    fn register(&self, task: RunningTask) -> Registration {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.running.lock().unwrap().insert(id, task);
+        Registration {
+            registry: self.running.clone(),
+            id,
+        }
+    }
+
+    /// Returns the tasks still running, in spawn order.
+    ///
+    /// A task is listed until it completes for good: a restarting task stays listed while waiting
+    /// for its backoff, an aborted task is removed once it has been dropped.
+    pub fn running_tasks(&self) -> Vec<TaskInfo> {
+        self.running
+            .lock()
+            .unwrap()
+            .values()
+            .map(|task| TaskInfo {
+                name: task.name,
+                spawned_at: format!("{}:{}", task.location.file(), task.location.line()),
+                supervision: match task.supervision {
+                    Supervision::Transient => "transient",
+                    Supervision::Critical => "critical",
+                    Supervision::RestartOnFailure(_) => "restart_on_failure",
+                },
+                uptime_secs: task.spawned_at.elapsed().as_secs(),
+                restarts: task.restarts.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Completes once a [critical](Supervision::Critical) task failed, returning its name.
+    ///
+    /// Roles select on it in their main loop to shut down as a whole.
+    pub async fn critical_failure(&self) -> &'static str {
+        let mut failed = self.critical_failure.subscribe();
+        // The sender lives as long as `self`, so the wait cannot fail
+        match failed.wait_for(Option::is_some).await {
+            Ok(name) => (*name).unwrap_or_default(),
+            Err(_) => std::future::pending().await,
+        }
+    }
+
+    /// Waits for all managed tasks to complete.
+    ///
+    /// This method will block until all tasks that were spawned through this
+    /// manager have finished executing. Tasks are joined in reverse order
+    /// (most recently spawned first).
+    pub async fn join_all(&self) {
+        let handles = {
+            let mut tasks = self.tasks.lock().unwrap();
+            std::mem::take(&mut *tasks)
+        };
+
+        for handle in handles {
+            let _ = handle.await;
+        }
+    }
+
+    /// Aborts all managed tasks.
+    ///
+    /// This method immediately cancels all tasks that were spawned through this
+    /// manager. The tasks will be terminated without waiting for them to complete.
+    pub async fn abort_all(&self) {
+        let mut tasks = self.tasks.lock().unwrap();
+        for handle in tasks.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+/// Records the failure of the critical task `name`, unless another one failed before.
+fn report_critical_failure(
+    critical_failure: &watch::Sender<Option<&'static str>>,
+    name: &'static str,
+) {
+    critical_failure.send_if_modified(|failed| {
+        if failed.is_some() {
+            return false;
+        }
+        *failed = Some(name);
+        true
+    });
+}
+
+/// Extracts the message of a panic payload.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: RestartPolicy = RestartPolicy {
+        max_retries: 2,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(3),
+    };
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        assert_eq!(POLICY.backoff(1), Duration::from_millis(1));
+        assert_eq!(POLICY.backoff(2), Duration::from_millis(2));
+        assert_eq!(POLICY.backoff(3), Duration::from_millis(3));
+        assert_eq!(POLICY.backoff(40), Duration::from_millis(3));
+    }
+
+    #[tokio::test]
+    async fn restarts_until_retries_are_exhausted() {
+        let task_manager = TaskManager::new();
+        let runs = Arc::new(AtomicU32::new(0));
+        let task_runs = runs.clone();
+        task_manager.spawn_restart_on_failure("flaky", POLICY, move || {
+            let runs = task_runs.clone();
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>("boom")
+            }
+        });
+
+        task_manager.join_all().await;
+        assert_eq!(runs.load(Ordering::SeqCst), POLICY.max_retries + 1);
+        assert!(task_manager.critical_failure.borrow().is_none());
+    }
+
+    #[tokio::test]
+    async fn restarted_task_can_recover() {
+        let task_manager = TaskManager::new();
+        let runs = Arc::new(AtomicU32::new(0));
+        let task_runs = runs.clone();
+        task_manager.spawn_restart_on_failure("recovers", POLICY, move || {
+            let runs = task_runs.clone();
+            async move {
+                if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("first run panics");
+                }
+            }
+        });
+
+        task_manager.join_all().await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert!(task_manager.critical_failure.borrow().is_none());
+    }
+
+    #[tokio::test]
+    async fn critical_panic_is_reported() {
+        let task_manager = TaskManager::new();
+        task_manager.spawn(async { panic!("transient panic") });
+        task_manager.spawn_critical("critical", async { panic!("critical panic") });
+
+        assert_eq!(task_manager.critical_failure().await, "critical");
+    }
+
+    #[tokio::test]
+    async fn panics_are_reported() {
+        let task_manager = TaskManager::new();
+        let (panic_tx, mut panic_rx) = tokio::sync::mpsc::unbounded_channel();
+        task_manager.on_panic(move |panic| {
+            let panic_tx = panic_tx.clone();
+            async move {
+                panic_tx.send(panic).unwrap();
+            }
+        });
+        task_manager.spawn_named("panicking", async { panic!("boom") });
+        task_manager.spawn(async { Err::<(), _>("not a panic") });
+
+        task_manager.join_all().await;
+        let panic = panic_rx.recv().await.unwrap();
+        assert_eq!(panic.task, "panicking");
+        assert_eq!(panic.message, "boom");
+        assert!(panic.spawned_at.starts_with(file!()));
+        assert!(panic_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn running_tasks_are_listed_until_they_complete() {
+        let task_manager = TaskManager::new();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        task_manager.spawn_named("waiting", async move {
+            let _ = stop_rx.await;
+        });
+        task_manager.spawn_critical("pending", std::future::pending::<()>());
+
+        let running = task_manager.running_tasks();
+        assert_eq!(running.len(), 2);
+        assert_eq!(running[0].name, "waiting");
+        assert_eq!(running[0].supervision, "transient");
+        assert!(running[0].spawned_at.starts_with(file!()));
+        assert_eq!(running[1].name, "pending");
+        assert_eq!(running[1].restarts, 0);
+
+        stop_tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let running = task_manager.running_tasks();
+        assert_eq!(running.len(), 1);
+        assert_eq!(running[0].name, "pending");
+
+        task_manager.abort_all().await;
+        task_manager.join_all().await;
+        assert!(task_manager.running_tasks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn restarts_are_counted() {
+        let task_manager = TaskManager::new();
+        let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+        let runs = Arc::new(AtomicU32::new(0));
+        task_manager.spawn_restart_on_failure("flaky", POLICY, move || {
+            let runs = runs.clone();
+            let mut stop_rx = stop_rx.clone();
+            async move {
+                if runs.fetch_add(1, Ordering::SeqCst) < 2 {
+                    return Err("boom");
+                }
+                let _ = stop_rx.wait_for(|stop| *stop).await;
+                Ok(())
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(task_manager.running_tasks()[0].restarts, 2);
+        stop_tx.send(true).unwrap();
+        task_manager.join_all().await;
+        assert!(task_manager.running_tasks().is_empty());
+    }
+}
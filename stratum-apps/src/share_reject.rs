@@ -0,0 +1,165 @@
+//! Reasons a share is rejected
+//!
+//! [`ShareRejectReason`] is the set of error codes the roles send in `SubmitSharesError`, so
+//! that downstreams, logs, persisted share events and metrics all name a rejection the same way.
+//! The codes the roles always sent (`invalid-channel-id`, `invalid-job-id`, `stale-share`,
+//! `difficulty-too-low`, `duplicate-share`, `bad-extranonce-size`, `invalid-share`) are the ones
+//! of the SV2 specification and are kept as is, as downstreams match on them.
+//! [`ShareRejectCounters`] counts the shares a role rejected, per reason.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+#[cfg(feature = "core")]
+use stratum_core::{
+    binary_sv2::Str0255, channels_sv2::server::share_accounting::ShareValidationError,
+};
+
+/// Reason a share is rejected with `SubmitSharesError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ShareRejectReason {
+    /// The share refers to a channel the downstream did not open.
+    InvalidChannelId,
+    /// The share refers to a job unknown to the channel.
+    JobNotFound,
+    /// The share refers to a job built on a previous chain tip.
+    StaleJob,
+    /// The hash of the share is above the target of the channel.
+    AboveTarget,
+    /// The share was already submitted on the channel.
+    Duplicate,
+    /// The share rolls version bits the channel does not allow.
+    InvalidVersionBits,
+    /// The extranonce of the share does not match the extranonce size of the channel.
+    BadExtranonceSize,
+    /// The downstream was banned after it connected.
+    Banned,
+    /// The channel submits shares far faster than its target allows.
+    RateLimited,
+    /// The share is invalid for another reason.
+    Invalid,
+}
+
+impl ShareRejectReason {
+    /// Every reason, in the order of the counters.
+    pub const ALL: [ShareRejectReason; 10] = [
+        ShareRejectReason::InvalidChannelId,
+        ShareRejectReason::JobNotFound,
+        ShareRejectReason::StaleJob,
+        ShareRejectReason::AboveTarget,
+        ShareRejectReason::Duplicate,
+        ShareRejectReason::InvalidVersionBits,
+        ShareRejectReason::BadExtranonceSize,
+        ShareRejectReason::Banned,
+        ShareRejectReason::RateLimited,
+        ShareRejectReason::Invalid,
+    ];
+
+    /// Returns the error code sent for this reason.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ShareRejectReason::InvalidChannelId => "invalid-channel-id",
+            ShareRejectReason::JobNotFound => "invalid-job-id",
+            ShareRejectReason::StaleJob => "stale-share",
+            ShareRejectReason::AboveTarget => "difficulty-too-low",
+            ShareRejectReason::Duplicate => "duplicate-share",
+            ShareRejectReason::InvalidVersionBits => "invalid-version-bits",
+            ShareRejectReason::BadExtranonceSize => "bad-extranonce-size",
+            ShareRejectReason::Banned => "banned",
+            ShareRejectReason::RateLimited => "rate-limited",
+            ShareRejectReason::Invalid => "invalid-share",
+        }
+    }
+
+    /// Returns the error code as the `error_code` field of a `SubmitSharesError`.
+    #[cfg(feature = "core")]
+    pub fn error_code(self) -> Str0255<'static> {
+        self.as_str()
+            .to_string()
+            .try_into()
+            .expect("error codes fit in a Str0255")
+    }
+
+    /// Returns the reason of a share refused by the validation of a server channel, `None` if
+    /// the error is not caused by the share itself.
+    #[cfg(feature = "core")]
+    pub fn from_validation_error(error: &ShareValidationError) -> Option<Self> {
+        match error {
+            ShareValidationError::Invalid => Some(ShareRejectReason::Invalid),
+            ShareValidationError::Stale => Some(ShareRejectReason::StaleJob),
+            ShareValidationError::InvalidJobId => Some(ShareRejectReason::JobNotFound),
+            ShareValidationError::DoesNotMeetTarget => Some(ShareRejectReason::AboveTarget),
+            ShareValidationError::VersionRollingNotAllowed => {
+                Some(ShareRejectReason::InvalidVersionBits)
+            }
+            ShareValidationError::DuplicateShare => Some(ShareRejectReason::Duplicate),
+            ShareValidationError::BadExtranonceSize => Some(ShareRejectReason::BadExtranonceSize),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ShareRejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Number of shares rejected by a role, per [`ShareRejectReason`].
+#[derive(Debug, Default)]
+pub struct ShareRejectCounters {
+    counts: [AtomicU64; ShareRejectReason::ALL.len()],
+}
+
+impl ShareRejectCounters {
+    /// Creates counters at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a share rejected for `reason`.
+    pub fn record(&self, reason: ShareRejectReason) {
+        self.counts[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of shares rejected for `reason`.
+    pub fn get(&self, reason: ShareRejectReason) -> u64 {
+        self.counts[reason as usize].load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of rejected shares keyed by error code, including the reasons no share
+    /// was rejected for, so that metrics always expose the same series.
+    pub fn snapshot(&self) -> BTreeMap<&'static str, u64> {
+        ShareRejectReason::ALL
+            .into_iter()
+            .map(|reason| (reason.as_str(), self.get(reason)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_rejections_per_reason() {
+        for (index, reason) in ShareRejectReason::ALL.into_iter().enumerate() {
+            assert_eq!(reason as usize, index);
+        }
+
+        let counters = ShareRejectCounters::new();
+        counters.record(ShareRejectReason::StaleJob);
+        counters.record(ShareRejectReason::StaleJob);
+        counters.record(ShareRejectReason::Banned);
+        assert_eq!(counters.get(ShareRejectReason::StaleJob), 2);
+
+        let snapshot = counters.snapshot();
+        assert_eq!(snapshot.len(), ShareRejectReason::ALL.len());
+        assert_eq!(snapshot["stale-share"], 2);
+        assert_eq!(snapshot["banned"], 1);
+        assert_eq!(snapshot["difficulty-too-low"], 0);
+    }
+}
@@ -0,0 +1,216 @@
+//! Capture files of the sniffer: one JSON object per line, for each SV2 message relayed.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use stratum_core::{buffer_sv2, framing_sv2::framing::Sv2Frame, parsers_sv2::message_type_to_name};
+
+use crate::custom_mutex::Mutex;
+
+use super::Message;
+
+/// Bit of the extension type flagging the messages addressed to a channel.
+const CHANNEL_MSG_BIT: u16 = 0x8000;
+
+/// Direction of a message relayed by the sniffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Sent by the downstream to the upstream.
+    ToUpstream,
+    /// Sent by the upstream to the downstream.
+    ToDownstream,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::ToUpstream => f.write_str("downstream -> upstream"),
+            Direction::ToDownstream => f.write_str("upstream -> downstream"),
+        }
+    }
+}
+
+/// A message relayed by the sniffer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Connection of the downstream the message was relayed for, numbered from 1.
+    pub connection: u64,
+    /// Milliseconds since the downstream connected.
+    pub elapsed_ms: u64,
+    pub direction: Direction,
+    /// Extension type of the frame, without its channel message bit.
+    pub extension_type: u16,
+    pub channel_msg: bool,
+    pub message_type: u8,
+    /// Name of the message, for the readers of the file.
+    #[serde(default)]
+    pub name: String,
+    /// Payload of the frame, as sent on the wire once decrypted.
+    #[serde(with = "hex_bytes")]
+    pub payload: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// Records `frame`, or returns `None` if its header cannot be read.
+    pub fn from_frame(
+        connection: u64,
+        elapsed_ms: u64,
+        direction: Direction,
+        frame: &mut Sv2Frame<Message, buffer_sv2::Slice>,
+    ) -> Option<Self> {
+        let header = frame.get_header()?;
+        Some(Self {
+            connection,
+            elapsed_ms,
+            direction,
+            extension_type: header.ext_type_without_channel_msg(),
+            channel_msg: header.channel_msg(),
+            message_type: header.msg_type(),
+            name: message_type_to_name(header.msg_type()).to_string(),
+            payload: frame.payload().to_vec(),
+        })
+    }
+
+    /// Rebuilds the frame of the message, byte for byte as it was relayed.
+    pub fn to_frame(&self) -> Sv2Frame<Message, buffer_sv2::Slice> {
+        let mut extension_type = self.extension_type & !CHANNEL_MSG_BIT;
+        if self.channel_msg {
+            extension_type |= CHANNEL_MSG_BIT;
+        }
+        let length = (self.payload.len() as u32).to_le_bytes();
+        let mut bytes = Vec::with_capacity(6 + self.payload.len());
+        bytes.extend_from_slice(&extension_type.to_le_bytes());
+        bytes.push(self.message_type);
+        bytes.extend_from_slice(&length[..3]);
+        bytes.extend_from_slice(&self.payload);
+        Sv2Frame::from_bytes_unchecked(buffer_sv2::Slice::from(bytes))
+    }
+
+    /// Whether `other` carries the same message, regardless of when and where it was relayed.
+    pub fn same_message(&self, other: &CaptureRecord) -> bool {
+        self.extension_type == other.extension_type
+            && self.channel_msg == other.channel_msg
+            && self.message_type == other.message_type
+            && self.payload == other.payload
+    }
+}
+
+/// Appends [`CaptureRecord`]s to a capture file.
+#[derive(Debug)]
+pub struct CaptureWriter {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl CaptureWriter {
+    /// Creates the capture file at `path`, truncating any previous capture.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(File::create(path)?)),
+        })
+    }
+
+    /// Appends `record` to the file. Records are flushed right away, so that a capture
+    /// interrupted by a crash of a role still holds every message relayed before.
+    pub fn record(&self, record: &CaptureRecord) -> io::Result<()> {
+        let line = serde_json::to_string(record).map_err(io::Error::other)?;
+        self.writer.super_safe_lock(|writer| {
+            writeln!(writer, "{line}")?;
+            writer.flush()
+        })
+    }
+}
+
+/// Reads the records of the capture file at `path`, in the order they were relayed.
+pub fn read_capture(path: &Path) -> io::Result<Vec<CaptureRecord>> {
+    let mut records = Vec::new();
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid record on line {}: {e}", index + 1),
+            )
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        hex::decode(hex).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("sv2-sniffer-capture-{}.jsonl", std::process::id()));
+        let records = vec![
+            CaptureRecord {
+                connection: 1,
+                elapsed_ms: 0,
+                direction: Direction::ToUpstream,
+                extension_type: 0,
+                channel_msg: false,
+                message_type: 0x00,
+                name: "SetupConnection".to_string(),
+                payload: vec![0x02, 0x00, 0x02],
+            },
+            CaptureRecord {
+                connection: 1,
+                elapsed_ms: 12,
+                direction: Direction::ToDownstream,
+                extension_type: 0,
+                channel_msg: true,
+                message_type: 0x1e,
+                name: "SetTarget".to_string(),
+                payload: vec![],
+            },
+        ];
+        let writer = CaptureWriter::create(&path).unwrap();
+        for record in &records {
+            writer.record(record).unwrap();
+        }
+        assert_eq!(read_capture(&path).unwrap(), records);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let record = CaptureRecord {
+            connection: 3,
+            elapsed_ms: 40,
+            direction: Direction::ToDownstream,
+            extension_type: 0,
+            channel_msg: true,
+            message_type: 0x1e,
+            name: "SetTarget".to_string(),
+            payload: vec![1, 2, 3, 4],
+        };
+        let mut frame = record.to_frame();
+        let rebuilt =
+            CaptureRecord::from_frame(3, 40, Direction::ToDownstream, &mut frame).unwrap();
+        assert_eq!(rebuilt, record);
+    }
+}
@@ -0,0 +1,448 @@
+//! SV2 sniffer
+//!
+//! [`Sv2Sniffer`] sits between a downstream and an upstream role, for debugging interop issues:
+//!
+//! - It is the Noise responder of the downstreams, with its own authority keypair, and the Noise
+//!   initiator of the upstream, optionally authenticating it with its authority public key. The
+//!   downstreams must therefore trust the key of the sniffer rather than the one of the upstream.
+//! - Every SV2 message relayed is logged, decoded when its type is known, along with the
+//!   connection it belongs to, its direction and the time elapsed since the downstream connected.
+//! - Messages are relayed untouched, in both directions, as soon as they are read.
+//! - With a capture file, every message is also recorded as a [`CaptureRecord`], see [`capture`].
+//!   A [`Replay`] plays either side of a captured connection back against a role, see [`replay`].
+//!
+//! Each downstream gets its own upstream connection, closed along with the downstream one.
+
+pub mod capture;
+pub mod replay;
+
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use stratum_core::{
+    codec_sv2::{HandshakeRole, StandardEitherFrame},
+    framing_sv2::framing::Frame,
+    noise_sv2::Responder,
+    parsers_sv2::AnyMessage,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+use crate::{
+    key_utils::{AuthorityPublicKeys, Secp256k1PublicKey, Secp256k1SecretKey},
+    network_helpers::{
+        transport::{connect_address, Encryption, Sv2TcpReadHalf, Sv2TcpStream, Sv2TcpWriteHalf},
+        Error,
+    },
+    runtime::TaskManager,
+};
+
+pub use capture::{read_capture, CaptureRecord, CaptureWriter, Direction};
+pub use replay::{Replay, ReplayError, ReplayReport};
+
+type Message = AnyMessage<'static>;
+type EitherFrame = StandardEitherFrame<Message>;
+
+/// Validity of the certificates the sniffer presents to the downstreams.
+const CERTIFICATE_VALIDITY: Duration = Duration::from_secs(3600);
+
+/// Configuration of a [`Sv2Sniffer`].
+#[derive(Debug, Clone)]
+pub struct SnifferConfig {
+    listen_address: SocketAddr,
+    upstream_address: String,
+    authority_public_key: Secp256k1PublicKey,
+    authority_secret_key: Secp256k1SecretKey,
+    upstream_authority_public_key: Option<Secp256k1PublicKey>,
+    capture: Option<PathBuf>,
+}
+
+impl SnifferConfig {
+    /// Creates the configuration of a sniffer listening on `listen_address` (port 0 for a random
+    /// one) and relaying to `upstream_address`, a `host:port` or a `unix:` path. The downstreams
+    /// authenticate the sniffer with `authority_public_key`.
+    pub fn new(
+        listen_address: SocketAddr,
+        upstream_address: String,
+        authority_public_key: Secp256k1PublicKey,
+        authority_secret_key: Secp256k1SecretKey,
+    ) -> Self {
+        Self {
+            listen_address,
+            upstream_address,
+            authority_public_key,
+            authority_secret_key,
+            upstream_authority_public_key: None,
+            capture: None,
+        }
+    }
+
+    /// Authenticates the upstream with its authority public key, any upstream is accepted
+    /// otherwise.
+    pub fn with_upstream_authority_public_key(mut self, public_key: Secp256k1PublicKey) -> Self {
+        self.upstream_authority_public_key = Some(public_key);
+        self
+    }
+
+    /// Records the messages relayed to the capture file at `path`.
+    pub fn with_capture(mut self, path: PathBuf) -> Self {
+        self.capture = Some(path);
+        self
+    }
+}
+
+struct Shared {
+    config: SnifferConfig,
+    capture: Option<CaptureWriter>,
+    connections: AtomicU64,
+}
+
+/// A sniffer relaying the messages between downstreams and an upstream, stopped by
+/// [`Sv2Sniffer::stop`].
+#[derive(Clone)]
+pub struct Sv2Sniffer {
+    local_address: SocketAddr,
+    task_manager: Arc<TaskManager>,
+}
+
+impl Sv2Sniffer {
+    /// Creates the capture file, if any, binds the listening address of `config` and starts
+    /// relaying.
+    pub async fn start(config: SnifferConfig) -> std::io::Result<Self> {
+        let capture = config
+            .capture
+            .as_deref()
+            .map(CaptureWriter::create)
+            .transpose()?;
+        let listener = TcpListener::bind(config.listen_address).await?;
+        let local_address = listener.local_addr()?;
+        info!(
+            "Sniffer listening on {local_address}, relaying to {}, authority public key {}",
+            config.upstream_address, config.authority_public_key
+        );
+        let shared = Arc::new(Shared {
+            config,
+            capture,
+            connections: AtomicU64::new(0),
+        });
+
+        let task_manager = Arc::new(TaskManager::new());
+        let accept_task_manager = task_manager.clone();
+        task_manager.spawn_named("sniffer_accept", async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let connection = shared.connections.fetch_add(1, Ordering::Relaxed) + 1;
+                        accept_task_manager.spawn_named(
+                            "sniffer_connection",
+                            sniff(shared.clone(), stream, peer, connection),
+                        )
+                    }
+                    Err(e) => warn!("Failed to accept a connection: {e}"),
+                }
+            }
+        });
+
+        Ok(Self {
+            local_address,
+            task_manager,
+        })
+    }
+
+    /// Address the downstreams connect to.
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Stops listening and closes every connection.
+    pub async fn stop(&self) {
+        self.task_manager.abort_all().await;
+    }
+}
+
+impl std::fmt::Debug for Sv2Sniffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sv2Sniffer")
+            .field("local_address", &self.local_address)
+            .finish_non_exhaustive()
+    }
+}
+
+async fn sniff(shared: Arc<Shared>, stream: TcpStream, peer: SocketAddr, connection: u64) {
+    let config = &shared.config;
+    let responder = match Responder::from_authority_kp(
+        &config.authority_public_key.into_bytes(),
+        &config.authority_secret_key.into_bytes(),
+        CERTIFICATE_VALIDITY,
+    ) {
+        Ok(responder) => responder,
+        Err(e) => {
+            warn!("Invalid authority keypair: {e:?}");
+            return;
+        }
+    };
+    let downstream = match Sv2TcpStream::<Message>::new(
+        stream,
+        Encryption::Noise,
+        HandshakeRole::Responder(responder),
+    )
+    .await
+    {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!("#{connection}: handshake with {peer} failed: {e:?}");
+            return;
+        }
+    };
+    let upstream_keys = config
+        .upstream_authority_public_key
+        .map(AuthorityPublicKeys::from);
+    let upstream = match connect_address::<Message>(
+        &config.upstream_address,
+        Encryption::Noise,
+        upstream_keys.as_ref(),
+    )
+    .await
+    {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!(
+                "#{connection}: failed to connect to {} for {peer}: {e:?}",
+                config.upstream_address
+            );
+            return;
+        }
+    };
+    info!(
+        "#{connection}: relaying {peer} to {}",
+        config.upstream_address
+    );
+
+    let started = Instant::now();
+    let (downstream_reader, mut downstream_writer) = downstream.into_split();
+    let (upstream_reader, mut upstream_writer) = upstream.into_split();
+    // Whichever side closes first ends the connection, so the reads interrupted here are never
+    // resumed.
+    let to_upstream = relay(
+        &shared,
+        connection,
+        started,
+        Direction::ToUpstream,
+        downstream_reader,
+        &mut upstream_writer,
+    );
+    let to_downstream = relay(
+        &shared,
+        connection,
+        started,
+        Direction::ToDownstream,
+        upstream_reader,
+        &mut downstream_writer,
+    );
+    let result = tokio::select! {
+        result = to_upstream => result,
+        result = to_downstream => result,
+    };
+    let _ = downstream_writer.shutdown().await;
+    let _ = upstream_writer.shutdown().await;
+    match result {
+        Ok(()) => info!("#{connection}: {peer} disconnected"),
+        Err(e) => info!("#{connection}: closed: {e:?}"),
+    }
+}
+
+// Relays the frames of `reader` to `writer` until either side closes.
+async fn relay(
+    shared: &Shared,
+    connection: u64,
+    started: Instant,
+    direction: Direction,
+    mut reader: Sv2TcpReadHalf<Message>,
+    writer: &mut Sv2TcpWriteHalf<Message>,
+) -> Result<(), Error> {
+    loop {
+        let frame = reader.read_frame().await?;
+        if let Frame::Sv2(mut sv2_frame) = frame {
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            let record =
+                CaptureRecord::from_frame(connection, elapsed_ms, direction, &mut sv2_frame)
+                    .ok_or(Error::HandshakeRemoteInvalidMessage)?;
+            log(&record);
+            if let Some(capture) = &shared.capture {
+                if let Err(e) = capture.record(&record) {
+                    warn!("Failed to record a message to the capture: {e}");
+                }
+            }
+            writer.write_frame(Frame::Sv2(sv2_frame)).await?;
+        } else {
+            writer.write_frame(frame).await?;
+        }
+    }
+}
+
+fn log(record: &CaptureRecord) {
+    let mut payload = record.payload.clone();
+    let decoded = match record.extension_type {
+        0 => match AnyMessage::try_from((record.message_type, payload.as_mut_slice())) {
+            Ok(message) => format!("{message:?}"),
+            Err(e) => format!("undecodable: {e:?}"),
+        },
+        extension_type => format!("extension {extension_type:#06x}, not decoded"),
+    };
+    info!(
+        "#{} +{}ms {} {} ({:#04x}, {} bytes): {decoded}",
+        record.connection,
+        record.elapsed_ms,
+        record.direction,
+        record.name,
+        record.message_type,
+        record.payload.len(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_core::{
+        buffer_sv2,
+        common_messages_sv2::{
+            Protocol, SetupConnection, SetupConnectionSuccess, MESSAGE_TYPE_SETUP_CONNECTION,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        },
+        framing_sv2::framing::Sv2Frame,
+        noise_sv2::Initiator,
+        parsers_sv2::CommonMessages,
+    };
+
+    fn frame(message: Message) -> EitherFrame {
+        let frame: Sv2Frame<Message, buffer_sv2::Slice> = message.try_into().unwrap();
+        Frame::Sv2(frame)
+    }
+
+    async fn next_message_type(reader: &mut Sv2TcpReadHalf<Message>) -> u8 {
+        let Frame::Sv2(mut frame) = reader.read_frame().await.unwrap() else {
+            panic!("unexpected handshake frame");
+        };
+        frame.get_header().unwrap().msg_type()
+    }
+
+    // An upstream answering the `SetupConnection` of its single client.
+    async fn start_upstream() -> (SocketAddr, Secp256k1PublicKey) {
+        let secret_key = Secp256k1SecretKey::generate();
+        let public_key = Secp256k1PublicKey::from(secret_key);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let responder = Responder::from_authority_kp(
+                &public_key.into_bytes(),
+                &secret_key.into_bytes(),
+                CERTIFICATE_VALIDITY,
+            )
+            .unwrap();
+            let (mut reader, mut writer) = Sv2TcpStream::<Message>::new(
+                stream,
+                Encryption::Noise,
+                HandshakeRole::Responder(responder),
+            )
+            .await
+            .unwrap()
+            .into_split();
+            assert_eq!(
+                next_message_type(&mut reader).await,
+                MESSAGE_TYPE_SETUP_CONNECTION
+            );
+            let success = SetupConnectionSuccess {
+                used_version: 2,
+                flags: 0,
+            };
+            writer
+                .write_frame(frame(AnyMessage::Common(
+                    CommonMessages::SetupConnectionSuccess(success),
+                )))
+                .await
+                .unwrap();
+            // Keeps the connection open until the client closes it.
+            let _ = reader.read_frame().await;
+        });
+        (address, public_key)
+    }
+
+    #[tokio::test]
+    async fn relays_and_records_messages() {
+        let (upstream_address, upstream_key) = start_upstream().await;
+        let capture_path =
+            std::env::temp_dir().join(format!("sv2-sniffer-relay-{}.jsonl", std::process::id()));
+        let secret_key = Secp256k1SecretKey::generate();
+        let public_key = Secp256k1PublicKey::from(secret_key);
+        let config = SnifferConfig::new(
+            "127.0.0.1:0".parse().unwrap(),
+            upstream_address.to_string(),
+            public_key,
+            secret_key,
+        )
+        .with_upstream_authority_public_key(upstream_key)
+        .with_capture(capture_path.clone());
+        let sniffer = Sv2Sniffer::start(config).await.unwrap();
+
+        let stream = TcpStream::connect(sniffer.local_address()).await.unwrap();
+        let initiator = Initiator::new(Some(public_key.0));
+        let (mut reader, mut writer) = Sv2TcpStream::<Message>::new(
+            stream,
+            Encryption::Noise,
+            HandshakeRole::Initiator(initiator),
+        )
+        .await
+        .unwrap()
+        .into_split();
+        let setup = SetupConnection {
+            protocol: Protocol::MiningProtocol,
+            min_version: 2,
+            max_version: 2,
+            flags: 0,
+            endpoint_host: b"127.0.0.1".to_vec().try_into().unwrap(),
+            endpoint_port: upstream_address.port(),
+            vendor: b"test".to_vec().try_into().unwrap(),
+            hardware_version: b"".to_vec().try_into().unwrap(),
+            firmware: b"".to_vec().try_into().unwrap(),
+            device_id: b"".to_vec().try_into().unwrap(),
+        };
+        writer
+            .write_frame(frame(AnyMessage::Common(CommonMessages::SetupConnection(
+                setup,
+            ))))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_message_type(&mut reader).await,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS
+        );
+
+        let records = read_capture(&capture_path).unwrap();
+        let relayed: Vec<_> = records
+            .iter()
+            .map(|record| (record.connection, record.direction, record.message_type))
+            .collect();
+        assert_eq!(
+            relayed,
+            vec![
+                (1, Direction::ToUpstream, MESSAGE_TYPE_SETUP_CONNECTION),
+                (
+                    1,
+                    Direction::ToDownstream,
+                    MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS
+                ),
+            ]
+        );
+        assert_eq!(records[0].name, "SetupConnection");
+        sniffer.stop().await;
+        std::fs::remove_file(&capture_path).unwrap();
+    }
+}
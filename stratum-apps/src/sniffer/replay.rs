@@ -0,0 +1,409 @@
+//! Replay of capture files, to reproduce field issues deterministically.
+//!
+//! A [`Replay`] plays one side of a captured connection against a role: as a fake downstream it
+//! connects to the role and sends the messages the downstream sent, as a fake upstream it accepts
+//! a connection from the role and sends the messages the upstream sent. In both cases the messages
+//! the role sends back are asserted to match the ones of the capture, in order.
+//!
+//! Messages are sent at their original time, relative to the connection, or faster with
+//! [`Replay::with_speed`]. A message expected from the role is waited for before sending the
+//! messages captured after it, so that the role sees the exchange in the same order as in the
+//! field. Messages carrying values which change on every run (timestamps, random ids, ...) can be
+//! compared on their type only with [`Replay::compare_type_only`].
+
+use std::{fmt, time::Duration};
+
+use stratum_core::{
+    codec_sv2::HandshakeRole, framing_sv2::framing::Frame, noise_sv2::Responder,
+    parsers_sv2::message_type_to_name,
+};
+use tokio::{net::TcpListener, time::Instant};
+use tracing::{debug, info};
+
+use super::{CaptureRecord, Direction, Message, CERTIFICATE_VALIDITY};
+use crate::{
+    key_utils::{AuthorityPublicKeys, Secp256k1PublicKey, Secp256k1SecretKey},
+    network_helpers::{
+        transport::{connect_address, Encryption, Sv2TcpReadHalf, Sv2TcpStream, Sv2TcpWriteHalf},
+        Error,
+    },
+};
+
+/// How long a message of the role is waited for, by default.
+const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a [`Replay`] failed.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The capture holds no message for the connection to replay.
+    EmptyCapture(u64),
+    /// The listening address or the authority keypair of the fake upstream cannot be used.
+    Io(std::io::Error),
+    /// The connection with the role failed, e.g. its handshake.
+    Connection(Error),
+    /// The role sent a message other than the one captured at `index`.
+    Mismatch {
+        index: usize,
+        expected: Box<CaptureRecord>,
+        actual: Box<CaptureRecord>,
+    },
+    /// The role did not send the message captured at `index` in time.
+    Timeout {
+        index: usize,
+        expected: Box<CaptureRecord>,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::EmptyCapture(connection) => {
+                write!(f, "no message captured for connection {connection}")
+            }
+            ReplayError::Io(e) => write!(f, "I/O error: {e}"),
+            ReplayError::Connection(e) => write!(f, "connection with the role failed: {e:?}"),
+            ReplayError::Mismatch {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "message {index}: expected {} ({} bytes), got {} ({} bytes)",
+                expected.name,
+                expected.payload.len(),
+                actual.name,
+                actual.payload.len()
+            ),
+            ReplayError::Timeout { index, expected } => {
+                write!(f, "message {index}: timeout waiting for {}", expected.name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+impl From<Error> for ReplayError {
+    fn from(e: Error) -> Self {
+        ReplayError::Connection(e)
+    }
+}
+
+/// Outcome of a successful [`Replay`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Messages sent to the role.
+    pub sent: usize,
+    /// Messages of the role matching the capture.
+    pub matched: usize,
+}
+
+/// One side of a captured connection, to play against a role.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    records: Vec<CaptureRecord>,
+    connection: u64,
+    speed: f64,
+    response_timeout: Duration,
+    type_only: Vec<u8>,
+}
+
+impl Replay {
+    /// Creates a replay of the connection numbered `connection` among `records`, usually read
+    /// with [`read_capture`](super::read_capture), at the original timing.
+    pub fn new(records: Vec<CaptureRecord>, connection: u64) -> Self {
+        let records = records
+            .into_iter()
+            .filter(|record| record.connection == connection)
+            .collect();
+        Self {
+            records,
+            connection,
+            speed: 1.0,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+            type_only: Vec::new(),
+        }
+    }
+
+    /// Sends the messages `speed` times faster than captured, `f64::INFINITY` for no wait at all.
+    ///
+    /// Panics if `speed` is not positive.
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        assert!(speed > 0.0, "the speed of a replay must be positive");
+        self.speed = speed;
+        self
+    }
+
+    /// Sets how long each message of the role is waited for, 10 seconds by default.
+    pub fn with_response_timeout(mut self, response_timeout: Duration) -> Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    /// Compares the messages of type `message_type` sent by the role on their type only, not on
+    /// their content.
+    pub fn compare_type_only(mut self, message_type: u8) -> Self {
+        self.type_only.push(message_type);
+        self
+    }
+
+    /// Plays the downstream of the capture: connects to the role at `upstream_address`,
+    /// authenticating it with `authority_public_key` if any, and replays the messages.
+    pub async fn run_as_downstream(
+        &self,
+        upstream_address: &str,
+        authority_public_key: Option<Secp256k1PublicKey>,
+    ) -> Result<ReplayReport, ReplayError> {
+        self.check_not_empty()?;
+        let keys = authority_public_key.map(AuthorityPublicKeys::from);
+        let stream =
+            connect_address::<Message>(upstream_address, Encryption::Noise, keys.as_ref()).await?;
+        info!(
+            "Replaying connection {} as downstream of {upstream_address}",
+            self.connection
+        );
+        self.run(stream, Direction::ToUpstream).await
+    }
+
+    /// Plays the upstream of the capture: accepts a single connection of the role on `listener`,
+    /// presenting the authority keypair, and replays the messages.
+    pub async fn run_as_upstream(
+        &self,
+        listener: TcpListener,
+        authority_public_key: Secp256k1PublicKey,
+        authority_secret_key: Secp256k1SecretKey,
+    ) -> Result<ReplayReport, ReplayError> {
+        self.check_not_empty()?;
+        let responder = Responder::from_authority_kp(
+            &authority_public_key.into_bytes(),
+            &authority_secret_key.into_bytes(),
+            CERTIFICATE_VALIDITY,
+        )
+        .map_err(|e| {
+            let message = format!("invalid authority keypair: {e:?}");
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+        })?;
+        let (stream, peer) = listener.accept().await?;
+        let stream = Sv2TcpStream::<Message>::new(
+            stream,
+            Encryption::Noise,
+            HandshakeRole::Responder(responder),
+        )
+        .await?;
+        info!(
+            "Replaying connection {} as upstream of {peer}",
+            self.connection
+        );
+        self.run(stream, Direction::ToDownstream).await
+    }
+
+    fn check_not_empty(&self) -> Result<(), ReplayError> {
+        if self.records.is_empty() {
+            return Err(ReplayError::EmptyCapture(self.connection));
+        }
+        Ok(())
+    }
+
+    // Sends the records going in `sent`, and expects the others from the role.
+    async fn run(
+        &self,
+        stream: Sv2TcpStream<Message>,
+        sent: Direction,
+    ) -> Result<ReplayReport, ReplayError> {
+        let (mut reader, mut writer) = stream.into_split();
+        let started = Instant::now();
+        let mut report = ReplayReport {
+            sent: 0,
+            matched: 0,
+        };
+        for (index, record) in self.records.iter().enumerate() {
+            if record.direction == sent {
+                let at = Duration::from_millis(record.elapsed_ms).div_f64(self.speed);
+                tokio::time::sleep_until(started + at).await;
+                debug!("Sending message {index}: {}", record.name);
+                writer.write_frame(Frame::Sv2(record.to_frame())).await?;
+                report.sent += 1;
+            } else {
+                self.expect(&mut reader, index, record, started).await?;
+                report.matched += 1;
+            }
+        }
+        let _ = writer.shutdown().await;
+        info!(
+            "Replay done: {} messages sent, {} matched",
+            report.sent, report.matched
+        );
+        Ok(report)
+    }
+
+    // Reads the next message of the role and checks it against `expected`.
+    async fn expect(
+        &self,
+        reader: &mut Sv2TcpReadHalf<Message>,
+        index: usize,
+        expected: &CaptureRecord,
+        started: Instant,
+    ) -> Result<(), ReplayError> {
+        let timeout = || ReplayError::Timeout {
+            index,
+            expected: Box::new(expected.clone()),
+        };
+        // The replay stops on a timeout, so the interrupted read is never resumed.
+        let frame = tokio::time::timeout(self.response_timeout, reader.read_frame())
+            .await
+            .map_err(|_| timeout())??;
+        let Frame::Sv2(mut frame) = frame else {
+            return Err(Error::HandshakeRemoteInvalidMessage.into());
+        };
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        let actual =
+            CaptureRecord::from_frame(self.connection, elapsed_ms, expected.direction, &mut frame)
+                .ok_or(Error::HandshakeRemoteInvalidMessage)?;
+        let matches = if self.type_only.contains(&expected.message_type) {
+            actual.extension_type == expected.extension_type
+                && actual.message_type == expected.message_type
+        } else {
+            actual.same_message(expected)
+        };
+        if !matches {
+            return Err(ReplayError::Mismatch {
+                index,
+                expected: Box::new(expected.clone()),
+                actual: Box::new(actual),
+            });
+        }
+        debug!(
+            "Message {index} matched: {}",
+            message_type_to_name(actual.message_type)
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stratum_core::{
+        buffer_sv2,
+        common_messages_sv2::{Protocol, SetupConnection, SetupConnectionSuccess},
+        framing_sv2::framing::Sv2Frame,
+        parsers_sv2::{AnyMessage, CommonMessages},
+    };
+
+    fn record(elapsed_ms: u64, direction: Direction, message: Message) -> CaptureRecord {
+        let mut frame: Sv2Frame<Message, buffer_sv2::Slice> = message.try_into().unwrap();
+        CaptureRecord::from_frame(1, elapsed_ms, direction, &mut frame).unwrap()
+    }
+
+    fn setup_connection() -> CaptureRecord {
+        let setup = SetupConnection {
+            protocol: Protocol::MiningProtocol,
+            min_version: 2,
+            max_version: 2,
+            flags: 0,
+            endpoint_host: b"127.0.0.1".to_vec().try_into().unwrap(),
+            endpoint_port: 34254,
+            vendor: b"test".to_vec().try_into().unwrap(),
+            hardware_version: b"".to_vec().try_into().unwrap(),
+            firmware: b"".to_vec().try_into().unwrap(),
+            device_id: b"".to_vec().try_into().unwrap(),
+        };
+        let message = AnyMessage::Common(CommonMessages::SetupConnection(setup));
+        record(0, Direction::ToUpstream, message)
+    }
+
+    fn setup_connection_success(flags: u32) -> CaptureRecord {
+        let success = SetupConnectionSuccess {
+            used_version: 2,
+            flags,
+        };
+        let message = AnyMessage::Common(CommonMessages::SetupConnectionSuccess(success));
+        record(20, Direction::ToDownstream, message)
+    }
+
+    // Plays `upstream` against `downstream`, each replay standing for the role of the other.
+    async fn replay_against(
+        upstream: Replay,
+        downstream: Replay,
+    ) -> (
+        Result<ReplayReport, ReplayError>,
+        Result<ReplayReport, ReplayError>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let secret_key = Secp256k1SecretKey::generate();
+        let public_key = Secp256k1PublicKey::from(secret_key);
+        let upstream = tokio::spawn(async move {
+            upstream
+                .run_as_upstream(listener, public_key, secret_key)
+                .await
+        });
+        let downstream = downstream
+            .run_as_downstream(&address, Some(public_key))
+            .await;
+        (upstream.await.unwrap(), downstream)
+    }
+
+    #[tokio::test]
+    async fn replays_matching_sessions() {
+        let capture = vec![setup_connection(), setup_connection_success(0)];
+        let (upstream, downstream) = replay_against(
+            Replay::new(capture.clone(), 1),
+            Replay::new(capture, 1).with_speed(10.0),
+        )
+        .await;
+        assert_eq!(
+            upstream.unwrap(),
+            ReplayReport {
+                sent: 1,
+                matched: 1
+            }
+        );
+        assert_eq!(
+            downstream.unwrap(),
+            ReplayReport {
+                sent: 1,
+                matched: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_mismatching_responses() {
+        let recorded = vec![setup_connection(), setup_connection_success(0)];
+        let replayed = vec![setup_connection(), setup_connection_success(1)];
+        let (_, downstream) = replay_against(
+            Replay::new(replayed.clone(), 1),
+            Replay::new(recorded.clone(), 1).with_speed(f64::INFINITY),
+        )
+        .await;
+        assert!(matches!(
+            downstream,
+            Err(ReplayError::Mismatch { index: 1, .. })
+        ));
+
+        let (_, downstream) = replay_against(
+            Replay::new(replayed, 1),
+            Replay::new(recorded, 1)
+                .with_speed(f64::INFINITY)
+                .compare_type_only(setup_connection_success(0).message_type),
+        )
+        .await;
+        assert!(downstream.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_empty_captures() {
+        let replay = Replay::new(vec![setup_connection()], 2);
+        assert!(matches!(
+            replay.run_as_downstream("127.0.0.1:1", None).await,
+            Err(ReplayError::EmptyCapture(2))
+        ));
+    }
+}
@@ -0,0 +1,39 @@
+//! Atomic writes of the state files of the roles.
+//!
+//! A state file (ban list, accounting rounds, lifetime statistics, ...) is written to a temporary
+//! file next to it, then renamed over it: a crash leaves either its previous contents or the new
+//! ones, never a truncated file.
+
+use std::{io, path::Path};
+
+/// Writes `contents` to the file at `path`, replacing it atomically.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, contents)?;
+    std::fs::rename(&temporary, path)
+}
+
+/// Writes `value` as JSON to the file at `path`, replacing it atomically.
+#[cfg(feature = "serde_json")]
+pub fn write_json_atomically<T: serde::Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let json = serde_json::to_vec(value).map_err(io::Error::other)?;
+    write_atomically(path, &json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_the_file_without_leaving_the_temporary_one() {
+        let dir = std::env::temp_dir().join(format!("state-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+        write_atomically(&path, b"first").unwrap();
+        write_atomically(&path, b"second").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert!(!dir.join("state.json.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
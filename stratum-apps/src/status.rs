@@ -0,0 +1,304 @@
+//! Machine-readable health of a role
+//!
+//! The components of a role (upstream connection, template receiver, channel manager, ...)
+//! report whether they are [healthy](HealthState::Healthy), [degraded](HealthState::Degraded) or
+//! [failed](HealthState::Failed), with a reason, to a [`HealthAggregator`] shared by the role.
+//! The overall health of the role is the worst state of its components.
+//!
+//! A role is live as long as none of its components failed, and ready once it
+//! [declared itself ready](HealthAggregator::set_ready) (e.g. after connecting to its upstream),
+//! all its [readiness conditions](HealthAggregator::set_condition) hold and it is live. With the
+//! `monitoring` feature, the endpoint served by `monitoring::serve` answers liveness and readiness
+//! probes from the [`HealthReport`] on `GET /healthz` and `GET /readyz`.
+//!
+//! The aggregator also keeps the last [`HEALTH_HISTORY_CAPACITY`] [transitions](HealthTransition)
+//! of the components, served on `GET /history` along with the last warnings and errors logged.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crate::unix_time;
+
+/// Health of a component, or of a whole role. Ordered from best to worst.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    /// Working as expected.
+    #[default]
+    Healthy,
+    /// Working with reduced functionality, e.g. while reconnecting or on a fallback.
+    Degraded,
+    /// Not working, the role must be restarted.
+    Failed,
+}
+
+/// Last health reported by a component.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComponentHealth {
+    pub state: HealthState,
+    /// Why the component is not healthy, `None` when healthy.
+    pub reason: Option<String>,
+    /// When the component entered `state`, in UNIX seconds.
+    pub since: u64,
+}
+
+/// Transitions kept by a [`HealthAggregator`], the oldest are dropped first.
+pub const HEALTH_HISTORY_CAPACITY: usize = 256;
+
+/// Change of the state of a component.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthTransition {
+    pub component: String,
+    /// State the component left, `None` when it was first reported.
+    pub from: Option<HealthState>,
+    pub to: HealthState,
+    /// Why the component is not healthy, `None` when healthy.
+    pub reason: Option<String>,
+    /// When the component entered `to`, in UNIX seconds.
+    pub at: u64,
+}
+
+/// Health of a role and of each of its components.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    /// Worst state of the components, healthy if there are none.
+    pub state: HealthState,
+    /// Whether the role declared itself ready and all its readiness conditions hold.
+    pub ready: bool,
+    pub components: BTreeMap<String, ComponentHealth>,
+    /// Readiness conditions of the role, e.g. `listener_bound`, and whether each holds.
+    pub conditions: BTreeMap<String, bool>,
+}
+
+impl Default for HealthReport {
+    /// Report of a role not tracking its health: always live and ready.
+    fn default() -> Self {
+        Self {
+            state: HealthState::Healthy,
+            ready: true,
+            components: BTreeMap::new(),
+            conditions: BTreeMap::new(),
+        }
+    }
+}
+
+impl HealthReport {
+    /// Whether none of the components failed.
+    pub fn is_live(&self) -> bool {
+        self.state != HealthState::Failed
+    }
+
+    /// Whether the role is ready and live.
+    pub fn is_ready(&self) -> bool {
+        self.ready && self.is_live()
+    }
+
+    /// Returns the readiness conditions which do not hold.
+    pub fn unmet_conditions(&self) -> impl Iterator<Item = &str> {
+        self.conditions
+            .iter()
+            .filter(|(_, met)| !**met)
+            .map(|(condition, _)| condition.as_str())
+    }
+}
+
+/// Collects the health reported by the components of a role.
+#[derive(Debug, Default)]
+pub struct HealthAggregator {
+    components: Mutex<BTreeMap<String, ComponentHealth>>,
+    ready: AtomicBool,
+    conditions: Mutex<BTreeMap<String, bool>>,
+    transitions: Mutex<VecDeque<HealthTransition>>,
+}
+
+impl HealthAggregator {
+    /// Creates an aggregator without components, not ready yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reports that `component` is healthy.
+    pub fn healthy(&self, component: &str) {
+        self.report(component, HealthState::Healthy, None);
+    }
+
+    /// Reports that `component` is degraded because of `reason`.
+    pub fn degraded(&self, component: &str, reason: impl Display) {
+        self.report(component, HealthState::Degraded, Some(reason.to_string()));
+    }
+
+    /// Reports that `component` failed because of `reason`.
+    pub fn failed(&self, component: &str, reason: impl Display) {
+        self.report(component, HealthState::Failed, Some(reason.to_string()));
+    }
+
+    /// Records the `state` of `component`. The time it entered the state is kept if it was
+    /// already in it, otherwise the transition is recorded.
+    pub fn report(&self, component: &str, state: HealthState, reason: Option<String>) {
+        let mut components = self.components.lock().unwrap();
+        let since = match components.get(component) {
+            Some(previous) if previous.state == state => previous.since,
+            previous => {
+                let since = unix_time::now_secs();
+                let mut transitions = self.transitions.lock().unwrap();
+                if transitions.len() == HEALTH_HISTORY_CAPACITY {
+                    transitions.pop_front();
+                }
+                transitions.push_back(HealthTransition {
+                    component: component.to_string(),
+                    from: previous.map(|previous| previous.state),
+                    to: state,
+                    reason: reason.clone(),
+                    at: since,
+                });
+                since
+            }
+        };
+        components.insert(
+            component.to_string(),
+            ComponentHealth {
+                state,
+                reason,
+                since,
+            },
+        );
+    }
+
+    /// Stops tracking `component`, e.g. once a connection is closed on purpose.
+    pub fn remove(&self, component: &str) {
+        self.components.lock().unwrap().remove(component);
+    }
+
+    /// Declares whether the role is ready to serve.
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    /// Records whether the readiness `condition` holds. The role is not ready while any of its
+    /// conditions does not, set them as unmet on startup for the role to wait for them.
+    pub fn set_condition(&self, condition: &str, met: bool) {
+        self.conditions
+            .lock()
+            .unwrap()
+            .insert(condition.to_string(), met);
+    }
+
+    /// Returns the transitions of the components kept, oldest first.
+    pub fn transitions(&self) -> Vec<HealthTransition> {
+        self.transitions.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Returns the current health of the role.
+    pub fn snapshot(&self) -> HealthReport {
+        let components = self.components.lock().unwrap().clone();
+        let conditions = self.conditions.lock().unwrap().clone();
+        HealthReport {
+            state: components
+                .values()
+                .map(|component| component.state)
+                .max()
+                .unwrap_or_default(),
+            ready: self.ready.load(Ordering::Relaxed) && conditions.values().all(|met| *met),
+            components,
+            conditions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overall_state_is_the_worst_component_state() {
+        let health = HealthAggregator::new();
+        assert_eq!(health.snapshot().state, HealthState::Healthy);
+
+        health.healthy("channel_manager");
+        health.degraded("upstream", "reconnecting");
+        let report = health.snapshot();
+        assert_eq!(report.state, HealthState::Degraded);
+        assert_eq!(
+            report.components["upstream"].reason.as_deref(),
+            Some("reconnecting")
+        );
+        assert!(report.is_live());
+
+        health.failed("channel_manager", "channel closed");
+        assert!(!health.snapshot().is_live());
+
+        health.remove("channel_manager");
+        health.healthy("upstream");
+        assert_eq!(health.snapshot().state, HealthState::Healthy);
+    }
+
+    #[test]
+    fn records_the_transitions_of_the_components() {
+        let health = HealthAggregator::new();
+        health.healthy("upstream");
+        health.healthy("upstream");
+        health.degraded("upstream", "reconnecting");
+        health.degraded("upstream", "still reconnecting");
+        health.healthy("upstream");
+
+        let transitions = health.transitions();
+        let states: Vec<_> = transitions
+            .iter()
+            .map(|transition| (transition.from, transition.to))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                (None, HealthState::Healthy),
+                (Some(HealthState::Healthy), HealthState::Degraded),
+                (Some(HealthState::Degraded), HealthState::Healthy),
+            ]
+        );
+        assert_eq!(transitions[1].reason.as_deref(), Some("reconnecting"));
+    }
+
+    #[test]
+    fn ready_only_when_declared_and_live() {
+        let health = HealthAggregator::new();
+        health.healthy("upstream");
+        assert!(!health.snapshot().is_ready());
+
+        health.set_ready(true);
+        assert!(health.snapshot().is_ready());
+
+        health.failed("upstream", "unreachable");
+        assert!(!health.snapshot().is_ready());
+    }
+
+    #[test]
+    fn ready_only_while_all_conditions_hold() {
+        let health = HealthAggregator::new();
+        health.set_condition("upstream_connected", false);
+        health.set_condition("listener_bound", false);
+        health.set_ready(true);
+        let report = health.snapshot();
+        assert!(!report.is_ready());
+        assert_eq!(
+            report.unmet_conditions().collect::<Vec<_>>(),
+            ["listener_bound", "upstream_connected"]
+        );
+
+        health.set_condition("upstream_connected", true);
+        health.set_condition("listener_bound", true);
+        assert!(health.snapshot().is_ready());
+
+        health.set_condition("upstream_connected", false);
+        assert!(!health.snapshot().is_ready());
+
+        health.set_condition("upstream_connected", true);
+        health.set_ready(false);
+        assert!(!health.snapshot().is_ready());
+    }
+}
@@ -0,0 +1,44 @@
+//! Unix timestamps of the events, statistics and state files of the roles.
+//!
+//! A clock set before the UNIX epoch reads as the epoch.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Returns the number of seconds between the UNIX epoch and `time`.
+pub fn secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Returns the number of milliseconds between the UNIX epoch and `time`.
+pub fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Returns the current UNIX time in seconds.
+pub fn now_secs() -> u64 {
+    secs(SystemTime::now())
+}
+
+/// Returns the current UNIX time in milliseconds.
+pub fn now_ms() -> u64 {
+    millis(SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn times_before_the_epoch_read_as_the_epoch() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_500);
+        assert_eq!(secs(time), 1);
+        assert_eq!(millis(time), 1_500);
+        assert_eq!(secs(UNIX_EPOCH - Duration::from_secs(1)), 0);
+        assert_eq!(millis(UNIX_EPOCH - Duration::from_secs(1)), 0);
+    }
+}
@@ -0,0 +1,133 @@
+//! JSON webhooks
+//!
+//! A [`Webhook`] POSTs JSON documents to an HTTP endpoint, one at a time and in order, from a
+//! task of its own. [`Webhook::notify`] never blocks the role: a document notified while
+//! [`WEBHOOK_QUEUE_SIZE`] others are still pending is dropped with a warning, and a request which
+//! fails or times out is logged and not retried.
+
+use http_body_util::Full;
+use hyper::{body::Bytes, header::CONTENT_TYPE, Request, Uri};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use serde::Serialize;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::runtime::TaskManager;
+
+/// Number of documents a webhook holds while its endpoint is slow, before dropping new ones.
+pub const WEBHOOK_QUEUE_SIZE: usize = 64;
+/// How long the endpoint may take to answer a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Cheap-to-clone handle notifying JSON documents to an HTTP endpoint.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: Uri,
+    sender: async_channel::Sender<Vec<u8>>,
+}
+
+impl Webhook {
+    /// Spawns on `task_manager` the task named `name` POSTing the notified documents to `url`,
+    /// until every handle of the webhook is dropped. Fails if `url` is not an `http://` URL.
+    pub fn spawn(
+        task_manager: &TaskManager,
+        name: &'static str,
+        url: &str,
+    ) -> Result<Self, String> {
+        let url = Self::parse_url(url)?;
+        let (sender, receiver) = async_channel::bounded::<Vec<u8>>(WEBHOOK_QUEUE_SIZE);
+        let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build_http();
+        let endpoint = url.clone();
+        task_manager.spawn_named(name, async move {
+            while let Ok(document) = receiver.recv().await {
+                let request = Request::post(endpoint.clone())
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Full::from(document))
+                    .expect("webhook requests are always valid");
+                match tokio::time::timeout(REQUEST_TIMEOUT, client.request(request)).await {
+                    Ok(Ok(response)) if response.status().is_success() => {}
+                    Ok(Ok(response)) => {
+                        warn!("Webhook {endpoint} answered {}", response.status());
+                    }
+                    Ok(Err(e)) => warn!("Webhook {endpoint} failed: {e}"),
+                    Err(_) => warn!("Webhook {endpoint} timed out"),
+                }
+            }
+        });
+        Ok(Self { url, sender })
+    }
+
+    /// Parses the URL of a webhook, failing if it is not an `http://` URL.
+    pub fn parse_url(url: &str) -> Result<Uri, String> {
+        let url: Uri = url
+            .parse()
+            .map_err(|e| format!("invalid webhook URL `{url}`: {e}"))?;
+        if url.scheme_str() != Some("http") || url.host().is_none() {
+            return Err(format!("webhook URL `{url}` must be an http:// URL"));
+        }
+        Ok(url)
+    }
+
+    /// Queues `document` to be POSTed, dropping it if the queue is full.
+    pub fn notify(&self, document: &impl Serialize) {
+        let document = match serde_json::to_vec(document) {
+            Ok(document) => document,
+            Err(e) => {
+                warn!(
+                    "Failed to serialize a document for webhook {}: {e}",
+                    self.url
+                );
+                return;
+            }
+        };
+        if self.sender.try_send(document).is_err() {
+            warn!("Webhook {} lagging behind, dropped a document", self.url);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn only_http_urls_are_accepted() {
+        let task_manager = TaskManager::new();
+        for url in ["https://example.com/hook", "example.com/hook", "not a url"] {
+            assert!(Webhook::spawn(&task_manager, "test_webhook", url).is_err());
+        }
+        assert!(Webhook::parse_url("http://127.0.0.1:8080/hook").is_ok());
+    }
+
+    #[tokio::test]
+    async fn documents_are_posted_as_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let task_manager = TaskManager::new();
+        let webhook = Webhook::spawn(&task_manager, "test_webhook", &url).unwrap();
+        webhook.notify(&serde_json::json!({"difficulty": 42}));
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.ends_with(b"{\"difficulty\":42}") {
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert_ne!(
+                read, 0,
+                "connection closed before the document was received"
+            );
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.contains("content-type: application/json\r\n"));
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+    }
+}
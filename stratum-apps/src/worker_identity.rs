@@ -0,0 +1,189 @@
+//! Account and worker of a user identity
+//!
+//! Miners carried over from SV1 log in as `account.worker`: the account is who the work is
+//! credited and paid to, the worker the machine submitting it, and dashboards report the work of
+//! both. A [`WorkerIdentityParser`] splits the `user_identity` of a channel at a configurable
+//! separator into a [`WorkerIdentity`], and optionally checks it against a regex first.
+//!
+//! ```toml
+//! [worker_identity]
+//! separator = "."                                  # default
+//! pattern = "[a-z0-9]+(\\.[A-Za-z0-9_-]{1,32})?"   # optional, must match the whole identity
+//! ```
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Default separator between the account and the worker of a user identity.
+pub const DEFAULT_WORKER_SEPARATOR: &str = ".";
+
+/// Account and worker of a user identity.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct WorkerIdentity {
+    /// Part of the identity before the first separator, the whole identity if there is none.
+    pub account: String,
+    /// Part of the identity after the first separator, if any and not empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker: Option<String>,
+}
+
+// Raw `[worker_identity]` table, checked by `WorkerIdentityParser::new`.
+#[derive(Deserialize)]
+#[serde(default)]
+struct WorkerIdentityConfig {
+    separator: String,
+    pattern: Option<String>,
+}
+
+impl Default for WorkerIdentityConfig {
+    fn default() -> Self {
+        Self {
+            separator: DEFAULT_WORKER_SEPARATOR.to_string(),
+            pattern: None,
+        }
+    }
+}
+
+impl TryFrom<WorkerIdentityConfig> for WorkerIdentityParser {
+    type Error = String;
+
+    fn try_from(config: WorkerIdentityConfig) -> Result<Self, Self::Error> {
+        Self::new(&config.separator, config.pattern.as_deref())
+    }
+}
+
+/// Splits user identities into a [`WorkerIdentity`], deserialized from a `[worker_identity]`
+/// table whose missing fields take their default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "WorkerIdentityConfig")]
+pub struct WorkerIdentityParser {
+    separator: String,
+    pattern: Option<Regex>,
+}
+
+impl Default for WorkerIdentityParser {
+    fn default() -> Self {
+        Self {
+            separator: DEFAULT_WORKER_SEPARATOR.to_string(),
+            pattern: None,
+        }
+    }
+}
+
+impl WorkerIdentityParser {
+    /// Creates a parser splitting identities at `separator` and, if set, only accepting the
+    /// identities `pattern` matches as a whole.
+    ///
+    /// Fails if `separator` is empty or `pattern` is not a valid regex.
+    pub fn new(separator: &str, pattern: Option<&str>) -> Result<Self, String> {
+        if separator.is_empty() {
+            return Err("worker identity separator must not be empty".to_string());
+        }
+        let pattern = pattern
+            .map(|pattern| Regex::new(&format!("^(?:{pattern})$")))
+            .transpose()
+            .map_err(|e| format!("invalid worker identity pattern: {e}"))?;
+        Ok(Self {
+            separator: separator.to_string(),
+            pattern,
+        })
+    }
+
+    /// Returns the separator between the account and the worker.
+    pub fn separator(&self) -> &str {
+        &self.separator
+    }
+
+    /// Returns the account and worker of `user_identity`, or `None` if the identity does not
+    /// match the pattern.
+    pub fn parse(&self, user_identity: &str) -> Option<WorkerIdentity> {
+        if let Some(pattern) = &self.pattern {
+            if !pattern.is_match(user_identity) {
+                return None;
+            }
+        }
+        Some(self.split(user_identity))
+    }
+
+    /// Returns the account and worker of `user_identity`, accepted by [`parse`](Self::parse)
+    /// beforehand.
+    pub fn split(&self, user_identity: &str) -> WorkerIdentity {
+        match user_identity.split_once(self.separator.as_str()) {
+            Some((account, worker)) => WorkerIdentity {
+                account: account.to_string(),
+                worker: (!worker.is_empty()).then(|| worker.to_string()),
+            },
+            None => WorkerIdentity {
+                account: user_identity.to_string(),
+                worker: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(account: &str, worker: Option<&str>) -> WorkerIdentity {
+        WorkerIdentity {
+            account: account.to_string(),
+            worker: worker.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn splits_at_the_first_separator() {
+        let parser = WorkerIdentityParser::default();
+        assert_eq!(parser.split("alice.rig1"), identity("alice", Some("rig1")));
+        assert_eq!(
+            parser.split("alice.rack.3"),
+            identity("alice", Some("rack.3"))
+        );
+        assert_eq!(parser.split("alice"), identity("alice", None));
+        assert_eq!(parser.split("alice."), identity("alice", None));
+
+        let parser = WorkerIdentityParser::new("_", None).unwrap();
+        assert_eq!(parser.split("bob_s19.a"), identity("bob", Some("s19.a")));
+    }
+
+    #[test]
+    fn only_accepts_identities_matching_the_whole_pattern() {
+        let parser = WorkerIdentityParser::new(".", Some(r"[a-z]+(\.[a-z0-9]+)?")).unwrap();
+        assert_eq!(
+            parser.parse("alice.rig1"),
+            Some(identity("alice", Some("rig1")))
+        );
+        assert_eq!(parser.parse("alice"), Some(identity("alice", None)));
+        assert_eq!(parser.parse("Alice.rig1"), None);
+        assert_eq!(parser.parse("alice.rig1 "), None);
+    }
+
+    #[test]
+    fn rejects_invalid_configurations() {
+        assert!(WorkerIdentityParser::new("", None).is_err());
+        assert!(WorkerIdentityParser::new(".", Some("(")).is_err());
+    }
+
+    #[test]
+    fn deserializes_from_a_table() {
+        let parse = |table: &str| {
+            ext_config::Config::builder()
+                .add_source(ext_config::File::from_str(
+                    table,
+                    ext_config::FileFormat::Toml,
+                ))
+                .build()
+                .and_then(|config| config.try_deserialize::<WorkerIdentityParser>())
+        };
+
+        let parser = parse("separator = \"+\"\npattern = \"[a-z+]+\"").unwrap();
+        assert_eq!(parser.separator(), "+");
+        assert_eq!(parser.parse("carol+x"), Some(identity("carol", Some("x"))));
+        assert_eq!(parser.parse("carol.x"), None);
+
+        let parser = parse("").unwrap();
+        assert_eq!(parser.separator(), DEFAULT_WORKER_SEPARATOR);
+        assert!(parse("separator = \"\"").is_err());
+    }
+}